@@ -3,23 +3,45 @@ use clap::{Parser, Subcommand, ValueEnum};
 mod dns_dev;
 mod tunnel_dev;
 
+use iroh_base::{EndpointId, RelayUrl};
 use lib::{
-    Advertisment, AdvertismentTicket, ConnectNode, DiscoveryMode, ListenNode, ProxyState, Repo,
-    TcpProxyData,
+    Advertisment, AdvertismentTicket, AlreadyListening, BulkOutcome, ConnectNode, DiscoveryMode,
+    IpFamily, ListenNode, Node, ProxyState, Relays, Repo, RouteRule, TcpProxyData, TunnelService,
+    UpdateChecker,
+    control::{AgentStatus, ControlServer, LocalListener},
+    crash::CrashReports,
     datum_cloud::{ApiEnv, DatumCloudClient},
+    health::HealthCheck,
+    http_front::{HeaderRule, HttpFront, TunnelAuth},
+    schedule::TunnelSchedule,
+    update::UpdateChannel,
+    usage::TransferQuota,
 };
 use std::{
+    io::IsTerminal,
     net::{IpAddr, SocketAddr},
     path::PathBuf,
     sync::Arc,
 };
-use tracing::info;
+use tracing::{debug, info, warn};
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 /// Datum Connect Agent
 #[derive(Parser, Debug)]
 struct Args {
     #[clap(short, long, env = "DATUM_CONNECT_REPO")]
     repo: Option<PathBuf>,
+    /// Named profile with its own keys, login and tunnels.
+    #[clap(short, long, env = "DATUM_CONNECT_PROFILE")]
+    profile: Option<String>,
+    /// Datum account to use instead of the active one. Each account has its
+    /// own login.
+    #[clap(short, long, env = "DATUM_CONNECT_ACCOUNT")]
+    account: Option<String>,
+    /// Send the crash reports of earlier runs to Datum. Reports are kept on
+    /// this device until then.
+    #[clap(long, global = true, env = "DATUM_CONNECT_SEND_CRASH_REPORTS")]
+    send_crash_reports: bool,
     #[clap(subcommand)]
     command: Commands,
 }
@@ -27,11 +49,18 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Start a tunnel server that exposes configured local services through the Datum gateway.
-    Serve,
+    Serve(ListenArgs),
 
     /// Join a proxy, i.e. connect to the proxy and expose the service locally.
     Connect(ConnectArgs),
 
+    /// Ask a remote node to listen on its machine and forward connections back
+    /// to a local service, like `ssh -R`.
+    ReverseForward(ReverseForwardArgs),
+
+    /// Allow or disallow a peer to request reverse forwards from this node.
+    AllowReverseForward(AllowReverseForwardArgs),
+
     /// Start a gateway server that forwards HTTP requests through a Datum Connect tunnel.
     Gateway(ServeArgs),
 
@@ -43,11 +72,89 @@ enum Commands {
     TunnelDev(TunnelDevArgs),
 
     /// List configured proxies.
-    List,
+    List {
+        /// Only list proxies with this tag. Can be repeated to require
+        /// several.
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Inspect a single tunnel.
+    #[clap(subcommand)]
+    Tunnel(TunnelCommands),
 
     /// Add proxies.
     #[clap(subcommand, alias = "ls")]
     Add(AddCommands),
+
+    /// List and revoke the connectors of your devices.
+    #[clap(subcommand)]
+    Devices(DeviceCommands),
+
+    /// Log in to Datum Cloud.
+    ///
+    /// Not needed with a service account token in DATUM_CONNECT_TOKEN or a
+    /// file named by DATUM_CONNECT_TOKEN_FILE.
+    Login {
+        /// Print a code to approve in a browser on any device, instead of
+        /// opening a browser here. For servers and containers.
+        #[clap(long)]
+        device_code: bool,
+    },
+
+    /// Manage the Datum accounts logged in on this device.
+    #[clap(subcommand)]
+    Accounts(AccountCommands),
+
+    /// Run connectivity checks and print suggestions for anything that fails.
+    Doctor,
+
+    /// Show the agent serving this repo, `serve` or the app, if one runs.
+    Status,
+
+    /// Show what tunnels transferred recently.
+    ///
+    /// History is kept for a week, at coarser steps the further back it goes.
+    Usage {
+        /// How far back to report.
+        #[clap(long, default_value = "24h")]
+        since: humantime::Duration,
+        /// Only report this tunnel.
+        #[clap(long)]
+        tunnel: Option<String>,
+        /// Print every step as CSV instead of a total per tunnel.
+        #[clap(long)]
+        csv: bool,
+    },
+
+    /// Replace this device's listen key, e.g. after it leaked.
+    ///
+    /// Tickets are published again with the new endpoint id, and the
+    /// connector of the selected project is pointed at it. The previous key
+    /// keeps accepting connections for the grace period, and is deleted
+    /// afterwards.
+    ///
+    /// Refused while `serve` or the app runs for this repo, since it would
+    /// keep serving the previous key: stop it first.
+    RotateKey {
+        /// How long clients may keep using the previous key.
+        #[clap(long, default_value = "24h")]
+        grace: humantime::Duration,
+    },
+
+    /// Replace this executable with the latest release.
+    ///
+    /// The download is checked against the signed checksums of the release
+    /// before anything is replaced.
+    Update {
+        /// Only report whether an update is available.
+        #[clap(long)]
+        check: bool,
+        /// Release channel to update from. Defaults to the one in the update
+        /// settings, which is stable unless changed.
+        #[clap(long, value_enum)]
+        channel: Option<UpdateChannelArg>,
+    },
 }
 
 #[derive(Debug, clap::Parser)]
@@ -56,9 +163,139 @@ enum AddCommands {
         host: String,
         #[clap(long)]
         label: Option<String>,
+        /// Send HTTP requests under a path prefix to another backend, e.g.
+        /// `--route /api=127.0.0.1:8080`. Can be repeated.
+        #[clap(long = "route", value_parser = parse_route)]
+        routes: Vec<RouteRule>,
+        /// Share of gateway traffic for this agent when other agents serve
+        /// the same tunnel. Defaults to 1.
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        weight: Option<u32>,
+        /// Check periodically that the target is up: `tcp` to connect, or a
+        /// path like `/healthz` for an HTTP GET.
+        #[clap(long, value_parser = parse_health_check)]
+        health_check: Option<HealthCheck>,
+        /// Unpublish the tunnel while its health check fails.
+        #[clap(long, requires = "health_check")]
+        unpublish_when_unhealthy: bool,
+        /// Rewrite HTTP headers, e.g. `--header response:remove:Server` or
+        /// `--header request:set:X-Forwarded-Host=example.com`. Can be repeated.
+        #[clap(long = "header", value_parser = parse_header_rule)]
+        headers: Vec<HeaderRule>,
+        /// Require HTTP Basic auth, given as `user:password`.
+        #[clap(long, conflicts_with = "bearer_token")]
+        basic_auth: Option<String>,
+        /// Require `Authorization: Bearer <token>`.
+        #[clap(long)]
+        bearer_token: Option<String>,
+        /// Tag the proxy, for `list --tag`. Can be repeated.
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+        /// Turn the proxy off after this long, e.g. `2h`.
+        #[clap(long)]
+        ttl: Option<humantime::Duration>,
+        /// Only serve the proxy at these local times, e.g.
+        /// `"mon-fri 09:00-17:00; sat 10:00-12:00"`.
+        #[clap(long, value_parser = parse_schedule)]
+        schedule: Option<TunnelSchedule>,
+        /// Refuse clients that can only reach the proxy through a relay.
+        #[clap(long)]
+        direct_only: bool,
+        /// Start connections to the service with a PROXY protocol v2 header
+        /// naming the client. The service must expect it.
+        #[clap(long)]
+        proxy_protocol: bool,
+        /// Serve the proxy under a key of its own, so its tickets keep
+        /// naming the same endpoint when the listen key is rotated.
+        #[clap(long)]
+        pin_key: bool,
+    },
+}
+
+#[derive(Debug, clap::Parser)]
+enum TunnelCommands {
+    /// Show a tunnel's target and its activity timeline.
+    Show { id: String },
+    /// Stop serving a local tunnel without deleting it. Its ticket is
+    /// unpublished; the id and codename stay.
+    Pause { id: String },
+    /// Serve a paused local tunnel again.
+    Resume { id: String },
+    /// Limit how much a tunnel may transfer before it is turned off. Sizes
+    /// take binary units, e.g. 500MB or 10GB.
+    Quota {
+        id: String,
+        /// Most bytes per calendar month (UTC).
+        #[clap(long, value_parser = parse_size)]
+        monthly: Option<u64>,
+        /// Most bytes over the tunnel's lifetime.
+        #[clap(long, value_parser = parse_size)]
+        total: Option<u64>,
+        /// Remove the quota.
+        #[clap(long, conflicts_with_all = ["monthly", "total"])]
+        clear: bool,
+    },
+    /// Turn on every tunnel in the selected project.
+    EnableAll,
+    /// Turn off every tunnel in the selected project.
+    DisableAll,
+    /// Delete tunnels in the selected project.
+    Delete {
+        #[clap(required = true)]
+        ids: Vec<String>,
+    },
+}
+
+#[derive(Debug, clap::Parser)]
+enum AccountCommands {
+    /// List the accounts, marking the active one.
+    List,
+    /// Make an account the one used by default.
+    Use { name: String },
+    /// Forget an account and its login.
+    Remove { name: String },
+}
+
+#[derive(Debug, clap::Parser)]
+enum DeviceCommands {
+    /// List the connectors of every project you can see.
+    List,
+    /// Delete another device's connector and its advertisements.
+    Revoke {
+        project_id: String,
+        connector_name: String,
     },
 }
 
+fn parse_route(s: &str) -> Result<RouteRule, String> {
+    s.parse::<RouteRule>().map_err(|err| format!("{err:#}"))
+}
+
+fn parse_header_rule(s: &str) -> Result<HeaderRule, String> {
+    s.parse::<HeaderRule>().map_err(|err| format!("{err:#}"))
+}
+
+fn parse_schedule(s: &str) -> Result<TunnelSchedule, String> {
+    s.parse::<TunnelSchedule>()
+        .map_err(|err| format!("{err:#}"))
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    lib::usage::parse_size(s).map_err(|err| format!("{err:#}"))
+}
+
+fn parse_health_check(s: &str) -> Result<HealthCheck, String> {
+    let http_path = match s {
+        "tcp" => None,
+        path if path.starts_with('/') => Some(path.to_string()),
+        _ => return Err("expected `tcp` or a path starting with '/'".to_string()),
+    };
+    Ok(HealthCheck {
+        http_path,
+        ..Default::default()
+    })
+}
+
 #[derive(Subcommand, Debug)]
 enum DnsDevArgs {
     /// Serve a local DNS responder for _iroh TXT records.
@@ -124,6 +361,28 @@ pub struct TunnelDevArgs {
     pub target_protocol: String,
 }
 
+#[derive(Parser, Debug)]
+pub struct ListenArgs {
+    /// Print a ticket and a scannable QR code for each enabled proxy.
+    #[clap(long)]
+    pub qr: bool,
+    /// Put the tickets of the enabled proxies on the clipboard, one per line.
+    #[clap(long)]
+    pub copy: bool,
+    /// Also serve the files in this directory, like `ngrok http file://`.
+    /// The tunnel is removed again on exit.
+    #[clap(long, group = "extra_tunnel")]
+    pub serve_dir: Option<PathBuf>,
+    /// Also tunnel to the service listening on this unix socket, e.g.
+    /// `/var/run/app.sock`. The tunnel is removed again on exit.
+    #[cfg(unix)]
+    #[clap(long, group = "extra_tunnel")]
+    pub unix_socket: Option<PathBuf>,
+    /// Label for the `--serve-dir` or `--unix-socket` tunnel.
+    #[clap(long, requires = "extra_tunnel")]
+    pub label: Option<String>,
+}
+
 #[derive(Parser, Debug)]
 pub struct ConnectArgs {
     /// The addresses to listen on for incoming tcp connections.
@@ -134,15 +393,52 @@ pub struct ConnectArgs {
     #[clap(long)]
     pub bind: SocketAddr,
 
+    /// If the bind port is taken, bind a free port on the same address instead.
+    #[clap(long)]
+    pub auto_port: bool,
+
     /// provide a ticket to drive connection directly.
-    #[clap(long, conflicts_with = "codename")]
-    pub ticket: AdvertismentTicket,
+    #[clap(
+        long,
+        conflicts_with = "codename",
+        required_unless_present = "codename"
+    )]
+    pub ticket: Option<AdvertismentTicket>,
+
+    /// Join a tunnel announced on the local network by its codename.
+    ///
+    /// Requires local_discovery in the config on both devices.
+    #[clap(long)]
+    pub codename: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ReverseForwardArgs {
+    /// Endpoint id of the remote node.
+    #[clap(long)]
+    pub remote: EndpointId,
+    /// Address the remote listens on, e.g. 127.0.0.1:8080.
+    #[clap(long)]
+    pub remote_bind: SocketAddr,
+    /// Local service to forward connections to, e.g. 127.0.0.1:3000.
+    #[clap(long)]
+    pub target: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct AllowReverseForwardArgs {
+    /// Endpoint id of the peer.
+    pub peer: EndpointId,
+    /// Disallow the peer instead.
+    #[clap(long)]
+    pub revoke: bool,
 }
 
 #[derive(Parser, Debug)]
 pub struct ServeArgs {
-    #[clap(long, default_value = "0.0.0.0")]
-    pub bind_addr: IpAddr,
+    /// Defaults to 0.0.0.0, or :: when the config prefers IPv6.
+    #[clap(long)]
+    pub bind_addr: Option<IpAddr>,
     #[clap(long, default_value = "8080")]
     pub port: u16,
     /// Optional bind address for Prometheus metrics server.
@@ -164,6 +460,28 @@ pub struct ServeArgs {
     /// DNS resolver address for discovery (e.g. 127.0.0.1:53535).
     #[clap(long)]
     pub dns_resolver: Option<SocketAddr>,
+    /// Use this relay server instead of the public ones. Can be repeated.
+    #[clap(long = "relay", value_name = "URL")]
+    pub relays: Vec<RelayUrl>,
+    /// Use no relay servers, only direct connections.
+    #[clap(long, conflicts_with = "relays")]
+    pub no_relays: bool,
+    /// Which IP versions to use.
+    #[clap(long, value_enum)]
+    pub ip_family: Option<IpFamilyArg>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum IpFamilyArg {
+    Any,
+    PreferIpv6,
+    Ipv6Only,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum UpdateChannelArg {
+    Stable,
+    Beta,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -177,11 +495,21 @@ pub enum DiscoveryModeArg {
     Default,
     Dns,
     Hybrid,
+    Static,
 }
 
 #[tokio::main]
 async fn main() -> n0_error::Result<()> {
-    tracing_subscriber::fmt::init();
+    let recent_logs = lib::crash::RecentLogs::default();
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt::layer())
+        .with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(recent_logs.clone()),
+        )
+        .init();
     if let Ok(path) = dotenv::dotenv() {
         info!("Loaded environment variables from {}", path.display());
     }
@@ -189,11 +517,27 @@ async fn main() -> n0_error::Result<()> {
     let args = Args::parse();
 
     let path = args.repo.unwrap_or_else(Repo::default_location);
-    let repo = Repo::open_or_create(path).await?;
+    let crash_reports = CrashReports::new(path.join("crashes"), ApiEnv::default());
+    crash_reports.install_panic_hook(env!("CARGO_PKG_VERSION"), recent_logs);
+    if args.send_crash_reports {
+        match crash_reports.submit().await {
+            Ok(0) => {}
+            Ok(sent) => info!("Sent {sent} crash reports"),
+            Err(err) => warn!("Failed to send crash reports: {err:#}"),
+        }
+    }
+    let repo = match args.profile {
+        Some(profile) => Repo::open_profile_in(path, &profile).await?,
+        None => Repo::open_or_create(path).await?,
+    };
+    if repo.secret_store().is_fallback() {
+        eprintln!("warning: {}", lib::secret_store::FALLBACK_WARNING);
+    }
+    let account = args.account;
 
     match args.command {
-        Commands::List => {
-            let datum = DatumCloudClient::with_repo(ApiEnv::default(), repo.clone()).await?;
+        Commands::List { tags } => {
+            let datum = datum_client(&repo, account.as_deref()).await?;
             let orgs = datum.orgs_and_projects().await?;
             for org in orgs {
                 println!("org: {} {}", org.org.resource_id, org.org.display_name);
@@ -207,23 +551,171 @@ async fn main() -> n0_error::Result<()> {
 
             println!();
             let state = repo.load_state().await?;
+            let tags = lib::tunnels::normalize_tags(tags);
             for p in state.get().proxies.iter() {
-                println!(
+                if !tags.iter().all(|tag| p.tags.contains(tag)) {
+                    continue;
+                }
+                print!(
                     "{} -> {}:{} (enabled: {})",
                     p.info.resource_id, p.info.data.host, p.info.data.port, p.enabled
-                )
+                );
+                if !p.tags.is_empty() {
+                    print!(" [{}]", p.tags.join(", "));
+                }
+                if let Some(expires_at) = p.expires_at {
+                    print!(" expires {}", expires_at.format("%Y-%m-%d %H:%M:%S UTC"));
+                }
+                println!();
             }
         }
-        Commands::Add(AddCommands::TcpProxy { host, label }) => {
-            let service = TcpProxyData::from_host_port_str(&host)?;
-            let advertisment = Advertisment::new(service, label);
-            let proxy = ProxyState {
-                enabled: true,
-                info: advertisment,
+        Commands::Tunnel(TunnelCommands::Show { id }) => {
+            let state = repo.load_state().await?;
+            let Some(proxy) = state.get().proxies.iter().find(|p| p.id() == id).cloned() else {
+                n0_error::bail_any!("No tunnel {id}");
             };
+            println!("id:      {}", proxy.id());
+            if let Some(label) = &proxy.info.label {
+                println!("label:   {label}");
+            }
+            println!("target:  {}", proxy.info.service().address());
+            println!("enabled: {}", proxy.enabled);
+            if let Some(check) = &proxy.health_check {
+                let kind = check.http_path.as_deref().unwrap_or("tcp");
+                println!("health:  {kind} every {}s", check.interval().as_secs());
+            }
+            if let Some(schedule) = &proxy.schedule {
+                println!("schedule: {schedule}");
+            }
+            if proxy.direct_only {
+                println!("paths:   direct only");
+            }
+            if proxy.proxy_protocol {
+                println!("clients: passed on with the PROXY protocol");
+            }
+            if let Some(endpoint_id) = proxy.pinned_endpoint {
+                println!("pinned:  {endpoint_id}");
+            }
+            let usage = repo.usage().get(&id).unwrap_or_default();
+            println!(
+                "usage:   {} bytes this month, {} bytes in total",
+                usage.bytes_this_month(std::time::SystemTime::now().into()),
+                usage.total_bytes
+            );
+            if let Some(quota) = &proxy.transfer_quota {
+                if let Some(limit) = quota.monthly_bytes {
+                    println!("quota:   {limit} bytes per month");
+                }
+                if let Some(limit) = quota.total_bytes {
+                    println!("quota:   {limit} bytes in total");
+                }
+            }
+            println!();
+            let timeline = repo
+                .activity()
+                .get(&id)
+                .map(|activity| activity.timeline())
+                .unwrap_or_default();
+            if timeline.is_empty() {
+                println!("no activity recorded");
+            }
+            for entry in timeline {
+                println!(
+                    "{}  {}",
+                    entry.at.format("%Y-%m-%d %H:%M:%S UTC"),
+                    entry.kind.description()
+                );
+            }
+        }
+        Commands::Add(AddCommands::TcpProxy {
+            host,
+            label,
+            routes,
+            weight,
+            health_check,
+            unpublish_when_unhealthy,
+            headers,
+            basic_auth,
+            bearer_token,
+            tags,
+            ttl,
+            schedule,
+            direct_only,
+            proxy_protocol,
+            pin_key,
+        }) => {
+            let auth = match (basic_auth, bearer_token) {
+                (Some(credentials), _) => {
+                    let Some((user, password)) = credentials.split_once(':') else {
+                        n0_error::bail_any!("--basic-auth takes user:password");
+                    };
+                    Some(TunnelAuth::basic(user, password)?)
+                }
+                (None, Some(token)) => Some(TunnelAuth::bearer(&token)?),
+                (None, None) => None,
+            };
+            let mut service = TcpProxyData::from_host_port_str(&host)?;
+            let mut host_target = None;
+            if repo.config().await?.resolves_target(&service.host) {
+                // Names from the `hosts` config or the target DNS server are
+                // relayed to from this port by `serve`, which resolves them
+                // for every connection.
+                let port = std::net::TcpListener::bind("127.0.0.1:0")?
+                    .local_addr()?
+                    .port();
+                host_target = Some(service.address());
+                service = TcpProxyData::from_host_port_str(&format!("127.0.0.1:{port}"))?;
+            }
+            let front = HttpFront {
+                rules: headers,
+                auth,
+                ..HttpFront::new(service.clone())
+            };
+            let (target, http_front) = if front.is_empty() {
+                (service, None)
+            } else {
+                // The proxy in front of the service is started on this port
+                // by `serve`.
+                let port = std::net::TcpListener::bind("127.0.0.1:0")?
+                    .local_addr()?
+                    .port();
+                let target = TcpProxyData::from_host_port_str(&format!("127.0.0.1:{port}"))?;
+                (target, Some(front))
+            };
+            let advertisment =
+                Advertisment::new(target.with_routes(routes), label).with_weight(weight);
+            let mut proxy = ProxyState::new(advertisment);
+            proxy.http_front = http_front;
+            proxy.host_target = host_target;
+            proxy.tags = lib::tunnels::normalize_tags(tags);
+            if let Some(ttl) = ttl {
+                proxy.expire_after(ttl.into());
+            }
+            proxy.schedule = schedule;
+            proxy.direct_only = direct_only;
+            proxy.proxy_protocol = proxy_protocol;
+            if pin_key {
+                // `serve` starts the endpoint for it.
+                proxy.pinned_endpoint = Some(repo.tunnel_key(proxy.id()).await?.public());
+            }
+            proxy.health_check = health_check.map(|check| HealthCheck {
+                unpublish_when_unhealthy,
+                ..check
+            });
 
-            println!("Adding {proxy:?})");
             let state = repo.load_state().await?;
+            let service = proxy.info.service();
+            if let Some(existing) = state
+                .get()
+                .listener_conflict(None, &service.host, service.port)
+            {
+                return Err(AlreadyListening {
+                    address: service.address(),
+                    tunnel_id: existing.id().to_string(),
+                }
+                .into());
+            }
+            println!("Adding {proxy:?})");
             state
                 .update(&repo, |state| {
                     state.set_proxy(proxy);
@@ -231,10 +723,24 @@ async fn main() -> n0_error::Result<()> {
                 .await?;
             println!("OK.");
         }
-        Commands::Serve => {
+        Commands::Serve(args) => {
+            let control = LocalListener::bind(repo.path()).await?;
             let node = ListenNode::new(repo).await?;
+            let _control = ControlServer::spawn(control, {
+                let node = node.clone();
+                move || AgentStatus::new("serve", &node)
+            });
             let endpoint_id = node.endpoint_id();
             println!("listening as {}", endpoint_id);
+            let dir_proxy = match &args.serve_dir {
+                Some(dir) => Some(node.add_serve_dir(dir, args.label.clone()).await?),
+                None => None,
+            };
+            #[cfg(unix)]
+            let socket_proxy = match &args.unix_socket {
+                Some(path) => Some(node.add_unix_socket(path, args.label.clone()).await?),
+                None => None,
+            };
             let bound_addrs = node.endpoint().bound_sockets();
             if !bound_addrs.is_empty() {
                 println!("iroh bound sockets:");
@@ -257,25 +763,62 @@ async fn main() -> n0_error::Result<()> {
                         .join(" --addr ")
                 );
             }
+            let mut tickets = Vec::new();
             for p in node.proxies() {
                 if !p.enabled {
                     continue;
                 };
+                tickets.push(p.ticket(endpoint_id).to_string());
                 println!(
                     "{} -> {}:{}",
                     p.info.resource_id, p.info.data.host, p.info.data.port
-                )
+                );
+                if let Some(dir) = &p.serve_dir {
+                    println!("  serving {}", dir.display());
+                }
+                if let Some(path) = &p.unix_socket {
+                    println!("  relaying to {}", path.display());
+                }
+                if args.qr {
+                    let ticket = p.ticket(endpoint_id).to_string();
+                    println!("  ticket: {ticket}");
+                    println!("{}", lib::qr::render_terminal(&ticket)?);
+                }
+            }
+            if args.copy {
+                copy_tickets(&tickets.join("\n"));
             }
             tokio::signal::ctrl_c().await?;
+            if let Some(proxy) = dir_proxy {
+                node.remove_proxy(proxy.id()).await?;
+            }
+            #[cfg(unix)]
+            if let Some(proxy) = socket_proxy {
+                node.remove_proxy(proxy.id()).await?;
+            }
             println!()
         }
         Commands::Connect(args) => {
-            let ConnectArgs { bind, ticket } = args;
+            let ConnectArgs {
+                bind,
+                auto_port,
+                ticket,
+                codename,
+            } = args;
             let node = ConnectNode::new(repo).await?;
+            let ticket = match (ticket, codename) {
+                (Some(ticket), _) => ticket,
+                (None, Some(codename)) => node.find_local(&codename).await?,
+                (None, None) => n0_error::bail_any!("Either --ticket or --codename is required"),
+            };
 
-            let handle = node
-                .connect_and_bind_local(ticket.endpoint, &ticket.data.data, bind)
-                .await?;
+            let handle = if auto_port {
+                node.connect_and_bind_local_auto(ticket.endpoint, &ticket.data.data, bind)
+                    .await?
+            } else {
+                node.connect_and_bind_local(ticket.endpoint, &ticket.data.data, bind)
+                    .await?
+            };
             println!(
                 "server listening on {}, forwarding connections to {} -> {}:{}",
                 handle.bound_addr(),
@@ -286,23 +829,66 @@ async fn main() -> n0_error::Result<()> {
             tokio::signal::ctrl_c().await?;
             handle.abort();
         }
+        Commands::ReverseForward(args) => {
+            let target = TcpProxyData::from_host_port_str(&args.target)?;
+            let node = ConnectNode::new(repo).await?;
+            let handle = node
+                .request_reverse_forward(args.remote, args.remote_bind, target)
+                .await?;
+            println!(
+                "{} listening on {}, forwarding connections to {}",
+                handle.remote_id().fmt_short(),
+                handle.remote_bound_addr(),
+                handle.target().address(),
+            );
+            tokio::signal::ctrl_c().await?;
+            handle.close();
+        }
+        Commands::AllowReverseForward(args) => {
+            let state = repo.load_state().await?;
+            state
+                .update(&repo, |state| {
+                    state.reverse_forward_peers.retain(|id| *id != args.peer);
+                    if !args.revoke {
+                        state.reverse_forward_peers.push(args.peer);
+                    }
+                })
+                .await?;
+            println!("OK.");
+        }
         Commands::Gateway(args) => {
-            let bind_addr: SocketAddr = (args.bind_addr, args.port).into();
+            let secret_key = repo.gateway_key().await?;
+            let mut config = repo.gateway_config().await?;
+            if let Some(ip_family) = args.ip_family {
+                config.common.ip_family = match ip_family {
+                    IpFamilyArg::Any => IpFamily::Any,
+                    IpFamilyArg::PreferIpv6 => IpFamily::PreferIpv6,
+                    IpFamilyArg::Ipv6Only => IpFamily::Ipv6Only,
+                };
+            }
+            let bind_ip = args
+                .bind_addr
+                .unwrap_or_else(|| config.common.unspecified_ip());
+            let bind_addr: SocketAddr = (bind_ip, args.port).into();
             let metrics_bind_addr = match (args.metrics_addr, args.metrics_port) {
                 (None, None) => None,
                 (Some(addr), Some(port)) => Some((addr, port).into()),
                 (Some(addr), None) => Some((addr, 9090).into()),
-                (None, Some(port)) => Some((args.bind_addr, port).into()),
+                (None, Some(port)) => Some((bind_ip, port).into()),
             };
-            let secret_key = repo.gateway_key().await?;
-            let mut config = repo.gateway_config().await?;
             if let Some(discovery) = args.discovery {
                 config.common.discovery_mode = match discovery {
                     DiscoveryModeArg::Default => DiscoveryMode::Default,
                     DiscoveryModeArg::Dns => DiscoveryMode::Dns,
                     DiscoveryModeArg::Hybrid => DiscoveryMode::Hybrid,
+                    DiscoveryModeArg::Static => DiscoveryMode::Static,
                 };
             }
+            if args.no_relays {
+                config.common.relays = Relays::Disabled;
+            } else if !args.relays.is_empty() {
+                config.common.relays = Relays::Custom(args.relays);
+            }
             if let Some(origin) = args.dns_origin {
                 config.common.dns_origin = Some(origin);
             }
@@ -310,15 +896,10 @@ async fn main() -> n0_error::Result<()> {
                 config.common.dns_resolver = Some(resolver);
             }
             #[cfg(unix)]
-            if let Some(uds_path) = &args.uds {
-                let sk = secret_key.clone();
-                let cfg = config.clone();
-                let path = uds_path.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = lib::gateway::bind_and_serve_uds(sk, cfg, path).await {
-                        tracing::warn!(%e, "UDS gateway task failed");
-                    }
-                });
+            if let Some(uds_path) = args.uds {
+                config.uds_path = Some(uds_path);
+            }
+            if let Some(uds_path) = &config.uds_path {
                 println!("UDS gateway at {}", uds_path.display());
             }
             println!("serving on port {bind_addr}");
@@ -347,9 +928,311 @@ async fn main() -> n0_error::Result<()> {
                 )?;
             }
         },
+        Commands::Tunnel(TunnelCommands::Pause { id }) => {
+            let node = ListenNode::new(repo).await?;
+            if !node.set_enabled(&id, false).await? {
+                n0_error::bail_any!("No tunnel {id}");
+            }
+            println!("paused {id}");
+        }
+        Commands::Tunnel(TunnelCommands::Resume { id }) => {
+            let node = ListenNode::new(repo).await?;
+            if !node.set_enabled(&id, true).await? {
+                n0_error::bail_any!("No tunnel {id}");
+            }
+            println!("resumed {id}");
+        }
+        Commands::Tunnel(TunnelCommands::Quota {
+            id,
+            monthly,
+            total,
+            clear,
+        }) => {
+            if !clear && monthly.is_none() && total.is_none() {
+                n0_error::bail_any!("Pass --monthly, --total or --clear");
+            }
+            let quota = (!clear).then_some(TransferQuota {
+                monthly_bytes: monthly,
+                total_bytes: total,
+            });
+            let state = repo.load_state().await?;
+            let found = state
+                .update(&repo, |state| state.set_transfer_quota(&id, quota))
+                .await?;
+            if !found {
+                n0_error::bail_any!("No tunnel {id}");
+            }
+            println!("OK.");
+        }
+        Commands::Tunnel(TunnelCommands::EnableAll) => {
+            let service = tunnel_service(repo, account.as_deref()).await?;
+            report_bulk(service.enable_all().await?, "enabled");
+        }
+        Commands::Tunnel(TunnelCommands::DisableAll) => {
+            let service = tunnel_service(repo, account.as_deref()).await?;
+            report_bulk(service.disable_all().await?, "disabled");
+        }
+        Commands::Tunnel(TunnelCommands::Delete { ids }) => {
+            let service = tunnel_service(repo, account.as_deref()).await?;
+            report_bulk(service.delete_many(ids).await, "deleted");
+        }
+        Commands::Devices(DeviceCommands::List) => {
+            let service = tunnel_service(repo, account.as_deref()).await?;
+            let now = std::time::SystemTime::now().into();
+            for device in service.list_devices().await? {
+                let last_seen = device
+                    .last_heartbeat
+                    .map(|at| at.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                let mut flags = Vec::new();
+                if device.this_device {
+                    flags.push("this device");
+                }
+                if device.is_stale(now) {
+                    flags.push("stale");
+                }
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    device.project_id,
+                    device.connector_name,
+                    device.endpoint_id.as_deref().unwrap_or("-"),
+                    device.agent_version.as_deref().unwrap_or("-"),
+                    last_seen,
+                    flags.join(","),
+                );
+            }
+        }
+        Commands::Devices(DeviceCommands::Revoke {
+            project_id,
+            connector_name,
+        }) => {
+            let service = tunnel_service(repo, account.as_deref()).await?;
+            service.revoke_device(&project_id, &connector_name).await?;
+            println!("revoked {connector_name}");
+        }
         Commands::TunnelDev(args) => {
             tunnel_dev::serve(args).await?;
         }
+        Commands::Login { device_code } => {
+            let datum = datum_client(&repo, account.as_deref()).await?;
+            let auth = datum.auth();
+            if auth.is_service_account() {
+                println!("Using the service account token, no login needed");
+                return Ok(());
+            }
+            if device_code {
+                let mut diagnostics = auth.login_diagnostics_watch();
+                let login = auth.login_device_code();
+                tokio::pin!(login);
+                loop {
+                    tokio::select! {
+                        res = &mut login => {
+                            res?;
+                            break;
+                        }
+                        Ok(()) = diagnostics.changed() => {
+                            let prompt = diagnostics.borrow_and_update().device_code.clone();
+                            if let Some(prompt) = prompt {
+                                let url = prompt
+                                    .verification_uri_complete
+                                    .unwrap_or(prompt.verification_uri);
+                                println!("To log in, open {url}");
+                                println!("and enter the code {}", prompt.user_code);
+                            }
+                        }
+                    }
+                }
+            } else {
+                auth.login().await?;
+            }
+            if let Ok(state) = datum.auth_state().get() {
+                repo.add_account(&datum.account()).await?;
+                println!(
+                    "Logged in as {} ({} account)",
+                    state.profile.email,
+                    datum.account()
+                );
+            }
+        }
+        Commands::Accounts(AccountCommands::List) => {
+            let active = repo.read_active_account().await?;
+            for name in repo.list_accounts().await? {
+                let marker = if name == active { "*" } else { " " };
+                println!("{marker} {name}");
+            }
+        }
+        Commands::Accounts(AccountCommands::Use { name }) => {
+            repo.write_active_account(&name).await?;
+            println!("Using account {name}");
+        }
+        Commands::Accounts(AccountCommands::Remove { name }) => {
+            repo.remove_account(ApiEnv::default().oauth_storage_key(), &name)
+                .await?;
+            println!("Removed account {name}");
+        }
+        Commands::Doctor => {
+            let datum = datum_client(&repo, account.as_deref()).await?;
+            let report = lib::doctor::run(&repo, &datum).await;
+            for check in &report.checks {
+                println!(
+                    "[{:>4}] {:<10} {}",
+                    check.status.label(),
+                    check.name,
+                    check.detail
+                );
+                if let Some(hint) = &check.hint {
+                    println!("       {:<10} -> {hint}", "");
+                }
+            }
+            if report.has_failures() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Status => match lib::control::agent_status(repo.path()).await? {
+            Some(status) => {
+                println!(
+                    "{} (pid {}, version {}) serves this repo",
+                    status.agent, status.pid, status.version
+                );
+                println!("endpoint id: {}", status.endpoint_id);
+                println!("enabled tunnels: {}", status.tunnels);
+            }
+            None => println!("no agent serves this repo"),
+        },
+        Commands::Usage { since, tunnel, csv } => {
+            let now = std::time::SystemTime::now();
+            let since = now - *since;
+            let records =
+                repo.bandwidth_history()
+                    .records(tunnel.as_deref(), since.into(), now.into());
+            let labels = repo.load_state().await?.get().proxy_labels();
+            if csv {
+                lib::bandwidth_history::write_csv(&records, &labels, std::io::stdout().lock())?;
+                return Ok(());
+            }
+            let mut totals = std::collections::BTreeMap::<&str, (u64, u64)>::new();
+            for record in &records {
+                let total = totals.entry(record.tunnel_id.as_str()).or_default();
+                total.0 += record.sent_bytes;
+                total.1 += record.received_bytes;
+            }
+            if totals.is_empty() {
+                println!("no traffic recorded");
+            }
+            for (id, (sent, received)) in totals {
+                let label = labels.get(id).map(String::as_str).unwrap_or("-");
+                println!("{id}\t{label}\tsent {sent} bytes\treceived {received} bytes");
+            }
+        }
+        Commands::RotateKey { grace } => {
+            if let Some(agent) = lib::control::agent_status(repo.path()).await? {
+                n0_error::bail_any!(
+                    "{} (pid {}) serves this repo with the current key, stop it before rotating",
+                    agent.agent,
+                    agent.pid
+                );
+            }
+            // Keeps an agent from starting with the previous key meanwhile.
+            let _control = LocalListener::bind(repo.path()).await?;
+            let mut node = Node::new(repo.clone()).await?;
+            let rotation = node.rotate_key(grace.into()).await?;
+            println!(
+                "rotated listen key: {} -> {}",
+                rotation.previous,
+                node.listen.endpoint_id()
+            );
+            let updated = match datum_client(&repo, account.as_deref()).await {
+                Ok(datum) => {
+                    TunnelService::new(datum, node.listen.clone())
+                        .update_connector_active()
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            match updated {
+                Ok(true) => println!("updated the connector with the new endpoint id"),
+                Ok(false) => println!("no connector for this device yet"),
+                Err(err) => eprintln!(
+                    "warning: failed to update the connector, `serve` retries when it starts: {err:#}"
+                ),
+            }
+            println!(
+                "the previous key is accepted until {}",
+                rotation.grace_until
+            );
+        }
+        Commands::Update { check, channel } => {
+            let checker = UpdateChecker::new(repo);
+            let channel = match channel {
+                Some(UpdateChannelArg::Stable) => UpdateChannel::Stable,
+                Some(UpdateChannelArg::Beta) => UpdateChannel::Beta,
+                None => checker.load_settings().await?.channel,
+            };
+            let Some(update) = checker.check_cli_update(channel).await? else {
+                println!("datum-connect {} is up to date", checker.current_version());
+                return Ok(());
+            };
+            println!(
+                "update available: {} -> {} ({}, {} bytes)",
+                checker.current_version(),
+                update.version,
+                update.release_name,
+                update.download_size
+            );
+            if check {
+                return Ok(());
+            }
+            let exe = std::env::current_exe()?;
+            checker.install_cli_update(&update, &exe).await?;
+            println!("updated {} to {}", exe.display(), update.version);
+        }
     }
     Ok(())
 }
+
+/// Datum Cloud client for `account`, or the repo's active account.
+async fn datum_client(repo: &Repo, account: Option<&str>) -> n0_error::Result<DatumCloudClient> {
+    match account {
+        Some(account) => {
+            DatumCloudClient::with_account(ApiEnv::default(), repo.clone(), account).await
+        }
+        None => DatumCloudClient::with_repo(ApiEnv::default(), repo.clone()).await,
+    }
+}
+
+/// Tunnel management for the project selected in the app.
+async fn tunnel_service(repo: Repo, account: Option<&str>) -> n0_error::Result<TunnelService> {
+    let datum = datum_client(&repo, account).await?;
+    let node = ListenNode::new(repo).await?;
+    Ok(TunnelService::new(datum, node))
+}
+
+/// Print what a bulk operation did, and exit with an error if any tunnel
+/// failed.
+fn report_bulk<T>(outcome: BulkOutcome<T>, done: &str) {
+    println!("{} tunnels {done}", outcome.succeeded.len());
+    for failure in &outcome.failed {
+        eprintln!("{}: {}", failure.tunnel_id, failure.error);
+    }
+    if !outcome.failed.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Put `tickets` on the clipboard, or ask the terminal to when there is no
+/// clipboard tool, e.g. over SSH.
+fn copy_tickets(tickets: &str) {
+    if tickets.is_empty() {
+        eprintln!("No enabled proxies, nothing copied");
+        return;
+    }
+    match lib::share::copy_to_clipboard(tickets) {
+        Ok(()) => eprintln!("Copied tickets to the clipboard"),
+        Err(err) if std::io::stdout().is_terminal() => {
+            debug!("clipboard tool failed, using the terminal: {err:#}");
+            print!("{}", lib::share::osc52(tickets));
+            eprintln!("Asked the terminal to copy the tickets");
+        }
+        Err(err) => eprintln!("Failed to copy tickets: {err:#}"),
+    }
+}
@@ -0,0 +1,402 @@
+//! Per-tunnel activity timelines.
+//!
+//! The [`EventLog`](crate::events::EventLog) keeps the newest events of the
+//! whole device, so a quiet tunnel's history is soon pushed out by a busy
+//! one. This keeps a small summary per tunnel instead: when it was created,
+//! when it first and last served a request, its peak bandwidth and bursts of
+//! refused requests. Summaries are stored in `activity.json` in the repo.
+//! Milestones are written right away; request times and peaks at most every
+//! [`SAVE_INTERVAL`].
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// How many error bursts are kept per tunnel.
+pub const MAX_ERROR_BURSTS: usize = 20;
+/// Refused requests this close together belong to the same burst.
+const BURST_WINDOW: TimeDelta = TimeDelta::seconds(60);
+/// Refused requests within [`BURST_WINDOW`] that make a burst.
+const BURST_THRESHOLD: usize = 10;
+/// Tunnels that served a request this recently count as active when
+/// throughput is sampled.
+const ACTIVE_WINDOW: TimeDelta = TimeDelta::seconds(5);
+/// Most time between writes of changes that aren't milestones.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelActivity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_request_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_request_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub requests: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_bandwidth: Option<PeakBandwidth>,
+    /// Oldest first, at most [`MAX_ERROR_BURSTS`].
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub error_bursts: VecDeque<ErrorBurst>,
+}
+
+/// The highest throughput seen while the tunnel was serving requests.
+///
+/// iroh counts traffic per endpoint, not per tunnel, so this is the node's
+/// throughput at a time the tunnel was active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeakBandwidth {
+    pub at: DateTime<Utc>,
+    pub bytes_per_sec: u64,
+}
+
+/// A run of refused requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBurst {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActivityKind {
+    Created,
+    FirstRequest,
+    LastRequest {
+        requests: u64,
+    },
+    PeakBandwidth {
+        bytes_per_sec: u64,
+    },
+    ErrorBurst {
+        errors: u64,
+        ended_at: DateTime<Utc>,
+    },
+}
+
+impl ActivityKind {
+    /// A one-line description for activity views.
+    pub fn description(&self) -> String {
+        match self {
+            ActivityKind::Created => "Created".to_string(),
+            ActivityKind::FirstRequest => "First request".to_string(),
+            ActivityKind::LastRequest { requests } => {
+                format!("Last request ({requests} in total)")
+            }
+            ActivityKind::PeakBandwidth { bytes_per_sec } => {
+                format!("Peak bandwidth of {bytes_per_sec} bytes/s")
+            }
+            ActivityKind::ErrorBurst { errors, ended_at } => {
+                format!(
+                    "{errors} refused requests until {}",
+                    ended_at.format("%H:%M:%S")
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityEntry {
+    pub at: DateTime<Utc>,
+    pub kind: ActivityKind,
+}
+
+impl TunnelActivity {
+    /// The milestones as a timeline, newest first.
+    pub fn timeline(&self) -> Vec<ActivityEntry> {
+        let mut entries = Vec::new();
+        let mut push = |at: Option<DateTime<Utc>>, kind| {
+            if let Some(at) = at {
+                entries.push(ActivityEntry { at, kind });
+            }
+        };
+        push(self.created_at, ActivityKind::Created);
+        push(self.first_request_at, ActivityKind::FirstRequest);
+        push(
+            self.last_request_at,
+            ActivityKind::LastRequest {
+                requests: self.requests,
+            },
+        );
+        if let Some(peak) = self.peak_bandwidth {
+            push(
+                Some(peak.at),
+                ActivityKind::PeakBandwidth {
+                    bytes_per_sec: peak.bytes_per_sec,
+                },
+            );
+        }
+        for burst in &self.error_bursts {
+            push(
+                Some(burst.started_at),
+                ActivityKind::ErrorBurst {
+                    errors: burst.errors,
+                    ended_at: burst.ended_at,
+                },
+            );
+        }
+        entries.sort_by(|a, b| b.at.cmp(&a.at));
+        entries
+    }
+}
+
+#[derive(derive_more::Debug, Clone)]
+pub struct ActivityLog {
+    path: Option<PathBuf>,
+    #[debug(skip)]
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    loaded: bool,
+    tunnels: BTreeMap<String, TunnelActivity>,
+    /// Times of recent refused requests per tunnel, for spotting bursts.
+    recent_errors: HashMap<String, VecDeque<DateTime<Utc>>>,
+    dirty: bool,
+    saved_at: Option<Instant>,
+}
+
+impl ActivityLog {
+    /// A log persisted at `path`. The file is read on first use.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self::new(Some(path.into()))
+    }
+
+    /// A log that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::new(None)
+    }
+
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn get(&self, tunnel_id: &str) -> Option<TunnelActivity> {
+        self.lock().tunnels.get(tunnel_id).cloned()
+    }
+
+    /// Note that a tunnel exists. Only the first call per tunnel counts.
+    pub fn record_created(&self, tunnel_id: &str) {
+        let mut inner = self.lock();
+        let activity = inner.tunnels.entry(tunnel_id.to_string()).or_default();
+        if activity.created_at.is_none() {
+            activity.created_at = Some(Utc::now());
+            self.save(&mut inner, true);
+        }
+    }
+
+    pub fn record_request(&self, tunnel_id: &str) {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        let activity = inner.tunnels.entry(tunnel_id.to_string()).or_default();
+        let first = activity.first_request_at.is_none();
+        activity.first_request_at.get_or_insert(now);
+        activity.last_request_at = Some(now);
+        activity.requests += 1;
+        self.save(&mut inner, first);
+    }
+
+    /// Note a refused request. Enough of them close together make a burst.
+    pub fn record_error(&self, tunnel_id: &str) {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        let recent = inner
+            .recent_errors
+            .entry(tunnel_id.to_string())
+            .or_default();
+        recent.push_back(now);
+        while recent.front().is_some_and(|at| now - *at > BURST_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() < BURST_THRESHOLD {
+            return;
+        }
+        let started_at = recent[0];
+        let count = recent.len() as u64;
+        let activity = inner.tunnels.entry(tunnel_id.to_string()).or_default();
+        match activity.error_bursts.back_mut() {
+            Some(burst) if now - burst.ended_at <= BURST_WINDOW => {
+                burst.ended_at = now;
+                burst.errors += 1;
+                self.save(&mut inner, false);
+            }
+            _ => {
+                activity.error_bursts.push_back(ErrorBurst {
+                    started_at,
+                    ended_at: now,
+                    errors: count,
+                });
+                while activity.error_bursts.len() > MAX_ERROR_BURSTS {
+                    activity.error_bursts.pop_front();
+                }
+                self.save(&mut inner, true);
+            }
+        }
+    }
+
+    /// Note the node's current throughput against every tunnel that served a
+    /// request within the last few seconds.
+    pub fn record_throughput(&self, bytes_per_sec: u64) {
+        if bytes_per_sec == 0 {
+            return;
+        }
+        let now = Utc::now();
+        let mut inner = self.lock();
+        let mut changed = false;
+        for activity in inner.tunnels.values_mut() {
+            let active = activity
+                .last_request_at
+                .is_some_and(|at| now - at <= ACTIVE_WINDOW);
+            let higher = activity
+                .peak_bandwidth
+                .is_none_or(|peak| bytes_per_sec > peak.bytes_per_sec);
+            if active && higher {
+                activity.peak_bandwidth = Some(PeakBandwidth {
+                    at: now,
+                    bytes_per_sec,
+                });
+                changed = true;
+            }
+        }
+        if changed {
+            self.save(&mut inner, false);
+        }
+    }
+
+    /// Forget a tunnel's activity.
+    pub fn remove(&self, tunnel_id: &str) {
+        let mut inner = self.lock();
+        inner.recent_errors.remove(tunnel_id);
+        if inner.tunnels.remove(tunnel_id).is_some() {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write pending changes, whether or not [`SAVE_INTERVAL`] has passed.
+    pub fn flush(&self) {
+        let mut inner = self.lock();
+        if inner.dirty {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write the log if `now` is set or the last write is old enough.
+    fn save(&self, inner: &mut Inner, now: bool) {
+        inner.dirty = true;
+        let Some(path) = &self.path else {
+            return;
+        };
+        let due = inner
+            .saved_at
+            .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL);
+        if !now && !due {
+            return;
+        }
+        match write(path, &inner.tunnels) {
+            Ok(()) => {
+                inner.dirty = false;
+                inner.saved_at = Some(Instant::now());
+            }
+            Err(err) => warn!("Failed to persist activity to {}: {err:#}", path.display()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        if !inner.loaded {
+            inner.loaded = true;
+            if let Some(path) = &self.path {
+                match std::fs::read(path) {
+                    Ok(data) => match serde_json::from_slice(&data) {
+                        Ok(tunnels) => inner.tunnels = tunnels,
+                        Err(err) => warn!("Ignoring unreadable {}: {err}", path.display()),
+                    },
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => warn!("Failed to read activity from {}: {err:#}", path.display()),
+                }
+            }
+        }
+        inner
+    }
+}
+
+fn write(path: &PathBuf, tunnels: &BTreeMap<String, TunnelActivity>) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(tunnels)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_milestones_and_persists_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("activity.json");
+        let log = ActivityLog::open(&path);
+        log.record_created("a");
+        log.record_created("a");
+        log.record_request("a");
+        log.record_request("a");
+        log.record_throughput(2048);
+        log.record_throughput(1024);
+        log.record_throughput(4096);
+        log.record_request("b");
+        log.flush();
+
+        let activity = ActivityLog::open(&path).get("a").unwrap();
+        assert!(activity.created_at.is_some());
+        assert!(activity.first_request_at <= activity.last_request_at);
+        assert_eq!(activity.requests, 2);
+        assert_eq!(activity.peak_bandwidth.unwrap().bytes_per_sec, 4096);
+
+        let timeline = activity.timeline();
+        assert_eq!(timeline.len(), 4);
+        assert!(timeline.windows(2).all(|pair| pair[0].at >= pair[1].at));
+        assert!(
+            timeline
+                .iter()
+                .any(|entry| entry.kind == ActivityKind::Created)
+        );
+
+        log.remove("a");
+        assert_eq!(ActivityLog::open(&path).get("a"), None);
+        assert!(ActivityLog::open(&path).get("b").is_some());
+    }
+
+    #[test]
+    fn groups_refused_requests_into_bursts() {
+        let log = ActivityLog::in_memory();
+        for _ in 0..BURST_THRESHOLD - 1 {
+            log.record_error("a");
+        }
+        assert_eq!(log.get("a"), None);
+
+        log.record_error("a");
+        for _ in 0..5 {
+            log.record_error("a");
+        }
+        let bursts = log.get("a").unwrap().error_bursts;
+        assert_eq!(bursts.len(), 1);
+        assert_eq!(bursts[0].errors, BURST_THRESHOLD as u64 + 5);
+        assert!(bursts[0].started_at <= bursts[0].ended_at);
+
+        // Throughput only counts for tunnels that served requests.
+        log.record_throughput(1000);
+        assert_eq!(log.get("a").unwrap().peak_bandwidth, None);
+    }
+}
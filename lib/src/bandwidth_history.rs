@@ -0,0 +1,440 @@
+//! Bandwidth history of tunnels, for charts that go back further than the
+//! moment a view was opened.
+//!
+//! Every [`SAMPLE_INTERVAL`] the per-tunnel byte counters of the
+//! [usage log](crate::usage) are appended to one ring buffer per
+//! [`HistoryRange`]. Each ring keeps a point every [`HistoryRange::step`] for
+//! the length of its range, so a week of history is as small as an hour of
+//! it. Rates are computed from the counters when the history is queried.
+//! Rings are stored in `bandwidth.json` in the repo at most every
+//! [`SAVE_INTERVAL`].
+//!
+//! Like usage, the split between tunnels is an estimate while several tunnels
+//! are busy at once.
+//!
+//! [`BandwidthHistory::records`] and [`write_csv`] turn the history into
+//! reports, e.g. for chargeback.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{Repo, usage::TunnelUsage};
+
+/// How often the counters are sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+/// Most time between writes of the history file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How far back a chart looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryRange {
+    Hour,
+    Day,
+    Week,
+}
+
+impl HistoryRange {
+    pub const ALL: [Self; 3] = [Self::Hour, Self::Day, Self::Week];
+
+    pub fn duration(self) -> TimeDelta {
+        match self {
+            Self::Hour => TimeDelta::hours(1),
+            Self::Day => TimeDelta::days(1),
+            Self::Week => TimeDelta::weeks(1),
+        }
+    }
+
+    /// Time between the points of this range.
+    pub fn step(self) -> TimeDelta {
+        match self {
+            Self::Hour => TimeDelta::seconds(10),
+            Self::Day => TimeDelta::minutes(5),
+            Self::Week => TimeDelta::minutes(30),
+        }
+    }
+
+    /// The finest range that reaches back `period`, or the longest one.
+    pub fn covering(period: TimeDelta) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|range| range.duration() >= period)
+            .unwrap_or(Self::Week)
+    }
+}
+
+/// Throughput of a tunnel over the step before `at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthPoint {
+    pub at: DateTime<Utc>,
+    pub send_per_s: u64,
+    pub recv_per_s: u64,
+}
+
+/// Bytes a tunnel transferred between two points of its history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageRecord {
+    pub tunnel_id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+}
+
+/// A tunnel's byte counters at one time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Sample {
+    at: DateTime<Utc>,
+    sent: u64,
+    received: u64,
+}
+
+/// The rings of one tunnel.
+type Rings = BTreeMap<HistoryRange, VecDeque<Sample>>;
+
+#[derive(derive_more::Debug, Clone)]
+pub struct BandwidthHistory {
+    path: Option<PathBuf>,
+    #[debug(skip)]
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    loaded: bool,
+    tunnels: BTreeMap<String, Rings>,
+    dirty: bool,
+    saved_at: Option<Instant>,
+}
+
+impl BandwidthHistory {
+    /// A history persisted at `path`. The file is read on first use.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self::new(Some(path.into()))
+    }
+
+    /// A history that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::new(None)
+    }
+
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            inner: Default::default(),
+        }
+    }
+
+    /// Add the counters of every tunnel in `usage` to the rings whose step
+    /// has passed since their last point.
+    pub fn record(&self, now: DateTime<Utc>, usage: &BTreeMap<String, TunnelUsage>) {
+        let mut inner = self.lock();
+        // Sampling runs late now and then, which must not skip a point.
+        let slack = TimeDelta::from_std(SAMPLE_INTERVAL).unwrap_or_default() / 2;
+        for (tunnel_id, usage) in usage {
+            let rings = inner.tunnels.entry(tunnel_id.clone()).or_default();
+            for range in HistoryRange::ALL {
+                let ring = rings.entry(range).or_default();
+                if ring
+                    .back()
+                    .is_some_and(|last| now - last.at < range.step() - slack)
+                {
+                    continue;
+                }
+                ring.push_back(Sample {
+                    at: now,
+                    sent: usage.sent_bytes,
+                    received: usage.received_bytes,
+                });
+                // One point before the range is needed for the first rate.
+                let start = now - range.duration() - range.step();
+                while ring.front().is_some_and(|first| first.at < start) {
+                    ring.pop_front();
+                }
+            }
+        }
+        self.save(&mut inner, false);
+    }
+
+    /// The throughput of a tunnel over `range` before `now`, oldest first.
+    pub fn query(
+        &self,
+        tunnel_id: &str,
+        range: HistoryRange,
+        now: DateTime<Utc>,
+    ) -> Vec<BandwidthPoint> {
+        let inner = self.lock();
+        let Some(ring) = inner
+            .tunnels
+            .get(tunnel_id)
+            .and_then(|rings| rings.get(&range))
+        else {
+            return Vec::new();
+        };
+        steps(ring, now - range.duration())
+            .map(|(a, b)| {
+                let secs = (b.at - a.at).num_milliseconds().max(1) as f64 / 1000.0;
+                let rate = |bytes: u64| (bytes as f64 / secs) as u64;
+                BandwidthPoint {
+                    at: b.at,
+                    send_per_s: rate(b.sent.saturating_sub(a.sent)),
+                    recv_per_s: rate(b.received.saturating_sub(a.received)),
+                }
+            })
+            .collect()
+    }
+
+    /// Bytes transferred per step since `since`, from the finest ring that
+    /// reaches back that far. Covers all tunnels unless `tunnel_id` is set,
+    /// ordered by tunnel and then time. Steps without traffic are left out.
+    pub fn records(
+        &self,
+        tunnel_id: Option<&str>,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<UsageRecord> {
+        let range = HistoryRange::covering(now - since);
+        let inner = self.lock();
+        inner
+            .tunnels
+            .iter()
+            .filter(|(id, _)| tunnel_id.is_none_or(|tunnel_id| tunnel_id == *id))
+            .filter_map(|(id, rings)| Some((id, rings.get(&range)?)))
+            .flat_map(|(id, ring)| {
+                steps(ring, since).map(|(a, b)| UsageRecord {
+                    tunnel_id: id.clone(),
+                    start: a.at,
+                    end: b.at,
+                    sent_bytes: b.sent.saturating_sub(a.sent),
+                    received_bytes: b.received.saturating_sub(a.received),
+                })
+            })
+            .filter(|record| record.sent_bytes > 0 || record.received_bytes > 0)
+            .collect()
+    }
+
+    /// Forget a tunnel's history.
+    pub fn remove(&self, tunnel_id: &str) {
+        let mut inner = self.lock();
+        if inner.tunnels.remove(tunnel_id).is_some() {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write pending changes, whether or not [`SAVE_INTERVAL`] has passed.
+    pub fn flush(&self) {
+        let mut inner = self.lock();
+        if inner.dirty {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write the history if `now` is set or the last write is old enough.
+    fn save(&self, inner: &mut Inner, now: bool) {
+        inner.dirty = true;
+        let Some(path) = &self.path else {
+            return;
+        };
+        let due = inner
+            .saved_at
+            .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL);
+        if !now && !due {
+            return;
+        }
+        match write(path, &inner.tunnels) {
+            Ok(()) => {
+                inner.dirty = false;
+                inner.saved_at = Some(Instant::now());
+            }
+            Err(err) => warn!("Failed to persist bandwidth to {}: {err:#}", path.display()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        if !inner.loaded {
+            inner.loaded = true;
+            if let Some(path) = &self.path {
+                match std::fs::read(path) {
+                    Ok(data) => match serde_json::from_slice(&data) {
+                        Ok(tunnels) => inner.tunnels = tunnels,
+                        Err(err) => warn!("Ignoring unreadable {}: {err}", path.display()),
+                    },
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => warn!("Failed to read bandwidth from {}: {err:#}", path.display()),
+                }
+            }
+        }
+        inner
+    }
+}
+
+/// Pairs of consecutive samples, for the steps that end after `start`.
+fn steps(
+    ring: &VecDeque<Sample>,
+    start: DateTime<Utc>,
+) -> impl Iterator<Item = (&Sample, &Sample)> {
+    // Counters start over when a tunnel's usage was reset, so callers
+    // subtract with saturation.
+    ring.iter()
+        .zip(ring.iter().skip(1))
+        .filter(move |(_, b)| b.at > start)
+}
+
+/// Write `records` as CSV with a header row. `labels` maps tunnel ids to the
+/// labels to show next to them.
+pub fn write_csv(
+    records: &[UsageRecord],
+    labels: &BTreeMap<String, String>,
+    mut out: impl std::io::Write,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "tunnel_id,label,start,end,sent_bytes,received_bytes,total_bytes"
+    )?;
+    for record in records {
+        let label = labels.get(&record.tunnel_id).map(String::as_str);
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            csv_field(&record.tunnel_id),
+            csv_field(label.unwrap_or_default()),
+            record.start.to_rfc3339_opts(SecondsFormat::Secs, true),
+            record.end.to_rfc3339_opts(SecondsFormat::Secs, true),
+            record.sent_bytes,
+            record.received_bytes,
+            record.sent_bytes.saturating_add(record.received_bytes),
+        )?;
+    }
+    Ok(())
+}
+
+/// `value` quoted if it needs to be, per RFC 4180.
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+fn write(path: &PathBuf, tunnels: &BTreeMap<String, Rings>) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(tunnels)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Sample the usage counters into the history. Runs until aborted.
+pub(crate) async fn run(repo: Repo) {
+    loop {
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+        repo.bandwidth_history()
+            .record(Utc::now(), &repo.usage().snapshot());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(sent: u64, received: u64) -> BTreeMap<String, TunnelUsage> {
+        let usage = TunnelUsage {
+            sent_bytes: sent,
+            received_bytes: received,
+            ..Default::default()
+        };
+        BTreeMap::from([("a".to_string(), usage)])
+    }
+
+    #[test]
+    fn keeps_rings_per_range_and_persists_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bandwidth.json");
+        let history = BandwidthHistory::open(&path);
+        let start = Utc::now() - TimeDelta::hours(2);
+        for i in 0..720 {
+            let at = start + TimeDelta::seconds(10 * i);
+            history.record(at, &usage(i as u64 * 1000, i as u64 * 100));
+        }
+        history.flush();
+
+        let history = BandwidthHistory::open(&path);
+        let now = start + TimeDelta::seconds(10 * 719);
+        let hour = history.query("a", HistoryRange::Hour, now);
+        assert_eq!(hour.len(), 360);
+        assert!(hour.iter().all(|point| point.send_per_s == 100));
+        assert!(hour.iter().all(|point| point.recv_per_s == 10));
+        assert!(hour.windows(2).all(|pair| pair[0].at < pair[1].at));
+        let day = history.query("a", HistoryRange::Day, now);
+        assert_eq!(day.len(), 23);
+        assert_eq!(day[0].send_per_s, 100);
+        assert!(history.query("b", HistoryRange::Week, now).is_empty());
+
+        history.remove("a");
+        assert!(history.query("a", HistoryRange::Hour, now).is_empty());
+    }
+
+    #[test]
+    fn exports_records_as_csv() {
+        let history = BandwidthHistory::in_memory();
+        let start = Utc::now() - TimeDelta::minutes(3);
+        for i in 0..10 {
+            let at = start + TimeDelta::seconds(10 * i);
+            // Traffic only in the first half.
+            let bytes = i.min(5) as u64 * 100;
+            history.record(at, &usage(bytes, bytes / 2));
+        }
+        let now = start + TimeDelta::seconds(90);
+        let records = history.records(None, now - TimeDelta::minutes(10), now);
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].start, start);
+        assert_eq!(records[0].sent_bytes, 100);
+        assert_eq!(records[0].received_bytes, 50);
+        assert!(history.records(Some("b"), start, now).is_empty());
+        assert_eq!(
+            HistoryRange::covering(TimeDelta::days(2)),
+            HistoryRange::Week
+        );
+        assert_eq!(
+            HistoryRange::covering(TimeDelta::days(30)),
+            HistoryRange::Week
+        );
+
+        let labels = BTreeMap::from([("a".to_string(), "web, \"prod\"".to_string())]);
+        let mut csv = Vec::new();
+        write_csv(&records[..1], &labels, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("tunnel_id,label,start,end,sent_bytes,received_bytes,total_bytes")
+        );
+        let row = lines.next().unwrap();
+        assert!(row.starts_with("a,\"web, \"\"prod\"\"\","), "{row}");
+        assert!(row.ends_with(",100,50,150"), "{row}");
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn tolerates_late_samples_and_reset_counters() {
+        let history = BandwidthHistory::in_memory();
+        let start = Utc::now();
+        history.record(start, &usage(5000, 0));
+        history.record(start + TimeDelta::seconds(8), &usage(6000, 0));
+        history.record(start + TimeDelta::seconds(9), &usage(7000, 0));
+        let points = history.query("a", HistoryRange::Hour, start + TimeDelta::seconds(9));
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].send_per_s, 125);
+        history.record(start + TimeDelta::seconds(20), &usage(0, 0));
+        let points = history.query("a", HistoryRange::Hour, start + TimeDelta::seconds(20));
+        assert_eq!(points[1].send_per_s, 0);
+    }
+}
@@ -0,0 +1,270 @@
+//! Streaming compression of HTTP bodies.
+//!
+//! Bodies are coded frame by frame, and every frame is flushed through the
+//! coder, so streamed responses like server-sent events keep arriving as
+//! they are written, at some cost in ratio. Trailers pass through as they
+//! are.
+
+use std::{
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use brotli::CompressorWriter;
+use flate2::{
+    Compression,
+    write::{GzDecoder, GzEncoder},
+};
+use hyper::{
+    body::{Body, Bytes, Frame},
+    header::{self, HeaderMap, HeaderValue},
+};
+
+pub(crate) const GZIP: &str = "gzip";
+pub(crate) const BROTLI: &str = "br";
+
+/// Brotli quality for bodies compressed on the fly, fast rather than small.
+const BROTLI_QUALITY: u32 = 4;
+/// Brotli window, as the base-2 logarithm of its size.
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER: usize = 4096;
+
+/// `body` compressed with gzip.
+pub(crate) fn gzip<B>(body: B) -> Coded<B> {
+    Coded::new(
+        body,
+        Coder::GzipEncode(GzEncoder::new(Vec::new(), Compression::default())),
+    )
+}
+
+/// `body` compressed with brotli.
+pub(crate) fn brotli<B>(body: B) -> Coded<B> {
+    let encoder = CompressorWriter::new(Vec::new(), BROTLI_BUFFER, BROTLI_QUALITY, BROTLI_WINDOW);
+    Coded::new(body, Coder::BrotliEncode(Box::new(encoder)))
+}
+
+/// `body` decompressed from gzip.
+pub(crate) fn gunzip<B>(body: B) -> Coded<B> {
+    Coded::new(body, Coder::GzipDecode(GzDecoder::new(Vec::new())))
+}
+
+/// Whether a response with `headers` is worth compressing: text-like, not
+/// compressed already, and not known to be shorter than `min_bytes`.
+pub(crate) fn compressible(headers: &HeaderMap<HeaderValue>, min_bytes: u64) -> bool {
+    if headers.contains_key(header::CONTENT_ENCODING) {
+        return false;
+    }
+    let short = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_some_and(|len| len < min_bytes);
+    if short {
+        return false;
+    }
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(compressible_type)
+}
+
+/// Text and structured data. Images, video, audio, archives and anything
+/// else that is compressed already isn't worth it, and neither is a body of
+/// unknown type.
+fn compressible_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/x-ndjson"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "font/ttf"
+                | "font/otf"
+        )
+}
+
+enum Coder {
+    GzipEncode(GzEncoder<Vec<u8>>),
+    GzipDecode(GzDecoder<Vec<u8>>),
+    BrotliEncode(Box<CompressorWriter<Vec<u8>>>),
+}
+
+impl Coder {
+    /// Code `data` and return what came out of it so far.
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::GzipEncode(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::GzipDecode(decoder) => {
+                decoder.write_all(data)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Self::BrotliEncode(encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// End the stream and return the rest of the output.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::GzipEncode(encoder) => encoder.finish(),
+            Self::GzipDecode(decoder) => decoder.finish(),
+            Self::BrotliEncode(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+/// A body run through a [`Coder`].
+pub(crate) struct Coded<B> {
+    inner: B,
+    /// `None` once the inner body ended.
+    coder: Option<Coder>,
+    /// The inner body's trailers, sent after the rest of the output.
+    trailers: Option<HeaderMap>,
+}
+
+impl<B> Coded<B> {
+    fn new(inner: B, coder: Coder) -> Self {
+        Self {
+            inner,
+            coder: Some(coder),
+            trailers: None,
+        }
+    }
+}
+
+impl<B> Body for Coded<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        let this = &mut *self;
+        loop {
+            let Some(coder) = this.coder.as_mut() else {
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            };
+            let out = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(frame) => match frame.map_err(io::Error::other)?.into_data() {
+                    Ok(data) => coder.write(&data)?,
+                    Err(frame) => {
+                        this.trailers = frame.into_trailers().ok();
+                        this.coder.take().expect("coder").finish()?
+                    }
+                },
+                None => this.coder.take().expect("coder").finish()?,
+            };
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.coder.is_none() && self.trailers.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::{BodyExt, StreamBody};
+
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn gzip_round_trips_with_trailers() {
+        let text = "hello, compressed world\n".repeat(1000);
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let frames = text
+            .as_bytes()
+            .chunks(700)
+            .map(|chunk| Ok::<_, io::Error>(Frame::data(Bytes::copy_from_slice(chunk))))
+            .chain([Ok(Frame::trailers(trailers.clone()))])
+            .collect::<Vec<_>>();
+        let body = StreamBody::new(n0_future::stream::iter(frames));
+
+        let collected = gunzip(gzip(body)).collect().await.unwrap();
+        assert_eq!(collected.trailers(), Some(&trailers));
+        assert_eq!(collected.to_bytes(), text.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn brotli_bodies_decode() {
+        let text = "hello, brotli\n".repeat(1000);
+        let frames = text
+            .as_bytes()
+            .chunks(700)
+            .map(|chunk| Ok::<_, io::Error>(Frame::data(Bytes::copy_from_slice(chunk))))
+            .collect::<Vec<_>>();
+        let body = StreamBody::new(n0_future::stream::iter(frames));
+
+        let compressed = brotli(body).collect().await.unwrap().to_bytes();
+        assert!(compressed.len() < text.len() / 10);
+        let mut decoded = String::new();
+        io::Read::read_to_string(
+            &mut ::brotli::Decompressor::new(&compressed[..], 4096),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn only_text_like_bodies_are_compressible() {
+        let json = headers(&[(header::CONTENT_TYPE, "application/json; charset=utf-8")]);
+        assert!(compressible(&json, 1024));
+        let html = headers(&[
+            (header::CONTENT_TYPE, "text/html"),
+            (header::CONTENT_LENGTH, "100"),
+        ]);
+        assert!(!compressible(&html, 1024));
+        assert!(compressible(&html, 64));
+        let encoded = headers(&[
+            (header::CONTENT_TYPE, "text/html"),
+            (header::CONTENT_ENCODING, "br"),
+        ]);
+        assert!(!compressible(&encoded, 0));
+        for content_type in [
+            "image/png",
+            "video/mp4",
+            "application/zip",
+            "application/grpc",
+        ] {
+            let media = headers(&[(header::CONTENT_TYPE, content_type)]);
+            assert!(!compressible(&media, 0), "{content_type}");
+        }
+        assert!(!compressible(&HeaderMap::new(), 0));
+    }
+}
@@ -1,11 +1,19 @@
 use std::{
+    collections::BTreeMap,
     fs,
-    net::{SocketAddr, SocketAddrV4, SocketAddrV6},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     path::PathBuf,
 };
 
+use iroh::{
+    EndpointAddr, EndpointId, RelayMap, RelayMode, RelayUrl, TransportAddr,
+    endpoint::default_relay_mode,
+};
 use n0_error::{Result, StackResultExt, StdResultExt};
 use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{TunnelTimeouts, ip_filter::IpCidr, nat64, outbound_proxy::OutboundProxy};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
@@ -17,6 +25,60 @@ pub enum DiscoveryMode {
     Dns,
     /// Use both n0des defaults and DNS discovery.
     Hybrid,
+    /// Use only the configured `static_endpoints`, no discovery service.
+    Static,
+}
+
+/// Which relay servers the endpoint uses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Relays {
+    /// n0's public relays.
+    #[default]
+    Default,
+    /// No relays. Endpoints are only reached over direct connections.
+    Disabled,
+    /// Only these relays, e.g. self-hosted ones.
+    Custom(Vec<RelayUrl>),
+}
+
+/// An endpoint whose addresses are known up front, so it is reached without
+/// asking a discovery service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StaticEndpoint {
+    pub id: EndpointId,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addrs: Vec<SocketAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_url: Option<RelayUrl>,
+}
+
+impl StaticEndpoint {
+    pub fn addr(&self) -> EndpointAddr {
+        let addrs = self
+            .addrs
+            .iter()
+            .map(|addr| TransportAddr::Ip(*addr))
+            .chain(self.relay_url.clone().map(TransportAddr::Relay));
+        EndpointAddr::from_parts(self.id, addrs)
+    }
+}
+
+/// Which IP versions the endpoint uses.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// IPv4 and IPv6, whichever works.
+    #[default]
+    Any,
+    /// IPv4 and IPv6, but listeners default to IPv6 (dual-stack where the
+    /// OS allows it).
+    PreferIpv6,
+    /// Only IPv6, for IPv6-only hosts. iroh always opens an IPv4 socket, so
+    /// it is bound to loopback and no IPv4 traffic leaves the host. IPv4
+    /// literals in the configuration are reached through NAT64.
+    Ipv6Only,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -49,6 +111,154 @@ pub struct Config {
     /// Useful for local development (e.g. 127.0.0.1:53535).
     #[serde(default)]
     pub dns_resolver: Option<SocketAddr>,
+
+    /// Pkarr relay this endpoint publishes its address to, e.g. a self-hosted
+    /// iroh-dns-server serving `dns_origin`.
+    ///
+    /// Only used when discovery_mode is `dns` or `hybrid`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkarr_relay: Option<Url>,
+
+    /// Endpoints to reach at fixed addresses, on top of discovery.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_endpoints: Vec<StaticEndpoint>,
+
+    /// Relay servers to use instead of n0's public ones, or none.
+    #[serde(default)]
+    pub relays: Relays,
+
+    /// Only use direct paths, e.g. on a LAN, for every tunnel this device
+    /// serves or joins. Turns off relays regardless of `relays`, so remotes
+    /// that can't be reached directly fail instead of being relayed.
+    #[serde(default)]
+    pub direct_only: bool,
+
+    /// Announce served tunnels on the local network and find the ones other
+    /// devices announce, so they can be joined by codename without n0des or
+    /// Datum Cloud. See [`crate::local_discovery`].
+    #[serde(default)]
+    pub local_discovery: bool,
+
+    /// How often, in seconds, the agent publishes the tickets of its tunnels
+    /// to n0des again, so gateways can tell a running agent from a crashed
+    /// one by the ticket's age. Defaults to 30.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ticket_refresh_secs: Option<u64>,
+
+    /// Idle timeout and keepalive for tunnels this device joins.
+    ///
+    /// Listening tunnels carry their own settings in the local state.
+    #[serde(default)]
+    pub connect_timeouts: TunnelTimeouts,
+
+    /// Which IP versions to use.
+    #[serde(default)]
+    pub ip_family: IpFamily,
+
+    /// The network's NAT64 /96 prefix, used in `ipv6_only` mode.
+    ///
+    /// If unset, it is discovered through DNS64 and falls back to the
+    /// well-known `64:ff9b::`.
+    #[serde(default)]
+    pub nat64_prefix: Option<Ipv6Addr>,
+
+    /// Addresses for tunnel target hosts the system resolver doesn't know,
+    /// like docker service names or split-horizon DNS entries.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, IpAddr>,
+
+    /// DNS server to resolve tunnel target hosts with instead of the system
+    /// resolver, e.g. the one of a VPN or a container network. Names in
+    /// `hosts` win; names the server doesn't know fall back to the system
+    /// resolver. See [`crate::host_bridge`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_dns_server: Option<SocketAddr>,
+
+    /// Most tunnels this device creates in a project, by project id, on top
+    /// of the project's quota in Datum Cloud. Useful to keep a shared
+    /// project from filling up.
+    #[serde(default)]
+    pub tunnel_limits: BTreeMap<String, u64>,
+
+    /// HTTP(S) proxy for requests to Datum Cloud. Unset parts are taken from
+    /// the environment, see [`crate::outbound_proxy`].
+    #[serde(default)]
+    pub outbound_proxy: OutboundProxy,
+
+    /// Where request bodies go that have to be read in full before they are
+    /// forwarded: by the gateway, when an agent needs the length of a body
+    /// sent without one, and by the agent, to send idempotent requests again
+    /// after the connection to the service failed.
+    #[serde(default)]
+    pub request_spool: SpoolConfig,
+
+    /// Gzip response bodies for gateways that offer to take them compressed.
+    #[serde(default)]
+    pub tunnel_compression: TunnelCompressionConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TunnelCompressionConfig {
+    /// Compress text-like responses to gateways whose HTTP/2 upstream asks
+    /// for it. Responses that are compressed already, media and archives
+    /// are sent as they are.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Responses known to be shorter than this are sent as they are.
+    /// Defaults to 1 KiB.
+    #[serde(default = "default_tunnel_compression_min_bytes")]
+    pub min_bytes: u64,
+}
+
+impl Default for TunnelCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_bytes: default_tunnel_compression_min_bytes(),
+        }
+    }
+}
+
+fn default_tunnel_compression_min_bytes() -> u64 {
+    1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SpoolConfig {
+    /// Bodies up to this size are kept in memory, larger ones in a temporary
+    /// file that is removed when the request is done. Defaults to 1 MiB.
+    #[serde(default = "default_spool_memory_bytes")]
+    pub memory_bytes: u64,
+
+    /// Largest body that is read in full; larger ones get a 413. Defaults to
+    /// 4 GiB.
+    #[serde(default = "default_spool_max_bytes")]
+    pub max_bytes: u64,
+
+    /// Directory for the temporary files, instead of the system's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            memory_bytes: default_spool_memory_bytes(),
+            max_bytes: default_spool_max_bytes(),
+            dir: None,
+        }
+    }
+}
+
+fn default_spool_memory_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_spool_max_bytes() -> u64 {
+    4 * 1024 * 1024 * 1024
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -56,9 +266,623 @@ pub struct Config {
 pub struct GatewayConfig {
     #[serde(flatten)]
     pub common: Config,
+
+    /// Ownership checks for custom hostnames served by this gateway.
+    #[serde(default)]
+    pub hostname_verification: HostnameVerificationConfig,
+
+    /// Fail fast with 503 for endpoints that don't answer a dial.
+    #[serde(default)]
+    pub liveness: LivenessConfig,
+
+    /// Route requests for `<codename>.<domain>` by the tickets agents publish
+    /// to n0des, when they don't name an endpoint.
+    #[serde(default)]
+    pub tickets: TicketsConfig,
+
+    /// Largest request headers the gateway forwards; larger ones get a 431.
+    #[serde(default)]
+    pub header_limits: HeaderLimitsConfig,
+
+    /// How the client address is found for per-tunnel IP allow and deny lists.
+    #[serde(default)]
+    pub client_ip: ClientIpConfig,
+
+    /// Time and body size limits for requests; over them a request gets a
+    /// 504 or 413.
+    #[serde(default)]
+    pub request_limits: RequestLimitsConfig,
+
+    /// One record per request, to stdout or a rotating file.
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+
+    /// How the gateway picks between several endpoints serving a tunnel.
+    #[serde(default)]
+    pub balancing: BalancingConfig,
+
+    /// Latency histograms in the metrics output.
+    #[serde(default)]
+    pub metrics: GatewayMetricsConfig,
+
+    /// How requests are sent on to the agents.
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+
+    /// Compress responses for clients that accept it.
+    #[serde(default)]
+    pub response_compression: ResponseCompressionConfig,
+
+    /// Also serve on a Unix domain socket at this path, e.g. for an Envoy
+    /// sidecar. It shares the TCP listener's endpoint, metrics and access
+    /// log. Ignored on Windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uds_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct HostnameVerificationConfig {
+    /// Require custom hostnames to carry a verification record before the
+    /// gateway forwards requests for them.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Domains managed by the gateway operator. Hostnames equal to or below
+    /// one of these are always served without verification.
+    #[serde(default = "default_managed_domains")]
+    pub managed_domains: Vec<String>,
+
+    /// How long a successful verification is cached, in seconds.
+    #[serde(default = "default_verification_ttl_secs")]
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for HostnameVerificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            managed_domains: default_managed_domains(),
+            cache_ttl_secs: default_verification_ttl_secs(),
+        }
+    }
+}
+
+fn default_managed_domains() -> Vec<String> {
+    vec![crate::DATUM_CONNECT_GATEWAY_DOMAIN_NAME.to_string()]
+}
+
+fn default_verification_ttl_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LivenessConfig {
+    /// Probe endpoints before forwarding to them and answer 503 if they are
+    /// unreachable, instead of waiting for the full connect timeout.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How long a probe dial may take before the endpoint counts as down,
+    /// in milliseconds.
+    #[serde(default = "default_probe_timeout_ms")]
+    pub probe_timeout_ms: u64,
+
+    /// How long a reachable endpoint is remembered, in seconds.
+    #[serde(default = "default_live_ttl_secs")]
+    pub live_ttl_secs: u64,
+
+    /// How much longer a reachable endpoint is still served while it is
+    /// probed again in the background, in seconds.
+    #[serde(default = "default_stale_ttl_secs")]
+    pub stale_ttl_secs: u64,
+
+    /// How long an unreachable endpoint is remembered, in seconds.
+    #[serde(default = "default_dead_ttl_secs")]
+    pub dead_ttl_secs: u64,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            probe_timeout_ms: default_probe_timeout_ms(),
+            live_ttl_secs: default_live_ttl_secs(),
+            stale_ttl_secs: default_stale_ttl_secs(),
+            dead_ttl_secs: default_dead_ttl_secs(),
+        }
+    }
+}
+
+fn default_probe_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_live_ttl_secs() -> u64 {
+    30
+}
+
+fn default_stale_ttl_secs() -> u64 {
+    30
+}
+
+fn default_dead_ttl_secs() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TicketsConfig {
+    /// Look up the ticket published under a request's codename when the
+    /// request has no `x-iroh-endpoint-id` header. Needs `N0DES_API_SECRET`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Hostnames one label below one of these domains are looked up by that
+    /// label.
+    #[serde(default = "default_managed_domains")]
+    pub domains: Vec<String>,
+
+    /// How long a ticket is served from the cache, in seconds.
+    #[serde(default = "default_ticket_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// How much longer a cached ticket is still served while it is fetched
+    /// again in the background, in seconds.
+    #[serde(default = "default_ticket_stale_secs")]
+    pub stale_secs: u64,
+
+    /// How long a codename without a ticket is remembered, in seconds.
+    #[serde(default = "default_ticket_negative_ttl_secs")]
+    pub negative_ttl_secs: u64,
+
+    /// Drop a cached ticket after its endpoint failed this many dials in a
+    /// row, so the next request fetches it again. Dials are only made with
+    /// liveness checks on.
+    #[serde(default = "default_ticket_max_connect_failures")]
+    pub max_connect_failures: u32,
+
+    /// Answer 503 without dialing for tickets published longer ago than
+    /// this, in seconds. Agents publish their tickets again every
+    /// `ticket_refresh_secs`, so an older ticket belongs to an agent that
+    /// stopped.
+    #[serde(default = "default_max_ticket_age_secs")]
+    pub max_ticket_age_secs: u64,
+
+    /// Also answer 503 for tickets without a publish time, from agents that
+    /// don't refresh them. Off by default, so those agents keep working.
+    #[serde(default)]
+    pub require_published_at: bool,
+
+    /// Subscribe to ticket changes from n0des, so a ticket that is published
+    /// again or unpublished is fetched again on the next request instead of
+    /// after `ttl_secs`. The cache TTLs still apply when n0des doesn't push
+    /// changes.
+    #[serde(default = "default_ticket_subscribe")]
+    pub subscribe: bool,
+
+    /// Cache lookups in the Redis server at this URL, like
+    /// `redis://cache:6379/0`, so all gateway replicas share them. Needs the
+    /// gateway built with the `redis` feature. Unset caches in process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis_url: Option<String>,
+}
+
+impl Default for TicketsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            domains: default_managed_domains(),
+            ttl_secs: default_ticket_ttl_secs(),
+            stale_secs: default_ticket_stale_secs(),
+            negative_ttl_secs: default_ticket_negative_ttl_secs(),
+            max_connect_failures: default_ticket_max_connect_failures(),
+            max_ticket_age_secs: default_max_ticket_age_secs(),
+            require_published_at: false,
+            subscribe: default_ticket_subscribe(),
+            redis_url: None,
+        }
+    }
+}
+
+fn default_ticket_ttl_secs() -> u64 {
+    30
+}
+
+fn default_ticket_stale_secs() -> u64 {
+    60
+}
+
+fn default_ticket_negative_ttl_secs() -> u64 {
+    5
+}
+
+fn default_ticket_max_connect_failures() -> u32 {
+    3
+}
+
+fn default_max_ticket_age_secs() -> u64 {
+    90
+}
+
+fn default_ticket_subscribe() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct HeaderLimitsConfig {
+    /// Most header fields a request may carry. Repeated names count once per
+    /// field.
+    #[serde(default = "default_max_request_headers")]
+    pub max_request_headers: usize,
+
+    /// Most bytes all header fields of a request may take together.
+    #[serde(default = "default_max_request_header_bytes")]
+    pub max_request_header_bytes: usize,
+
+    /// Most header fields a response from an agent may carry, and trailer
+    /// fields after its body. Only applies with `upstream.http2`, where the
+    /// gateway reads responses itself.
+    #[serde(default = "default_max_response_headers")]
+    pub max_response_headers: usize,
+
+    /// Most bytes of a response head from an agent, status line included.
+    /// Only applies with `upstream.http2`.
+    #[serde(default = "default_max_response_header_bytes")]
+    pub max_response_header_bytes: usize,
+}
+
+impl Default for HeaderLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_request_headers: default_max_request_headers(),
+            max_request_header_bytes: default_max_request_header_bytes(),
+            max_response_headers: default_max_response_headers(),
+            max_response_header_bytes: default_max_response_header_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ClientIpConfig {
+    /// Number of proxies in front of the gateway that append the address
+    /// they saw to `X-Forwarded-For`. With 0 the socket peer is the client.
+    #[serde(default)]
+    pub trusted_hops: usize,
+
+    /// Expect a PROXY protocol v2 header on every TCP connection, as sent by
+    /// L4 load balancers, and take the client from it instead of the socket
+    /// peer. `X-Forwarded-For` entries still apply on top.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// Networks of the balancers allowed to send a PROXY header, e.g.
+    /// `10.0.0.0/8`. Connections from anywhere else are taken as they are,
+    /// with the socket peer as the client. Empty trusts every peer, which is
+    /// only safe if nothing but the balancers can reach the listener.
+    #[serde(default)]
+    pub proxy_protocol_sources: Vec<IpCidr>,
+
+    /// Set `X-Forwarded-For`, `-Proto`, `-Host` and `Forwarded` on requests
+    /// to tunneled services. Values from the client are replaced unless
+    /// `trusted_hops` is above 0, in which case the gateway appends to them.
+    #[serde(default)]
+    pub forwarded_headers: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RequestLimitsConfig {
+    /// How long the gateway may take to pick and check an endpoint for a
+    /// request, in milliseconds. This covers hostname verification and the
+    /// liveness probe.
+    #[serde(default = "default_resolve_timeout_ms")]
+    pub resolve_timeout_ms: u64,
+
+    /// Largest request body the gateway forwards, by its `Content-Length`.
+    /// Unset means no limit.
+    #[serde(default)]
+    pub max_request_body_bytes: Option<u64>,
+}
+
+impl Default for RequestLimitsConfig {
+    fn default() -> Self {
+        Self {
+            resolve_timeout_ms: default_resolve_timeout_ms(),
+            max_request_body_bytes: None,
+        }
+    }
+}
+
+fn default_resolve_timeout_ms() -> u64 {
+    10_000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub format: AccessLogFormat,
+
+    /// File to append records to. Unset writes them to stdout.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+
+    /// Size at which the file is rotated to `<path>.1`, in bytes.
+    #[serde(default = "default_access_log_max_file_bytes")]
+    pub max_file_bytes: u64,
+
+    /// How many rotated files are kept next to the current one.
+    #[serde(default = "default_access_log_max_files")]
+    pub max_files: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: AccessLogFormat::default(),
+            path: None,
+            max_file_bytes: default_access_log_max_file_bytes(),
+            max_files: default_access_log_max_files(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// Common Log Format, with host, endpoint, duration and path type
+    /// appended.
+    Clf,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UpstreamConfig {
+    /// Serve the TCP listener with the gateway's own HTTP server and send
+    /// requests to agents over HTTP/2 where they accept it. Older agents,
+    /// CONNECT requests and upgrades still get HTTP/1.1. Off, and on the
+    /// Unix socket listener, every request goes through iroh-proxy-utils as
+    /// HTTP/1.1.
+    #[serde(default)]
+    pub http2: bool,
+
+    /// Offer agents on HTTP/2 upstreams to gzip response bodies, which the
+    /// gateway decompresses before they go on to the client. Agents compress
+    /// if their `tunnel_compression` is enabled.
+    #[serde(default)]
+    pub compression: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ResponseCompressionConfig {
+    /// Compress text-like responses with brotli or gzip, as the client's
+    /// `Accept-Encoding` allows. Needs `upstream.http2`, whose server is the
+    /// only one that sees response bodies. Responses the service compressed
+    /// already, media and archives are sent as they are.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Responses known to be shorter than this are sent as they are.
+    /// Defaults to 1 KiB.
+    #[serde(default = "default_response_compression_min_bytes")]
+    pub min_bytes: u64,
+}
+
+impl Default for ResponseCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_bytes: default_response_compression_min_bytes(),
+        }
+    }
+}
+
+fn default_response_compression_min_bytes() -> u64 {
+    1024
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BalancingConfig {
+    #[serde(default)]
+    pub policy: BalancePolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct GatewayMetricsConfig {
+    /// Upper bounds of the latency histogram buckets, in seconds. A `+Inf`
+    /// bucket is always added.
+    #[serde(default = "default_latency_buckets")]
+    pub latency_buckets: Vec<f64>,
+
+    /// Time upstream responses by relaying TCP connections through a
+    /// loopback listener, which costs an extra hop per connection. Without
+    /// it only resolve and QUIC connect times are recorded.
+    #[serde(default)]
+    pub upstream_timing: bool,
+
+    #[serde(default)]
+    pub endpoint_labels: EndpointLabelsConfig,
+}
+
+impl Default for GatewayMetricsConfig {
+    fn default() -> Self {
+        Self {
+            latency_buckets: default_latency_buckets(),
+            upstream_timing: false,
+            endpoint_labels: EndpointLabelsConfig::default(),
+        }
+    }
+}
+
+fn default_latency_buckets() -> Vec<f64> {
+    vec![
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ]
+}
+
+/// Series per destination endpoint, limited so a gateway serving many
+/// tunnels doesn't export a series for each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct EndpointLabelsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How many of the busiest endpoints get series of their own. The rest
+    /// are summed up as `endpoint="other"`.
+    #[serde(default = "default_endpoint_labels_top_k")]
+    pub top_k: usize,
+
+    /// Endpoints that always get series of their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<EndpointId>,
+}
+
+impl Default for EndpointLabelsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top_k: default_endpoint_labels_top_k(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+fn default_endpoint_labels_top_k() -> usize {
+    20
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalancePolicy {
+    /// Send every request to the first reachable endpoint, in the order
+    /// they are listed.
+    #[default]
+    Failover,
+    /// Start with a different endpoint for each request, in proportion to
+    /// the endpoints' weights, skipping unreachable ones.
+    #[serde(alias = "weighted_round_robin")]
+    RoundRobin,
+}
+
+fn default_access_log_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_access_log_max_files() -> usize {
+    5
+}
+
+fn default_max_request_headers() -> usize {
+    64
+}
+
+fn default_max_request_header_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_max_response_headers() -> usize {
+    128
+}
+
+fn default_max_response_header_bytes() -> usize {
+    64 * 1024
 }
 
 impl Config {
+    pub fn relay_mode(&self) -> Result<RelayMode> {
+        if self.direct_only {
+            return Ok(RelayMode::Disabled);
+        }
+        match &self.relays {
+            Relays::Default => Ok(default_relay_mode()),
+            Relays::Disabled => Ok(RelayMode::Disabled),
+            Relays::Custom(urls) if urls.is_empty() => {
+                n0_error::bail_any!("relays: custom needs at least one relay URL")
+            }
+            Relays::Custom(urls) => {
+                let map = urls.iter().cloned().collect::<RelayMap>();
+                Ok(RelayMode::Custom(map))
+            }
+        }
+    }
+
+    /// The DNS resolver address to use, reached through NAT64 when it is an
+    /// IPv4 address and only IPv6 is allowed.
+    pub fn dns_resolver_addr(&self) -> Option<SocketAddr> {
+        self.dns_resolver.map(|addr| self.reachable(addr))
+    }
+
+    /// The [`Self::target_dns_server`] address, reached like
+    /// [`Self::dns_resolver_addr`].
+    pub fn target_dns_server_addr(&self) -> Option<SocketAddr> {
+        self.target_dns_server.map(|addr| self.reachable(addr))
+    }
+
+    fn reachable(&self, addr: SocketAddr) -> SocketAddr {
+        match self.ip_family {
+            IpFamily::Ipv6Only => nat64::map_socket_addr(self.nat64_prefix(), addr),
+            IpFamily::Any | IpFamily::PreferIpv6 => addr,
+        }
+    }
+
+    pub fn nat64_prefix(&self) -> Ipv6Addr {
+        self.nat64_prefix.unwrap_or(nat64::WELL_KNOWN_PREFIX)
+    }
+
+    /// Fill in [`Self::nat64_prefix`] from DNS64 if it is needed and unset.
+    pub async fn discover_nat64_prefix(&mut self) {
+        if self.ip_family != IpFamily::Ipv6Only || self.nat64_prefix.is_some() {
+            return;
+        }
+        let resolver = iroh_relay::dns::DnsResolver::new();
+        if let Some(prefix) = nat64::discover_prefix(&resolver).await {
+            tracing::info!(%prefix, "discovered NAT64 prefix");
+            self.nat64_prefix = Some(prefix);
+        }
+    }
+
+    /// The override address for `host` from [`Self::hosts`]. Names match
+    /// case-insensitively, with or without a trailing dot.
+    pub fn host_override(&self, host: &str) -> Option<IpAddr> {
+        let host = host.trim_end_matches('.');
+        self.hosts
+            .iter()
+            .find(|(name, _)| name.trim_end_matches('.').eq_ignore_ascii_case(host))
+            .map(|(_, ip)| *ip)
+    }
+
+    /// Whether tunnels to `host` have to be resolved by the agent, through
+    /// [`Self::hosts`] or [`Self::target_dns_server`], rather than by the
+    /// system resolver.
+    pub fn resolves_target(&self, host: &str) -> bool {
+        self.host_override(host).is_some()
+            || (self.target_dns_server.is_some()
+                && crate::dial::unbracket(host).parse::<IpAddr>().is_err())
+    }
+
+    /// The unspecified address for listeners that don't name one.
+    pub fn unspecified_ip(&self) -> IpAddr {
+        match self.ip_family {
+            IpFamily::Any => Ipv4Addr::UNSPECIFIED.into(),
+            IpFamily::PreferIpv6 | IpFamily::Ipv6Only => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+
     pub async fn from_file(path: PathBuf) -> Result<Self> {
         let config = tokio::fs::read_to_string(path)
             .await
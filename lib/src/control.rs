@@ -0,0 +1,400 @@
+//! A control socket, for the CLI and the app to talk to the agent serving a
+//! repo.
+//!
+//! The agent, `datum-connect serve` or the app, serves a [`LocalListener`]
+//! for its repo: a unix socket at `<repo>/control.sock`, or on Windows a
+//! named pipe `\\.\pipe\datum-connect-<hash>` named after the repo path.
+//! [`LocalStream::connect`] reaches either. Each connection carries one
+//! [`ControlRequest`] and its [`ControlResponse`], as a line of JSON each.
+//!
+//! A second agent for the same repo fails to bind, so a listener also tells
+//! that a repo is already served.
+
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use iroh::EndpointId;
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tracing::{Instrument, debug, error_span};
+
+use crate::ListenNode;
+
+/// The socket file in the repo, on unix.
+#[cfg(unix)]
+const SOCKET_FILE: &str = "control.sock";
+/// Longest request line we read.
+const MAX_LINE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status(AgentStatus),
+    Error { message: String },
+}
+
+/// What an agent tells about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStatus {
+    /// `serve` or `app`.
+    pub agent: String,
+    pub version: String,
+    pub pid: u32,
+    pub endpoint_id: EndpointId,
+    /// Tunnels enabled on this device.
+    pub tunnels: usize,
+}
+
+impl AgentStatus {
+    /// The status of `node`, served by `agent`.
+    pub fn new(agent: &str, node: &ListenNode) -> Self {
+        Self {
+            agent: agent.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            endpoint_id: node.endpoint_id(),
+            tunnels: node.proxies().iter().filter(|p| p.enabled).count(),
+        }
+    }
+}
+
+/// The control socket of a repo, bound by its agent.
+#[derive(Debug)]
+pub struct LocalListener {
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+    #[cfg(windows)]
+    name: String,
+    /// The pipe instance the next client connects to.
+    #[cfg(windows)]
+    next: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+impl LocalListener {
+    /// Bind the control socket of the repo at `repo_path`. Fails if an agent
+    /// already serves it.
+    #[cfg(unix)]
+    pub async fn bind(repo_path: &Path) -> Result<Self> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = repo_path.join(SOCKET_FILE);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            if tokio::net::UnixStream::connect(&path).await.is_ok() {
+                n0_error::bail_any!("another agent is serving {}", repo_path.display());
+            }
+            // Left behind by an agent that didn't shut down.
+            tokio::fs::remove_file(&path)
+                .await
+                .with_std_context(|_| format!("failed to remove {}", path.display()))?;
+        }
+        let listener = tokio::net::UnixListener::bind(&path)
+            .with_std_context(|_| format!("failed to bind {}", path.display()))?;
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .std_context("failed to restrict control socket")?;
+        Ok(Self { listener, path })
+    }
+
+    /// Bind the control pipe of the repo at `repo_path`. Fails if an agent
+    /// already serves it.
+    #[cfg(windows)]
+    pub async fn bind(repo_path: &Path) -> Result<Self> {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        let name = pipe_name(repo_path);
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .reject_remote_clients(true)
+            .create(&name)
+            .with_std_context(|_| {
+                format!(
+                    "failed to create {name}, is another agent serving {}?",
+                    repo_path.display()
+                )
+            })?;
+        Ok(Self { name, next })
+    }
+
+    /// Wait for the next client.
+    pub async fn accept(&mut self) -> io::Result<LocalStream> {
+        #[cfg(unix)]
+        {
+            let (stream, _) = self.listener.accept().await?;
+            Ok(LocalStream::Unix(stream))
+        }
+        #[cfg(windows)]
+        {
+            use tokio::net::windows::named_pipe::ServerOptions;
+
+            self.next.connect().await?;
+            let next = ServerOptions::new()
+                .reject_remote_clients(true)
+                .create(&self.name)?;
+            let stream = std::mem::replace(&mut self.next, next);
+            Ok(LocalStream::PipeServer(stream))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LocalListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A connection on a control socket.
+#[derive(Debug)]
+pub enum LocalStream {
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+    #[cfg(windows)]
+    PipeServer(tokio::net::windows::named_pipe::NamedPipeServer),
+    #[cfg(windows)]
+    PipeClient(tokio::net::windows::named_pipe::NamedPipeClient),
+}
+
+impl LocalStream {
+    /// Connect to the control socket of the repo at `repo_path`. Fails with
+    /// [`io::ErrorKind::NotFound`] or [`io::ErrorKind::ConnectionRefused`]
+    /// if no agent serves it.
+    #[cfg(unix)]
+    pub async fn connect(repo_path: &Path) -> io::Result<Self> {
+        let stream = tokio::net::UnixStream::connect(repo_path.join(SOCKET_FILE)).await?;
+        Ok(Self::Unix(stream))
+    }
+
+    /// Connect to the control pipe of the repo at `repo_path`. Fails with
+    /// [`io::ErrorKind::NotFound`] if no agent serves it.
+    #[cfg(windows)]
+    pub async fn connect(repo_path: &Path) -> io::Result<Self> {
+        use tokio::net::windows::named_pipe::ClientOptions;
+
+        /// `ERROR_PIPE_BUSY`: every instance is taken, until the agent
+        /// creates the next one.
+        const PIPE_BUSY: i32 = 231;
+
+        let name = pipe_name(repo_path);
+        let mut attempts = 0;
+        loop {
+            match ClientOptions::new().open(&name) {
+                Ok(client) => return Ok(Self::PipeClient(client)),
+                Err(err) if err.raw_os_error() == Some(PIPE_BUSY) && attempts < 20 => {
+                    attempts += 1;
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+macro_rules! each_stream {
+    ($self:expr, $stream:ident => $body:expr) => {
+        match $self.get_mut() {
+            #[cfg(unix)]
+            LocalStream::Unix($stream) => $body,
+            #[cfg(windows)]
+            LocalStream::PipeServer($stream) => $body,
+            #[cfg(windows)]
+            LocalStream::PipeClient($stream) => $body,
+        }
+    };
+}
+
+impl AsyncRead for LocalStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        each_stream!(self, s => Pin::new(s).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for LocalStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        each_stream!(self, s => Pin::new(s).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each_stream!(self, s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        each_stream!(self, s => Pin::new(s).poll_shutdown(cx))
+    }
+}
+
+/// The pipe of a repo. Pipes live in one namespace for the whole machine,
+/// so the name is a hash of the repo path.
+#[cfg(windows)]
+fn pipe_name(repo_path: &Path) -> String {
+    let hash = blake3::hash(repo_path.to_string_lossy().to_lowercase().as_bytes());
+    format!(
+        r"\\.\pipe\datum-connect-{}",
+        data_encoding::HEXLOWER.encode(&hash.as_bytes()[..8])
+    )
+}
+
+/// Answers control requests until dropped.
+#[derive(Debug)]
+pub struct ControlServer {
+    _task: AbortOnDropHandle<()>,
+}
+
+impl ControlServer {
+    /// Serve `listener`, answering status requests with `status`.
+    pub fn spawn(
+        mut listener: LocalListener,
+        status: impl Fn() -> AgentStatus + Send + Sync + 'static,
+    ) -> Self {
+        let status = Arc::new(status);
+        let task = tokio::spawn(
+            async move {
+                loop {
+                    let stream = match listener.accept().await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            debug!("failed to accept control connection: {err:#}");
+                            continue;
+                        }
+                    };
+                    let status = status.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = answer(stream, |request| match request {
+                            ControlRequest::Status => ControlResponse::Status(status()),
+                        })
+                        .await
+                        {
+                            debug!("control connection failed: {err:#}");
+                        }
+                    });
+                }
+            }
+            .instrument(error_span!("control")),
+        );
+        Self {
+            _task: AbortOnDropHandle::new(task),
+        }
+    }
+}
+
+async fn answer(
+    stream: LocalStream,
+    handle: impl FnOnce(ControlRequest) -> ControlResponse,
+) -> Result<()> {
+    let (read, mut write) = tokio::io::split(stream);
+    let mut line = String::new();
+    tokio::io::AsyncReadExt::take(BufReader::new(read), MAX_LINE)
+        .read_line(&mut line)
+        .await
+        .std_context("failed to read request")?;
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => handle(request),
+        Err(err) => ControlResponse::Error {
+            message: format!("invalid request: {err}"),
+        },
+    };
+    let mut line = serde_json::to_vec(&response).anyerr()?;
+    line.push(b'\n');
+    write
+        .write_all(&line)
+        .await
+        .std_context("failed to write response")?;
+    write.shutdown().await.ok();
+    Ok(())
+}
+
+/// Send `request` to the agent serving the repo at `repo_path`. `None` if no
+/// agent serves it.
+pub async fn request(
+    repo_path: &Path,
+    request: &ControlRequest,
+) -> Result<Option<ControlResponse>> {
+    let stream = match LocalStream::connect(repo_path).await {
+        Ok(stream) => stream,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(err) => return Err(err).std_context("failed to connect to agent"),
+    };
+    let (read, mut write) = tokio::io::split(stream);
+    let mut line = serde_json::to_vec(request).anyerr()?;
+    line.push(b'\n');
+    write
+        .write_all(&line)
+        .await
+        .std_context("failed to send request to agent")?;
+    let mut line = String::new();
+    BufReader::new(read)
+        .read_line(&mut line)
+        .await
+        .std_context("failed to read response from agent")?;
+    let response = serde_json::from_str(&line).std_context("invalid response from agent")?;
+    Ok(Some(response))
+}
+
+/// The status of the agent serving the repo at `repo_path`, if one does.
+pub async fn agent_status(repo_path: &Path) -> Result<Option<AgentStatus>> {
+    match request(repo_path, &ControlRequest::Status).await? {
+        None => Ok(None),
+        Some(ControlResponse::Status(status)) => Ok(Some(status)),
+        Some(ControlResponse::Error { message }) => n0_error::bail_any!("agent: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_status_and_refuses_a_second_agent() -> Result {
+        let dir = tempfile::tempdir()?;
+        assert!(agent_status(dir.path()).await?.is_none());
+
+        let status = AgentStatus {
+            agent: "serve".to_string(),
+            version: "0.1.0".to_string(),
+            pid: 42,
+            endpoint_id: iroh::SecretKey::generate(&mut rand::rng()).public(),
+            tunnels: 2,
+        };
+        let server = ControlServer::spawn(LocalListener::bind(dir.path()).await?, {
+            let status = status.clone();
+            move || status.clone()
+        });
+        assert_eq!(agent_status(dir.path()).await?, Some(status));
+        assert!(LocalListener::bind(dir.path()).await.is_err());
+
+        drop(server);
+        tokio::task::yield_now().await;
+        // The socket is gone with the listener, so another agent may bind.
+        LocalListener::bind(dir.path()).await?;
+        Ok(())
+    }
+}
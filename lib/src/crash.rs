@@ -0,0 +1,302 @@
+//! Crash reports, kept on this device and sent only with consent.
+//!
+//! With [`CrashReports::install_panic_hook`], a panic writes a
+//! [`CrashReport`] to the `crashes` directory of the repo: the message and
+//! where it happened, a backtrace, the platform and the last log lines
+//! [`RecentLogs`] kept. Panics with the same message and location in the
+//! same version are aggregated into one report that counts them, so a panic
+//! in a loop leaves one file rather than thousands.
+//!
+//! Reports are only sent by [`CrashReports::submit`], which the app calls
+//! when the user opted in and the CLI with `--send-crash-reports`. Sent
+//! reports are deleted. Log lines can name tunnels and hosts, so
+//! [`CrashReports::pending`] shows what would be sent.
+
+use std::{
+    collections::VecDeque,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use n0_error::{Result, StdResultExt};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{datum_cloud::ApiEnv, telemetry::Platform};
+
+/// Log lines kept for a report.
+const RECENT_LOG_LINES: usize = 200;
+
+/// Overrides where reports are sent, e.g. for testing against a local collector.
+const CRASH_REPORT_URL_ENV: &str = "DATUM_CONNECT_CRASH_REPORT_URL";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CrashReport {
+    pub platform: Platform,
+    pub message: String,
+    /// `file:line:column` of the panic.
+    pub location: Option<String>,
+    pub thread: Option<String>,
+    /// The backtrace of the latest occurrence.
+    pub backtrace: String,
+    /// Log lines before the latest occurrence, oldest first.
+    #[serde(default)]
+    pub recent_logs: Vec<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub occurrences: u64,
+}
+
+impl CrashReport {
+    /// The file name shared by reports of the same panic.
+    fn file_name(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        (&self.platform.app_version, &self.message, &self.location).hash(&mut hasher);
+        format!("{:016x}.json", hasher.finish())
+    }
+
+    /// `self` as a later occurrence of `earlier`.
+    fn merge(self, earlier: CrashReport) -> Self {
+        Self {
+            first_seen: earlier.first_seen,
+            occurrences: earlier.occurrences + self.occurrences,
+            ..self
+        }
+    }
+}
+
+/// The last log lines, for crash reports. Use it as the writer of a
+/// `tracing_subscriber::fmt` layer.
+#[derive(Debug, Clone, Default)]
+pub struct RecentLogs {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl RecentLogs {
+    /// The kept lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn push(&self, text: &str) {
+        let mut lines = self.lines.lock().expect("poisoned");
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            if lines.len() == RECENT_LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+/// Collects one formatted event and keeps it when dropped.
+#[derive(Debug)]
+pub struct RecentLogsWriter {
+    logs: RecentLogs,
+    buf: Vec<u8>,
+}
+
+impl Write for RecentLogsWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLogsWriter {
+    fn drop(&mut self) {
+        self.logs.push(&String::from_utf8_lossy(&self.buf));
+    }
+}
+
+impl<'a> MakeWriter<'a> for RecentLogs {
+    type Writer = RecentLogsWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLogsWriter {
+            logs: self.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// The crash reports in a directory.
+#[derive(Debug, Clone)]
+pub struct CrashReports {
+    dir: PathBuf,
+    url: String,
+}
+
+impl CrashReports {
+    /// The reports in `dir`, sent to the endpoint of `api_env`.
+    pub fn new(dir: impl Into<PathBuf>, api_env: ApiEnv) -> Self {
+        let url = std::env::var(CRASH_REPORT_URL_ENV)
+            .unwrap_or_else(|_| format!("{}/crash-reports/v1/datum-connect", api_env.api_url()));
+        Self {
+            dir: dir.into(),
+            url,
+        }
+    }
+
+    /// Write a report for every panic from now on, then run the previous
+    /// panic hook.
+    pub fn install_panic_hook(&self, app_version: &str, logs: RecentLogs) {
+        let this = self.clone();
+        let platform = Platform::current(app_version);
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string());
+            let now = Utc::now();
+            let report = CrashReport {
+                platform: platform.clone(),
+                message,
+                location: info.location().map(|l| l.to_string()),
+                thread: std::thread::current().name().map(str::to_string),
+                backtrace: std::backtrace::Backtrace::force_capture().to_string(),
+                recent_logs: logs.lines(),
+                first_seen: now,
+                last_seen: now,
+                occurrences: 1,
+            };
+            if let Err(err) = this.record(report) {
+                eprintln!("Failed to write crash report: {err}");
+            }
+            previous(info);
+        }));
+    }
+
+    /// Write `report`, adding it to an unsent report of the same panic.
+    pub fn record(&self, report: CrashReport) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(report.file_name());
+        let report = match read(&path) {
+            Some(earlier) => report.merge(earlier),
+            None => report,
+        };
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&report)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    /// The reports not sent yet, with their files.
+    pub fn pending(&self) -> Vec<(PathBuf, CrashReport)> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut reports = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| Some((path.clone(), read(&path)?)))
+            .collect::<Vec<_>>();
+        reports.sort_by_key(|(_, report)| report.last_seen);
+        reports
+    }
+
+    /// Send the pending reports and delete them. Returns how many were sent.
+    pub async fn submit(&self) -> Result<usize> {
+        let http = reqwest::Client::new();
+        let mut sent = 0;
+        for (path, report) in self.pending() {
+            http.post(&self.url)
+                .json(&report)
+                .send()
+                .await
+                .std_context("sending crash report")?
+                .error_for_status()
+                .std_context("sending crash report")?;
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!("Failed to remove sent {}: {err:#}", path.display());
+            }
+            sent += 1;
+        }
+        if sent > 0 {
+            debug!(url = %self.url, sent, "sent crash reports");
+        }
+        Ok(sent)
+    }
+}
+
+fn read(path: &Path) -> Option<CrashReport> {
+    let data = std::fs::read(path).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(report) => Some(report),
+        Err(err) => {
+            warn!("Ignoring unreadable {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(message: &str) -> CrashReport {
+        let now = Utc::now();
+        CrashReport {
+            platform: Platform::current("1.0.0"),
+            message: message.to_string(),
+            location: Some("lib/src/node.rs:10:5".to_string()),
+            thread: Some("main".to_string()),
+            backtrace: String::new(),
+            recent_logs: Vec::new(),
+            first_seen: now,
+            last_seen: now,
+            occurrences: 1,
+        }
+    }
+
+    #[test]
+    fn aggregates_reports_of_the_same_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let reports = CrashReports::new(dir.path(), ApiEnv::Staging);
+        let first = reports.record(report("index out of bounds")).unwrap();
+        let mut again = report("index out of bounds");
+        again.recent_logs = vec!["INFO retrying".to_string()];
+        assert_eq!(reports.record(again).unwrap(), first);
+        reports.record(report("called unwrap on None")).unwrap();
+
+        let pending = reports.pending();
+        assert_eq!(pending.len(), 2);
+        let (_, aggregated) = pending.iter().find(|(path, _)| *path == first).unwrap();
+        assert_eq!(aggregated.occurrences, 2);
+        assert_eq!(aggregated.recent_logs, ["INFO retrying"]);
+        assert!(aggregated.first_seen <= aggregated.last_seen);
+    }
+
+    #[test]
+    fn keeps_the_last_log_lines() {
+        let logs = RecentLogs::default();
+        for i in 0..RECENT_LOG_LINES + 5 {
+            let mut writer = logs.make_writer();
+            writeln!(writer, "line {i}").unwrap();
+        }
+        let lines = logs.lines();
+        assert_eq!(lines.len(), RECENT_LOG_LINES);
+        assert_eq!(lines[0], "line 5");
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("line {}", RECENT_LOG_LINES + 4)
+        );
+    }
+}
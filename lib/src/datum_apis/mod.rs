@@ -3,3 +3,4 @@ pub mod connector_advertisement;
 pub mod connector_class;
 pub mod http_proxy;
 pub mod lease;
+pub mod quota;
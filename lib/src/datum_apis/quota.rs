@@ -0,0 +1,41 @@
+use kube::CustomResource;
+use serde::{Deserialize, Serialize};
+
+/// Resource type of the per-project tunnel (HTTPProxy) quota.
+pub const TUNNEL_RESOURCE_TYPE: &str = "networking.datumapis.com/httpproxies";
+/// Resource type of the per-project monthly egress bandwidth quota, in bytes.
+pub const BANDWIDTH_RESOURCE_TYPE: &str = "networking.datumapis.com/egress-bytes";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerRef {
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize)]
+#[kube(
+    group = "quota.miloapis.com",
+    version = "v1alpha1",
+    kind = "AllowanceBucket",
+    plural = "allowancebuckets",
+    namespaced,
+    status = "AllowanceBucketStatus",
+    schema = "disabled"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowanceBucketSpec {
+    pub consumer_ref: ConsumerRef,
+    pub resource_type: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowanceBucketStatus {
+    #[serde(default)]
+    pub limit: i64,
+    #[serde(default)]
+    pub allocated: i64,
+    #[serde(default)]
+    pub available: i64,
+}
@@ -1,39 +1,60 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use arc_swap::ArcSwap;
 use n0_error::{Result, StackResultExt, StdResultExt};
 use n0_future::{BufferedStreamExt, TryStreamExt, task::AbortOnDropHandle};
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use tracing::warn;
 
-use crate::{ProjectControlPlaneClient, Repo, SelectedContext};
+use crate::{ProjectControlPlaneClient, Repo, SelectedContext, outbound_proxy::OutboundProxy};
 
 pub use self::{
-    auth::{AuthClient, AuthState, LoginState, MaybeAuth, UserProfile},
+    auth::{
+        AuthClient, AuthEvent, AuthState, DeviceCodePrompt, LoginDiagnostics, LoginState,
+        MaybeAuth, REDIRECT_SERVER_PORT, REDIRECT_SERVER_PORTS, SERVICE_ACCOUNT_USER_ID,
+        SERVICE_TOKEN_ENV, SERVICE_TOKEN_FILE_ENV, UserProfile,
+    },
+    clock_skew::{ClockOffset, ClockSkew, MAX_CLOCK_SKEW},
     env::ApiEnv,
 };
 
 mod auth;
+mod clock_skew;
 mod env;
 
+/// How long [`DatumCloudClient::api_reachable`] waits for an answer.
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(derive_more::Debug, Clone)]
 pub struct DatumCloudClient {
     env: ApiEnv,
     auth: AuthClient,
     http: reqwest::Client,
+    proxy: OutboundProxy,
     session: SessionStateWrapper,
     _session_task: Option<Arc<AbortOnDropHandle<()>>>,
 }
 
 impl DatumCloudClient {
+    /// Logs in with the repo's active account. Uses the proxy from the
+    /// repo's config, or the environment's.
     pub async fn with_repo(env: ApiEnv, repo: Repo) -> Result<Self> {
-        let auth = AuthClient::with_repo(env, repo.clone()).await?;
+        let account = repo.read_active_account().await?;
+        Self::with_account(env, repo, &account).await
+    }
+
+    /// Like [`Self::with_repo`], with the login of `account` instead of the
+    /// active one. Several clients for different accounts can share a repo.
+    pub async fn with_account(env: ApiEnv, repo: Repo, account: &str) -> Result<Self> {
+        let proxy = repo.config().await?.outbound_proxy.or_env();
+        let auth = AuthClient::with_account(env, repo.clone(), account, &proxy).await?;
         let session = SessionStateWrapper::from_repo(Some(repo)).await?;
-        let http = reqwest::Client::builder().build().anyerr()?;
+        let http = proxy.apply(reqwest::Client::builder())?.build().anyerr()?;
         let mut client = Self {
             env,
             auth,
             http,
+            proxy,
             session,
             _session_task: None,
         };
@@ -41,14 +62,17 @@ impl DatumCloudClient {
         Ok(client)
     }
 
+    /// Uses the proxy from the environment, if any.
     pub async fn new(env: ApiEnv) -> Result<Self> {
-        let auth = AuthClient::new(env).await?;
+        let proxy = OutboundProxy::from_env();
+        let auth = AuthClient::new(env, &proxy).await?;
         let session = SessionStateWrapper::empty();
-        let http = reqwest::Client::builder().build().anyerr()?;
+        let http = proxy.apply(reqwest::Client::builder())?.build().anyerr()?;
         let mut client = Self {
             env,
             auth,
             http,
+            proxy,
             session,
             _session_task: None,
         };
@@ -64,16 +88,49 @@ impl DatumCloudClient {
         self.env.api_url()
     }
 
+    /// Whether the API answers at all. Any HTTP response counts; only a
+    /// failed connection or a timeout means it can't be reached.
+    pub async fn api_reachable(&self) -> bool {
+        self.http
+            .get(self.api_url())
+            .timeout(REACHABILITY_TIMEOUT)
+            .send()
+            .await
+            .inspect_err(|err| tracing::debug!("Datum API unreachable: {err:#}"))
+            .is_ok()
+    }
+
     pub fn web_url(&self) -> &'static str {
         self.env.web_url()
     }
 
+    /// The account this client is logged in with.
+    pub fn account(&self) -> String {
+        self.auth.account()
+    }
+
+    /// Switch this client and all its clones to `account`, and make it the
+    /// repo's active account. The projects of the previous account are
+    /// forgotten, and the selected project is kept only if the new account
+    /// can see it.
+    pub async fn switch_account(&self, repo: &Repo, account: &str) -> Result<()> {
+        repo.write_active_account(account).await?;
+        self.session.set_orgs_projects(Vec::new());
+        self.auth.switch_account(account).await
+    }
+
+    /// The proxy used for requests to Datum Cloud.
+    pub fn outbound_proxy(&self) -> &OutboundProxy {
+        &self.proxy
+    }
+
     pub fn auth(&self) -> &AuthClient {
         &self.auth
     }
 
-    pub fn auth_update_watch(&self) -> watch::Receiver<u64> {
-        self.auth.auth_update_watch()
+    /// Logins, logouts and refreshes from now on.
+    pub fn auth_events(&self) -> broadcast::Receiver<AuthEvent> {
+        self.auth.auth_events()
     }
 
     pub fn auth_state(&self) -> Arc<MaybeAuth> {
@@ -297,27 +354,22 @@ impl DatumCloudClient {
             return;
         }
         let client = self.clone();
-        let mut login_rx = self.auth.login_state_watch();
-        let mut auth_update_rx = self.auth.auth_update_watch();
+        let mut events = self.auth.auth_events();
         let task = tokio::spawn(async move {
-            if *login_rx.borrow() != LoginState::Missing {
+            if client.login_state() != LoginState::Missing {
                 let _ = client.refresh_orgs_projects_and_validate_context().await;
             }
             loop {
-                tokio::select! {
-                    res = login_rx.changed() => {
-                        if res.is_err() {
-                            return;
-                        }
-                    }
-                    res = auth_update_rx.changed() => {
-                        if res.is_err() {
-                            return;
+                match events.recv().await {
+                    Ok(AuthEvent::ProfileUpdated) => {}
+                    Ok(event) if event.is_logout() => client.session.set_orgs_projects(Vec::new()),
+                    // After missing events, check the current state instead.
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        if client.login_state() != LoginState::Missing {
+                            let _ = client.refresh_orgs_projects_and_validate_context().await;
                         }
                     }
-                }
-                if *login_rx.borrow() != LoginState::Missing {
-                    let _ = client.refresh_orgs_projects_and_validate_context().await;
+                    Err(broadcast::error::RecvError::Closed) => return,
                 }
             }
         });
@@ -1,10 +1,4 @@
-use std::{
-    sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
-    },
-    time::Duration,
-};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use arc_swap::ArcSwap;
 use chrono::Utc;
@@ -16,24 +10,75 @@ use openidconnect::{
     core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, error, info, warn};
 
-use crate::Repo;
+use crate::{Repo, events::EventKind, outbound_proxy::OutboundProxy};
 
+pub use self::redirect_server::{REDIRECT_SERVER_PORT, REDIRECT_SERVER_PORTS};
 use self::{redirect_server::RedirectServer, types::OidcTokenResponse};
-use super::ApiEnv;
+use super::{ApiEnv, clock_skew};
 
 const LOGIN_TIMEOUT: Duration = Duration::from_secs(60);
+/// Grant type for polling the token endpoint during a device-code login (RFC 8628).
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Poll interval used when the provider doesn't specify one.
+const DEVICE_CODE_DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
 /// Refresh auth or relogin if access token is valid for less than 30min
 const REFRESH_AUTH_WHEN: Duration = Duration::from_secs(60 * 30);
 
+/// Environment variable with a long-lived service account token, used
+/// instead of an interactive login.
+pub const SERVICE_TOKEN_ENV: &str = "DATUM_CONNECT_TOKEN";
+/// Environment variable with the path of a file holding the service account
+/// token, e.g. a mounted secret.
+pub const SERVICE_TOKEN_FILE_ENV: &str = "DATUM_CONNECT_TOKEN_FILE";
+/// User id of the profile used with service account tokens.
+pub const SERVICE_ACCOUNT_USER_ID: &str = "service-account";
+/// How often a service account token is read again, so rotated secrets are
+/// picked up.
+const SERVICE_TOKEN_REREAD: Duration = Duration::from_secs(60 * 60);
+/// Auth events a slow subscriber can fall behind by before missing some.
+const AUTH_EVENTS_CAPACITY: usize = 16;
+
 pub struct AuthProvider {
     pub issuer_url: String,
     pub client_id: String,
     pub client_secret: Option<String>,
 }
 
+/// A change of the auth state, for subsystems that depend on the login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthEvent {
+    /// A user logged in, or an account with a stored login was switched to.
+    LoggedIn { email: String },
+    /// The user logged out, or an account without a login was switched to.
+    LoggedOut,
+    /// The access token was replaced by a fresh one for the same user.
+    Refreshed,
+    /// The login could not be refreshed and is gone. The user has to log in
+    /// again.
+    RefreshFailed { error: String },
+    /// The user's profile changed, the tokens did not.
+    ProfileUpdated,
+}
+
+impl AuthEvent {
+    /// Whether the user is logged out after this event.
+    pub fn is_logout(&self) -> bool {
+        matches!(self, Self::LoggedOut | Self::RefreshFailed { .. })
+    }
+
+    fn for_login(auth: Option<&AuthState>) -> Self {
+        match auth {
+            Some(auth) => Self::LoggedIn {
+                email: auth.profile.email.clone(),
+            },
+            None => Self::LoggedOut,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum LoginState {
     Missing,
@@ -124,24 +169,80 @@ impl UserProfile {
     // }
 }
 
+/// What happened during the most recent interactive login.
+///
+/// Updated as the login progresses, so a UI can explain why a login that
+/// never completes is stuck instead of just timing out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoginDiagnostics {
+    /// The URL the browser was sent to. Can be opened by hand.
+    pub auth_url: Option<String>,
+    /// The local address the redirect server listens on, once bound.
+    pub redirect_addr: Option<SocketAddr>,
+    /// Why the redirect server could not bind, if it couldn't.
+    pub bind_error: Option<String>,
+    /// Whether the browser was launched. `None` until it was attempted.
+    pub browser_opened: Option<bool>,
+    /// Whether the browser never returned to the redirect server in time.
+    pub timed_out: bool,
+    /// The code to enter during a device-code login.
+    pub device_code: Option<DeviceCodePrompt>,
+}
+
+/// What the user needs to approve a device-code login in any browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCodePrompt {
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Verification URL with the user code already filled in, if supported.
+    pub verification_uri_complete: Option<String>,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorizationResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    #[serde(default)]
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    #[serde(default)]
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceTokenError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct StatelessClient {
     oidc: types::OidcClient,
+    issuer: IssuerUrl,
     http: reqwest::Client,
     env: ApiEnv,
 }
 
 impl StatelessClient {
-    pub async fn new(env: ApiEnv) -> Result<Self> {
-        Self::with_provider(env, env.auth_provider()).await
+    pub async fn new(env: ApiEnv, proxy: &OutboundProxy) -> Result<Self> {
+        Self::with_provider(env, env.auth_provider(), proxy).await
     }
 
-    pub async fn with_provider(env: ApiEnv, provider: AuthProvider) -> Result<Self> {
-        let http = reqwest::ClientBuilder::new()
+    pub async fn with_provider(
+        env: ApiEnv,
+        provider: AuthProvider,
+        proxy: &OutboundProxy,
+    ) -> Result<Self> {
+        let builder = reqwest::ClientBuilder::new()
             // Following redirects opens the client up to SSRF vulnerabilities.
-            .redirect(reqwest::redirect::Policy::none())
+            .redirect(reqwest::redirect::Policy::none());
+        let http = proxy
+            .apply(builder)?
             .build()
-            .expect("Client should build");
+            .std_context("Failed to build the login HTTP client")?;
 
         // Use OpenID Connect Discovery to fetch the provider metadata.
         let provider_metadata = CoreProviderMetadata::discover_async(
@@ -150,6 +251,7 @@ impl StatelessClient {
         )
         .await
         .std_context("Failed to discover OIDC provider metadata")?;
+        let issuer = provider_metadata.issuer().clone();
 
         // Create an OpenID Connect client
         let oidc = CoreClient::from_provider_metadata(
@@ -157,19 +259,52 @@ impl StatelessClient {
             ClientId::new(provider.client_id),
             provider.client_secret.clone().map(ClientSecret::new),
         )
-        .set_redirect_uri(RedirectServer::url());
+        .set_redirect_uri(RedirectServer::url_for(REDIRECT_SERVER_PORT));
 
-        Ok(Self { oidc, http, env })
+        Ok(Self {
+            oidc,
+            issuer,
+            http,
+            env,
+        })
     }
 
     pub async fn login(&self) -> Result<AuthState> {
+        let (diagnostics, _) = watch::channel(LoginDiagnostics::default());
+        self.login_with_diagnostics(&diagnostics).await
+    }
+
+    /// Log in through the browser, reporting progress to `diagnostics`.
+    pub async fn login_with_diagnostics(
+        &self,
+        diagnostics: &watch::Sender<LoginDiagnostics>,
+    ) -> Result<AuthState> {
+        diagnostics.send_replace(LoginDiagnostics::default());
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let csrf_token = CsrfToken::new_random();
 
-        let (auth_url, csrf_token, nonce) = self
-            .oidc
+        // Bind a localhost HTTP server to receive the redirect, on the first
+        // free port of the ones registered with the provider.
+        let mut redirect_server = match RedirectServer::bind(csrf_token.clone()).await {
+            Ok(server) => server,
+            Err(err) => {
+                diagnostics.send_modify(|d| d.bind_error = Some(err.to_string()));
+                return Err(err).with_std_context(|_| {
+                    format!(
+                        "Failed to listen for the login redirect on any of ports {}-{}",
+                        REDIRECT_SERVER_PORTS.start(),
+                        REDIRECT_SERVER_PORTS.end()
+                    )
+                });
+            }
+        };
+        diagnostics.send_modify(|d| d.redirect_addr = Some(redirect_server.addr()));
+        let oidc = self.oidc.clone().set_redirect_uri(redirect_server.url());
+
+        let (auth_url, _, nonce) = oidc
             .authorize_url(
                 CoreAuthenticationFlow::AuthorizationCode,
-                CsrfToken::new_random,
+                move || csrf_token,
                 Nonce::new_random,
             )
             .add_scope(Scope::new("openid".to_string()))
@@ -178,23 +313,32 @@ impl StatelessClient {
             .add_scope(Scope::new("offline_access".to_string()))
             .set_pkce_challenge(pkce_challenge)
             .url();
-        debug!(auth_uri=%self.oidc.auth_uri(), "attempting login");
-
-        // Bind a localhost HTTP server to receive the redirect.
-        let mut redirect_server = RedirectServer::bind(csrf_token.clone()).await?;
+        debug!(
+            auth_uri=%oidc.auth_uri(),
+            redirect_addr=%redirect_server.addr(),
+            "attempting login"
+        );
+        diagnostics.send_modify(|d| d.auth_url = Some(auth_url.to_string()));
 
         // Open the auth URL in the platform's default browser.
-        if let Err(err) = open::that(auth_url.to_string()) {
-            warn!("Failed to auto-open url: {err}");
-            println!("Open this URL in a browser to complete the login:\n{auth_url}")
-        }
+        let opened = match open::that(auth_url.to_string()) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Failed to auto-open url: {err}");
+                println!("Open this URL in a browser to complete the login:\n{auth_url}");
+                false
+            }
+        };
+        diagnostics.send_modify(|d| d.browser_opened = Some(opened));
 
-        let authorization_code = redirect_server.recv_with_timeout(LOGIN_TIMEOUT).await?;
+        let authorization_code = redirect_server
+            .recv_with_timeout(LOGIN_TIMEOUT)
+            .await
+            .inspect_err(|_| diagnostics.send_modify(|d| d.timed_out = true))?;
         debug!("received redirect with authorization code");
 
         // Exchange auth code for ID and access tokens.
-        let tokens = self
-            .oidc
+        let tokens = oidc
             .exchange_code(AuthorizationCode::new(authorization_code))
             .std_context("Missing OIDC provider metadata")?
             .set_pkce_verifier(pkce_verifier)
@@ -223,6 +367,131 @@ impl StatelessClient {
         Ok(state)
     }
 
+    /// Log in with the OAuth device authorization grant (RFC 8628).
+    ///
+    /// Needs no local redirect server: the user approves the login in any
+    /// browser, on any device, by entering the code published to
+    /// `diagnostics`. Use this when the browser redirect can't reach us.
+    pub async fn login_device_code(
+        &self,
+        diagnostics: &watch::Sender<LoginDiagnostics>,
+    ) -> Result<AuthState> {
+        let device_auth_url = self.device_authorization_url().await?;
+        let client_id = self.oidc.client_id().as_str();
+        let res = self
+            .http
+            .post(&device_auth_url)
+            .form(&[
+                ("client_id", client_id),
+                ("scope", "openid profile email offline_access"),
+            ])
+            .send()
+            .await
+            .std_context("Failed to request a device code")?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            warn!(url=%device_auth_url, "Device authorization failed: {status} {text}");
+            n0_error::bail_any!("Device authorization failed with status {status}");
+        }
+        let auth: DeviceAuthorizationResponse = res
+            .json()
+            .await
+            .std_context("Failed to parse device authorization response")?;
+
+        let expires_at = Utc::now() + Duration::from_secs(auth.expires_in);
+        diagnostics.send_modify(|d| {
+            d.device_code = Some(DeviceCodePrompt {
+                user_code: auth.user_code.clone(),
+                verification_uri: auth.verification_uri.clone(),
+                verification_uri_complete: auth.verification_uri_complete.clone(),
+                expires_at,
+            })
+        });
+        debug!(verification_uri=%auth.verification_uri, "waiting for device-code approval");
+
+        let token_url = self
+            .oidc
+            .token_uri()
+            .context("Missing OIDC provider metadata")?
+            .url()
+            .clone();
+        let mut interval = auth
+            .interval
+            .map(Duration::from_secs)
+            .unwrap_or(DEVICE_CODE_DEFAULT_INTERVAL);
+        let tokens: OidcTokenResponse = loop {
+            tokio::time::sleep(interval).await;
+            if Utc::now() > expires_at {
+                n0_error::bail_any!("The login code expired before it was approved");
+            }
+            let res = self
+                .http
+                .post(token_url.clone())
+                .form(&[
+                    ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                    ("device_code", auth.device_code.as_str()),
+                    ("client_id", client_id),
+                ])
+                .send()
+                .await
+                .std_context("Failed to poll for device-code approval")?;
+            if res.status().is_success() {
+                break res
+                    .json()
+                    .await
+                    .std_context("Failed to parse token response")?;
+            }
+            let err: DeviceTokenError = res
+                .json()
+                .await
+                .std_context("Failed to parse token error response")?;
+            match err.error.as_str() {
+                "authorization_pending" => {}
+                "slow_down" => interval += DEVICE_CODE_DEFAULT_INTERVAL,
+                "access_denied" => n0_error::bail_any!("The login was denied"),
+                "expired_token" => {
+                    n0_error::bail_any!("The login code expired before it was approved")
+                }
+                other => n0_error::bail_any!(
+                    "Device-code login failed: {}",
+                    err.error_description.as_deref().unwrap_or(other)
+                ),
+            }
+        };
+
+        // Device-code logins carry no nonce.
+        let state = self
+            .parse_token_response(tokens, refresh_nonce_verifier)
+            .await?;
+        info!(email=%state.profile.email, expires_at=%state.tokens.expires_at(), "device-code login succesfull");
+        Ok(state)
+    }
+
+    /// The device authorization endpoint from the provider's discovery document.
+    ///
+    /// Not part of the core metadata `openidconnect` parses, so it is fetched
+    /// separately and only when needed.
+    async fn device_authorization_url(&self) -> Result<String> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.as_str().trim_end_matches('/')
+        );
+        let json: serde_json::Value = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .with_std_context(|_| format!("Failed to fetch {url}"))?
+            .json()
+            .await
+            .std_context("Failed to parse OIDC discovery document")?;
+        json.get("device_authorization_endpoint")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .context("The login provider does not support device-code login")
+    }
+
     pub async fn refresh(&self, tokens: &AuthTokens) -> Result<AuthState> {
         let refresh_token = tokens.refresh_token.as_ref().context("No refresh token")?;
         debug!("Refreshing access token");
@@ -249,11 +518,19 @@ impl StatelessClient {
         let id_token = tokens
             .id_token()
             .ok_or_else(|| anyerr!("Server did not return an ID token"))?;
+        // A wrong clock makes the token look expired or not yet valid, so
+        // check it first and validate against the server's time.
+        let received_at = Utc::now();
+        let clock_offset = match clock_skew::unverified_issue_time(&id_token.to_string()) {
+            Some(issued_at) => clock_skew::check(issued_at, received_at)?,
+            None => chrono::TimeDelta::zero(),
+        };
         let id_token_verifier = self
             .oidc
             .id_token_verifier()
             // Datum auth backend includes multiple audiences in the id tokens
-            .set_other_audience_verifier_fn(|_audience| true);
+            .set_other_audience_verifier_fn(|_audience| true)
+            .set_time_fn(move || Utc::now() - clock_offset);
 
         let claims = id_token
             .claims(&id_token_verifier, nonce_verifier)
@@ -280,11 +557,11 @@ impl StatelessClient {
 
         // Extract user_id from ID token claims
         let user_id = claims.subject().to_string();
-        let issued_at = claims.issue_time();
 
-        // Create auth tokens
+        // Create auth tokens. `expires_in` counts from now, so expiry is
+        // tracked in local time, which keeps it right on a skewed clock.
         let auth_tokens = AuthTokens {
-            issued_at,
+            issued_at: received_at,
             access_token: tokens.access_token().clone(),
             refresh_token: tokens.refresh_token().cloned(),
             expires_in: tokens.expires_in().context("Missing expires_in claim")?,
@@ -406,6 +683,54 @@ impl StatelessClient {
 #[error("Not logged in")]
 pub struct NotLoggedIn;
 
+/// The service account token from [`SERVICE_TOKEN_ENV`] or
+/// [`SERVICE_TOKEN_FILE_ENV`], if either is set.
+async fn read_service_token() -> Result<Option<String>> {
+    if let Ok(token) = std::env::var(SERVICE_TOKEN_ENV) {
+        let token = token.trim().to_string();
+        if !token.is_empty() {
+            return Ok(Some(token));
+        }
+    }
+    let Some(path) = std::env::var_os(SERVICE_TOKEN_FILE_ENV) else {
+        return Ok(None);
+    };
+    let token = tokio::fs::read_to_string(&path)
+        .await
+        .with_std_context(|_| {
+            format!(
+                "Failed to read service account token from {}",
+                path.display()
+            )
+        })?;
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        n0_error::bail_any!("The service account token file {} is empty", path.display());
+    }
+    Ok(Some(token))
+}
+
+/// Auth for a service account token. The token has no refresh token and is
+/// read again every [`SERVICE_TOKEN_REREAD`] instead of being refreshed.
+fn service_account_auth(token: String) -> AuthState {
+    AuthState {
+        tokens: AuthTokens {
+            access_token: AccessToken::new(token),
+            refresh_token: None,
+            issued_at: Utc::now(),
+            expires_in: SERVICE_TOKEN_REREAD + REFRESH_AUTH_WHEN,
+        },
+        profile: UserProfile {
+            user_id: SERVICE_ACCOUNT_USER_ID.to_string(),
+            email: SERVICE_ACCOUNT_USER_ID.to_string(),
+            first_name: None,
+            last_name: None,
+            avatar_url: None,
+            registration_approval: None,
+        },
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct MaybeAuth(Option<AuthState>);
 
@@ -423,40 +748,65 @@ impl MaybeAuth {
 struct AuthStateWrapper {
     inner: Arc<ArcSwap<MaybeAuth>>,
     repo: Option<Repo>,
-    oauth_key: String,
+    /// The account logged in, and where its state is stored. Shared by all
+    /// clones, so switching accounts affects all of them.
+    account: Arc<ArcSwap<(String, String)>>,
+    /// Authenticated with a service account token instead of a login.
+    service_account: bool,
     login_state_tx: watch::Sender<LoginState>,
-    auth_update_tx: watch::Sender<u64>,
-    auth_update_counter: Arc<AtomicU64>,
+    events: broadcast::Sender<AuthEvent>,
 }
 
 impl AuthStateWrapper {
     fn empty() -> Self {
         let (login_state_tx, _) = watch::channel(LoginState::Missing);
-        let (auth_update_tx, _) = watch::channel(0);
+        let (events, _) = broadcast::channel(AUTH_EVENTS_CAPACITY);
         Self {
             inner: Arc::new(ArcSwap::new(Default::default())),
             repo: None,
-            oauth_key: String::new(),
+            account: Arc::new(ArcSwap::from_pointee((
+                Repo::DEFAULT_ACCOUNT.to_string(),
+                String::new(),
+            ))),
+            service_account: false,
             login_state_tx,
-            auth_update_tx,
-            auth_update_counter: Arc::new(AtomicU64::new(0)),
+            events,
         }
     }
 
-    async fn from_repo(repo: Repo, oauth_key: &str) -> Result<Self> {
-        let state = repo.read_oauth_for_key(oauth_key).await?;
+    async fn from_repo(repo: Repo, account: &str, oauth_key: String) -> Result<Self> {
+        let state = repo.read_oauth_for_key(&oauth_key).await?;
         let (login_state_tx, _) = watch::channel(login_state_for(state.as_ref()));
-        let (auth_update_tx, _) = watch::channel(0);
+        let (events, _) = broadcast::channel(AUTH_EVENTS_CAPACITY);
         Ok(Self {
             inner: Arc::new(ArcSwap::new(Arc::new(MaybeAuth(state)))),
             repo: Some(repo),
-            oauth_key: oauth_key.to_string(),
+            account: Arc::new(ArcSwap::from_pointee((account.to_string(), oauth_key))),
+            service_account: false,
             login_state_tx,
-            auth_update_tx,
-            auth_update_counter: Arc::new(AtomicU64::new(0)),
+            events,
         })
     }
 
+    /// Auth for a service account token. Never written to the repo, so the
+    /// token stays wherever it was provided.
+    fn service_account(token: String, repo: Option<Repo>) -> Self {
+        let state = service_account_auth(token);
+        let (login_state_tx, _) = watch::channel(login_state_for(Some(&state)));
+        let (events, _) = broadcast::channel(AUTH_EVENTS_CAPACITY);
+        Self {
+            inner: Arc::new(ArcSwap::new(Arc::new(MaybeAuth(Some(state))))),
+            repo,
+            account: Arc::new(ArcSwap::from_pointee((
+                Repo::DEFAULT_ACCOUNT.to_string(),
+                String::new(),
+            ))),
+            service_account: true,
+            login_state_tx,
+            events,
+        }
+    }
+
     fn load(&self) -> Arc<MaybeAuth> {
         self.inner.load_full()
     }
@@ -465,22 +815,46 @@ impl AuthStateWrapper {
         self.login_state_tx.subscribe()
     }
 
-    fn subscribe_auth_updates(&self) -> watch::Receiver<u64> {
-        self.auth_update_tx.subscribe()
+    fn subscribe_events(&self) -> broadcast::Receiver<AuthEvent> {
+        self.events.subscribe()
     }
 
-    async fn set(&self, auth: Option<AuthState>) -> Result<()> {
-        if let Some(repo) = self.repo.as_ref() {
-            repo.write_oauth_for_key(&self.oauth_key, auth.as_ref())
-                .await?;
+    fn account(&self) -> String {
+        self.account.load().0.clone()
+    }
+
+    async fn set(&self, auth: Option<AuthState>, event: AuthEvent) -> Result<()> {
+        if let Some(repo) = self.repo.as_ref().filter(|_| !self.service_account) {
+            let account = self.account.load_full();
+            repo.write_oauth_for_key(&account.1, auth.as_ref()).await?;
         }
         self.inner.store(Arc::new(MaybeAuth(auth)));
+        self.notify(event);
+        Ok(())
+    }
+
+    /// Load the stored login of another account in place of this one.
+    async fn switch(&self, account: &str, oauth_key: String) -> Result<()> {
+        let repo = self
+            .repo
+            .as_ref()
+            .context("Accounts need a repo to store their logins")?;
+        let state = repo.read_oauth_for_key(&oauth_key).await?;
+        let event = AuthEvent::for_login(state.as_ref());
+        self.account
+            .store(Arc::new((account.to_string(), oauth_key)));
+        self.inner.store(Arc::new(MaybeAuth(state)));
+        self.notify(event);
+        Ok(())
+    }
+
+    fn notify(&self, event: AuthEvent) {
         let _ = self
             .login_state_tx
             .send(login_state_for(self.load().get().ok()));
-        let next = self.auth_update_counter.fetch_add(1, Ordering::Relaxed) + 1;
-        let _ = self.auth_update_tx.send(next);
-        Ok(())
+        debug!(?event, "auth state changed");
+        // Nobody listening is fine.
+        let _ = self.events.send(event);
     }
 }
 
@@ -495,34 +869,76 @@ fn login_state_for(auth: Option<&AuthState>) -> LoginState {
 pub struct AuthClient {
     state: AuthStateWrapper,
     client: StatelessClient,
+    diagnostics: Arc<watch::Sender<LoginDiagnostics>>,
     _refresh_task: Option<Arc<n0_future::task::AbortOnDropHandle<()>>>,
 }
 
 impl AuthClient {
-    pub async fn with_repo(env: ApiEnv, repo: Repo) -> Result<Self> {
-        let auth = AuthStateWrapper::from_repo(repo, env.oauth_storage_key()).await?;
-        let auth_client = StatelessClient::new(env).await?;
+    /// Uses the stored login of `account`, or a service account token
+    /// instead if one is set, see [`SERVICE_TOKEN_ENV`].
+    pub async fn with_account(
+        env: ApiEnv,
+        repo: Repo,
+        account: &str,
+        proxy: &OutboundProxy,
+    ) -> Result<Self> {
+        let auth = match read_service_token().await? {
+            Some(token) => {
+                info!("using service account token");
+                AuthStateWrapper::service_account(token, Some(repo))
+            }
+            None => {
+                let oauth_key = Repo::account_oauth_key(env.oauth_storage_key(), account);
+                AuthStateWrapper::from_repo(repo, account, oauth_key).await?
+            }
+        };
+        let auth_client = StatelessClient::new(env, proxy).await?;
         let mut client = Self {
             state: auth,
             client: auth_client,
+            diagnostics: Arc::new(watch::channel(LoginDiagnostics::default()).0),
             _refresh_task: None,
         };
         client.start_refresh_loop();
         Ok(client)
     }
 
-    pub async fn new(env: ApiEnv) -> Result<Self> {
-        let auth = AuthStateWrapper::empty();
-        let auth_client = StatelessClient::new(env).await?;
+    pub async fn new(env: ApiEnv, proxy: &OutboundProxy) -> Result<Self> {
+        let auth = match read_service_token().await? {
+            Some(token) => AuthStateWrapper::service_account(token, None),
+            None => AuthStateWrapper::empty(),
+        };
+        let auth_client = StatelessClient::new(env, proxy).await?;
         let mut client = Self {
             state: auth,
             client: auth_client,
+            diagnostics: Arc::new(watch::channel(LoginDiagnostics::default()).0),
             _refresh_task: None,
         };
         client.start_refresh_loop();
         Ok(client)
     }
 
+    /// The account this client is logged in with.
+    pub fn account(&self) -> String {
+        self.state.account()
+    }
+
+    /// Use the stored login of `account` from now on, in this client and
+    /// all its clones. The account is logged out if it has no login yet.
+    pub async fn switch_account(&self, account: &str) -> Result<()> {
+        if self.state.service_account {
+            n0_error::bail_any!("Can't switch accounts while using a service account token");
+        }
+        let oauth_key = Repo::account_oauth_key(self.client.env.oauth_storage_key(), account);
+        self.state.switch(account, oauth_key).await
+    }
+
+    /// Whether this client uses a service account token instead of a login.
+    pub fn is_service_account(&self) -> bool {
+        self.state.service_account
+    }
+
     pub fn login_state(&self) -> LoginState {
         match self.state.load().get().ok() {
             None => LoginState::Missing,
@@ -538,8 +954,14 @@ impl AuthClient {
         self.state.subscribe_login_state()
     }
 
-    pub fn auth_update_watch(&self) -> watch::Receiver<u64> {
-        self.state.subscribe_auth_updates()
+    /// Changes of the auth state from now on.
+    pub fn auth_events(&self) -> broadcast::Receiver<AuthEvent> {
+        self.state.subscribe_events()
+    }
+
+    /// Progress of the most recent interactive login.
+    pub fn login_diagnostics_watch(&self) -> watch::Receiver<LoginDiagnostics> {
+        self.diagnostics.subscribe()
     }
 
     fn start_refresh_loop(&mut self) {
@@ -547,7 +969,7 @@ impl AuthClient {
             return;
         }
         let client = self.clone();
-        let mut auth_update_rx = self.auth_update_watch();
+        let mut events = self.auth_events();
         let task = tokio::spawn(async move {
             loop {
                 if let Err(err) = client.refresh_if_needed().await {
@@ -556,8 +978,8 @@ impl AuthClient {
                 let sleep_for = client.next_refresh_delay();
                 tokio::select! {
                     _ = tokio::time::sleep(sleep_for) => {},
-                    res = auth_update_rx.changed() => {
-                        if res.is_err() {
+                    res = events.recv() => {
+                        if let Err(broadcast::error::RecvError::Closed) = res {
                             return;
                         }
                     }
@@ -606,46 +1028,88 @@ impl AuthClient {
     }
 
     pub async fn logout(&self) -> Result<()> {
-        self.state.set(None).await?;
+        self.state.set(None, AuthEvent::LoggedOut).await?;
         Ok(())
     }
 
     pub async fn login(&self) -> Result<()> {
+        if self.state.service_account {
+            return Ok(());
+        }
         let auth = self.state.load();
         let auth = match auth.get() {
-            Err(_) => self.client.login().await?,
+            Err(_) => {
+                self.client
+                    .login_with_diagnostics(&self.diagnostics)
+                    .await?
+            }
             Ok(auth) if auth.tokens.expires_in_less_than(REFRESH_AUTH_WHEN) => {
                 match self.client.refresh(&auth.tokens).await {
                     Ok(auth) => auth,
                     Err(err) => {
                         warn!("Failed to refresh auth token: {err:#}");
-                        self.client.login().await?
+                        self.client
+                            .login_with_diagnostics(&self.diagnostics)
+                            .await?
                     }
                 }
             }
             Ok(_) => return Ok(()),
         };
-        self.state.set(Some(auth)).await?;
+        let event = AuthEvent::for_login(Some(&auth));
+        self.state.set(Some(auth), event).await?;
+        Ok(())
+    }
+
+    /// Log in with a device code instead of the browser redirect.
+    ///
+    /// The code to enter is published via [`Self::login_diagnostics_watch`].
+    pub async fn login_device_code(&self) -> Result<()> {
+        if self.state.service_account {
+            return Ok(());
+        }
+        self.diagnostics.send_modify(|d| d.device_code = None);
+        let auth = self.client.login_device_code(&self.diagnostics).await?;
+        let event = AuthEvent::for_login(Some(&auth));
+        self.state.set(Some(auth), event).await?;
         Ok(())
     }
 
     pub async fn refresh(&self) -> Result<()> {
+        if self.state.service_account {
+            let token = read_service_token()
+                .await?
+                .context("The service account token is no longer set")?;
+            self.state
+                .set(Some(service_account_auth(token)), AuthEvent::Refreshed)
+                .await?;
+            return Ok(());
+        }
         let auth = self.state.load();
         let auth = auth.get()?;
         let new_auth = match self.client.refresh(&auth.tokens).await {
             Ok(auth) => auth,
             Err(err) => {
                 warn!("Failed to refresh auth tokens, logging out: {err:#}");
-                self.state.set(None).await?;
+                let event = AuthEvent::RefreshFailed {
+                    error: format!("{err:#}"),
+                };
+                self.state.set(None, event).await?;
                 Err(err).context("Failed to refresh auth tokens, needs login")?
             }
         };
-        self.state.set(Some(new_auth)).await?;
+        self.state.set(Some(new_auth), AuthEvent::Refreshed).await?;
+        if let Some(repo) = self.state.repo.as_ref() {
+            repo.events().record(EventKind::AuthRefreshed);
+        }
         Ok(())
     }
 
     /// Refresh the user profile from the API without refreshing tokens
     pub async fn refresh_profile(&self) -> Result<()> {
+        if self.state.service_account {
+            return Ok(());
+        }
         let auth = self.state.load();
         let auth = auth.get()?;
         let user_id = auth.profile.user_id.clone();
@@ -662,7 +1126,9 @@ impl AuthClient {
             },
             profile: new_profile,
         };
-        self.state.set(Some(new_auth)).await?;
+        self.state
+            .set(Some(new_auth), AuthEvent::ProfileUpdated)
+            .await?;
         Ok(())
     }
 }
@@ -731,14 +1197,21 @@ mod redirect_server {
     use openidconnect::{CsrfToken, RedirectUrl};
     use serde::Deserialize;
     use std::{
+        io,
         net::{Ipv4Addr, SocketAddr},
+        ops::RangeInclusive,
         time::Duration,
     };
     use tokio::{net::TcpListener, sync::mpsc};
     use tokio_util::sync::CancellationToken;
     use tracing::{Instrument, debug, instrument, warn};
 
+    /// The preferred port for the redirect server.
     pub const REDIRECT_SERVER_PORT: u16 = 7076;
+    /// Ports tried in order when the preferred one is taken. The redirect
+    /// URIs for all of them are registered with the OIDC provider.
+    pub const REDIRECT_SERVER_PORTS: RangeInclusive<u16> =
+        REDIRECT_SERVER_PORT..=REDIRECT_SERVER_PORT + 4;
 
     #[derive(Deserialize, Debug)]
     struct OauthRedirectData {
@@ -750,12 +1223,14 @@ mod redirect_server {
         rx: mpsc::Receiver<n0_error::Result<OauthRedirectData>>,
         cancel_token: CancellationToken,
         csrf_token: CsrfToken,
+        addr: SocketAddr,
     }
 
     impl RedirectServer {
         #[instrument("oidc-redirect-server")]
-        pub async fn bind(csrf_token: CsrfToken) -> std::io::Result<Self> {
-            let bind_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), REDIRECT_SERVER_PORT);
+        pub async fn bind(csrf_token: CsrfToken) -> io::Result<Self> {
+            let listener = Self::bind_listener().await?;
+            let bind_addr = listener.local_addr()?;
             let cancel_token = CancellationToken::new();
             let (tx, rx) = mpsc::channel(1);
             let state = AppState { sender: tx.clone() };
@@ -763,7 +1238,6 @@ mod redirect_server {
             let app = Router::new()
                 .route("/oauth/redirect", get(oauth_redirect))
                 .with_state(state);
-            let listener = TcpListener::bind(bind_addr).await?;
             debug!(addr=%bind_addr, "OIDC redirect HTTP server listening");
 
             tokio::spawn({
@@ -786,15 +1260,39 @@ mod redirect_server {
                 cancel_token,
                 rx,
                 csrf_token,
+                addr: bind_addr,
             })
         }
 
-        pub fn url() -> RedirectUrl {
-            RedirectUrl::new(format!(
-                "http://localhost:{}/oauth/redirect",
-                REDIRECT_SERVER_PORT
-            ))
-            .expect("valid url")
+        /// Binds the first free port of [`REDIRECT_SERVER_PORTS`].
+        async fn bind_listener() -> io::Result<TcpListener> {
+            let mut last_err = None;
+            for port in REDIRECT_SERVER_PORTS {
+                let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+                match TcpListener::bind(addr).await {
+                    Ok(listener) => return Ok(listener),
+                    Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                        debug!(%addr, "redirect port taken, trying the next one");
+                        last_err = Some(err);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(last_err.expect("at least one port"))
+        }
+
+        /// The local address the server listens on.
+        pub fn addr(&self) -> SocketAddr {
+            self.addr
+        }
+
+        /// The redirect URI for the port the server listens on.
+        pub fn url(&self) -> RedirectUrl {
+            Self::url_for(self.addr.port())
+        }
+
+        pub fn url_for(port: u16) -> RedirectUrl {
+            RedirectUrl::new(format!("http://localhost:{port}/oauth/redirect")).expect("valid url")
         }
 
         pub async fn recv_with_timeout(&mut self, timeout: Duration) -> n0_error::Result<String> {
@@ -0,0 +1,84 @@
+//! Detecting a wrong system clock from the tokens Datum Cloud issues.
+//!
+//! A fresh ID token is issued the moment it is returned, so its `iat` claim
+//! tells the server's time. Small differences are tolerated by validating
+//! tokens against the server's time instead of ours; beyond
+//! [`MAX_CLOCK_SKEW`] the login fails with [`ClockSkew`], which tells the
+//! user to fix their clock instead of failing token validation in confusing
+//! ways.
+
+use std::{fmt, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use n0_error::stack_error;
+
+/// How far the system clock may be off before logins are refused.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// How far the system clock is off; positive when it is ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockOffset(pub TimeDelta);
+
+impl fmt::Display for ClockOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.num_seconds().unsigned_abs();
+        let direction = if self.0 > TimeDelta::zero() {
+            "ahead"
+        } else {
+            "behind"
+        };
+        match (secs / 86400, secs / 3600 % 24, secs / 60 % 60) {
+            (0, 0, minutes) => write!(f, "{minutes} min {direction}"),
+            (0, hours, minutes) => write!(f, "{hours} h {minutes} min {direction}"),
+            (days, hours, _) => write!(f, "{days} d {hours} h {direction}"),
+        }
+    }
+}
+
+#[stack_error(derive)]
+#[error(
+    "Your system clock is wrong: it is {offset} of Datum Cloud. Correct the date and time settings of this computer and log in again."
+)]
+pub struct ClockSkew {
+    pub offset: ClockOffset,
+}
+
+/// The `iat` claim of a JWT, read without verifying it.
+pub(super) fn unverified_issue_time(jwt: &str) -> Option<DateTime<Utc>> {
+    let payload = jwt.split('.').nth(1)?;
+    let payload = BASE64URL_NOPAD.decode(payload.as_bytes()).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    DateTime::from_timestamp(claims.get("iat")?.as_i64()?, 0)
+}
+
+/// How far `now` is off from the issue time of a token issued just now.
+pub(super) fn check(issued_at: DateTime<Utc>, now: DateTime<Utc>) -> Result<TimeDelta, ClockSkew> {
+    let offset = now - issued_at;
+    if offset.abs() > TimeDelta::from_std(MAX_CLOCK_SKEW).expect("small") {
+        return Err(ClockSkew {
+            offset: ClockOffset(offset),
+        });
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_skew() {
+        let claims = BASE64URL_NOPAD.encode(br#"{"sub":"u","iat":1700000000}"#);
+        let iat = unverified_issue_time(&format!("e30.{claims}.sig")).unwrap();
+        assert_eq!(iat.timestamp(), 1_700_000_000);
+        assert!(unverified_issue_time("not-a-jwt").is_none());
+
+        let offset = check(iat, iat + TimeDelta::seconds(30)).unwrap();
+        assert_eq!(offset, TimeDelta::seconds(30));
+        let err = check(iat, iat + TimeDelta::hours(3)).unwrap_err();
+        assert_eq!(err.offset.to_string(), "3 h 0 min ahead");
+        let err = check(iat, iat - TimeDelta::days(2)).unwrap_err();
+        assert_eq!(err.offset.to_string(), "2 d 0 h behind");
+    }
+}
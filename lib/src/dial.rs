@@ -0,0 +1,114 @@
+//! Connecting to local targets over IPv6 and IPv4.
+//!
+//! A name like `localhost` usually resolves to both `::1` and `127.0.0.1`,
+//! while the service often listens on only one of them. Trying the addresses
+//! one after another can wait out a whole connect timeout on the family that
+//! is firewalled, so they are raced Happy Eyeballs style (RFC 8305): the
+//! families alternate, starting with the one the resolver returned first,
+//! and every attempt gets [`CONNECTION_ATTEMPT_DELAY`] before the next one
+//! starts. The first connection wins and the others are dropped.
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::{net::TcpStream, task::JoinSet};
+
+/// How long an attempt runs alone before the next address is tried.
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connect to `host:port`. IPv6 literals may be given with or without
+/// brackets.
+pub async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let host = unbracket(host);
+    let addrs = tokio::net::lookup_host((host, port)).await?.collect();
+    connect_addrs(addrs).await
+}
+
+/// Connect to the first of `addrs` that accepts, see the [module docs](self).
+pub async fn connect_addrs(addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut addrs = interleave(addrs).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.spawn(TcpStream::connect(addr));
+        }
+        if attempts.is_empty() {
+            break;
+        }
+        let more = !addrs.as_slice().is_empty();
+        tokio::select! {
+            Some(res) = attempts.join_next() => match res {
+                Ok(Ok(stream)) => return Ok(stream),
+                // A failed attempt starts the next one right away.
+                Ok(Err(err)) => last_err = Some(err),
+                Err(err) => last_err = Some(io::Error::other(err)),
+            },
+            _ = n0_future::time::sleep(CONNECTION_ATTEMPT_DELAY), if more => {}
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")))
+}
+
+/// `host` without the brackets around an IPv6 literal.
+pub fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Alternate address families, starting with the family of the first
+/// address and keeping the order within each family.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut out = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn interleaves_families() {
+        let v4 = |port| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let v6 = |port| SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+        let addrs = interleave(vec![v6(1), v6(2), v6(3), v4(4), v4(5)]);
+        assert_eq!(addrs, vec![v6(1), v4(4), v6(2), v4(5), v6(3)]);
+        let addrs = interleave(vec![v4(1), v6(2), v4(3)]);
+        assert_eq!(addrs, vec![v4(1), v6(2), v4(3)]);
+        assert!(interleave(Vec::new()).is_empty());
+        assert_eq!(unbracket("[::1]"), "::1");
+        assert_eq!(unbracket("localhost"), "localhost");
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_address_that_accepts() -> io::Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let open = listener.local_addr()?;
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await?
+            .local_addr()?;
+        let stream = connect_addrs(vec![closed, open]).await?;
+        assert_eq!(stream.peer_addr()?, open);
+        assert!(connect_addrs(vec![closed]).await.is_err());
+        assert!(connect_addrs(Vec::new()).await.is_err());
+        Ok(())
+    }
+}
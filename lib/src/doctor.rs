@@ -0,0 +1,272 @@
+//! Connectivity diagnostics.
+//!
+//! [`run`] binds a throwaway iroh endpoint with the repo's configuration and
+//! checks everything a tunnel depends on: relay reachability, NAT traversal,
+//! n0des, the Datum API and the stored login, and how that login is stored. Each check produces a
+//! [`CheckResult`] with a short hint on how to fix a failure.
+
+use std::time::Duration;
+
+use iroh::{SecretKey, Watcher};
+use n0_error::{Result, StdResultExt};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    Repo,
+    datum_cloud::{DatumCloudClient, LoginState},
+    node::{build_endpoint, build_n0des_client, n0des_api_secret_from_env},
+};
+
+const ONLINE_TIMEOUT: Duration = Duration::from_secs(10);
+const NET_REPORT_TIMEOUT: Duration = Duration::from_secs(5);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+impl CheckStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skipped => "skip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What the user can do about a non-ok result.
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DoctorReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Fail)
+    }
+}
+
+/// Run all connectivity checks.
+pub async fn run(repo: &Repo, datum: &DatumCloudClient) -> DoctorReport {
+    let mut checks = Vec::new();
+    checks.extend(check_iroh(repo).await);
+    checks.push(check_n0des(repo).await);
+    checks.push(check_datum_api(datum).await);
+    checks.push(check_auth(datum).await);
+    checks.push(check_secret_store(repo));
+    DoctorReport { checks }
+}
+
+/// Relay reachability and NAT traversal. Both need a bound endpoint, so they
+/// share one.
+async fn check_iroh(repo: &Repo) -> Vec<CheckResult> {
+    let endpoint = match bind_probe_endpoint(repo).await {
+        Ok(endpoint) => endpoint,
+        Err(err) => {
+            return vec![
+                CheckResult::new(
+                    "relay",
+                    CheckStatus::Fail,
+                    format!("failed to bind iroh endpoint: {err:#}"),
+                )
+                .with_hint("Check the ipv4_addr/ipv6_addr and discovery settings in config.yml"),
+                CheckResult::new("nat", CheckStatus::Skipped, "no endpoint"),
+            ];
+        }
+    };
+
+    let relay = match tokio::time::timeout(ONLINE_TIMEOUT, endpoint.online()).await {
+        Ok(()) => {
+            let addr = endpoint.addr();
+            let relays = addr
+                .relay_urls()
+                .map(|url| url.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            CheckResult::new("relay", CheckStatus::Ok, format!("connected to {relays}"))
+        }
+        Err(_) => CheckResult::new(
+            "relay",
+            CheckStatus::Fail,
+            format!("no home relay after {}s", ONLINE_TIMEOUT.as_secs()),
+        )
+        .with_hint("Outbound HTTPS (443) to the relay servers may be blocked by a firewall"),
+    };
+
+    let nat = {
+        let mut watcher = endpoint.net_report();
+        let report = tokio::time::timeout(NET_REPORT_TIMEOUT, watcher.initialized())
+            .await
+            .ok();
+        match report {
+            None => CheckResult::new("nat", CheckStatus::Warn, "net report did not complete")
+                .with_hint("Connections will fall back to the relay"),
+            Some(report) if !report.udp_v4 && !report.udp_v6 => CheckResult::new(
+                "nat",
+                CheckStatus::Warn,
+                "UDP is blocked, hole punching is not possible",
+            )
+            .with_hint(
+                "Allow outbound UDP to get direct connections; traffic is relayed meanwhile",
+            ),
+            Some(report) if report.mapping_varies_by_dest() == Some(true) => CheckResult::new(
+                "nat",
+                CheckStatus::Warn,
+                "symmetric NAT detected, hole punching is unlikely to succeed",
+            )
+            .with_hint("Traffic will mostly be relayed; consider a less restrictive NAT"),
+            Some(report) => {
+                let direct = endpoint.addr().ip_addrs().count();
+                CheckResult::new(
+                    "nat",
+                    CheckStatus::Ok,
+                    format!(
+                        "udp v4: {}, udp v6: {}, {direct} direct address(es)",
+                        report.udp_v4, report.udp_v6
+                    ),
+                )
+            }
+        }
+    };
+
+    endpoint.close().await;
+    vec![relay, nat]
+}
+
+async fn bind_probe_endpoint(repo: &Repo) -> Result<iroh::Endpoint> {
+    let config = repo.config().await?;
+    // Use a fresh key so the probe never collides with a running listen node.
+    build_endpoint(
+        SecretKey::generate(&mut rand::rng()),
+        &config,
+        Default::default(),
+    )
+    .await
+}
+
+async fn check_n0des(repo: &Repo) -> CheckResult {
+    let secret = match n0des_api_secret_from_env() {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            return CheckResult::new("n0des", CheckStatus::Skipped, "N0DES_API_SECRET is not set");
+        }
+        Err(err) => {
+            return CheckResult::new("n0des", CheckStatus::Fail, format!("{err:#}"))
+                .with_hint("N0DES_API_SECRET must be a valid n0des API secret");
+        }
+    };
+    let endpoint = match bind_probe_endpoint(repo).await {
+        Ok(endpoint) => endpoint,
+        Err(err) => {
+            return CheckResult::new("n0des", CheckStatus::Skipped, format!("{err:#}"));
+        }
+    };
+    let res = tokio::time::timeout(ONLINE_TIMEOUT, build_n0des_client(&endpoint, secret)).await;
+    let result = match res {
+        Ok(Ok(_client)) => CheckResult::new("n0des", CheckStatus::Ok, "connected"),
+        Ok(Err(err)) => CheckResult::new("n0des", CheckStatus::Fail, format!("{err:#}"))
+            .with_hint("Metrics and ticket publishing will be unavailable"),
+        Err(_) => CheckResult::new("n0des", CheckStatus::Fail, "timed out")
+            .with_hint("Metrics and ticket publishing will be unavailable"),
+    };
+    endpoint.close().await;
+    result
+}
+
+async fn check_datum_api(datum: &DatumCloudClient) -> CheckResult {
+    let url = datum.api_url();
+    let res = async {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .anyerr()?;
+        let res = client.get(url).send().await.anyerr()?;
+        n0_error::Ok(res.status())
+    }
+    .await;
+    match res {
+        // Any HTTP response means the API is reachable; the root path is not
+        // expected to return success.
+        Ok(status) => {
+            debug!(%url, %status, "datum api probe");
+            CheckResult::new("datum-api", CheckStatus::Ok, format!("{url} ({status})"))
+        }
+        Err(err) => CheckResult::new("datum-api", CheckStatus::Fail, format!("{url}: {err:#}"))
+            .with_hint("Check your internet connection and any HTTP proxy settings"),
+    }
+}
+
+fn check_secret_store(repo: &Repo) -> CheckResult {
+    let store = repo.secret_store();
+    if store.is_fallback() {
+        CheckResult::new(
+            "secrets",
+            CheckStatus::Warn,
+            "stored unencrypted: no keychain available",
+        )
+        .with_hint(
+            "Set DATUM_CONNECT_PASSPHRASE to encrypt keys and logins, or DATUM_CONNECT_SECRET_STORE=plaintext to keep plain files on purpose",
+        )
+    } else {
+        CheckResult::new("secrets", CheckStatus::Ok, store.kind())
+    }
+}
+
+async fn check_auth(datum: &DatumCloudClient) -> CheckResult {
+    match datum.login_state() {
+        LoginState::Missing => CheckResult::new("auth", CheckStatus::Fail, "not logged in")
+            .with_hint("Log in from the desktop app"),
+        LoginState::Valid | LoginState::NeedsRefresh => match datum.auth().load_refreshed().await {
+            Ok(auth) => match auth.get() {
+                Ok(auth) => CheckResult::new(
+                    "auth",
+                    CheckStatus::Ok,
+                    format!(
+                        "logged in as {}, token expires {}",
+                        auth.profile.email,
+                        auth.tokens.expires_at()
+                    ),
+                ),
+                Err(_) => CheckResult::new("auth", CheckStatus::Fail, "not logged in")
+                    .with_hint("Log in from the desktop app"),
+            },
+            Err(err) => CheckResult::new(
+                "auth",
+                CheckStatus::Fail,
+                format!("token refresh failed: {err:#}"),
+            )
+            .with_hint("Your session expired; log out and log in again"),
+        },
+    }
+}
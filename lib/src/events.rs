@@ -0,0 +1,403 @@
+//! A bounded, persisted log of notable things that happened on this device.
+//!
+//! Events are appended as JSON lines to `events.jsonl` in the repo. Only the
+//! newest [`MAX_EVENTS`] are kept; the file is compacted once it grows to
+//! twice that. A corrupt line is skipped rather than failing the whole log.
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// How many events are kept.
+pub const MAX_EVENTS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    TunnelCreated {
+        tunnel_id: String,
+        label: String,
+    },
+    TunnelEnabled {
+        tunnel_id: String,
+    },
+    TunnelDisabled {
+        tunnel_id: String,
+    },
+    TunnelDeleted {
+        tunnel_id: String,
+    },
+    /// A tunnel's target, label, tags, expiry or filters were changed.
+    TunnelUpdated {
+        tunnel_id: String,
+    },
+    /// A tunnel was turned off because its expiry passed.
+    TunnelExpired {
+        tunnel_id: String,
+    },
+    /// A tunnel was turned off because it transferred `used` bytes of a
+    /// `limit` byte quota.
+    TunnelQuotaExceeded {
+        tunnel_id: String,
+        used: u64,
+        limit: u64,
+    },
+    TargetHealthy {
+        tunnel_id: String,
+    },
+    TargetUnhealthy {
+        tunnel_id: String,
+        error: String,
+    },
+    ClientConnected {
+        remote_id: EndpointId,
+        service: String,
+    },
+    ClientDisconnected {
+        remote_id: EndpointId,
+        service: String,
+    },
+    AuthRefreshed,
+    HeartbeatFailed {
+        project_id: String,
+        error: String,
+    },
+    /// An advertisement whose HTTPProxy no longer exists was deleted.
+    AdvertisementPruned {
+        project_id: String,
+        tunnel_id: String,
+    },
+    /// An enabled tunnel that had lost its advertisement got it back.
+    AdvertisementRestored {
+        project_id: String,
+        tunnel_id: String,
+    },
+}
+
+impl EventKind {
+    /// The tunnel this event is about, if any.
+    pub fn tunnel_id(&self) -> Option<&str> {
+        match self {
+            EventKind::TunnelCreated { tunnel_id, .. }
+            | EventKind::TunnelEnabled { tunnel_id }
+            | EventKind::TunnelDisabled { tunnel_id }
+            | EventKind::TunnelDeleted { tunnel_id }
+            | EventKind::TunnelUpdated { tunnel_id }
+            | EventKind::TunnelExpired { tunnel_id }
+            | EventKind::TunnelQuotaExceeded { tunnel_id, .. }
+            | EventKind::TargetHealthy { tunnel_id }
+            | EventKind::TargetUnhealthy { tunnel_id, .. }
+            | EventKind::AdvertisementPruned { tunnel_id, .. }
+            | EventKind::AdvertisementRestored { tunnel_id, .. } => Some(tunnel_id),
+            _ => None,
+        }
+    }
+
+    /// What went wrong, for events about a failure.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            EventKind::TargetUnhealthy { error, .. } | EventKind::HeartbeatFailed { error, .. } => {
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+
+    /// A one-line description for activity feeds.
+    pub fn description(&self) -> String {
+        match self {
+            EventKind::TunnelCreated { label, .. } => format!("Tunnel \"{label}\" created"),
+            EventKind::TunnelEnabled { tunnel_id } => format!("Tunnel {tunnel_id} enabled"),
+            EventKind::TunnelDisabled { tunnel_id } => format!("Tunnel {tunnel_id} disabled"),
+            EventKind::TunnelDeleted { tunnel_id } => format!("Tunnel {tunnel_id} deleted"),
+            EventKind::TunnelUpdated { tunnel_id } => format!("Tunnel {tunnel_id} changed"),
+            EventKind::TunnelExpired { tunnel_id } => format!("Tunnel {tunnel_id} expired"),
+            EventKind::TunnelQuotaExceeded {
+                tunnel_id,
+                used,
+                limit,
+            } => format!("Tunnel {tunnel_id} disabled after transferring {used} of {limit} bytes"),
+            EventKind::TargetHealthy { tunnel_id } => {
+                format!("Target of tunnel {tunnel_id} is healthy")
+            }
+            EventKind::TargetUnhealthy { tunnel_id, error } => {
+                format!("Target of tunnel {tunnel_id} is unhealthy: {error}")
+            }
+            EventKind::ClientConnected { remote_id, service } => {
+                format!("Client {} connected to {service}", remote_id.fmt_short())
+            }
+            EventKind::ClientDisconnected { remote_id, service } => {
+                format!(
+                    "Client {} disconnected from {service}",
+                    remote_id.fmt_short()
+                )
+            }
+            EventKind::AuthRefreshed => "Login refreshed".to_string(),
+            EventKind::HeartbeatFailed { project_id, error } => {
+                format!("Heartbeat for {project_id} failed: {error}")
+            }
+            EventKind::AdvertisementPruned {
+                project_id,
+                tunnel_id,
+            } => format!("Removed orphaned advertisement {tunnel_id} in {project_id}"),
+            EventKind::AdvertisementRestored {
+                project_id,
+                tunnel_id,
+            } => format!("Restored missing advertisement of tunnel {tunnel_id} in {project_id}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+    /// Email of the user who caused the event, for changes made while
+    /// logged in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+}
+
+/// Filters for [`EventLog::query`]. The default matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    /// Only events at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only events about this tunnel.
+    pub tunnel_id: Option<String>,
+    /// At most this many events, newest first.
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    fn matches(&self, event: &Event) -> bool {
+        if let Some(since) = self.since
+            && event.at < since
+        {
+            return false;
+        }
+        if let Some(tunnel_id) = &self.tunnel_id
+            && event.kind.tunnel_id() != Some(tunnel_id.as_str())
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(derive_more::Debug, Clone)]
+pub struct EventLog {
+    path: Option<PathBuf>,
+    #[debug(skip)]
+    inner: Arc<Mutex<Inner>>,
+    #[debug(skip)]
+    tx: broadcast::Sender<Event>,
+}
+
+#[derive(Default)]
+struct Inner {
+    loaded: bool,
+    events: VecDeque<Event>,
+    /// Lines in the file, including ones already trimmed from `events`.
+    lines_on_disk: usize,
+}
+
+impl EventLog {
+    /// A log persisted at `path`. The file is read on first use.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self::new(Some(path.into()))
+    }
+
+    /// A log that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::new(None)
+    }
+
+    fn new(path: Option<PathBuf>) -> Self {
+        let (tx, _) = broadcast::channel(64);
+        Self {
+            path,
+            inner: Default::default(),
+            tx,
+        }
+    }
+
+    /// Record an event now.
+    pub fn record(&self, kind: EventKind) {
+        self.record_by(kind, None);
+    }
+
+    /// Record an event now, caused by the user with email `by`.
+    pub fn record_by(&self, kind: EventKind, by: Option<String>) {
+        self.push(Event {
+            at: Utc::now(),
+            kind,
+            by,
+        });
+    }
+
+    fn push(&self, event: Event) {
+        let mut inner = self.lock();
+        inner.events.push_back(event.clone());
+        while inner.events.len() > MAX_EVENTS {
+            inner.events.pop_front();
+        }
+        if let Some(path) = &self.path {
+            let res = if inner.lines_on_disk + 1 >= MAX_EVENTS * 2 {
+                rewrite(path, &inner.events).map(|()| inner.events.len())
+            } else {
+                append(path, &event).map(|()| inner.lines_on_disk + 1)
+            };
+            match res {
+                Ok(lines) => inner.lines_on_disk = lines,
+                Err(err) => warn!("Failed to persist event to {}: {err:#}", path.display()),
+            }
+        }
+        drop(inner);
+        self.tx.send(event).ok();
+    }
+
+    /// Events matching `query`, newest first.
+    pub fn query(&self, query: &EventQuery) -> Vec<Event> {
+        let inner = self.lock();
+        inner
+            .events
+            .iter()
+            .rev()
+            .filter(|event| query.matches(event))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// The newest event matching `f`.
+    pub fn latest(&self, mut f: impl FnMut(&Event) -> bool) -> Option<Event> {
+        let inner = self.lock();
+        inner.events.iter().rev().find(|event| f(event)).cloned()
+    }
+
+    /// Events as they are recorded.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        if !inner.loaded {
+            inner.loaded = true;
+            if let Some(path) = &self.path {
+                match load(path) {
+                    Ok((events, lines)) => {
+                        inner.events = events;
+                        inner.lines_on_disk = lines;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => warn!("Failed to read events from {}: {err:#}", path.display()),
+                }
+            }
+        }
+        inner
+    }
+}
+
+fn load(path: &PathBuf) -> std::io::Result<(VecDeque<Event>, usize)> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut events = VecDeque::new();
+    let mut lines = 0;
+    for line in file.lines() {
+        let line = line?;
+        lines += 1;
+        match serde_json::from_str(&line) {
+            Ok(event) => events.push_back(event),
+            Err(err) => warn!("Skipping unreadable event in {}: {err}", path.display()),
+        }
+        if events.len() > MAX_EVENTS {
+            events.pop_front();
+        }
+    }
+    Ok((events, lines))
+}
+
+fn append(path: &PathBuf, event: &Event) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    file.write_all(&line)
+}
+
+fn rewrite(path: &PathBuf, events: &VecDeque<Event>) -> std::io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut data = Vec::new();
+    for event in events {
+        serde_json::to_writer(&mut data, event)?;
+        data.push(b'\n');
+    }
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persists_and_stays_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let log = EventLog::open(&path);
+        for i in 0..(MAX_EVENTS * 2 + 10) {
+            log.record(EventKind::TunnelEnabled {
+                tunnel_id: format!("t{i}"),
+            });
+        }
+        log.record(EventKind::AuthRefreshed);
+
+        let reopened = EventLog::open(&path);
+        let events = reopened.query(&EventQuery::default());
+        assert_eq!(events.len(), MAX_EVENTS);
+        assert_eq!(events[0].kind, EventKind::AuthRefreshed);
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < MAX_EVENTS * 2);
+    }
+
+    #[test]
+    fn query_filters_by_tunnel() {
+        let log = EventLog::in_memory();
+        log.record(EventKind::TunnelCreated {
+            tunnel_id: "a".into(),
+            label: "A".into(),
+        });
+        log.record(EventKind::TunnelCreated {
+            tunnel_id: "b".into(),
+            label: "B".into(),
+        });
+        log.record(EventKind::TunnelDisabled {
+            tunnel_id: "a".into(),
+        });
+
+        let events = log.query(&EventQuery {
+            tunnel_id: Some("a".into()),
+            limit: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(
+            events.iter().map(|e| &e.kind).collect::<Vec<_>>(),
+            vec![&EventKind::TunnelDisabled {
+                tunnel_id: "a".into()
+            }]
+        );
+    }
+}
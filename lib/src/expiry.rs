@@ -0,0 +1,50 @@
+//! Turning off tunnels whose expiry has passed.
+//!
+//! A tunnel shared for a limited time carries an
+//! [`expires_at`](crate::ProxyState::expires_at). Once it passes, the agent
+//! disables the proxy, which stops the listener from accepting clients for
+//! it, and takes its ticket out of n0des. Cloud tunnels are additionally
+//! turned off in Datum Cloud by [`TunnelService::run_expiry`].
+//!
+//! [`TunnelService::run_expiry`]: crate::tunnels::TunnelService::run_expiry
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use iroh::EndpointId;
+use tracing::{info, warn};
+
+use crate::{Repo, StateWrapper, events::EventKind, health};
+
+/// How often expiries are checked.
+const TICK: Duration = Duration::from_secs(1);
+
+pub(crate) async fn run(
+    state: StateWrapper,
+    repo: Repo,
+    n0des: Option<Arc<iroh_n0des::Client>>,
+    endpoint_id: EndpointId,
+) {
+    loop {
+        let now = Utc::now();
+        let due = state
+            .get()
+            .proxies
+            .iter()
+            .any(|p| p.enabled && p.is_expired(now));
+        if due {
+            match state.update(&repo, |state| state.expire(now)).await {
+                Ok(expired) => {
+                    for proxy in expired {
+                        let tunnel_id = proxy.id().to_string();
+                        info!(%tunnel_id, "tunnel expired");
+                        health::set_published(n0des.as_deref(), &proxy, endpoint_id, false).await;
+                        repo.events().record(EventKind::TunnelExpired { tunnel_id });
+                    }
+                }
+                Err(err) => warn!("Failed to expire tunnels: {err:#}"),
+            }
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
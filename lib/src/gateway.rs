@@ -1,6 +1,12 @@
-use std::{io, net::SocketAddr, str::FromStr, sync::Arc};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use askama::Template;
+use chrono::Utc;
 use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use hyper::{
     StatusCode,
@@ -14,26 +20,122 @@ use iroh_proxy_utils::{
         Deny, DownstreamProxy, ErrorResponder, HttpProxyOpts, ProxyMode, RequestHandler, SrcAddr,
     },
 };
-use n0_error::Result;
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
 use tokio::net::TcpListener;
 #[cfg(unix)]
 use tokio::net::UnixListener;
-use tracing::info;
+use tracing::{debug, info, warn};
 
+mod access_log;
+mod balancer;
+mod destinations;
+mod forwarded;
+mod front;
+mod http1;
+mod limits;
+mod liveness;
 mod metrics;
+pub(crate) mod proxy_protocol;
+mod switch;
+pub mod ticket_cache;
+mod ticket_events;
+pub mod tickets;
+mod timing;
+mod upstreams;
+pub mod verification;
 
-use self::metrics::{GatewayMetrics, MetricsHttpState, serve_metrics_http, shared_gateway_metrics};
-use crate::build_endpoint;
+pub use self::{
+    ticket_cache::{MemoryTicketCache, TicketCache},
+    tickets::TicketClient,
+};
+
+use self::{
+    access_log::{AccessLog, AccessRecord, Outcome, shared_access_log},
+    balancer::{Backend, Balancer},
+    front::Front,
+    http1::ResponseLimits,
+    liveness::LivenessChecker,
+    metrics::{
+        GatewayMetrics, MetricsHttpState, RouteKind, serve_metrics_http, shared_gateway_metrics,
+    },
+    proxy_protocol::ProxiedPeers,
+    switch::EndpointSwitches,
+    verification::HostnameVerifier,
+};
+use crate::{
+    AdvertismentTicket, PathInfo, build_endpoint,
+    config::{ClientIpConfig, GatewayConfig, HeaderLimitsConfig, RequestLimitsConfig},
+    ip_filter::{self, IpFilter},
+    log_limit::warn_limited,
+    spool::Spool,
+};
 
 pub async fn bind_and_serve(
     secret_key: SecretKey,
-    config: crate::config::GatewayConfig,
+    mut config: crate::config::GatewayConfig,
     tcp_bind_addr: SocketAddr,
     metrics_bind_addr: Option<SocketAddr>,
 ) -> Result<()> {
+    config.common.discover_nat64_prefix().await;
     let listener = TcpListener::bind(tcp_bind_addr).await?;
-    let endpoint = build_endpoint(secret_key, &config.common).await?;
-    serve_with_metrics(endpoint, listener, metrics_bind_addr).await
+    let endpoint = build_endpoint(secret_key, &config.common, Default::default()).await?;
+    let tickets = ticket_client(&endpoint, &config).await?;
+    #[cfg(unix)]
+    if let Some(path) = &config.uds_path {
+        let uds_listener = bind_uds(path)?;
+        let tcp = serve_with_tickets(
+            endpoint.clone(),
+            listener,
+            &config,
+            metrics_bind_addr,
+            tickets.clone(),
+        );
+        let uds = serve_uds_with_tickets(endpoint, uds_listener, &config, tickets);
+        tokio::try_join!(tcp, uds)?;
+        return Ok(());
+    }
+    #[cfg(not(unix))]
+    if config.uds_path.is_some() {
+        warn!("uds_path is set but Unix domain sockets are not supported here");
+    }
+    serve_with_tickets(endpoint, listener, &config, metrics_bind_addr, tickets).await
+}
+
+/// The codename lookup client, if `tickets.enabled`. It talks to n0des with
+/// the API secret in `N0DES_API_SECRET`, and caches in Redis if
+/// `tickets.redis_url` is set.
+async fn ticket_client(
+    endpoint: &Endpoint,
+    config: &GatewayConfig,
+) -> Result<Option<TicketClient>> {
+    if !config.tickets.enabled {
+        return Ok(None);
+    }
+    let Some(api_secret) = crate::node::n0des_api_secret_from_env()? else {
+        n0_error::bail_any!("tickets.enabled needs N0DES_API_SECRET to be set");
+    };
+    let remote = api_secret.remote.clone();
+    let n0des = crate::node::build_n0des_client(endpoint, api_secret).await?;
+    let mut client = TicketClient::new(n0des, config);
+    if let Some(url) = &config.tickets.redis_url {
+        #[cfg(feature = "redis")]
+        {
+            let cache = ticket_cache::RedisTicketCache::connect(url).await?;
+            info!("sharing ticket lookups through Redis");
+            client = client.with_cache(Arc::new(cache));
+        }
+        #[cfg(not(feature = "redis"))]
+        {
+            n0_error::bail_any!(
+                "tickets.redis_url is {url}, but the gateway was built without the redis feature"
+            );
+        }
+    }
+    if config.tickets.subscribe {
+        client = client.subscribe(endpoint.clone(), remote);
+    }
+    Ok(Some(client))
 }
 
 pub async fn serve(endpoint: Endpoint, listener: TcpListener) -> Result<()> {
@@ -44,6 +146,33 @@ pub async fn serve_with_metrics(
     endpoint: Endpoint,
     listener: TcpListener,
     metrics_bind_addr: Option<SocketAddr>,
+) -> Result<()> {
+    serve_with_config(
+        endpoint,
+        listener,
+        &GatewayConfig::default(),
+        metrics_bind_addr,
+    )
+    .await
+}
+
+pub async fn serve_with_config(
+    endpoint: Endpoint,
+    listener: TcpListener,
+    config: &GatewayConfig,
+    metrics_bind_addr: Option<SocketAddr>,
+) -> Result<()> {
+    serve_with_tickets(endpoint, listener, config, metrics_bind_addr, None).await
+}
+
+/// Like [`serve_with_config`], routing codename hosts by the tickets `tickets`
+/// looks up.
+pub async fn serve_with_tickets(
+    endpoint: Endpoint,
+    listener: TcpListener,
+    config: &GatewayConfig,
+    metrics_bind_addr: Option<SocketAddr>,
+    tickets: Option<TicketClient>,
 ) -> Result<()> {
     let tcp_bind_addr = listener.local_addr()?;
     info!(
@@ -54,7 +183,7 @@ pub async fn serve_with_metrics(
 
     // Use one shared metrics instance so both TCP and UDS listeners contribute
     // to the same /metrics output in this process.
-    let metrics = shared_gateway_metrics();
+    let metrics = shared_gateway_metrics(&config.metrics);
     if let Some(metrics_bind_addr) = metrics_bind_addr {
         let state = MetricsHttpState::new(endpoint.clone(), metrics.clone());
         tokio::spawn(async move {
@@ -64,19 +193,107 @@ pub async fn serve_with_metrics(
         });
     }
 
-    let resolver_endpoint = endpoint.clone();
-    let error_endpoint = endpoint.clone();
-    let proxy = DownstreamProxy::new(endpoint, Default::default());
-    let mode = ProxyMode::Http(
-        HttpProxyOpts::new(HeaderResolver::new(resolver_endpoint, metrics.clone()))
-            .error_responder(ErrorResponseWriter::new(error_endpoint, metrics)),
-    );
-    proxy.forward_tcp_listener(listener, mode).await
+    let proxied_peers = config
+        .client_ip
+        .proxy_protocol
+        .then(|| Arc::new(ProxiedPeers::default()));
+    let server = if config.upstream.http2 {
+        let resolver = header_resolver(
+            &endpoint,
+            config,
+            metrics.clone(),
+            proxied_peers.clone(),
+            tickets,
+        )?;
+        let mut spool = config.common.request_spool.clone();
+        if let Some(max) = config.request_limits.max_request_body_bytes {
+            spool.max_bytes = spool.max_bytes.min(max);
+        }
+        let front = Front::new(
+            endpoint,
+            resolver,
+            Spool::new(spool),
+            config.upstream.compression,
+            config.response_compression.clone(),
+            ResponseLimits::new(&config.header_limits),
+            metrics.clone(),
+        );
+        TcpServer::Front(Arc::new(front))
+    } else {
+        if config.response_compression.enabled {
+            warn!("response_compression needs upstream.http2, responses are sent as they are");
+        }
+        let mode = http_proxy_mode(
+            &endpoint,
+            config,
+            metrics.clone(),
+            proxied_peers.clone(),
+            tickets,
+        )?;
+        TcpServer::Proxy(DownstreamProxy::new(endpoint, Default::default()), mode)
+    };
+    let timing = config.metrics.upstream_timing.then(|| metrics.clone());
+    if proxied_peers.is_none() && timing.is_none() {
+        return server.serve(listener).await;
+    }
+    // The server takes a loopback listener; connections reach it through
+    // the PROXY protocol or timing relay.
+    let inner = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let inner_addr = inner.local_addr()?;
+    let relay = match proxied_peers {
+        Some(peers) => {
+            let sources = config.client_ip.proxy_protocol_sources.clone().into();
+            tokio::spawn(proxy_protocol::serve(
+                listener, inner_addr, peers, sources, timing,
+            ))
+        }
+        None => tokio::spawn(timing::serve(listener, inner_addr, metrics)),
+    };
+    let _relay = AbortOnDropHandle::new(relay);
+    server.serve(inner).await
+}
+
+/// What serves the requests of the TCP listener.
+enum TcpServer {
+    Proxy(DownstreamProxy, ProxyMode),
+    /// The gateway's own server, with `upstream.http2`.
+    Front(Arc<Front>),
+}
+
+impl TcpServer {
+    async fn serve(self, listener: TcpListener) -> Result<()> {
+        match self {
+            Self::Proxy(proxy, mode) => proxy.forward_tcp_listener(listener, mode).await,
+            Self::Front(front) => front::serve(listener, front).await,
+        }
+    }
 }
 
 /// Serves the gateway on a Unix Domain Socket.
 #[cfg(unix)]
 pub async fn serve_uds(endpoint: Endpoint, listener: UnixListener) -> Result<()> {
+    serve_uds_with_config(endpoint, listener, &GatewayConfig::default()).await
+}
+
+/// Serves the gateway on a Unix Domain Socket with the given gateway config.
+#[cfg(unix)]
+pub async fn serve_uds_with_config(
+    endpoint: Endpoint,
+    listener: UnixListener,
+    config: &GatewayConfig,
+) -> Result<()> {
+    serve_uds_with_tickets(endpoint, listener, config, None).await
+}
+
+/// Like [`serve_uds_with_config`], routing codename hosts by the tickets
+/// `tickets` looks up.
+#[cfg(unix)]
+pub async fn serve_uds_with_tickets(
+    endpoint: Endpoint,
+    listener: UnixListener,
+    config: &GatewayConfig,
+    tickets: Option<TicketClient>,
+) -> Result<()> {
     let uds_path = listener
         .local_addr()
         .ok()
@@ -87,14 +304,9 @@ pub async fn serve_uds(endpoint: Endpoint, listener: UnixListener) -> Result<()>
         "UDS proxy gateway started"
     );
 
-    let metrics = shared_gateway_metrics();
-    let resolver_endpoint = endpoint.clone();
-    let error_endpoint = endpoint.clone();
+    let metrics = shared_gateway_metrics(&config.metrics);
+    let mode = http_proxy_mode(&endpoint, config, metrics, None, tickets)?;
     let proxy = DownstreamProxy::new(endpoint, Default::default());
-    let mode = ProxyMode::Http(
-        HttpProxyOpts::new(HeaderResolver::new(resolver_endpoint, metrics.clone()))
-            .error_responder(ErrorResponseWriter::new(error_endpoint, metrics)),
-    );
     proxy.forward_uds_listener(listener, mode).await
 }
 
@@ -102,27 +314,125 @@ pub async fn serve_uds(endpoint: Endpoint, listener: UnixListener) -> Result<()>
 #[cfg(unix)]
 pub async fn bind_and_serve_uds(
     secret_key: SecretKey,
-    config: crate::config::GatewayConfig,
+    mut config: crate::config::GatewayConfig,
     path: impl AsRef<std::path::Path>,
 ) -> Result<()> {
-    let path = path.as_ref();
+    config.common.discover_nat64_prefix().await;
+    let listener = bind_uds(path.as_ref())?;
+    let endpoint = build_endpoint(secret_key, &config.common, Default::default()).await?;
+    let tickets = ticket_client(&endpoint, &config).await?;
+    serve_uds_with_tickets(endpoint, listener, &config, tickets).await
+}
+
+/// Bind a Unix domain socket at `path`, replacing a stale socket file.
+#[cfg(unix)]
+fn bind_uds(path: &std::path::Path) -> Result<UnixListener> {
     if path.exists() {
         std::fs::remove_file(path)?;
     }
-    let listener = UnixListener::bind(path)?;
-    let endpoint = build_endpoint(secret_key, &config.common).await?;
-    serve_uds(endpoint, listener).await
+    UnixListener::bind(path).with_std_context(|_| format!("Failed to bind {}", path.display()))
+}
+
+fn http_proxy_mode(
+    endpoint: &Endpoint,
+    config: &GatewayConfig,
+    metrics: Arc<GatewayMetrics>,
+    proxied_peers: Option<Arc<ProxiedPeers>>,
+    tickets: Option<TicketClient>,
+) -> Result<ProxyMode> {
+    let resolver = header_resolver(endpoint, config, metrics.clone(), proxied_peers, tickets)?;
+    Ok(ProxyMode::Http(
+        HttpProxyOpts::new(resolver)
+            .error_responder(ErrorResponseWriter::new(endpoint.clone(), metrics)),
+    ))
+}
+
+fn header_resolver(
+    endpoint: &Endpoint,
+    config: &GatewayConfig,
+    metrics: Arc<GatewayMetrics>,
+    proxied_peers: Option<Arc<ProxiedPeers>>,
+    tickets: Option<TicketClient>,
+) -> Result<HeaderResolver> {
+    let verifier = config
+        .hostname_verification
+        .enabled
+        .then(|| HostnameVerifier::new(&config.hostname_verification, &config.common));
+    let liveness = config
+        .liveness
+        .enabled
+        .then(|| LivenessChecker::new(endpoint.clone(), &config.liveness, metrics.clone()));
+    let access_log =
+        shared_access_log(&config.access_log).std_context("Failed to open access log")?;
+    Ok(HeaderResolver::new(
+        endpoint.clone(),
+        metrics,
+        verifier,
+        liveness,
+        config,
+        access_log,
+        proxied_peers,
+        tickets,
+    ))
+}
+
+/// The host a request was sent to, from `Host` or the HTTP/2 authority.
+fn request_host(req: &HttpRequest) -> Option<&str> {
+    req.headers
+        .get(http::header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| req.uri.host())
+}
+
+/// The request body size announced in `Content-Length`.
+fn content_length(headers: &HeaderMap<HeaderValue>) -> Option<u64> {
+    headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// The endpoint a request asked for, if it names exactly one.
+fn requested_endpoint(headers: &HeaderMap<HeaderValue>) -> Option<EndpointId> {
+    let value = headers.get(HEADER_NODE_ID)?.to_str().ok()?;
+    let mut entries = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    match (entries.next(), entries.next()) {
+        (Some(entry), None) => Backend::parse(entry).map(|backend| backend.endpoint_id),
+        _ => None,
+    }
 }
 
 const HEADER_NODE_ID: &str = "x-iroh-endpoint-id";
 const HEADER_TARGET_HOST: &str = "x-datum-target-host";
 const HEADER_TARGET_PORT: &str = "x-datum-target-port";
+/// Set to `http1` to keep a request off HTTP/2 upstreams.
+const HEADER_UPSTREAM_PROTOCOL: &str = "x-datum-upstream-protocol";
 
-const DATUM_HEADERS: [&str; 3] = [HEADER_NODE_ID, HEADER_TARGET_HOST, HEADER_TARGET_PORT];
+const DATUM_HEADERS: [&str; 6] = [
+    HEADER_NODE_ID,
+    HEADER_TARGET_HOST,
+    HEADER_TARGET_PORT,
+    HEADER_UPSTREAM_PROTOCOL,
+    ip_filter::ALLOW_HEADER,
+    ip_filter::DENY_HEADER,
+];
 
 struct HeaderResolver {
     endpoint: Endpoint,
     metrics: Arc<GatewayMetrics>,
+    verifier: Option<HostnameVerifier>,
+    liveness: Option<LivenessChecker>,
+    header_limits: HeaderLimitsConfig,
+    client_ip: ClientIpConfig,
+    request_limits: RequestLimitsConfig,
+    access_log: Option<Arc<AccessLog>>,
+    switches: EndpointSwitches,
+    balancer: Balancer,
+    proxied_peers: Option<Arc<ProxiedPeers>>,
+    tickets: Option<TicketClient>,
 }
 
 impl RequestHandler for HeaderResolver {
@@ -131,12 +441,178 @@ impl RequestHandler for HeaderResolver {
         src_addr: SrcAddr,
         req: &mut HttpRequest,
     ) -> Result<EndpointId, Deny> {
-        let is_tcp = matches!(src_addr, SrcAddr::Tcp(_));
+        self.handle(src_addr, req).await.map_err(Deny::from)
+    }
+}
+
+impl HeaderResolver {
+    /// Resolve a request and rewrite it for forwarding, with the metrics and
+    /// access log entry that go with it.
+    async fn handle(&self, src_addr: SrcAddr, req: &mut HttpRequest) -> Result<EndpointId, Denial> {
+        let started = Instant::now();
+        let kind = if req.method == http::Method::CONNECT {
+            RouteKind::Tunnel
+        } else {
+            RouteKind::Origin
+        };
+        let record = self
+            .access_log
+            .is_some()
+            .then(|| self.access_record(&src_addr, req));
+        let request_bytes = content_length(&req.headers);
+        let timeout = Duration::from_millis(self.request_limits.resolve_timeout_ms);
+        let res = match tokio::time::timeout(timeout, self.resolve(src_addr, req)).await {
+            Ok(res) => res,
+            Err(_) => {
+                debug!("denied request: resolving the endpoint took longer than {timeout:?}");
+                self.metrics.inc_denied_timeout();
+                Err(Denial::new(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "timed out resolving the endpoint",
+                ))
+            }
+        };
+        match &res {
+            Ok(endpoint_id) => {
+                self.metrics.observe_upstream(*endpoint_id, request_bytes);
+                self.metrics.observe_resolve(kind, started.elapsed());
+            }
+            Err(_) => {
+                if let Some(endpoint_id) = requested_endpoint(&req.headers) {
+                    self.metrics.observe_denied(endpoint_id);
+                }
+            }
+        }
+        if let Some(record) = record {
+            self.log_access(record, &res, started.elapsed());
+        }
+        res
+    }
+
+    fn new(
+        endpoint: Endpoint,
+        metrics: Arc<GatewayMetrics>,
+        verifier: Option<HostnameVerifier>,
+        liveness: Option<LivenessChecker>,
+        config: &GatewayConfig,
+        access_log: Option<Arc<AccessLog>>,
+        proxied_peers: Option<Arc<ProxiedPeers>>,
+        tickets: Option<TicketClient>,
+    ) -> Self {
+        Self {
+            endpoint,
+            metrics,
+            verifier,
+            liveness,
+            header_limits: config.header_limits.clone(),
+            client_ip: config.client_ip.clone(),
+            request_limits: config.request_limits.clone(),
+            access_log,
+            switches: EndpointSwitches::new(),
+            balancer: Balancer::new(config.balancing.policy),
+            proxied_peers,
+            tickets,
+        }
+    }
+
+    /// The address the connection came from: the socket peer, or the client
+    /// named in its PROXY header.
+    fn peer(&self, src_addr: &SrcAddr) -> Option<IpAddr> {
+        self.peer_addr(src_addr).map(|addr| addr.ip())
+    }
+
+    /// [`Self::peer`] with its port.
+    fn peer_addr(&self, src_addr: &SrcAddr) -> Option<SocketAddr> {
         match src_addr {
-            SrcAddr::Tcp(_) => self.metrics.inc_tcp_requests(),
+            SrcAddr::Tcp(addr) => {
+                let client = self
+                    .proxied_peers
+                    .as_ref()
+                    .and_then(|peers| peers.client(*addr));
+                Some(client.unwrap_or(*addr))
+            }
+            #[cfg(unix)]
+            SrcAddr::Unix(_) => None,
+        }
+    }
+
+    /// The client's address to pass on to agents: the [peer](Self::peer),
+    /// or the `X-Forwarded-For` entry `trusted_hops` names, which comes
+    /// without a port.
+    fn client_addr(
+        &self,
+        src_addr: &SrcAddr,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<SocketAddr> {
+        let peer = self.peer_addr(src_addr)?;
+        match ip_filter::client_ip(Some(peer.ip()), headers, self.client_ip.trusted_hops)? {
+            ip if ip == peer.ip() => Some(peer),
+            ip => Some(SocketAddr::new(ip, 0)),
+        }
+    }
+
+    /// Start an access log record with what is known before resolving.
+    fn access_record(&self, src_addr: &SrcAddr, req: &HttpRequest) -> AccessRecord {
+        let peer = self.peer(src_addr);
+        let host = request_host(req);
+        AccessRecord {
+            timestamp: Utc::now(),
+            client: ip_filter::client_ip(peer, &req.headers, self.client_ip.trusted_hops),
+            host: host.map(str::to_string),
+            method: req.method.to_string(),
+            path: req
+                .uri
+                .path_and_query()
+                .map_or_else(|| req.uri.to_string(), |path| path.to_string()),
+            version: format!("{:?}", req.version),
+            endpoint_id: None,
+            outcome: Outcome::Denied,
+            status: None,
+            request_bytes: content_length(&req.headers),
+            duration_ms: 0.0,
+            path_type: None,
+        }
+    }
+
+    fn log_access(
+        &self,
+        mut record: AccessRecord,
+        res: &Result<EndpointId, Denial>,
+        duration: Duration,
+    ) {
+        let Some(access_log) = &self.access_log else {
+            return;
+        };
+        match res {
+            Ok(endpoint_id) => {
+                record.endpoint_id = Some(*endpoint_id);
+                record.outcome = Outcome::Forwarded;
+                let path = PathInfo::for_remote(&self.endpoint, *endpoint_id);
+                record.path_type = Some(path.kind.label());
+            }
+            Err(denial) => record.status = denial.status().map(|status| status.as_u16()),
+        }
+        record.set_duration(duration);
+        access_log.record(&record);
+    }
+
+    /// Pick the endpoint for a request and rewrite it for forwarding.
+    async fn resolve(
+        &self,
+        src_addr: SrcAddr,
+        req: &mut HttpRequest,
+    ) -> Result<EndpointId, Denial> {
+        let is_tcp = matches!(src_addr, SrcAddr::Tcp(_));
+        if is_tcp {
+            self.metrics.inc_tcp_requests();
+        } else {
             #[cfg(unix)]
-            SrcAddr::Unix(_) => self.metrics.inc_uds_requests(),
+            self.metrics.inc_uds_requests();
         }
+        let peer = self.peer(&src_addr);
+        self.check_header_limits(&req.headers)?;
+        self.check_body_limit(&req.headers)?;
+        self.check_ip_filter(peer, &req.headers)?;
         match req.classify()? {
             HttpRequestKind::Tunnel => {
                 self.metrics.inc_tunnel_requests();
@@ -148,7 +624,8 @@ impl RequestHandler for HeaderResolver {
                     #[cfg(unix)]
                     self.metrics.inc_tunnel_uds_requests();
                 }
-                let endpoint_id = self.endpoint_id_from_headers(&req.headers)?;
+                let backends = self.backends_from_headers(&req.headers)?;
+                let endpoint_id = self.select_endpoint(&backends).await?;
                 req.remove_headers(DATUM_HEADERS);
                 Ok(endpoint_id)
             }
@@ -162,55 +639,360 @@ impl RequestHandler for HeaderResolver {
                     #[cfg(unix)]
                     self.metrics.inc_origin_uds_requests();
                 }
-                let endpoint_id = self.endpoint_id_from_headers(&req.headers)?;
-                let host = self.header_value(&req.headers, HEADER_TARGET_HOST)?;
-                let port = self
-                    .header_value(&req.headers, HEADER_TARGET_PORT)?
-                    .parse::<u16>()
-                    .map_err(|_| {
-                        self.metrics.inc_denied_invalid_target_port();
-                        Deny::bad_request("invalid x-datum-target-port header")
-                    })?;
+                let (endpoint_id, target) = match self.ticket_for(req).await? {
+                    Some((codename, ticket)) => {
+                        self.observe_endpoint(&req.headers, ticket.endpoint);
+                        let ticket = self.select_ticket_endpoint(&codename, ticket).await?;
+                        self.observe_endpoint(&req.headers, ticket.endpoint);
+                        let endpoint_id = ticket.endpoint;
+                        let (host, port) = ticket.service().target_for_path(req.uri.path());
+                        (endpoint_id, Authority::new(host.to_string(), port))
+                    }
+                    None => self.route_by_headers(&req.headers).await?,
+                };
+                if self.client_ip.forwarded_headers {
+                    let client_host = request_host(req).map(str::to_string);
+                    forwarded::set_headers(
+                        &mut req.headers,
+                        client_host.as_deref(),
+                        peer,
+                        self.client_ip.trusted_hops,
+                    );
+                }
                 // Rewrite the request target.
-                req.set_absolute_http_authority(Authority::new(host.to_string(), port))?
+                req.set_absolute_http_authority(target)?
                     .remove_headers(DATUM_HEADERS);
                 Ok(endpoint_id)
             }
         }
     }
-}
 
-impl HeaderResolver {
-    fn new(endpoint: Endpoint, metrics: Arc<GatewayMetrics>) -> Self {
-        Self { endpoint, metrics }
+    /// Route an origin request by the endpoint and target headers Envoy
+    /// attached.
+    async fn route_by_headers(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<(EndpointId, Authority), Denial> {
+        let backends = self.backends_from_headers(headers)?;
+        // Switches are only meaningful for a hostname served by a
+        // single endpoint; replicas take turns by design.
+        if let [backend] = backends[..] {
+            self.verify_hostname(headers, backend.endpoint_id).await?;
+            self.observe_endpoint(headers, backend.endpoint_id);
+        }
+        let endpoint_id = self.select_endpoint(&backends).await?;
+        if backends.len() > 1 {
+            self.verify_hostname(headers, endpoint_id).await?;
+        }
+        let host = self.header_value(headers, HEADER_TARGET_HOST)?;
+        let port = self
+            .header_value(headers, HEADER_TARGET_PORT)?
+            .parse::<u16>()
+            .map_err(|_| {
+                self.metrics.inc_denied_invalid_target_port();
+                Denial::bad_request("invalid x-datum-target-port header")
+            })?;
+        Ok((endpoint_id, Authority::new(host.to_string(), port)))
+    }
+
+    /// The ticket to route an origin request by: with ticket lookups on, the
+    /// one published for a codename host when Envoy did not name an endpoint.
+    async fn ticket_for(
+        &self,
+        req: &HttpRequest,
+    ) -> Result<Option<(String, AdvertismentTicket)>, Denial> {
+        let Some(tickets) = &self.tickets else {
+            return Ok(None);
+        };
+        if req.headers.contains_key(HEADER_NODE_ID) {
+            return Ok(None);
+        }
+        let Some(codename) = request_host(req).and_then(|host| tickets.codename(host)) else {
+            return Ok(None);
+        };
+        match tickets.get(&codename).await {
+            Ok(Some(ticket)) if !tickets.is_fresh(&ticket) => {
+                debug!(%codename, age = ?ticket.age(), "denied request: ticket is outdated");
+                self.metrics.inc_denied_stale_ticket();
+                Err(Denial::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "the agent of this tunnel is not running",
+                ))
+            }
+            Ok(Some(ticket)) => Ok(Some((codename, ticket))),
+            Ok(None) => {
+                debug!(%codename, "denied request: no ticket is published");
+                self.metrics.inc_denied_unknown_codename();
+                Err(Denial::new(
+                    StatusCode::NOT_FOUND,
+                    format!("no tunnel is published as {codename}"),
+                ))
+            }
+            Err(err) => {
+                warn_limited!("gateway.tickets", %codename, "ticket lookup failed: {err:#}");
+                Err(Denial::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "ticket lookup failed",
+                ))
+            }
+        }
+    }
+
+    /// [`Self::select_endpoint`] for the endpoint of a codename's ticket,
+    /// telling the ticket cache how the liveness dial went. Returns the
+    /// ticket the request is routed by.
+    ///
+    /// If the dial fails, the ticket is fetched again and the request
+    /// resolved once more if the agent published it since, under another
+    /// endpoint or after a restart, so a request that arrives just after the
+    /// agent came back isn't turned away by what was cached before.
+    async fn select_ticket_endpoint(
+        &self,
+        codename: &str,
+        ticket: AdvertismentTicket,
+    ) -> Result<AdvertismentTicket, Denial> {
+        let denial = match self.dial_ticket_endpoint(codename, &ticket).await {
+            Ok(_) => return Ok(ticket),
+            Err(denial) => denial,
+        };
+        let (Some(tickets), Some(liveness)) = (&self.tickets, &self.liveness) else {
+            return Err(denial);
+        };
+        let fresh = match tickets.refetch(codename).await {
+            Ok(Some(fresh)) if tickets.is_fresh(&fresh) => fresh,
+            _ => return Err(denial),
+        };
+        let republished = fresh.endpoint != ticket.endpoint
+            || fresh
+                .published_at
+                .is_some_and(|at| ticket.published_at.is_none_or(|previous| at > previous));
+        if !republished {
+            return Err(denial);
+        }
+        debug!(%codename, endpoint_id = %fresh.endpoint.fmt_short(), "retrying with a republished ticket");
+        self.metrics.inc_ticket_retries();
+        liveness.invalidate(&fresh.endpoint);
+        self.dial_ticket_endpoint(codename, &fresh).await?;
+        Ok(fresh)
     }
 
-    fn endpoint_id_from_headers(
+    async fn dial_ticket_endpoint(
         &self,
+        codename: &str,
+        ticket: &AdvertismentTicket,
+    ) -> Result<EndpointId, Denial> {
+        let backend = Backend {
+            endpoint_id: ticket.endpoint,
+            weight: ticket.data.weight.unwrap_or(1).max(1),
+        };
+        let res = self.select_endpoint(&[backend]).await;
+        if let (Some(tickets), Some(_)) = (&self.tickets, &self.liveness) {
+            tickets
+                .record_dial(codename, ticket.endpoint, res.is_ok())
+                .await;
+        }
+        res
+    }
+
+    /// Deny requests whose headers are over the configured limits.
+    fn check_header_limits(&self, headers: &HeaderMap<HeaderValue>) -> Result<(), Denial> {
+        limits::check_request_headers(headers, &self.header_limits).map_err(|err| {
+            debug!("denied request: {err}");
+            self.metrics.inc_denied_header_limit();
+            Denial::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, err.to_string())
+        })
+    }
+
+    /// Deny requests that declare a body over the configured limit.
+    fn check_body_limit(&self, headers: &HeaderMap<HeaderValue>) -> Result<(), Denial> {
+        limits::check_request_body(headers, &self.request_limits).map_err(|err| {
+            debug!("denied request: {err}");
+            self.metrics.inc_denied_body_limit();
+            Denial::new(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+        })
+    }
+
+    /// Deny clients excluded by the tunnel's IP allow and deny lists, which
+    /// Envoy attaches as headers.
+    fn check_ip_filter(
+        &self,
+        peer: Option<IpAddr>,
         headers: &HeaderMap<HeaderValue>,
-    ) -> Result<EndpointId, Deny> {
+    ) -> Result<(), Denial> {
+        let filter = IpFilter::from_headers(headers).map_err(|err| {
+            warn_limited!("gateway.ip_filter", "invalid IP filter on request: {err:#}");
+            self.metrics.inc_denied_ip_filter();
+            Denial::new(StatusCode::FORBIDDEN, "invalid IP filter for this tunnel")
+        })?;
+        if filter.is_empty() {
+            return Ok(());
+        }
+        let client = ip_filter::client_ip(peer, headers, self.client_ip.trusted_hops);
+        if client.is_some_and(|ip| filter.permits(ip)) {
+            return Ok(());
+        }
+        self.metrics.inc_denied_ip_filter();
+        let reason = match client {
+            Some(ip) => format!("client address {ip} is not allowed for this tunnel"),
+            None => "client address is unknown".to_string(),
+        };
+        Err(Denial::new(StatusCode::FORBIDDEN, reason))
+    }
+
+    /// Notice when a hostname starts pointing at a different endpoint, so the
+    /// new endpoint isn't judged by a stale liveness result.
+    fn observe_endpoint(&self, headers: &HeaderMap<HeaderValue>, endpoint_id: EndpointId) {
+        let Some(host) = headers
+            .get(http::header::HOST)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return;
+        };
+        let Some(previous) = self.switches.observe(host, endpoint_id) else {
+            return;
+        };
+        info!(
+            %host,
+            from = %previous.fmt_short(),
+            to = %endpoint_id.fmt_short(),
+            "hostname switched endpoints"
+        );
+        self.metrics.inc_endpoint_switches();
+        if let Some(liveness) = &self.liveness {
+            liveness.invalidate(&endpoint_id);
+        }
+    }
+
+    /// Pick the endpoint to forward to from `backends`, in the balancer's
+    /// order. With liveness checks on, endpoints that did not answer a recent
+    /// dial are skipped, and the request is denied if none did.
+    async fn select_endpoint(&self, backends: &[Backend]) -> Result<EndpointId, Denial> {
+        let ordered = self.balancer.order(backends);
+        let Some(liveness) = &self.liveness else {
+            return Ok(ordered[0]);
+        };
+        for (i, &endpoint_id) in ordered.iter().enumerate() {
+            if liveness.is_live(endpoint_id).await {
+                if i > 0 {
+                    debug!(endpoint_id = %endpoint_id.fmt_short(), "failing over");
+                    self.metrics.inc_failovers();
+                }
+                return Ok(endpoint_id);
+            }
+            self.metrics.observe_unreachable(endpoint_id);
+        }
+        self.metrics.inc_denied_endpoint_unreachable();
+        Err(Denial::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "endpoint is not reachable",
+        ))
+    }
+
+    /// Deny requests for custom hostnames the endpoint has not proven to own.
+    async fn verify_hostname(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        endpoint_id: EndpointId,
+    ) -> Result<(), Denial> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(());
+        };
+        let host = self.header_value(headers, http::header::HOST.as_str())?;
+        if verifier.verify(host, endpoint_id).await {
+            Ok(())
+        } else {
+            self.metrics.inc_denied_unverified_hostname();
+            Err(Denial::new(
+                StatusCode::FORBIDDEN,
+                format!("hostname {host} is not verified for this endpoint"),
+            ))
+        }
+    }
+
+    /// The endpoints serving the tunnel; several when replicas share it.
+    fn backends_from_headers(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Vec<Backend>, Denial> {
         let s = self.header_value(headers, HEADER_NODE_ID)?;
-        EndpointId::from_str(s).map_err(|_| {
-            self.metrics.inc_denied_invalid_endpoint();
-            Deny::bad_request("invalid x-iroh-endpoint-id value")
-        })
+        let backends = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(Backend::parse)
+            .collect::<Option<Vec<_>>>();
+        match backends {
+            Some(backends) if !backends.is_empty() => Ok(backends),
+            _ => {
+                self.metrics.inc_denied_invalid_endpoint();
+                Err(Denial::bad_request("invalid x-iroh-endpoint-id value"))
+            }
+        }
     }
 
     fn header_value<'a>(
         &self,
         headers: &'a HeaderMap<HeaderValue>,
         name: &str,
-    ) -> Result<&'a str, Deny> {
+    ) -> Result<&'a str, Denial> {
         headers
             .get(name)
             .and_then(|value| value.to_str().ok())
             .ok_or_else(|| {
                 self.metrics.inc_denied_missing_header_name(name);
-                Deny::bad_request(format!("Missing header {name}"))
+                Denial::bad_request(format!("Missing header {name}"))
             })
     }
 }
 
+/// A refused request.
+///
+/// [`Deny`] doesn't expose its status, so the gateway keeps its own denials
+/// in this form until they are handed back, for the access log.
+enum Denial {
+    Gateway {
+        status: StatusCode,
+        reason: String,
+    },
+    /// Refused by iroh-proxy-utils while parsing or rewriting the request.
+    Proxy(Deny),
+}
+
+impl Denial {
+    fn new(status: StatusCode, reason: impl Into<String>) -> Self {
+        Self::Gateway {
+            status,
+            reason: reason.into(),
+        }
+    }
+
+    fn bad_request(reason: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, reason)
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Gateway { status, .. } => Some(*status),
+            Self::Proxy(_) => None,
+        }
+    }
+}
+
+impl From<Deny> for Denial {
+    fn from(deny: Deny) -> Self {
+        Self::Proxy(deny)
+    }
+}
+
+impl From<Denial> for Deny {
+    fn from(denial: Denial) -> Self {
+        match denial {
+            Denial::Gateway { status, reason } => Deny::new(status, reason),
+            Denial::Proxy(deny) => deny,
+        }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "gateway_error.html")]
 struct GatewayErrorTemplate<'a> {
@@ -247,11 +1029,16 @@ impl ErrorResponder for ErrorResponseWriter {
             }
             StatusCode::FORBIDDEN => "Access to this resource is not allowed through the gateway.",
             StatusCode::NOT_FOUND => "The requested page could not be found through the gateway.",
+            StatusCode::PAYLOAD_TOO_LARGE => "The request body is larger than the gateway accepts.",
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE => {
+                "The request carried more or larger headers than the gateway accepts."
+            }
             StatusCode::INTERNAL_SERVER_ERROR => {
                 "The gateway encountered an internal error. Please try again later."
             }
             StatusCode::BAD_GATEWAY => {
-                "The gateway could not get a valid response from the upstream service."
+                "The gateway could not get a valid response from the upstream service. \
+                 This includes responses with more or larger headers than it can parse."
             }
             StatusCode::SERVICE_UNAVAILABLE => {
                 "The service is temporarily unavailable. Please try again shortly."
@@ -297,3 +1084,119 @@ fn has_existing_peer_conn(endpoint: &Endpoint) -> bool {
         .saturating_sub(endpoint_metrics.magicsock.num_relay_conns_removed.get());
     direct_current + relay_current > 0
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+
+    async fn read(raw: &'static [u8]) -> (http::Response<()>, Bytes, Option<HeaderMap>) {
+        let res = http1::read_http1_response_from_stream(raw, &Method::GET, Default::default())
+            .await
+            .unwrap();
+        let (head, body) = res.into_parts();
+        let body = body.collect().await.unwrap();
+        let trailers = body.trailers().cloned();
+        (
+            http::Response::from_parts(head, ()),
+            body.to_bytes(),
+            trailers,
+        )
+    }
+
+    #[tokio::test]
+    async fn chunked_responses_keep_their_trailers() {
+        let (res, body, trailers) = read(
+            b"HTTP/1.1 200 OK\r\n\
+              Transfer-Encoding: chunked\r\n\
+              Trailer: grpc-status, grpc-message\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\
+              grpc-status: 0\r\n\
+              grpc-message: done\r\n\r\n",
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(http::header::TRANSFER_ENCODING));
+        assert_eq!(body, "hello world");
+        let trailers = trailers.expect("trailers");
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["grpc-message"], "done");
+
+        let (_, body, trailers) =
+            read(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n")
+                .await;
+        assert_eq!(body, "ok");
+        assert_eq!(trailers, None);
+    }
+
+    #[tokio::test]
+    async fn early_hints_are_folded_into_the_final_response() {
+        let (res, body, _) = read(
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 103 Early Hints\r\n\
+              Link: </style.css>; rel=preload; as=style\r\n\
+              Link: </app.js>; rel=preload; as=script\r\n\r\n\
+              HTTP/1.1 200 OK\r\n\
+              Content-Length: 2\r\n\
+              Link: </app.js>; rel=preload; as=script\r\n\r\n\
+              ok",
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body, "ok");
+        let links = res
+            .headers()
+            .get_all(http::header::LINK)
+            .iter()
+            .map(|link| link.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                "</app.js>; rel=preload; as=script",
+                "</style.css>; rel=preload; as=style",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn switching_protocols_is_the_final_response() {
+        let (res, body, _) = read(
+            b"HTTP/1.1 101 Switching Protocols\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\r\n",
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn response_heads_are_held_to_the_configured_limits() {
+        let raw: &'static [u8] = b"HTTP/1.1 200 OK\r\n\
+              Content-Length: 2\r\n\
+              X-One: 1\r\n\
+              X-Two: 2\r\n\r\n\
+              ok";
+        let limits = |max_headers, max_head_bytes| ResponseLimits {
+            max_headers,
+            max_head_bytes,
+        };
+        assert!(
+            http1::read_http1_response_from_stream(raw, &Method::GET, limits(3, 1024))
+                .await
+                .is_ok()
+        );
+        assert!(
+            http1::read_http1_response_from_stream(raw, &Method::GET, limits(2, 1024))
+                .await
+                .is_err()
+        );
+        assert!(
+            http1::read_http1_response_from_stream(raw, &Method::GET, limits(3, 32))
+                .await
+                .is_err()
+        );
+    }
+}
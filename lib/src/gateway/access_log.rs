@@ -0,0 +1,285 @@
+//! Structured access logs.
+//!
+//! The gateway writes one record per request once it has decided what to do
+//! with it: forward it to an endpoint or refuse it. Forwarded responses are
+//! streamed back by iroh-proxy-utils without passing through the gateway, so
+//! their records carry no status and their duration covers only the gateway's
+//! own work (limits, verification, liveness).
+//!
+//! Records are handed to a writer thread over a bounded channel; when the
+//! writer can't keep up, records are dropped rather than slowing requests.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, mpsc},
+    time::Duration,
+};
+
+use crate::{
+    config::{AccessLogConfig, AccessLogFormat},
+    log_limit::warn_limited,
+};
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use serde::Serialize;
+
+const QUEUE_LEN: usize = 4096;
+
+static SHARED_LOGS: OnceLock<Mutex<HashMap<Option<PathBuf>, Arc<AccessLog>>>> = OnceLock::new();
+
+/// The access log for `config`, or `None` if it is disabled.
+///
+/// Listeners writing to the same file (or stdout) share one writer, so the
+/// TCP and UDS listeners of a process don't rotate the file under each other.
+pub(super) fn shared_access_log(config: &AccessLogConfig) -> io::Result<Option<Arc<AccessLog>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let mut logs = SHARED_LOGS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("poisoned");
+    if let Some(log) = logs.get(&config.path) {
+        return Ok(Some(log.clone()));
+    }
+    let log = Arc::new(AccessLog::spawn(config)?);
+    logs.insert(config.path.clone(), log.clone());
+    Ok(Some(log))
+}
+
+/// What the gateway did with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Outcome {
+    Forwarded,
+    Denied,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(super) struct AccessRecord {
+    pub(super) timestamp: DateTime<Utc>,
+    pub(super) client: Option<IpAddr>,
+    pub(super) host: Option<String>,
+    pub(super) method: String,
+    pub(super) path: String,
+    pub(super) version: String,
+    pub(super) endpoint_id: Option<EndpointId>,
+    pub(super) outcome: Outcome,
+    /// Set for refused requests; forwarded responses come from the service.
+    pub(super) status: Option<u16>,
+    /// The request body size from `Content-Length`, if declared.
+    pub(super) request_bytes: Option<u64>,
+    pub(super) duration_ms: f64,
+    /// `direct`, `relay`, `mixed` or `none`, for forwarded requests.
+    pub(super) path_type: Option<&'static str>,
+}
+
+impl AccessRecord {
+    pub(super) fn set_duration(&mut self, duration: Duration) {
+        self.duration_ms = duration.as_secs_f64() * 1000.0;
+    }
+
+    pub(super) fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).expect("serializable"),
+            AccessLogFormat::Clf => self.format_clf(),
+        }
+    }
+
+    /// `client - - [time] "request" status bytes "host" endpoint duration path`,
+    /// with `-` for anything unknown.
+    fn format_clf(&self) -> String {
+        fn or_dash(value: Option<impl ToString>) -> String {
+            value.map_or_else(|| "-".to_string(), |value| value.to_string())
+        }
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {} \"{}\" {} {:.3} {}",
+            or_dash(self.client),
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.version,
+            or_dash(self.status),
+            or_dash(self.request_bytes),
+            self.host.as_deref().unwrap_or("-"),
+            or_dash(self.endpoint_id),
+            self.duration_ms,
+            self.path_type.unwrap_or("-"),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct AccessLog {
+    format: AccessLogFormat,
+    tx: mpsc::SyncSender<String>,
+}
+
+impl AccessLog {
+    fn spawn(config: &AccessLogConfig) -> io::Result<Self> {
+        let mut sink = match &config.path {
+            Some(path) => Sink::File(RotatingFile::open(
+                path.clone(),
+                config.max_file_bytes,
+                config.max_files,
+            )?),
+            None => Sink::Stdout(io::stdout()),
+        };
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("gateway-access-log".into())
+            .spawn(move || {
+                while let Ok(line) = rx.recv() {
+                    if let Err(err) = sink.write_line(&line) {
+                        warn_limited!("gateway.access_log", "Failed to write access log: {err:#}");
+                    }
+                }
+            })?;
+        Ok(Self {
+            format: config.format,
+            tx,
+        })
+    }
+
+    pub(super) fn record(&self, record: &AccessRecord) {
+        // A full queue means the writer is behind; dropping keeps requests
+        // from waiting on disk.
+        self.tx.try_send(record.format(self.format)).ok();
+    }
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    File(RotatingFile),
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Stdout(out) => writeln!(out.lock(), "{line}"),
+            Sink::File(file) => file.write_line(line),
+        }
+    }
+}
+
+/// A file that is moved to `<path>.1` (and older ones up to `<path>.<n>`)
+/// once it reaches `max_bytes`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, i + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            client: Some("192.0.2.1".parse().unwrap()),
+            host: Some("abc.example.com".into()),
+            method: "GET".into(),
+            path: "/hello?x=1".into(),
+            version: "HTTP/1.1".into(),
+            endpoint_id: None,
+            outcome: Outcome::Denied,
+            status: Some(403),
+            request_bytes: None,
+            duration_ms: 1.5,
+            path_type: None,
+        }
+    }
+
+    #[test]
+    fn formats_common_log_lines() {
+        assert_eq!(
+            record().format(AccessLogFormat::Clf),
+            "192.0.2.1 - - [14/Nov/2023:22:13:20 +0000] \"GET /hello?x=1 HTTP/1.1\" 403 - \
+             \"abc.example.com\" - 1.500 -"
+        );
+    }
+
+    #[test]
+    fn formats_json_lines() {
+        let line = record().format(AccessLogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["status"], 403);
+        assert_eq!(value["outcome"], "denied");
+        assert_eq!(value["client"], "192.0.2.1");
+        assert_eq!(value["timestamp"], "2023-11-14T22:13:20Z");
+        assert!(value["endpoint_id"].is_null());
+    }
+
+    #[test]
+    fn rotates_files_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut file = RotatingFile::open(path.clone(), 8, 2).unwrap();
+        for line in ["aaaa", "bbbb", "cccc", "dddd"] {
+            file.write_line(line).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "dddd\n");
+        assert_eq!(read(rotated_path(&path, 1)), "cccc\n");
+        assert_eq!(read(rotated_path(&path, 2)), "bbbb\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! Choosing between several endpoints serving the same tunnel.
+//!
+//! A tunnel served by more than one agent (replicas of a service) lists all
+//! of their endpoint ids in `x-iroh-endpoint-id`, comma-separated and
+//! optionally weighted as `<id>;weight=<n>`. The balancer orders them for a
+//! request; the resolver then takes the first one the liveness checker
+//! considers reachable.
+//!
+//! There is no least-connections policy: the gateway hands each request to
+//! iroh-proxy-utils and isn't told when its stream ends, so it can't count
+//! requests in flight per endpoint.
+
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use iroh::EndpointId;
+
+use crate::config::BalancePolicy;
+
+/// Round-robin positions are kept per set of endpoints, hashed into this many
+/// slots. Tunnels sharing a slot share a position, which only makes their
+/// rotation less regular.
+const ROTATION_SLOTS: usize = 64;
+
+/// One endpoint listed for a tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Backend {
+    pub(super) endpoint_id: EndpointId,
+    /// Relative share of requests under round robin; at least 1.
+    pub(super) weight: u32,
+}
+
+impl Backend {
+    /// Parse one `x-iroh-endpoint-id` entry, `<id>` or `<id>;weight=<n>`.
+    pub(super) fn parse(entry: &str) -> Option<Self> {
+        let (id, params) = entry.split_once(';').unwrap_or((entry, ""));
+        let endpoint_id = EndpointId::from_str(id.trim()).ok()?;
+        let mut weight = 1;
+        for param in params.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, value) = param.split_once('=')?;
+            if !name.trim().eq_ignore_ascii_case("weight") {
+                return None;
+            }
+            weight = value.trim().parse().ok().filter(|w| *w > 0)?;
+        }
+        Some(Self {
+            endpoint_id,
+            weight,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Balancer {
+    policy: BalancePolicy,
+    rotations: [AtomicUsize; ROTATION_SLOTS],
+}
+
+impl Balancer {
+    pub(super) fn new(policy: BalancePolicy) -> Self {
+        Self {
+            policy,
+            rotations: std::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+
+    /// The order in which to try `backends` for the next request.
+    ///
+    /// Under round robin each backend comes first for a share of requests
+    /// proportional to its weight; the others follow in listed order.
+    pub(super) fn order(&self, backends: &[Backend]) -> Vec<EndpointId> {
+        let mut ordered = backends.iter().map(|b| b.endpoint_id).collect::<Vec<_>>();
+        if backends.len() > 1 && self.policy == BalancePolicy::RoundRobin {
+            let total = backends.iter().map(|b| u64::from(b.weight)).sum::<u64>();
+            let turn = self.rotation(backends).fetch_add(1, Ordering::Relaxed) as u64 % total;
+            let start = backends
+                .iter()
+                .scan(0, |end, b| {
+                    *end += u64::from(b.weight);
+                    Some(*end)
+                })
+                .position(|end| turn < end)
+                .unwrap_or_default();
+            ordered.rotate_left(start);
+        }
+        ordered
+    }
+
+    fn rotation(&self, backends: &[Backend]) -> &AtomicUsize {
+        let mut hasher = DefaultHasher::new();
+        for backend in backends {
+            backend.endpoint_id.hash(&mut hasher);
+        }
+        &self.rotations[hasher.finish() as usize % ROTATION_SLOTS]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    fn backends(weights: &[u32]) -> Vec<Backend> {
+        weights
+            .iter()
+            .map(|&weight| Backend {
+                endpoint_id: SecretKey::generate(&mut rand::rng()).public(),
+                weight,
+            })
+            .collect()
+    }
+
+    fn ids(backends: &[Backend]) -> Vec<EndpointId> {
+        backends.iter().map(|b| b.endpoint_id).collect()
+    }
+
+    #[test]
+    fn orders_candidates_by_policy() {
+        let backends = backends(&[1, 1, 1]);
+        let ids = ids(&backends);
+        let failover = Balancer::new(BalancePolicy::Failover);
+        assert_eq!(failover.order(&backends), ids);
+        assert_eq!(failover.order(&backends), ids);
+
+        let round_robin = Balancer::new(BalancePolicy::RoundRobin);
+        let firsts = (0..4)
+            .map(|_| round_robin.order(&backends)[0])
+            .collect::<Vec<_>>();
+        assert_eq!(firsts, vec![ids[0], ids[1], ids[2], ids[0]]);
+        assert_eq!(round_robin.order(&backends), vec![ids[1], ids[2], ids[0]]);
+    }
+
+    #[test]
+    fn round_robin_follows_weights() {
+        let backends = backends(&[3, 1]);
+        let ids = ids(&backends);
+        let round_robin = Balancer::new(BalancePolicy::RoundRobin);
+        let firsts = (0..8)
+            .map(|_| round_robin.order(&backends)[0])
+            .collect::<Vec<_>>();
+        assert_eq!(firsts.iter().filter(|id| **id == ids[0]).count(), 6);
+        assert_eq!(firsts.iter().filter(|id| **id == ids[1]).count(), 2);
+
+        // Failover ignores weights.
+        let failover = Balancer::new(BalancePolicy::Failover);
+        assert_eq!(failover.order(&backends), ids);
+    }
+
+    #[test]
+    fn parses_weighted_entries() {
+        let id = SecretKey::generate(&mut rand::rng()).public();
+        assert_eq!(Backend::parse(&id.to_string()).unwrap().weight, 1);
+        let backend = Backend::parse(&format!("{id}; weight=5")).unwrap();
+        assert_eq!(backend.endpoint_id, id);
+        assert_eq!(backend.weight, 5);
+        assert!(Backend::parse(&format!("{id};weight=0")).is_none());
+        assert!(Backend::parse(&format!("{id};zone=a")).is_none());
+        assert!(Backend::parse("not-an-id;weight=2").is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Requests, errors and bytes per destination endpoint.
+//!
+//! A series per endpoint lets operators see which tunnels drive traffic and
+//! errors, but a gateway serving thousands of tunnels can't export one for
+//! each. With `metrics.endpoint_labels` on, allow-listed endpoints and the
+//! `top_k` endpoints with the most requests get series of their own and the
+//! rest are summed up as `endpoint="other"`. An endpoint's series comes and
+//! goes as it enters and leaves the top, so its counter can restart.
+//!
+//! Responses stream back through iroh-proxy-utils without passing the
+//! gateway, so the bytes counted are request bodies, as announced by
+//! `Content-Length`.
+
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use iroh::EndpointId;
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder};
+
+use super::metrics::encode_counters;
+use crate::config::EndpointLabelsConfig;
+
+/// Endpoints counted one by one. Beyond this, the endpoint with the fewest
+/// requests is folded into `other` to make room.
+const TRACKED_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counters {
+    requests: u64,
+    request_bytes: u64,
+    /// Requests for the endpoint the gateway refused.
+    denied: u64,
+    /// Requests that skipped the endpoint because it was unreachable.
+    unreachable: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.requests += other.requests;
+        self.request_bytes += other.request_bytes;
+        self.denied += other.denied;
+        self.unreachable += other.unreachable;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    endpoints: HashMap<EndpointId, Counters>,
+    /// Counts of endpoints dropped to make room.
+    evicted: Counters,
+}
+
+#[derive(Debug)]
+pub(super) struct Destinations {
+    config: EndpointLabelsConfig,
+    capacity: usize,
+    tracked: Mutex<Tracked>,
+}
+
+impl Default for Destinations {
+    fn default() -> Self {
+        Self::new(&EndpointLabelsConfig::default())
+    }
+}
+
+impl Destinations {
+    pub(super) fn new(config: &EndpointLabelsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            capacity: TRACKED_CAPACITY,
+            tracked: Default::default(),
+        }
+    }
+
+    /// Record a request forwarded to `endpoint_id`.
+    pub(super) fn observe_request(&self, endpoint_id: EndpointId, request_bytes: Option<u64>) {
+        self.update(endpoint_id, |counters| {
+            counters.requests += 1;
+            counters.request_bytes += request_bytes.unwrap_or_default();
+        });
+    }
+
+    /// Record a refused request for `endpoint_id`.
+    pub(super) fn observe_denied(&self, endpoint_id: EndpointId) {
+        self.update(endpoint_id, |counters| counters.denied += 1);
+    }
+
+    /// Record that `endpoint_id` was skipped because it is unreachable.
+    pub(super) fn observe_unreachable(&self, endpoint_id: EndpointId) {
+        self.update(endpoint_id, |counters| counters.unreachable += 1);
+    }
+
+    fn update(&self, endpoint_id: EndpointId, f: impl FnOnce(&mut Counters)) {
+        if !self.config.enabled {
+            return;
+        }
+        let mut tracked = self.tracked.lock().expect("poisoned");
+        let full = tracked.endpoints.len() >= self.capacity;
+        if full && !tracked.endpoints.contains_key(&endpoint_id) {
+            let least = tracked
+                .endpoints
+                .iter()
+                .filter(|(id, _)| !self.config.allow.contains(id))
+                .min_by_key(|(_, counters)| counters.requests)
+                .map(|(id, _)| *id);
+            if let Some(counters) = least.and_then(|id| tracked.endpoints.remove(&id)) {
+                tracked.evicted.add(&counters);
+            }
+        }
+        f(tracked.endpoints.entry(endpoint_id).or_default());
+    }
+
+    /// The endpoints with series of their own, by endpoint id, and the sum
+    /// of the others.
+    fn rows(&self) -> (Vec<(EndpointId, Counters)>, Counters) {
+        let tracked = self.tracked.lock().expect("poisoned");
+        let mut ranked = tracked
+            .endpoints
+            .iter()
+            .map(|(id, counters)| (*id, *counters))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.requests.cmp(&a.1.requests).then(a.0.cmp(&b.0)));
+        let mut other = tracked.evicted;
+        let mut rows = Vec::new();
+        let mut top = 0;
+        for (id, counters) in ranked {
+            if self.config.allow.contains(&id) {
+                rows.push((id, counters));
+            } else if top < self.config.top_k {
+                top += 1;
+                rows.push((id, counters));
+            } else {
+                other.add(&counters);
+            }
+        }
+        for id in &self.config.allow {
+            if !tracked.endpoints.contains_key(id) {
+                rows.push((*id, Counters::default()));
+            }
+        }
+        rows.sort_by_key(|(id, _)| *id);
+        (rows, other)
+    }
+
+    /// The counters as of now, or nothing if disabled.
+    pub(super) fn collector(&self) -> Option<DestinationsCollector> {
+        if !self.config.enabled {
+            return None;
+        }
+        let (rows, other) = self.rows();
+        let rows = rows
+            .into_iter()
+            .map(|(id, counters)| (id.to_string(), counters))
+            .chain([("other".to_string(), other)])
+            .collect();
+        Some(DestinationsCollector(rows))
+    }
+}
+
+/// The counters of each destination endpoint, from a snapshot taken for a
+/// scrape.
+#[derive(Debug)]
+pub(super) struct DestinationsCollector(Vec<(String, Counters)>);
+
+impl Collector for DestinationsCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> fmt::Result {
+        let rows = &self.0;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_destination_requests",
+            "Requests forwarded to each destination endpoint.",
+            rows.iter()
+                .map(|(endpoint, counters)| ([("endpoint", endpoint.clone())], counters.requests)),
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_destination_errors",
+            "Requests for each destination endpoint that failed, by reason.",
+            rows.iter().flat_map(|(endpoint, counters)| {
+                [
+                    ("denied", counters.denied),
+                    ("unreachable", counters.unreachable),
+                ]
+                .map(|(reason, value)| {
+                    (
+                        [
+                            ("endpoint", endpoint.clone()),
+                            ("reason", reason.to_string()),
+                        ],
+                        value,
+                    )
+                })
+            }),
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_destination_request_bytes",
+            "Request body bytes forwarded to each destination endpoint.",
+            rows.iter().map(|(endpoint, counters)| {
+                ([("endpoint", endpoint.clone())], counters.request_bytes)
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+    use prometheus_client::{encoding::text::encode_registry, registry::Registry};
+
+    use super::*;
+
+    fn render(stats: &Destinations) -> String {
+        let mut registry = Registry::default();
+        if let Some(collector) = stats.collector() {
+            registry.register_collector(Box::new(collector));
+        }
+        let mut text = String::new();
+        encode_registry(&mut text, &registry).unwrap();
+        text
+    }
+
+    fn endpoint_id() -> EndpointId {
+        SecretKey::generate(&mut rand::rng()).public()
+    }
+
+    fn config(top_k: usize, allow: Vec<EndpointId>) -> EndpointLabelsConfig {
+        EndpointLabelsConfig {
+            enabled: true,
+            top_k,
+            allow,
+        }
+    }
+
+    #[test]
+    fn labels_top_k_and_allowed_endpoints() {
+        let (busy, quiet, allowed) = (endpoint_id(), endpoint_id(), endpoint_id());
+        let stats = Destinations::new(&config(1, vec![allowed]));
+        stats.observe_request(busy, Some(100));
+        stats.observe_request(busy, None);
+        stats.observe_request(quiet, Some(7));
+        stats.observe_denied(quiet);
+
+        let text = render(&stats);
+        assert!(text.contains(&format!(
+            "iroh_gateway_destination_requests_total{{endpoint=\"{busy}\"}} 2\n"
+        )));
+        assert!(text.contains(&format!(
+            "iroh_gateway_destination_requests_total{{endpoint=\"{allowed}\"}} 0\n"
+        )));
+        assert!(!text.contains(&quiet.to_string()));
+        assert!(text.contains("iroh_gateway_destination_requests_total{endpoint=\"other\"} 1\n"));
+        assert!(text.contains(
+            "iroh_gateway_destination_errors_total{endpoint=\"other\",reason=\"denied\"} 1\n"
+        ));
+        assert!(text.contains(&format!(
+            "iroh_gateway_destination_request_bytes_total{{endpoint=\"{busy}\"}} 100\n"
+        )));
+    }
+
+    #[test]
+    fn folds_evicted_endpoints_into_other() {
+        let (first, second, third) = (endpoint_id(), endpoint_id(), endpoint_id());
+        let mut stats = Destinations::new(&config(5, Vec::new()));
+        stats.capacity = 2;
+        stats.observe_request(first, None);
+        stats.observe_request(first, None);
+        stats.observe_unreachable(second);
+        stats.observe_request(third, None);
+
+        let (rows, other) = stats.rows();
+        let ids = rows.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert!(ids.contains(&first) && ids.contains(&third) && !ids.contains(&second));
+        assert_eq!(other.unreachable, 1);
+    }
+
+    #[test]
+    fn records_nothing_when_disabled() {
+        let stats = Destinations::default();
+        stats.observe_request(endpoint_id(), Some(1));
+        assert!(stats.tracked.lock().unwrap().endpoints.is_empty());
+        assert_eq!(render(&stats), "");
+    }
+}
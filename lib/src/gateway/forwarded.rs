@@ -0,0 +1,141 @@
+//! `X-Forwarded-*` and `Forwarded` headers for tunneled services.
+//!
+//! Whether the values a request arrives with can be kept depends on who set
+//! them. With `trusted_hops` of 0 nothing in front of the gateway is
+//! trusted, so client-sent values are replaced; otherwise the gateway
+//! appends its own hop to what the trusted proxies sent.
+
+use std::net::IpAddr;
+
+use hyper::http::{HeaderMap, HeaderName, HeaderValue, header};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Add the gateway's hop to the forwarding headers of a request for `host`
+/// from `peer`.
+pub(super) fn set_headers(
+    headers: &mut HeaderMap<HeaderValue>,
+    host: Option<&str>,
+    peer: Option<IpAddr>,
+    trusted_hops: usize,
+) {
+    if trusted_hops == 0 {
+        for name in [
+            X_FORWARDED_FOR,
+            X_FORWARDED_PROTO,
+            X_FORWARDED_HOST,
+            header::FORWARDED,
+        ] {
+            headers.remove(name);
+        }
+    }
+    if let Some(peer) = peer {
+        append(headers, X_FORWARDED_FOR, &peer.to_string());
+    }
+    // The gateway itself only speaks plain HTTP; TLS ends in front of it, at
+    // a proxy that sets these when it is trusted.
+    if !headers.contains_key(X_FORWARDED_PROTO) {
+        insert(headers, X_FORWARDED_PROTO, "http");
+    }
+    if let Some(host) = host
+        && !headers.contains_key(X_FORWARDED_HOST)
+    {
+        insert(headers, X_FORWARDED_HOST, host);
+    }
+
+    let mut element = format!("for={}", forwarded_node(peer));
+    if let Some(host) = host {
+        element.push_str(&format!(";host=\"{}\"", host.replace(['"', '\\'], "")));
+    }
+    element.push_str(";proto=http");
+    append(headers, header::FORWARDED, &element);
+}
+
+/// A node for the `for` parameter of `Forwarded` (RFC 7239).
+fn forwarded_node(peer: Option<IpAddr>) -> String {
+    match peer {
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        Some(IpAddr::V6(ip)) => format!("\"[{ip}]\""),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Append `value` to the list in `name`, joining repeated headers into one.
+fn append(headers: &mut HeaderMap<HeaderValue>, name: HeaderName, value: &str) {
+    let existing = headers
+        .get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if existing.is_empty() {
+        insert(headers, name, value);
+    } else {
+        insert(headers, name, &format!("{existing}, {value}"));
+    }
+}
+
+fn insert(headers: &mut HeaderMap<HeaderValue>, name: HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap<HeaderValue> {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn get<'a>(headers: &'a HeaderMap<HeaderValue>, name: &str) -> &'a str {
+        headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn replaces_untrusted_values() {
+        let mut map = headers(&[
+            ("x-forwarded-for", "10.0.0.1"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=10.0.0.1"),
+        ]);
+        let peer = "192.0.2.1".parse().unwrap();
+        set_headers(&mut map, Some("app.example.com"), Some(peer), 0);
+        assert_eq!(get(&map, "x-forwarded-for"), "192.0.2.1");
+        assert_eq!(get(&map, "x-forwarded-proto"), "http");
+        assert_eq!(get(&map, "x-forwarded-host"), "app.example.com");
+        assert_eq!(
+            get(&map, "forwarded"),
+            "for=192.0.2.1;host=\"app.example.com\";proto=http"
+        );
+    }
+
+    #[test]
+    fn appends_to_trusted_values() {
+        let mut map = headers(&[
+            ("x-forwarded-for", "203.0.113.5"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=203.0.113.5;proto=https"),
+        ]);
+        let peer = "2001:db8::1".parse().unwrap();
+        set_headers(&mut map, Some("app.example.com"), Some(peer), 1);
+        assert_eq!(get(&map, "x-forwarded-for"), "203.0.113.5, 2001:db8::1");
+        assert_eq!(get(&map, "x-forwarded-proto"), "https");
+        assert_eq!(
+            get(&map, "forwarded"),
+            "for=203.0.113.5;proto=https, \
+             for=\"[2001:db8::1]\";host=\"app.example.com\";proto=http"
+        );
+    }
+}
@@ -0,0 +1,577 @@
+//! The gateway's own HTTP server for the TCP listener, with
+//! `upstream.http2`.
+//!
+//! Requests are resolved by the same [`HeaderResolver`] as with
+//! iroh-proxy-utils, then sent to the agent over HTTP/2 on
+//! [`HTTP2_UPSTREAM_ALPN`] where it serves it. Agents that refuse the ALPN
+//! predate it and get HTTP/1.1 the way iroh-proxy-utils sends it, as do
+//! CONNECT requests, upgrades and requests that ask for it with
+//! `x-datum-upstream-protocol: http1`. A refusal is remembered for
+//! [`NEGOTIATION_TTL`], so old agents aren't dialed twice for every request.
+//!
+//! HTTP/1.1 requests to agents need the length of their body up front. A
+//! body sent without one is spooled first, up to `request_spool.max_bytes`
+//! or the request body limit, whichever is lower.
+//!
+//! With `upstream.compression`, HTTP/2 requests offer the agent to gzip the
+//! response body, see [`HEADER_ACCEPT_ENCODING`]. Bodies that come back
+//! compressed are decompressed here, so clients get them as the service
+//! sent them.
+//!
+//! With `response_compression`, text-like responses are compressed for
+//! clients that accept it, with brotli where the client's `Accept-Encoding`
+//! allows and gzip otherwise.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http_body_util::{BodyExt, Empty, combinators::BoxBody};
+use hyper::{
+    Method, Request, Response, StatusCode, Version,
+    body::{Body as _, Bytes, Incoming},
+    client::conn::http2::{self, SendRequest},
+    header::{self, HeaderMap, HeaderValue},
+    http::request::Parts,
+    service::service_fn,
+    upgrade::OnUpgrade,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use iroh::{Endpoint, EndpointId, endpoint::Connection};
+use iroh_proxy_utils::{
+    ALPN as IROH_HTTP_CONNECT_ALPN, HttpRequest,
+    downstream::{ErrorResponder, SrcAddr},
+};
+use n0_error::{Result, StdResultExt};
+use tokio::net::TcpListener;
+use tracing::{Instrument, debug, error_span};
+
+use super::{
+    ErrorResponseWriter, HEADER_UPSTREAM_PROTOCOL, HeaderResolver,
+    http1::{self, CONNECTION_HEADERS, ResponseLimits, Tunnel},
+    metrics::GatewayMetrics,
+};
+use crate::{
+    HTTP2_UPSTREAM_ALPN,
+    compression::{self, BROTLI, GZIP},
+    config::ResponseCompressionConfig,
+    http2_upstream::{HEADER_ACCEPT_ENCODING, HEADER_CLIENT_ADDR, HEADER_CONTENT_ENCODING},
+    log_limit::warn_limited,
+    spool::{Spool, SpoolError},
+};
+
+pub(super) type Body = BoxBody<Bytes, io::Error>;
+
+/// How long an agent that refused [`HTTP2_UPSTREAM_ALPN`] gets HTTP/1.1
+/// before it is asked again.
+const NEGOTIATION_TTL: Duration = Duration::from_secs(5 * 60);
+
+pub(super) fn empty() -> Body {
+    Empty::new().map_err(|never| match never {}).boxed()
+}
+
+/// Serve requests on `listener` until it fails.
+pub(super) async fn serve(listener: TcpListener, front: Arc<Front>) -> Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn_limited!("gateway.accept", "accepting a connection failed: {err:#}");
+                continue;
+            }
+        };
+        let front = front.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let front = front.clone();
+                async move { Ok::<_, Infallible>(front.handle(SrcAddr::Tcp(peer), req).await) }
+            });
+            if let Err(err) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(%peer, "connection failed: {err:#}");
+            }
+        });
+    }
+}
+
+pub(super) struct Front {
+    resolver: HeaderResolver,
+    errors: ErrorResponseWriter,
+    upstreams: Upstreams,
+    spool: Spool,
+    /// Offer agents to compress response bodies.
+    upstream_compression: bool,
+    response_compression: ResponseCompressionConfig,
+    response_limits: ResponseLimits,
+    metrics: Arc<GatewayMetrics>,
+}
+
+impl Front {
+    pub(super) fn new(
+        endpoint: Endpoint,
+        resolver: HeaderResolver,
+        spool: Spool,
+        upstream_compression: bool,
+        response_compression: ResponseCompressionConfig,
+        response_limits: ResponseLimits,
+        metrics: Arc<GatewayMetrics>,
+    ) -> Self {
+        Self {
+            resolver,
+            errors: ErrorResponseWriter::new(endpoint.clone(), metrics.clone()),
+            upstreams: Upstreams::new(endpoint, response_limits),
+            spool,
+            upstream_compression,
+            response_compression,
+            response_limits,
+            metrics,
+        }
+    }
+
+    async fn handle(&self, src_addr: SrcAddr, mut req: Request<Incoming>) -> Response<Body> {
+        let tunnel = req.method() == Method::CONNECT || req.headers().contains_key(header::UPGRADE);
+        let upgrade = tunnel.then(|| hyper::upgrade::on(&mut req));
+        let (parts, body) = req.into_parts();
+        let http1_only = tunnel
+            || parts
+                .headers
+                .get(HEADER_UPSTREAM_PROTOCOL)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.eq_ignore_ascii_case("http1"));
+        let encoding =
+            (self.response_compression.enabled && !tunnel && parts.method != Method::HEAD)
+                .then(|| accepted_encoding(&parts.headers))
+                .flatten();
+        let Some(mut head) = request_head(&parts) else {
+            return self.errors.error_response(StatusCode::BAD_REQUEST).await;
+        };
+        let client = self.resolver.client_addr(&src_addr, &parts.headers);
+        let endpoint_id = match self.resolver.handle(src_addr, &mut head).await {
+            Ok(endpoint_id) => endpoint_id,
+            Err(denial) => {
+                // Denials from iroh-proxy-utils are about malformed requests.
+                let status = denial.status().unwrap_or(StatusCode::BAD_REQUEST);
+                return self.errors.error_response(status).await;
+            }
+        };
+        let req = upstream_request(head, body.map_err(io::Error::other).boxed());
+        let res = match upgrade {
+            Some(upgrade) => self.tunnel(endpoint_id, req, upgrade).await,
+            None => self.forward(endpoint_id, req, client, http1_only).await,
+        };
+        match res {
+            Ok(res) => match encoding {
+                Some(encoding) => self.compress(res, encoding),
+                None => res,
+            },
+            Err(err) => {
+                warn_limited!(
+                    "gateway.upstream",
+                    endpoint_id = %endpoint_id.fmt_short(),
+                    "request to the agent failed: {err:#}"
+                );
+                self.errors.error_response(StatusCode::BAD_GATEWAY).await
+            }
+        }
+    }
+
+    /// Send `req` to the agent over HTTP/2 if it can take it, or HTTP/1.1.
+    /// Over HTTP/2 the agent is told the `client` address too, for tunnels
+    /// that pass it on to their service.
+    async fn forward(
+        &self,
+        endpoint_id: EndpointId,
+        mut req: Request<Body>,
+        client: Option<SocketAddr>,
+        http1_only: bool,
+    ) -> Result<Response<Body>> {
+        if !http1_only && let Some(mut sender) = self.upstreams.http2(endpoint_id).await {
+            let mut h2_req = http2_request(req);
+            // Only the gateway makes the offer, never the client.
+            let headers = h2_req.headers_mut();
+            headers.remove(HEADER_ACCEPT_ENCODING);
+            if self.upstream_compression {
+                headers.insert(HEADER_ACCEPT_ENCODING, HeaderValue::from_static(GZIP));
+            }
+            headers.remove(HEADER_CLIENT_ADDR);
+            if let Some(client) = client {
+                let value = HeaderValue::from_str(&client.to_string()).expect("valid header value");
+                headers.insert(HEADER_CLIENT_ADDR, value);
+            }
+            match sender.try_send_request(h2_req).await {
+                Ok(res) => {
+                    self.metrics.inc_upstream_http2();
+                    return Ok(self.decompress(res));
+                }
+                Err(mut err) => match err.take_message() {
+                    // The connection closed before the request went out.
+                    Some(mut unsent) => {
+                        unsent.headers_mut().remove(HEADER_ACCEPT_ENCODING);
+                        unsent.headers_mut().remove(HEADER_CLIENT_ADDR);
+                        req = unsent;
+                    }
+                    None => return Err(err.into_error()).anyerr(),
+                },
+            }
+        }
+        *req.version_mut() = Version::HTTP_11;
+        if req.body().size_hint().exact().is_none() {
+            let (parts, body) = req.into_parts();
+            let spooled = match self.spool.spool(body).await {
+                Ok(spooled) => spooled,
+                Err(SpoolError::TooLarge { limit }) => {
+                    debug!("denied request: body is larger than {limit} bytes");
+                    self.metrics.inc_denied_body_limit();
+                    return Ok(self
+                        .errors
+                        .error_response(StatusCode::PAYLOAD_TOO_LARGE)
+                        .await);
+                }
+                Err(err) => return Err(err).anyerr(),
+            };
+            self.metrics.inc_spooled_body(spooled.in_file());
+            req = Request::from_parts(parts, spooled.body());
+        }
+        let conn = self.upstreams.http1(endpoint_id).await?;
+        match http1::send_request(&conn, req, self.response_limits).await {
+            Ok(res) => {
+                self.metrics.inc_upstream_http1();
+                Ok(res)
+            }
+            Err(err) => {
+                self.upstreams.forget(endpoint_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// `res` compressed with `encoding` for the client, if it is worth it.
+    fn compress(&self, res: Response<Body>, encoding: &'static str) -> Response<Body> {
+        let status = res.status();
+        let skip = status.is_informational()
+            || matches!(
+                status,
+                StatusCode::NO_CONTENT | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+            )
+            || res.body().is_end_stream()
+            || res.headers().contains_key(header::CONTENT_RANGE)
+            || no_transform(res.headers())
+            || !compression::compressible(res.headers(), self.response_compression.min_bytes);
+        if skip {
+            return res;
+        }
+        let (mut parts, body) = res.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        let varies = parts
+            .headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding"));
+        if !varies {
+            parts
+                .headers
+                .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        // The compressed body is a different representation, so a strong
+        // validator of the original no longer matches it byte for byte.
+        if let Some(etag) = parts.headers.get_mut(header::ETAG)
+            && !etag.as_bytes().starts_with(b"W/")
+            && let Ok(weak) = HeaderValue::from_bytes(&[&b"W/"[..], etag.as_bytes()].concat())
+        {
+            *etag = weak;
+        }
+        self.metrics.inc_compressed_response(encoding);
+        let body = match encoding {
+            BROTLI => compression::brotli(body).boxed(),
+            _ => compression::gzip(body).boxed(),
+        };
+        Response::from_parts(parts, body)
+    }
+
+    /// `res` with its body as the service sent it, if the agent compressed
+    /// it.
+    fn decompress(&self, res: Response<Incoming>) -> Response<Body> {
+        let (mut parts, body) = res.into_parts();
+        match parts.headers.remove(HEADER_CONTENT_ENCODING) {
+            Some(coding) if coding == GZIP => {
+                self.metrics.inc_upstream_compressed();
+                parts.headers.remove(header::CONTENT_LENGTH);
+                Response::from_parts(parts, compression::gunzip(body).boxed())
+            }
+            _ => Response::from_parts(parts, body.map_err(io::Error::other).boxed()),
+        }
+    }
+
+    /// Open a CONNECT tunnel or upgrade on the agent over HTTP/1.1, and carry
+    /// the client's connection over it once both sides switched.
+    async fn tunnel(
+        &self,
+        endpoint_id: EndpointId,
+        req: Request<Body>,
+        upgrade: OnUpgrade,
+    ) -> Result<Response<Body>> {
+        let conn = self.upstreams.http1(endpoint_id).await?;
+        let (res, tunnel) = match http1::open_tunnel(&conn, req, self.response_limits).await {
+            Ok(opened) => opened,
+            Err(err) => {
+                self.upstreams.forget(endpoint_id);
+                return Err(err);
+            }
+        };
+        self.metrics.inc_upstream_http1();
+        if let Some(tunnel) = tunnel {
+            tokio::spawn(
+                splice(upgrade, tunnel)
+                    .instrument(error_span!("tunnel", endpoint_id = %endpoint_id.fmt_short())),
+            );
+        }
+        Ok(res)
+    }
+}
+
+/// The head of a request as [`HeaderResolver`] takes it, as if it had come
+/// in over HTTP/1.1.
+fn request_head(parts: &Parts) -> Option<HttpRequest> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", parts.method, parts.uri).into_bytes();
+    if !parts.headers.contains_key(header::HOST)
+        && let Some(authority) = parts.uri.authority()
+    {
+        let host = HeaderValue::from_str(authority.as_str()).ok()?;
+        http1::append_header(&mut head, header::HOST.as_str(), &host);
+    }
+    for (name, value) in &parts.headers {
+        http1::append_header(&mut head, name.as_str(), value);
+    }
+    head.extend_from_slice(b"\r\n");
+    let mut req = HttpRequest::parse(&head).ok().flatten()?;
+    req.version = parts.version;
+    Some(req)
+}
+
+/// The request to send to the agent, from the head as resolved.
+fn upstream_request(head: HttpRequest, body: Body) -> Request<Body> {
+    let mut req = Request::new(body);
+    *req.method_mut() = head.method;
+    *req.uri_mut() = head.uri;
+    *req.headers_mut() = head.headers;
+    req
+}
+
+/// The encoding to compress a response in for a client that sent `headers`:
+/// brotli over gzip, unless the client refuses it with `q=0` or doesn't
+/// name it.
+fn accepted_encoding(headers: &HeaderMap) -> Option<&'static str> {
+    let accepted = headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|item| {
+            let mut params = item.split(';');
+            let coding = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let refused = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            (coding, !refused)
+        })
+        .collect::<Vec<_>>();
+    let allows = |coding: &str| {
+        accepted
+            .iter()
+            .find(|(name, _)| name == coding)
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+            .is_some_and(|(_, allowed)| *allowed)
+    };
+    [BROTLI, GZIP].into_iter().find(|&coding| allows(coding))
+}
+
+/// Whether `Cache-Control: no-transform` asks to leave the body alone.
+fn no_transform(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+}
+
+/// `req` without the headers HTTP/2 doesn't allow.
+fn http2_request(mut req: Request<Body>) -> Request<Body> {
+    let headers = req.headers_mut();
+    for name in &CONNECTION_HEADERS {
+        headers.remove(name);
+    }
+    if headers
+        .get(header::TE)
+        .is_some_and(|value| *value != "trailers")
+    {
+        headers.remove(header::TE);
+    }
+    *req.version_mut() = Version::HTTP_2;
+    req
+}
+
+/// Carry bytes between the client's upgraded connection and the agent's
+/// stream until either side closes.
+async fn splice(upgrade: OnUpgrade, mut tunnel: Tunnel) {
+    let mut client = match upgrade.await {
+        Ok(upgraded) => TokioIo::new(upgraded),
+        Err(err) => {
+            debug!("client connection was not upgraded: {err:#}");
+            return;
+        }
+    };
+    if let Err(err) = tokio::io::copy_bidirectional(&mut client, &mut tunnel).await {
+        debug!("tunnel closed: {err:#}");
+    }
+}
+
+/// Connections to agents, by the protocol they were negotiated to speak.
+struct Upstreams {
+    endpoint: Endpoint,
+    limits: ResponseLimits,
+    http2: Mutex<HashMap<EndpointId, Http2State>>,
+    http1: Mutex<HashMap<EndpointId, Connection>>,
+}
+
+enum Http2State {
+    Connected(SendRequest<Body>),
+    /// The agent refused [`HTTP2_UPSTREAM_ALPN`] at this time.
+    Refused(Instant),
+}
+
+impl Upstreams {
+    fn new(endpoint: Endpoint, limits: ResponseLimits) -> Self {
+        Self {
+            endpoint,
+            limits,
+            http2: Default::default(),
+            http1: Default::default(),
+        }
+    }
+
+    /// A sender for HTTP/2 requests to `endpoint_id`, or `None` if it
+    /// refused [`HTTP2_UPSTREAM_ALPN`].
+    async fn http2(&self, endpoint_id: EndpointId) -> Option<SendRequest<Body>> {
+        match self.http2.lock().expect("poisoned").get(&endpoint_id) {
+            Some(Http2State::Connected(sender)) if !sender.is_closed() => {
+                return Some(sender.clone());
+            }
+            Some(Http2State::Refused(at)) if at.elapsed() < NEGOTIATION_TTL => return None,
+            _ => {}
+        }
+        let (state, sender) = match self.dial_http2(endpoint_id).await {
+            Ok(sender) => (Http2State::Connected(sender.clone()), Some(sender)),
+            Err(err) => {
+                debug!(endpoint_id = %endpoint_id.fmt_short(), "agent has no HTTP/2 upstream: {err:#}");
+                (Http2State::Refused(Instant::now()), None)
+            }
+        };
+        self.http2
+            .lock()
+            .expect("poisoned")
+            .insert(endpoint_id, state);
+        sender
+    }
+
+    async fn dial_http2(&self, endpoint_id: EndpointId) -> Result<SendRequest<Body>> {
+        let conn = self
+            .endpoint
+            .connect(endpoint_id, HTTP2_UPSTREAM_ALPN)
+            .await
+            .std_context("Failed to connect to the agent")?;
+        let (send, recv) = conn.open_bi().await.anyerr()?;
+        let io = TokioIo::new(tokio::io::join(recv, send));
+        let max_header_list_size = u32::try_from(self.limits.max_head_bytes).unwrap_or(u32::MAX);
+        let (sender, connection) = http2::Builder::new(TokioExecutor::new())
+            .max_header_list_size(max_header_list_size)
+            .handshake(io)
+            .await
+            .anyerr()?;
+        tokio::spawn(
+            async move {
+                if let Err(err) = connection.await {
+                    debug!("HTTP/2 upstream closed: {err:#}");
+                }
+                drop(conn);
+            }
+            .instrument(error_span!("http2-upstream", endpoint_id = %endpoint_id.fmt_short())),
+        );
+        Ok(sender)
+    }
+
+    /// A connection to `endpoint_id` on the iroh-proxy-utils ALPN.
+    async fn http1(&self, endpoint_id: EndpointId) -> Result<Connection> {
+        if let Some(conn) = self.http1.lock().expect("poisoned").get(&endpoint_id)
+            && conn.close_reason().is_none()
+        {
+            return Ok(conn.clone());
+        }
+        let conn = self
+            .endpoint
+            .connect(endpoint_id, IROH_HTTP_CONNECT_ALPN)
+            .await
+            .std_context("Failed to connect to the agent")?;
+        self.http1
+            .lock()
+            .expect("poisoned")
+            .insert(endpoint_id, conn.clone());
+        Ok(conn)
+    }
+
+    /// Drop what is known about `endpoint_id` after a request to it failed,
+    /// so the next one dials and negotiates again.
+    fn forget(&self, endpoint_id: EndpointId) {
+        self.http2.lock().expect("poisoned").remove(&endpoint_id);
+        self.http1.lock().expect("poisoned").remove(&endpoint_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> Option<&'static str> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_str(value).unwrap(),
+        );
+        accepted_encoding(&headers)
+    }
+
+    #[test]
+    fn brotli_is_preferred_over_gzip() {
+        assert_eq!(accept("gzip, deflate, br, zstd"), Some(BROTLI));
+        assert_eq!(accept("gzip"), Some(GZIP));
+        assert_eq!(accept("br;q=0, gzip;q=0.5"), Some(GZIP));
+        assert_eq!(accept("*"), Some(BROTLI));
+        assert_eq!(accept("*;q=0, gzip"), Some(GZIP));
+        assert_eq!(accept("identity"), None);
+        assert_eq!(accept("gzip;q=0"), None);
+        assert_eq!(accepted_encoding(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,452 @@
+//! HTTP/1.1 requests to agents without HTTP/2 upstreams.
+//!
+//! This is the encoding iroh-proxy-utils uses on its ALPN: one request per
+//! QUIC stream, with the head in absolute form and the body as is, after
+//! which the send side is finished. The response comes back as HTTP/1.1 on
+//! the same stream. Requests always carry their `Content-Length`, so a body
+//! of unknown size has to be spooled by the caller first.
+//!
+//! CONNECT requests and upgrades leave the stream open after the head. Once
+//! the agent accepts one, the stream is handed back as a [`Tunnel`] for the
+//! caller to carry the client's bytes over.
+//!
+//! Trailers of chunked responses are passed on as a trailers frame. Interim
+//! responses can't be, as hyper's server doesn't send them, so the `Link`
+//! headers of `103 Early Hints` are added to the final response instead,
+//! where browsers still preload from them.
+//!
+//! Response heads are held to [`ResponseLimits`], from the gateway's
+//! `header_limits`.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http_body_util::BodyExt;
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Body as _, Bytes, Frame},
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    http::request::Parts,
+};
+use iroh::endpoint::{Connection, RecvStream, SendStream};
+use n0_error::{Result, StdResultExt, anyerr};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Join},
+    sync::mpsc,
+};
+use tracing::debug;
+
+use super::{
+    content_length,
+    front::{Body, empty},
+};
+use crate::{config::HeaderLimitsConfig, http2_upstream::add_early_hints};
+
+/// Longest line of a chunked body outside its data.
+const MAX_LINE_LEN: u64 = 8 * 1024;
+/// Largest piece of a response body read at once.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Headers that describe a connection rather than the message, which
+/// HTTP/2 doesn't allow.
+pub(super) const CONNECTION_HEADERS: [HeaderName; 5] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+];
+
+/// A stream to an agent that accepted a CONNECT request or an upgrade.
+pub(super) type Tunnel = Join<BufReader<RecvStream>, SendStream>;
+
+/// How large response heads from agents may be.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ResponseLimits {
+    /// Most header fields in a response head, and trailer fields after a
+    /// chunked body.
+    pub(super) max_headers: usize,
+    /// Most bytes of a response head, status line included.
+    pub(super) max_head_bytes: usize,
+}
+
+impl ResponseLimits {
+    pub(super) fn new(config: &HeaderLimitsConfig) -> Self {
+        Self {
+            max_headers: config.max_response_headers,
+            max_head_bytes: config.max_response_header_bytes,
+        }
+    }
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        Self::new(&HeaderLimitsConfig::default())
+    }
+}
+
+/// Send `req` on a new stream of `conn` and read the response head. The
+/// body is sent and the response body read by tasks of their own.
+pub(super) async fn send_request(
+    conn: &Connection,
+    req: Request<Body>,
+    limits: ResponseLimits,
+) -> Result<Response<Body>> {
+    let (parts, body) = req.into_parts();
+    let Some(len) = body.size_hint().exact() else {
+        return Err(anyerr!("Request body has no length"));
+    };
+    let (mut send, recv) = conn.open_bi().await.anyerr()?;
+    send.write_all(&request_head(&parts, Some(len))).await?;
+    tokio::spawn(async move {
+        if let Err(err) = write_body(&mut send, body).await {
+            debug!("failed to send the request body: {err:#}");
+        }
+    });
+    read_http1_response_from_stream(BufReader::new(recv), &parts.method, limits).await
+}
+
+/// Read the response to a `method` request from `recv`, with its body read
+/// by a task.
+pub(super) async fn read_http1_response_from_stream<R>(
+    mut recv: R,
+    method: &Method,
+    limits: ResponseLimits,
+) -> Result<Response<Body>>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let head = read_response_head(&mut recv, limits).await?;
+    let framing = Framing::of(method, &head);
+    Ok(response(head, framing, recv, limits))
+}
+
+/// Send a CONNECT or upgrade request on a new stream of `conn`. If the agent
+/// accepts it, the stream comes back with the response, to carry the
+/// tunnel's bytes.
+pub(super) async fn open_tunnel(
+    conn: &Connection,
+    req: Request<Body>,
+    limits: ResponseLimits,
+) -> Result<(Response<Body>, Option<Tunnel>)> {
+    let (parts, _body) = req.into_parts();
+    let (mut send, recv) = conn.open_bi().await.anyerr()?;
+    send.write_all(&request_head(&parts, None)).await?;
+    let mut recv = BufReader::new(recv);
+    let head = read_response_head(&mut recv, limits).await?;
+    let connect = parts.method == Method::CONNECT;
+    let accepted = if connect {
+        head.status().is_success()
+    } else {
+        head.status() == StatusCode::SWITCHING_PROTOCOLS
+    };
+    if !accepted {
+        let framing = Framing::of(&parts.method, &head);
+        return Ok((response(head, framing, recv, limits), None));
+    }
+    let (mut head, ()) = head.into_parts();
+    if connect {
+        for name in &CONNECTION_HEADERS {
+            head.headers.remove(name);
+        }
+        head.headers.remove(header::CONTENT_LENGTH);
+    }
+    let tunnel = tokio::io::join(recv, send);
+    Ok((Response::from_parts(head, empty()), Some(tunnel)))
+}
+
+/// The head of a request in absolute form. `len` is the length of its body,
+/// or `None` for CONNECT requests and upgrades, which keep their connection
+/// headers.
+fn request_head(parts: &Parts, len: Option<u64>) -> Vec<u8> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", parts.method, parts.uri).into_bytes();
+    for (name, value) in &parts.headers {
+        if *name == header::CONTENT_LENGTH || (len.is_some() && CONNECTION_HEADERS.contains(name)) {
+            continue;
+        }
+        append_header(&mut head, name.as_str(), value);
+    }
+    if let Some(len) = len
+        && (len > 0 || parts.headers.contains_key(header::CONTENT_LENGTH))
+    {
+        let len = HeaderValue::from(len);
+        append_header(&mut head, header::CONTENT_LENGTH.as_str(), &len);
+    }
+    head.extend_from_slice(b"\r\n");
+    head
+}
+
+/// Append a header line to a message head.
+pub(super) fn append_header(head: &mut Vec<u8>, name: &str, value: &HeaderValue) {
+    head.extend_from_slice(name.as_bytes());
+    head.extend_from_slice(b": ");
+    head.extend_from_slice(value.as_bytes());
+    head.extend_from_slice(b"\r\n");
+}
+
+async fn write_body(send: &mut SendStream, mut body: Body) -> Result<()> {
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            send.write_all(&data).await?;
+        }
+    }
+    send.finish().anyerr()?;
+    Ok(())
+}
+
+/// Read response heads from `recv` up to the final one. Interim responses
+/// other than `101 Switching Protocols` are skipped, after the links of
+/// early hints are kept for the final one.
+async fn read_response_head<R: AsyncBufRead + Unpin>(
+    recv: &mut R,
+    limits: ResponseLimits,
+) -> Result<Response<()>> {
+    let mut hints = HeaderMap::new();
+    loop {
+        let mut head = read_head(recv, limits).await?;
+        let status = head.status();
+        if status == StatusCode::EARLY_HINTS {
+            for link in head.headers().get_all(header::LINK) {
+                hints.append(header::LINK, link.clone());
+            }
+        } else if status.is_informational() && status != StatusCode::SWITCHING_PROTOCOLS {
+            debug!(%status, "skipping interim response");
+        } else {
+            add_early_hints(head.headers_mut(), &hints);
+            return Ok(head);
+        }
+    }
+}
+
+async fn read_head<R: AsyncBufRead + Unpin>(
+    recv: &mut R,
+    limits: ResponseLimits,
+) -> Result<Response<()>> {
+    let mut buf = Vec::new();
+    loop {
+        let start = buf.len();
+        let limit = limits.max_head_bytes.saturating_sub(start) as u64;
+        let read = (&mut *recv).take(limit).read_until(b'\n', &mut buf).await?;
+        if read == 0 {
+            return Err(anyerr!("Response head ended early or is too large"));
+        }
+        // An empty line ends the head.
+        if start > 0 && matches!(&buf[start..], b"\r\n" | b"\n") {
+            break;
+        }
+    }
+    let mut headers = vec![httparse::EMPTY_HEADER; limits.max_headers];
+    let mut parsed = httparse::Response::new(&mut headers);
+    if parsed
+        .parse(&buf)
+        .std_context("Invalid response head or too many headers")?
+        .is_partial()
+    {
+        return Err(anyerr!("Incomplete response head"));
+    }
+    let mut head = Response::new(());
+    *head.status_mut() = StatusCode::from_u16(parsed.code.unwrap_or_default())
+        .std_context("Invalid response status")?;
+    *head.headers_mut() = header_map(parsed.headers)?;
+    Ok(head)
+}
+
+fn header_map(fields: &[httparse::Header<'_>]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for field in fields {
+        let name =
+            HeaderName::from_bytes(field.name.as_bytes()).std_context("Invalid response header")?;
+        let value = HeaderValue::from_bytes(field.value).std_context("Invalid response header")?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// How the end of a response body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    /// The body ends with the stream.
+    Close,
+}
+
+impl Framing {
+    fn of(method: &Method, head: &Response<()>) -> Self {
+        let status = head.status();
+        if *method == Method::HEAD
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return Self::Empty;
+        }
+        let chunked = head
+            .headers()
+            .get_all(header::TRANSFER_ENCODING)
+            .iter()
+            .any(|value| {
+                value
+                    .to_str()
+                    .is_ok_and(|value| value.to_ascii_lowercase().contains("chunked"))
+            });
+        if chunked {
+            return Self::Chunked;
+        }
+        match content_length(head.headers()) {
+            Some(len) => Self::Length(len),
+            None => Self::Close,
+        }
+    }
+}
+
+/// The response for the client, with its body read from `recv` by a task.
+fn response<R>(
+    head: Response<()>,
+    framing: Framing,
+    recv: R,
+    limits: ResponseLimits,
+) -> Response<Body>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let (mut head, ()) = head.into_parts();
+    for name in &CONNECTION_HEADERS {
+        head.headers.remove(name);
+    }
+    let body = match framing {
+        Framing::Empty => empty(),
+        framing => {
+            let (tx, rx) = mpsc::channel(4);
+            tokio::spawn(async move {
+                let mut recv = recv;
+                if let Err(err) = read_body(&mut recv, framing, limits, &tx).await {
+                    tx.send(Err(io::Error::other(format!("{err:#}"))))
+                        .await
+                        .ok();
+                }
+            });
+            ChannelBody(rx).boxed()
+        }
+    };
+    Response::from_parts(head, body)
+}
+
+type FrameSender = mpsc::Sender<io::Result<Frame<Bytes>>>;
+
+async fn read_body<R: AsyncBufRead + Unpin>(
+    recv: &mut R,
+    framing: Framing,
+    limits: ResponseLimits,
+    tx: &FrameSender,
+) -> Result<()> {
+    match framing {
+        Framing::Empty => Ok(()),
+        Framing::Length(len) => read_data(recv, Some(len), tx).await,
+        Framing::Close => read_data(recv, None, tx).await,
+        Framing::Chunked => loop {
+            let line = read_line(recv).await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16).std_context("Invalid chunk size")?;
+            if size == 0 {
+                let trailers = read_trailers(recv, limits.max_headers).await?;
+                if !trailers.is_empty() {
+                    tx.send(Ok(Frame::trailers(trailers)))
+                        .await
+                        .map_err(|_| anyerr!("Response body was dropped"))?;
+                }
+                return Ok(());
+            }
+            read_data(recv, Some(size), tx).await?;
+            if !read_line(recv).await?.is_empty() {
+                return Err(anyerr!("Chunk is longer than its size"));
+            }
+        },
+    }
+}
+
+/// Send `len` bytes of `recv` as data frames, or everything up to the end
+/// of the stream.
+async fn read_data<R: AsyncBufRead + Unpin>(
+    recv: &mut R,
+    len: Option<u64>,
+    tx: &FrameSender,
+) -> Result<()> {
+    let mut remaining = len;
+    while remaining != Some(0) {
+        let want = remaining.map_or(READ_CHUNK, |left| left.min(READ_CHUNK as u64) as usize);
+        let mut buf = vec![0; want];
+        let read = recv.read(&mut buf).await?;
+        if read == 0 {
+            return match remaining {
+                Some(_) => Err(anyerr!("Stream ended inside the response body")),
+                None => Ok(()),
+            };
+        }
+        buf.truncate(read);
+        if let Some(left) = remaining.as_mut() {
+            *left -= read as u64;
+        }
+        tx.send(Ok(Frame::data(Bytes::from(buf))))
+            .await
+            .map_err(|_| anyerr!("Response body was dropped"))?;
+    }
+    Ok(())
+}
+
+/// The trailer fields after the last chunk, up to the empty line that ends
+/// them, of which there may be `max_fields`.
+async fn read_trailers<R: AsyncBufRead + Unpin>(
+    recv: &mut R,
+    max_fields: usize,
+) -> Result<HeaderMap> {
+    let mut buf = Vec::new();
+    for _ in 0..=max_fields {
+        let line = read_line(recv).await?;
+        if line.is_empty() {
+            let mut fields = vec![httparse::EMPTY_HEADER; max_fields];
+            buf.extend_from_slice(b"\r\n");
+            let fields = match httparse::parse_headers(&buf, &mut fields) {
+                Ok(httparse::Status::Complete((_, fields))) => fields,
+                _ => return Err(anyerr!("Invalid response trailers")),
+            };
+            return header_map(fields);
+        }
+        buf.extend_from_slice(line.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    Err(anyerr!("Too many response trailers"))
+}
+
+/// A line of a chunked body outside its data, without the line ending.
+async fn read_line<R: AsyncBufRead + Unpin>(recv: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    (&mut *recv)
+        .take(MAX_LINE_LEN)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if !line.ends_with(b"\n") {
+        return Err(anyerr!("Invalid chunked body"));
+    }
+    let line = String::from_utf8(line).std_context("Invalid chunked body")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// A response body that a task reads from the agent's stream.
+struct ChannelBody(mpsc::Receiver<io::Result<Frame<Bytes>>>);
+
+impl hyper::body::Body for ChannelBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        self.0.poll_recv(cx)
+    }
+}
@@ -0,0 +1,217 @@
+//! Limits on the size of requests the gateway forwards.
+//!
+//! The HTTP parser in iroh-proxy-utils accepts a request before the gateway
+//! sees it, so these limits are enforced on the parsed header map. Requests
+//! over a limit are answered with 431 or 413 and a reason naming the limit,
+//! rather than failing somewhere upstream with an opaque 502.
+//!
+//! Bodies are streamed to the endpoint by iroh-proxy-utils, so only the
+//! length a request declares can be checked here. Bodies without one that
+//! the gateway spools are held to the same limit as they are read.
+
+use std::fmt;
+
+use hyper::http::{HeaderMap, HeaderValue};
+
+use crate::config::{HeaderLimitsConfig, RequestLimitsConfig};
+
+/// Why a request's headers were rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum HeaderLimitExceeded {
+    /// More header fields than `max_request_headers`.
+    Count { count: usize, limit: usize },
+    /// Header names and values add up to more than `max_request_header_bytes`.
+    Size { bytes: usize, limit: usize },
+}
+
+impl HeaderLimitExceeded {
+    /// Stable code for logs and metrics.
+    pub(super) fn code(&self) -> &'static str {
+        match self {
+            Self::Count { .. } => "too_many_headers",
+            Self::Size { .. } => "headers_too_large",
+        }
+    }
+}
+
+impl fmt::Display for HeaderLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count { count, limit } => write!(
+                f,
+                "{}: request has {count} header fields, the gateway allows {limit}",
+                self.code()
+            ),
+            Self::Size { bytes, limit } => write!(
+                f,
+                "{}: request headers are {bytes} bytes, the gateway allows {limit}",
+                self.code()
+            ),
+        }
+    }
+}
+
+/// Check `headers` against the configured limits.
+///
+/// Each field is counted as its name, value, and the `": "` and CRLF around
+/// them, which is what it takes on the wire in HTTP/1.1.
+pub(super) fn check_request_headers(
+    headers: &HeaderMap<HeaderValue>,
+    limits: &HeaderLimitsConfig,
+) -> Result<(), HeaderLimitExceeded> {
+    let count = headers.len();
+    if count > limits.max_request_headers {
+        return Err(HeaderLimitExceeded::Count {
+            count,
+            limit: limits.max_request_headers,
+        });
+    }
+    let bytes = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum::<usize>();
+    if bytes > limits.max_request_header_bytes {
+        return Err(HeaderLimitExceeded::Size {
+            bytes,
+            limit: limits.max_request_header_bytes,
+        });
+    }
+    Ok(())
+}
+
+/// A request declared a body longer than `max_request_body_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct BodyLimitExceeded {
+    pub(super) length: u64,
+    pub(super) limit: u64,
+}
+
+impl fmt::Display for BodyLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "body_too_large: request body is {} bytes, the gateway allows {}",
+            self.length, self.limit
+        )
+    }
+}
+
+/// Check the `Content-Length` of a request against the configured limit.
+///
+/// Requests without a valid length, such as chunked uploads, are let through.
+pub(super) fn check_request_body(
+    headers: &HeaderMap<HeaderValue>,
+    limits: &RequestLimitsConfig,
+) -> Result<(), BodyLimitExceeded> {
+    let Some(limit) = limits.max_request_body_bytes else {
+        return Ok(());
+    };
+    let length = headers
+        .get(hyper::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    match length {
+        Some(length) if length > limit => Err(BodyLimitExceeded { length, limit }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::http::HeaderName;
+
+    use super::*;
+
+    fn limits(max_request_headers: usize, max_request_header_bytes: usize) -> HeaderLimitsConfig {
+        HeaderLimitsConfig {
+            max_request_headers,
+            max_request_header_bytes,
+            ..Default::default()
+        }
+    }
+
+    fn headers(fields: impl IntoIterator<Item = (String, String)>) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in fields {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(&value).unwrap(),
+            );
+        }
+        map
+    }
+
+    #[test]
+    fn allows_headers_at_the_limits() {
+        let map = headers((0..4).map(|i| (format!("x-h{i}"), "v".repeat(10))));
+        // Each field is 4 + 10 + 4 bytes.
+        assert_eq!(check_request_headers(&map, &limits(4, 4 * 18)), Ok(()));
+        assert_eq!(
+            check_request_headers(&map, &HeaderLimitsConfig::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_many_small_headers() {
+        let map = headers((0..1000).map(|i| (format!("x-h{i}"), "v".into())));
+        let err = check_request_headers(&map, &HeaderLimitsConfig::default()).unwrap_err();
+        assert_eq!(
+            err,
+            HeaderLimitExceeded::Count {
+                count: 1000,
+                limit: 64
+            }
+        );
+        assert!(err.to_string().starts_with("too_many_headers:"));
+    }
+
+    #[test]
+    fn counts_repeated_header_names_separately() {
+        let map = headers((0..5).map(|_| ("cookie".to_string(), "a=b".to_string())));
+        assert_eq!(
+            check_request_headers(&map, &limits(4, 1024)),
+            Err(HeaderLimitExceeded::Count { count: 5, limit: 4 })
+        );
+    }
+
+    #[test]
+    fn rejects_a_single_huge_value() {
+        let map = headers([("cookie".to_string(), "a".repeat(100 * 1024))]);
+        let err = check_request_headers(&map, &HeaderLimitsConfig::default()).unwrap_err();
+        assert_eq!(err.code(), "headers_too_large");
+        assert!(matches!(err, HeaderLimitExceeded::Size { bytes, .. } if bytes > 100 * 1024));
+    }
+
+    #[test]
+    fn checks_declared_body_length() {
+        let limits = RequestLimitsConfig {
+            max_request_body_bytes: Some(1024),
+            ..Default::default()
+        };
+        let length = |len: &str| headers([("content-length".to_string(), len.to_string())]);
+
+        assert_eq!(check_request_body(&length("1024"), &limits), Ok(()));
+        assert_eq!(
+            check_request_body(&length("1025"), &limits),
+            Err(BodyLimitExceeded {
+                length: 1025,
+                limit: 1024
+            })
+        );
+        assert_eq!(check_request_body(&HeaderMap::new(), &limits), Ok(()));
+        assert_eq!(
+            check_request_body(&length("1025"), &RequestLimitsConfig::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_many_headers_that_add_up() {
+        let map = headers((0..50).map(|i| (format!("x-h{i}"), "v".repeat(2000))));
+        assert!(matches!(
+            check_request_headers(&map, &HeaderLimitsConfig::default()),
+            Err(HeaderLimitExceeded::Size { .. })
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+//! Fast failure for endpoints that are not reachable.
+//!
+//! Without this, a request for an endpoint whose agent has crashed waits for
+//! the full iroh connect timeout before the gateway gives up. When liveness
+//! checks are enabled, the gateway dials an endpoint with a short timeout the
+//! first time it sees it and answers `503` right away if the dial fails. Both
+//! outcomes are cached so the probe does not run on every request: reachable
+//! endpoints for `live_ttl_secs`, unreachable ones for `dead_ttl_secs`.
+//!
+//! A reachable result older than `live_ttl_secs` but younger than
+//! `live_ttl_secs + stale_ttl_secs` is still served, and the endpoint is
+//! probed again in the background, so busy endpoints never wait on a probe.
+//!
+//! Agents keep their connector lease renewed (see [`crate::HeartbeatAgent`]),
+//! so the control plane independently stops routing to an endpoint whose
+//! lease has expired; this check covers the window before that happens.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use iroh::{Endpoint, EndpointId};
+use iroh_proxy_utils::ALPN as IROH_HTTP_CONNECT_ALPN;
+use tracing::debug;
+use ttl_cache::TtlCache;
+
+use super::metrics::GatewayMetrics;
+use crate::config::LivenessConfig;
+
+const CACHE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+struct ProbeResult {
+    live: bool,
+    at: Instant,
+}
+
+#[derive(derive_more::Debug, Clone)]
+pub(super) struct LivenessChecker {
+    endpoint: Endpoint,
+    probe_timeout: Duration,
+    live_ttl: Duration,
+    stale_ttl: Duration,
+    dead_ttl: Duration,
+    #[debug(skip)]
+    metrics: Arc<GatewayMetrics>,
+    #[debug(skip)]
+    cache: Arc<Mutex<TtlCache<EndpointId, ProbeResult>>>,
+    /// Endpoints with a background probe in flight.
+    #[debug(skip)]
+    refreshing: Arc<Mutex<HashSet<EndpointId>>>,
+}
+
+/// How a cached result was used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum CacheLookup {
+    Hit,
+    /// A reachable result past `live_ttl`, served while it is refreshed.
+    Stale,
+    Miss,
+}
+
+impl LivenessChecker {
+    pub(super) fn new(
+        endpoint: Endpoint,
+        config: &LivenessConfig,
+        metrics: Arc<GatewayMetrics>,
+    ) -> Self {
+        Self {
+            endpoint,
+            probe_timeout: Duration::from_millis(config.probe_timeout_ms),
+            live_ttl: Duration::from_secs(config.live_ttl_secs),
+            stale_ttl: Duration::from_secs(config.stale_ttl_secs),
+            dead_ttl: Duration::from_secs(config.dead_ttl_secs),
+            metrics,
+            cache: Arc::new(Mutex::new(TtlCache::new(CACHE_CAPACITY))),
+            refreshing: Default::default(),
+        }
+    }
+
+    /// Returns true if `endpoint_id` answered a dial recently.
+    pub(super) async fn is_live(&self, endpoint_id: EndpointId) -> bool {
+        let cached = self
+            .cache
+            .lock()
+            .expect("poisoned")
+            .get(&endpoint_id)
+            .copied();
+        if let Some(result) = cached {
+            if result.live && result.at.elapsed() > self.live_ttl {
+                self.metrics.inc_liveness_cache(CacheLookup::Stale);
+                self.refresh_in_background(endpoint_id);
+            } else {
+                self.metrics.inc_liveness_cache(CacheLookup::Hit);
+            }
+            return result.live;
+        }
+        self.metrics.inc_liveness_cache(CacheLookup::Miss);
+        self.probe(endpoint_id).await
+    }
+
+    fn refresh_in_background(&self, endpoint_id: EndpointId) {
+        if !self
+            .refreshing
+            .lock()
+            .expect("poisoned")
+            .insert(endpoint_id)
+        {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.probe(endpoint_id).await;
+            this.refreshing
+                .lock()
+                .expect("poisoned")
+                .remove(&endpoint_id);
+        });
+    }
+
+    /// Dial `endpoint_id` and cache the result.
+    async fn probe(&self, endpoint_id: EndpointId) -> bool {
+        let started = Instant::now();
+        let live = match tokio::time::timeout(
+            self.probe_timeout,
+            self.endpoint.connect(endpoint_id, IROH_HTTP_CONNECT_ALPN),
+        )
+        .await
+        {
+            Ok(Ok(conn)) => {
+                self.metrics.observe_quic_connect(started.elapsed());
+                conn.close(0u32.into(), b"liveness probe");
+                true
+            }
+            Ok(Err(err)) => {
+                debug!(endpoint_id = %endpoint_id.fmt_short(), "liveness probe failed: {err:#}");
+                false
+            }
+            Err(_) => {
+                debug!(endpoint_id = %endpoint_id.fmt_short(), "liveness probe timed out");
+                false
+            }
+        };
+        let ttl = if live {
+            self.live_ttl + self.stale_ttl
+        } else {
+            self.dead_ttl
+        };
+        let result = ProbeResult {
+            live,
+            at: Instant::now(),
+        };
+        self.cache
+            .lock()
+            .expect("poisoned")
+            .insert(endpoint_id, result, ttl);
+        live
+    }
+
+    /// Forget the cached result for `endpoint_id` so the next request probes
+    /// it again.
+    pub(super) fn invalidate(&self, endpoint_id: &EndpointId) {
+        self.cache.lock().expect("poisoned").remove(endpoint_id);
+    }
+}
@@ -1,330 +1,830 @@
+//! The gateway's metrics, in the OpenMetrics text format.
+//!
+//! Counters and latency histograms are [prometheus-client] families kept in
+//! a [`Registry`]. Series the gateway knows up front are created with it, so
+//! they are exported at zero before their first increment. What is read at
+//! scrape time, the transport counters of the endpoint, the
+//! [upstream paths](super::upstreams) and the
+//! [destination endpoints](super::destinations), is encoded by a
+//! [`Collector`] for each scrape.
+//!
+//! [prometheus-client]: prometheus_client
+
 use std::{
+    fmt,
     net::SocketAddr,
-    sync::{
-        Arc, OnceLock,
-        atomic::{AtomicU64, Ordering},
-    },
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 
-use axum::{Router, extract::State, routing::get};
+use axum::{Json, Router, extract::State, routing::get};
 use hyper::http::header;
-use iroh::Endpoint;
-use iroh_metrics::Registry;
+use iroh::{Endpoint, EndpointId};
 use n0_error::Result;
+use prometheus_client::{
+    collector::Collector,
+    encoding::{DescriptorEncoder, EncodeMetric, text},
+    metrics::{
+        MetricType,
+        counter::{ConstCounter, Counter},
+        family::{Family, MetricConstructor},
+        gauge::ConstGauge,
+        histogram::Histogram,
+    },
+    registry::Registry,
+};
 use tokio::net::TcpListener;
 use tracing::info;
 
-#[derive(Debug, Default)]
+use super::{
+    destinations::Destinations,
+    liveness::CacheLookup,
+    tickets::TicketLookup,
+    upstreams::{self, UpstreamPath, UpstreamPaths},
+};
+use crate::config::GatewayMetricsConfig;
+
+/// The labels of one series, as name and value.
+type Labels<const N: usize> = [(&'static str, &'static str); N];
+
+/// Reasons a request is denied, as in `iroh_gateway_denied_requests_total`.
+const DENIED_REASONS: [&str; 12] = [
+    "missing_header",
+    "missing_header_node_id",
+    "invalid_endpoint_id",
+    "invalid_target_port",
+    "unverified_hostname",
+    "endpoint_unreachable",
+    "header_limit",
+    "ip_filter",
+    "body_limit",
+    "timeout",
+    "unknown_codename",
+    "stale_ticket",
+];
+const STATUSES: [&str; 5] = ["500", "502", "503", "504", "other_5xx"];
+const PEER_CONN_STATES: [&str; 2] = ["with_existing", "without_existing"];
+
+/// Which kind of request a latency was measured for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RouteKind {
+    /// `CONNECT` requests for a tunnel.
+    Tunnel,
+    /// Requests for a hostname the gateway routes to a tunnel's origin.
+    Origin,
+}
+
+impl RouteKind {
+    const ALL: [Self; 2] = [Self::Tunnel, Self::Origin];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Tunnel => "tunnel",
+            Self::Origin => "origin",
+        }
+    }
+
+    fn labels(self) -> Labels<1> {
+        [("kind", self.label())]
+    }
+}
+
+/// Histogram buckets from the config, to create histograms of a family with.
+#[derive(Debug, Clone)]
+struct Buckets(Arc<[f64]>);
+
+impl Buckets {
+    /// The finite, positive bounds in seconds, ascending. `+Inf` is added by
+    /// the histograms.
+    fn new(bounds: &[f64]) -> Self {
+        let mut bounds = bounds
+            .iter()
+            .copied()
+            .filter(|bound| bound.is_finite() && *bound > 0.0)
+            .collect::<Vec<_>>();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        Self(bounds.into())
+    }
+
+    fn histogram(&self) -> Histogram {
+        Histogram::new(self.0.iter().copied())
+    }
+}
+
+impl MetricConstructor<Histogram> for Buckets {
+    fn new_metric(&self) -> Histogram {
+        self.histogram()
+    }
+}
+
+/// One histogram per [`RouteKind`].
+type RouteHistograms = Family<Labels<1>, Histogram, Buckets>;
+
+/// `family` with a series for each of `label_sets`.
+fn with_series<const N: usize, M, C: MetricConstructor<M>>(
+    family: Family<Labels<N>, M, C>,
+    label_sets: impl IntoIterator<Item = Labels<N>>,
+) -> Family<Labels<N>, M, C> {
+    for labels in label_sets {
+        family.get_or_create(&labels);
+    }
+    family
+}
+
+#[derive(Debug)]
 pub(super) struct GatewayMetrics {
-    requests_tunnel_total: AtomicU64,
-    requests_origin_total: AtomicU64,
-    requests_tcp_total: AtomicU64,
-    requests_uds_total: AtomicU64,
-    requests_tunnel_tcp_total: AtomicU64,
-    requests_tunnel_uds_total: AtomicU64,
-    requests_origin_tcp_total: AtomicU64,
-    requests_origin_uds_total: AtomicU64,
-    tunnel_reuse_attempts_with_existing_peer_conn_total: AtomicU64,
-    tunnel_reuse_attempts_without_existing_peer_conn_total: AtomicU64,
-    origin_reuse_attempts_with_existing_peer_conn_total: AtomicU64,
-    origin_reuse_attempts_without_existing_peer_conn_total: AtomicU64,
-    denied_missing_header_total: AtomicU64,
-    denied_missing_header_node_id_total: AtomicU64,
-    denied_invalid_endpoint_total: AtomicU64,
-    denied_invalid_target_port_total: AtomicU64,
-    responses_4xx_total: AtomicU64,
-    responses_5xx_total: AtomicU64,
-    responses_500_total: AtomicU64,
-    responses_502_total: AtomicU64,
-    responses_503_total: AtomicU64,
-    responses_504_total: AtomicU64,
-    responses_other_5xx_total: AtomicU64,
-    failures_5xx_with_existing_peer_conn_total: AtomicU64,
-    failures_5xx_without_existing_peer_conn_total: AtomicU64,
+    /// The families below.
+    registry: Registry,
+    upstream_paths: UpstreamPaths,
+    destinations: Destinations,
+    resolve_seconds: RouteHistograms,
+    quic_connect_seconds: Histogram,
+    upstream_ttfb_seconds: RouteHistograms,
+    request_duration_seconds: RouteHistograms,
+    /// By `kind`.
+    requests: Family<Labels<1>, Counter>,
+    /// By `source`.
+    requests_by_source: Family<Labels<1>, Counter>,
+    /// By `source` and `kind`.
+    requests_by_source_and_kind: Family<Labels<2>, Counter>,
+    /// By `kind` and `peer_conn_state`.
+    upstream_reuse_attempts: Family<Labels<2>, Counter>,
+    /// By `protocol`.
+    upstream_protocols: Family<Labels<1>, Counter>,
+    /// By `storage`.
+    spooled_bodies: Family<Labels<1>, Counter>,
+    upstream_compressed_responses: Counter,
+    /// By `encoding`.
+    compressed_responses: Family<Labels<1>, Counter>,
+    /// By `reason`.
+    denied_requests: Family<Labels<1>, Counter>,
+    endpoint_switches: Counter,
+    failovers: Counter,
+    /// By `result`.
+    liveness_cache: Family<Labels<1>, Counter>,
+    /// By `result`.
+    ticket_cache: Family<Labels<1>, Counter>,
+    ticket_fetch_errors: Counter,
+    ticket_invalidations: Counter,
+    ticket_events: Counter,
+    ticket_retries: Counter,
+    /// By `class`.
+    error_responses: Family<Labels<1>, Counter>,
+    /// By `status`.
+    error_responses_by_status: Family<Labels<1>, Counter>,
+    /// By `class` and `peer_conn_state`.
+    upstream_failures: Family<Labels<2>, Counter>,
 }
 
 static SHARED_METRICS: OnceLock<Arc<GatewayMetrics>> = OnceLock::new();
 
-pub(super) fn shared_gateway_metrics() -> Arc<GatewayMetrics> {
+/// The metrics of this process. Histogram buckets are taken from the config
+/// of the first caller.
+pub(super) fn shared_gateway_metrics(config: &GatewayMetricsConfig) -> Arc<GatewayMetrics> {
     SHARED_METRICS
-        .get_or_init(|| Arc::new(GatewayMetrics::default()))
+        .get_or_init(|| Arc::new(GatewayMetrics::new(config)))
         .clone()
 }
 
 impl GatewayMetrics {
+    pub(super) fn new(config: &GatewayMetricsConfig) -> Self {
+        let buckets = Buckets::new(&config.latency_buckets);
+        let route_histograms = || {
+            with_series(
+                RouteHistograms::new_with_constructor(buckets.clone()),
+                RouteKind::ALL.map(RouteKind::labels),
+            )
+        };
+        let kinds = RouteKind::ALL.map(|kind| kind.label());
+        let mut metrics = Self {
+            registry: Registry::default(),
+            upstream_paths: UpstreamPaths::default(),
+            destinations: Destinations::new(&config.endpoint_labels),
+            resolve_seconds: route_histograms(),
+            quic_connect_seconds: buckets.histogram(),
+            upstream_ttfb_seconds: route_histograms(),
+            request_duration_seconds: route_histograms(),
+            requests: with_series(Family::default(), kinds.map(|kind| [("kind", kind)])),
+            requests_by_source: with_series(
+                Family::default(),
+                ["tcp", "uds"].map(|source| [("source", source)]),
+            ),
+            requests_by_source_and_kind: with_series(
+                Family::default(),
+                kinds.into_iter().flat_map(|kind| {
+                    ["tcp", "uds"].map(|source| [("source", source), ("kind", kind)])
+                }),
+            ),
+            upstream_reuse_attempts: with_series(
+                Family::default(),
+                kinds.into_iter().flat_map(|kind| {
+                    PEER_CONN_STATES.map(|state| [("kind", kind), ("peer_conn_state", state)])
+                }),
+            ),
+            upstream_protocols: with_series(
+                Family::default(),
+                ["http1", "http2"].map(|protocol| [("protocol", protocol)]),
+            ),
+            spooled_bodies: with_series(
+                Family::default(),
+                ["memory", "file"].map(|storage| [("storage", storage)]),
+            ),
+            upstream_compressed_responses: Counter::default(),
+            compressed_responses: with_series(
+                Family::default(),
+                ["br", "gzip"].map(|encoding| [("encoding", encoding)]),
+            ),
+            denied_requests: with_series(
+                Family::default(),
+                DENIED_REASONS.map(|reason| [("reason", reason)]),
+            ),
+            endpoint_switches: Counter::default(),
+            failovers: Counter::default(),
+            liveness_cache: with_series(
+                Family::default(),
+                ["hit", "stale", "miss"].map(|result| [("result", result)]),
+            ),
+            ticket_cache: with_series(
+                Family::default(),
+                ["hit", "stale", "negative", "miss"].map(|result| [("result", result)]),
+            ),
+            ticket_fetch_errors: Counter::default(),
+            ticket_invalidations: Counter::default(),
+            ticket_events: Counter::default(),
+            ticket_retries: Counter::default(),
+            error_responses: with_series(
+                Family::default(),
+                ["4xx", "5xx"].map(|class| [("class", class)]),
+            ),
+            error_responses_by_status: with_series(
+                Family::default(),
+                STATUSES.map(|status| [("status", status)]),
+            ),
+            upstream_failures: with_series(
+                Family::default(),
+                PEER_CONN_STATES.map(|state| [("class", "5xx"), ("peer_conn_state", state)]),
+            ),
+        };
+        let mut registry = Registry::default();
+        metrics.register(&mut registry);
+        metrics.registry = registry;
+        metrics
+    }
+
+    /// How long picking an endpoint and rewriting a request took.
+    pub(super) fn observe_resolve(&self, kind: RouteKind, duration: Duration) {
+        self.resolve_seconds
+            .get_or_create(&kind.labels())
+            .observe(duration.as_secs_f64());
+    }
+
+    /// How long dialing an endpoint took, for dials that succeeded.
+    pub(super) fn observe_quic_connect(&self, duration: Duration) {
+        self.quic_connect_seconds.observe(duration.as_secs_f64());
+    }
+
+    /// Time from a request to the first byte of its response.
+    pub(super) fn observe_upstream_ttfb(&self, kind: RouteKind, duration: Duration) {
+        self.upstream_ttfb_seconds
+            .get_or_create(&kind.labels())
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Time from a request to the last byte of its response.
+    pub(super) fn observe_request_duration(&self, kind: RouteKind, duration: Duration) {
+        self.request_duration_seconds
+            .get_or_create(&kind.labels())
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Observations of the time to first byte and of the request duration
+    /// of `kind`, read from the exposition since histograms don't tell.
+    #[cfg(test)]
+    pub(super) fn request_durations(&self, kind: RouteKind) -> (u64, u64) {
+        let mut text = String::new();
+        text::encode(&mut text, &self.registry).unwrap();
+        let count = |name: &str| {
+            let prefix = format!("{name}_count{{kind=\"{}\"}} ", kind.label());
+            text.lines()
+                .find_map(|line| line.strip_prefix(&prefix)?.parse().ok())
+                .unwrap_or_default()
+        };
+        (
+            count("iroh_gateway_upstream_ttfb_seconds"),
+            count("iroh_gateway_request_duration_seconds"),
+        )
+    }
+
     pub(super) fn inc_tunnel_requests(&self) {
-        self.requests_tunnel_total.fetch_add(1, Ordering::Relaxed);
+        self.requests.get_or_create(&[("kind", "tunnel")]).inc();
     }
 
     pub(super) fn inc_origin_requests(&self) {
-        self.requests_origin_total.fetch_add(1, Ordering::Relaxed);
+        self.requests.get_or_create(&[("kind", "origin")]).inc();
     }
 
     pub(super) fn inc_tunnel_reuse_attempt(&self, has_existing_peer_conn: bool) {
-        if has_existing_peer_conn {
-            self.tunnel_reuse_attempts_with_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        } else {
-            self.tunnel_reuse_attempts_without_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        }
+        self.inc_reuse_attempt("tunnel", has_existing_peer_conn);
     }
 
     pub(super) fn inc_origin_reuse_attempt(&self, has_existing_peer_conn: bool) {
-        if has_existing_peer_conn {
-            self.origin_reuse_attempts_with_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        } else {
-            self.origin_reuse_attempts_without_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        }
+        self.inc_reuse_attempt("origin", has_existing_peer_conn);
+    }
+
+    fn inc_reuse_attempt(&self, kind: &'static str, has_existing_peer_conn: bool) {
+        self.upstream_reuse_attempts
+            .get_or_create(&[
+                ("kind", kind),
+                ("peer_conn_state", peer_conn_state(has_existing_peer_conn)),
+            ])
+            .inc();
+    }
+
+    /// A request was sent to an agent over HTTP/2.
+    pub(super) fn inc_upstream_http2(&self) {
+        self.upstream_protocols
+            .get_or_create(&[("protocol", "http2")])
+            .inc();
+    }
+
+    /// A request was sent to an agent over HTTP/1.1 by the gateway's own
+    /// HTTP server.
+    pub(super) fn inc_upstream_http1(&self) {
+        self.upstream_protocols
+            .get_or_create(&[("protocol", "http1")])
+            .inc();
+    }
+
+    /// A request body without a length was read in full before it was sent.
+    pub(super) fn inc_spooled_body(&self, in_file: bool) {
+        let storage = if in_file { "file" } else { "memory" };
+        self.spooled_bodies
+            .get_or_create(&[("storage", storage)])
+            .inc();
+    }
+
+    /// An agent sent a response body gzipped over its HTTP/2 upstream.
+    pub(super) fn inc_upstream_compressed(&self) {
+        self.upstream_compressed_responses.inc();
+    }
+
+    /// A response was compressed for the client with `encoding`.
+    pub(super) fn inc_compressed_response(&self, encoding: &'static str) {
+        self.compressed_responses
+            .get_or_create(&[("encoding", encoding)])
+            .inc();
     }
 
     pub(super) fn inc_tunnel_tcp_requests(&self) {
-        self.requests_tunnel_tcp_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_requests_by_source_and_kind("tcp", "tunnel");
     }
 
     #[cfg(unix)]
     pub(super) fn inc_tunnel_uds_requests(&self) {
-        self.requests_tunnel_uds_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_requests_by_source_and_kind("uds", "tunnel");
     }
 
     pub(super) fn inc_origin_tcp_requests(&self) {
-        self.requests_origin_tcp_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_requests_by_source_and_kind("tcp", "origin");
     }
 
     #[cfg(unix)]
     pub(super) fn inc_origin_uds_requests(&self) {
-        self.requests_origin_uds_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_requests_by_source_and_kind("uds", "origin");
+    }
+
+    fn inc_requests_by_source_and_kind(&self, source: &'static str, kind: &'static str) {
+        self.requests_by_source_and_kind
+            .get_or_create(&[("source", source), ("kind", kind)])
+            .inc();
     }
 
     pub(super) fn inc_tcp_requests(&self) {
-        self.requests_tcp_total.fetch_add(1, Ordering::Relaxed);
+        self.requests_by_source
+            .get_or_create(&[("source", "tcp")])
+            .inc();
     }
 
     #[cfg(unix)]
     pub(super) fn inc_uds_requests(&self) {
-        self.requests_uds_total.fetch_add(1, Ordering::Relaxed);
+        self.requests_by_source
+            .get_or_create(&[("source", "uds")])
+            .inc();
+    }
+
+    fn inc_denied(&self, reason: &'static str) {
+        self.denied_requests
+            .get_or_create(&[("reason", reason)])
+            .inc();
     }
 
     pub(super) fn inc_denied_missing_header(&self) {
-        self.denied_missing_header_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_denied("missing_header");
     }
 
     pub(super) fn inc_denied_missing_header_name(&self, name: &str) {
         self.inc_denied_missing_header();
         if name == "x-iroh-endpoint-id" {
-            self.denied_missing_header_node_id_total
-                .fetch_add(1, Ordering::Relaxed);
+            self.inc_denied("missing_header_node_id");
         }
     }
 
     pub(super) fn inc_denied_invalid_endpoint(&self) {
-        self.denied_invalid_endpoint_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_denied("invalid_endpoint_id");
     }
 
     pub(super) fn inc_denied_invalid_target_port(&self) {
-        self.denied_invalid_target_port_total
-            .fetch_add(1, Ordering::Relaxed);
+        self.inc_denied("invalid_target_port");
+    }
+
+    pub(super) fn inc_denied_unverified_hostname(&self) {
+        self.inc_denied("unverified_hostname");
+    }
+
+    pub(super) fn inc_denied_endpoint_unreachable(&self) {
+        self.inc_denied("endpoint_unreachable");
+    }
+
+    pub(super) fn inc_denied_header_limit(&self) {
+        self.inc_denied("header_limit");
+    }
+
+    pub(super) fn inc_denied_ip_filter(&self) {
+        self.inc_denied("ip_filter");
+    }
+
+    pub(super) fn inc_denied_body_limit(&self) {
+        self.inc_denied("body_limit");
+    }
+
+    pub(super) fn inc_denied_timeout(&self) {
+        self.inc_denied("timeout");
+    }
+
+    pub(super) fn inc_denied_unknown_codename(&self) {
+        self.inc_denied("unknown_codename");
+    }
+
+    pub(super) fn inc_denied_stale_ticket(&self) {
+        self.inc_denied("stale_ticket");
+    }
+
+    pub(super) fn observe_upstream(&self, endpoint_id: EndpointId, request_bytes: Option<u64>) {
+        self.upstream_paths.observe(endpoint_id);
+        self.destinations
+            .observe_request(endpoint_id, request_bytes);
+    }
+
+    pub(super) fn observe_unreachable(&self, endpoint_id: EndpointId) {
+        self.upstream_paths.observe_unreachable(endpoint_id);
+        self.destinations.observe_unreachable(endpoint_id);
+    }
+
+    pub(super) fn observe_denied(&self, endpoint_id: EndpointId) {
+        self.destinations.observe_denied(endpoint_id);
+    }
+
+    pub(super) fn inc_endpoint_switches(&self) {
+        self.endpoint_switches.inc();
+    }
+
+    pub(super) fn inc_failovers(&self) {
+        self.failovers.inc();
+    }
+
+    pub(super) fn inc_liveness_cache(&self, lookup: CacheLookup) {
+        let result = match lookup {
+            CacheLookup::Hit => "hit",
+            CacheLookup::Stale => "stale",
+            CacheLookup::Miss => "miss",
+        };
+        self.liveness_cache
+            .get_or_create(&[("result", result)])
+            .inc();
+    }
+
+    pub(super) fn inc_ticket_cache(&self, lookup: TicketLookup) {
+        let result = match lookup {
+            TicketLookup::Hit => "hit",
+            TicketLookup::Stale => "stale",
+            TicketLookup::Negative => "negative",
+            TicketLookup::Miss => "miss",
+        };
+        self.ticket_cache.get_or_create(&[("result", result)]).inc();
+    }
+
+    pub(super) fn inc_ticket_fetch_errors(&self) {
+        self.ticket_fetch_errors.inc();
+    }
+
+    pub(super) fn inc_ticket_invalidations(&self) {
+        self.ticket_invalidations.inc();
+    }
+
+    pub(super) fn inc_ticket_events(&self) {
+        self.ticket_events.inc();
+    }
+
+    pub(super) fn inc_ticket_retries(&self) {
+        self.ticket_retries.inc();
     }
 
     pub(super) fn inc_status_code(&self, status: hyper::StatusCode) {
-        if status.is_client_error() {
-            self.responses_4xx_total.fetch_add(1, Ordering::Relaxed);
+        let class = if status.is_client_error() {
+            "4xx"
         } else if status.is_server_error() {
-            self.responses_5xx_total.fetch_add(1, Ordering::Relaxed);
-            match status {
-                hyper::StatusCode::INTERNAL_SERVER_ERROR => {
-                    self.responses_500_total.fetch_add(1, Ordering::Relaxed);
-                }
-                hyper::StatusCode::BAD_GATEWAY => {
-                    self.responses_502_total.fetch_add(1, Ordering::Relaxed);
-                }
-                hyper::StatusCode::SERVICE_UNAVAILABLE => {
-                    self.responses_503_total.fetch_add(1, Ordering::Relaxed);
-                }
-                hyper::StatusCode::GATEWAY_TIMEOUT => {
-                    self.responses_504_total.fetch_add(1, Ordering::Relaxed);
-                }
-                _ => {
-                    self.responses_other_5xx_total
-                        .fetch_add(1, Ordering::Relaxed);
-                }
-            }
+            "5xx"
+        } else {
+            return;
+        };
+        self.error_responses
+            .get_or_create(&[("class", class)])
+            .inc();
+        if status.is_server_error() {
+            let status = match status {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR => "500",
+                hyper::StatusCode::BAD_GATEWAY => "502",
+                hyper::StatusCode::SERVICE_UNAVAILABLE => "503",
+                hyper::StatusCode::GATEWAY_TIMEOUT => "504",
+                _ => "other_5xx",
+            };
+            self.error_responses_by_status
+                .get_or_create(&[("status", status)])
+                .inc();
         }
     }
 
     pub(super) fn inc_5xx_failure_by_peer_conn_state(&self, has_existing_peer_conn: bool) {
-        if has_existing_peer_conn {
-            self.failures_5xx_with_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        } else {
-            self.failures_5xx_without_existing_peer_conn_total
-                .fetch_add(1, Ordering::Relaxed);
-        }
+        self.upstream_failures
+            .get_or_create(&[
+                ("class", "5xx"),
+                ("peer_conn_state", peer_conn_state(has_existing_peer_conn)),
+            ])
+            .inc();
+    }
+
+    /// The gateway's own families, under the names it has always exported.
+    /// Counter names get their `_total` from the encoder, and help texts
+    /// their final period from the registry.
+    fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "iroh_gateway_requests",
+            "Gateway request count by proxy request kind",
+            self.requests.clone(),
+        );
+        registry.register(
+            "iroh_gateway_requests_by_source",
+            "Gateway request count by ingress source",
+            self.requests_by_source.clone(),
+        );
+        registry.register(
+            "iroh_gateway_requests_by_source_and_kind",
+            "Gateway request count by ingress source and request kind",
+            self.requests_by_source_and_kind.clone(),
+        );
+        registry.register(
+            "iroh_gateway_upstream_reuse_attempts",
+            "Gateway upstream attempt count by request kind and whether a peer connection already existed",
+            self.upstream_reuse_attempts.clone(),
+        );
+        registry.register(
+            "iroh_gateway_upstream_protocol_requests",
+            "Requests the gateway's HTTP server sent to agents by protocol",
+            self.upstream_protocols.clone(),
+        );
+        registry.register(
+            "iroh_gateway_spooled_request_bodies",
+            "Request bodies the gateway read in full before sending them to an agent, by storage",
+            self.spooled_bodies.clone(),
+        );
+        registry.register(
+            "iroh_gateway_upstream_compressed_responses",
+            "Responses an agent gzipped for the gateway on an HTTP/2 upstream",
+            self.upstream_compressed_responses.clone(),
+        );
+        registry.register(
+            "iroh_gateway_compressed_responses",
+            "Responses the gateway compressed for the client, by encoding",
+            self.compressed_responses.clone(),
+        );
+        registry.register(
+            "iroh_gateway_denied_requests",
+            "Gateway denied request count by reason",
+            self.denied_requests.clone(),
+        );
+        registry.register(
+            "iroh_gateway_endpoint_switches",
+            "Number of times a hostname started routing to a different endpoint",
+            self.endpoint_switches.clone(),
+        );
+        registry.register(
+            "iroh_gateway_failovers",
+            "Number of requests sent to another endpoint of a tunnel because the preferred one was unreachable",
+            self.failovers.clone(),
+        );
+        registry.register(
+            "iroh_gateway_liveness_cache",
+            "Liveness cache lookups by result",
+            self.liveness_cache.clone(),
+        );
+        registry.register(
+            "iroh_gateway_ticket_cache",
+            "Codename ticket lookups by result",
+            self.ticket_cache.clone(),
+        );
+        registry.register(
+            "iroh_gateway_ticket_fetch_errors",
+            "Codename ticket fetches from n0des that failed",
+            self.ticket_fetch_errors.clone(),
+        );
+        registry.register(
+            "iroh_gateway_ticket_invalidations",
+            "Cached tickets dropped after repeated failed dials to their endpoint",
+            self.ticket_invalidations.clone(),
+        );
+        registry.register(
+            "iroh_gateway_ticket_events",
+            "Ticket changes pushed by n0des that dropped a cached lookup",
+            self.ticket_events.clone(),
+        );
+        registry.register(
+            "iroh_gateway_ticket_retries",
+            "Codename requests resolved again with a republished ticket after a failed dial",
+            self.ticket_retries.clone(),
+        );
+        registry.register(
+            "iroh_gateway_error_responses",
+            "Gateway error response count grouped by status class",
+            self.error_responses.clone(),
+        );
+        registry.register(
+            "iroh_gateway_error_responses_by_status",
+            "Gateway 5xx response count grouped by exact status code",
+            self.error_responses_by_status.clone(),
+        );
+        registry.register(
+            "iroh_gateway_upstream_failures",
+            "Gateway upstream 5xx failures grouped by whether a peer connection existed when the error was generated",
+            self.upstream_failures.clone(),
+        );
+        registry.register(
+            "iroh_gateway_resolve_duration_seconds",
+            "Time to pick an endpoint for a request and rewrite it",
+            self.resolve_seconds.clone(),
+        );
+        registry.register(
+            "iroh_gateway_quic_connect_duration_seconds",
+            "Time to dial an endpoint, from liveness probes",
+            self.quic_connect_seconds.clone(),
+        );
+        registry.register(
+            "iroh_gateway_upstream_ttfb_seconds",
+            "Time from a request to the first byte of its response, with upstream_timing",
+            self.upstream_ttfb_seconds.clone(),
+        );
+        registry.register(
+            "iroh_gateway_request_duration_seconds",
+            "Time from a request to the last byte of its response, with upstream_timing",
+            self.request_duration_seconds.clone(),
+        );
     }
 
     fn render(&self, endpoint: &Endpoint) -> String {
-        let endpoint_metrics = endpoint.metrics();
-        let direct_added = endpoint_metrics.magicsock.num_direct_conns_added.get();
-        let direct_removed = endpoint_metrics.magicsock.num_direct_conns_removed.get();
-        let relay_added = endpoint_metrics.magicsock.num_relay_conns_added.get();
-        let relay_removed = endpoint_metrics.magicsock.num_relay_conns_removed.get();
-        let relay_send_errors = endpoint_metrics.magicsock.send_relay_error.get();
-        let relay_home_changes = endpoint_metrics.magicsock.relay_home_change.get();
-        let handshake_success = endpoint_metrics
-            .magicsock
-            .connection_handshake_success
-            .get();
-        let endpoints_contacted = endpoint_metrics.magicsock.endpoints_contacted.get();
-        let endpoints_contacted_directly = endpoint_metrics
-            .magicsock
-            .endpoints_contacted_directly
-            .get();
-        let path_ping_failures = endpoint_metrics.magicsock.path_ping_failures.get();
-        let path_marked_outdated = endpoint_metrics.magicsock.path_marked_outdated.get();
-        let path_failure_resets = endpoint_metrics.magicsock.path_failure_resets.get();
-        let direct_current = direct_added.saturating_sub(direct_removed);
-        let relay_current = relay_added.saturating_sub(relay_removed);
-        let recv_total = endpoint_metrics.magicsock.recv_data_ipv4.get()
-            + endpoint_metrics.magicsock.recv_data_ipv6.get()
-            + endpoint_metrics.magicsock.recv_data_relay.get();
-        let send_total = endpoint_metrics.magicsock.send_data.get();
-        let mut endpoint_openmetrics = String::new();
-        let mut registry = Registry::default();
-        registry
+        let mut scrape = Registry::default();
+        scrape.register_collector(Box::new(EndpointCollector(endpoint.clone())));
+        scrape.register_collector(Box::new(upstreams::PathsCollector(
+            self.upstream_paths.snapshot(endpoint),
+        )));
+        if let Some(destinations) = self.destinations.collector() {
+            scrape.register_collector(Box::new(destinations));
+        }
+
+        let mut out = String::new();
+        let _ = text::encode_registry(&mut out, &self.registry);
+        let _ = text::encode_registry(&mut out, &scrape);
+        let mut endpoint_registry = iroh_metrics::Registry::default();
+        endpoint_registry
             .sub_registry_with_prefix("iroh_gateway_endpoint")
             .register_all(endpoint.metrics());
-        let _ = registry.encode_openmetrics_to_writer(&mut endpoint_openmetrics);
-
-        format!(
-            concat!(
-                "# HELP iroh_gateway_requests_total Gateway request count by proxy request kind.\n",
-                "# TYPE iroh_gateway_requests_total counter\n",
-                "iroh_gateway_requests_total{{kind=\"tunnel\"}} {}\n",
-                "iroh_gateway_requests_total{{kind=\"origin\"}} {}\n",
-                "# HELP iroh_gateway_requests_by_source_total Gateway request count by ingress source.\n",
-                "# TYPE iroh_gateway_requests_by_source_total counter\n",
-                "iroh_gateway_requests_by_source_total{{source=\"tcp\"}} {}\n",
-                "iroh_gateway_requests_by_source_total{{source=\"uds\"}} {}\n",
-                "# HELP iroh_gateway_requests_by_source_and_kind_total Gateway request count by ingress source and request kind.\n",
-                "# TYPE iroh_gateway_requests_by_source_and_kind_total counter\n",
-                "iroh_gateway_requests_by_source_and_kind_total{{source=\"tcp\",kind=\"tunnel\"}} {}\n",
-                "iroh_gateway_requests_by_source_and_kind_total{{source=\"uds\",kind=\"tunnel\"}} {}\n",
-                "iroh_gateway_requests_by_source_and_kind_total{{source=\"tcp\",kind=\"origin\"}} {}\n",
-                "iroh_gateway_requests_by_source_and_kind_total{{source=\"uds\",kind=\"origin\"}} {}\n",
-                "# HELP iroh_gateway_upstream_reuse_attempts_total Gateway upstream attempt count by request kind and whether a peer connection already existed.\n",
-                "# TYPE iroh_gateway_upstream_reuse_attempts_total counter\n",
-                "iroh_gateway_upstream_reuse_attempts_total{{kind=\"tunnel\",peer_conn_state=\"with_existing\"}} {}\n",
-                "iroh_gateway_upstream_reuse_attempts_total{{kind=\"tunnel\",peer_conn_state=\"without_existing\"}} {}\n",
-                "iroh_gateway_upstream_reuse_attempts_total{{kind=\"origin\",peer_conn_state=\"with_existing\"}} {}\n",
-                "iroh_gateway_upstream_reuse_attempts_total{{kind=\"origin\",peer_conn_state=\"without_existing\"}} {}\n",
-                "# HELP iroh_gateway_denied_requests_total Gateway denied request count by reason.\n",
-                "# TYPE iroh_gateway_denied_requests_total counter\n",
-                "iroh_gateway_denied_requests_total{{reason=\"missing_header\"}} {}\n",
-                "iroh_gateway_denied_requests_total{{reason=\"missing_header_node_id\"}} {}\n",
-                "iroh_gateway_denied_requests_total{{reason=\"invalid_endpoint_id\"}} {}\n",
-                "iroh_gateway_denied_requests_total{{reason=\"invalid_target_port\"}} {}\n",
-                "# HELP iroh_gateway_error_responses_total Gateway error response count grouped by status class.\n",
-                "# TYPE iroh_gateway_error_responses_total counter\n",
-                "iroh_gateway_error_responses_total{{class=\"4xx\"}} {}\n",
-                "iroh_gateway_error_responses_total{{class=\"5xx\"}} {}\n",
-                "# HELP iroh_gateway_error_responses_by_status_total Gateway 5xx response count grouped by exact status code.\n",
-                "# TYPE iroh_gateway_error_responses_by_status_total counter\n",
-                "iroh_gateway_error_responses_by_status_total{{status=\"500\"}} {}\n",
-                "iroh_gateway_error_responses_by_status_total{{status=\"502\"}} {}\n",
-                "iroh_gateway_error_responses_by_status_total{{status=\"503\"}} {}\n",
-                "iroh_gateway_error_responses_by_status_total{{status=\"504\"}} {}\n",
-                "iroh_gateway_error_responses_by_status_total{{status=\"other_5xx\"}} {}\n",
-                "# HELP iroh_gateway_upstream_failures_total Gateway upstream 5xx failures grouped by whether a peer connection existed when the error was generated.\n",
-                "# TYPE iroh_gateway_upstream_failures_total counter\n",
-                "iroh_gateway_upstream_failures_total{{class=\"5xx\",peer_conn_state=\"with_existing\"}} {}\n",
-                "iroh_gateway_upstream_failures_total{{class=\"5xx\",peer_conn_state=\"without_existing\"}} {}\n",
-                "# HELP iroh_gateway_iroh_recv_bytes_total Total iroh magicsock bytes received.\n",
-                "# TYPE iroh_gateway_iroh_recv_bytes_total counter\n",
-                "iroh_gateway_iroh_recv_bytes_total {}\n",
-                "# HELP iroh_gateway_iroh_send_bytes_total Total iroh magicsock bytes sent.\n",
-                "# TYPE iroh_gateway_iroh_send_bytes_total counter\n",
-                "iroh_gateway_iroh_send_bytes_total {}\n\n",
-                "# HELP iroh_gateway_quic_connections_opened_total QUIC peer connections opened by transport path.\n",
-                "# TYPE iroh_gateway_quic_connections_opened_total counter\n",
-                "iroh_gateway_quic_connections_opened_total{{path=\"direct\"}} {}\n",
-                "iroh_gateway_quic_connections_opened_total{{path=\"relay\"}} {}\n",
-                "# HELP iroh_gateway_quic_connections_closed_total QUIC peer connections closed by transport path.\n",
-                "# TYPE iroh_gateway_quic_connections_closed_total counter\n",
-                "iroh_gateway_quic_connections_closed_total{{path=\"direct\"}} {}\n",
-                "iroh_gateway_quic_connections_closed_total{{path=\"relay\"}} {}\n",
-                "# HELP iroh_gateway_quic_connections_current Current QUIC peer connections by transport path.\n",
-                "# TYPE iroh_gateway_quic_connections_current gauge\n",
-                "iroh_gateway_quic_connections_current{{path=\"direct\"}} {}\n",
-                "iroh_gateway_quic_connections_current{{path=\"relay\"}} {}\n\n",
-                "# HELP iroh_gateway_tunnel_connectivity_events_total Tunnel connectivity events from iroh magicsock state.\n",
-                "# TYPE iroh_gateway_tunnel_connectivity_events_total counter\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"relay_send_error\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"relay_home_change\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"connection_handshake_success\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"endpoints_contacted\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"endpoints_contacted_directly\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"path_ping_failures\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"path_marked_outdated\"}} {}\n",
-                "iroh_gateway_tunnel_connectivity_events_total{{event=\"path_failure_resets\"}} {}\n\n",
+        let _ = endpoint_registry.encode_openmetrics_to_writer(&mut out);
+        let _ = text::encode_eof(&mut out);
+        out
+    }
+}
+
+fn peer_conn_state(has_existing_peer_conn: bool) -> &'static str {
+    if has_existing_peer_conn {
+        "with_existing"
+    } else {
+        "without_existing"
+    }
+}
+
+/// Encode a counter family read at scrape time, from its series.
+pub(super) fn encode_counters<const N: usize>(
+    encoder: &mut DescriptorEncoder,
+    name: &str,
+    help: &str,
+    series: impl IntoIterator<Item = ([(&'static str, String); N], u64)>,
+) -> fmt::Result {
+    let mut family = encoder.encode_descriptor(name, help, None, MetricType::Counter)?;
+    for (labels, value) in series {
+        ConstCounter::new(value).encode(family.encode_family(&labels)?)?;
+    }
+    Ok(())
+}
+
+/// Encode a gauge family read at scrape time, from its series.
+pub(super) fn encode_gauges<const N: usize, V>(
+    encoder: &mut DescriptorEncoder,
+    name: &str,
+    help: &str,
+    series: impl IntoIterator<Item = ([(&'static str, String); N], V)>,
+) -> fmt::Result
+where
+    ConstGauge<V>: EncodeMetric,
+{
+    let mut family = encoder.encode_descriptor(name, help, None, MetricType::Gauge)?;
+    for (labels, value) in series {
+        ConstGauge::new(value).encode(family.encode_family(&labels)?)?;
+    }
+    Ok(())
+}
+
+/// Transport counters iroh keeps for the gateway's endpoint.
+#[derive(Debug)]
+struct EndpointCollector(Endpoint);
+
+impl Collector for EndpointCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> fmt::Result {
+        let magicsock = &self.0.metrics().magicsock;
+        let direct_added = magicsock.num_direct_conns_added.get();
+        let direct_removed = magicsock.num_direct_conns_removed.get();
+        let relay_added = magicsock.num_relay_conns_added.get();
+        let relay_removed = magicsock.num_relay_conns_removed.get();
+        let recv_total = magicsock.recv_data_ipv4.get()
+            + magicsock.recv_data_ipv6.get()
+            + magicsock.recv_data_relay.get();
+        let path = |path: &str| [("path", path.to_string())];
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_iroh_recv_bytes",
+            "Total iroh magicsock bytes received.",
+            [([], recv_total)],
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_iroh_send_bytes",
+            "Total iroh magicsock bytes sent.",
+            [([], magicsock.send_data.get())],
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_quic_connections_opened",
+            "QUIC peer connections opened by transport path.",
+            [(path("direct"), direct_added), (path("relay"), relay_added)],
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_quic_connections_closed",
+            "QUIC peer connections closed by transport path.",
+            [
+                (path("direct"), direct_removed),
+                (path("relay"), relay_removed),
+            ],
+        )?;
+        encode_gauges(
+            &mut encoder,
+            "iroh_gateway_quic_connections_current",
+            "Current QUIC peer connections by transport path.",
+            [
+                (path("direct"), direct_added.saturating_sub(direct_removed)),
+                (path("relay"), relay_added.saturating_sub(relay_removed)),
+            ],
+        )?;
+        let events = [
+            ("relay_send_error", &magicsock.send_relay_error),
+            ("relay_home_change", &magicsock.relay_home_change),
+            (
+                "connection_handshake_success",
+                &magicsock.connection_handshake_success,
+            ),
+            ("endpoints_contacted", &magicsock.endpoints_contacted),
+            (
+                "endpoints_contacted_directly",
+                &magicsock.endpoints_contacted_directly,
             ),
-            self.requests_tunnel_total.load(Ordering::Relaxed),
-            self.requests_origin_total.load(Ordering::Relaxed),
-            self.requests_tcp_total.load(Ordering::Relaxed),
-            self.requests_uds_total.load(Ordering::Relaxed),
-            self.requests_tunnel_tcp_total.load(Ordering::Relaxed),
-            self.requests_tunnel_uds_total.load(Ordering::Relaxed),
-            self.requests_origin_tcp_total.load(Ordering::Relaxed),
-            self.requests_origin_uds_total.load(Ordering::Relaxed),
-            self.tunnel_reuse_attempts_with_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            self.tunnel_reuse_attempts_without_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            self.origin_reuse_attempts_with_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            self.origin_reuse_attempts_without_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            self.denied_missing_header_total.load(Ordering::Relaxed),
-            self.denied_missing_header_node_id_total
-                .load(Ordering::Relaxed),
-            self.denied_invalid_endpoint_total.load(Ordering::Relaxed),
-            self.denied_invalid_target_port_total
-                .load(Ordering::Relaxed),
-            self.responses_4xx_total.load(Ordering::Relaxed),
-            self.responses_5xx_total.load(Ordering::Relaxed),
-            self.responses_500_total.load(Ordering::Relaxed),
-            self.responses_502_total.load(Ordering::Relaxed),
-            self.responses_503_total.load(Ordering::Relaxed),
-            self.responses_504_total.load(Ordering::Relaxed),
-            self.responses_other_5xx_total.load(Ordering::Relaxed),
-            self.failures_5xx_with_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            self.failures_5xx_without_existing_peer_conn_total
-                .load(Ordering::Relaxed),
-            recv_total,
-            send_total,
-            direct_added,
-            relay_added,
-            direct_removed,
-            relay_removed,
-            direct_current,
-            relay_current,
-            relay_send_errors,
-            relay_home_changes,
-            handshake_success,
-            endpoints_contacted,
-            endpoints_contacted_directly,
-            path_ping_failures,
-            path_marked_outdated,
-            path_failure_resets,
-        ) + &endpoint_openmetrics
+            ("path_ping_failures", &magicsock.path_ping_failures),
+            ("path_marked_outdated", &magicsock.path_marked_outdated),
+            ("path_failure_resets", &magicsock.path_failure_resets),
+        ];
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_tunnel_connectivity_events",
+            "Tunnel connectivity events from iroh magicsock state.",
+            events.map(|(event, counter)| ([("event", event.to_string())], counter.get())),
+        )
     }
 }
 
@@ -343,6 +843,7 @@ impl MetricsHttpState {
 pub(super) async fn serve_metrics_http(addr: SocketAddr, state: MetricsHttpState) -> Result<()> {
     let app = Router::new()
         .route("/metrics", get(metrics_handler))
+        .route("/upstreams", get(upstreams_handler))
         .with_state(state);
     let listener = TcpListener::bind(addr).await?;
     info!(metrics_bind_addr = %addr, "gateway metrics server started");
@@ -356,8 +857,68 @@ async fn metrics_handler(
     (
         [(
             header::CONTENT_TYPE,
-            "text/plain; version=0.0.4; charset=utf-8",
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
         )],
         state.metrics.render(&state.endpoint),
     )
 }
+
+async fn upstreams_handler(State(state): State<MetricsHttpState>) -> Json<Vec<UpstreamPath>> {
+    Json(state.metrics.upstream_paths.snapshot(&state.endpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn keeps_metric_names_and_one_type_per_family() {
+        let metrics = GatewayMetrics::new(&GatewayMetricsConfig::default());
+        metrics.inc_tunnel_requests();
+        metrics.inc_denied_missing_header_name("x-iroh-endpoint-id");
+        metrics.inc_status_code(hyper::StatusCode::BAD_GATEWAY);
+        metrics.observe_resolve(RouteKind::Origin, Duration::from_millis(3));
+        let mut text = String::new();
+        text::encode(&mut text, &metrics.registry).unwrap();
+
+        for line in [
+            "iroh_gateway_requests_total{kind=\"tunnel\"} 1",
+            "iroh_gateway_requests_total{kind=\"origin\"} 0",
+            "iroh_gateway_requests_by_source_and_kind_total{source=\"uds\",kind=\"origin\"} 0",
+            "iroh_gateway_upstream_reuse_attempts_total{kind=\"tunnel\",peer_conn_state=\"without_existing\"} 0",
+            "iroh_gateway_denied_requests_total{reason=\"missing_header\"} 1",
+            "iroh_gateway_denied_requests_total{reason=\"missing_header_node_id\"} 1",
+            "iroh_gateway_endpoint_switches_total 0",
+            "iroh_gateway_error_responses_total{class=\"5xx\"} 1",
+            "iroh_gateway_error_responses_by_status_total{status=\"502\"} 1",
+            "iroh_gateway_resolve_duration_seconds_bucket{le=\"0.005\",kind=\"origin\"} 1",
+            "iroh_gateway_resolve_duration_seconds_count{kind=\"tunnel\"} 0",
+            "# HELP iroh_gateway_requests Gateway request count by proxy request kind.",
+            "# EOF",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line}");
+        }
+        let mut families = HashSet::new();
+        for line in text.lines().filter(|l| l.starts_with("# TYPE ")) {
+            let name = line.split(' ').nth(2).unwrap();
+            assert!(families.insert(name), "{name} declared twice");
+        }
+        for line in text.lines().filter(|l| !l.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            let family = ["_total", "_bucket", "_sum", "_count"]
+                .iter()
+                .find_map(|suffix| name.strip_suffix(suffix))
+                .filter(|family| families.contains(family))
+                .unwrap_or(name);
+            assert!(families.contains(family), "{name} has no # TYPE");
+        }
+    }
+
+    #[test]
+    fn ignores_unusable_buckets() {
+        let buckets = Buckets::new(&[0.1, f64::NAN, 0.01, 0.1, -1.0]);
+        assert_eq!(*buckets.0, [0.01, 0.1]);
+    }
+}
@@ -0,0 +1,315 @@
+//! PROXY protocol v2 on the gateway's TCP listener.
+//!
+//! Behind an L4 load balancer the socket peer is the balancer. With
+//! `client_ip.proxy_protocol` set, the balancer prefixes each connection with
+//! a PROXY header naming the real client. iroh-proxy-utils owns the accept
+//! loop and would read that header as HTTP, so the gateway accepts
+//! connections itself, strips the header and relays the rest to the proxy
+//! over loopback. [`ProxiedPeers`] maps each loopback connection back to the
+//! client it carries.
+//!
+//! With `client_ip.proxy_protocol_sources` set, only peers in those networks
+//! are expected to send a header; connections from anywhere else are relayed
+//! as they are, with the socket peer as the client, so they can't claim an
+//! address of their choosing.
+//!
+//! Agents write the same header with [`header`] for tunnels that pass the
+//! client on to their service.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use n0_error::{Result, StdResultExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::debug;
+
+use super::{metrics::GatewayMetrics, timing};
+use crate::ip_filter::IpCidr;
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Headers longer than this are refused. Addresses take at most 216 bytes;
+/// the rest is TLVs, which the gateway skips.
+const MAX_HEADER_LEN: usize = 4096;
+/// How long a new connection may take to send its PROXY header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client behind each relayed loopback connection, keyed by the
+/// loopback connection's local address.
+#[derive(Debug, Default)]
+pub(super) struct ProxiedPeers {
+    clients: Mutex<HashMap<SocketAddr, SocketAddr>>,
+}
+
+impl ProxiedPeers {
+    /// The client for a request that arrived from `loopback` on the inner
+    /// listener.
+    pub(super) fn client(&self, loopback: SocketAddr) -> Option<SocketAddr> {
+        self.clients
+            .lock()
+            .expect("poisoned")
+            .get(&loopback)
+            .copied()
+    }
+
+    fn insert(&self, loopback: SocketAddr, client: SocketAddr) {
+        self.clients
+            .lock()
+            .expect("poisoned")
+            .insert(loopback, client);
+    }
+
+    fn remove(&self, loopback: SocketAddr) {
+        self.clients.lock().expect("poisoned").remove(&loopback);
+    }
+}
+
+/// Accept connections on `listener`, read the PROXY header of those from
+/// `sources` and relay them to `inner`, timing the responses if `timing` is
+/// set. With no `sources`, every peer is expected to send a header.
+pub(super) async fn serve(
+    listener: TcpListener,
+    inner: SocketAddr,
+    peers: Arc<ProxiedPeers>,
+    sources: Arc<[IpCidr]>,
+    timing: Option<Arc<GatewayMetrics>>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                debug!("failed to accept connection: {err:#}");
+                continue;
+            }
+        };
+        let peers = peers.clone();
+        let timing = timing.clone();
+        let trusted = sources.is_empty() || sources.iter().any(|net| net.contains(peer.ip()));
+        tokio::spawn(async move {
+            if let Err(err) = relay(stream, peer, trusted, inner, &peers, timing.as_deref()).await {
+                debug!(%peer, "PROXY protocol connection failed: {err:#}");
+            }
+        });
+    }
+}
+
+/// Relay a connection from `peer` to `inner`, reading its PROXY header first
+/// if the peer is `trusted` to send one.
+async fn relay(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    trusted: bool,
+    inner: SocketAddr,
+    peers: &ProxiedPeers,
+    timing: Option<&GatewayMetrics>,
+) -> Result<()> {
+    let client = if trusted {
+        tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+            .await
+            .std_context("Timed out reading PROXY header")??
+            .unwrap_or(peer)
+    } else {
+        peer
+    };
+    let mut upstream = TcpStream::connect(inner)
+        .await
+        .std_context("Failed to connect to the gateway listener")?;
+    let loopback = upstream.local_addr()?;
+    peers.insert(loopback, client);
+    let res = match timing {
+        Some(metrics) => timing::relay(stream, upstream, metrics).await,
+        None => tokio::io::copy_bidirectional(&mut stream, &mut upstream)
+            .await
+            .map(|_| ())
+            .std_context("Failed to relay connection"),
+    };
+    peers.remove(loopback);
+    res
+}
+
+/// A PROXY protocol v2 header for a connection from `client` to `server`, or
+/// a `LOCAL` one if the client is unknown. Addresses of different families
+/// are both sent as IPv6.
+pub(crate) fn header(client: Option<SocketAddr>, server: SocketAddr) -> Vec<u8> {
+    let mut bytes = SIGNATURE.to_vec();
+    let Some(client) = client else {
+        bytes.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        return bytes;
+    };
+    let mut addrs = Vec::with_capacity(36);
+    let family = match (client.ip().to_canonical(), server.ip().to_canonical()) {
+        (IpAddr::V4(client), IpAddr::V4(server)) => {
+            addrs.extend_from_slice(&client.octets());
+            addrs.extend_from_slice(&server.octets());
+            0x11
+        }
+        (client, server) => {
+            addrs.extend_from_slice(&ipv6(client).octets());
+            addrs.extend_from_slice(&ipv6(server).octets());
+            0x21
+        }
+    };
+    addrs.extend_from_slice(&client.port().to_be_bytes());
+    addrs.extend_from_slice(&server.port().to_be_bytes());
+    bytes.extend_from_slice(&[0x21, family]);
+    bytes.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&addrs);
+    bytes
+}
+
+fn ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+/// Read a PROXY protocol v2 header. Returns the client address, or `None`
+/// for `LOCAL` connections (the balancer's own health checks) and address
+/// families other than TCP over IPv4 or IPv6.
+pub(crate) async fn read_header(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<SocketAddr>> {
+    let mut head = [0u8; 16];
+    reader
+        .read_exact(&mut head)
+        .await
+        .std_context("Failed to read PROXY header")?;
+    if head[..12] != SIGNATURE {
+        n0_error::bail_any!("Missing PROXY protocol v2 signature");
+    }
+    let (version, command) = (head[12] >> 4, head[12] & 0x0f);
+    if version != 2 {
+        n0_error::bail_any!("Unsupported PROXY protocol version {version}");
+    }
+    let family = head[13];
+    let len = usize::from(u16::from_be_bytes([head[14], head[15]]));
+    if len > MAX_HEADER_LEN {
+        n0_error::bail_any!("PROXY header of {len} bytes is too long");
+    }
+    let mut body = vec![0u8; len];
+    reader
+        .read_exact(&mut body)
+        .await
+        .std_context("Failed to read PROXY header")?;
+    match command {
+        0x0 => return Ok(None),
+        0x1 => {}
+        other => n0_error::bail_any!("Unknown PROXY command {other:#x}"),
+    }
+    let addr = match family {
+        // TCP over IPv4: source, destination, source port, destination port.
+        0x11 if len >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&body[..4]).expect("length checked"));
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))
+        }
+        0x21 if len >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).expect("length checked"));
+            SocketAddr::new(ip.into(), u16::from_be_bytes([body[32], body[33]]))
+        }
+        0x11 | 0x21 => n0_error::bail_any!("Truncated PROXY header addresses"),
+        _ => return Ok(None),
+    };
+    Ok(Some(addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    fn header(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.push(0x20 | command);
+        bytes.push(family);
+        bytes.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(addrs);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn reads_client_addresses() {
+        let v4 = [192, 0, 2, 1, 10, 0, 0, 1, 0x1f, 0x90, 0, 80];
+        let mut bytes = header(0x1, 0x11, &v4);
+        bytes.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        let mut reader = bytes.as_slice();
+        assert_eq!(
+            read_header(&mut reader).await.unwrap(),
+            Some("192.0.2.1:8080".parse().unwrap())
+        );
+        assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+
+        let mut v6 = [0u8; 36];
+        v6[..16].copy_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        v6[32..34].copy_from_slice(&443u16.to_be_bytes());
+        let bytes = header(0x1, 0x21, &v6);
+        assert_eq!(
+            read_header(&mut bytes.as_slice()).await.unwrap(),
+            Some("[2001:db8::7]:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_socket_peer_for_local_connections() {
+        let bytes = header(0x0, 0x00, &[]);
+        assert_eq!(read_header(&mut bytes.as_slice()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn writes_headers_it_reads() {
+        let server: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        for client in ["192.0.2.1:40000", "[2001:db8::7]:443"] {
+            let client: SocketAddr = client.parse().unwrap();
+            let bytes = header(Some(client), server);
+            let read = read_header(&mut bytes.as_slice()).await.unwrap().unwrap();
+            assert_eq!(read.ip().to_canonical(), client.ip());
+            assert_eq!(read.port(), client.port());
+        }
+        let bytes = header(None, server);
+        assert_eq!(read_header(&mut bytes.as_slice()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn only_reads_headers_from_trusted_sources() {
+        let inner = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let inner_addr = inner.local_addr().unwrap();
+        let outer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(outer.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer) = outer.accept().await.unwrap();
+
+        // A peer that isn't trusted is relayed as it is, header and all.
+        let sent = header(Some("192.0.2.1:40000".parse().unwrap()), inner_addr);
+        client.write_all(&sent).await.unwrap();
+        let peers = Arc::new(ProxiedPeers::default());
+        let relay = tokio::spawn({
+            let peers = peers.clone();
+            async move { relay(stream, peer, false, inner_addr, &peers, None).await }
+        });
+        let (mut relayed, loopback) = inner.accept().await.unwrap();
+        let mut received = vec![0u8; sent.len()];
+        relayed.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, sent);
+        assert_eq!(peers.client(loopback), Some(peer));
+
+        drop(client);
+        drop(relayed);
+        relay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_headers() {
+        let mut reader: &[u8] = b"GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert!(read_header(&mut reader).await.is_err());
+        let bytes = header(0x1, 0x11, &[192, 0, 2, 1]);
+        assert!(read_header(&mut bytes.as_slice()).await.is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! Detects when a hostname moves to a different endpoint.
+//!
+//! A tunnel's hostname keeps its name when the agent behind it is replaced
+//! (a new install, a restored backup, a blue/green deploy), but the endpoint
+//! id in `x-iroh-endpoint-id` changes. The gateway remembers the last endpoint
+//! it saw per hostname so a switch is noticed on the first request that
+//! carries the new id, rather than when the old connection eventually fails.
+
+use std::{sync::Mutex, time::Duration};
+
+use iroh::EndpointId;
+use ttl_cache::TtlCache;
+
+const CACHE_CAPACITY: usize = 16384;
+/// Hostnames not seen for this long are forgotten; the next request is
+/// treated as a first sighting rather than a switch.
+const ENTRY_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(derive_more::Debug)]
+pub(super) struct EndpointSwitches {
+    #[debug(skip)]
+    last_seen: Mutex<TtlCache<String, EndpointId>>,
+}
+
+impl EndpointSwitches {
+    pub(super) fn new() -> Self {
+        Self {
+            last_seen: Mutex::new(TtlCache::new(CACHE_CAPACITY)),
+        }
+    }
+
+    /// Record that `host` was routed to `endpoint_id`.
+    ///
+    /// Returns the previous endpoint if the hostname moved.
+    pub(super) fn observe(&self, host: &str, endpoint_id: EndpointId) -> Option<EndpointId> {
+        let host = host.to_ascii_lowercase();
+        let mut last_seen = self.last_seen.lock().expect("poisoned");
+        let previous = last_seen.insert(host, endpoint_id, ENTRY_TTL);
+        previous.filter(|previous| *previous != endpoint_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn reports_only_actual_switches() {
+        let blue = SecretKey::generate(&mut rand::rng()).public();
+        let green = SecretKey::generate(&mut rand::rng()).public();
+        let switches = EndpointSwitches::new();
+
+        assert_eq!(switches.observe("app.example.com", blue), None);
+        assert_eq!(switches.observe("APP.example.com", blue), None);
+        assert_eq!(switches.observe("app.example.com", green), Some(blue));
+        assert_eq!(switches.observe("app.example.com", green), None);
+        assert_eq!(switches.observe("other.example.com", blue), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Where [`TicketClient`](super::TicketClient) keeps codename lookups.
+//!
+//! Each gateway process caches lookups in memory by default. Replicas behind
+//! one load balancer can share them through Redis instead, with
+//! `tickets.redis_url` and the `redis` feature, so a codename is fetched from
+//! n0des once per TTL for the whole fleet rather than once per replica.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use n0_error::Result;
+use n0_future::boxed::BoxFuture;
+use ttl_cache::TtlCache;
+
+use crate::AdvertismentTicket;
+
+const CACHE_CAPACITY: usize = 4096;
+
+/// The result of one fetch from n0des.
+#[derive(Debug, Clone)]
+pub struct CachedTicket {
+    /// `None` if nothing is published under the codename.
+    pub ticket: Option<AdvertismentTicket>,
+    pub fetched_at: SystemTime,
+}
+
+impl CachedTicket {
+    pub fn new(ticket: Option<AdvertismentTicket>) -> Self {
+        Self {
+            ticket,
+            fetched_at: SystemTime::now(),
+        }
+    }
+
+    /// How long ago the ticket was fetched.
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed().unwrap_or_default()
+    }
+}
+
+/// A store for codename lookups that expires entries after their TTL.
+///
+/// Errors are logged by the caller and treated as a miss, so a cache outage
+/// only sends more lookups to n0des.
+pub trait TicketCache: std::fmt::Debug + Send + Sync + 'static {
+    fn get(&self, codename: &str) -> BoxFuture<Result<Option<CachedTicket>>>;
+
+    fn put(&self, codename: &str, entry: CachedTicket, ttl: Duration) -> BoxFuture<Result<()>>;
+
+    fn remove(&self, codename: &str) -> BoxFuture<Result<()>>;
+}
+
+/// Lookups cached in this process.
+#[derive(Debug, Clone)]
+pub struct MemoryTicketCache {
+    entries: Arc<Mutex<TtlCache<String, CachedTicket>>>,
+}
+
+impl Default for MemoryTicketCache {
+    fn default() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(TtlCache::new(CACHE_CAPACITY))),
+        }
+    }
+}
+
+impl TicketCache for MemoryTicketCache {
+    fn get(&self, codename: &str) -> BoxFuture<Result<Option<CachedTicket>>> {
+        let entry = self
+            .entries
+            .lock()
+            .expect("poisoned")
+            .get(codename)
+            .cloned();
+        Box::pin(async move { Ok(entry) })
+    }
+
+    fn put(&self, codename: &str, entry: CachedTicket, ttl: Duration) -> BoxFuture<Result<()>> {
+        self.entries
+            .lock()
+            .expect("poisoned")
+            .insert(codename.to_string(), entry, ttl);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn remove(&self, codename: &str) -> BoxFuture<Result<()>> {
+        self.entries.lock().expect("poisoned").remove(codename);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_cache::RedisTicketCache;
+
+#[cfg(feature = "redis")]
+mod redis_cache {
+    use std::time::{Duration, SystemTime};
+
+    use n0_error::{Result, StdResultExt};
+    use n0_future::boxed::BoxFuture;
+    use redis::{AsyncCommands, aio::ConnectionManager};
+
+    use super::{CachedTicket, TicketCache};
+    use crate::AdvertismentTicket;
+
+    const KEY_PREFIX: &str = "datum-connect:ticket:";
+
+    /// Lookups shared through Redis.
+    ///
+    /// An entry is stored as `<fetched at, in ms since the epoch>:<ticket>`,
+    /// with nothing after the colon for a codename without a ticket.
+    #[derive(derive_more::Debug, Clone)]
+    pub struct RedisTicketCache {
+        #[debug(skip)]
+        conn: ConnectionManager,
+    }
+
+    impl RedisTicketCache {
+        /// Connect to the Redis server at `url`, like `redis://cache:6379/0`.
+        pub async fn connect(url: &str) -> Result<Self> {
+            let client = redis::Client::open(url).std_context("Invalid Redis URL")?;
+            let conn = ConnectionManager::new(client)
+                .await
+                .std_context("Failed to connect to Redis")?;
+            Ok(Self { conn })
+        }
+    }
+
+    impl TicketCache for RedisTicketCache {
+        fn get(&self, codename: &str) -> BoxFuture<Result<Option<CachedTicket>>> {
+            let mut conn = self.conn.clone();
+            let key = format!("{KEY_PREFIX}{codename}");
+            Box::pin(async move {
+                let value: Option<String> = conn.get(&key).await.std_context("Redis GET failed")?;
+                value.map(|value| decode(&value)).transpose()
+            })
+        }
+
+        fn put(&self, codename: &str, entry: CachedTicket, ttl: Duration) -> BoxFuture<Result<()>> {
+            let mut conn = self.conn.clone();
+            let key = format!("{KEY_PREFIX}{codename}");
+            Box::pin(async move {
+                let ttl_ms = ttl.as_millis().max(1) as u64;
+                conn.pset_ex::<_, _, ()>(&key, encode(&entry), ttl_ms)
+                    .await
+                    .std_context("Redis SET failed")
+            })
+        }
+
+        fn remove(&self, codename: &str) -> BoxFuture<Result<()>> {
+            let mut conn = self.conn.clone();
+            let key = format!("{KEY_PREFIX}{codename}");
+            Box::pin(async move {
+                conn.del::<_, ()>(&key)
+                    .await
+                    .std_context("Redis DEL failed")
+            })
+        }
+    }
+
+    fn encode(entry: &CachedTicket) -> String {
+        let fetched_at = entry
+            .fetched_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        match &entry.ticket {
+            Some(ticket) => format!("{fetched_at}:{ticket}"),
+            None => format!("{fetched_at}:"),
+        }
+    }
+
+    fn decode(value: &str) -> Result<CachedTicket> {
+        let (fetched_at, ticket) = value.split_once(':').std_context("Invalid cached ticket")?;
+        let fetched_at = fetched_at
+            .parse::<u64>()
+            .std_context("Invalid cached ticket time")?;
+        let ticket = match ticket {
+            "" => None,
+            ticket => Some(
+                ticket
+                    .parse::<AdvertismentTicket>()
+                    .std_context("Invalid cached ticket")?,
+            ),
+        };
+        Ok(CachedTicket {
+            ticket,
+            fetched_at: SystemTime::UNIX_EPOCH + Duration::from_millis(fetched_at),
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{Advertisment, TcpProxyData};
+
+        #[test]
+        fn entries_round_trip() {
+            let data = TcpProxyData::from_host_port_str("127.0.0.1:8080").unwrap();
+            let endpoint = iroh::SecretKey::from_bytes(&[7u8; 32]).public();
+            let ticket = Advertisment::with_id("proxy-abc".into(), data, None)
+                .ticket(endpoint)
+                .published_now();
+            for ticket in [Some(ticket), None] {
+                let entry = CachedTicket::new(ticket);
+                let decoded = decode(&encode(&entry)).unwrap();
+                assert_eq!(
+                    decoded.ticket.map(|t| t.to_string()),
+                    entry.ticket.map(|t| t.to_string())
+                );
+                assert!(decoded.age() < Duration::from_secs(5));
+            }
+            assert!(decode("garbage").is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_cache_expires_entries() -> Result<()> {
+        let cache = MemoryTicketCache::default();
+        cache
+            .put("a", CachedTicket::new(None), Duration::from_secs(60))
+            .await?;
+        cache
+            .put("b", CachedTicket::new(None), Duration::from_millis(1))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(cache.get("a").await?.is_some());
+        assert!(cache.get("b").await?.is_none());
+        cache.remove("a").await?;
+        assert!(cache.get("a").await?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Ticket changes pushed by n0des.
+//!
+//! A gateway with `tickets.subscribe` keeps a stream open to n0des on
+//! [`TICKET_EVENTS_ALPN`] and drops the cached lookup of every codename whose
+//! ticket is published or unpublished, so the next request for it fetches
+//! the current ticket. Events are length-prefixed JSON, like the reverse
+//! forward messages. `n0des-local` serves the ALPN next to the n0des
+//! protocol, with its own copy of these types since it can't depend on this
+//! crate.
+//!
+//! Events sent while the stream is down are lost, and n0des deployments that
+//! don't serve the ALPN refuse the dial. Either way the cache TTLs still
+//! bound how long an outdated lookup is served, and the stream is dialed
+//! again after [`RETRY_INTERVAL`].
+
+use std::{sync::Arc, time::Duration};
+
+use iroh::{Endpoint, EndpointAddr};
+use iroh_tickets::Ticket;
+use n0_error::{Result, StdResultExt, anyerr};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::debug;
+
+use super::{metrics::GatewayMetrics, ticket_cache::TicketCache};
+use crate::{AdvertismentTicket, log_limit::warn_limited};
+
+pub const TICKET_EVENTS_ALPN: &[u8] = b"datum-connect/ticket-events/0";
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+/// Sent once by the gateway after opening the stream.
+#[derive(Debug, Serialize, Deserialize)]
+struct Subscribe {
+    ticket_kind: String,
+}
+
+/// A ticket of the subscribed kind changed.
+#[derive(Debug, Serialize, Deserialize)]
+struct TicketEvent {
+    name: String,
+    /// `false` if the ticket was unpublished.
+    published: bool,
+}
+
+/// Drop cached lookups as n0des reports ticket changes, until aborted.
+pub(super) async fn run(
+    endpoint: Endpoint,
+    remote: EndpointAddr,
+    cache: Arc<dyn TicketCache>,
+    metrics: Arc<GatewayMetrics>,
+) {
+    loop {
+        if let Err(err) = subscribe(&endpoint, remote.clone(), &cache, &metrics).await {
+            warn_limited!(
+                "gateway.ticket_events",
+                "ticket event stream failed: {err:#}"
+            );
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+async fn subscribe(
+    endpoint: &Endpoint,
+    remote: EndpointAddr,
+    cache: &Arc<dyn TicketCache>,
+    metrics: &GatewayMetrics,
+) -> Result<()> {
+    let conn = endpoint
+        .connect(remote, TICKET_EVENTS_ALPN)
+        .await
+        .std_context("Failed to connect to n0des")?;
+    let (mut send, mut recv) = conn.open_bi().await.anyerr()?;
+    let request = Subscribe {
+        ticket_kind: AdvertismentTicket::KIND.to_string(),
+    };
+    let request = serde_json::to_vec(&request).anyerr()?;
+    send.write_u32(request.len() as u32).await?;
+    send.write_all(&request).await?;
+    send.finish().anyerr()?;
+    debug!("subscribed to ticket events");
+
+    loop {
+        let len = recv.read_u32().await? as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(anyerr!("Ticket event too large: {len} bytes"));
+        }
+        let mut data = vec![0; len];
+        recv.read_exact(&mut data).await?;
+        let event: TicketEvent =
+            serde_json::from_slice(&data).std_context("Invalid ticket event")?;
+        let codename = event.name;
+        debug!(%codename, published = event.published, "ticket changed");
+        metrics.inc_ticket_events();
+        if let Err(err) = cache.remove(&codename).await {
+            warn_limited!("gateway.ticket_cache", %codename, "dropping cached ticket failed: {err:#}");
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Codename lookups through n0des.
+//!
+//! Envoy normally names the endpoint of a request in `x-iroh-endpoint-id`.
+//! With `tickets.enabled`, an origin request without that header for
+//! `<codename>.<domain>` is routed by the ticket the agent published to n0des
+//! under the codename instead: to the ticket's endpoint and target.
+//!
+//! Lookups are cached in a [`TicketCache`], in process unless the gateway is
+//! given a shared one. A ticket is served for `ttl_secs`, then for
+//! another `stale_secs` while one background fetch refreshes it, so busy
+//! codenames never wait on n0des. A codename without a ticket is remembered
+//! for `negative_ttl_secs`. Concurrent misses for one codename share a single
+//! fetch. When the endpoint of a cached ticket fails `max_connect_failures`
+//! liveness dials in a row, the ticket is dropped and the next request
+//! fetches it again, in case the agent came back under another ticket.
+//!
+//! Agents publish their tickets again every few seconds with the current
+//! time. A ticket older than `max_ticket_age_secs` belongs to an agent that
+//! stopped, and the gateway answers 503 for it without dialing. Such a ticket
+//! is fetched again at most every `negative_ttl_secs`, to notice the agent
+//! coming back.
+//!
+//! With `tickets.subscribe`, n0des also pushes ticket changes to the
+//! gateway, which drops the cached lookup so the next request fetches the
+//! current ticket. See [`ticket_events`](super::ticket_events).
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use iroh::{Endpoint, EndpointAddr, EndpointId};
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use tracing::{Instrument, debug, error_span};
+
+use super::{
+    metrics::{GatewayMetrics, shared_gateway_metrics},
+    ticket_cache::{CachedTicket, MemoryTicketCache, TicketCache},
+    ticket_events,
+    verification::normalize_host,
+};
+use crate::{AdvertismentTicket, config::GatewayConfig, log_limit::warn_limited};
+
+/// How a lookup was answered, as counted in
+/// `iroh_gateway_ticket_cache_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TicketLookup {
+    Hit,
+    /// A ticket past `ttl_secs`, served while it is fetched again.
+    Stale,
+    /// A cached absence of a ticket.
+    Negative,
+    Miss,
+}
+
+/// Resolves codenames to the tickets agents published for them.
+#[derive(derive_more::Debug, Clone)]
+pub struct TicketClient {
+    #[debug(skip)]
+    n0des: Arc<iroh_n0des::Client>,
+    domains: Vec<String>,
+    ttl: Duration,
+    stale: Duration,
+    negative_ttl: Duration,
+    max_connect_failures: u32,
+    max_ticket_age: Duration,
+    require_published_at: bool,
+    #[debug(skip)]
+    metrics: Arc<GatewayMetrics>,
+    cache: Arc<dyn TicketCache>,
+    /// Fetches in flight, so a burst of requests for one codename sends one
+    /// request to n0des.
+    #[debug(skip)]
+    fetches: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    /// Codenames with a background refresh in flight.
+    #[debug(skip)]
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    /// Dials in a row that failed, by codename.
+    #[debug(skip)]
+    failures: Arc<Mutex<HashMap<String, u32>>>,
+    #[debug(skip)]
+    _events_task: Option<Arc<AbortOnDropHandle<()>>>,
+}
+
+impl TicketClient {
+    pub fn new(n0des: Arc<iroh_n0des::Client>, config: &GatewayConfig) -> Self {
+        let tickets = &config.tickets;
+        Self {
+            n0des,
+            domains: tickets
+                .domains
+                .iter()
+                .map(|d| d.trim_matches('.').to_ascii_lowercase())
+                .collect(),
+            ttl: Duration::from_secs(tickets.ttl_secs),
+            stale: Duration::from_secs(tickets.stale_secs),
+            negative_ttl: Duration::from_secs(tickets.negative_ttl_secs),
+            max_connect_failures: tickets.max_connect_failures,
+            max_ticket_age: Duration::from_secs(tickets.max_ticket_age_secs),
+            require_published_at: tickets.require_published_at,
+            metrics: shared_gateway_metrics(&config.metrics),
+            cache: Arc::new(MemoryTicketCache::default()),
+            fetches: Default::default(),
+            refreshing: Default::default(),
+            failures: Default::default(),
+            _events_task: None,
+        }
+    }
+
+    /// Keep lookups in `cache`, for example one shared by all replicas,
+    /// instead of in this process.
+    pub fn with_cache(mut self, cache: Arc<dyn TicketCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Drop cached lookups when n0des at `remote` reports that their ticket
+    /// changed. Call this after [`Self::with_cache`].
+    pub fn subscribe(mut self, endpoint: Endpoint, remote: EndpointAddr) -> Self {
+        let task = tokio::spawn(
+            ticket_events::run(endpoint, remote, self.cache.clone(), self.metrics.clone())
+                .instrument(error_span!("ticket-events")),
+        );
+        self._events_task = Some(Arc::new(AbortOnDropHandle::new(task)));
+        self
+    }
+
+    /// The codename of `host` if it is one label below a configured domain.
+    ///
+    /// `host` may include a port, which is ignored.
+    pub(super) fn codename(&self, host: &str) -> Option<String> {
+        codename(host, &self.domains)
+    }
+
+    /// The ticket published under `codename`, or `None` if there is none.
+    pub(super) async fn get(&self, codename: &str) -> Result<Option<AdvertismentTicket>> {
+        if let Some(lookup) = self.cached(codename).await {
+            self.count(codename, &lookup);
+            return Ok(lookup.ticket);
+        }
+
+        let fetch = self
+            .fetches
+            .lock()
+            .expect("poisoned")
+            .entry(codename.to_string())
+            .or_default()
+            .clone();
+        let _guard = fetch.lock().await;
+        // Whoever held the lock before us may have fetched it already.
+        let res = match self.cached(codename).await {
+            Some(lookup) => {
+                self.count(codename, &lookup);
+                Ok(lookup.ticket)
+            }
+            None => {
+                self.metrics.inc_ticket_cache(TicketLookup::Miss);
+                self.fetch(codename).await.map(|lookup| lookup.ticket)
+            }
+        };
+        self.fetches.lock().expect("poisoned").remove(codename);
+        res
+    }
+
+    /// Fetch the ticket published under `codename` from n0des, skipping
+    /// the cache, and cache it.
+    pub(super) async fn refetch(&self, codename: &str) -> Result<Option<AdvertismentTicket>> {
+        self.fetch(codename).await.map(|lookup| lookup.ticket)
+    }
+
+    /// Whether the agent behind `ticket` recently published it.
+    pub(super) fn is_fresh(&self, ticket: &AdvertismentTicket) -> bool {
+        match ticket.age() {
+            Some(age) => age <= self.max_ticket_age,
+            None => !self.require_published_at,
+        }
+    }
+
+    /// The cached lookup for `codename`, unless it holds an outdated ticket
+    /// that is due to be fetched again.
+    async fn cached(&self, codename: &str) -> Option<CachedTicket> {
+        let lookup = match self.cache.get(codename).await {
+            Ok(lookup) => lookup?,
+            Err(err) => {
+                warn_limited!("gateway.ticket_cache", %codename, "ticket cache lookup failed: {err:#}");
+                return None;
+            }
+        };
+        let outdated = lookup
+            .ticket
+            .as_ref()
+            .is_some_and(|ticket| !self.is_fresh(ticket));
+        if outdated && lookup.age() > self.negative_ttl {
+            return None;
+        }
+        Some(lookup)
+    }
+
+    /// Count a cached lookup, and refresh it in the background if it is
+    /// stale.
+    fn count(&self, codename: &str, lookup: &CachedTicket) {
+        let result = if lookup.ticket.is_none() {
+            TicketLookup::Negative
+        } else if lookup.age() > self.ttl {
+            TicketLookup::Stale
+        } else {
+            TicketLookup::Hit
+        };
+        self.metrics.inc_ticket_cache(result);
+        if result == TicketLookup::Stale {
+            self.refresh_in_background(codename);
+        }
+    }
+
+    fn refresh_in_background(&self, codename: &str) {
+        if !self
+            .refreshing
+            .lock()
+            .expect("poisoned")
+            .insert(codename.to_string())
+        {
+            return;
+        }
+        let this = self.clone();
+        let codename = codename.to_string();
+        tokio::spawn(async move {
+            this.fetch(&codename).await.ok();
+            this.refreshing.lock().expect("poisoned").remove(&codename);
+        });
+    }
+
+    /// Fetch the ticket for `codename` from n0des and cache the result.
+    async fn fetch(&self, codename: &str) -> Result<CachedTicket> {
+        let ticket = self
+            .n0des
+            .fetch_ticket::<AdvertismentTicket>(codename.to_string())
+            .await
+            .inspect_err(|err| {
+                debug!(%codename, "ticket fetch failed: {err:#}");
+                self.metrics.inc_ticket_fetch_errors();
+            })
+            .std_context("Failed to fetch ticket")?
+            .map(|published| published.ticket);
+        let ttl = match ticket {
+            Some(_) => self.ttl + self.stale,
+            None => self.negative_ttl,
+        };
+        let lookup = CachedTicket::new(ticket);
+        if let Err(err) = self.cache.put(codename, lookup.clone(), ttl).await {
+            warn_limited!("gateway.ticket_cache", %codename, "caching ticket failed: {err:#}");
+        }
+        Ok(lookup)
+    }
+
+    /// Record whether a dial to `endpoint_id`, taken from the ticket of
+    /// `codename`, succeeded. Enough failures in a row drop the ticket.
+    pub(super) async fn record_dial(
+        &self,
+        codename: &str,
+        endpoint_id: EndpointId,
+        reachable: bool,
+    ) {
+        {
+            let mut failures = self.failures.lock().expect("poisoned");
+            if reachable {
+                failures.remove(codename);
+                return;
+            }
+            let count = failures.entry(codename.to_string()).or_default();
+            *count += 1;
+            if *count < self.max_connect_failures {
+                return;
+            }
+            failures.remove(codename);
+        }
+        let cached = self
+            .cached(codename)
+            .await
+            .and_then(|lookup| lookup.ticket)
+            .map(|ticket| ticket.endpoint);
+        if cached == Some(endpoint_id) {
+            debug!(%codename, endpoint_id = %endpoint_id.fmt_short(), "dropping ticket after failed dials");
+            self.invalidate(codename).await;
+            self.metrics.inc_ticket_invalidations();
+        }
+    }
+
+    /// Drop the cached lookup for `codename`.
+    pub(super) async fn invalidate(&self, codename: &str) {
+        if let Err(err) = self.cache.remove(codename).await {
+            warn_limited!("gateway.ticket_cache", %codename, "dropping cached ticket failed: {err:#}");
+        }
+    }
+}
+
+fn codename(host: &str, domains: &[String]) -> Option<String> {
+    let host = normalize_host(host);
+    domains.iter().find_map(|domain| {
+        let label = host.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+        (!label.is_empty() && !label.contains('.')).then(|| label.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::{Endpoint, SecretKey};
+
+    use super::*;
+    use crate::{Advertisment, TcpProxyData, config::TicketsConfig, node::build_n0des_client};
+
+    async fn client(api_secret: iroh_n0des::ApiSecret) -> Result<Arc<iroh_n0des::Client>> {
+        let endpoint = Endpoint::bind().await?;
+        build_n0des_client(&endpoint, api_secret).await
+    }
+
+    fn config(negative_ttl_secs: u64, max_connect_failures: u32) -> GatewayConfig {
+        GatewayConfig {
+            tickets: TicketsConfig {
+                enabled: true,
+                domains: vec!["localhost".to_string()],
+                negative_ttl_secs,
+                max_connect_failures,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn codename_is_one_label_below_a_domain() {
+        let domains = vec!["iroh.datum.net".to_string()];
+        assert_eq!(
+            codename("Proxy-Abc.iroh.datum.net:443", &domains).as_deref(),
+            Some("proxy-abc")
+        );
+        assert_eq!(codename("a.b.iroh.datum.net", &domains), None);
+        assert_eq!(codename("iroh.datum.net", &domains), None);
+        assert_eq!(codename("example.com", &domains), None);
+    }
+
+    #[tokio::test]
+    async fn caches_tickets_and_their_absence() -> Result<()> {
+        let (api_secret, _router) = n0des_local::bind_and_start().await?;
+        let publisher = client(api_secret.clone()).await?;
+        let tickets = TicketClient::new(client(api_secret).await?, &config(60, 3));
+
+        assert!(tickets.get("proxy-abc").await?.is_none());
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+        let advertisment = Advertisment::with_id("proxy-abc".into(), data, None);
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+        publisher
+            .publish_ticket("proxy-abc".into(), advertisment.ticket(endpoint_id))
+            .await
+            .anyerr()?;
+        // Still the cached absence.
+        assert!(tickets.get("proxy-abc").await?.is_none());
+
+        tickets.invalidate("proxy-abc").await;
+        let ticket = tickets.get("proxy-abc").await?.expect("published");
+        assert_eq!(ticket.endpoint, endpoint_id);
+        publisher
+            .unpublish_ticket::<AdvertismentTicket>("proxy-abc".into())
+            .await
+            .anyerr()?;
+        // Served from the cache until it expires.
+        assert!(tickets.get("proxy-abc").await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pushed_changes_drop_cached_lookups() -> Result<()> {
+        let (api_secret, _router) = n0des_local::bind_and_start().await?;
+        let publisher = client(api_secret.clone()).await?;
+        let endpoint = Endpoint::bind().await?;
+        let remote = api_secret.remote.clone();
+        let n0des = build_n0des_client(&endpoint, api_secret).await?;
+        let tickets = TicketClient::new(n0des, &config(60, 3)).subscribe(endpoint, remote);
+
+        // Give the subscription time to come up.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(tickets.get("proxy-abc").await?.is_none());
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+        let advertisment = Advertisment::with_id("proxy-abc".into(), data, None);
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+        publisher
+            .publish_ticket("proxy-abc".into(), advertisment.ticket(endpoint_id))
+            .await
+            .anyerr()?;
+        // The cached absence is dropped long before it expires.
+        let ticket = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(ticket) = tickets.get("proxy-abc").await? {
+                    return Ok::<_, n0_error::AnyError>(ticket);
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .anyerr()??;
+        assert_eq!(ticket.endpoint, endpoint_id);
+
+        publisher
+            .unpublish_ticket::<AdvertismentTicket>("proxy-abc".into())
+            .await
+            .anyerr()?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while tickets.get("proxy-abc").await?.is_some() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            Ok::<_, n0_error::AnyError>(())
+        })
+        .await
+        .anyerr()??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replicas_share_lookups_through_the_cache() -> Result<()> {
+        let (api_secret, _router) = n0des_local::bind_and_start().await?;
+        let publisher = client(api_secret.clone()).await?;
+        let cache = Arc::new(MemoryTicketCache::default());
+        let first = TicketClient::new(client(api_secret.clone()).await?, &config(60, 3))
+            .with_cache(cache.clone());
+        let second = TicketClient::new(client(api_secret).await?, &config(60, 3)).with_cache(cache);
+
+        assert!(first.get("proxy-abc").await?.is_none());
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+        let advertisment = Advertisment::with_id("proxy-abc".into(), data, None);
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+        publisher
+            .publish_ticket("proxy-abc".into(), advertisment.ticket(endpoint_id))
+            .await
+            .anyerr()?;
+        // The second replica sees the absence the first one cached.
+        assert!(second.get("proxy-abc").await?.is_none());
+        second.invalidate("proxy-abc").await;
+        assert!(first.get("proxy-abc").await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn outdated_tickets_are_fetched_again() -> Result<()> {
+        let (api_secret, _router) = n0des_local::bind_and_start().await?;
+        let publisher = client(api_secret.clone()).await?;
+        let tickets = TicketClient::new(client(api_secret).await?, &config(0, 3));
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+        let advertisment = Advertisment::with_id("proxy-abc".into(), data, None);
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+
+        let mut outdated = advertisment.ticket(endpoint_id).published_now();
+        outdated.published_at = outdated.published_at.map(|at| at - 3600);
+        publisher
+            .publish_ticket("proxy-abc".into(), outdated)
+            .await
+            .anyerr()?;
+        let ticket = tickets.get("proxy-abc").await?.expect("published");
+        assert!(!tickets.is_fresh(&ticket));
+        assert!(tickets.is_fresh(&advertisment.ticket(endpoint_id)));
+
+        publisher
+            .publish_ticket(
+                "proxy-abc".into(),
+                advertisment.ticket(endpoint_id).published_now(),
+            )
+            .await
+            .anyerr()?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let ticket = tickets.get("proxy-abc").await?.expect("published");
+        assert!(tickets.is_fresh(&ticket));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drops_ticket_after_repeated_dial_failures() -> Result<()> {
+        let (api_secret, _router) = n0des_local::bind_and_start().await?;
+        let publisher = client(api_secret.clone()).await?;
+        let tickets = TicketClient::new(client(api_secret).await?, &config(5, 2));
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+        let advertisment = Advertisment::with_id("proxy-abc".into(), data, None);
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public();
+        publisher
+            .publish_ticket("proxy-abc".into(), advertisment.ticket(endpoint_id))
+            .await
+            .anyerr()?;
+        assert!(tickets.get("proxy-abc").await?.is_some());
+
+        tickets.record_dial("proxy-abc", endpoint_id, false).await;
+        tickets.record_dial("proxy-abc", endpoint_id, true).await;
+        tickets.record_dial("proxy-abc", endpoint_id, false).await;
+        assert!(tickets.cached("proxy-abc").await.is_some());
+        tickets.record_dial("proxy-abc", endpoint_id, false).await;
+        assert!(tickets.cached("proxy-abc").await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Upstream response times on the gateway's TCP listener.
+//!
+//! iroh-proxy-utils doesn't say when a response starts or ends, so with
+//! `metrics.upstream_timing` the gateway relays each TCP connection to the
+//! proxy over loopback, like the [PROXY protocol relay](super::proxy_protocol),
+//! and watches the bytes go by. HTTP/1.1 connections take turns: a request
+//! starts when the client sends after the previous response began, the first
+//! byte back is its time to first byte, and it ends with the last byte back
+//! before the next request or the close. CONNECT tunnels and HTTP/2 carry
+//! opaque or interleaved streams, so only their first response is timed, and
+//! only to its first byte.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use n0_error::{Result, StdResultExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::debug;
+
+use super::metrics::{GatewayMetrics, RouteKind};
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0";
+
+/// The request/response turns of one connection.
+#[derive(Debug, Default)]
+struct Turns {
+    /// Set by the first bytes of the client.
+    kind: Option<RouteKind>,
+    /// Time only the first response, to its first byte.
+    first_only: bool,
+    /// Start of the current request, and the last response byte so far.
+    request: Option<(Instant, Option<Instant>)>,
+    done: bool,
+}
+
+impl Turns {
+    fn client_sent(&mut self, data: &[u8], now: Instant, metrics: &GatewayMetrics) {
+        if self.done {
+            return;
+        }
+        let kind = *self.kind.get_or_insert_with(|| {
+            let connect = data.starts_with(b"CONNECT ");
+            self.first_only = connect || data.starts_with(HTTP2_PREFACE);
+            if connect {
+                RouteKind::Tunnel
+            } else {
+                RouteKind::Origin
+            }
+        });
+        match self.request {
+            // More of a request whose response hasn't started.
+            Some((_, None)) => {}
+            Some((start, Some(last_byte))) => {
+                metrics.observe_request_duration(kind, last_byte - start);
+                self.request = Some((now, None));
+            }
+            None => self.request = Some((now, None)),
+        }
+    }
+
+    fn upstream_sent(&mut self, now: Instant, metrics: &GatewayMetrics) {
+        let (Some(kind), Some((start, last_byte))) = (self.kind, self.request.as_mut()) else {
+            return;
+        };
+        if self.done {
+            return;
+        }
+        if last_byte.is_none() {
+            metrics.observe_upstream_ttfb(kind, now - *start);
+            self.done = self.first_only;
+        }
+        *last_byte = Some(now);
+    }
+
+    /// The connection closed; its last response ended with its last byte.
+    fn finish(&mut self, metrics: &GatewayMetrics) {
+        if let (false, Some(kind), Some((start, Some(last_byte)))) =
+            (self.done, self.kind, self.request)
+        {
+            metrics.observe_request_duration(kind, last_byte - start);
+        }
+        self.done = true;
+    }
+}
+
+/// Accept connections on `listener` and relay them to `inner`, timing the
+/// responses.
+pub(super) async fn serve(listener: TcpListener, inner: SocketAddr, metrics: Arc<GatewayMetrics>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                debug!("failed to accept connection: {err:#}");
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let res = async {
+                let upstream = TcpStream::connect(inner)
+                    .await
+                    .std_context("Failed to connect to the gateway listener")?;
+                relay(stream, upstream, &metrics).await
+            };
+            if let Err(err) = res.await {
+                debug!(%peer, "timed connection failed: {err:#}");
+            }
+        });
+    }
+}
+
+/// Relay `client` to `upstream` until both are done, recording the response
+/// times in `metrics`.
+pub(super) async fn relay(
+    client: TcpStream,
+    upstream: TcpStream,
+    metrics: &GatewayMetrics,
+) -> Result<()> {
+    let turns = Mutex::new(Turns::default());
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut upstream_read, mut upstream_write) = upstream.into_split();
+    let to_upstream = pump(&mut client_read, &mut upstream_write, |data| {
+        let mut turns = turns.lock().expect("poisoned");
+        turns.client_sent(data, Instant::now(), metrics);
+    });
+    let to_client = pump(&mut upstream_read, &mut client_write, |_| {
+        let mut turns = turns.lock().expect("poisoned");
+        turns.upstream_sent(Instant::now(), metrics);
+    });
+    let res = tokio::try_join!(to_upstream, to_client);
+    turns.lock().expect("poisoned").finish(metrics);
+    res.std_context("Failed to relay connection")?;
+    Ok(())
+}
+
+/// Copy `reader` to `writer`, showing each chunk to `observe` first, and
+/// shut `writer` down at the end.
+async fn pump(
+    reader: &mut (impl AsyncRead + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    mut observe: impl FnMut(&[u8]),
+) -> std::io::Result<()> {
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        observe(&buf[..n]);
+        writer.write_all(&buf[..n]).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::GatewayMetricsConfig;
+
+    #[test]
+    fn times_each_turn() {
+        let metrics = GatewayMetrics::new(&GatewayMetricsConfig::default());
+        let t0 = Instant::now();
+        let at = |ms| t0 + Duration::from_millis(ms);
+
+        // Two keep-alive requests, the first with a body in two chunks.
+        let mut turns = Turns::default();
+        turns.client_sent(b"POST / HTTP/1.1\r\n", at(0), &metrics);
+        turns.client_sent(b"body", at(5), &metrics);
+        turns.upstream_sent(at(20), &metrics);
+        turns.upstream_sent(at(30), &metrics);
+        turns.client_sent(b"GET / HTTP/1.1\r\n", at(40), &metrics);
+        assert_eq!(metrics.request_durations(RouteKind::Origin), (1, 1));
+        turns.upstream_sent(at(50), &metrics);
+        turns.finish(&metrics);
+        assert_eq!(metrics.request_durations(RouteKind::Origin), (2, 2));
+
+        // A tunnel is timed to the first byte of its first response only.
+        let mut turns = Turns::default();
+        turns.client_sent(b"CONNECT db:5432 HTTP/1.1\r\n", at(0), &metrics);
+        turns.upstream_sent(at(10), &metrics);
+        turns.client_sent(b"\x00\x01", at(20), &metrics);
+        turns.upstream_sent(at(30), &metrics);
+        turns.finish(&metrics);
+        assert_eq!(metrics.request_durations(RouteKind::Tunnel), (1, 0));
+
+        // A connection closed before any response records nothing.
+        let mut turns = Turns::default();
+        turns.client_sent(b"GET / HTTP/1.1\r\n", at(0), &metrics);
+        turns.finish(&metrics);
+        assert_eq!(metrics.request_durations(RouteKind::Origin), (2, 2));
+    }
+}
@@ -0,0 +1,259 @@
+//! Network paths to the endpoints the gateway has recently forwarded to.
+//!
+//! The QUIC connections themselves are pooled inside iroh-proxy-utils, which
+//! does not hand them out, so path details come from the gateway's endpoint:
+//! whether the path is direct or relayed and the round-trip time iroh has
+//! measured. Congestion window and loss are per-connection statistics and
+//! can't be read from here.
+//!
+//! Requests forwarded to each endpoint and the times it was skipped as
+//! unreachable are counted here too, so replicas of a tunnel can be compared.
+
+use std::{
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use iroh::{Endpoint, EndpointId};
+use prometheus_client::{collector::Collector, encoding::DescriptorEncoder};
+use serde::Serialize;
+use ttl_cache::TtlCache;
+
+use super::metrics::{encode_counters, encode_gauges};
+use crate::{PathInfo, PathKind};
+
+const CACHE_CAPACITY: usize = 1024;
+/// Endpoints that got no request for this long drop out of the report.
+const ENTRY_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(derive_more::Debug)]
+pub(super) struct UpstreamPaths {
+    #[debug(skip)]
+    entries: Mutex<TtlCache<EndpointId, Entry>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    last_request: Option<Instant>,
+    requests: u64,
+    unreachable: u64,
+}
+
+impl Default for UpstreamPaths {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(TtlCache::new(CACHE_CAPACITY)),
+        }
+    }
+}
+
+impl UpstreamPaths {
+    /// Record that a request was forwarded to `endpoint_id`.
+    pub(super) fn observe(&self, endpoint_id: EndpointId) {
+        self.update(endpoint_id, |entry| {
+            entry.last_request = Some(Instant::now());
+            entry.requests += 1;
+        });
+    }
+
+    /// Record that `endpoint_id` was skipped because it is unreachable.
+    pub(super) fn observe_unreachable(&self, endpoint_id: EndpointId) {
+        self.update(endpoint_id, |entry| entry.unreachable += 1);
+    }
+
+    fn update(&self, endpoint_id: EndpointId, f: impl FnOnce(&mut Entry)) {
+        let mut entries = self.entries.lock().expect("poisoned");
+        let mut entry = entries.remove(&endpoint_id).unwrap_or_default();
+        f(&mut entry);
+        entries.insert(endpoint_id, entry, ENTRY_TTL);
+    }
+
+    /// The current path to each recently used endpoint, by endpoint id.
+    pub(super) fn snapshot(&self, endpoint: &Endpoint) -> Vec<UpstreamPath> {
+        let seen = self
+            .entries
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(id, entry)| (*id, *entry))
+            .collect::<Vec<_>>();
+        let mut paths = seen
+            .into_iter()
+            .map(|(id, entry)| UpstreamPath::new(id, PathInfo::for_remote(endpoint, id), entry))
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.endpoint_id.cmp(&b.endpoint_id));
+        paths
+    }
+}
+
+/// One row of the `/upstreams` report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(super) struct UpstreamPath {
+    pub(super) endpoint_id: String,
+    /// `direct`, `relay`, `mixed`, or `none` when no path is known.
+    pub(super) path: &'static str,
+    pub(super) rtt_ms: Option<f64>,
+    /// Seconds since the gateway last forwarded a request to the endpoint,
+    /// unset if it only ever found the endpoint unreachable.
+    pub(super) idle_secs: Option<u64>,
+    /// Requests forwarded to the endpoint.
+    pub(super) requests: u64,
+    /// Requests that skipped the endpoint because it was unreachable.
+    pub(super) unreachable: u64,
+}
+
+impl UpstreamPath {
+    fn new(endpoint_id: EndpointId, info: PathInfo, entry: Entry) -> Self {
+        Self {
+            endpoint_id: endpoint_id.to_string(),
+            path: info.kind.label(),
+            rtt_ms: info.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            idle_secs: entry.last_request.map(|at| at.elapsed().as_secs()),
+            requests: entry.requests,
+            unreachable: entry.unreachable,
+        }
+    }
+}
+
+/// The paths and counters of each endpoint, from a snapshot taken for a
+/// scrape.
+#[derive(Debug)]
+pub(super) struct PathsCollector(pub(super) Vec<UpstreamPath>);
+
+impl Collector for PathsCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> fmt::Result {
+        let endpoint = |path: &UpstreamPath| ("endpoint", path.endpoint_id.clone());
+        encode_gauges(
+            &mut encoder,
+            "iroh_gateway_upstream_path",
+            "Path to each recently used upstream endpoint.",
+            self.0.iter().flat_map(|path| {
+                [
+                    PathKind::Direct,
+                    PathKind::Relay,
+                    PathKind::Mixed,
+                    PathKind::None,
+                ]
+                .map(|kind| {
+                    let value = u64::from(path.path == kind.label());
+                    ([endpoint(path), ("path", kind.label().to_string())], value)
+                })
+            }),
+        )?;
+        encode_gauges(
+            &mut encoder,
+            "iroh_gateway_upstream_rtt_seconds",
+            "Round-trip time to each recently used upstream endpoint.",
+            self.0.iter().filter_map(|path| {
+                let rtt_ms = path.rtt_ms?;
+                Some(([endpoint(path)], rtt_ms / 1000.0))
+            }),
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_upstream_requests",
+            "Requests forwarded to each recently used upstream endpoint.",
+            self.0.iter().map(|path| ([endpoint(path)], path.requests)),
+        )?;
+        encode_counters(
+            &mut encoder,
+            "iroh_gateway_upstream_errors",
+            "Requests that could not use each recently used upstream endpoint, by reason.",
+            self.0.iter().map(|path| {
+                (
+                    [endpoint(path), ("reason", "unreachable".to_string())],
+                    path.unreachable,
+                )
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+    use prometheus_client::{encoding::text::encode_registry, registry::Registry};
+
+    use super::*;
+
+    fn render(paths: &[UpstreamPath]) -> String {
+        let mut registry = Registry::default();
+        registry.register_collector(Box::new(PathsCollector(paths.to_vec())));
+        let mut text = String::new();
+        encode_registry(&mut text, &registry).unwrap();
+        text
+    }
+
+    fn endpoint_id() -> EndpointId {
+        SecretKey::generate(&mut rand::rng()).public()
+    }
+
+    fn entry(idle: Duration) -> Entry {
+        Entry {
+            last_request: Some(Instant::now() - idle),
+            requests: 1,
+            unreachable: 0,
+        }
+    }
+
+    #[test]
+    fn renders_one_path_per_endpoint() {
+        let id = endpoint_id();
+        let paths = [UpstreamPath::new(
+            id,
+            PathInfo {
+                kind: PathKind::Relay,
+                rtt: Some(Duration::from_millis(40)),
+            },
+            entry(Duration::from_secs(3)),
+        )];
+        let text = render(&paths);
+        assert!(text.contains(&format!(
+            "iroh_gateway_upstream_path{{endpoint=\"{id}\",path=\"relay\"}} 1\n"
+        )));
+        assert!(text.contains(&format!(
+            "iroh_gateway_upstream_path{{endpoint=\"{id}\",path=\"direct\"}} 0\n"
+        )));
+        assert!(text.contains(&format!(
+            "iroh_gateway_upstream_rtt_seconds{{endpoint=\"{id}\"}} 0.04\n"
+        )));
+        assert_eq!(paths[0].idle_secs, Some(3));
+    }
+
+    #[test]
+    fn leaves_out_unmeasured_rtt() {
+        let paths = [UpstreamPath::new(
+            endpoint_id(),
+            PathInfo::default(),
+            entry(Duration::ZERO),
+        )];
+        let text = render(&paths);
+        assert!(text.contains("path=\"none\"} 1"));
+        assert!(!text.contains("iroh_gateway_upstream_rtt_seconds{"));
+    }
+
+    #[test]
+    fn counts_requests_and_errors_per_endpoint() {
+        let paths = UpstreamPaths::default();
+        let (up, down) = (endpoint_id(), endpoint_id());
+        paths.observe(up);
+        paths.observe(up);
+        paths.observe_unreachable(down);
+        let entries = paths.entries.lock().unwrap();
+        let up_entry = entries.get(&up).unwrap();
+        assert_eq!((up_entry.requests, up_entry.unreachable), (2, 0));
+        let down_entry = entries.get(&down).unwrap();
+        assert_eq!((down_entry.requests, down_entry.unreachable), (0, 1));
+        assert!(down_entry.last_request.is_none());
+
+        let row = UpstreamPath::new(down, PathInfo::default(), *down_entry);
+        let text = render(&[row]);
+        assert!(text.contains(&format!(
+            "iroh_gateway_upstream_errors_total{{endpoint=\"{down}\",reason=\"unreachable\"}} 1\n"
+        )));
+        assert!(text.contains(&format!(
+            "iroh_gateway_upstream_requests_total{{endpoint=\"{down}\"}} 0\n"
+        )));
+    }
+}
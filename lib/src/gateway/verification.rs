@@ -0,0 +1,246 @@
+//! Ownership verification for custom hostnames.
+//!
+//! A shared gateway must not forward requests for `example.com` to an
+//! endpoint just because that endpoint's owner configured the hostname. When
+//! verification is enabled, a hostname outside the managed domains is only
+//! served once a TXT record at `_datum-connect-challenge.<host>` with the
+//! value `endpoint-id=<endpoint id>` proves the endpoint owner controls it.
+//!
+//! There is no HTTP token to fall back to: requests for the hostname are
+//! what this gateway serves, so fetching a token from it would be answered
+//! by the very endpoint being checked.
+//!
+//! Results are cached per `(host, endpoint)` pair so lookups don't happen on
+//! every request, and concurrent misses for one pair share a single lookup.
+//! The lookup's entry goes away with the last request waiting on it, also
+//! when a request is dropped halfway, e.g. on its resolve timeout.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use iroh::EndpointId;
+use iroh_relay::dns::{DnsProtocol, DnsResolver};
+use tracing::debug;
+use ttl_cache::TtlCache;
+
+use crate::config::{Config, HostnameVerificationConfig};
+
+pub const TXT_RECORD_PREFIX: &str = "_datum-connect-challenge";
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(30);
+const CACHE_CAPACITY: usize = 4096;
+
+type LookupKey = (String, EndpointId);
+type Lookups = Mutex<HashMap<LookupKey, Arc<tokio::sync::Mutex<()>>>>;
+
+/// The TXT record value that proves `endpoint_id` may serve a hostname.
+pub fn txt_record_value(endpoint_id: &EndpointId) -> String {
+    format!("endpoint-id={endpoint_id}")
+}
+
+#[derive(derive_more::Debug)]
+pub(super) struct HostnameVerifier {
+    managed_domains: Vec<String>,
+    ttl: Duration,
+    #[debug(skip)]
+    resolver: DnsResolver,
+    #[debug(skip)]
+    cache: Mutex<TtlCache<LookupKey, bool>>,
+    /// Lookups in flight, so a burst of requests for an unverified hostname
+    /// sends one DNS query instead of one per request.
+    #[debug(skip)]
+    lookups: Lookups,
+}
+
+impl HostnameVerifier {
+    pub(super) fn new(config: &HostnameVerificationConfig, common: &Config) -> Self {
+        let resolver = match common.dns_resolver_addr() {
+            Some(addr) => DnsResolver::builder()
+                .with_nameserver(addr, DnsProtocol::Udp)
+                .build(),
+            None => DnsResolver::new(),
+        };
+        Self {
+            managed_domains: config
+                .managed_domains
+                .iter()
+                .map(|d| d.trim_matches('.').to_ascii_lowercase())
+                .collect(),
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            resolver,
+            cache: Mutex::new(TtlCache::new(CACHE_CAPACITY)),
+            lookups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if `endpoint_id` may serve requests for `host`.
+    ///
+    /// `host` may include a port, which is ignored.
+    pub(super) async fn verify(&self, host: &str, endpoint_id: EndpointId) -> bool {
+        let host = normalize_host(host);
+        if is_managed(&host, &self.managed_domains) {
+            return true;
+        }
+        let key = (host.clone(), endpoint_id);
+        if let Some(verified) = self.cached(&key) {
+            return verified;
+        }
+
+        let lookup = LookupEntry::join(&self.lookups, key.clone());
+        let _turn = lookup.turn().await;
+        // Whoever had the turn before us may have done the lookup already.
+        if let Some(verified) = self.cached(&key) {
+            return verified;
+        }
+
+        let verified = self.verify_txt(&host, &endpoint_id).await;
+        let ttl = if verified {
+            self.ttl
+        } else {
+            NEGATIVE_CACHE_TTL
+        };
+        debug!(%host, endpoint_id = %endpoint_id.fmt_short(), verified, "hostname verification");
+        self.cache
+            .lock()
+            .expect("poisoned")
+            .insert(key, verified, ttl);
+        verified
+    }
+
+    fn cached(&self, key: &LookupKey) -> Option<bool> {
+        self.cache.lock().expect("poisoned").get(key).copied()
+    }
+
+    async fn verify_txt(&self, host: &str, endpoint_id: &EndpointId) -> bool {
+        let name = format!("{TXT_RECORD_PREFIX}.{host}");
+        let expected = txt_record_value(endpoint_id);
+        match self.resolver.lookup_txt(name.clone(), LOOKUP_TIMEOUT).await {
+            Ok(records) => records
+                .into_iter()
+                .any(|record| record.to_string().trim() == expected),
+            Err(err) => {
+                debug!(%name, "TXT verification lookup failed: {err:#}");
+                false
+            }
+        }
+    }
+}
+
+/// A request's share of a lookup in [`HostnameVerifier::lookups`]. The last
+/// one to be dropped removes the entry.
+struct LookupEntry<'a> {
+    lookups: &'a Lookups,
+    key: LookupKey,
+    lookup: Option<Arc<tokio::sync::Mutex<()>>>,
+}
+
+impl<'a> LookupEntry<'a> {
+    fn join(lookups: &'a Lookups, key: LookupKey) -> Self {
+        let lookup = lookups
+            .lock()
+            .expect("poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Self {
+            lookups,
+            key,
+            lookup: Some(lookup),
+        }
+    }
+
+    /// Wait until the requests before this one are done with the lookup.
+    async fn turn(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.lookup.as_ref().expect("not dropped").lock().await
+    }
+}
+
+impl Drop for LookupEntry<'_> {
+    fn drop(&mut self) {
+        // Shares are only taken and given up under the map's lock, so the
+        // count is exact.
+        let mut lookups = self.lookups.lock().expect("poisoned");
+        drop(self.lookup.take());
+        if lookups
+            .get(&self.key)
+            .is_some_and(|lookup| Arc::strong_count(lookup) == 1)
+        {
+            lookups.remove(&self.key);
+        }
+    }
+}
+
+pub(super) fn normalize_host(host: &str) -> String {
+    // Strip a trailing port, leaving bare IPv6 addresses alone.
+    let host = match host.rsplit_once(':') {
+        Some((h, port))
+            if port.parse::<u16>().is_ok() && (!h.contains(':') || h.ends_with(']')) =>
+        {
+            h
+        }
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn is_managed(host: &str, managed_domains: &[String]) -> bool {
+    managed_domains.iter().any(|domain| {
+        host == domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_strips_port_and_case() {
+        assert_eq!(normalize_host("Example.COM:8080"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]:443"), "[::1]");
+    }
+
+    #[tokio::test]
+    async fn cancelled_lookups_leave_no_entry_behind() {
+        // A DNS server that never answers, so lookups hang until cancelled.
+        let dns = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let common = Config {
+            dns_resolver: Some(dns.local_addr().unwrap()),
+            ..Default::default()
+        };
+        let verifier = HostnameVerifier::new(&HostnameVerificationConfig::default(), &common);
+        let endpoint_id = iroh::SecretKey::generate(&mut rand::rng()).public();
+
+        let mut first = Box::pin(verifier.verify("example.com", endpoint_id));
+        let mut second = Box::pin(verifier.verify("example.com", endpoint_id));
+        // The first request starts the lookup and the second waits for it.
+        let wait = Duration::from_millis(100);
+        tokio::time::timeout(wait, &mut first).await.unwrap_err();
+        tokio::time::timeout(wait, &mut second).await.unwrap_err();
+        assert_eq!(verifier.lookups.lock().unwrap().len(), 1);
+
+        // Cancelling the first leaves the entry to the second...
+        drop(first);
+        assert_eq!(verifier.lookups.lock().unwrap().len(), 1);
+        // ...which cleans it up when it is cancelled too.
+        tokio::time::timeout(wait, &mut second).await.unwrap_err();
+        drop(second);
+        assert!(verifier.lookups.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn managed_domains_match_subdomains_only() {
+        let managed = vec!["iroh.datum.net".to_string()];
+        assert!(is_managed("iroh.datum.net", &managed));
+        assert!(is_managed("vast-gold-mine.iroh.datum.net", &managed));
+        assert!(!is_managed("eviliroh.datum.net", &managed));
+        assert!(!is_managed("example.com", &managed));
+    }
+}
@@ -0,0 +1,334 @@
+//! Health checks from the agent to the local targets of its tunnels.
+//!
+//! A tunnel is advertised whether or not anything listens on its target, and
+//! clients only find out through failing requests. Tunnels with a
+//! [`HealthCheck`] are probed periodically, by TCP connect or an HTTP `GET`,
+//! and the latest result is kept per tunnel in a [`HealthMonitor`]. A check
+//! can also take the tunnel's ticket out of n0des while the target is down.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use n0_error::{Result, StdResultExt};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinSet};
+use tracing::{debug, warn};
+
+use crate::{
+    AdvertismentTicket, ProxyState, Repo, StateWrapper, dial, events::EventKind, join_host_port,
+};
+
+/// How often the monitor looks for checks that are due.
+const TICK: Duration = Duration::from_secs(1);
+
+/// How a tunnel's target is checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// Path for an HTTP `GET`; any response below 500 counts as healthy.
+    /// Unset only checks that the target accepts TCP connections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_path: Option<String>,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Unpublish the tunnel's ticket while its target is unhealthy.
+    #[serde(default)]
+    pub unpublish_when_unhealthy: bool,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            http_path: None,
+            interval_secs: default_interval_secs(),
+            timeout_ms: default_timeout_ms(),
+            unpublish_when_unhealthy: false,
+        }
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Check the target at `host:port`.
+    pub async fn run(&self, host: &str, port: u16) -> Result<()> {
+        let check = async {
+            match &self.http_path {
+                None => {
+                    dial::connect(host, port)
+                        .await
+                        .std_context("Connection failed")?;
+                }
+                Some(path) => {
+                    let address = join_host_port(dial::unbracket(host), port);
+                    let res = reqwest::get(format!("http://{address}{path}"))
+                        .await
+                        .std_context("Request failed")?;
+                    if res.status().is_server_error() {
+                        n0_error::bail_any!("Responded with {}", res.status());
+                    }
+                }
+            }
+            Ok(())
+        };
+        tokio::time::timeout(self.timeout(), check)
+            .await
+            .map_err(|_| n0_error::anyerr!("No answer within {:?}", self.timeout()))?
+    }
+}
+
+/// The latest health check result of a tunnel's target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetHealth {
+    pub healthy: bool,
+    /// Why the latest check failed.
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+    /// When the target last turned healthy or unhealthy.
+    pub since: DateTime<Utc>,
+}
+
+/// Health of the targets of tunnels with a [`HealthCheck`], by tunnel id.
+#[derive(Debug, Clone)]
+pub struct HealthMonitor {
+    health: Arc<watch::Sender<BTreeMap<String, TargetHealth>>>,
+}
+
+impl Default for HealthMonitor {
+    fn default() -> Self {
+        Self {
+            health: Arc::new(watch::channel(BTreeMap::new()).0),
+        }
+    }
+}
+
+impl HealthMonitor {
+    pub fn get(&self, tunnel_id: &str) -> Option<TargetHealth> {
+        self.health.borrow().get(tunnel_id).cloned()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<BTreeMap<String, TargetHealth>> {
+        self.health.subscribe()
+    }
+
+    /// Store a check result. Returns whether the target was healthy before,
+    /// if it had been checked.
+    pub(crate) fn record(&self, tunnel_id: &str, result: &Result<()>) -> Option<bool> {
+        let now = Utc::now();
+        let healthy = result.is_ok();
+        let mut was_healthy = None;
+        self.health.send_modify(|health| {
+            let prev = health.get(tunnel_id);
+            was_healthy = prev.map(|prev| prev.healthy);
+            let since = match prev {
+                Some(prev) if prev.healthy == healthy => prev.since,
+                _ => now,
+            };
+            health.insert(
+                tunnel_id.to_string(),
+                TargetHealth {
+                    healthy,
+                    error: result.as_ref().err().map(|err| format!("{err:#}")),
+                    checked_at: now,
+                    since,
+                },
+            );
+        });
+        was_healthy
+    }
+
+    /// Forget tunnels that are gone or no longer checked.
+    pub(crate) fn retain(&self, mut keep: impl FnMut(&str) -> bool) {
+        self.health.send_if_modified(|health| {
+            let before = health.len();
+            health.retain(|id, _| keep(id));
+            health.len() != before
+        });
+    }
+}
+
+/// Check the targets of enabled tunnels with a [`HealthCheck`] as their
+/// intervals come due, until the task is dropped.
+pub(crate) async fn run(
+    state: StateWrapper,
+    repo: Repo,
+    n0des: Option<Arc<iroh_n0des::Client>>,
+    endpoint_id: EndpointId,
+    monitor: HealthMonitor,
+) {
+    let mut last_run = HashMap::<String, Instant>::new();
+    // Tickets taken out of n0des because their target was unhealthy.
+    let mut unpublished = HashSet::<String>::new();
+    loop {
+        let current = state.get_cloned();
+        let proxies = current
+            .proxies
+            .iter()
+            .filter(|p| p.enabled && p.health_check.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        monitor.retain(|id| proxies.iter().any(|p| p.id() == id));
+        last_run.retain(|id, _| proxies.iter().any(|p| p.id() == id));
+
+        // Put back tickets whose check was removed or no longer unpublishes.
+        let restore = unpublished
+            .iter()
+            .filter(|id| {
+                !proxies.iter().any(|p| {
+                    p.id() == id.as_str()
+                        && p.health_check
+                            .as_ref()
+                            .is_some_and(|c| c.unpublish_when_unhealthy)
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for tunnel_id in restore {
+            unpublished.remove(&tunnel_id);
+            if let Some(proxy) = current.proxies.iter().find(|p| p.id() == tunnel_id)
+                && proxy.enabled
+            {
+                set_published(n0des.as_deref(), proxy, endpoint_id, true).await;
+            }
+        }
+
+        let mut checks = JoinSet::new();
+        for proxy in proxies {
+            let Some(check) = proxy.health_check.clone() else {
+                continue;
+            };
+            if last_run
+                .get(proxy.id())
+                .is_some_and(|at| at.elapsed() < check.interval())
+            {
+                continue;
+            }
+            last_run.insert(proxy.id().to_string(), Instant::now());
+            let repo = repo.clone();
+            checks.spawn(async move {
+                let service = proxy.info.service();
+                let host = match repo.config().await {
+                    Ok(config) => config.host_override(&service.host).map(|ip| ip.to_string()),
+                    Err(_) => None,
+                };
+                let host = host.as_deref().unwrap_or(&service.host);
+                let result = check.run(host, service.port).await;
+                (proxy, check, result)
+            });
+        }
+        while let Some(joined) = checks.join_next().await {
+            let Ok((proxy, check, result)) = joined else {
+                continue;
+            };
+            let tunnel_id = proxy.id().to_string();
+            match (monitor.record(&tunnel_id, &result), &result) {
+                (None | Some(true), Err(err)) => {
+                    debug!(%tunnel_id, "target is unhealthy: {err:#}");
+                    repo.events().record(EventKind::TargetUnhealthy {
+                        tunnel_id: tunnel_id.clone(),
+                        error: format!("{err:#}"),
+                    });
+                    if check.unpublish_when_unhealthy {
+                        set_published(n0des.as_deref(), &proxy, endpoint_id, false).await;
+                        unpublished.insert(tunnel_id);
+                    }
+                }
+                (Some(false), Ok(())) => {
+                    debug!(%tunnel_id, "target is healthy again");
+                    repo.events().record(EventKind::TargetHealthy {
+                        tunnel_id: tunnel_id.clone(),
+                    });
+                    if unpublished.remove(&tunnel_id) {
+                        set_published(n0des.as_deref(), &proxy, endpoint_id, true).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+pub(crate) async fn set_published(
+    n0des: Option<&iroh_n0des::Client>,
+    proxy: &ProxyState,
+    endpoint_id: EndpointId,
+    publish: bool,
+) {
+    let Some(n0des) = n0des else {
+        return;
+    };
+    let tunnel_id = proxy.id().to_string();
+    if publish {
+        if let Err(err) = n0des
+            .publish_ticket(tunnel_id.clone(), proxy.ticket(endpoint_id).published_now())
+            .await
+        {
+            warn!(%tunnel_id, "Failed to publish ticket: {err:#}");
+        }
+    } else if let Err(err) = n0des
+        .unpublish_ticket::<AdvertismentTicket>(tunnel_id.clone())
+        .await
+    {
+        warn!(%tunnel_id, "Failed to unpublish ticket: {err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_tcp_targets_and_tracks_changes() -> Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let port = listener.local_addr()?.port();
+        let check = HealthCheck {
+            timeout_ms: 500,
+            ..Default::default()
+        };
+        let monitor = HealthMonitor::default();
+
+        let up = check.run("127.0.0.1", port).await;
+        assert!(up.is_ok());
+        assert_eq!(monitor.record("t", &up), None);
+        let since = monitor.get("t").unwrap().since;
+        assert_eq!(monitor.record("t", &up), Some(true));
+        assert_eq!(monitor.get("t").unwrap().since, since);
+
+        drop(listener);
+        let down = check.run("127.0.0.1", port).await;
+        assert!(down.is_err());
+        assert_eq!(monitor.record("t", &down), Some(true));
+        let health = monitor.get("t").unwrap();
+        assert!(!health.healthy);
+        assert!(health.error.is_some());
+
+        monitor.retain(|id| id != "t");
+        assert!(monitor.get("t").is_none());
+        Ok(())
+    }
+}
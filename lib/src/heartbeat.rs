@@ -1,18 +1,23 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use chrono::Utc;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
-use kube::api::{ListParams, Patch, PatchParams};
-use kube::{Api, ResourceExt};
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams, PostParams};
+use kube::{Api, Client, ResourceExt};
 use n0_error::{Result, StdResultExt};
 use n0_future::task::AbortOnDropHandle;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::sync::Mutex;
+use tokio::{
+    sync::{Mutex, broadcast},
+    task::JoinSet,
+};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
@@ -21,8 +26,13 @@ use crate::datum_apis::connector::{
     Connector, ConnectorConnectionDetails, ConnectorConnectionDetailsPublicKey,
     ConnectorConnectionType, PublicKeyConnectorAddress, PublicKeyDiscoveryMode,
 };
+use crate::datum_apis::connector_advertisement::ConnectorAdvertisement;
+use crate::datum_apis::http_proxy::HTTPProxy;
 use crate::datum_apis::lease::Lease;
-use crate::datum_cloud::{DatumCloudClient, LoginState};
+use crate::datum_cloud::{AuthEvent, DatumCloudClient, LoginState};
+use crate::events::EventKind;
+use crate::log_limit::warn_limited;
+use crate::tunnels::{advertisement_for, proxy_uses_connector};
 
 type ProjectRunner = Arc<
     dyn Fn(
@@ -39,6 +49,53 @@ const DEFAULT_PCP_NAMESPACE: &str = "default";
 const DEFAULT_LEASE_DURATION_SECS: i32 = 30;
 const BACKOFF_INITIAL: Duration = Duration::from_secs(2);
 const BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// Prefix of the Connector annotations describing the agent.
+pub(crate) const AGENT_ANNOTATION_PREFIX: &str = "connect.datumapis.com/agent-";
+/// Longer errors are cut off in the `last-error` annotation.
+const MAX_ERROR_CHARS: usize = 256;
+/// How long [`HeartbeatAgent::shutdown`] waits for leases to be released.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often a project is checked for orphaned advertisements.
+const ORPHAN_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Objects younger than this are left alone, they may be part of a create or
+/// delete that is still in progress.
+const ORPHAN_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// Facts the agent reports about itself in annotations on its Connector, so
+/// the Datum console and the rest of the project can see how the fleet is
+/// doing. Each can be withheld, see [`HeartbeatAgent::set_redacted_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStatusField {
+    Version,
+    /// Operating system and CPU architecture.
+    Os,
+    /// When the agent started.
+    Uptime,
+    ActiveTunnels,
+    /// The newest failure in the event log and when it happened.
+    LastError,
+}
+
+impl AgentStatusField {
+    pub const ALL: [Self; 5] = [
+        Self::Version,
+        Self::Os,
+        Self::Uptime,
+        Self::ActiveTunnels,
+        Self::LastError,
+    ];
+
+    fn annotations(&self) -> &'static [&'static str] {
+        match self {
+            Self::Version => &["version"],
+            Self::Os => &["os"],
+            Self::Uptime => &["started-at"],
+            Self::ActiveTunnels => &["active-tunnels"],
+            Self::LastError => &["last-error", "last-error-at"],
+        }
+    }
+}
 
 #[derive(derive_more::Debug, Clone)]
 pub struct HeartbeatAgent {
@@ -53,6 +110,7 @@ struct HeartbeatInner {
     projects: Mutex<HashMap<String, ProjectHeartbeat>>,
     known_projects: Mutex<HashSet<String>>,
     login_task: Mutex<Option<AbortOnDropHandle<()>>>,
+    redacted: Arc<ArcSwap<BTreeSet<AgentStatusField>>>,
 }
 
 struct ProjectHeartbeat {
@@ -62,17 +120,19 @@ struct ProjectHeartbeat {
 
 impl HeartbeatAgent {
     pub fn new(datum: DatumCloudClient, listen: ListenNode) -> Self {
-        let provider = Arc::new(ListenNodeDetailsProvider::new(listen));
+        let redacted = Arc::new(ArcSwap::default());
+        let provider = Arc::new(ListenNodeDetailsProvider::new(listen, redacted.clone()));
         let runner: ProjectRunner = Arc::new(|project_id, datum, provider, cancel| {
             tokio::spawn(run_project(project_id, datum, provider, cancel))
         });
-        Self::new_with_runner(datum, provider, runner)
+        Self::new_with_runner(datum, provider, runner, redacted)
     }
 
     fn new_with_runner(
         datum: DatumCloudClient,
         provider: Arc<dyn HeartbeatDetailsProvider>,
         runner: ProjectRunner,
+        redacted: Arc<ArcSwap<BTreeSet<AgentStatusField>>>,
     ) -> Self {
         Self {
             inner: Arc::new(HeartbeatInner {
@@ -82,48 +142,54 @@ impl HeartbeatAgent {
                 projects: Mutex::new(HashMap::new()),
                 known_projects: Mutex::new(HashSet::new()),
                 login_task: Mutex::new(None),
+                redacted,
             }),
         }
     }
 
+    /// Stop reporting `fields` on connectors, removing them on the next
+    /// heartbeat.
+    pub fn set_redacted_status(&self, fields: BTreeSet<AgentStatusField>) {
+        self.inner.redacted.store(Arc::new(fields));
+    }
+
     pub async fn start(&self) {
         let mut guard = self.inner.login_task.lock().await;
         if guard.is_some() {
             return;
         }
         let this = self.clone();
-        let mut login_rx = this.inner.datum.auth().login_state_watch();
+        let mut events = this.inner.datum.auth_events();
         let mut projects_rx = this.inner.datum.orgs_projects_watch();
         let task = tokio::spawn(async move {
-            if *login_rx.borrow() != LoginState::Missing
+            if this.inner.datum.login_state() != LoginState::Missing
                 && let Err(err) = this.refresh_projects().await
             {
                 warn!("heartbeat: bootstrap failed: {err:#}");
             }
             loop {
                 tokio::select! {
-                    res = login_rx.changed() => {
-                        if res.is_err() {
-                            return;
-                        }
-                        let login_state = *login_rx.borrow();
-                        match login_state {
-                            LoginState::Missing => {
+                    res = events.recv() => {
+                        match res {
+                            // A login may be another user's, whose projects
+                            // are picked up once their orgs are loaded.
+                            Ok(
+                                AuthEvent::LoggedIn { .. }
+                                | AuthEvent::LoggedOut
+                                | AuthEvent::RefreshFailed { .. },
+                            ) => {
                                 this.clear_projects().await;
                                 this.clear_known_projects().await;
                             }
-                            _ => {
-                                if let Err(err) = this.refresh_projects().await {
-                                    warn!("heartbeat: bootstrap failed: {err:#}");
-                                }
-                            }
+                            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                            Err(broadcast::error::RecvError::Closed) => return,
                         }
                     }
                     res = projects_rx.changed() => {
                         if res.is_err() {
                             return;
                         }
-                        if *login_rx.borrow() != LoginState::Missing
+                        if this.inner.datum.login_state() != LoginState::Missing
                             && let Err(err) = this.refresh_projects().await {
                                 warn!("heartbeat: bootstrap failed: {err:#}");
                             }
@@ -163,6 +229,40 @@ impl HeartbeatAgent {
         }
     }
 
+    /// Stop all heartbeats and release this agent's leases, so the console
+    /// shows its connectors as disconnected right away instead of once the
+    /// leases time out. Gives up after [`RELEASE_TIMEOUT`].
+    pub async fn shutdown(&self) {
+        self.inner.login_task.lock().await.take();
+        let project_ids = self
+            .inner
+            .projects
+            .lock()
+            .await
+            .drain()
+            .map(|(project_id, project)| {
+                project.cancel.cancel();
+                project_id
+            })
+            .collect::<Vec<_>>();
+        let mut releases = JoinSet::new();
+        for project_id in project_ids {
+            let datum = self.inner.datum.clone();
+            let provider = self.inner.provider.clone();
+            releases.spawn(async move {
+                if let Err(err) = release_project(&project_id, datum, provider).await {
+                    warn!(%project_id, "heartbeat: failed to release lease: {err:#}");
+                }
+            });
+        }
+        if tokio::time::timeout(RELEASE_TIMEOUT, releases.join_all())
+            .await
+            .is_err()
+        {
+            warn!("heartbeat: gave up releasing leases on shutdown");
+        }
+    }
+
     async fn clear_projects(&self) {
         let mut projects = self.inner.projects.lock().await;
         for (_, project) in projects.drain() {
@@ -222,7 +322,11 @@ impl HeartbeatAgent {
                     debug!(%project_id, "heartbeat: no connector yet");
                 }
                 Err(err) => {
-                    warn!(%project_id, "heartbeat: connector probe failed: {err:#}");
+                    warn_limited!(
+                        "heartbeat.connector_probe",
+                        %project_id,
+                        "heartbeat: connector probe failed: {err:#}"
+                    );
                 }
             }
         }
@@ -236,6 +340,7 @@ struct ConnectorCache {
     lease_name: Option<String>,
     lease_duration_seconds: Option<i32>,
     last_details: Option<serde_json::Value>,
+    last_status: Option<serde_json::Map<String, serde_json::Value>>,
     last_home_relay: Option<String>,
 }
 
@@ -247,6 +352,8 @@ async fn run_project(
 ) {
     let mut backoff = Backoff::new();
     let mut cache: Option<ConnectorCache> = None;
+    let mut failing = false;
+    let mut last_repair: Option<Instant> = None;
 
     loop {
         if cancel.is_cancelled() {
@@ -256,7 +363,12 @@ async fn run_project(
         let pcp = match datum.project_control_plane_client(&project_id).await {
             Ok(client) => client,
             Err(err) => {
-                warn!(%project_id, "heartbeat: failed to get pcp client: {err:#}");
+                warn_limited!(
+                    "heartbeat.pcp_client",
+                    %project_id,
+                    "heartbeat: failed to get pcp client: {err:#}"
+                );
+                report_failure(&*provider, &project_id, &mut failing, format!("{err:#}"));
                 sleep_with_cancel(backoff.next(), &cancel).await;
                 continue;
             }
@@ -266,7 +378,16 @@ async fn run_project(
         let leases: Api<Lease> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
 
         if cache.is_none() {
-            match find_connector(&connectors, provider.endpoint_id()).await {
+            let found = match find_connector(&connectors, provider.endpoint_id()).await {
+                // After a key rotation the connector carries the previous id
+                // until the details below are patched.
+                Ok(None) => match provider.previous_endpoint_id() {
+                    Some(previous) => find_connector(&connectors, previous).await,
+                    None => Ok(None),
+                },
+                res => res,
+            };
+            match found {
                 Ok(Some(connector)) => {
                     let lease_name = connector
                         .status
@@ -284,6 +405,7 @@ async fn run_project(
                         lease_name,
                         lease_duration_seconds: None,
                         last_details: None,
+                        last_status: None,
                         last_home_relay,
                     });
                     backoff.reset();
@@ -294,7 +416,11 @@ async fn run_project(
                     continue;
                 }
                 Err(err) => {
-                    warn!(%project_id, "heartbeat: connector lookup failed: {err:#}");
+                    warn_limited!(
+                        "heartbeat.connector_lookup",
+                        %project_id,
+                        "heartbeat: connector lookup failed: {err:#}"
+                    );
                     sleep_with_cancel(backoff.next(), &cancel).await;
                     continue;
                 }
@@ -326,7 +452,8 @@ async fn run_project(
                         .map(|details| details.home_relay.clone());
                 }
                 Err(err) => {
-                    warn!(
+                    warn_limited!(
+                        "heartbeat.fetch_connector",
                         %project_id,
                         connector = %cached.name,
                         "heartbeat: failed to fetch connector: {err:#}"
@@ -341,7 +468,12 @@ async fn run_project(
         let details = match provider.connection_details(cached.last_home_relay.as_deref()) {
             Some(details) => details,
             None => {
-                warn!(%project_id, connector = %cached.name, "heartbeat: missing home relay");
+                warn_limited!(
+                    "heartbeat.home_relay",
+                    %project_id,
+                    connector = %cached.name,
+                    "heartbeat: missing home relay"
+                );
                 cache = Some(cached);
                 sleep_with_cancel(backoff.next(), &cancel).await;
                 continue;
@@ -378,6 +510,24 @@ async fn run_project(
             }
         }
 
+        let status = provider.agent_status();
+        if !status.is_empty() && cached.last_status.as_ref() != Some(&status) {
+            let patch = json!({ "metadata": { "annotations": status } });
+            if let Err(err) = connectors
+                .patch(&cached.name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await
+            {
+                warn_limited!(
+                    "heartbeat.agent_status",
+                    %project_id,
+                    connector = %cached.name,
+                    "heartbeat: failed to patch agent status: {err:#}"
+                );
+            } else {
+                cached.last_status = Some(status);
+            }
+        }
+
         if cached.lease_duration_seconds.is_none() {
             let Some(lease_name) = cached.lease_name.as_ref() else {
                 cache = Some(cached);
@@ -392,7 +542,8 @@ async fn run_project(
                         .and_then(|spec| spec.lease_duration_seconds);
                 }
                 Err(err) => {
-                    warn!(
+                    warn_limited!(
+                        "heartbeat.fetch_lease",
                         %project_id,
                         lease = %lease_name,
                         "heartbeat: failed to fetch lease: {err:#}"
@@ -416,22 +567,197 @@ async fn run_project(
             .patch(lease_name, &PatchParams::default(), &Patch::Merge(&patch))
             .await
         {
-            warn!(%project_id, lease = %lease_name, "heartbeat: lease renew failed: {err:#}");
+            warn_limited!(
+                "heartbeat.lease_renew",
+                %project_id,
+                lease = %lease_name,
+                "heartbeat: lease renew failed: {err:#}"
+            );
+            report_failure(
+                &*provider,
+                &project_id,
+                &mut failing,
+                format!("lease renew failed: {err:#}"),
+            );
             cache = Some(cached);
             sleep_with_cancel(backoff.next(), &cancel).await;
             continue;
         }
 
+        if last_repair.is_none_or(|at| at.elapsed() >= ORPHAN_CHECK_INTERVAL) {
+            last_repair = Some(Instant::now());
+            if let Err(err) =
+                repair_orphans(&project_id, pcp.client(), &cached.name, &*provider).await
+            {
+                warn_limited!(
+                    "heartbeat.orphans",
+                    %project_id,
+                    connector = %cached.name,
+                    "heartbeat: orphan check failed: {err:#}"
+                );
+            }
+        }
+
         let lease_duration = cached
             .lease_duration_seconds
             .unwrap_or(DEFAULT_LEASE_DURATION_SECS);
         let interval = renewal_interval(lease_duration);
         backoff.reset();
+        failing = false;
         cache = Some(cached);
         sleep_with_cancel(interval, &cancel).await;
     }
 }
 
+/// Log a heartbeat failure, once per streak of failures.
+fn report_failure(
+    provider: &dyn HeartbeatDetailsProvider,
+    project_id: &str,
+    failing: &mut bool,
+    error: String,
+) {
+    if !std::mem::replace(failing, true) {
+        provider.record_event(EventKind::HeartbeatFailed {
+            project_id: project_id.to_string(),
+            error,
+        });
+    }
+}
+
+/// Repair what failed partial creates and deletes left behind on this
+/// agent's connector.
+///
+/// A tunnel is an HTTPProxy plus, while it is enabled, a
+/// ConnectorAdvertisement of the same name. An advertisement whose proxy is
+/// gone is deleted. A proxy without an advertisement looks just like a
+/// disabled tunnel, so its advertisement is only recreated if the tunnel is
+/// enabled on this device.
+async fn repair_orphans(
+    project_id: &str,
+    client: Client,
+    connector_name: &str,
+    provider: &dyn HeartbeatDetailsProvider,
+) -> Result<()> {
+    let proxies: Api<HTTPProxy> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+    let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+    let proxy_list = proxies
+        .list(&ListParams::default())
+        .await
+        .std_context("failed to list proxies")?
+        .items;
+    let selector = format!("spec.connectorRef.name={connector_name}");
+    let ad_list = ads
+        .list(&ListParams::default().fields(&selector))
+        .await
+        .std_context("failed to list advertisements")?
+        .items;
+    let settled = |created: Option<&DateTime<Utc>>| {
+        created.is_some_and(|created| {
+            (Utc::now() - *created).to_std().unwrap_or_default() >= ORPHAN_GRACE
+        })
+    };
+
+    for ad in &ad_list {
+        let name = ad.name_any();
+        if proxy_list.iter().any(|proxy| proxy.name_any() == name)
+            || !settled(ad.metadata.creation_timestamp.as_ref().map(|t| &t.0))
+        {
+            continue;
+        }
+        ads.delete(&name, &DeleteParams::default())
+            .await
+            .std_context("failed to delete advertisement")?;
+        debug!(%project_id, advertisement = %name, "heartbeat: pruned orphaned advertisement");
+        provider.record_event(EventKind::AdvertisementPruned {
+            project_id: project_id.to_string(),
+            tunnel_id: name,
+        });
+    }
+
+    let enabled = provider.enabled_tunnels();
+    for proxy in &proxy_list {
+        let name = proxy.name_any();
+        if !enabled.contains(&name)
+            || !proxy_uses_connector(proxy, connector_name)
+            || ad_list.iter().any(|ad| ad.name_any() == name)
+            || !settled(proxy.metadata.creation_timestamp.as_ref().map(|t| &t.0))
+        {
+            continue;
+        }
+        let Some(ad) = advertisement_for(proxy, connector_name) else {
+            continue;
+        };
+        ads.create(&PostParams::default(), &ad)
+            .await
+            .std_context("failed to create advertisement")?;
+        debug!(%project_id, tunnel = %name, "heartbeat: restored missing advertisement");
+        provider.record_event(EventKind::AdvertisementRestored {
+            project_id: project_id.to_string(),
+            tunnel_id: name,
+        });
+    }
+    Ok(())
+}
+
+/// Mark this agent's connector in a project disconnected and expire its
+/// lease.
+async fn release_project(
+    project_id: &str,
+    datum: DatumCloudClient,
+    provider: Arc<dyn HeartbeatDetailsProvider>,
+) -> Result<()> {
+    let pcp = datum.project_control_plane_client(project_id).await?;
+    let client = pcp.client();
+    let connectors: Api<Connector> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+    let leases: Api<Lease> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+    let Some(connector) = find_connector(&connectors, provider.endpoint_id()).await? else {
+        return Ok(());
+    };
+
+    let mut annotations = serde_json::Map::new();
+    annotations.insert(
+        format!("{AGENT_ANNOTATION_PREFIX}disconnected-at"),
+        Utc::now().to_rfc3339().into(),
+    );
+    let patch = json!({ "metadata": { "annotations": annotations } });
+    connectors
+        .patch(
+            &connector.name_any(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch),
+        )
+        .await
+        .std_context("failed to mark connector disconnected")?;
+
+    let Some(lease_name) = connector
+        .status
+        .as_ref()
+        .and_then(|status| status.lease_ref.as_ref())
+        .map(|lease| lease.name.clone())
+    else {
+        return Ok(());
+    };
+    let lease = leases
+        .get(&lease_name)
+        .await
+        .std_context("failed to fetch lease")?;
+    let lease_duration = lease
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.lease_duration_seconds)
+        .unwrap_or(DEFAULT_LEASE_DURATION_SECS);
+    // Back-date the last renewal by a full lease duration so the lease reads
+    // as expired now.
+    let expired = Utc::now() - chrono::Duration::seconds(lease_duration.into());
+    let patch = json!({ "spec": { "renewTime": MicroTime(expired) } });
+    leases
+        .patch(&lease_name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .std_context("failed to expire lease")?;
+    debug!(%project_id, lease = %lease_name, "heartbeat: released lease");
+    Ok(())
+}
+
 async fn probe_connector(
     project_id: &str,
     datum: DatumCloudClient,
@@ -440,8 +766,16 @@ async fn probe_connector(
     let pcp = datum.project_control_plane_client(project_id).await?;
     let client = pcp.client();
     let connectors: Api<Connector> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
-    let selector = provider.endpoint_id();
-    Ok(find_connector(&connectors, selector).await?.is_some())
+    if find_connector(&connectors, provider.endpoint_id())
+        .await?
+        .is_some()
+    {
+        return Ok(true);
+    }
+    match provider.previous_endpoint_id() {
+        Some(previous) => Ok(find_connector(&connectors, previous).await?.is_some()),
+        None => Ok(false),
+    }
 }
 
 async fn find_connector(
@@ -468,19 +802,41 @@ async fn find_connector(
 
 trait HeartbeatDetailsProvider: Send + Sync {
     fn endpoint_id(&self) -> String;
+    /// The previous endpoint id during a key rotation grace period.
+    fn previous_endpoint_id(&self) -> Option<String> {
+        None
+    }
     fn connection_details(
         &self,
         fallback_home_relay: Option<&str>,
     ) -> Option<ConnectorConnectionDetails>;
+    fn record_event(&self, _kind: EventKind) {}
+
+    /// Connector annotations describing the agent. Withheld fields are
+    /// `null`, which removes them in a merge patch.
+    fn agent_status(&self) -> serde_json::Map<String, serde_json::Value> {
+        Default::default()
+    }
+
+    /// Ids of the tunnels enabled on this device.
+    fn enabled_tunnels(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 struct ListenNodeDetailsProvider {
     listen: ListenNode,
+    started_at: DateTime<Utc>,
+    redacted: Arc<ArcSwap<BTreeSet<AgentStatusField>>>,
 }
 
 impl ListenNodeDetailsProvider {
-    fn new(listen: ListenNode) -> Self {
-        Self { listen }
+    fn new(listen: ListenNode, redacted: Arc<ArcSwap<BTreeSet<AgentStatusField>>>) -> Self {
+        Self {
+            listen,
+            started_at: Utc::now(),
+            redacted,
+        }
     }
 }
 
@@ -489,6 +845,39 @@ impl HeartbeatDetailsProvider for ListenNodeDetailsProvider {
         self.listen.endpoint_id().to_string()
     }
 
+    fn previous_endpoint_id(&self) -> Option<String> {
+        self.listen.previous_endpoint_id().map(|id| id.to_string())
+    }
+
+    fn record_event(&self, kind: EventKind) {
+        self.listen.events().record(kind);
+    }
+
+    fn enabled_tunnels(&self) -> Vec<String> {
+        self.listen
+            .proxies()
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| p.id().to_string())
+            .collect()
+    }
+
+    fn agent_status(&self) -> serde_json::Map<String, serde_json::Value> {
+        let active_tunnels = self.listen.proxies().iter().filter(|p| p.enabled).count();
+        let last_error = self
+            .listen
+            .events()
+            .latest(|event| event.kind.error().is_some());
+        agent_status(
+            &self.redacted.load(),
+            self.started_at,
+            active_tunnels,
+            last_error
+                .as_ref()
+                .and_then(|event| Some((event.kind.error()?, event.at))),
+        )
+    }
+
     fn connection_details(
         &self,
         fallback_home_relay: Option<&str>,
@@ -520,6 +909,43 @@ impl HeartbeatDetailsProvider for ListenNodeDetailsProvider {
     }
 }
 
+fn agent_status(
+    redacted: &BTreeSet<AgentStatusField>,
+    started_at: DateTime<Utc>,
+    active_tunnels: usize,
+    last_error: Option<(&str, DateTime<Utc>)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut status = serde_json::Map::new();
+    for field in AgentStatusField::ALL {
+        let values = match field {
+            AgentStatusField::Version => vec![Some(env!("CARGO_PKG_VERSION").to_string())],
+            AgentStatusField::Os => vec![Some(format!(
+                "{}/{}",
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            ))],
+            AgentStatusField::Uptime => vec![Some(started_at.to_rfc3339())],
+            AgentStatusField::ActiveTunnels => vec![Some(active_tunnels.to_string())],
+            AgentStatusField::LastError => vec![
+                last_error.map(|(error, _)| error.chars().take(MAX_ERROR_CHARS).collect()),
+                last_error.map(|(_, at)| at.to_rfc3339()),
+            ],
+        };
+        for (key, value) in field.annotations().iter().zip(values) {
+            let value = value
+                .filter(|_| !redacted.contains(&field))
+                .map_or(serde_json::Value::Null, serde_json::Value::String);
+            status.insert(format!("{AGENT_ANNOTATION_PREFIX}{key}"), value);
+        }
+    }
+    // Set by `release_project` when the agent shuts down.
+    status.insert(
+        format!("{AGENT_ANNOTATION_PREFIX}disconnected-at"),
+        serde_json::Value::Null,
+    );
+    status
+}
+
 fn renewal_interval(lease_duration_seconds: i32) -> Duration {
     let lease_duration_seconds = lease_duration_seconds.max(1) as u64;
     let base = Duration::from_secs((lease_duration_seconds / 2).max(1));
@@ -603,7 +1029,8 @@ mod tests {
                 cancel.cancelled().await;
             })
         });
-        let agent = HeartbeatAgent::new_with_runner(datum, provider, runner);
+        let agent =
+            HeartbeatAgent::new_with_runner(datum, provider, runner, Arc::new(ArcSwap::default()));
 
         agent.register_project("project-1").await;
         agent.register_project("project-1").await;
@@ -616,6 +1043,28 @@ mod tests {
         assert_eq!(count, 0);
     }
 
+    #[test]
+    fn agent_status_withholds_redacted_fields() {
+        let started_at = Utc::now();
+        let status = agent_status(
+            &BTreeSet::new(),
+            started_at,
+            3,
+            Some(("lease renew failed", started_at)),
+        );
+        let key = |name: &str| format!("{AGENT_ANNOTATION_PREFIX}{name}");
+        assert_eq!(status[&key("active-tunnels")], "3");
+        assert_eq!(status[&key("last-error")], "lease renew failed");
+        assert_eq!(status[&key("version")], env!("CARGO_PKG_VERSION"));
+
+        let redacted = BTreeSet::from([AgentStatusField::LastError, AgentStatusField::Os]);
+        let status = agent_status(&redacted, started_at, 3, Some(("boom", started_at)));
+        assert!(status[&key("last-error")].is_null());
+        assert!(status[&key("last-error-at")].is_null());
+        assert!(status[&key("os")].is_null());
+        assert_eq!(status[&key("started-at")], started_at.to_rfc3339());
+    }
+
     #[test]
     fn renewal_interval_in_range() {
         for lease_duration_seconds in [1, 2, 10, 60] {
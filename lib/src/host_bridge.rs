@@ -0,0 +1,184 @@
+//! Tunnels to host names only this device knows, through the `hosts` config
+//! or a DNS server of its own.
+//!
+//! Tunnel targets are dialed by iroh-proxy-utils with the system resolver,
+//! which doesn't know the names in [`Config::hosts`] nor asks
+//! [`Config::target_dns_server`]. Such a target is served through a relay on
+//! a loopback port, the same way [unix socket tunnels](crate::unix_socket)
+//! are: the relay looks the name up again for every connection, in the
+//! config first and DNS second, so a container or DHCP lease that changed its
+//! address is picked up by the next connection instead of the tunnel
+//! breaking.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use iroh_relay::dns::{DnsProtocol, DnsResolver};
+use n0_error::{Result, StdResultExt, stack_error};
+use n0_future::task::AbortOnDropHandle;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use crate::{Config, Repo, dial};
+
+#[stack_error(derive)]
+#[error("Failed to resolve {host}: {reason}")]
+pub struct ResolveFailed {
+    pub host: String,
+    pub reason: String,
+}
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The addresses of `host:port`, from the `hosts` config or else DNS, asking
+/// the `target_dns_server` before the system resolver if one is set.
+pub async fn resolve(
+    config: &Config,
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>, ResolveFailed> {
+    if let Some(ip) = config.host_override(host) {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    if let Ok(ip) = dial::unbracket(host).parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    if let Some(server) = config.target_dns_server_addr() {
+        let resolver = DnsResolver::builder()
+            .with_nameserver(server, DnsProtocol::Udp)
+            .build();
+        match resolver.lookup_ipv4_ipv6(host, LOOKUP_TIMEOUT).await {
+            Ok(ips) => {
+                let addrs = ips.map(|ip| SocketAddr::new(ip, port)).collect::<Vec<_>>();
+                if !addrs.is_empty() {
+                    return Ok(addrs);
+                }
+            }
+            Err(err) => debug!(%host, %server, "Target DNS server lookup failed: {err:#}"),
+        }
+    }
+    let failed = |reason: String| ResolveFailed {
+        host: host.to_string(),
+        reason,
+    };
+    let addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|err| failed(err.to_string()))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(failed("no addresses".to_string()));
+    }
+    Ok(addrs)
+}
+
+/// A relay from a loopback port to a host name, stopped when dropped.
+#[derive(Debug)]
+pub struct HostBridge {
+    target: String,
+    local_addr: SocketAddr,
+    _task: AbortOnDropHandle<()>,
+}
+
+impl HostBridge {
+    /// Relay connections on `addr` to `host:port`. Use port 0 to bind any
+    /// free port.
+    pub async fn bind(host: &str, port: u16, addr: SocketAddr, repo: Repo) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_std_context(|_| format!("Failed to bind {addr}"))?;
+        let local_addr = listener.local_addr()?;
+        let target = format!("{host}:{port}");
+        let task = tokio::spawn({
+            let host = Arc::<str>::from(host);
+            async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else {
+                        continue;
+                    };
+                    let host = host.clone();
+                    let repo = repo.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = relay(stream, &repo, &host, port).await {
+                            debug!(%host, port, "host relay failed: {err:#}");
+                        }
+                    });
+                }
+            }
+        });
+        debug!(%target, %local_addr, "relaying to host");
+        Ok(Self {
+            target,
+            local_addr,
+            _task: AbortOnDropHandle::new(task),
+        })
+    }
+
+    /// The `host:port` relayed to.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+async fn relay(mut stream: TcpStream, repo: &Repo, host: &str, port: u16) -> Result<()> {
+    let config = repo.config().await?;
+    let addrs = match resolve(&config, host, port).await {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            // Closing the connection is all the client learns, so say why here.
+            warn!("{err}");
+            return Err(err.into());
+        }
+    };
+    let mut target = dial::connect_addrs(addrs)
+        .await
+        .with_std_context(|_| format!("Failed to connect to {host}:{port}"))?;
+    tokio::io::copy_bidirectional(&mut stream, &mut target)
+        .await
+        .std_context("Failed to relay connection")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_overrides_first() -> Result<()> {
+        let mut config = Config::default();
+        config.hosts.insert(
+            "app.internal".to_string(),
+            Ipv4Addr::new(10, 0, 0, 5).into(),
+        );
+        let addrs = resolve(&config, "APP.internal.", 8080).await?;
+        assert_eq!(addrs, vec!["10.0.0.5:8080".parse().unwrap()]);
+        let addrs = resolve(&config, "127.0.0.1", 80).await?;
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+        let err = resolve(&config, "missing.invalid", 80).await.unwrap_err();
+        assert_eq!(err.host, "missing.invalid");
+        Ok(())
+    }
+
+    #[test]
+    fn bridges_names_the_agent_resolves() {
+        let mut config = Config::default();
+        config.hosts.insert(
+            "app.internal".to_string(),
+            Ipv4Addr::new(10, 0, 0, 5).into(),
+        );
+        assert!(config.resolves_target("app.internal"));
+        assert!(!config.resolves_target("db.vpn"));
+        config.target_dns_server = Some("10.8.0.1:53".parse().unwrap());
+        assert!(config.resolves_target("db.vpn"));
+        assert!(!config.resolves_target("10.0.0.7"));
+        assert!(!config.resolves_target("[fd00::12]"));
+    }
+}
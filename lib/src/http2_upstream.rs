@@ -0,0 +1,403 @@
+//! HTTP/2 from the gateway to the agent.
+//!
+//! iroh-proxy-utils carries each request as HTTP/1.1 absolute-form on a QUIC
+//! stream of its own. A listen node also serves [`HTTP2_UPSTREAM_ALPN`], on
+//! which the gateway opens one bidirectional stream and speaks HTTP/2 over
+//! it, so a chatty app's requests share one connection's setup and trailers
+//! reach the gateway. Gateways try this ALPN first and fall back to
+//! HTTP/1.1 for agents that refuse it.
+//!
+//! Requests name the tunnel's target as their authority, like the absolute
+//! form does, and are authorized the same way before they are forwarded to
+//! it. The public hostname stays in the `Host` header.
+//!
+//! Idempotent requests are sent to the service once more if the connection
+//! fails after they went out, e.g. because the service closed a pooled
+//! connection at the same time. Their bodies are spooled for that, see
+//! [`Config::request_spool`](crate::config::Config::request_spool); other
+//! requests stream through.
+//!
+//! gRPC requests go to the service over HTTP/2 with prior knowledge instead,
+//! on a client of their own, since gRPC needs it end to end. Their bodies
+//! stream both ways frame by frame, and the trailers with the gRPC status
+//! come back with the response.
+//!
+//! hyper's server doesn't send interim responses, so the `Link` headers of a
+//! service's `103 Early Hints` are added to the final response instead.
+//!
+//! Gateways may offer to take response bodies compressed with
+//! [`HEADER_ACCEPT_ENCODING`]. With
+//! [`Config::tunnel_compression`](crate::config::Config::tunnel_compression)
+//! on, text-like responses are then gzipped and marked with
+//! [`HEADER_CONTENT_ENCODING`], and the gateway decompresses them again.
+//! Neither header reaches the service or the client.
+//!
+//! Gateways also send the client's address in [`HEADER_CLIENT_ADDR`]. For
+//! tunnels with [`ProxyState::proxy_protocol`](crate::ProxyState::proxy_protocol)
+//! each request then goes to the service on a connection of its own, which
+//! starts with a PROXY protocol v2 header naming the client. The header
+//! doesn't reach the service either. Requests the gateway sends over
+//! HTTP/1.1 go through iroh-proxy-utils, which doesn't write PROXY headers.
+
+use std::{
+    convert::Infallible,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use http_body_util::{BodyExt, Empty, combinators::BoxBody};
+use hyper::{
+    Request, Response, StatusCode, Uri, Version,
+    body::{Body as _, Bytes, Incoming},
+    client::conn,
+    header::{self, HeaderMap, HeaderValue},
+    http::request::Parts,
+    server::conn::http2,
+    service::service_fn,
+};
+use hyper_util::{
+    client::legacy::{self, Client, connect::HttpConnector},
+    rt::{TokioExecutor, TokioIo},
+};
+use iroh::{
+    EndpointId,
+    endpoint::{Connection, RecvStream, SendStream},
+    protocol::{AcceptError, ProtocolHandler},
+};
+use n0_error::{Result, StdResultExt};
+use tokio::{io::AsyncWriteExt, task::JoinSet};
+use tracing::debug;
+
+use crate::{
+    compression::{self, GZIP},
+    config::{Config, TunnelCompressionConfig},
+    dial,
+    gateway::proxy_protocol,
+    node::TrackingAuth,
+    spool::{Spool, SpoolError, Spooled},
+};
+
+pub const HTTP2_UPSTREAM_ALPN: &[u8] = b"datum-connect/http2/0";
+
+/// Sent by the gateway with the encodings it takes response bodies in.
+pub(crate) const HEADER_ACCEPT_ENCODING: &str = "x-datum-accept-encoding";
+/// Set on responses whose body was compressed for the gateway.
+pub(crate) const HEADER_CONTENT_ENCODING: &str = "x-datum-content-encoding";
+/// Sent by the gateway with the address of the client, `ip:port`.
+pub(crate) const HEADER_CLIENT_ADDR: &str = "x-datum-client-addr";
+
+type Body = BoxBody<Bytes, io::Error>;
+
+/// Serves [`HTTP2_UPSTREAM_ALPN`] on a listen node.
+#[derive(derive_more::Debug, Clone)]
+pub(crate) struct Http2Upstream {
+    auth: TrackingAuth,
+    spool: Spool,
+    compression: TunnelCompressionConfig,
+    #[debug(skip)]
+    client: Client<HttpConnector, Body>,
+    /// For gRPC services, which only speak HTTP/2.
+    #[debug(skip)]
+    grpc_client: Client<HttpConnector, Body>,
+}
+
+impl Http2Upstream {
+    pub(crate) fn new(auth: TrackingAuth, config: &Config) -> Self {
+        let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let grpc_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build(HttpConnector::new());
+        Self {
+            auth,
+            spool: Spool::new(config.request_spool.clone()),
+            compression: config.tunnel_compression.clone(),
+            client,
+            grpc_client,
+        }
+    }
+
+    async fn serve(&self, conn: Connection) -> Result<()> {
+        let remote_id = conn.remote_id();
+        let mut streams = JoinSet::new();
+        while let Ok((send, recv)) = conn.accept_bi().await {
+            streams.spawn(self.clone().serve_stream(remote_id, send, recv));
+        }
+        Ok(())
+    }
+
+    /// Serve one HTTP/2 connection on a stream of the gateway's.
+    async fn serve_stream(self, remote_id: EndpointId, send: SendStream, recv: RecvStream) {
+        let io = TokioIo::new(tokio::io::join(recv, send));
+        let service = service_fn(move |req| {
+            let this = self.clone();
+            async move { Ok::<_, Infallible>(this.forward(remote_id, req).await) }
+        });
+        if let Err(err) = http2::Builder::new(TokioExecutor::new())
+            .serve_connection(io, service)
+            .await
+        {
+            debug!(remote_id = %remote_id.fmt_short(), "HTTP/2 upstream stream failed: {err:#}");
+        }
+    }
+
+    async fn forward(&self, remote_id: EndpointId, req: Request<Incoming>) -> Response<Body> {
+        let Some((host, port)) = target(req.uri()) else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        if self
+            .auth
+            .authorize_target(remote_id, &host, port)
+            .await
+            .is_err()
+        {
+            return status(StatusCode::FORBIDDEN);
+        }
+        let (mut parts, body) = req.into_parts();
+        let gzip = parts
+            .headers
+            .remove(HEADER_ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().map(str::to_string).ok())
+            .is_some_and(|value| value.split(',').any(|coding| coding.trim() == GZIP));
+        let client = parts
+            .headers
+            .remove(HEADER_CLIENT_ADDR)
+            .and_then(|value| value.to_str().ok()?.parse::<SocketAddr>().ok());
+        let early_hints = EarlyHints::default();
+        let mut parts = early_hints.watch(parts);
+        let res = if self.auth.proxy_protocol(&host, port) {
+            let body = body.map_err(io::Error::other).boxed();
+            send_proxied(&host, port, client, Request::from_parts(parts, body)).await
+        } else if is_grpc(&parts) {
+            let body = body.map_err(io::Error::other).boxed();
+            self.grpc_client
+                .request(Request::from_parts(parts, body))
+                .await
+                .anyerr()
+        } else if parts.method.is_idempotent() {
+            // The service gets HTTP/1.1 from the pooled client.
+            parts.version = Version::HTTP_11;
+            match self.spool.spool(body).await {
+                Ok(spooled) => self.send_twice(parts, &spooled).await.anyerr(),
+                Err(SpoolError::TooLarge { limit }) => {
+                    debug!(%host, port, "request body is larger than {limit} bytes");
+                    return status(StatusCode::PAYLOAD_TOO_LARGE);
+                }
+                Err(err) => {
+                    debug!(%host, port, "{err:#}");
+                    return status(StatusCode::BAD_GATEWAY);
+                }
+            }
+        } else {
+            // Likewise, with the body streamed through.
+            parts.version = Version::HTTP_11;
+            let body = body.map_err(io::Error::other).boxed();
+            self.client
+                .request(Request::from_parts(parts, body))
+                .await
+                .anyerr()
+        };
+        let mut res = match res {
+            Ok(res) => res,
+            Err(err) => {
+                debug!(%host, port, "failed to reach the target: {err:#}");
+                return status(StatusCode::BAD_GATEWAY);
+            }
+        };
+        add_early_hints(res.headers_mut(), &early_hints.0.lock().expect("poisoned"));
+        if gzip && self.compresses(&res) {
+            let (mut parts, body) = res.into_parts();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(HEADER_CONTENT_ENCODING, HeaderValue::from_static(GZIP));
+            Response::from_parts(parts, compression::gzip(body).boxed())
+        } else {
+            res.map(|body| body.map_err(io::Error::other).boxed())
+        }
+    }
+
+    fn compresses(&self, res: &Response<Incoming>) -> bool {
+        self.compression.enabled
+            && !res.body().is_end_stream()
+            && compression::compressible(res.headers(), self.compression.min_bytes)
+    }
+
+    /// Send a spooled request, and send it again if the connection failed
+    /// after it was made.
+    async fn send_twice(
+        &self,
+        parts: Parts,
+        spooled: &Spooled,
+    ) -> Result<Response<Incoming>, legacy::Error> {
+        match self
+            .client
+            .request(Request::from_parts(parts.clone(), spooled.body()))
+            .await
+        {
+            Err(err) if !err.is_connect() => {
+                debug!("sending the request again after the connection failed: {err:#}");
+                self.client
+                    .request(Request::from_parts(parts, spooled.body()))
+                    .await
+            }
+            res => res,
+        }
+    }
+}
+
+/// Send `req` to the service at `host:port` on a connection of its own,
+/// starting with a PROXY protocol header naming `client`. gRPC requests go
+/// over HTTP/2, others over HTTP/1.1. The connection isn't reused, as its
+/// header names this client only.
+async fn send_proxied(
+    host: &str,
+    port: u16,
+    client: Option<SocketAddr>,
+    req: Request<Body>,
+) -> Result<Response<Incoming>> {
+    let mut stream = dial::connect(host, port)
+        .await
+        .std_context("failed to connect to the service")?;
+    let server = stream.peer_addr()?;
+    stream
+        .write_all(&proxy_protocol::header(client, server))
+        .await?;
+    let io = TokioIo::new(stream);
+    let (mut parts, body) = req.into_parts();
+    if is_grpc(&parts) {
+        let (mut sender, conn) = conn::http2::handshake(TokioExecutor::new(), io)
+            .await
+            .anyerr()?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                debug!("connection to the service failed: {err:#}");
+            }
+        });
+        sender
+            .send_request(Request::from_parts(parts, body))
+            .await
+            .anyerr()
+    } else {
+        parts.version = Version::HTTP_11;
+        // The connection is to the service already, so the target goes in
+        // origin form.
+        let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+        parts.uri = path.parse().anyerr()?;
+        let (mut sender, conn) = conn::http1::handshake(io).await.anyerr()?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                debug!("connection to the service failed: {err:#}");
+            }
+        });
+        sender
+            .send_request(Request::from_parts(parts, body))
+            .await
+            .anyerr()
+    }
+}
+
+impl ProtocolHandler for Http2Upstream {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        if let Err(err) = self.serve(connection).await {
+            debug!("HTTP/2 upstream failed: {err:#}");
+        }
+        Ok(())
+    }
+}
+
+/// The `Link` headers of `103 Early Hints` from the service.
+#[derive(Debug, Default)]
+struct EarlyHints(Arc<Mutex<HeaderMap>>);
+
+impl EarlyHints {
+    /// Keep the early hints the service sends for the request of `parts`.
+    fn watch(&self, parts: Parts) -> Parts {
+        let mut req = Request::from_parts(parts, ());
+        let hints = self.0.clone();
+        hyper::ext::on_informational(&mut req, move |res| {
+            if res.status() == StatusCode::EARLY_HINTS {
+                let mut hints = hints.lock().expect("poisoned");
+                for link in res.headers().get_all(header::LINK) {
+                    hints.append(header::LINK, link.clone());
+                }
+            }
+        });
+        req.into_parts().0
+    }
+}
+
+/// Add the `Link` headers of early hints to the final response's `headers`,
+/// unless it has them already.
+pub(crate) fn add_early_hints(headers: &mut HeaderMap, hints: &HeaderMap) {
+    for link in hints.get_all(header::LINK) {
+        if !headers
+            .get_all(header::LINK)
+            .iter()
+            .any(|have| have == link)
+        {
+            headers.append(header::LINK, link.clone());
+        }
+    }
+}
+
+/// Whether a request is gRPC, which needs HTTP/2 to the service. gRPC-web
+/// works over HTTP/1.1 and goes the usual way.
+fn is_grpc(parts: &Parts) -> bool {
+    parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            let value = value.to_ascii_lowercase();
+            value.starts_with("application/grpc") && !value.starts_with("application/grpc-web")
+        })
+}
+
+/// The target host and port a request is for, from its authority. IPv6
+/// hosts are returned without brackets.
+fn target(uri: &Uri) -> Option<(String, u16)> {
+    let host = uri.host()?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    Some((host.to_string(), uri.port_u16().unwrap_or(80)))
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Empty::new().map_err(|never| match never {}).boxed())
+        .expect("valid response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_come_from_the_authority() {
+        let uri = Uri::from_static("http://localhost:5173/api");
+        assert_eq!(target(&uri), Some(("localhost".to_string(), 5173)));
+        let uri = Uri::from_static("http://[::1]/");
+        assert_eq!(target(&uri), Some(("::1".to_string(), 80)));
+        assert_eq!(target(&Uri::from_static("/api")), None);
+    }
+
+    #[test]
+    fn grpc_requests_are_told_apart_from_grpc_web() {
+        let parts = |content_type: &str| {
+            Request::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .body(())
+                .unwrap()
+                .into_parts()
+                .0
+        };
+        assert!(is_grpc(&parts("application/grpc")));
+        assert!(is_grpc(&parts("application/grpc+proto")));
+        assert!(!is_grpc(&parts("application/grpc-web+proto")));
+        assert!(!is_grpc(&parts("application/json")));
+    }
+}
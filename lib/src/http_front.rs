@@ -0,0 +1,852 @@
+//! An HTTP proxy the agent runs in front of a tunnel's service, for header
+//! rewrite rules and password protection.
+//!
+//! A tunnel with either is served through a small proxy on loopback, the same
+//! way [directory tunnels](crate::static_files) are: the tunnel's target is
+//! the proxy, which checks credentials, applies the request rules, forwards to
+//! the real service and applies the response rules on the way back. Protocol
+//! upgrades such as WebSockets are not carried through.
+//!
+//! Header rules are written `<request|response>:<set|add|remove>:<name>[=<value>]`,
+//! e.g. `request:set:X-Forwarded-Host=example.com` or `response:remove:Server`.
+//! Passwords and tokens are only stored as Argon2 hashes.
+//!
+//! Argon2 is slow on purpose, so the proxy remembers `Authorization` values it
+//! has checked by a keyed BLAKE3 hash, accepted ones for a while and rejected
+//! ones briefly, and runs at most [`MAX_CONCURRENT_CHECKS`] Argon2 checks at a
+//! time. Requests that can't get a turn within [`CHECK_QUEUE_TIMEOUT`] are
+//! turned away with `429 Too Many Requests`.
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use argon2::Argon2;
+use axum::{
+    Router,
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header},
+    response::Response,
+};
+use data_encoding::{BASE64, HEXLOWER};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use n0_error::{Result, StdResultExt, anyerr};
+use n0_future::task::AbortOnDropHandle;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::Semaphore};
+use tracing::{debug, warn};
+use ttl_cache::TtlCache;
+
+use crate::TcpProxyData;
+
+/// Headers that describe the connection rather than the message. Rewriting
+/// them would break the proxy itself, so rules may not touch them.
+const HOP_BY_HOP: &[HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+];
+
+/// Argon2 checks that may run at once, per proxy.
+const MAX_CONCURRENT_CHECKS: usize = 2;
+/// How long a request waits for its turn to be checked.
+const CHECK_QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an accepted `Authorization` value is let through unchecked.
+const ACCEPTED_TTL: Duration = Duration::from_secs(15 * 60);
+/// How long a rejected value is rejected without checking it again.
+const REJECTED_TTL: Duration = Duration::from_secs(30);
+/// Checked values remembered, accepted and rejected together.
+const CHECKED_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderDirection {
+    Request,
+    Response,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderOp {
+    /// Replace all values of the header.
+    Set,
+    /// Add a value, keeping existing ones.
+    Add,
+    /// Drop the header.
+    Remove,
+}
+
+/// One header change, applied to requests or responses of a tunnel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderRule {
+    pub direction: HeaderDirection,
+    pub op: HeaderOp,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl HeaderRule {
+    /// Check the name and value are valid and allowed.
+    pub fn validate(&self) -> Result<()> {
+        let name = HeaderName::from_str(&self.name)
+            .with_std_context(|_| format!("Invalid header name {:?}", self.name))?;
+        if HOP_BY_HOP.contains(&name) {
+            n0_error::bail_any!("The {name} header can't be rewritten");
+        }
+        match (self.op, &self.value) {
+            (HeaderOp::Remove, Some(_)) => {
+                n0_error::bail_any!("Removing {name} takes no value")
+            }
+            (HeaderOp::Remove, None) => {}
+            (_, None) => n0_error::bail_any!("Setting {name} needs a value"),
+            (_, Some(value)) => {
+                HeaderValue::from_str(value)
+                    .with_std_context(|_| format!("Invalid value for {name}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        // Rules are validated when set; skip any that still don't parse.
+        let Ok(name) = HeaderName::from_str(&self.name) else {
+            return;
+        };
+        let value = self.value.as_deref().map(HeaderValue::from_str);
+        match (self.op, value) {
+            (HeaderOp::Remove, _) => {
+                headers.remove(name);
+            }
+            (HeaderOp::Set, Some(Ok(value))) => {
+                headers.insert(name, value);
+            }
+            (HeaderOp::Add, Some(Ok(value))) => {
+                headers.append(name, value);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl FromStr for HeaderRule {
+    type Err = n0_error::AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, ':');
+        let (Some(direction), Some(op), Some(rest)) = (parts.next(), parts.next(), parts.next())
+        else {
+            n0_error::bail_any!(
+                "Invalid header rule {s:?}: expected <request|response>:<set|add|remove>:<name>"
+            );
+        };
+        let direction = match direction.trim().to_ascii_lowercase().as_str() {
+            "request" | "req" => HeaderDirection::Request,
+            "response" | "res" => HeaderDirection::Response,
+            other => n0_error::bail_any!("Invalid header rule direction {other:?}"),
+        };
+        let op = match op.trim().to_ascii_lowercase().as_str() {
+            "set" => HeaderOp::Set,
+            "add" => HeaderOp::Add,
+            "remove" => HeaderOp::Remove,
+            other => n0_error::bail_any!("Invalid header rule operation {other:?}"),
+        };
+        let (name, value) = match rest.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim().to_string())),
+            None => (rest, None),
+        };
+        let rule = Self {
+            direction,
+            op,
+            name: name.trim().to_ascii_lowercase(),
+            value,
+        };
+        rule.validate()?;
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for HeaderRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            HeaderDirection::Request => "request",
+            HeaderDirection::Response => "response",
+        };
+        let op = match self.op {
+            HeaderOp::Set => "set",
+            HeaderOp::Add => "add",
+            HeaderOp::Remove => "remove",
+        };
+        write!(f, "{direction}:{op}:{}", self.name)?;
+        if let Some(value) = &self.value {
+            write!(f, "={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Apply the rules for `direction` to `headers`, in order.
+pub fn apply_rules(rules: &[HeaderRule], direction: HeaderDirection, headers: &mut HeaderMap) {
+    for rule in rules.iter().filter(|rule| rule.direction == direction) {
+        rule.apply(headers);
+    }
+}
+
+/// Credentials a tunnel requires, checked before requests reach the service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "scheme")]
+pub enum TunnelAuth {
+    /// HTTP Basic auth; browsers prompt for the username and password.
+    Basic {
+        username: String,
+        password: SecretHash,
+    },
+    /// `Authorization: Bearer <token>`, for scripts and API clients.
+    Bearer { token: SecretHash },
+}
+
+impl TunnelAuth {
+    pub fn basic(username: &str, password: &str) -> Result<Self> {
+        if username.is_empty() || username.contains(':') {
+            n0_error::bail_any!("The username must not be empty or contain ':'");
+        }
+        if password.is_empty() {
+            n0_error::bail_any!("The password must not be empty");
+        }
+        Ok(Self::Basic {
+            username: username.to_string(),
+            password: SecretHash::new(password)?,
+        })
+    }
+
+    pub fn bearer(token: &str) -> Result<Self> {
+        if token.is_empty() || token.contains(char::is_whitespace) {
+            n0_error::bail_any!("The token must not be empty or contain spaces");
+        }
+        Ok(Self::Bearer {
+            token: SecretHash::new(token)?,
+        })
+    }
+
+    /// Whether an `Authorization` header value satisfies this.
+    pub fn verify(&self, authorization: &str) -> bool {
+        let (scheme, credentials) = authorization
+            .trim()
+            .split_once(' ')
+            .unwrap_or((authorization, ""));
+        match self {
+            Self::Basic { username, password } if scheme.eq_ignore_ascii_case("basic") => {
+                let Ok(decoded) = BASE64.decode(credentials.trim().as_bytes()) else {
+                    return false;
+                };
+                let Ok(decoded) = String::from_utf8(decoded) else {
+                    return false;
+                };
+                let Some((user, pass)) = decoded.split_once(':') else {
+                    return false;
+                };
+                // Hash even on a wrong username, so timing doesn't tell them apart.
+                let pass_ok = password.verify(pass);
+                constant_time_eq(user.as_bytes(), username.as_bytes()) && pass_ok
+            }
+            Self::Bearer { token } if scheme.eq_ignore_ascii_case("bearer") => {
+                token.verify(credentials.trim())
+            }
+            _ => false,
+        }
+    }
+
+    fn challenge(&self) -> &'static str {
+        match self {
+            Self::Basic { .. } => "Basic realm=\"datum-connect\", charset=\"UTF-8\"",
+            Self::Bearer { .. } => "Bearer realm=\"datum-connect\"",
+        }
+    }
+}
+
+/// A salted Argon2 hash of a password or token.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretHash {
+    salt: String,
+    hash: String,
+}
+
+impl fmt::Debug for SecretHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretHash(..)")
+    }
+}
+
+impl SecretHash {
+    const SALT_LEN: usize = 16;
+    const HASH_LEN: usize = 32;
+
+    pub fn new(secret: &str) -> Result<Self> {
+        let mut salt = [0u8; Self::SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        let hash = Self::hash(secret, &salt)?;
+        Ok(Self {
+            salt: HEXLOWER.encode(&salt),
+            hash: HEXLOWER.encode(&hash),
+        })
+    }
+
+    pub fn verify(&self, secret: &str) -> bool {
+        let (Ok(salt), Ok(expected)) = (
+            HEXLOWER.decode(self.salt.as_bytes()),
+            HEXLOWER.decode(self.hash.as_bytes()),
+        ) else {
+            return false;
+        };
+        match Self::hash(secret, &salt) {
+            Ok(hash) => constant_time_eq(&hash, &expected),
+            Err(_) => false,
+        }
+    }
+
+    fn hash(secret: &str, salt: &[u8]) -> Result<[u8; Self::HASH_LEN]> {
+        let mut hash = [0u8; Self::HASH_LEN];
+        Argon2::default()
+            .hash_password_into(secret.as_bytes(), salt, &mut hash)
+            .map_err(|err| anyerr!("hashing secret: {err}"))?;
+        Ok(hash)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What the agent's proxy in front of a tunnel's service does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpFront {
+    /// The service requests are forwarded to.
+    pub upstream: TcpProxyData,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<HeaderRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<TunnelAuth>,
+}
+
+impl HttpFront {
+    pub fn new(upstream: TcpProxyData) -> Self {
+        Self {
+            upstream,
+            rules: Vec::new(),
+            auth: None,
+        }
+    }
+
+    /// Nothing to do, so no proxy is needed.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.auth.is_none()
+    }
+
+    fn validate(&self) -> Result<()> {
+        for rule in &self.rules {
+            rule.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// The proxy in front of one service, stopped when dropped.
+#[derive(derive_more::Debug)]
+pub struct HttpFrontProxy {
+    local_addr: SocketAddr,
+    #[debug(skip)]
+    front: Arc<ArcSwap<HttpFront>>,
+    #[debug(skip)]
+    checked: Arc<CheckedCredentials>,
+    _task: AbortOnDropHandle<()>,
+}
+
+impl HttpFrontProxy {
+    /// Serve `front` on `addr`. Use port 0 to bind any free port.
+    pub async fn bind(front: HttpFront, addr: SocketAddr) -> Result<Self> {
+        front.validate()?;
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_std_context(|_| format!("Failed to bind {addr}"))?;
+        let local_addr = listener.local_addr()?;
+        let front = Arc::new(ArcSwap::from_pointee(front));
+        let checked = Arc::new(CheckedCredentials::new());
+        let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        let app = Router::new().fallback(forward).with_state(Forwarder {
+            front: front.clone(),
+            checked: checked.clone(),
+            checks: Arc::new(Semaphore::new(MAX_CONCURRENT_CHECKS)),
+            client,
+        });
+        let task = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, app).await {
+                warn!(%local_addr, "tunnel front proxy failed: {err:#}");
+            }
+        });
+        debug!(%local_addr, "serving tunnel front proxy");
+        Ok(Self {
+            local_addr,
+            front,
+            checked,
+            _task: AbortOnDropHandle::new(task),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn front(&self) -> Arc<HttpFront> {
+        self.front.load_full()
+    }
+
+    /// Apply new settings to requests from now on.
+    pub fn set_front(&self, front: HttpFront) -> Result<()> {
+        front.validate()?;
+        self.front.store(Arc::new(front));
+        self.checked.clear();
+        Ok(())
+    }
+}
+
+/// `Authorization` values already checked against the tunnel's credentials,
+/// by their keyed hash so the values themselves aren't kept around.
+struct CheckedCredentials {
+    key: [u8; 32],
+    results: Mutex<CheckedResults>,
+}
+
+struct CheckedResults {
+    /// Bumped whenever the credentials change, so checks against the old
+    /// ones that finish afterwards aren't remembered.
+    generation: u64,
+    cache: TtlCache<[u8; 32], bool>,
+}
+
+impl CheckedCredentials {
+    fn new() -> Self {
+        let mut key = [0u8; 32];
+        rand::rng().fill_bytes(&mut key);
+        Self {
+            key,
+            results: Mutex::new(CheckedResults {
+                generation: 0,
+                cache: TtlCache::new(CHECKED_CAPACITY),
+            }),
+        }
+    }
+
+    fn hash(&self, authorization: &str) -> [u8; 32] {
+        *blake3::keyed_hash(&self.key, authorization.as_bytes()).as_bytes()
+    }
+
+    fn generation(&self) -> u64 {
+        self.results.lock().expect("poisoned").generation
+    }
+
+    fn get(&self, hash: &[u8; 32]) -> Option<bool> {
+        self.results
+            .lock()
+            .expect("poisoned")
+            .cache
+            .get(hash)
+            .copied()
+    }
+
+    /// Remember a result checked in `generation`, unless the credentials
+    /// have changed since.
+    fn insert(&self, generation: u64, hash: [u8; 32], accepted: bool) {
+        let ttl = if accepted { ACCEPTED_TTL } else { REJECTED_TTL };
+        let mut results = self.results.lock().expect("poisoned");
+        if results.generation == generation {
+            results.cache.insert(hash, accepted, ttl);
+        }
+    }
+
+    fn clear(&self) {
+        let mut results = self.results.lock().expect("poisoned");
+        results.generation += 1;
+        results.cache.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthCheck {
+    Accepted,
+    Rejected,
+    /// Too many checks are running to get to this one.
+    Busy,
+}
+
+#[derive(Clone)]
+struct Forwarder {
+    front: Arc<ArcSwap<HttpFront>>,
+    checked: Arc<CheckedCredentials>,
+    /// Turns to run Argon2, so a flood of guesses can't tie up every
+    /// blocking thread.
+    checks: Arc<Semaphore>,
+    client: Client<HttpConnector, Body>,
+}
+
+impl Forwarder {
+    /// Check `headers` against `auth`, the credentials as of `generation`.
+    async fn authorized(
+        &self,
+        generation: u64,
+        auth: &TunnelAuth,
+        headers: &HeaderMap,
+    ) -> AuthCheck {
+        let Some(value) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return AuthCheck::Rejected;
+        };
+        let hash = self.checked.hash(value);
+        if let Some(accepted) = self.checked.get(&hash) {
+            return AuthCheck::from(accepted);
+        }
+        let permit =
+            match tokio::time::timeout(CHECK_QUEUE_TIMEOUT, self.checks.clone().acquire_owned())
+                .await
+            {
+                Ok(Ok(permit)) => permit,
+                _ => return AuthCheck::Busy,
+            };
+        // The same value may have been checked while we waited.
+        if let Some(accepted) = self.checked.get(&hash) {
+            return AuthCheck::from(accepted);
+        }
+        let auth = auth.clone();
+        let value = value.to_string();
+        let accepted = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            auth.verify(&value)
+        })
+        .await
+        .unwrap_or(false);
+        self.checked.insert(generation, hash, accepted);
+        AuthCheck::from(accepted)
+    }
+}
+
+impl From<bool> for AuthCheck {
+    fn from(accepted: bool) -> Self {
+        if accepted {
+            Self::Accepted
+        } else {
+            Self::Rejected
+        }
+    }
+}
+
+async fn forward(State(forwarder): State<Forwarder>, mut req: Request) -> Response {
+    // Taken before the settings: `set_front` clears after storing new ones.
+    let generation = forwarder.checked.generation();
+    let front = forwarder.front.load_full();
+    if let Some(auth) = &front.auth
+        && !is_cors_preflight(&req)
+    {
+        match forwarder.authorized(generation, auth, req.headers()).await {
+            AuthCheck::Accepted => {}
+            AuthCheck::Rejected => {
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, auth.challenge())
+                    .body(Body::empty())
+                    .expect("valid response");
+            }
+            AuthCheck::Busy => {
+                return Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .header(header::RETRY_AFTER, CHECK_QUEUE_TIMEOUT.as_secs())
+                    .body(Body::empty())
+                    .expect("valid response");
+            }
+        }
+        // The credentials are for the tunnel, not for the service.
+        req.headers_mut().remove(header::AUTHORIZATION);
+    }
+    let address = front.upstream.address();
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str())
+        .to_string();
+    match Uri::try_from(format!("http://{address}{path}")) {
+        Ok(uri) => *req.uri_mut() = uri,
+        Err(err) => {
+            debug!(%address, "invalid upstream uri: {err:#}");
+            return status(StatusCode::BAD_GATEWAY);
+        }
+    }
+    apply_rules(&front.rules, HeaderDirection::Request, req.headers_mut());
+    match forwarder.client.request(req).await {
+        Ok(res) => {
+            let mut res = res.map(Body::new);
+            apply_rules(&front.rules, HeaderDirection::Response, res.headers_mut());
+            res
+        }
+        Err(err) => {
+            debug!(%address, "failed to reach upstream: {err:#}");
+            status(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+/// Browsers send CORS preflights without credentials, so they have to reach
+/// the service even on a protected tunnel. They carry no body and the
+/// service only answers which methods and headers it allows.
+fn is_cors_preflight(req: &Request) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ORIGIN)
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+fn status(code: StatusCode) -> Response {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_validates_rules() {
+        let rule: HeaderRule = "request:set:X-Forwarded-Host=example.com".parse().unwrap();
+        assert_eq!(rule.direction, HeaderDirection::Request);
+        assert_eq!(rule.op, HeaderOp::Set);
+        assert_eq!(rule.name, "x-forwarded-host");
+        assert_eq!(rule.value.as_deref(), Some("example.com"));
+        assert_eq!(rule.to_string(), "request:set:x-forwarded-host=example.com");
+
+        let rule: HeaderRule = "response:remove:Server".parse().unwrap();
+        assert_eq!(rule.to_string().parse::<HeaderRule>().unwrap(), rule);
+
+        assert!("response:remove:Server=x".parse::<HeaderRule>().is_err());
+        assert!("request:add:X-Foo".parse::<HeaderRule>().is_err());
+        assert!(
+            "request:set:Transfer-Encoding=chunked"
+                .parse::<HeaderRule>()
+                .is_err()
+        );
+        assert!("request:set:bad name=x".parse::<HeaderRule>().is_err());
+        assert!("sideways:set:X-Foo=1".parse::<HeaderRule>().is_err());
+    }
+
+    #[test]
+    fn applies_rules_in_order() {
+        let rules: Vec<HeaderRule> = [
+            "request:set:x-a=1",
+            "request:add:x-a=2",
+            "request:remove:x-b",
+            "response:set:x-c=3",
+        ]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-a", HeaderValue::from_static("0"));
+        headers.insert("x-b", HeaderValue::from_static("0"));
+        apply_rules(&rules, HeaderDirection::Request, &mut headers);
+        let values: Vec<_> = headers.get_all("x-a").iter().collect();
+        assert_eq!(values, ["1", "2"]);
+        assert!(!headers.contains_key("x-b"));
+        assert!(!headers.contains_key("x-c"));
+    }
+
+    #[tokio::test]
+    async fn rewrites_through_proxy() -> Result<()> {
+        let origin = TcpListener::bind("127.0.0.1:0").await?;
+        let origin_addr = origin.local_addr()?;
+        let app = Router::new().fallback(|headers: HeaderMap| async move {
+            let host = headers
+                .get("x-forwarded-host")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            ([(header::SERVER, "origin/1.0")], host)
+        });
+        let _origin = AbortOnDropHandle::new(tokio::spawn(async move {
+            axum::serve(origin, app).await.ok();
+        }));
+
+        let front = HttpFront {
+            rules: vec![
+                "request:set:X-Forwarded-Host=example.com".parse()?,
+                "response:remove:Server".parse()?,
+            ],
+            ..HttpFront::new(TcpProxyData::from_host_port_str(&origin_addr.to_string())?)
+        };
+        let proxy = HttpFrontProxy::bind(front, "127.0.0.1:0".parse().unwrap()).await?;
+        let res = reqwest::get(format!("http://{}/", proxy.local_addr()))
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(header::SERVER).is_none());
+        assert_eq!(res.text().await.anyerr()?, "example.com");
+
+        let mut front = (*proxy.front()).clone();
+        front.auth = Some(TunnelAuth::basic("alice", "hunter2")?);
+        proxy.set_front(front)?;
+        let url = format!("http://{}/", proxy.local_addr());
+        let client = reqwest::Client::new();
+        let res = client.get(&url).send().await.anyerr()?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+        let res = client
+            .get(&url)
+            .basic_auth("alice", Some("wrong"))
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = client
+            .get(&url)
+            .basic_auth("alice", Some("hunter2"))
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PROPFIND")
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = client
+            .request(reqwest::Method::OPTIONS, &url)
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_hashed_credentials() {
+        let auth = TunnelAuth::basic("alice", "hunter2").unwrap();
+        let yaml = serde_yml::to_string(&auth).unwrap();
+        assert!(!yaml.contains("hunter2"));
+        let auth: TunnelAuth = serde_yml::from_str(&yaml).unwrap();
+        let header = format!("Basic {}", BASE64.encode(b"alice:hunter2"));
+        assert!(auth.verify(&header));
+        assert!(!auth.verify(&format!("Basic {}", BASE64.encode(b"bob:hunter2"))));
+        assert!(!auth.verify("Bearer hunter2"));
+
+        let auth = TunnelAuth::bearer("s3cret").unwrap();
+        assert!(auth.verify("Bearer s3cret"));
+        assert!(auth.verify("bearer s3cret"));
+        assert!(!auth.verify("Bearer s3cre"));
+        assert!(TunnelAuth::basic("a:b", "x").is_err());
+    }
+
+    #[tokio::test]
+    async fn checks_each_credential_once() {
+        let auth = TunnelAuth::bearer("s3cret").unwrap();
+        let forwarder = Forwarder {
+            front: Arc::new(ArcSwap::from_pointee(HttpFront::new(
+                TcpProxyData::from_host_port_str("127.0.0.1:1").unwrap(),
+            ))),
+            checked: Arc::new(CheckedCredentials::new()),
+            checks: Arc::new(Semaphore::new(1)),
+            client: Client::builder(TokioExecutor::new()).build(HttpConnector::new()),
+        };
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+            headers
+        };
+        let good = headers("Bearer s3cret");
+        let bad = headers("Bearer guess");
+        assert_eq!(
+            forwarder.authorized(0, &auth, &good).await,
+            AuthCheck::Accepted
+        );
+        assert_eq!(
+            forwarder.authorized(0, &auth, &bad).await,
+            AuthCheck::Rejected
+        );
+
+        // With no turns left, only values checked before get an answer.
+        forwarder.checks.close();
+        assert_eq!(
+            forwarder.authorized(0, &auth, &good).await,
+            AuthCheck::Accepted
+        );
+        assert_eq!(
+            forwarder.authorized(0, &auth, &bad).await,
+            AuthCheck::Rejected
+        );
+        let other = headers("Bearer other");
+        assert_eq!(
+            forwarder.authorized(0, &auth, &other).await,
+            AuthCheck::Busy
+        );
+        assert_eq!(
+            forwarder.authorized(0, &auth, &HeaderMap::new()).await,
+            AuthCheck::Rejected
+        );
+    }
+
+    #[tokio::test]
+    async fn forgets_checks_against_rotated_credentials() {
+        let old = TunnelAuth::bearer("old").unwrap();
+        let new = TunnelAuth::bearer("new").unwrap();
+        let forwarder = Forwarder {
+            front: Arc::new(ArcSwap::from_pointee(HttpFront::new(
+                TcpProxyData::from_host_port_str("127.0.0.1:1").unwrap(),
+            ))),
+            checked: Arc::new(CheckedCredentials::new()),
+            checks: Arc::new(Semaphore::new(0)),
+            client: Client::builder(TokioExecutor::new()).build(HttpConnector::new()),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer old".parse().unwrap());
+
+        // A check against the old credentials waits for a turn...
+        let generation = forwarder.checked.generation();
+        let check = tokio::spawn({
+            let forwarder = forwarder.clone();
+            let headers = headers.clone();
+            async move { forwarder.authorized(generation, &old, &headers).await }
+        });
+        tokio::task::yield_now().await;
+
+        // ...while they are rotated, and only gets its turn after.
+        forwarder.checked.clear();
+        forwarder.checks.add_permits(1);
+        assert_eq!(check.await.unwrap(), AuthCheck::Accepted);
+
+        // The old token must be checked again, against the new credentials.
+        forwarder.checks.close();
+        let generation = forwarder.checked.generation();
+        assert_eq!(
+            forwarder.authorized(generation, &new, &headers).await,
+            AuthCheck::Busy
+        );
+    }
+}
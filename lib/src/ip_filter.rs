@@ -0,0 +1,324 @@
+//! Per-tunnel IP allow and deny lists.
+//!
+//! The lists are stored on the tunnel's HTTPProxy as a request header filter,
+//! so Envoy attaches them to every request it forwards to the gateway. The
+//! gateway checks the client address against them and answers 403 before
+//! dialing the endpoint; the headers never reach the service.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use hyper::http::{HeaderMap, HeaderValue};
+use n0_error::{AnyError, Result, StdResultExt, anyerr};
+use serde::{Deserialize, Serialize};
+
+/// Header carrying the comma-separated allowed CIDRs.
+pub const ALLOW_HEADER: &str = "x-datum-ip-allow";
+/// Header carrying the comma-separated denied CIDRs.
+pub const DENY_HEADER: &str = "x-datum-ip-deny";
+
+/// An IP network such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// A bare address is a network of one. Host bits are cleared when parsing,
+/// so `10.1.2.3/8` is the same as `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max = max_prefix_len(addr);
+        if prefix_len > max {
+            return Err(anyerr!(
+                "Prefix length {prefix_len} is longer than {max} bits"
+            ));
+        }
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from_bits(v4.to_bits() & v4_mask(prefix_len))),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from_bits(v6.to_bits() & v6_mask(prefix_len))),
+        };
+        Ok(Self { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether `ip` is in this network. IPv4-mapped IPv6 addresses match
+    /// IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                ip.to_bits() & v4_mask(self.prefix_len) == net.to_bits()
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                ip.to_bits() & v6_mask(self.prefix_len) == net.to_bits()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn v4_mask(prefix_len: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+fn v6_mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+impl FromStr for IpCidr {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_std_context(|_| format!("Invalid IP address in {s:?}"))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .with_std_context(|_| format!("Invalid prefix length in {s:?}"))?,
+            None => max_prefix_len(addr),
+        };
+        Self::new(addr, prefix_len)
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = AnyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpCidr> for String {
+    fn from(value: IpCidr) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Which client addresses may use a tunnel.
+///
+/// A client in any `deny` network is refused. If `allow` is not empty, a
+/// client must also be in one of its networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<IpCidr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<IpCidr>,
+}
+
+impl IpFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip))
+    }
+
+    /// The filter as header name and value pairs; empty lists are left out.
+    pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+        [(ALLOW_HEADER, &self.allow), (DENY_HEADER, &self.deny)]
+            .into_iter()
+            .filter(|(_, nets)| !nets.is_empty())
+            .map(|(name, nets)| (name, join(nets)))
+            .collect()
+    }
+
+    /// Read a filter from request headers. Missing headers mean an empty list.
+    pub fn from_headers(headers: &HeaderMap<HeaderValue>) -> Result<Self> {
+        Ok(Self {
+            allow: parse_header(headers, ALLOW_HEADER)?,
+            deny: parse_header(headers, DENY_HEADER)?,
+        })
+    }
+
+    /// Build a filter from the header values set on a tunnel.
+    pub fn from_header_values<'a>(
+        values: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self> {
+        let mut filter = Self::default();
+        for (name, value) in values {
+            if name.eq_ignore_ascii_case(ALLOW_HEADER) {
+                filter.allow.extend(parse_list(value)?);
+            } else if name.eq_ignore_ascii_case(DENY_HEADER) {
+                filter.deny.extend(parse_list(value)?);
+            }
+        }
+        Ok(filter)
+    }
+}
+
+fn join(nets: &[IpCidr]) -> String {
+    nets.iter()
+        .map(IpCidr::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_list(value: &str) -> Result<Vec<IpCidr>> {
+    value
+        .split(',')
+        .filter(|item| !item.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn parse_header(headers: &HeaderMap<HeaderValue>, name: &str) -> Result<Vec<IpCidr>> {
+    let mut nets = Vec::new();
+    for value in headers.get_all(name) {
+        let value = value
+            .to_str()
+            .with_std_context(|_| format!("Invalid {name} header"))?;
+        nets.extend(parse_list(value)?);
+    }
+    Ok(nets)
+}
+
+/// The address of the client that made a request.
+///
+/// With `trusted_hops` of 0 this is the socket peer. Behind proxies that
+/// append to `X-Forwarded-For`, it is the entry `trusted_hops` from the
+/// right, the one the outermost trusted proxy saw. Returns `None` if that
+/// entry is missing or not an IP address.
+pub fn client_ip(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap<HeaderValue>,
+    trusted_hops: usize,
+) -> Option<IpAddr> {
+    if trusted_hops == 0 {
+        return peer;
+    }
+    let entries = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let entry = entries
+        .len()
+        .checked_sub(trusted_hops)
+        .map(|i| entries[i])?;
+    entry.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> IpCidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_matches_cidrs() {
+        assert_eq!(cidr("10.1.2.3/8").to_string(), "10.0.0.0/8");
+        assert_eq!(cidr("192.0.2.7").to_string(), "192.0.2.7/32");
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(cidr("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("example.com/8".parse::<IpCidr>().is_err());
+
+        assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = IpFilter {
+            allow: vec![cidr("10.0.0.0/8")],
+            deny: vec![cidr("10.0.0.0/24")],
+        };
+        assert!(filter.permits(ip("10.1.0.1")));
+        assert!(!filter.permits(ip("10.0.0.1")));
+        assert!(!filter.permits(ip("192.0.2.1")));
+
+        let deny_only = IpFilter {
+            deny: vec![cidr("192.0.2.0/24")],
+            ..Default::default()
+        };
+        assert!(deny_only.permits(ip("198.51.100.1")));
+        assert!(!deny_only.permits(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn round_trips_through_headers() {
+        let filter = IpFilter {
+            allow: vec![cidr("10.0.0.0/8"), cidr("2001:db8::/32")],
+            deny: vec![cidr("10.0.0.1")],
+        };
+        let mut headers = HeaderMap::new();
+        for (name, value) in filter.to_headers() {
+            headers.insert(name, value.parse().unwrap());
+        }
+        assert_eq!(IpFilter::from_headers(&headers).unwrap(), filter);
+        assert_eq!(
+            IpFilter::from_headers(&HeaderMap::new()).unwrap(),
+            IpFilter::default()
+        );
+
+        headers.insert(DENY_HEADER, "10.0.0.1,not-an-ip".parse().unwrap());
+        assert!(IpFilter::from_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn picks_client_from_forwarded_for() {
+        let peer = Some(ip("127.0.0.1"));
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "1.1.1.1, 192.0.2.1".parse().unwrap());
+        headers.append("x-forwarded-for", "198.51.100.7".parse().unwrap());
+
+        assert_eq!(client_ip(peer, &headers, 0), peer);
+        assert_eq!(client_ip(peer, &headers, 1), Some(ip("198.51.100.7")));
+        assert_eq!(client_ip(peer, &headers, 2), Some(ip("192.0.2.1")));
+        assert_eq!(client_ip(peer, &headers, 4), None);
+        assert_eq!(client_ip(peer, &HeaderMap::new(), 1), None);
+    }
+}
@@ -0,0 +1,46 @@
+//! Replacing the listen key, e.g. after it leaked.
+//!
+//! [`Node::rotate_key`](crate::Node::rotate_key) generates a new listen key
+//! and restarts the listen node with it, which publishes the tickets again
+//! with the new endpoint id. The heartbeat then moves the device's Connectors
+//! over to the new id. Clients still holding old tickets keep working for a
+//! grace period: the previous key is served by a second endpoint until then,
+//! and deleted afterwards.
+//!
+//! Rotating again within the grace period ends the grace period of the key
+//! before.
+//!
+//! `datum-connect rotate-key` moves the selected project's Connector itself,
+//! as no heartbeat runs in it. It refuses while an agent serves the repo,
+//! which it asks on the [control socket](crate::control), since that agent
+//! would keep serving the previous key.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use serde::{Deserialize, Serialize};
+
+/// How long the previous key keeps accepting connections by default.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A rotation whose previous key may still be in use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    /// Endpoint id of the previous key.
+    pub previous: EndpointId,
+    pub rotated_at: DateTime<Utc>,
+    /// When the previous key stops accepting connections.
+    pub grace_until: DateTime<Utc>,
+}
+
+impl KeyRotation {
+    pub fn in_grace(&self, now: DateTime<Utc>) -> bool {
+        now < self.grace_until
+    }
+
+    /// Time left until the previous key is retired.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Duration {
+        (self.grace_until - now).to_std().unwrap_or_default()
+    }
+}
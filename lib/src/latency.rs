@@ -0,0 +1,148 @@
+//! Round-trip times of tunnels, to tell a slow tunnel from a slow service.
+//!
+//! Every [`PROBE_INTERVAL`] the RTT of the iroh connection of each client that
+//! used a tunnel recently is read from the path stats QUIC keeps. Acks and
+//! keepalives keep those current, so probing adds no traffic. The RTTs are
+//! summarized per tunnel; the time the service itself takes to answer is not
+//! part of them.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+use crate::{ListenerConnectionInfo, StateWrapper};
+
+/// How often RTTs are read.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// RTTs of the clients of a tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TunnelLatency {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    /// Clients with a measured RTT.
+    pub clients: usize,
+}
+
+impl TunnelLatency {
+    /// A summary of `rtts`, or `None` without any.
+    pub fn from_rtts(rtts: impl IntoIterator<Item = Duration>) -> Option<Self> {
+        let mut rtts = rtts.into_iter();
+        let first = rtts.next()?;
+        let mut this = Self {
+            min: first,
+            avg: first,
+            max: first,
+            clients: 1,
+        };
+        let mut sum = first;
+        for rtt in rtts {
+            this.min = this.min.min(rtt);
+            this.max = this.max.max(rtt);
+            this.clients += 1;
+            sum += rtt;
+        }
+        this.avg = sum / this.clients as u32;
+        Some(this)
+    }
+}
+
+/// Latency of tunnels with recent clients, by tunnel id.
+#[derive(Debug, Clone)]
+pub struct LatencyMonitor {
+    latency: Arc<watch::Sender<BTreeMap<String, TunnelLatency>>>,
+}
+
+impl Default for LatencyMonitor {
+    fn default() -> Self {
+        Self {
+            latency: Arc::new(watch::channel(BTreeMap::new()).0),
+        }
+    }
+}
+
+impl LatencyMonitor {
+    pub fn get(&self, tunnel_id: &str) -> Option<TunnelLatency> {
+        self.latency.borrow().get(tunnel_id).copied()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<BTreeMap<String, TunnelLatency>> {
+        self.latency.subscribe()
+    }
+
+    /// The average RTT over the clients of all tunnels.
+    pub fn overall(&self) -> Option<Duration> {
+        let latency = self.latency.borrow();
+        let clients: usize = latency.values().map(|l| l.clients).sum();
+        let sum: Duration = latency.values().map(|l| l.avg * l.clients as u32).sum();
+        (clients > 0).then(|| sum / clients as u32)
+    }
+
+    fn set(&self, latency: BTreeMap<String, TunnelLatency>) {
+        self.latency.send_if_modified(|current| {
+            let changed = *current != latency;
+            *current = latency;
+            changed
+        });
+    }
+}
+
+/// Summarize the RTTs of the clients `connections` returns per enabled
+/// tunnel, every [`PROBE_INTERVAL`] until the task is dropped.
+pub(crate) async fn run(
+    monitor: LatencyMonitor,
+    state: StateWrapper,
+    connections: impl Fn() -> Vec<ListenerConnectionInfo>,
+) {
+    loop {
+        let connections = connections();
+        let latency = state
+            .get()
+            .proxies
+            .iter()
+            .filter(|p| p.enabled)
+            .filter_map(|p| {
+                let service = p.info.service();
+                let rtts = connections
+                    .iter()
+                    .filter(|c| service.serves(&c.service.host, c.service.port))
+                    .filter_map(|c| c.path.rtt);
+                Some((p.id().to_string(), TunnelLatency::from_rtts(rtts)?))
+            })
+            .collect();
+        monitor.set(latency);
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_rtts() {
+        let ms = Duration::from_millis;
+        assert_eq!(TunnelLatency::from_rtts([]), None);
+        let latency = TunnelLatency::from_rtts([ms(30), ms(10), ms(20)]).unwrap();
+        assert_eq!(
+            latency,
+            TunnelLatency {
+                min: ms(10),
+                avg: ms(20),
+                max: ms(30),
+                clients: 3,
+            }
+        );
+
+        let monitor = LatencyMonitor::default();
+        assert_eq!(monitor.overall(), None);
+        let other = TunnelLatency::from_rtts([ms(60)]).unwrap();
+        monitor.set(BTreeMap::from([
+            ("a".to_string(), latency),
+            ("b".to_string(), other),
+        ]));
+        assert_eq!(monitor.overall(), Some(ms(30)));
+        assert_eq!(monitor.get("b"), Some(other));
+    }
+}
@@ -1,24 +1,71 @@
+pub mod activity;
 mod auth;
+pub mod bandwidth_history;
+mod compression;
 pub mod config;
+pub mod control;
+pub mod crash;
 pub mod datum_apis;
 pub mod datum_cloud;
+pub mod dial;
+pub mod doctor;
+pub mod events;
+mod expiry;
 pub mod gateway;
+pub mod health;
 pub mod heartbeat;
+pub mod host_bridge;
+mod http2_upstream;
+pub mod http_front;
+pub mod ip_filter;
+pub mod key_rotation;
+pub mod latency;
+pub mod local_discovery;
+pub mod local_ports;
+pub mod log_limit;
+pub mod nat64;
 mod node;
+pub mod outbound_proxy;
+pub mod permissions;
+mod preferences;
 pub mod project_control_plane;
+pub mod qr;
 mod repo;
+mod reverse_forward;
+pub mod schedule;
+pub mod secret_store;
+pub mod share;
+mod spool;
 mod state;
+pub mod static_files;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+pub mod telemetry;
+mod ticket_refresh;
 pub mod tunnels;
+#[cfg(unix)]
+pub mod unix_socket;
 pub mod update;
+pub mod updater;
+pub mod usage;
 
-pub use config::{Config, DiscoveryMode, GatewayConfig};
-pub use heartbeat::HeartbeatAgent;
+pub use config::{Config, DiscoveryMode, GatewayConfig, IpFamily, Relays, StaticEndpoint};
+pub use heartbeat::{AgentStatusField, HeartbeatAgent};
+pub use http2_upstream::HTTP2_UPSTREAM_ALPN;
 pub use node::*;
+pub use preferences::{Preferences, StatsdConfig};
 pub use project_control_plane::ProjectControlPlaneClient;
-pub use repo::Repo;
+pub use repo::{Repo, RepoSnapshot};
+pub use reverse_forward::{REVERSE_FORWARD_ALPN, ReverseForwardHandle, ReverseForwardInfo};
 pub use state::*;
-pub use tunnels::{TunnelDeleteOutcome, TunnelService, TunnelSummary};
-pub use update::{UpdateChecker, UpdateInfo, UpdateSettings};
+pub use telemetry::{Telemetry, TelemetryReport};
+pub use tunnels::{
+    BulkFailure, BulkOutcome, DeletedTunnel, ProjectQuotas, QuotaSource, QuotaUsage,
+    TunnelDeleteImpact, TunnelDeleteOutcome, TunnelLimitReached, TunnelService, TunnelSort,
+    TunnelSummary,
+};
+pub use update::{CliUpdate, UpdateChannel, UpdateChecker, UpdateInfo, UpdateSettings};
+pub use updater::{AvailableUpdate, ReleaseManifest, Updater};
 
 /// The root domain for datum connect urls to subdomain from. A proxy URL will
 /// be a three-word-codename subdomain off this URL. eg: "https://vast-gold-mine.iroh.datum.net"
@@ -0,0 +1,258 @@
+//! Finding tunnels on the local network without n0des or Datum Cloud.
+//!
+//! With [`Config::local_discovery`](crate::Config::local_discovery) on, a
+//! listen node announces its enabled tunnels and the addresses it can be
+//! dialed on to a multicast group every few seconds. Connect nodes collect
+//! the announcements, so a tunnel on the same LAN can be joined by its
+//! codename alone and is dialed at the announced addresses, which is handy
+//! for offline demos and labs.
+//!
+//! Everyone on the network sees the announced codenames and targets. Joining
+//! still goes through the listen node's usual access checks.
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId, TransportAddr, discovery::static_provider::StaticProvider,
+};
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tracing::{Instrument, debug, error_span, warn};
+
+use crate::{Advertisment, AdvertismentTicket, StateWrapper};
+
+/// Multicast group and port the announcements are sent to.
+pub const MULTICAST_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 77), 47077);
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a lookup waits for a tunnel to be announced.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(6);
+/// Peers that missed this many announcements are forgotten.
+const ANNOUNCEMENT_TTL: Duration = Duration::from_secs(20);
+const MAX_DATAGRAM: usize = 65_507;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Announcement {
+    endpoint: EndpointId,
+    addrs: Vec<SocketAddr>,
+    tunnels: Vec<Advertisment>,
+}
+
+/// Announce the enabled tunnels of `state` until aborted.
+pub(crate) fn spawn_announcer(endpoint: Endpoint, state: StateWrapper) -> AbortOnDropHandle<()> {
+    let task = tokio::spawn(
+        async move {
+            if let Err(err) = announce(endpoint, state).await {
+                warn!("Local discovery announcements stopped: {err:#}");
+            }
+        }
+        .instrument(error_span!("local-announce")),
+    );
+    AbortOnDropHandle::new(task)
+}
+
+async fn announce(endpoint: Endpoint, state: StateWrapper) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .std_context("Failed to bind local discovery socket")?;
+    loop {
+        let announcement = Announcement {
+            endpoint: endpoint.id(),
+            addrs: endpoint.addr().ip_addrs().copied().collect(),
+            tunnels: state
+                .get()
+                .proxies
+                .iter()
+                .filter(|p| p.enabled)
+                .map(|p| p.info.clone())
+                .collect(),
+        };
+        let bytes = postcard::to_stdvec(&announcement).anyerr()?;
+        if bytes.len() > MAX_DATAGRAM {
+            warn!(
+                len = bytes.len(),
+                "Too many tunnels to announce on the local network"
+            );
+        } else if !announcement.addrs.is_empty()
+            && let Err(err) = socket.send_to(&bytes, MULTICAST_ADDR).await
+        {
+            debug!("Failed to send local announcement: {err}");
+        }
+        n0_future::time::sleep(ANNOUNCE_INTERVAL).await;
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    addrs: Vec<SocketAddr>,
+    tunnels: Vec<Advertisment>,
+    seen: Instant,
+}
+
+/// The tunnels announced on the local network, see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct LocalDiscovery {
+    peers: Arc<Mutex<HashMap<EndpointId, Peer>>>,
+    provider: StaticProvider,
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+
+impl LocalDiscovery {
+    /// Listen for announcements and let `endpoint` dial the announced
+    /// addresses.
+    pub(crate) async fn spawn(endpoint: &Endpoint) -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, MULTICAST_ADDR.port()))
+            .await
+            .std_context("Failed to bind local discovery port")?;
+        socket
+            .join_multicast_v4(*MULTICAST_ADDR.ip(), Ipv4Addr::UNSPECIFIED)
+            .std_context("Failed to join local discovery group")?;
+        let provider = StaticProvider::new();
+        endpoint.discovery().add(provider.clone());
+        let peers: Arc<Mutex<HashMap<EndpointId, Peer>>> = Default::default();
+        let own_id = endpoint.id();
+        let task = tokio::spawn(
+            {
+                let peers = peers.clone();
+                let provider = provider.clone();
+                async move {
+                    let mut buf = vec![0u8; MAX_DATAGRAM];
+                    loop {
+                        let (len, from) = match socket.recv_from(&mut buf).await {
+                            Ok(res) => res,
+                            Err(err) => {
+                                warn!("Local discovery stopped: {err}");
+                                return;
+                            }
+                        };
+                        match postcard::from_bytes::<Announcement>(&buf[..len]) {
+                            Ok(announcement) => {
+                                receive(own_id, &peers, &provider, announcement, Instant::now())
+                            }
+                            Err(err) => debug!(%from, "Ignoring invalid announcement: {err}"),
+                        }
+                    }
+                }
+            }
+            .instrument(error_span!("local-discovery")),
+        );
+        Ok(Self {
+            peers,
+            provider,
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        })
+    }
+
+    /// The tunnels announced recently, by codename.
+    pub fn tunnels(&self) -> Vec<AdvertismentTicket> {
+        let mut peers = self.peers.lock().expect("poisoned");
+        expire(&mut peers, &self.provider, Instant::now());
+        let mut tickets = peers
+            .iter()
+            .flat_map(|(id, peer)| peer.tunnels.iter().map(|ad| ad.ticket(*id)))
+            .collect::<Vec<_>>();
+        tickets.sort_by_key(|ticket| ticket.data.codename());
+        tickets
+    }
+
+    /// The tunnel announced under `codename`, if any.
+    pub fn find(&self, codename: &str) -> Option<AdvertismentTicket> {
+        self.tunnels()
+            .into_iter()
+            .find(|ticket| ticket.data.codename() == codename)
+    }
+}
+
+fn receive(
+    own_id: EndpointId,
+    peers: &Mutex<HashMap<EndpointId, Peer>>,
+    provider: &StaticProvider,
+    announcement: Announcement,
+    now: Instant,
+) {
+    if announcement.endpoint == own_id {
+        return;
+    }
+    let mut peers = peers.lock().expect("poisoned");
+    let known = peers
+        .get(&announcement.endpoint)
+        .is_some_and(|peer| peer.addrs == announcement.addrs);
+    if !known {
+        debug!(
+            remote = %announcement.endpoint.fmt_short(),
+            addrs = ?announcement.addrs,
+            "found endpoint on the local network",
+        );
+        provider.add_endpoint_info(EndpointAddr::from_parts(
+            announcement.endpoint,
+            announcement.addrs.iter().copied().map(TransportAddr::Ip),
+        ));
+    }
+    peers.insert(
+        announcement.endpoint,
+        Peer {
+            addrs: announcement.addrs,
+            tunnels: announcement.tunnels,
+            seen: now,
+        },
+    );
+    expire(&mut peers, provider, now);
+}
+
+fn expire(peers: &mut HashMap<EndpointId, Peer>, provider: &StaticProvider, now: Instant) {
+    peers.retain(|id, peer| {
+        let alive = now.duration_since(peer.seen) < ANNOUNCEMENT_TTL;
+        if !alive {
+            provider.remove_endpoint_info(*id);
+        }
+        alive
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+    use crate::TcpProxyData;
+
+    #[test]
+    fn collects_announcements() {
+        let own_id = SecretKey::generate(&mut rand::rng()).public();
+        let remote = SecretKey::generate(&mut rand::rng()).public();
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8080").unwrap();
+        let ad = Advertisment::new(data, Some("demo".to_string()));
+        let announcement = Announcement {
+            endpoint: remote,
+            addrs: vec!["192.168.1.20:4433".parse().unwrap()],
+            tunnels: vec![ad],
+        };
+        let bytes = postcard::to_stdvec(&announcement).unwrap();
+        let decoded: Announcement = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, announcement);
+
+        let peers = Mutex::new(HashMap::new());
+        let provider = StaticProvider::new();
+        let now = Instant::now();
+        receive(own_id, &peers, &provider, decoded, now);
+        let own = Announcement {
+            endpoint: own_id,
+            ..announcement
+        };
+        receive(own_id, &peers, &provider, own, now);
+        assert_eq!(peers.lock().unwrap().len(), 1);
+        assert!(provider.get_endpoint_info(remote).is_some());
+
+        let mut peers = peers.into_inner().unwrap();
+        expire(&mut peers, &provider, now + ANNOUNCEMENT_TTL);
+        assert!(peers.is_empty());
+        assert!(provider.get_endpoint_info(remote).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+//! Services listening on this machine, to suggest as tunnel targets.
+//!
+//! [`scan`] lists the TCP ports in the listen state, with the name of the
+//! process that opened them where the platform tells: on Linux from
+//! `/proc/net/tcp*` and the `/proc/<pid>/fd` links of processes we may
+//! inspect, on macOS from `lsof`. Elsewhere, or when that fails, a few
+//! ports common for development servers are probed on loopback instead, and
+//! processes stay unnamed.
+//!
+//! Sockets of this process are left out, so the app doesn't suggest
+//! tunneling to its own listeners.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use n0_error::{Result, StdResultExt};
+use tokio::task::JoinSet;
+use tracing::debug;
+
+/// Ports probed when listening sockets can't be listed.
+const COMMON_PORTS: &[u16] = &[
+    3000, 3001, 4000, 4200, 5000, 5173, 5432, 6379, 8000, 8008, 8080, 8081, 8443, 8888, 9000,
+];
+/// How long a probe waits for a connection.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// A TCP port something listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListeningPort {
+    /// The address the socket is bound to, e.g. `0.0.0.0:8080`.
+    pub addr: SocketAddr,
+    pub pid: Option<u32>,
+    /// The name of the process, if we may see it.
+    pub process: Option<String>,
+}
+
+impl ListeningPort {
+    /// The address to forward a tunnel to, as `host:port`. Sockets bound to
+    /// every interface are reached on loopback.
+    pub fn target(&self) -> String {
+        let ip = match self.addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        SocketAddr::new(ip, self.addr.port()).to_string()
+    }
+}
+
+/// The TCP ports listening on this machine, one per port, ordered by port.
+pub async fn scan() -> Vec<ListeningPort> {
+    let listed = tokio::task::spawn_blocking(list)
+        .await
+        .std_context("port scan panicked")
+        .and_then(|res| res);
+    let ports = match listed {
+        Ok(ports) => ports,
+        Err(err) => {
+            debug!("Failed to list listening ports, probing common ones: {err:#}");
+            probe().await
+        }
+    };
+    let own = std::process::id();
+    dedup(ports.into_iter().filter(|port| port.pid != Some(own)))
+}
+
+/// One entry per port, preferring IPv4 and loopback bindings since those
+/// make the plainest targets.
+fn dedup(ports: impl IntoIterator<Item = ListeningPort>) -> Vec<ListeningPort> {
+    let rank = |port: &ListeningPort| (port.addr.is_ipv6(), !port.addr.ip().is_loopback());
+    let mut by_port = BTreeMap::<u16, ListeningPort>::new();
+    for mut port in ports {
+        if let Some(existing) = by_port.remove(&port.addr.port()) {
+            let (keep, other) = if rank(&port) < rank(&existing) {
+                (port, existing)
+            } else {
+                (existing, port)
+            };
+            port = ListeningPort {
+                pid: keep.pid.or(other.pid),
+                process: keep.process.or(other.process),
+                ..keep
+            };
+        }
+        by_port.insert(port.addr.port(), port);
+    }
+    by_port.into_values().collect()
+}
+
+/// Connect to [`COMMON_PORTS`] on loopback and keep the ones that accept.
+async fn probe() -> Vec<ListeningPort> {
+    let mut probes = JoinSet::new();
+    for &port in COMMON_PORTS {
+        probes.spawn(async move {
+            let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+            let connect = tokio::net::TcpStream::connect(addr);
+            match tokio::time::timeout(PROBE_TIMEOUT, connect).await {
+                Ok(Ok(_)) => Some(ListeningPort {
+                    addr,
+                    pid: None,
+                    process: None,
+                }),
+                _ => None,
+            }
+        });
+    }
+    probes.join_all().await.into_iter().flatten().collect()
+}
+
+#[cfg(target_os = "linux")]
+fn list() -> Result<Vec<ListeningPort>> {
+    use std::collections::HashMap;
+
+    let mut sockets = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        match std::fs::read_to_string(table) {
+            Ok(text) => sockets.extend(text.lines().skip(1).filter_map(parse_proc_net_line)),
+            // No IPv6 on this machine.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).std_context("failed to read TCP socket table"),
+        }
+    }
+    let mut owners = HashMap::<u64, (u32, Option<String>)>::new();
+    for entry in std::fs::read_dir("/proc")
+        .std_context("failed to read /proc")?
+        .flatten()
+    {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // Processes of other users can't be inspected, which is fine.
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let mut comm = None;
+        for fd in fds.flatten() {
+            let Ok(link) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = link
+                .to_str()
+                .and_then(|link| link.strip_prefix("socket:["))
+                .and_then(|link| link.strip_suffix(']'))
+                .and_then(|inode| inode.parse::<u64>().ok());
+            if let Some(inode) = inode {
+                let name = comm.get_or_insert_with(|| {
+                    std::fs::read_to_string(entry.path().join("comm"))
+                        .ok()
+                        .map(|name| name.trim().to_string())
+                });
+                owners.insert(inode, (pid, name.clone()));
+            }
+        }
+    }
+    Ok(sockets
+        .into_iter()
+        .map(|(addr, inode)| {
+            let (pid, process) = owners.remove(&inode).unzip();
+            ListeningPort {
+                addr,
+                pid,
+                process: process.flatten(),
+            }
+        })
+        .collect())
+}
+
+/// The local address and socket inode of a `/proc/net/tcp` or `tcp6` line in
+/// the listen state.
+#[cfg(target_os = "linux")]
+fn parse_proc_net_line(line: &str) -> Option<(SocketAddr, u64)> {
+    /// State `0A` is `TCP_LISTEN`.
+    const LISTEN: &str = "0A";
+
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let (local, state, inode) = (fields.get(1)?, fields.get(3)?, fields.get(9)?);
+    if *state != LISTEN {
+        return None;
+    }
+    let (ip, port) = local.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    // Addresses are 32-bit words in host byte order.
+    let words = (0..ip.len())
+        .step_by(8)
+        .map(|i| {
+            Some(
+                u32::from_str_radix(ip.get(i..i + 8)?, 16)
+                    .ok()?
+                    .to_ne_bytes(),
+            )
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let ip = match words.as_slice() {
+        [a] => IpAddr::from(*a),
+        [a, b, c, d] => {
+            let mut octets = [0u8; 16];
+            for (chunk, word) in octets.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(word);
+            }
+            IpAddr::from(octets)
+        }
+        _ => return None,
+    };
+    Some((SocketAddr::new(ip, port), inode.parse().ok()?))
+}
+
+#[cfg(target_os = "macos")]
+fn list() -> Result<Vec<ListeningPort>> {
+    let output = std::process::Command::new("lsof")
+        .args(["-nP", "-iTCP", "-sTCP:LISTEN", "-F", "pcn"])
+        .output()
+        .std_context("failed to run lsof")?;
+    // lsof also exits with 1 when some sockets couldn't be read, so only
+    // fail without any output. Nothing listening ends up probed, which finds
+    // nothing either.
+    if !output.status.success() && output.stdout.is_empty() {
+        n0_error::bail_any!("lsof failed: {}", output.status);
+    }
+    Ok(parse_lsof(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The sockets in `lsof -F pcn` output: `p<pid>` and `c<command>` lines
+/// start a process, `n<address>` lines name its sockets.
+#[cfg(any(target_os = "macos", test))]
+fn parse_lsof(output: &str) -> Vec<ListeningPort> {
+    let mut ports = Vec::new();
+    let (mut pid, mut process) = (None, None);
+    for line in output.lines() {
+        let Some((field, value)) = line.split_at_checked(1) else {
+            continue;
+        };
+        match field {
+            "p" => {
+                pid = value.parse().ok();
+                process = None;
+            }
+            "c" => process = Some(value.to_string()),
+            "n" => {
+                let Some((host, port)) = value.rsplit_once(':') else {
+                    continue;
+                };
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let ip = match host {
+                    "*" => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                    host => host.parse().ok(),
+                };
+                if let (Some(ip), Ok(port)) = (ip, port.parse()) {
+                    ports.push(ListeningPort {
+                        addr: SocketAddr::new(ip, port),
+                        pid,
+                        process: process.clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    ports
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn list() -> Result<Vec<ListeningPort>> {
+    n0_error::bail_any!("listing listening sockets is not supported on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(target_os = "linux", target_endian = "little"))]
+    #[test]
+    fn parses_proc_net_tcp() {
+        let header = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode";
+        assert_eq!(parse_proc_net_line(header), None);
+        let listen = "   0: 0100007F:1435 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 48213 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_proc_net_line(listen),
+            Some(("127.0.0.1:5173".parse().unwrap(), 48213))
+        );
+        let established = "   1: 0100007F:1435 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000  1000        0 48999 1 0000000000000000 20 4 30 10 -1";
+        assert_eq!(parse_proc_net_line(established), None);
+        let v6 = "   0: 00000000000000000000000001000000:1F90 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 51000 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_proc_net_line(v6),
+            Some(("[::1]:8080".parse().unwrap(), 51000))
+        );
+    }
+
+    #[test]
+    fn parses_lsof_and_keeps_one_entry_per_port() {
+        let output =
+            "p812\ncnode\nf23\nn*:3000\nf24\nn[::1]:3000\np901\ncpostgres\nf7\nn127.0.0.1:5432\n";
+        let ports = dedup(parse_lsof(output));
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[0].target(), "127.0.0.1:3000");
+        assert_eq!(ports[0].process.as_deref(), Some("node"));
+        assert_eq!(ports[0].pid, Some(812));
+        assert_eq!(ports[1].target(), "127.0.0.1:5432");
+        assert_eq!(ports[1].process.as_deref(), Some("postgres"));
+    }
+}
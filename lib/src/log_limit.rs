@@ -0,0 +1,134 @@
+//! Rate limiting for warnings logged from hot paths.
+//!
+//! During an outage the same warning can fire for every request or every
+//! backoff tick. [`warn_limited!`] logs a call site at most once per
+//! interval and adds how many similar warnings were dropped since the last
+//! one, as `suppressed_similar=1.2k`.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How often each call site of [`warn_limited!`] may log.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+static GLOBAL: LazyLock<LogLimiter> = LazyLock::new(|| LogLimiter::new(INTERVAL));
+
+/// The limiter used by [`warn_limited!`].
+pub fn global() -> &'static LogLimiter {
+    &GLOBAL
+}
+
+/// Log a warning at most once per minute for `key`.
+///
+/// ```ignore
+/// warn_limited!("heartbeat.lease_renew", %project_id, "lease renew failed: {err:#}");
+/// ```
+macro_rules! warn_limited {
+    ($key:expr, $($arg:tt)+) => {
+        match $crate::log_limit::global().check($key) {
+            Some(suppressed) if suppressed.0 > 0 => {
+                ::tracing::warn!(suppressed_similar = %suppressed, $($arg)+)
+            }
+            Some(_) => ::tracing::warn!($($arg)+),
+            None => {}
+        }
+    };
+}
+pub(crate) use warn_limited;
+
+#[derive(Debug)]
+pub struct LogLimiter {
+    interval: Duration,
+    sites: Mutex<HashMap<&'static str, Site>>,
+}
+
+#[derive(Debug)]
+struct Site {
+    last_logged: Instant,
+    suppressed: u64,
+}
+
+impl LogLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            sites: Default::default(),
+        }
+    }
+
+    /// Whether `key` may log now. Returns how many events were suppressed
+    /// since it last did, or `None` if this one is suppressed too.
+    pub fn check(&self, key: &'static str) -> Option<Suppressed> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &'static str, now: Instant) -> Option<Suppressed> {
+        let mut sites = self.sites.lock().expect("poisoned");
+        match sites.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(Site {
+                    last_logged: now,
+                    suppressed: 0,
+                });
+                Some(Suppressed(0))
+            }
+            Entry::Occupied(mut entry) => {
+                let site = entry.get_mut();
+                if now.duration_since(site.last_logged) >= self.interval {
+                    site.last_logged = now;
+                    Some(Suppressed(std::mem::take(&mut site.suppressed)))
+                } else {
+                    site.suppressed += 1;
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// A count of suppressed events, shown as `950`, `1.2k` or `3.4M`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suppressed(pub u64);
+
+impl fmt::Display for Suppressed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = self.0;
+        if n < 1_000 {
+            write!(f, "{n}")
+        } else if n < 1_000_000 {
+            write!(f, "{:.1}k", n as f64 / 1_000.0)
+        } else {
+            write!(f, "{:.1}M", n as f64 / 1_000_000.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_once_per_interval_with_a_count() {
+        let limiter = LogLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(limiter.check_at("a", at(0)), Some(Suppressed(0)));
+        assert_eq!(limiter.check_at("a", at(1)), None);
+        assert_eq!(limiter.check_at("a", at(59)), None);
+        assert_eq!(limiter.check_at("b", at(1)), Some(Suppressed(0)));
+        assert_eq!(limiter.check_at("a", at(60)), Some(Suppressed(2)));
+        assert_eq!(limiter.check_at("a", at(121)), Some(Suppressed(0)));
+    }
+
+    #[test]
+    fn formats_large_counts() {
+        assert_eq!(Suppressed(950).to_string(), "950");
+        assert_eq!(Suppressed(1_234).to_string(), "1.2k");
+        assert_eq!(Suppressed(3_400_000).to_string(), "3.4M");
+    }
+}
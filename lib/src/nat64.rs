@@ -0,0 +1,96 @@
+//! Reaching IPv4 addresses from IPv6-only hosts through NAT64.
+//!
+//! On an IPv6-only network with NAT64, an IPv4 address is reached by
+//! embedding it in the network's /96 NAT64 prefix. Names are handled by
+//! DNS64 already; this is for IPv4 literals from configuration, like a DNS
+//! resolver address. The prefix is either configured or discovered as in
+//! RFC 7050, by resolving `ipv4only.arpa`.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use iroh_relay::dns::DnsResolver;
+use tracing::debug;
+
+/// The well-known NAT64 prefix `64:ff9b::/96` from RFC 6052.
+pub const WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// The name RFC 7050 reserves for NAT64 prefix discovery.
+const DISCOVERY_NAME: &str = "ipv4only.arpa";
+/// The IPv4 addresses `ipv4only.arpa` resolves to.
+const DISCOVERY_ADDRS: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Embed `v4` in the /96 `prefix`.
+pub fn synthesize(prefix: Ipv6Addr, v4: Ipv4Addr) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[12..].copy_from_slice(&v4.octets());
+    Ipv6Addr::from(octets)
+}
+
+/// `addr` with an IPv4 address replaced by its NAT64 form; IPv6 is unchanged.
+pub fn map_socket_addr(prefix: Ipv6Addr, addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(v4) => SocketAddr::new(synthesize(prefix, v4).into(), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+/// The /96 prefix of a synthesized `ipv4only.arpa` address, if it is one.
+fn prefix_of(addr: Ipv6Addr) -> Option<Ipv6Addr> {
+    let octets = addr.octets();
+    let embedded = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    if !DISCOVERY_ADDRS.contains(&embedded) {
+        return None;
+    }
+    let mut prefix = [0u8; 16];
+    prefix[..12].copy_from_slice(&octets[..12]);
+    Some(Ipv6Addr::from(prefix))
+}
+
+/// Discover the network's NAT64 prefix. `None` if the network has no DNS64.
+pub async fn discover_prefix(resolver: &DnsResolver) -> Option<Ipv6Addr> {
+    let addrs = match resolver
+        .lookup_ipv6(DISCOVERY_NAME, DISCOVERY_TIMEOUT)
+        .await
+    {
+        Ok(addrs) => addrs,
+        Err(err) => {
+            debug!("no NAT64 prefix found: {err:#}");
+            return None;
+        }
+    };
+    addrs.into_iter().find_map(|addr| match addr {
+        IpAddr::V6(v6) => prefix_of(v6),
+        IpAddr::V4(_) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthesizes_and_recovers_prefix() {
+        let v4 = Ipv4Addr::new(192, 0, 2, 33);
+        assert_eq!(
+            synthesize(WELL_KNOWN_PREFIX, v4),
+            "64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()
+        );
+
+        let prefix: Ipv6Addr = "2001:db8:64::".parse().unwrap();
+        let discovered = synthesize(prefix, DISCOVERY_ADDRS[0]);
+        assert_eq!(prefix_of(discovered), Some(prefix));
+        assert_eq!(prefix_of(synthesize(prefix, v4)), None);
+
+        let v6: SocketAddr = "[2001:db8::1]:53".parse().unwrap();
+        assert_eq!(map_socket_addr(prefix, v6), v6);
+        assert_eq!(
+            map_socket_addr(prefix, (v4, 53).into()),
+            "[2001:db8:64::c000:221]:53".parse().unwrap()
+        );
+    }
+}
@@ -1,26 +1,65 @@
-use std::{fmt::Debug, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use iroh::{
-    Endpoint, EndpointId, SecretKey, discovery::dns::DnsDiscovery, endpoint::default_relay_mode,
+    Endpoint, EndpointId, SecretKey, Watcher,
+    discovery::{dns::DnsDiscovery, pkarr::PkarrPublisher, static_provider::StaticProvider},
+    endpoint::{ConnectionType, TransportConfig},
     protocol::Router,
 };
 use iroh_n0des::ApiSecret;
 use iroh_proxy_utils::{ALPN as IROH_HTTP_CONNECT_ALPN, HttpProxyRequest, HttpProxyRequestKind};
 use iroh_proxy_utils::{
-    downstream::{DownstreamProxy, EndpointAuthority, ProxyMode},
+    Authority, HttpRequest, HttpRequestKind,
+    downstream::{
+        Deny, DownstreamProxy, EndpointAuthority, HttpProxyOpts, ProxyMode, RequestHandler, SrcAddr,
+    },
     upstream::{AuthError, AuthHandler, UpstreamProxy},
 };
 use iroh_relay::dns::{DnsProtocol, DnsResolver};
-use n0_error::{Result, StackResultExt, StdResultExt};
+use n0_error::{Result, StackResultExt, StdResultExt, stack_error};
 use n0_future::task::AbortOnDropHandle;
 use tokio::{
     net::TcpListener,
-    sync::{broadcast, futures::Notified},
+    sync::{broadcast, futures::Notified, watch},
     task::JoinHandle,
 };
 use tracing::{Instrument, debug, error_span, info, instrument, warn};
 
-use crate::{ProxyState, Repo, StateWrapper, TcpProxyData, config::Config};
+#[cfg(unix)]
+use crate::unix_socket::SocketBridge;
+use crate::{
+    Advertisment, AdvertismentTicket, IssuedShare, ProxyState, Repo, StateWrapper, TcpProxyData,
+    TunnelTimeouts,
+    activity::TunnelActivity,
+    bandwidth_history::{self, BandwidthPoint, HistoryRange},
+    config::{Config, IpFamily},
+    events::{EventKind, EventLog},
+    expiry,
+    health::{self, HealthCheck, HealthMonitor, TargetHealth},
+    host_bridge::HostBridge,
+    http_front::{HeaderRule, HttpFront, HttpFrontProxy, TunnelAuth},
+    http2_upstream::{HTTP2_UPSTREAM_ALPN, Http2Upstream},
+    join_host_port,
+    key_rotation::KeyRotation,
+    latency::{self, LatencyMonitor, TunnelLatency},
+    local_discovery::{self, LocalDiscovery},
+    reverse_forward::{
+        self, REVERSE_FORWARD_ALPN, ReverseForwardHandle, ReverseForwardInfo,
+        ReverseForwardProtocol,
+    },
+    schedule::{self, TunnelSchedule},
+    static_files::FileServer,
+    ticket_refresh,
+    usage::{self, TransferQuota, TunnelUsage},
+};
 
 #[derive(Debug, Clone)]
 pub struct Node {
@@ -34,12 +73,133 @@ impl Node {
         let connect = ConnectNode::new(repo).await?;
         Ok(Self { listen, connect })
     }
+
+    pub fn proxies(&self) -> Vec<ProxyState> {
+        self.listen.proxies()
+    }
+
+    /// Watch the local proxies; fires on add, remove, enable and disable.
+    pub fn proxies_watch(&self) -> watch::Receiver<Vec<ProxyState>> {
+        self.listen.proxies_watch()
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connect.connections()
+    }
+
+    /// Watch the outbound connections; fires when one is opened or closed.
+    pub fn connections_watch(&self) -> watch::Receiver<Vec<ConnectionInfo>> {
+        self.connect.connections_watch()
+    }
+
+    /// Remote clients currently using the local proxies.
+    pub fn listener_connections(&self) -> Vec<ListenerConnectionInfo> {
+        self.listen.connections()
+    }
+
+    /// Replace the listen key, see [`crate::key_rotation`].
+    ///
+    /// The listen node is restarted with the new key, and the previous one is
+    /// served for `grace`. Clones of the previous [`ListenNode`] stop serving.
+    pub async fn rotate_key(&mut self, grace: Duration) -> Result<KeyRotation> {
+        let repo = self.listen.repo.clone();
+        self.listen
+            .router
+            .shutdown()
+            .await
+            .std_context("Failed to stop the listen node")?;
+        // The new node serves them again, with the same keys.
+        self.listen.stop_pinned_endpoints().await;
+        let rotation = repo.rotate_listen_key(grace).await?;
+        self.listen = ListenNode::new(repo).await?;
+        Ok(rotation)
+    }
+}
+
+/// How traffic to a remote endpoint currently travels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathKind {
+    /// Straight to the remote over UDP.
+    Direct,
+    /// Through a relay server.
+    Relay,
+    /// Relayed while a direct path is being confirmed.
+    Mixed,
+    /// No path is currently known.
+    #[default]
+    None,
+}
+
+impl PathKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PathKind::Direct => "direct",
+            PathKind::Relay => "relay",
+            PathKind::Mixed => "mixed",
+            PathKind::None => "none",
+        }
+    }
+}
+
+/// The current network path to a remote endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathInfo {
+    pub kind: PathKind,
+    /// Round-trip time on the path in use, if it has been measured.
+    pub rtt: Option<Duration>,
+}
+
+impl PathInfo {
+    pub(crate) fn for_remote(endpoint: &Endpoint, remote_id: EndpointId) -> Self {
+        let kind = match endpoint
+            .conn_type(remote_id)
+            .map(|mut conn_type| conn_type.get())
+        {
+            Some(ConnectionType::Direct(_)) => PathKind::Direct,
+            Some(ConnectionType::Relay(_)) => PathKind::Relay,
+            Some(ConnectionType::Mixed(..)) => PathKind::Mixed,
+            Some(ConnectionType::None) | None => PathKind::None,
+        };
+        Self {
+            kind,
+            rtt: endpoint.latency(remote_id),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct MetricsUpdate {
     pub send: u64,
     pub recv: u64,
+    /// Average round-trip time to the clients of all tunnels, see
+    /// [`crate::latency`].
+    pub rtt: Option<Duration>,
+}
+
+/// Turns running byte totals into a rate about once a second.
+#[derive(Debug, Default)]
+struct ThroughputSampler {
+    last: Option<(Instant, u64)>,
+}
+
+impl ThroughputSampler {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    fn sample(&mut self, update: MetricsUpdate) -> Option<u64> {
+        let now = Instant::now();
+        let total = update.send + update.recv;
+        let Some((at, last_total)) = self.last else {
+            self.last = Some((now, total));
+            return None;
+        };
+        let elapsed = now.duration_since(at);
+        if elapsed < Self::INTERVAL {
+            return None;
+        }
+        self.last = Some((now, total));
+        let bytes = total.saturating_sub(last_total);
+        Some((bytes as f64 / elapsed.as_secs_f64()) as u64)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,9 +207,33 @@ pub struct ListenNode {
     router: Router,
     state: StateWrapper,
     repo: Repo,
-    _n0des: Option<Arc<iroh_n0des::Client>>,
+    n0des: Option<Arc<iroh_n0des::Client>>,
     metrics_tx: broadcast::Sender<MetricsUpdate>,
     _metrics_task: Arc<AbortOnDropHandle<()>>,
+    clients: InboundClients,
+    applied_timeouts: TunnelTimeouts,
+    reverse_forwards: ReverseForwardProtocol,
+    file_servers: Arc<Mutex<HashMap<SocketAddr, FileServer>>>,
+    #[cfg(unix)]
+    socket_bridges: Arc<Mutex<HashMap<SocketAddr, SocketBridge>>>,
+    host_bridges: Arc<Mutex<HashMap<SocketAddr, HostBridge>>>,
+    front_proxies: Arc<Mutex<HashMap<String, HttpFrontProxy>>>,
+    /// Endpoints of proxies with a pinned key, by proxy id.
+    pinned_endpoints: Arc<Mutex<HashMap<String, Router>>>,
+    health: HealthMonitor,
+    latency: LatencyMonitor,
+    _latency_task: Arc<AbortOnDropHandle<()>>,
+    _health_task: Arc<AbortOnDropHandle<()>>,
+    _expiry_task: Arc<AbortOnDropHandle<()>>,
+    _schedule_task: Arc<AbortOnDropHandle<()>>,
+    _quota_task: Arc<AbortOnDropHandle<()>>,
+    _history_task: Arc<AbortOnDropHandle<()>>,
+    _ticket_refresh_task: Arc<AbortOnDropHandle<()>>,
+    /// Set with [`Config::local_discovery`].
+    _announce_task: Option<Arc<AbortOnDropHandle<()>>>,
+    /// Set while the previous key of a rotation is still served.
+    rotation: Option<KeyRotation>,
+    _previous_key_task: Option<Arc<AbortOnDropHandle<()>>>,
 }
 
 impl ListenNode {
@@ -63,26 +247,79 @@ impl ListenNode {
         repo: Repo,
         n0des_api_secret: Option<ApiSecret>,
     ) -> Result<Self> {
-        let config = repo.config().await?;
+        let mut config = repo.config().await?;
+        config.discover_nat64_prefix().await;
         let secret_key = repo.listen_key().await?;
-        let endpoint = build_endpoint(secret_key, &config).await?;
-        let n0des = build_n0des_client_opt(&endpoint, n0des_api_secret).await;
         let state = repo.load_state().await?;
+        let timeouts = TunnelTimeouts::combine(
+            state
+                .get()
+                .proxies
+                .iter()
+                .filter(|p| p.enabled)
+                .map(|p| &p.timeouts),
+        );
+        let endpoint = build_endpoint(secret_key, &config, timeouts).await?;
+        let n0des = build_n0des_client_opt(&endpoint, n0des_api_secret).await;
+
+        let clients = InboundClients::new(repo.events().clone());
+        let auth = TrackingAuth {
+            endpoint: endpoint.clone(),
+            state: state.clone(),
+            repo: repo.clone(),
+            clients: clients.clone(),
+        };
+        let upstream_proxy = UpstreamProxy::new(auth.clone())?;
+        let http2_upstream = Http2Upstream::new(auth, &config);
 
-        let upstream_proxy = UpstreamProxy::new(state.clone())?;
+        let reverse_forwards = ReverseForwardProtocol::new(state.clone());
+
+        let (rotation, previous_key_task) = match repo.previous_listen_key().await? {
+            Some((key, rotation)) => {
+                let task = serve_previous_key(
+                    key,
+                    rotation.clone(),
+                    &config,
+                    timeouts,
+                    state.clone(),
+                    repo.clone(),
+                    clients.clone(),
+                )
+                .await?;
+                (Some(rotation), Some(Arc::new(task)))
+            }
+            None => (None, None),
+        };
 
         let router = Router::builder(endpoint)
             .accept(IROH_HTTP_CONNECT_ALPN, upstream_proxy)
+            .accept(HTTP2_UPSTREAM_ALPN, http2_upstream)
+            .accept(REVERSE_FORWARD_ALPN, reverse_forwards.clone())
             .spawn();
 
         let (metrics_tx, _) = broadcast::channel(1);
 
+        let latency = LatencyMonitor::default();
+        let latency_task = tokio::spawn(
+            latency::run(latency.clone(), state.clone(), {
+                let endpoint = router.endpoint().clone();
+                let clients = clients.clone();
+                move || clients.connections(&endpoint)
+            })
+            .instrument(error_span!("latency")),
+        );
+
         let metrics_update_interval = Duration::from_millis(100);
         let metrics_task = tokio::spawn(
             {
                 let endpoint = router.endpoint().clone();
                 let metrics_tx = metrics_tx.clone();
+                let activity = repo.activity().clone();
+                let usage = repo.usage().clone();
+                let latency = latency.clone();
                 async move {
+                    let mut throughput = ThroughputSampler::default();
+                    let mut last_update = None::<MetricsUpdate>;
                     loop {
                         let metrics = endpoint.metrics();
                         let recv_total = metrics.magicsock.recv_data_ipv4.get()
@@ -92,7 +329,18 @@ impl ListenNode {
                         let update = MetricsUpdate {
                             send: send_total,
                             recv: recv_total,
+                            rtt: latency.overall(),
                         };
+                        if let Some(bytes_per_sec) = throughput.sample(update) {
+                            activity.record_throughput(bytes_per_sec);
+                        }
+                        if let Some(last) = last_update.replace(update) {
+                            usage.record_transfer(
+                                send_total.saturating_sub(last.send),
+                                recv_total.saturating_sub(last.recv),
+                                chrono::Utc::now(),
+                            );
+                        }
                         metrics_tx.send(update).ok();
                         n0_future::time::sleep(metrics_update_interval).await;
                     }
@@ -101,93 +349,1496 @@ impl ListenNode {
             .instrument(error_span!("metrics")),
         );
 
-        let this = Self {
-            repo,
-            router,
-            state,
-            metrics_tx,
-            _metrics_task: Arc::new(AbortOnDropHandle::new(metrics_task)),
-            _n0des: n0des,
-        };
-        Ok(this)
+        let health = HealthMonitor::default();
+        let health_task = tokio::spawn(
+            health::run(
+                state.clone(),
+                repo.clone(),
+                n0des.clone(),
+                router.endpoint().id(),
+                health.clone(),
+            )
+            .instrument(error_span!("health")),
+        );
+        let expiry_task = tokio::spawn(
+            expiry::run(
+                state.clone(),
+                repo.clone(),
+                n0des.clone(),
+                router.endpoint().id(),
+            )
+            .instrument(error_span!("expiry")),
+        );
+        let schedule_task = tokio::spawn(
+            schedule::run(
+                state.clone(),
+                repo.clone(),
+                n0des.clone(),
+                router.endpoint().id(),
+            )
+            .instrument(error_span!("schedule")),
+        );
+        let quota_task = tokio::spawn(
+            usage::run(
+                state.clone(),
+                repo.clone(),
+                n0des.clone(),
+                router.endpoint().id(),
+            )
+            .instrument(error_span!("quota")),
+        );
+        let history_task = tokio::spawn(
+            bandwidth_history::run(repo.clone()).instrument(error_span!("bandwidth_history")),
+        );
+        let ticket_refresh_task = tokio::spawn(
+            ticket_refresh::run(
+                state.clone(),
+                n0des.clone(),
+                router.endpoint().id(),
+                health.clone(),
+                config
+                    .ticket_refresh_secs
+                    .map_or(ticket_refresh::DEFAULT_INTERVAL, Duration::from_secs),
+            )
+            .instrument(error_span!("ticket_refresh")),
+        );
+        let announce_task = config.local_discovery.then(|| {
+            Arc::new(local_discovery::spawn_announcer(
+                router.endpoint().clone(),
+                state.clone(),
+            ))
+        });
+
+        let this = Self {
+            repo,
+            router,
+            state,
+            metrics_tx,
+            _metrics_task: Arc::new(AbortOnDropHandle::new(metrics_task)),
+            n0des,
+            clients,
+            applied_timeouts: timeouts,
+            reverse_forwards,
+            file_servers: Default::default(),
+            #[cfg(unix)]
+            socket_bridges: Default::default(),
+            host_bridges: Default::default(),
+            front_proxies: Default::default(),
+            pinned_endpoints: Default::default(),
+            health,
+            latency,
+            _latency_task: Arc::new(AbortOnDropHandle::new(latency_task)),
+            _health_task: Arc::new(AbortOnDropHandle::new(health_task)),
+            _expiry_task: Arc::new(AbortOnDropHandle::new(expiry_task)),
+            _schedule_task: Arc::new(AbortOnDropHandle::new(schedule_task)),
+            _quota_task: Arc::new(AbortOnDropHandle::new(quota_task)),
+            _history_task: Arc::new(AbortOnDropHandle::new(history_task)),
+            _ticket_refresh_task: Arc::new(AbortOnDropHandle::new(ticket_refresh_task)),
+            _announce_task: announce_task,
+            rotation,
+            _previous_key_task: previous_key_task,
+        };
+        this.restore_file_servers().await;
+        #[cfg(unix)]
+        this.restore_socket_bridges().await;
+        this.restore_host_bridges().await;
+        this.restore_front_proxies().await;
+        this.restore_pinned_endpoints().await;
+        if let Err(err) = this.cleanup_stale_tickets().await {
+            warn!("Failed to clean up stale tickets: {err:#}");
+        }
+        if let Err(err) = this.republish_tickets().await {
+            warn!("Failed to republish tickets: {err:#}");
+        }
+        Ok(this)
+    }
+
+    pub fn state_updated(&self) -> Notified<'_> {
+        self.state.updated()
+    }
+
+    pub fn state(&self) -> &StateWrapper {
+        &self.state
+    }
+
+    pub fn metrics(&self) -> broadcast::Receiver<MetricsUpdate> {
+        self.metrics_tx.subscribe()
+    }
+
+    /// The event log of this node's repo.
+    pub fn events(&self) -> &EventLog {
+        self.repo.events()
+    }
+
+    /// Lifecycle and traffic milestones of a tunnel.
+    pub fn activity(&self, tunnel_id: &str) -> Option<TunnelActivity> {
+        self.repo.activity().get(tunnel_id)
+    }
+
+    /// The latest health check result of a tunnel's target, if it is checked.
+    pub fn target_health(&self, tunnel_id: &str) -> Option<TargetHealth> {
+        self.health.get(tunnel_id)
+    }
+
+    /// Round-trip times to the recent clients of a tunnel, if it has any.
+    pub fn tunnel_latency(&self, tunnel_id: &str) -> Option<TunnelLatency> {
+        self.latency.get(tunnel_id)
+    }
+
+    /// Round-trip times of all tunnels with recent clients, by tunnel id.
+    pub fn tunnel_latency_watch(&self) -> watch::Receiver<BTreeMap<String, TunnelLatency>> {
+        self.latency.watch()
+    }
+
+    /// Health check results of all checked tunnels, by tunnel id.
+    pub fn target_health_watch(&self) -> watch::Receiver<BTreeMap<String, TargetHealth>> {
+        self.health.watch()
+    }
+
+    /// Set or remove the health check of a proxy. Returns false if there is
+    /// no such proxy.
+    pub async fn set_health_check(
+        &self,
+        resource_id: &str,
+        check: Option<HealthCheck>,
+    ) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_health_check(resource_id, check)
+            })
+            .await
+    }
+
+    pub fn proxies(&self) -> Vec<ProxyState> {
+        self.state.get().proxies.to_vec()
+    }
+
+    pub fn proxies_watch(&self) -> watch::Receiver<Vec<ProxyState>> {
+        self.state.proxies_watch()
+    }
+
+    pub fn proxy_by_id(&self, id: &str) -> Option<ProxyState> {
+        self.state
+            .get()
+            .proxies
+            .iter()
+            .find(|p| p.id() == id)
+            .cloned()
+    }
+
+    /// Fails with [`AlreadyListening`] if a proxy other than `resource_id`
+    /// already serves `host:port`.
+    pub fn check_available(
+        &self,
+        resource_id: Option<&str>,
+        host: &str,
+        port: u16,
+    ) -> Result<(), AlreadyListening> {
+        let state = self.state.get();
+        match state.listener_conflict(resource_id, host, port) {
+            Some(existing) => Err(AlreadyListening {
+                address: join_host_port(host, port),
+                tunnel_id: existing.id().to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Store a proxy and publish its ticket to n0des, if connected and the
+    /// proxy is enabled.
+    ///
+    /// Fails with [`AlreadyListening`] if another proxy serves its target.
+    pub async fn set_proxy(&self, proxy: ProxyState) -> Result<()> {
+        let service = proxy.info.service();
+        self.check_available(Some(proxy.id()), &service.host, service.port)?;
+        self.repo.activity().record_created(proxy.id());
+        self.state
+            .update(&self.repo, |state| state.set_proxy(proxy.clone()))
+            .await?;
+        if let Some(n0des) = &self.n0des
+            && proxy.enabled
+        {
+            n0des
+                .publish_ticket(
+                    proxy.id().to_string(),
+                    proxy.ticket(self.endpoint_id()).published_now(),
+                )
+                .await
+                .std_context("Failed to publish ticket")?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_proxy_state(&self, proxy: ProxyState) -> Result<()> {
+        self.repo.activity().record_created(proxy.id());
+        self.state
+            .update(&self.repo, |state| state.set_proxy(proxy))
+            .await?;
+        Ok(())
+    }
+
+    /// Pause or resume a proxy without removing it. A paused proxy keeps its
+    /// id and codename, but the listener refuses its clients and its ticket
+    /// is taken out of n0des. Returns false if there is no such proxy.
+    pub async fn set_enabled(&self, resource_id: &str, enabled: bool) -> Result<bool> {
+        let Some(current) = self.proxy_by_id(resource_id) else {
+            return Ok(false);
+        };
+        if current.enabled == enabled {
+            return Ok(true);
+        }
+        let Some(proxy) = self
+            .state
+            .update(&self.repo, |state| state.set_enabled(resource_id, enabled))
+            .await?
+        else {
+            return Ok(false);
+        };
+        health::set_published(self.n0des.as_deref(), &proxy, self.endpoint_id(), enabled).await;
+        let tunnel_id = resource_id.to_string();
+        self.repo.events().record(if enabled {
+            EventKind::TunnelEnabled { tunnel_id }
+        } else {
+            EventKind::TunnelDisabled { tunnel_id }
+        });
+        Ok(true)
+    }
+
+    /// Remove a proxy and unpublish its ticket. Use [`Self::set_enabled`] to
+    /// stop serving it for a while instead.
+    pub async fn remove_proxy(&self, resource_id: &str) -> Result<Option<ProxyState>> {
+        debug!(%resource_id, "removing proxy {resource_id}");
+        let res = self
+            .state
+            .update(&self.repo, move |state| state.remove_proxy(resource_id))
+            .await;
+        debug!(%resource_id, "removed {res:?}");
+        if let Ok(Some(proxy)) = &res {
+            self.repo.activity().remove(proxy.id());
+            self.repo.usage().remove(proxy.id());
+            self.repo.bandwidth_history().remove(proxy.id());
+        }
+        // The proxy is gone from state either way; a ticket left behind here
+        // is unpublished by the cleanup at the next startup.
+        if let Ok(Some(proxy)) = &res
+            && let Some(n0des) = &self.n0des
+            && let Err(err) = n0des
+                .unpublish_ticket::<AdvertismentTicket>(proxy.id().to_string())
+                .await
+        {
+            warn!(%resource_id, "Failed to unpublish ticket: {err:#}");
+        }
+        if let Ok(Some(proxy)) = &res
+            && proxy.serve_dir.is_some()
+            && let Ok(addr) = proxy.info.service().address().parse()
+        {
+            self.stop_file_server(addr);
+        }
+        #[cfg(unix)]
+        if let Ok(Some(proxy)) = &res
+            && proxy.unix_socket.is_some()
+            && let Ok(addr) = proxy.info.service().address().parse()
+        {
+            self.stop_socket_bridge(addr);
+        }
+        if let Ok(Some(proxy)) = &res
+            && proxy.host_target.is_some()
+            && let Ok(addr) = proxy.info.service().address().parse()
+        {
+            self.stop_host_bridge(addr);
+        }
+        if let Ok(Some(proxy)) = &res
+            && proxy.http_front.is_some()
+        {
+            self.front_proxies
+                .lock()
+                .expect("poisoned")
+                .remove(proxy.id());
+        }
+        if let Ok(Some(proxy)) = &res
+            && proxy.pinned_endpoint.is_some()
+        {
+            self.stop_pinned_endpoint(proxy.id()).await;
+            if let Err(err) = self.repo.delete_tunnel_key(proxy.id()).await {
+                warn!(%resource_id, "Failed to delete the pinned key: {err:#}");
+            }
+        }
+        res
+    }
+
+    /// Set the header rules of a proxy. An empty list removes them.
+    ///
+    /// See [`Self::update_http_front`] for how this changes the target.
+    pub async fn set_header_rules(
+        &self,
+        resource_id: &str,
+        rules: Vec<HeaderRule>,
+    ) -> Result<ProxyState> {
+        for rule in &rules {
+            rule.validate()?;
+        }
+        self.update_http_front(resource_id, |front| front.rules = rules)
+            .await
+    }
+
+    /// Require credentials for a proxy, or stop requiring them with `None`.
+    ///
+    /// See [`Self::update_http_front`] for how this changes the target.
+    pub async fn set_tunnel_auth(
+        &self,
+        resource_id: &str,
+        auth: Option<TunnelAuth>,
+    ) -> Result<ProxyState> {
+        self.update_http_front(resource_id, |front| front.auth = auth)
+            .await
+    }
+
+    /// Replace the tags of a local proxy. Does nothing if there is none.
+    pub async fn set_tags(&self, resource_id: &str, tags: Vec<String>) -> Result<()> {
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(proxy) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    proxy.tags = tags;
+                }
+            })
+            .await
+    }
+
+    /// Set or remove the schedule of a proxy. Returns false if there is no
+    /// such proxy.
+    pub async fn set_schedule(
+        &self,
+        resource_id: &str,
+        schedule: Option<TunnelSchedule>,
+    ) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_schedule(resource_id, schedule)
+            })
+            .await
+    }
+
+    /// Set or clear the bytes a proxy may transfer before it is disabled.
+    /// Returns false if there is no such proxy.
+    pub async fn set_transfer_quota(
+        &self,
+        resource_id: &str,
+        quota: Option<TransferQuota>,
+    ) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_transfer_quota(resource_id, quota)
+            })
+            .await
+    }
+
+    /// Bytes a proxy transferred, see [`crate::usage`].
+    pub fn transfer_usage(&self, resource_id: &str) -> TunnelUsage {
+        self.repo.usage().get(resource_id).unwrap_or_default()
+    }
+
+    /// Throughput of a proxy over `range`, oldest first, see
+    /// [`crate::bandwidth_history`].
+    pub fn bandwidth_history(&self, resource_id: &str, range: HistoryRange) -> Vec<BandwidthPoint> {
+        self.repo
+            .bandwidth_history()
+            .query(resource_id, range, chrono::Utc::now())
+    }
+
+    /// Write a CSV report of what one proxy, or all of them, transferred
+    /// since `since` to the downloads folder. Returns the file's path.
+    pub async fn export_usage_csv(
+        &self,
+        resource_id: Option<&str>,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<PathBuf> {
+        let now = chrono::Utc::now();
+        let records = self
+            .repo
+            .bandwidth_history()
+            .records(resource_id, since, now);
+        let mut csv = Vec::new();
+        bandwidth_history::write_csv(&records, &self.state.get().proxy_labels(), &mut csv)?;
+        let dir = dirs_next::download_dir().unwrap_or_else(|| self.repo.path().join("reports"));
+        tokio::fs::create_dir_all(&dir).await?;
+        let name = format!(
+            "datum-usage-{}-{}.csv",
+            resource_id.unwrap_or("all"),
+            now.format("%Y%m%d-%H%M%S")
+        );
+        let path = dir.join(name);
+        tokio::fs::write(&path, csv)
+            .await
+            .with_std_context(|_| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Serve a proxy to clients with a direct path only. Returns false if
+    /// there is no such proxy.
+    pub async fn set_direct_only(&self, resource_id: &str, direct_only: bool) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_direct_only(resource_id, direct_only)
+            })
+            .await
+    }
+
+    /// Start connections to a proxy's service with a PROXY protocol header
+    /// naming the client. Returns false if there is no such proxy.
+    pub async fn set_proxy_protocol(
+        &self,
+        resource_id: &str,
+        proxy_protocol: bool,
+    ) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_proxy_protocol(resource_id, proxy_protocol)
+            })
+            .await
+    }
+
+    /// Serve a proxy under an endpoint with its own key, or under the listen
+    /// endpoint again. Returns false if there is no such proxy.
+    ///
+    /// The key is kept in the repo, so the proxy's tickets name the same
+    /// endpoint across restarts and listen key rotations, and gateways and
+    /// clients holding one keep reaching it. The ticket is published again
+    /// with the new endpoint.
+    pub async fn set_key_pinned(&self, resource_id: &str, pinned: bool) -> Result<bool> {
+        let Some(current) = self.proxy_by_id(resource_id) else {
+            return Ok(false);
+        };
+        if current.pinned_endpoint.is_some() == pinned {
+            return Ok(true);
+        }
+        let endpoint_id = if pinned {
+            let key = self.repo.tunnel_key(resource_id).await?;
+            let endpoint_id = key.public();
+            self.start_pinned_endpoint(resource_id, key).await?;
+            Some(endpoint_id)
+        } else {
+            None
+        };
+        self.state
+            .update(&self.repo, |state| {
+                state.set_pinned_endpoint(resource_id, endpoint_id)
+            })
+            .await?;
+        if !pinned {
+            self.stop_pinned_endpoint(resource_id).await;
+            self.repo.delete_tunnel_key(resource_id).await?;
+        }
+        if let Some(proxy) = self.proxy_by_id(resource_id)
+            && proxy.enabled
+        {
+            health::set_published(self.n0des.as_deref(), &proxy, self.endpoint_id(), true).await;
+        }
+        Ok(true)
+    }
+
+    /// The endpoint a proxy with a pinned key is served under.
+    pub fn pinned_endpoint(&self, resource_id: &str) -> Option<Endpoint> {
+        self.pinned_endpoints
+            .lock()
+            .expect("poisoned")
+            .get(resource_id)
+            .map(|router| router.endpoint().clone())
+    }
+
+    async fn start_pinned_endpoint(&self, resource_id: &str, key: SecretKey) -> Result<()> {
+        let mut config = self.repo.config().await?;
+        config.discover_nat64_prefix().await;
+        let router = serve_extra_key(
+            key,
+            &config,
+            self.applied_timeouts,
+            self.state.clone(),
+            self.repo.clone(),
+            self.clients.clone(),
+        )
+        .await?;
+        info!(
+            tunnel_id = %resource_id,
+            endpoint_id = %router.endpoint().id().fmt_short(),
+            "serving pinned endpoint",
+        );
+        let previous = self
+            .pinned_endpoints
+            .lock()
+            .expect("poisoned")
+            .insert(resource_id.to_string(), router);
+        if let Some(previous) = previous {
+            previous.shutdown().await.ok();
+        }
+        Ok(())
+    }
+
+    async fn stop_pinned_endpoint(&self, resource_id: &str) {
+        let router = self
+            .pinned_endpoints
+            .lock()
+            .expect("poisoned")
+            .remove(resource_id);
+        if let Some(router) = router
+            && let Err(err) = router.shutdown().await
+        {
+            warn!(tunnel_id = %resource_id, "Failed to stop the pinned endpoint: {err:#}");
+        }
+    }
+
+    async fn stop_pinned_endpoints(&self) {
+        let ids = self
+            .pinned_endpoints
+            .lock()
+            .expect("poisoned")
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.stop_pinned_endpoint(&id).await;
+        }
+    }
+
+    /// Serve the endpoints of proxies with a pinned key again.
+    async fn restore_pinned_endpoints(&self) {
+        for proxy in self.proxies() {
+            let Some(pinned) = proxy.pinned_endpoint else {
+                continue;
+            };
+            let res = async {
+                let key = self.repo.tunnel_key(proxy.id()).await?;
+                if key.public() != pinned {
+                    n0_error::bail_any!(
+                        "the key in the repo is for {}, not {}",
+                        key.public().fmt_short(),
+                        pinned.fmt_short()
+                    );
+                }
+                self.start_pinned_endpoint(proxy.id(), key).await
+            };
+            if let Err(err) = res.await {
+                warn!(tunnel_id = %proxy.id(), "Failed to serve the pinned endpoint: {err:#}");
+            }
+        }
+    }
+
+    /// Set or clear when a local proxy expires. Does nothing if there is none.
+    pub async fn set_expiry(
+        &self,
+        resource_id: &str,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<()> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_expiry(resource_id, expires_at);
+            })
+            .await
+    }
+
+    /// Change what the proxy in front of a tunnel's service does.
+    ///
+    /// The first setting starts the proxy on a free loopback port and makes it
+    /// the tunnel's target; clearing the last one points the tunnel back at
+    /// the service. Returns the updated proxy, whose target has to be
+    /// published if it changed.
+    async fn update_http_front(
+        &self,
+        resource_id: &str,
+        f: impl FnOnce(&mut HttpFront),
+    ) -> Result<ProxyState> {
+        let mut proxy = self
+            .proxy_by_id(resource_id)
+            .with_context(|| format!("No local proxy for tunnel {resource_id}"))?;
+        let running = proxy.http_front.is_some();
+        let mut front = proxy.http_front.take().unwrap_or_else(|| {
+            HttpFront::new(TcpProxyData {
+                routes: Vec::new(),
+                ..proxy.info.data.clone()
+            })
+        });
+        f(&mut front);
+        match (running, front.is_empty()) {
+            (false, true) => return Ok(proxy),
+            (true, true) => {
+                self.front_proxies
+                    .lock()
+                    .expect("poisoned")
+                    .remove(resource_id);
+                let routes = std::mem::take(&mut proxy.info.data.routes);
+                proxy.info.data = front.upstream.with_routes(routes);
+            }
+            (true, false) => {
+                let updated = self
+                    .front_proxies
+                    .lock()
+                    .expect("poisoned")
+                    .get(resource_id)
+                    .map(|server| server.set_front(front.clone()));
+                match updated {
+                    Some(res) => res?,
+                    None => {
+                        let addr = proxy.info.service().address().parse().anyerr()?;
+                        self.bind_front_proxy(resource_id, front.clone(), addr)
+                            .await?;
+                    }
+                }
+                proxy.http_front = Some(front);
+            }
+            (false, false) => {
+                let loopback = (Ipv4Addr::LOCALHOST, 0).into();
+                let addr = self
+                    .bind_front_proxy(resource_id, front.clone(), loopback)
+                    .await?;
+                let routes = std::mem::take(&mut proxy.info.data.routes);
+                proxy.info.data =
+                    TcpProxyData::from_host_port_str(&addr.to_string())?.with_routes(routes);
+                proxy.http_front = Some(front);
+            }
+        }
+        let updated = proxy.clone();
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(existing) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    *existing = updated;
+                }
+            })
+            .await?;
+        Ok(proxy)
+    }
+
+    /// Point the proxy in front of a tunnel at a new service.
+    pub async fn set_front_upstream(
+        &self,
+        resource_id: &str,
+        upstream: TcpProxyData,
+    ) -> Result<()> {
+        let Some(proxy) = self.proxy_by_id(resource_id) else {
+            return Ok(());
+        };
+        let Some(front) = proxy.http_front else {
+            return Ok(());
+        };
+        let front = HttpFront { upstream, ..front };
+        let updated = self
+            .front_proxies
+            .lock()
+            .expect("poisoned")
+            .get(resource_id)
+            .map(|server| server.set_front(front.clone()));
+        if let Some(res) = updated {
+            res?;
+        }
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(existing) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    existing.http_front = Some(front);
+                }
+            })
+            .await
+    }
+
+    /// Publish the ticket of every persisted, enabled proxy again.
+    ///
+    /// Endpoint ids come from the persisted listen key or the proxy's pinned
+    /// key, so tickets don't change across restarts; this fills in any that
+    /// a failed publish or a reset n0des store lost while the agent was
+    /// down.
+    pub(crate) async fn republish_tickets(&self) -> Result<()> {
+        let Some(n0des) = &self.n0des else {
+            return Ok(());
+        };
+        for proxy in self.proxies().into_iter().filter(|p| p.enabled) {
+            n0des
+                .publish_ticket(
+                    proxy.id().to_string(),
+                    proxy.ticket(self.endpoint_id()).published_now(),
+                )
+                .await
+                .with_std_context(|_| format!("Failed to publish ticket {}", proxy.id()))?;
+        }
+        Ok(())
+    }
+
+    /// Unpublish tickets of this endpoint that no persisted, enabled proxy
+    /// backs.
+    ///
+    /// A crash between removing a proxy and unpublishing its ticket leaves the
+    /// ticket in n0des, and codenames would keep resolving to a tunnel that no
+    /// longer exists. Returns the names of the tickets that were unpublished.
+    pub(crate) async fn cleanup_stale_tickets(&self) -> Result<Vec<String>> {
+        let Some(n0des) = &self.n0des else {
+            return Ok(Vec::new());
+        };
+        let state = self.state.get();
+        let known = state
+            .proxies
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| p.id().to_string())
+            .collect::<HashSet<_>>();
+        let endpoint_ids = state
+            .proxies
+            .iter()
+            .filter_map(|p| p.pinned_endpoint)
+            .chain([self.endpoint_id()])
+            .collect::<HashSet<_>>();
+        drop(state);
+
+        // Collect before unpublishing so removals don't shift the pages.
+        let mut stale = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = n0des
+                .fetch_tickets::<AdvertismentTicket>(offset, TICKET_PAGE_SIZE)
+                .await
+                .std_context("Failed to list published tickets")?;
+            let len = page.len() as u32;
+            stale.extend(
+                page.into_iter()
+                    .filter(|t| {
+                        endpoint_ids.contains(&t.ticket.endpoint) && !known.contains(&t.name)
+                    })
+                    .map(|t| t.name),
+            );
+            if len < TICKET_PAGE_SIZE {
+                break;
+            }
+            offset += len;
+        }
+
+        for name in &stale {
+            n0des
+                .unpublish_ticket::<AdvertismentTicket>(name.clone())
+                .await
+                .with_std_context(|_| format!("Failed to unpublish ticket {name}"))?;
+            info!(%name, "unpublished stale ticket");
+        }
+        Ok(stale)
+    }
+
+    /// Start the proxies in front of tunnels' services again, on their
+    /// targets.
+    async fn restore_front_proxies(&self) {
+        for proxy in self.proxies() {
+            let Some(front) = proxy.http_front.clone() else {
+                continue;
+            };
+            let res = match proxy.info.service().address().parse() {
+                Ok(addr) => self
+                    .bind_front_proxy(proxy.id(), front, addr)
+                    .await
+                    .map(|_| ()),
+                Err(err) => Err(err).anyerr(),
+            };
+            if let Err(err) = res {
+                warn!(tunnel_id = %proxy.id(), "Failed to start tunnel front proxy: {err:#}");
+            }
+        }
+    }
+
+    async fn bind_front_proxy(
+        &self,
+        resource_id: &str,
+        front: HttpFront,
+        addr: SocketAddr,
+    ) -> Result<SocketAddr> {
+        let server = HttpFrontProxy::bind(front, addr).await?;
+        let addr = server.local_addr();
+        self.front_proxies
+            .lock()
+            .expect("poisoned")
+            .insert(resource_id.to_string(), server);
+        Ok(addr)
+    }
+
+    /// Serve `dir` on a free loopback port, to be used as a tunnel target.
+    ///
+    /// The server runs until [`Self::stop_file_server`] or until the tunnel
+    /// with this target is removed; record the directory on the tunnel with
+    /// [`Self::set_serve_dir`] so it is served again after a restart.
+    pub async fn start_file_server(&self, dir: &Path) -> Result<SocketAddr> {
+        self.bind_file_server(dir, (Ipv4Addr::LOCALHOST, 0).into())
+            .await
+    }
+
+    pub fn stop_file_server(&self, addr: SocketAddr) {
+        if let Some(server) = self.file_servers.lock().expect("poisoned").remove(&addr) {
+            debug!(%addr, root = %server.root().display(), "stopped serving directory");
+        }
+    }
+
+    /// Mark a proxy as a directory tunnel serving `dir`.
+    pub async fn set_serve_dir(&self, resource_id: &str, dir: PathBuf) -> Result<()> {
+        // Stored absolute, so it is found again whatever the working directory.
+        let dir = tokio::fs::canonicalize(&dir).await.unwrap_or(dir);
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(proxy) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    proxy.serve_dir = Some(dir);
+                }
+            })
+            .await
+    }
+
+    /// Add a local directory tunnel serving `dir`.
+    pub async fn add_serve_dir(&self, dir: &Path, label: Option<String>) -> Result<ProxyState> {
+        let addr = self.start_file_server(dir).await?;
+        let service = TcpProxyData::from_host_port_str(&addr.to_string())?;
+        let proxy = ProxyState::new(Advertisment::new(service, label));
+        let res = async {
+            self.set_proxy(proxy.clone()).await?;
+            self.set_serve_dir(proxy.id(), dir.to_path_buf()).await
+        };
+        if let Err(err) = res.await {
+            self.stop_file_server(addr);
+            return Err(err);
+        }
+        Ok(self.proxy_by_id(proxy.id()).unwrap_or(proxy))
+    }
+
+    /// Serve the directories of directory tunnels again, on their targets.
+    async fn restore_file_servers(&self) {
+        for proxy in self.proxies() {
+            let Some(dir) = &proxy.serve_dir else {
+                continue;
+            };
+            let res = match proxy.info.service().address().parse() {
+                Ok(addr) => self.bind_file_server(dir, addr).await.map(|_| ()),
+                Err(err) => Err(err).anyerr(),
+            };
+            if let Err(err) = res {
+                warn!(tunnel_id = %proxy.id(), "Failed to serve {}: {err:#}", dir.display());
+            }
+        }
+    }
+
+    async fn bind_file_server(&self, dir: &Path, addr: SocketAddr) -> Result<SocketAddr> {
+        let server = FileServer::bind(dir, addr).await?;
+        let addr = server.local_addr();
+        self.file_servers
+            .lock()
+            .expect("poisoned")
+            .insert(addr, server);
+        Ok(addr)
+    }
+
+    /// Relay a free loopback port to the unix socket at `path`, to be used as
+    /// a tunnel target.
+    ///
+    /// The relay runs until [`Self::stop_socket_bridge`] or until the tunnel
+    /// with this target is removed; record the socket on the tunnel with
+    /// [`Self::set_unix_socket`] so it is relayed again after a restart.
+    #[cfg(unix)]
+    pub async fn start_socket_bridge(&self, path: &Path) -> Result<SocketAddr> {
+        crate::unix_socket::check_socket(path).await?;
+        self.bind_socket_bridge(path, (Ipv4Addr::LOCALHOST, 0).into())
+            .await
+    }
+
+    #[cfg(unix)]
+    pub fn stop_socket_bridge(&self, addr: SocketAddr) {
+        if let Some(bridge) = self.socket_bridges.lock().expect("poisoned").remove(&addr) {
+            debug!(%addr, path = %bridge.path().display(), "stopped relaying to unix socket");
+        }
+    }
+
+    /// Mark a proxy as a tunnel to the unix socket at `path`.
+    #[cfg(unix)]
+    pub async fn set_unix_socket(&self, resource_id: &str, path: PathBuf) -> Result<()> {
+        let path = tokio::fs::canonicalize(&path).await.unwrap_or(path);
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(proxy) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    proxy.unix_socket = Some(path);
+                }
+            })
+            .await
+    }
+
+    /// Add a local tunnel to the service listening on the unix socket at
+    /// `path`.
+    #[cfg(unix)]
+    pub async fn add_unix_socket(&self, path: &Path, label: Option<String>) -> Result<ProxyState> {
+        let addr = self.start_socket_bridge(path).await?;
+        let service = TcpProxyData::from_host_port_str(&addr.to_string())?;
+        let proxy = ProxyState::new(Advertisment::new(service, label));
+        let res = async {
+            self.set_proxy(proxy.clone()).await?;
+            self.set_unix_socket(proxy.id(), path.to_path_buf()).await
+        };
+        if let Err(err) = res.await {
+            self.stop_socket_bridge(addr);
+            return Err(err);
+        }
+        Ok(self.proxy_by_id(proxy.id()).unwrap_or(proxy))
+    }
+
+    /// Relay the targets of unix socket tunnels to their sockets again.
+    #[cfg(unix)]
+    async fn restore_socket_bridges(&self) {
+        for proxy in self.proxies() {
+            let Some(path) = &proxy.unix_socket else {
+                continue;
+            };
+            let res = match proxy.info.service().address().parse() {
+                Ok(addr) => self.bind_socket_bridge(path, addr).await.map(|_| ()),
+                Err(err) => Err(err).anyerr(),
+            };
+            if let Err(err) = res {
+                warn!(tunnel_id = %proxy.id(), "Failed to relay to {}: {err:#}", path.display());
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    async fn bind_socket_bridge(&self, path: &Path, addr: SocketAddr) -> Result<SocketAddr> {
+        let bridge = SocketBridge::bind(path, addr).await?;
+        let addr = bridge.local_addr();
+        self.socket_bridges
+            .lock()
+            .expect("poisoned")
+            .insert(addr, bridge);
+        Ok(addr)
+    }
+
+    /// Relay a free loopback port to `host:port`, to be used as the target of
+    /// a tunnel to a name from the `hosts` config. See [`crate::host_bridge`].
+    ///
+    /// The relay runs until [`Self::stop_host_bridge`] or until the tunnel
+    /// with this target is removed; record the name on the tunnel with
+    /// [`Self::set_host_target`] so it is relayed again after a restart.
+    pub async fn start_host_bridge(&self, host: &str, port: u16) -> Result<SocketAddr> {
+        self.bind_host_bridge(host, port, (Ipv4Addr::LOCALHOST, 0).into())
+            .await
+    }
+
+    pub fn stop_host_bridge(&self, addr: SocketAddr) {
+        if let Some(bridge) = self.host_bridges.lock().expect("poisoned").remove(&addr) {
+            debug!(%addr, target = %bridge.target(), "stopped relaying to host");
+        }
+    }
+
+    /// Mark a proxy as a tunnel to `host:port` relayed by a host bridge, or
+    /// as a plain tunnel with `None`.
+    pub async fn set_host_target(&self, resource_id: &str, target: Option<String>) -> Result<()> {
+        self.state
+            .update(&self.repo, |state| {
+                if let Some(proxy) = state.proxies.iter_mut().find(|p| p.id() == resource_id) {
+                    proxy.host_target = target;
+                }
+            })
+            .await
+    }
+
+    /// Relay the targets of host name tunnels to their hosts again.
+    async fn restore_host_bridges(&self) {
+        for proxy in self.proxies() {
+            let Some(target) = &proxy.host_target else {
+                continue;
+            };
+            let res = async {
+                let (host, port) = TcpProxyData::parse_host_port(target)?;
+                let addr = proxy.info.service().address().parse().anyerr()?;
+                self.bind_host_bridge(&host, port, addr).await
+            };
+            if let Err(err) = res.await {
+                warn!(tunnel_id = %proxy.id(), "Failed to relay to {target}: {err:#}");
+            }
+        }
+    }
+
+    async fn bind_host_bridge(
+        &self,
+        host: &str,
+        port: u16,
+        addr: SocketAddr,
+    ) -> Result<SocketAddr> {
+        let bridge = HostBridge::bind(host, port, addr, self.repo.clone()).await?;
+        let addr = bridge.local_addr();
+        self.host_bridges
+            .lock()
+            .expect("poisoned")
+            .insert(addr, bridge);
+        Ok(addr)
+    }
+
+    /// Issue a share link for a local proxy, valid for `ttl` or until revoked.
+    pub async fn issue_share(&self, tunnel_id: &str, ttl: Option<Duration>) -> Result<IssuedShare> {
+        let proxy = self
+            .proxy_by_id(tunnel_id)
+            .with_context(|| format!("No local proxy for tunnel {tunnel_id}"))?;
+        let created_at = chrono::Utc::now();
+        let share = IssuedShare {
+            id: uuid::Uuid::new_v4().to_string(),
+            tunnel_id: tunnel_id.to_string(),
+            ticket: proxy.ticket(self.endpoint_id()).to_string(),
+            created_at,
+            expires_at: ttl.map(|ttl| created_at + ttl),
+            revoked: false,
+            clients: Vec::new(),
+        };
+        self.state
+            .update(&self.repo, |state| state.shares.push(share.clone()))
+            .await?;
+        Ok(share)
+    }
+
+    /// The usable shares of a local proxy, newest first.
+    pub fn shares(&self, tunnel_id: &str) -> Vec<IssuedShare> {
+        let now = chrono::Utc::now();
+        let mut shares: Vec<_> = self
+            .state
+            .get()
+            .shares
+            .iter()
+            .filter(|share| share.tunnel_id == tunnel_id && share.is_active(now))
+            .cloned()
+            .collect();
+        shares.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        shares
+    }
+
+    /// Revoke a share. Clients that connected through it are refused from now on.
+    pub async fn revoke_share(&self, share_id: &str) -> Result<Option<IssuedShare>> {
+        self.state
+            .update(&self.repo, |state| {
+                let share = state.shares.iter_mut().find(|share| share.id == share_id)?;
+                share.revoked = true;
+                Some(share.clone())
+            })
+            .await
+    }
+
+    /// Set a proxy's idle timeout and keepalive. Returns false if there is no
+    /// such proxy. Takes effect the next time the node starts.
+    pub async fn set_timeouts(&self, resource_id: &str, timeouts: TunnelTimeouts) -> Result<bool> {
+        self.state
+            .update(&self.repo, |state| {
+                state.set_timeouts(resource_id, timeouts)
+            })
+            .await
+    }
+
+    /// The combined timeouts the endpoint was started with.
+    pub fn applied_timeouts(&self) -> TunnelTimeouts {
+        self.applied_timeouts
+    }
+
+    /// Allow or disallow `peer` to ask this node to open reverse forwards.
+    pub async fn set_reverse_forward_allowed(&self, peer: EndpointId, allowed: bool) -> Result<()> {
+        self.state
+            .update(&self.repo, |state| {
+                state.reverse_forward_peers.retain(|id| *id != peer);
+                if allowed {
+                    state.reverse_forward_peers.push(peer);
+                }
+            })
+            .await
+    }
+
+    /// Listeners currently open for remote peers' reverse forwards.
+    pub fn reverse_forwards(&self) -> Vec<ReverseForwardInfo> {
+        self.reverse_forwards.active()
+    }
+
+    pub async fn remove_proxy_state(&self, resource_id: &str) -> Result<Option<ProxyState>> {
+        debug!(%resource_id, "removing proxy state {resource_id}");
+        let res = self
+            .state
+            .update(&self.repo, move |state| state.remove_proxy(resource_id))
+            .await;
+        debug!(%resource_id, "removed {res:?}");
+        if let Ok(Some(proxy)) = &res {
+            self.repo.activity().remove(proxy.id());
+            self.repo.usage().remove(proxy.id());
+            self.repo.bandwidth_history().remove(proxy.id());
+        }
+        res
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        self.router.endpoint()
+    }
+
+    /// The address `host` is overridden to in the agent's `hosts` config.
+    ///
+    /// The config is read on each call, so edits apply without a restart.
+    pub async fn host_override(&self, host: &str) -> Option<IpAddr> {
+        match self.repo.config().await {
+            Ok(config) => config.host_override(host),
+            Err(err) => {
+                warn!("Failed to read config for host overrides: {err:#}");
+                None
+            }
+        }
+    }
+
+    /// Whether tunnels to `host` need a host bridge, see
+    /// [`Config::resolves_target`]. Read on each call like
+    /// [`Self::host_override`].
+    pub async fn resolves_target(&self, host: &str) -> bool {
+        match self.repo.config().await {
+            Ok(config) => config.resolves_target(host),
+            Err(err) => {
+                warn!("Failed to read config for target resolution: {err:#}");
+                false
+            }
+        }
+    }
+
+    /// The tunnel limit for `project_id` in the agent's `tunnel_limits`
+    /// config, read on each call like [`Self::host_override`].
+    pub async fn tunnel_limit(&self, project_id: &str) -> Option<u64> {
+        match self.repo.config().await {
+            Ok(config) => config.tunnel_limits.get(project_id).copied(),
+            Err(err) => {
+                warn!("Failed to read config for tunnel limits: {err:#}");
+                None
+            }
+        }
+    }
+
+    pub fn endpoint_id(&self) -> EndpointId {
+        self.router.endpoint().id()
+    }
+
+    /// The endpoint id of the previous key while it is still served after a
+    /// [key rotation](crate::key_rotation).
+    pub fn previous_endpoint_id(&self) -> Option<EndpointId> {
+        self.rotation
+            .as_ref()
+            .filter(|rotation| rotation.in_grace(chrono::Utc::now()))
+            .map(|rotation| rotation.previous)
+    }
+
+    /// Remote clients that used a local proxy recently, with their current path.
+    pub fn connections(&self) -> Vec<ListenerConnectionInfo> {
+        self.clients.connections(self.router.endpoint())
+    }
+
+    /// Remote clients that used the local proxy `proxy`.
+    pub fn connections_for(&self, proxy: &ProxyState) -> Vec<ListenerConnectionInfo> {
+        let service = proxy.info.service();
+        self.connections()
+            .into_iter()
+            .filter(|c| service.serves(&c.service.host, c.service.port))
+            .collect()
+    }
+}
+
+/// A remote client using one of the local proxies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerConnectionInfo {
+    pub remote_id: EndpointId,
+    /// The local service the client was let through to.
+    pub service: TcpProxyData,
+    pub path: PathInfo,
+    /// When the client's first request was authorized.
+    pub first_seen: Instant,
+    /// When the client's latest request was authorized.
+    pub last_seen: Instant,
+    pub requests: u64,
+}
+
+impl ListenerConnectionInfo {
+    pub fn age(&self) -> Duration {
+        self.first_seen.elapsed()
+    }
+}
+
+/// How many published tickets to fetch from n0des per request.
+const TICKET_PAGE_SIZE: u32 = 100;
+
+/// Clients idle for longer than this are no longer listed as connected.
+const INBOUND_CLIENT_IDLE: Duration = Duration::from_secs(5 * 60);
+
+/// Remote clients seen by the upstream proxy, keyed by client and service.
+///
+/// The upstream proxy doesn't report connection lifetimes, so clients are
+/// recorded as their requests are authorized and dropped once idle. Both are
+/// written to the event log; a disconnect is logged when it is noticed.
+#[derive(Debug, Clone)]
+struct InboundClients {
+    inner: Arc<Mutex<HashMap<(EndpointId, TcpProxyData), ListenerConnectionInfo>>>,
+    events: EventLog,
+}
+
+impl InboundClients {
+    fn new(events: EventLog) -> Self {
+        Self {
+            inner: Default::default(),
+            events,
+        }
     }
 
-    pub fn state_updated(&self) -> Notified<'_> {
-        self.state.updated()
+    fn record(&self, remote_id: EndpointId, service: TcpProxyData) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().expect("poisoned");
+        self.evict_idle(&mut inner);
+        if let Some(info) = inner.get_mut(&(remote_id, service.clone())) {
+            info.last_seen = now;
+            info.requests += 1;
+            return;
+        }
+        self.events.record(EventKind::ClientConnected {
+            remote_id,
+            service: service.address(),
+        });
+        inner.insert(
+            (remote_id, service.clone()),
+            ListenerConnectionInfo {
+                remote_id,
+                service,
+                path: PathInfo::default(),
+                first_seen: now,
+                last_seen: now,
+                requests: 1,
+            },
+        );
     }
 
-    pub fn state(&self) -> &StateWrapper {
-        &self.state
+    /// [`Self::active`] clients with their current path.
+    fn connections(&self, endpoint: &Endpoint) -> Vec<ListenerConnectionInfo> {
+        self.active()
+            .into_iter()
+            .map(|mut info| {
+                info.path = PathInfo::for_remote(endpoint, info.remote_id);
+                info
+            })
+            .collect()
     }
 
-    pub fn metrics(&self) -> broadcast::Receiver<MetricsUpdate> {
-        self.metrics_tx.subscribe()
+    fn active(&self) -> Vec<ListenerConnectionInfo> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        self.evict_idle(&mut inner);
+        let mut active: Vec<_> = inner.values().cloned().collect();
+        active.sort_by_key(|info| info.first_seen);
+        active
     }
 
-    pub fn proxies(&self) -> Vec<ProxyState> {
-        self.state.get().proxies.to_vec()
+    fn evict_idle(&self, inner: &mut HashMap<(EndpointId, TcpProxyData), ListenerConnectionInfo>) {
+        inner.retain(|_, info| {
+            let active = info.last_seen.elapsed() < INBOUND_CLIENT_IDLE;
+            if !active {
+                self.events.record(EventKind::ClientDisconnected {
+                    remote_id: info.remote_id,
+                    service: info.service.address(),
+                });
+            }
+            active
+        });
     }
+}
 
-    pub fn proxy_by_id(&self, id: &str) -> Option<ProxyState> {
+/// Authorizes upstream requests against the local state and records who made them.
+#[derive(Debug, Clone)]
+pub(crate) struct TrackingAuth {
+    endpoint: Endpoint,
+    state: StateWrapper,
+    repo: Repo,
+    clients: InboundClients,
+}
+
+impl TrackingAuth {
+    /// Whether the enabled proxy serving `host:port` passes the client on
+    /// with the PROXY protocol.
+    pub(crate) fn proxy_protocol(&self, host: &str, port: u16) -> bool {
         self.state
             .get()
             .proxies
             .iter()
-            .find(|p| p.id() == id)
-            .cloned()
+            .any(|p| p.enabled && p.proxy_protocol && p.info.service().serves(host, port))
     }
 
-    pub async fn set_proxy(&self, proxy: ProxyState) -> Result<()> {
+    fn is_direct_only(&self, tunnel_id: &str) -> bool {
         self.state
-            .update(&self.repo, |state| state.set_proxy(proxy.clone()))
-            .await?;
-        Ok(())
+            .get()
+            .proxies
+            .iter()
+            .any(|p| p.id() == tunnel_id && p.direct_only)
     }
 
-    pub async fn set_proxy_state(&self, proxy: ProxyState) -> Result<()> {
-        self.state
-            .update(&self.repo, |state| state.set_proxy(proxy))
-            .await?;
-        Ok(())
+    /// Refuse clients of revoked or expired shares, and attribute new clients
+    /// to the proxy's active share.
+    async fn check_share(&self, remote_id: EndpointId, service: &TcpProxyData) -> bool {
+        let now = chrono::Utc::now();
+        let (tunnel_id, attribute_to) = {
+            let state = self.state.get();
+            let Some(proxy) = state
+                .proxies
+                .iter()
+                .find(|p| p.info.service().serves(&service.host, service.port))
+            else {
+                return true;
+            };
+            let tunnel_id = proxy.id().to_string();
+            let mut shares = state
+                .shares
+                .iter()
+                .filter(|share| share.tunnel_id == tunnel_id)
+                .peekable();
+            if shares.peek().is_none() {
+                return true;
+            }
+            let known: Vec<_> = shares
+                .filter(|share| share.clients.contains(&remote_id))
+                .collect();
+            if !known.is_empty() {
+                return known.iter().any(|share| share.is_active(now));
+            }
+            let attribute_to = state
+                .active_share(&tunnel_id, now)
+                .map(|share| share.id.clone());
+            (tunnel_id, attribute_to)
+        };
+        if let Some(share_id) = attribute_to {
+            let res = self
+                .state
+                .update(&self.repo, |state| {
+                    if let Some(share) = state.shares.iter_mut().find(|s| s.id == share_id) {
+                        share.clients.push(remote_id);
+                    }
+                })
+                .await;
+            if let Err(err) = res {
+                warn!(%tunnel_id, "Failed to record share client: {err:#}");
+            }
+        }
+        true
     }
+}
 
-    pub async fn remove_proxy(&self, resource_id: &str) -> Result<Option<ProxyState>> {
-        debug!(%resource_id, "removing proxy {resource_id}");
-        let res = self
-            .state
-            .update(&self.repo, move |state| state.remove_proxy(resource_id))
-            .await;
-        debug!(%resource_id, "removed {res:?}");
-        res
+impl AuthHandler for TrackingAuth {
+    async fn authorize<'a>(
+        &'a self,
+        remote_id: EndpointId,
+        req: &'a HttpProxyRequest,
+    ) -> Result<(), AuthError> {
+        let target = match &req.kind {
+            HttpProxyRequestKind::Tunnel { target } => {
+                Some((strip_host_scheme(&target.host).to_string(), target.port))
+            }
+            HttpProxyRequestKind::Absolute { target, .. } => parse_host_port_from_url(target),
+        };
+        let allowed = self.state.authorize(remote_id, req).await;
+        self.check(remote_id, target, allowed).await
     }
+}
 
-    pub async fn remove_proxy_state(&self, resource_id: &str) -> Result<Option<ProxyState>> {
-        debug!(%resource_id, "removing proxy state {resource_id}");
-        let res = self
-            .state
-            .update(&self.repo, move |state| state.remove_proxy(resource_id))
-            .await;
-        debug!(%resource_id, "removed {res:?}");
-        res
+impl TrackingAuth {
+    /// [`AuthHandler::authorize`] for a request to `host:port` that didn't
+    /// come through iroh-proxy-utils.
+    pub(crate) async fn authorize_target(
+        &self,
+        remote_id: EndpointId,
+        host: &str,
+        port: u16,
+    ) -> Result<(), AuthError> {
+        let allowed = if self.state.tcp_proxy_exists(host, port) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden)
+        };
+        let target = Some((strip_host_scheme(host).to_string(), port));
+        self.check(remote_id, target, allowed).await
     }
 
-    pub fn endpoint(&self) -> &Endpoint {
-        self.router.endpoint()
+    /// Apply the per-tunnel checks on top of whether the state `allowed` the
+    /// request, and record it.
+    async fn check(
+        &self,
+        remote_id: EndpointId,
+        target: Option<(String, u16)>,
+        allowed: Result<(), AuthError>,
+    ) -> Result<(), AuthError> {
+        let tunnel_id = target
+            .as_ref()
+            .and_then(|(host, port)| self.state.tunnel_id_for(host, *port));
+        let activity = self.repo.activity();
+        if let Err(err) = allowed {
+            if let Some(tunnel_id) = &tunnel_id {
+                activity.record_error(tunnel_id);
+            }
+            return Err(err);
+        }
+        if let Some(tunnel_id) = &tunnel_id
+            && self.is_direct_only(tunnel_id)
+            && !wait_for_direct_path(&self.endpoint, remote_id).await
+        {
+            warn!(
+                remote_id = %remote_id.fmt_short(),
+                %tunnel_id,
+                "refusing relayed client of direct-only tunnel"
+            );
+            activity.record_error(tunnel_id);
+            return Err(AuthError::Forbidden);
+        }
+        if let Some((host, port)) = target {
+            let service = TcpProxyData {
+                host,
+                port,
+                routes: Vec::new(),
+            };
+            if !self.check_share(remote_id, &service).await {
+                debug!(
+                    remote_id = %remote_id.fmt_short(),
+                    "refusing client of revoked or expired share"
+                );
+                if let Some(tunnel_id) = &tunnel_id {
+                    activity.record_error(tunnel_id);
+                }
+                return Err(AuthError::Forbidden);
+            }
+            self.clients.record(remote_id, service);
+        }
+        if let Some(tunnel_id) = &tunnel_id {
+            activity.record_request(tunnel_id);
+            self.repo.usage().record_request(tunnel_id);
+        }
+        Ok(())
     }
+}
 
-    pub fn endpoint_id(&self) -> EndpointId {
-        self.router.endpoint().id()
-    }
+/// Whether the path to `remote_id` is direct, or becomes direct within
+/// [`DIRECT_PATH_TIMEOUT`] while hole punching completes.
+async fn wait_for_direct_path(endpoint: &Endpoint, remote_id: EndpointId) -> bool {
+    let Some(mut conn_type) = endpoint.conn_type(remote_id) else {
+        return false;
+    };
+    let direct = async {
+        loop {
+            if matches!(conn_type.get(), ConnectionType::Direct(_)) {
+                return true;
+            }
+            if conn_type.updated().await.is_err() {
+                return false;
+            }
+        }
+    };
+    tokio::time::timeout(DIRECT_PATH_TIMEOUT, direct)
+        .await
+        .unwrap_or(false)
 }
 
 impl StateWrapper {
+    /// The proxy serving `host:port`, enabled or not.
+    fn tunnel_id_for(&self, host: &str, port: u16) -> Option<String> {
+        self.get()
+            .proxies
+            .iter()
+            .find(|p| p.info.service().serves(host, port))
+            .map(|p| p.id().to_string())
+    }
+
     fn tcp_proxy_exists(&self, host: &str, port: u16) -> bool {
         // Strip scheme from incoming host (e.g., "http://127.0.0.1" -> "127.0.0.1")
         // The gateway may send the host with scheme, but local state stores without
         let normalized_host = strip_host_scheme(host);
-        let exists = self.get().proxies.iter().any(|a| {
-            a.enabled && a.info.service().host == normalized_host && a.info.service().port == port
-        });
+        let exists = self
+            .get()
+            .proxies
+            .iter()
+            .any(|a| a.enabled && a.info.service().serves(normalized_host, port));
         if !exists {
             debug!(
                 requested_host = host,
@@ -237,6 +1888,8 @@ impl AuthHandler for StateWrapper {
 }
 
 /// Parse host and port from an absolute URL (e.g., "http://localhost:5173/path")
+///
+/// IPv6 hosts are returned without brackets.
 fn parse_host_port_from_url(url: &str) -> Option<(String, u16)> {
     // Remove scheme
     let without_scheme = url
@@ -247,26 +1900,70 @@ fn parse_host_port_from_url(url: &str) -> Option<(String, u16)> {
     let authority = without_scheme.split('/').next()?;
 
     // Split host and port
-    if let Some((host, port_str)) = authority.rsplit_once(':') {
-        let port = port_str.parse().ok()?;
-        Some((host.to_string(), port))
+    if let Ok((host, port)) = TcpProxyData::parse_host_port(authority) {
+        Some((host, port))
     } else {
         // Default ports
+        let host = crate::dial::unbracket(authority).to_string();
         if url.starts_with("https://") {
-            Some((authority.to_string(), 443))
+            Some((host, 443))
         } else {
-            Some((authority.to_string(), 80))
+            Some((host, 80))
         }
     }
 }
 
+const RECONNECT_BACKOFF_INITIAL: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// How often a reachable remote is re-dialed to notice it going away.
+const REMOTE_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a client of a direct-only tunnel has to find a direct path.
+const DIRECT_PATH_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 pub struct ConnectNode {
     endpoint: Endpoint,
+    /// Relays are off, see [`Config::direct_only`].
+    direct_only: bool,
+    /// Set with [`Config::local_discovery`].
+    local: Option<LocalDiscovery>,
     proxy: DownstreamProxy,
+    connections: Arc<watch::Sender<Vec<ConnectionInfo>>>,
     _n0des: Option<Arc<iroh_n0des::Client>>,
 }
 
+/// A local port forwarding to a service on a remote endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub remote_id: EndpointId,
+    pub bound_addr: SocketAddr,
+    pub service: TcpProxyData,
+    /// The path to the remote as of the last [`ConnectNode::connections`] call.
+    ///
+    /// Path changes don't fire [`ConnectNode::connections_watch`].
+    pub path: PathInfo,
+    pub opened_at: Instant,
+    pub state: ConnectionState,
+}
+
+/// Whether the remote of an outbound connection is reachable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    #[default]
+    Connected,
+    /// The remote stopped answering and is being re-dialed with backoff.
+    Reconnecting { attempt: u32 },
+    /// The remote can't be reached directly, and relays are off because of
+    /// [`Config::direct_only`]. It is re-dialed with backoff.
+    NoDirectPath { attempt: u32 },
+}
+
+impl ConnectionInfo {
+    pub fn age(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+}
+
 impl ConnectNode {
     pub async fn new(repo: Repo) -> Result<Self> {
         let n0des_api_secret = n0des_api_secret_from_env()?;
@@ -278,60 +1975,366 @@ impl ConnectNode {
         repo: Repo,
         n0des_api_secret: Option<ApiSecret>,
     ) -> Result<Self> {
-        let config = repo.config().await?;
+        let mut config = repo.config().await?;
+        config.discover_nat64_prefix().await;
         let secret_key = repo.connect_key().await?;
-        let endpoint = build_endpoint(secret_key, &config).await?;
+        let endpoint = build_endpoint(secret_key, &config, config.connect_timeouts).await?;
         let n0des = build_n0des_client_opt(&endpoint, n0des_api_secret).await;
+        let local = if config.local_discovery {
+            Some(LocalDiscovery::spawn(&endpoint).await?)
+        } else {
+            None
+        };
         let pool = DownstreamProxy::new(endpoint.clone(), Default::default());
+        let (connections, _) = watch::channel(Vec::new());
         Ok(Self {
             endpoint,
+            direct_only: config.direct_only,
+            local,
             _n0des: n0des,
             proxy: pool,
+            connections: Arc::new(connections),
         })
     }
 
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
     pub fn endpoint_id(&self) -> EndpointId {
         self.endpoint.id()
     }
 
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections = self.connections.borrow().clone();
+        for connection in connections.iter_mut() {
+            connection.path = PathInfo::for_remote(&self.endpoint, connection.remote_id);
+        }
+        connections
+    }
+
+    pub fn connections_watch(&self) -> watch::Receiver<Vec<ConnectionInfo>> {
+        self.connections.subscribe()
+    }
+
+    /// The tunnels announced on the local network, if
+    /// [`Config::local_discovery`] is on.
+    pub fn local_discovery(&self) -> Option<&LocalDiscovery> {
+        self.local.as_ref()
+    }
+
+    /// The tunnel announced on the local network under `codename`. Waits
+    /// for the next round of announcements if it isn't known yet.
+    pub async fn find_local(&self, codename: &str) -> Result<AdvertismentTicket> {
+        let Some(local) = &self.local else {
+            n0_error::bail_any!("Local discovery is off, turn on local_discovery in the config");
+        };
+        let deadline = Instant::now() + local_discovery::LOOKUP_TIMEOUT;
+        loop {
+            if let Some(ticket) = local.find(codename) {
+                return Ok(ticket);
+            }
+            if Instant::now() >= deadline {
+                n0_error::bail_any!("No tunnel {codename} found on the local network");
+            }
+            n0_future::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Ask the remote listen node to listen on `remote_bind_addr` and forward
+    /// connections back to `target` on this machine, like `ssh -R`.
+    ///
+    /// The remote must have allowed this node with
+    /// [`ListenNode::set_reverse_forward_allowed`].
+    pub async fn request_reverse_forward(
+        &self,
+        remote_id: EndpointId,
+        remote_bind_addr: SocketAddr,
+        target: TcpProxyData,
+    ) -> Result<ReverseForwardHandle> {
+        reverse_forward::request(&self.endpoint, remote_id, remote_bind_addr, target).await
+    }
+
+    /// Forward connections to `bind_addr` to the remote service.
+    ///
+    /// Fails with [`AddrInUse`] if the address is taken. Use port 0 to bind
+    /// any free port; [`OutboundProxyHandle::bound_addr`] is the address
+    /// actually bound.
     pub async fn connect_and_bind_local(
         &self,
         remote_id: EndpointId,
         advertisment: &TcpProxyData,
         bind_addr: SocketAddr,
     ) -> Result<OutboundProxyHandle> {
-        let local_socket = TcpListener::bind(bind_addr).await?;
+        let local_socket = bind_local(bind_addr).await?;
+        self.forward_local(remote_id, advertisment, local_socket)
+    }
+
+    /// Like [`Self::connect_and_bind_local`], but if `bind_addr` is taken,
+    /// binds a free port on the same IP instead.
+    pub async fn connect_and_bind_local_auto(
+        &self,
+        remote_id: EndpointId,
+        advertisment: &TcpProxyData,
+        bind_addr: SocketAddr,
+    ) -> Result<OutboundProxyHandle> {
+        let local_socket = match TcpListener::bind(bind_addr).await {
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                debug!(%bind_addr, "local address in use, binding a free port instead");
+                bind_local(SocketAddr::new(bind_addr.ip(), 0)).await?
+            }
+            res => res.with_std_context(|_| format!("Failed to bind {bind_addr}"))?,
+        };
+        self.forward_local(remote_id, advertisment, local_socket)
+    }
+
+    fn forward_local(
+        &self,
+        remote_id: EndpointId,
+        advertisment: &TcpProxyData,
+        local_socket: TcpListener,
+    ) -> Result<OutboundProxyHandle> {
         let bound_addr = local_socket.local_addr()?;
 
-        let upstream = EndpointAuthority::new(remote_id, advertisment.clone().into());
-        let mode = ProxyMode::Tcp(upstream);
+        let mode = if advertisment.routes.is_empty() {
+            let upstream = EndpointAuthority::new(remote_id, advertisment.clone().into());
+            ProxyMode::Tcp(upstream)
+        } else {
+            ProxyMode::Http(HttpProxyOpts::new(PathRouter {
+                remote_id,
+                service: advertisment.clone(),
+            }))
+        };
+
+        self.connections.send_modify(|connections| {
+            connections.push(ConnectionInfo {
+                remote_id,
+                bound_addr,
+                service: advertisment.clone(),
+                path: PathInfo::default(),
+                opened_at: Instant::now(),
+                state: ConnectionState::Connected,
+            })
+        });
 
         let proxy = self.proxy.clone();
+        let endpoint = self.endpoint.clone();
+        let direct_only = self.direct_only;
+        let connections = self.connections.clone();
         let task = tokio::spawn(async move {
             info!("bound local socket on {bound_addr}");
-            if let Err(err) = proxy.forward_tcp_listener(local_socket, mode).await {
-                warn!("Forwarding local socket failed: {err:#}");
+            let forward = forward_with_retry(&proxy, mode, local_socket, bound_addr, &connections);
+            let watch = watch_remote(&endpoint, direct_only, remote_id, bound_addr, &connections);
+            tokio::select! {
+                _ = forward => {}
+                _ = watch => {}
             }
+            remove_connection(&connections, bound_addr);
         }.instrument(error_span!("forward-tcp", remote_id=%remote_id.fmt_short(), authority=%advertisment.address())));
         Ok(OutboundProxyHandle {
             remote_id,
             task,
-            bound_addr: bind_addr,
+            bound_addr,
             advertisment: advertisment.clone(),
+            connections: self.connections.clone(),
         })
     }
 }
 
+/// Sends each HTTP request of a joined tunnel with path routes to the remote
+/// backend for its path.
+#[derive(Debug)]
+struct PathRouter {
+    remote_id: EndpointId,
+    service: TcpProxyData,
+}
+
+impl RequestHandler for PathRouter {
+    async fn handle_request(
+        &self,
+        _src_addr: SrcAddr,
+        req: &mut HttpRequest,
+    ) -> Result<EndpointId, Deny> {
+        match req.classify()? {
+            HttpRequestKind::Tunnel => Err(Deny::bad_request(
+                "CONNECT is not supported on tunnels with path routes",
+            )),
+            HttpRequestKind::Origin | HttpRequestKind::Http1Absolute => {
+                let (host, port) = self.service.target_for_path(req.uri.path());
+                debug!(path = req.uri.path(), %host, port, "routing request");
+                req.set_absolute_http_authority(Authority::new(host.to_string(), port))?;
+                Ok(self.remote_id)
+            }
+        }
+    }
+}
+
+#[stack_error(derive)]
+#[error("Local address {addr} is already in use")]
+pub struct AddrInUse {
+    pub addr: SocketAddr,
+}
+
+#[stack_error(derive)]
+#[error("{address} is already served by tunnel {tunnel_id}")]
+pub struct AlreadyListening {
+    pub address: String,
+    pub tunnel_id: String,
+}
+
+/// Whether a local TCP address can be bound right now.
+///
+/// Only a hint for validating input up front: another process may take the
+/// port before the join binds it.
+pub async fn local_addr_available(addr: SocketAddr) -> bool {
+    TcpListener::bind(addr).await.is_ok()
+}
+
+async fn bind_local(addr: SocketAddr) -> Result<TcpListener> {
+    match TcpListener::bind(addr).await {
+        Ok(socket) => Ok(socket),
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => Err(AddrInUse { addr }.into()),
+        Err(err) => Err(err).with_std_context(|_| format!("Failed to bind {addr}")),
+    }
+}
+
+/// Forward a local listener, rebinding and retrying with backoff if forwarding fails.
+///
+/// Returns once the listener is closed normally.
+async fn forward_with_retry(
+    proxy: &DownstreamProxy,
+    mode: ProxyMode,
+    listener: TcpListener,
+    bound_addr: SocketAddr,
+    connections: &watch::Sender<Vec<ConnectionInfo>>,
+) {
+    let mut listener = Some(listener);
+    let mut backoff = Backoff::default();
+    loop {
+        let socket = match listener.take() {
+            Some(socket) => socket,
+            None => match TcpListener::bind(bound_addr).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    warn!("Failed to rebind local socket: {err:#}");
+                    backoff.wait().await;
+                    continue;
+                }
+            },
+        };
+        match proxy.forward_tcp_listener(socket, mode.clone()).await {
+            Ok(()) => return,
+            Err(err) => {
+                let attempt = backoff.attempt + 1;
+                warn!(attempt, "Forwarding local socket failed, retrying: {err:#}");
+                set_connection_state(
+                    connections,
+                    bound_addr,
+                    ConnectionState::Reconnecting { attempt },
+                );
+                backoff.wait().await;
+            }
+        }
+    }
+}
+
+/// Periodically dial the remote, so a restarted or moved listener is found
+/// again through discovery, and report whether it is reachable.
+async fn watch_remote(
+    endpoint: &Endpoint,
+    direct_only: bool,
+    remote_id: EndpointId,
+    bound_addr: SocketAddr,
+    connections: &watch::Sender<Vec<ConnectionInfo>>,
+) {
+    let mut backoff = Backoff::default();
+    loop {
+        match endpoint.connect(remote_id, IROH_HTTP_CONNECT_ALPN).await {
+            Ok(conn) => {
+                conn.close(0u32.into(), b"probe");
+                if backoff.attempt > 0 {
+                    info!("remote reachable again");
+                }
+                backoff = Backoff::default();
+                set_connection_state(connections, bound_addr, ConnectionState::Connected);
+                tokio::time::sleep(REMOTE_PROBE_INTERVAL).await;
+            }
+            Err(err) => {
+                let attempt = backoff.attempt + 1;
+                let state = if direct_only {
+                    warn!(attempt, "no direct path to remote, relays are off: {err:#}");
+                    ConnectionState::NoDirectPath { attempt }
+                } else {
+                    warn!(attempt, "remote unreachable, reconnecting: {err:#}");
+                    ConnectionState::Reconnecting { attempt }
+                };
+                set_connection_state(connections, bound_addr, state);
+                backoff.wait().await;
+            }
+        }
+    }
+}
+
+/// Exponential backoff between reconnect attempts.
+#[derive(Debug)]
+struct Backoff {
+    attempt: u32,
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            attempt: 0,
+            delay: RECONNECT_BACKOFF_INITIAL,
+        }
+    }
+}
+
+impl Backoff {
+    async fn wait(&mut self) {
+        self.attempt += 1;
+        tokio::time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(RECONNECT_BACKOFF_MAX);
+    }
+}
+
+fn set_connection_state(
+    connections: &watch::Sender<Vec<ConnectionInfo>>,
+    bound_addr: SocketAddr,
+    state: ConnectionState,
+) {
+    connections.send_if_modified(|connections| {
+        match connections.iter_mut().find(|c| c.bound_addr == bound_addr) {
+            Some(connection) if connection.state != state => {
+                connection.state = state;
+                true
+            }
+            _ => false,
+        }
+    });
+}
+
+fn remove_connection(connections: &watch::Sender<Vec<ConnectionInfo>>, bound_addr: SocketAddr) {
+    connections.send_if_modified(|connections| {
+        let before = connections.len();
+        connections.retain(|c| c.bound_addr != bound_addr);
+        connections.len() != before
+    });
+}
+
 pub struct OutboundProxyHandle {
     task: JoinHandle<()>,
     bound_addr: SocketAddr,
     remote_id: EndpointId,
     advertisment: TcpProxyData,
+    connections: Arc<watch::Sender<Vec<ConnectionInfo>>>,
 }
 
 impl OutboundProxyHandle {
     pub fn abort(&self) {
         self.task.abort();
+        remove_connection(&self.connections, self.bound_addr);
     }
 
     pub fn remote_id(&self) -> EndpointId {
@@ -347,25 +2350,114 @@ impl OutboundProxyHandle {
     }
 }
 
+/// Serve the proxies on a second endpoint with `key`, for the previous key
+/// of a rotation or a proxy's pinned key.
+async fn serve_extra_key(
+    key: SecretKey,
+    config: &Config,
+    timeouts: TunnelTimeouts,
+    state: StateWrapper,
+    repo: Repo,
+    clients: InboundClients,
+) -> Result<Router> {
+    // Fixed ports are taken by the current key's endpoint.
+    let mut config = config.clone();
+    config.ipv4_addr = config
+        .ipv4_addr
+        .map(|addr| SocketAddrV4::new(*addr.ip(), 0));
+    config.ipv6_addr = config
+        .ipv6_addr
+        .map(|addr| SocketAddrV6::new(*addr.ip(), 0, 0, 0));
+    let endpoint = build_endpoint(key, &config, timeouts).await?;
+    let auth = TrackingAuth {
+        endpoint: endpoint.clone(),
+        state,
+        repo,
+        clients,
+    };
+    let upstream_proxy = UpstreamProxy::new(auth.clone())?;
+    Ok(Router::builder(endpoint)
+        .accept(IROH_HTTP_CONNECT_ALPN, upstream_proxy)
+        .accept(HTTP2_UPSTREAM_ALPN, Http2Upstream::new(auth, &config))
+        .spawn())
+}
+
+/// Accept clients of the previous listen key until its grace period ends,
+/// then delete it. See [`crate::key_rotation`].
+async fn serve_previous_key(
+    key: SecretKey,
+    rotation: KeyRotation,
+    config: &Config,
+    timeouts: TunnelTimeouts,
+    state: StateWrapper,
+    repo: Repo,
+    clients: InboundClients,
+) -> Result<AbortOnDropHandle<()>> {
+    let router = serve_extra_key(key, config, timeouts, state, repo.clone(), clients).await?;
+    let remaining = rotation.remaining(chrono::Utc::now());
+    info!(
+        previous = %rotation.previous.fmt_short(),
+        until = %rotation.grace_until,
+        "serving previous listen key",
+    );
+    let task = tokio::spawn(
+        async move {
+            n0_future::time::sleep(remaining).await;
+            if let Err(err) = router.shutdown().await {
+                warn!("Failed to stop the previous key's endpoint: {err:#}");
+            }
+            if let Err(err) = repo.finish_key_rotation().await {
+                warn!("Failed to delete the previous listen key: {err:#}");
+            }
+            info!("previous listen key retired");
+        }
+        .instrument(error_span!("previous-key")),
+    );
+    Ok(AbortOnDropHandle::new(task))
+}
+
 /// Build a new iroh endpoint, applying all relevant details from Configuration
 /// to the base endpoint setup
-pub(crate) async fn build_endpoint(secret_key: SecretKey, common: &Config) -> Result<Endpoint> {
+pub(crate) async fn build_endpoint(
+    secret_key: SecretKey,
+    common: &Config,
+    timeouts: TunnelTimeouts,
+) -> Result<Endpoint> {
+    let relay_mode = common.relay_mode()?;
     let mut builder = match common.discovery_mode {
-        crate::config::DiscoveryMode::Dns => {
-            Endpoint::empty_builder(default_relay_mode()).secret_key(secret_key)
+        crate::config::DiscoveryMode::Dns | crate::config::DiscoveryMode::Static => {
+            Endpoint::empty_builder(relay_mode).secret_key(secret_key)
         }
         crate::config::DiscoveryMode::Default | crate::config::DiscoveryMode::Hybrid => {
-            Endpoint::builder().secret_key(secret_key)
+            Endpoint::builder()
+                .relay_mode(relay_mode)
+                .secret_key(secret_key)
         }
     };
-    if let Some(addr) = common.ipv4_addr {
-        builder = builder.bind_addr_v4(addr);
+    match common.ip_family {
+        IpFamily::Ipv6Only => {
+            if let Some(addr) = common.ipv4_addr
+                && !addr.ip().is_loopback()
+            {
+                n0_error::bail_any!("ipv4_addr {addr} can't be used with ip_family ipv6_only");
+            }
+            builder = builder.bind_addr_v4(
+                common
+                    .ipv4_addr
+                    .unwrap_or(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)),
+            );
+        }
+        IpFamily::Any | IpFamily::PreferIpv6 => {
+            if let Some(addr) = common.ipv4_addr {
+                builder = builder.bind_addr_v4(addr);
+            }
+        }
     }
     if let Some(addr) = common.ipv6_addr {
         builder = builder.bind_addr_v6(addr);
     }
     match common.discovery_mode {
-        crate::config::DiscoveryMode::Default => {}
+        crate::config::DiscoveryMode::Default | crate::config::DiscoveryMode::Static => {}
         crate::config::DiscoveryMode::Dns | crate::config::DiscoveryMode::Hybrid => {
             let origin = match &common.dns_origin {
                 Some(origin) => origin.clone(),
@@ -373,20 +2465,63 @@ pub(crate) async fn build_endpoint(secret_key: SecretKey, common: &Config) -> Re
                     "dns_origin is required when discovery_mode is set to dns or hybrid"
                 ),
             };
-            if let Some(resolver_addr) = common.dns_resolver {
+            if let Some(resolver_addr) = common.dns_resolver_addr() {
                 let resolver = DnsResolver::builder()
                     .with_nameserver(resolver_addr, DnsProtocol::Udp)
                     .build();
                 builder = builder.dns_resolver(resolver);
             }
             builder = builder.discovery(DnsDiscovery::builder(origin));
+            if let Some(pkarr_relay) = &common.pkarr_relay {
+                builder = builder.discovery(PkarrPublisher::builder(pkarr_relay.clone()));
+            }
+        }
+    }
+    if !common.static_endpoints.is_empty() {
+        let provider = StaticProvider::new();
+        for endpoint in &common.static_endpoints {
+            provider.add_endpoint_info(endpoint.addr());
         }
+        builder = builder.discovery(provider);
+    }
+    if !timeouts.is_default() {
+        builder = builder.transport_config(transport_config(timeouts)?);
     }
     let endpoint = builder.bind().await?;
+    if common.ip_family == IpFamily::Ipv6Only
+        && !endpoint.bound_sockets().iter().any(|addr| addr.is_ipv6())
+    {
+        n0_error::bail_any!("ip_family is ipv6_only, but no IPv6 socket could be bound");
+    }
     info!(id = %endpoint.id(), "iroh endpoint bound");
     Ok(endpoint)
 }
 
+/// Keepalive interval used when a tunnel only sets an idle timeout.
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(5);
+/// Idle timeout used when a tunnel only sets a keepalive interval.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn transport_config(timeouts: TunnelTimeouts) -> Result<TransportConfig> {
+    let idle_timeout = timeouts.idle_timeout().unwrap_or(DEFAULT_IDLE_TIMEOUT);
+    let keepalive = timeouts.keepalive().unwrap_or(DEFAULT_KEEPALIVE);
+    if keepalive >= idle_timeout {
+        warn!(
+            ?keepalive,
+            ?idle_timeout,
+            "keepalive is not shorter than the idle timeout; idle connections will be closed"
+        );
+    }
+    let idle_timeout = idle_timeout
+        .try_into()
+        .std_context("idle timeout out of range")?;
+    let mut config = TransportConfig::default();
+    config
+        .max_idle_timeout(Some(idle_timeout))
+        .keep_alive_interval(Some(keepalive));
+    Ok(config)
+}
+
 pub(crate) fn n0des_api_secret_from_env() -> Result<Option<ApiSecret>> {
     let api_secret_str = match std::env::var("N0DES_API_SECRET") {
         Ok(s) => s,
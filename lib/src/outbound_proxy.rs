@@ -0,0 +1,153 @@
+//! HTTP(S) proxy for the connections to Datum Cloud.
+//!
+//! Corporate networks often only let HTTP out through a proxy. The proxy is
+//! taken from the `outbound_proxy` section of the [`Config`](crate::Config),
+//! or else from the usual `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and
+//! `NO_PROXY` environment variables, and used for the login, the Datum API
+//! and the project control planes alike. Tunnel traffic goes over iroh and
+//! is not affected.
+
+use std::net::IpAddr;
+
+use n0_error::{Result, StdResultExt};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::ip_filter::IpCidr;
+
+const PROXY_ENV: [&str; 6] = [
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+const NO_PROXY_ENV: [&str; 2] = ["NO_PROXY", "no_proxy"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct OutboundProxy {
+    /// Proxy URL, e.g. `http://proxy.corp.example:3128`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Hosts to reach without the proxy, comma separated in `NO_PROXY`
+    /// syntax: `*`, host names, `.domain` suffixes, IPs and CIDRs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+}
+
+fn first_env(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+impl OutboundProxy {
+    pub fn from_env() -> Self {
+        Self {
+            url: first_env(&PROXY_ENV),
+            no_proxy: first_env(&NO_PROXY_ENV),
+        }
+    }
+
+    /// This proxy, with what it leaves unset taken from the environment.
+    pub fn or_env(self) -> Self {
+        let env = Self::from_env();
+        Self {
+            url: self.url.or(env.url),
+            no_proxy: self.no_proxy.or(env.no_proxy),
+        }
+    }
+
+    /// Whether `host` is reached directly according to `no_proxy`.
+    pub fn bypasses(&self, host: &str) -> bool {
+        let Some(no_proxy) = &self.no_proxy else {
+            return false;
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = host.parse::<IpAddr>().ok();
+        no_proxy
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| {
+                if entry == "*" {
+                    return true;
+                }
+                if let Some(ip) = ip {
+                    return entry.parse::<IpCidr>().is_ok_and(|cidr| cidr.contains(ip));
+                }
+                let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+                host.eq_ignore_ascii_case(domain)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+            })
+    }
+
+    /// Route the client's requests through the proxy. Without one, the
+    /// client connects directly and ignores the environment, which was
+    /// already consulted by [`Self::or_env`].
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+        let Some(url) = &self.url else {
+            return Ok(builder.no_proxy());
+        };
+        let proxy = reqwest::Proxy::all(url)
+            .with_std_context(|_| format!("Invalid proxy URL {url:?}"))?
+            .no_proxy(
+                self.no_proxy
+                    .as_deref()
+                    .and_then(reqwest::NoProxy::from_string),
+            );
+        Ok(builder.proxy(proxy))
+    }
+
+    /// Route a kube client's requests through the proxy, unless its cluster
+    /// is excluded by `no_proxy`.
+    pub fn apply_kube(&self, config: &mut kube::Config) {
+        let Some(url) = &self.url else {
+            return;
+        };
+        if config
+            .cluster_url
+            .host()
+            .is_some_and(|host| self.bypasses(host))
+        {
+            return;
+        }
+        match url.parse() {
+            Ok(url) => config.proxy_url = Some(url),
+            Err(err) => warn!("Ignoring invalid proxy URL {url:?}: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_no_proxy() {
+        let proxy = OutboundProxy {
+            url: Some("http://proxy:3128".to_string()),
+            no_proxy: Some("localhost, .internal.example,10.0.0.0/8,::1".to_string()),
+        };
+        assert!(proxy.bypasses("localhost"));
+        assert!(proxy.bypasses("api.internal.example"));
+        assert!(proxy.bypasses("internal.example"));
+        assert!(!proxy.bypasses("notinternal.example"));
+        assert!(proxy.bypasses("10.1.2.3"));
+        assert!(proxy.bypasses("[::1]"));
+        assert!(!proxy.bypasses("api.datum.net"));
+        assert!(!proxy.bypasses("192.168.1.1"));
+
+        let all = OutboundProxy {
+            no_proxy: Some("*".to_string()),
+            ..proxy
+        };
+        assert!(all.bypasses("api.datum.net"));
+    }
+}
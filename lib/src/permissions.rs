@@ -0,0 +1,285 @@
+//! Preflight checks of what the signed-in user may do with tunnels.
+//!
+//! Tunnels are HTTPProxy and ConnectorAdvertisement objects (plus the
+//! Connector behind them) in a project's control plane. Users with read-only
+//! access to a project otherwise only find out through a generic API error
+//! halfway through an operation. Asking the API server with
+//! `SelfSubjectAccessReview`s up front lets callers fail with a
+//! [`PermissionDenied`] naming the missing access, and lets the UI hide
+//! actions the user can't take.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::{Api, Client, api::PostParams};
+use n0_error::{Result, StdResultExt, stack_error};
+use ttl_cache::TtlCache;
+
+const API_GROUP: &str = "networking.datumapis.com";
+const NAMESPACE: &str = "default";
+
+/// How long checked permissions are reused before asking again.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+const CACHE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verb {
+    List,
+    Create,
+    Patch,
+    Delete,
+}
+
+impl Verb {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Create => "create",
+            Self::Patch => "patch",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+impl fmt::Display for Verb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self {
+            Self::List => "view",
+            Self::Create => "create",
+            Self::Patch => "change",
+            Self::Delete => "delete",
+        };
+        f.write_str(verb)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resource {
+    HttpProxies,
+    ConnectorAdvertisements,
+    Connectors,
+}
+
+impl Resource {
+    fn plural(&self) -> &'static str {
+        match self {
+            Self::HttpProxies => "httpproxies",
+            Self::ConnectorAdvertisements => "connectoradvertisements",
+            Self::Connectors => "connectors",
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let resource = match self {
+            Self::HttpProxies => "HTTP proxies",
+            Self::ConnectorAdvertisements => "connector advertisements",
+            Self::Connectors => "connectors",
+        };
+        f.write_str(resource)
+    }
+}
+
+/// Access needed to list tunnels.
+pub const LIST: &[(Verb, Resource)] = &[
+    (Verb::List, Resource::HttpProxies),
+    (Verb::List, Resource::ConnectorAdvertisements),
+    (Verb::List, Resource::Connectors),
+];
+
+/// Access needed to create a tunnel, including the connector on first use.
+pub const CREATE: &[(Verb, Resource)] = &[
+    (Verb::Create, Resource::HttpProxies),
+    (Verb::Create, Resource::ConnectorAdvertisements),
+    (Verb::Create, Resource::Connectors),
+];
+
+/// Access needed to change a tunnel's target, label or filters.
+pub const UPDATE: &[(Verb, Resource)] = &[(Verb::Patch, Resource::HttpProxies)];
+
+/// Access needed to turn a tunnel on or off.
+pub const TOGGLE: &[(Verb, Resource)] = &[
+    (Verb::Create, Resource::ConnectorAdvertisements),
+    (Verb::Delete, Resource::ConnectorAdvertisements),
+];
+
+/// Access needed to delete a tunnel.
+pub const DELETE: &[(Verb, Resource)] = &[
+    (Verb::Delete, Resource::HttpProxies),
+    (Verb::Delete, Resource::ConnectorAdvertisements),
+];
+
+/// Access needed to revoke another device's connector.
+pub const REVOKE: &[(Verb, Resource)] = &[
+    (Verb::Delete, Resource::Connectors),
+    (Verb::Delete, Resource::ConnectorAdvertisements),
+];
+
+#[stack_error(derive)]
+#[error(
+    "You don't have permission to {verb} {resource} in project {project_id}. Ask an admin of the project for access."
+)]
+pub struct PermissionDenied {
+    pub project_id: String,
+    pub verb: Verb,
+    pub resource: Resource,
+}
+
+/// What the signed-in user may not do with tunnels in a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TunnelPermissions {
+    pub project_id: String,
+    pub denied: Vec<(Verb, Resource)>,
+}
+
+impl TunnelPermissions {
+    pub fn allows(&self, verb: Verb, resource: Resource) -> bool {
+        !self.denied.contains(&(verb, resource))
+    }
+
+    pub fn can_view(&self) -> bool {
+        self.require(LIST).is_ok()
+    }
+
+    /// Whether the user can make every kind of tunnel change.
+    pub fn can_manage(&self) -> bool {
+        [CREATE, UPDATE, TOGGLE, DELETE]
+            .iter()
+            .all(|needed| self.require(needed).is_ok())
+    }
+
+    /// Fails with the first of `needed` the user lacks.
+    pub fn require(&self, needed: &[(Verb, Resource)]) -> Result<(), PermissionDenied> {
+        match needed
+            .iter()
+            .find(|(verb, resource)| !self.allows(*verb, *resource))
+        {
+            Some(&(verb, resource)) => Err(PermissionDenied {
+                project_id: self.project_id.clone(),
+                verb,
+                resource,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Ask the project's API server which tunnel operations the user may perform.
+pub async fn check(client: Client, project_id: &str) -> Result<TunnelPermissions> {
+    let reviews: Api<SelfSubjectAccessReview> = Api::all(client);
+    let mut reviewed = Vec::new();
+    let mut denied = Vec::new();
+    let all = LIST
+        .iter()
+        .chain(CREATE)
+        .chain(UPDATE)
+        .chain(TOGGLE)
+        .chain(DELETE)
+        .chain(REVOKE);
+    for &(verb, resource) in all {
+        if reviewed.contains(&(verb, resource)) {
+            continue;
+        }
+        reviewed.push((verb, resource));
+        let review = SelfSubjectAccessReview {
+            spec: SelfSubjectAccessReviewSpec {
+                resource_attributes: Some(ResourceAttributes {
+                    group: Some(API_GROUP.to_string()),
+                    namespace: Some(NAMESPACE.to_string()),
+                    resource: Some(resource.plural().to_string()),
+                    verb: Some(verb.as_str().to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let review = reviews
+            .create(&PostParams::default(), &review)
+            .await
+            .std_context("Failed to review access")?;
+        if !review.status.is_some_and(|status| status.allowed) {
+            denied.push((verb, resource));
+        }
+    }
+    Ok(TunnelPermissions {
+        project_id: project_id.to_string(),
+        denied,
+    })
+}
+
+/// Recently checked permissions by project.
+#[derive(Clone)]
+pub struct PermissionCache {
+    entries: Arc<Mutex<TtlCache<String, TunnelPermissions>>>,
+}
+
+impl Default for PermissionCache {
+    fn default() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(TtlCache::new(CACHE_CAPACITY))),
+        }
+    }
+}
+
+impl fmt::Debug for PermissionCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PermissionCache").finish_non_exhaustive()
+    }
+}
+
+impl PermissionCache {
+    pub fn get(&self, project_id: &str) -> Option<TunnelPermissions> {
+        self.entries
+            .lock()
+            .expect("poisoned")
+            .get(project_id)
+            .cloned()
+    }
+
+    pub fn insert(&self, permissions: TunnelPermissions) {
+        self.entries.lock().expect("poisoned").insert(
+            permissions.project_id.clone(),
+            permissions,
+            CACHE_TTL,
+        );
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().expect("poisoned").clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_first_missing_permission() {
+        let permissions = TunnelPermissions {
+            project_id: "p".to_string(),
+            denied: vec![
+                (Verb::Patch, Resource::HttpProxies),
+                (Verb::Delete, Resource::HttpProxies),
+            ],
+        };
+        assert!(permissions.can_view());
+        assert!(!permissions.can_manage());
+        assert!(permissions.require(CREATE).is_ok());
+
+        let err = permissions.require(DELETE).unwrap_err();
+        assert_eq!(err.verb, Verb::Delete);
+        assert_eq!(err.resource, Resource::HttpProxies);
+        assert!(
+            err.to_string()
+                .starts_with("You don't have permission to delete HTTP proxies in project p.")
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeSet, path::PathBuf, time::Duration};
+
+use n0_error::{Result, StackResultExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{heartbeat::AgentStatusField, repo::migrations};
+
+/// User-facing app preferences, persisted in the repo as `preferences.yml`.
+///
+/// Unlike [`crate::Config`], nothing in here affects how the node talks to
+/// the network; these only change what the desktop app does on its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Preferences {
+    /// Watch the clipboard for tunnel tickets and offer to join them.
+    ///
+    /// Off by default: reading the clipboard in the background is only done
+    /// when the user opts in.
+    #[serde(default)]
+    pub clipboard_watch: bool,
+
+    /// Draw the bandwidth chart cheaply: fewer points, straight lines and a
+    /// slower refresh. For low-end machines.
+    #[serde(default)]
+    pub chart_performance_mode: bool,
+
+    /// Send anonymous usage counts to Datum. See [`crate::telemetry`].
+    ///
+    /// Off by default: nothing is collected until the user opts in.
+    #[serde(default)]
+    pub telemetry: bool,
+
+    /// Send crash reports to Datum. See [`crate::crash`].
+    ///
+    /// Off by default: reports stay on this device until the user opts in.
+    #[serde(default)]
+    pub crash_reports: bool,
+
+    /// Agent status fields not to report on this device's connectors. See
+    /// [`AgentStatusField`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub redacted_agent_status: BTreeSet<AgentStatusField>,
+
+    /// Push agent metrics to a statsd server. Only used when built with the
+    /// `statsd` feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statsd: Option<StatsdConfig>,
+}
+
+/// Where and how often to push agent metrics to statsd.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StatsdConfig {
+    pub host: String,
+    #[serde(default = "default_statsd_port")]
+    pub port: u16,
+    /// Prepended to every metric name, followed by a dot. May be empty.
+    #[serde(default = "default_statsd_prefix")]
+    pub prefix: String,
+    /// Send tunnels and error classes as DogStatsD tags instead of putting
+    /// them in the metric name.
+    #[serde(default)]
+    pub dogstatsd: bool,
+    #[serde(default = "default_statsd_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl StatsdConfig {
+    /// A config pushing to `host` on the default port.
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            port: default_statsd_port(),
+            prefix: default_statsd_prefix(),
+            dogstatsd: false,
+            interval_secs: default_statsd_interval_secs(),
+        }
+    }
+
+    /// Time between pushes, at least a second.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+}
+
+fn default_statsd_port() -> u16 {
+    8125
+}
+
+fn default_statsd_prefix() -> String {
+    "datum_connect".to_string()
+}
+
+fn default_statsd_interval_secs() -> u64 {
+    10
+}
+
+impl Preferences {
+    pub async fn from_file(path: PathBuf) -> Result<Self> {
+        let data = tokio::fs::read(&path)
+            .await
+            .context("reading preferences file")?;
+        let (prefs, migrated): (Self, bool) = migrations::PREFERENCES.parse(&data)?;
+        if migrated {
+            prefs.write(path).await?;
+        }
+        Ok(prefs)
+    }
+
+    pub async fn write(&self, path: PathBuf) -> Result<()> {
+        let data = migrations::PREFERENCES.to_string(self)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+}
@@ -5,9 +5,11 @@ use kube::{Client, Config};
 use n0_error::{Result, StdResultExt};
 use n0_future::task::AbortOnDropHandle;
 use secrecy::SecretString;
+use tokio::sync::broadcast;
 use tracing::warn;
 
-use crate::datum_cloud::{DatumCloudClient, LoginState};
+use crate::datum_cloud::{AuthEvent, DatumCloudClient, LoginState};
+use crate::outbound_proxy::OutboundProxy;
 
 #[derive(derive_more::Debug, Clone)]
 pub struct ProjectControlPlaneClient {
@@ -27,7 +29,7 @@ impl ProjectControlPlaneClient {
         access_token: String,
         datum: DatumCloudClient,
     ) -> Result<Self> {
-        let client = Self::build_kube_client(&server_url, &access_token)?;
+        let client = Self::build_kube_client(&server_url, &access_token, datum.outbound_proxy())?;
         let mut this = Self {
             project_id,
             server_url,
@@ -64,13 +66,27 @@ impl ProjectControlPlaneClient {
         Ok(self.client())
     }
 
-    fn build_kube_client(server_url: &str, access_token: &str) -> Result<Client> {
+    fn build_kube_client(
+        server_url: &str,
+        access_token: &str,
+        proxy: &OutboundProxy,
+    ) -> Result<Client> {
         let uri = server_url
             .parse()
             .std_context("Invalid project control plane URL")?;
         let mut config = Config::new(uri);
         config.auth_info.token = Some(SecretString::new(access_token.to_string().into_boxed_str()));
-        Client::try_from(config).std_context("Failed to create project control plane client")
+        proxy.apply_kube(&mut config);
+        match Client::try_from(config.clone()) {
+            Ok(client) => Ok(client),
+            Err(err) if config.proxy_url.is_some() => {
+                warn!("Failed to use the proxy for the project control plane: {err:#}");
+                config.proxy_url = None;
+                Client::try_from(config)
+                    .std_context("Failed to create project control plane client")
+            }
+            Err(err) => Err(err).std_context("Failed to create project control plane client"),
+        }
     }
 
     fn rebuild_if_changed(&self, access_token: &str) -> Result<()> {
@@ -79,7 +95,8 @@ impl ProjectControlPlaneClient {
             return Ok(());
         }
 
-        let client = Self::build_kube_client(&self.server_url, access_token)?;
+        let client =
+            Self::build_kube_client(&self.server_url, access_token, self.datum.outbound_proxy())?;
         self.client.store(Arc::new(client));
         self.access_token.store(Arc::new(access_token.to_string()));
         Ok(())
@@ -98,32 +115,23 @@ impl ProjectControlPlaneClient {
             return;
         }
         let client = self.clone();
-        let mut login_rx = self.datum.auth().login_state_watch();
-        let mut auth_update_rx = self.datum.auth_update_watch();
+        let mut events = self.datum.auth_events();
         let task = tokio::spawn(async move {
-            if *login_rx.borrow() != LoginState::Missing
-                && let Err(err) = client.refresh_client_from_update().await
-            {
-                warn!("failed to refresh project control plane client: {err:#}");
-            }
             loop {
-                tokio::select! {
-                    res = login_rx.changed() => {
-                        if res.is_err() {
-                            return;
-                        }
-                    }
-                    res = auth_update_rx.changed() => {
-                        if res.is_err() {
-                            return;
-                        }
-                    }
-                }
-                if *login_rx.borrow() != LoginState::Missing
+                if client.datum.login_state() != LoginState::Missing
                     && let Err(err) = client.refresh_client_from_update().await
                 {
                     warn!("failed to refresh project control plane client: {err:#}");
                 }
+                // Only a new access token needs a new client.
+                loop {
+                    match events.recv().await {
+                        Ok(AuthEvent::LoggedIn { .. } | AuthEvent::Refreshed)
+                        | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    }
+                }
             }
         });
         self._auth_task = Some(Arc::new(AbortOnDropHandle::new(task)));
@@ -0,0 +1,29 @@
+//! QR code rendering for sharing tickets with another device.
+
+use n0_error::{Result, StdResultExt};
+use qrcode::{
+    QrCode,
+    render::{svg, unicode},
+};
+
+/// Render `data` as a QR code made of unicode half blocks, for printing to a
+/// terminal.
+pub fn render_terminal(data: &str) -> Result<String> {
+    let code = QrCode::new(data.as_bytes()).std_context("encoding QR code")?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Render `data` as an SVG document, for display in the UI.
+pub fn render_svg(data: &str, size: u32) -> Result<String> {
+    let code = QrCode::new(data.as_bytes()).std_context("encoding QR code")?;
+    Ok(code
+        .render::<svg::Color<'_>>()
+        .min_dimensions(size, size)
+        .quiet_zone(true)
+        .build())
+}
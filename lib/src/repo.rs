@@ -1,30 +1,85 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use iroh::SecretKey;
 use log::{info, warn};
 use n0_error::{Result, StackResultExt, StdResultExt};
+use tokio::sync::watch;
 
 use crate::{
     StateWrapper,
+    activity::ActivityLog,
     auth::Auth,
+    bandwidth_history::BandwidthHistory,
     config::{Config, GatewayConfig},
     datum_cloud::AuthState,
-    state::State,
+    events::EventLog,
+    key_rotation::KeyRotation,
+    preferences::Preferences,
+    secret_store::{self, SecretStore},
+    state::{SelectedContext, State},
+    usage::UsageLog,
 };
 
+pub(crate) mod migrations;
+
+/// How often [`Repo::watch_state`] looks for changes on disk.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The parts of a repo other processes may change while we run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoSnapshot {
+    pub state: State,
+    pub preferences: Preferences,
+    pub selected_context: Option<SelectedContext>,
+}
+
 // Repo builds up a series of file path conventions from a root directory path.
+// Secrets (keys and OAuth tokens) go through a [`SecretStore`] instead.
 #[derive(Debug, Clone)]
-pub struct Repo(PathBuf);
+pub struct Repo {
+    path: PathBuf,
+    secrets: Arc<dyn SecretStore>,
+    events: EventLog,
+    activity: ActivityLog,
+    usage: UsageLog,
+    bandwidth_history: BandwidthHistory,
+}
 
 impl Repo {
     const CONNECT_KEY_FILE: &str = "connect_key";
     const LISTEN_KEY_FILE: &str = "listen_key";
     const GATEWAY_KEY_FILE: &str = "gateway_key";
+    const PREVIOUS_LISTEN_KEY_FILE: &str = "listen_key.previous";
+    const TUNNEL_KEY_PREFIX: &str = "tunnel_key";
+    const KEY_ROTATION_FILE: &str = "key_rotation.yml";
     const CONFIG_FILE: &str = "config.yml";
     const OAUTH_FILE: &str = "oauth.yml";
     const AUTH_FILE: &str = "auth.yml";
     const STATE_FILE: &str = "state.yml";
     const SELECTED_CONTEXT_FILE: &str = "selected_context.yml";
+    const PREFERENCES_FILE: &str = "preferences.yml";
+    const EVENTS_FILE: &str = "events.jsonl";
+    const ACTIVITY_FILE: &str = "activity.json";
+    const USAGE_FILE: &str = "usage.json";
+    const BANDWIDTH_FILE: &str = "bandwidth.json";
+    const PENDING_MUTATIONS_FILE: &str = "pending_mutations.yml";
+    const PROFILES_DIR: &str = "profiles";
+    const ACTIVE_PROFILE_FILE: &str = "active_profile";
+    const ACCOUNTS_FILE: &str = "accounts.yml";
+    const ACTIVE_ACCOUNT_FILE: &str = "active_account";
+
+    /// The profile that lives directly in the base directory, so repos created
+    /// before profiles existed keep working unchanged.
+    pub const DEFAULT_PROFILE: &str = "default";
+
+    /// The account whose login is stored where logins were kept before
+    /// accounts existed.
+    pub const DEFAULT_ACCOUNT: &str = "default";
 
     pub fn default_location() -> PathBuf {
         match std::env::var("DATUM_CONNECT_REPO") {
@@ -41,13 +96,119 @@ impl Repo {
         tokio::fs::create_dir_all(&base_dir).await?;
         info!("opening repo at {}", base_dir.display());
 
-        let this = Self(base_dir);
+        let secrets = {
+            let base_dir = base_dir.clone();
+            tokio::task::spawn_blocking(move || secret_store::from_env(base_dir))
+                .await
+                .std_context("secret store task failed")??
+        };
+        info!("using {} secret store", secrets.kind());
+        let this = Self {
+            events: EventLog::open(base_dir.join(Self::EVENTS_FILE)),
+            activity: ActivityLog::open(base_dir.join(Self::ACTIVITY_FILE)),
+            usage: UsageLog::open(base_dir.join(Self::USAGE_FILE)),
+            bandwidth_history: BandwidthHistory::open(base_dir.join(Self::BANDWIDTH_FILE)),
+            path: base_dir,
+            secrets: secrets.into(),
+        };
 
         Ok(this)
     }
 
+    /// Opens or creates a repo with an explicit secret store.
+    pub async fn open_with_secret_store(
+        base_dir: impl Into<PathBuf>,
+        secrets: impl SecretStore,
+    ) -> Result<Self> {
+        let base_dir = base_dir.into();
+        tokio::fs::create_dir_all(&base_dir).await?;
+        Ok(Self {
+            events: EventLog::open(base_dir.join(Self::EVENTS_FILE)),
+            activity: ActivityLog::open(base_dir.join(Self::ACTIVITY_FILE)),
+            usage: UsageLog::open(base_dir.join(Self::USAGE_FILE)),
+            bandwidth_history: BandwidthHistory::open(base_dir.join(Self::BANDWIDTH_FILE)),
+            path: base_dir,
+            secrets: Arc::new(secrets),
+        })
+    }
+
+    /// Opens or creates the named profile under the default location.
+    ///
+    /// Each profile has its own keys, auth state and tunnel state.
+    pub async fn open_profile(name: &str) -> Result<Self> {
+        Self::open_profile_in(Self::default_location(), name).await
+    }
+
+    /// Opens or creates the named profile under `base_dir`.
+    pub async fn open_profile_in(base_dir: impl AsRef<Path>, name: &str) -> Result<Self> {
+        let path = Self::profile_location(base_dir.as_ref(), name)?;
+        Self::open_or_create(path).await
+    }
+
+    /// Directory of the named profile under `base_dir`.
+    pub fn profile_location(base_dir: &Path, name: &str) -> Result<PathBuf> {
+        if name == Self::DEFAULT_PROFILE {
+            return Ok(base_dir.to_path_buf());
+        }
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            n0_error::bail_any!(
+                "invalid profile name {name:?}: use letters, digits, '-' and '_' only"
+            );
+        }
+        Ok(base_dir.join(Self::PROFILES_DIR).join(name))
+    }
+
+    /// Lists the profiles under `base_dir`, starting with the default profile.
+    pub async fn list_profiles(base_dir: impl AsRef<Path>) -> Result<Vec<String>> {
+        let mut profiles = vec![Self::DEFAULT_PROFILE.to_string()];
+        let dir = base_dir.as_ref().join(Self::PROFILES_DIR);
+        if !dir.exists() {
+            return Ok(profiles);
+        }
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        let mut named = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir()
+                && let Some(name) = entry.file_name().to_str()
+            {
+                named.push(name.to_string());
+            }
+        }
+        named.sort();
+        profiles.extend(named);
+        Ok(profiles)
+    }
+
+    /// The profile the desktop app opens on startup.
+    pub async fn read_active_profile(base_dir: impl AsRef<Path>) -> Result<String> {
+        let path = base_dir.as_ref().join(Self::ACTIVE_PROFILE_FILE);
+        if !path.exists() {
+            return Ok(Self::DEFAULT_PROFILE.to_string());
+        }
+        let name = tokio::fs::read_to_string(path)
+            .await
+            .context("failed to read active profile file")?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Ok(Self::DEFAULT_PROFILE.to_string());
+        }
+        Ok(name.to_string())
+    }
+
+    pub async fn write_active_profile(base_dir: impl AsRef<Path>, name: &str) -> Result<()> {
+        // Validate before persisting so a bad name can't brick startup.
+        Self::profile_location(base_dir.as_ref(), name)?;
+        tokio::fs::create_dir_all(base_dir.as_ref()).await?;
+        tokio::fs::write(base_dir.as_ref().join(Self::ACTIVE_PROFILE_FILE), name).await?;
+        Ok(())
+    }
+
     pub async fn config(&self) -> Result<Config> {
-        let config_file_path = self.0.join(Self::CONFIG_FILE);
+        let config_file_path = self.path.join(Self::CONFIG_FILE);
         if !config_file_path.exists() {
             warn!("secret key does not exist. creating new key");
             let cfg = Config::default();
@@ -59,7 +220,7 @@ impl Repo {
     }
 
     pub async fn gateway_config(&self) -> Result<GatewayConfig> {
-        let config_file_path = self.0.join(Self::CONFIG_FILE);
+        let config_file_path = self.path.join(Self::CONFIG_FILE);
         if !config_file_path.exists() {
             warn!("gateway config does not exist. creating new config");
             let cfg = GatewayConfig::default();
@@ -70,8 +231,20 @@ impl Repo {
         GatewayConfig::from_file(config_file_path).await
     }
 
+    pub async fn preferences(&self) -> Result<Preferences> {
+        let path = self.path.join(Self::PREFERENCES_FILE);
+        if !path.exists() {
+            return Ok(Preferences::default());
+        }
+        Preferences::from_file(path).await
+    }
+
+    pub async fn write_preferences(&self, prefs: &Preferences) -> Result<()> {
+        prefs.write(self.path.join(Self::PREFERENCES_FILE)).await
+    }
+
     pub async fn load_state(&self) -> Result<StateWrapper> {
-        let state_file_path = self.0.join(Self::STATE_FILE);
+        let state_file_path = self.path.join(Self::STATE_FILE);
         let state = if !state_file_path.exists() {
             let state = State::default();
             state.write_to_file(state_file_path).await?;
@@ -83,21 +256,91 @@ impl Repo {
     }
 
     pub async fn write_state(&self, state: &State) -> Result<()> {
-        state.write_to_file(self.0.join(Self::STATE_FILE)).await
+        state.write_to_file(self.path.join(Self::STATE_FILE)).await
+    }
+
+    /// Watch the state, preferences and selected context on disk.
+    ///
+    /// Another process like the CLI or a second app window may write them at
+    /// any time. The files are checked every [`WATCH_INTERVAL`] and a new
+    /// snapshot is sent when one of them changed. A file caught mid-write is
+    /// read again on the next check. Stops once every receiver is dropped.
+    pub async fn watch_state(&self) -> Result<watch::Receiver<RepoSnapshot>> {
+        let mut modified = self.watched_modified().await;
+        let (tx, rx) = watch::channel(self.snapshot().await?);
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    _ = interval.tick() => {}
+                }
+                let current = this.watched_modified().await;
+                if current == modified {
+                    continue;
+                }
+                match this.snapshot().await {
+                    Ok(snapshot) => {
+                        modified = current;
+                        tx.send_if_modified(|prev| {
+                            if *prev == snapshot {
+                                return false;
+                            }
+                            *prev = snapshot;
+                            true
+                        });
+                    }
+                    Err(err) => warn!("failed to reload repo state: {err:#}"),
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn snapshot(&self) -> Result<RepoSnapshot> {
+        let state_file_path = self.path.join(Self::STATE_FILE);
+        let state = if state_file_path.exists() {
+            State::from_file(state_file_path).await?
+        } else {
+            State::default()
+        };
+        Ok(RepoSnapshot {
+            state,
+            preferences: self.preferences().await?,
+            selected_context: self.read_selected_context().await?,
+        })
+    }
+
+    async fn watched_modified(&self) -> [Option<SystemTime>; 3] {
+        let files = [
+            Self::STATE_FILE,
+            Self::PREFERENCES_FILE,
+            Self::SELECTED_CONTEXT_FILE,
+        ];
+        let mut modified = [None; 3];
+        for (slot, file) in modified.iter_mut().zip(files) {
+            *slot = tokio::fs::metadata(self.path.join(file))
+                .await
+                .and_then(|meta| meta.modified())
+                .ok();
+        }
+        modified
     }
 
     pub async fn write_selected_context(
         &self,
         selected: Option<&crate::SelectedContext>,
     ) -> Result<()> {
-        let path = self.0.join(Self::SELECTED_CONTEXT_FILE);
+        let path = self.path.join(Self::SELECTED_CONTEXT_FILE);
         let data = serde_yml::to_string(&selected).anyerr()?;
         tokio::fs::write(path, data).await?;
         Ok(())
     }
 
     pub async fn read_selected_context(&self) -> Result<Option<crate::SelectedContext>> {
-        let path = self.0.join(Self::SELECTED_CONTEXT_FILE);
+        let path = self.path.join(Self::SELECTED_CONTEXT_FILE);
         if path.exists() {
             let data = tokio::fs::read_to_string(path)
                 .await
@@ -109,8 +352,30 @@ impl Repo {
         Ok(None)
     }
 
+    /// Tunnel changes queued while the API was unreachable.
+    pub async fn read_pending_mutations(&self) -> Result<Vec<crate::tunnels::PendingMutation>> {
+        let path = self.path.join(Self::PENDING_MUTATIONS_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .context("failed to read pending mutations file")?;
+        serde_yml::from_str(&data).std_context("failed to parse pending mutations file")
+    }
+
+    pub async fn write_pending_mutations(
+        &self,
+        pending: &[crate::tunnels::PendingMutation],
+    ) -> Result<()> {
+        let path = self.path.join(Self::PENDING_MUTATIONS_FILE);
+        let data = serde_yml::to_string(pending).anyerr()?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
     pub async fn auth(&self) -> Result<Auth> {
-        let auth_file_path = self.0.join(Self::AUTH_FILE);
+        let auth_file_path = self.path.join(Self::AUTH_FILE);
         if !auth_file_path.exists() {
             warn!("auth file does not exist. creating new auth");
             let auth = Auth::default();
@@ -122,41 +387,244 @@ impl Repo {
     }
 
     pub async fn listen_key(&self) -> Result<SecretKey> {
-        let key_file_path = self.0.join(Self::LISTEN_KEY_FILE);
-        self.secret_key(key_file_path).await
+        self.secret_key(Self::LISTEN_KEY_FILE).await
+    }
+
+    /// Replace the listen key with a new one, keeping the current one for
+    /// `grace`. Takes effect when the listen node starts next.
+    pub async fn rotate_listen_key(&self, grace: Duration) -> Result<KeyRotation> {
+        let current = self.listen_key().await?;
+        self.with_secrets(move |secrets| {
+            secrets.set(Self::PREVIOUS_LISTEN_KEY_FILE, &current.to_bytes())
+        })
+        .await?;
+        let rotated_at = chrono::Utc::now();
+        let rotation = KeyRotation {
+            previous: current.public(),
+            rotated_at,
+            grace_until: rotated_at + chrono::TimeDelta::from_std(grace).anyerr()?,
+        };
+        let data = serde_yml::to_string(&rotation).anyerr()?;
+        tokio::fs::write(self.path.join(Self::KEY_ROTATION_FILE), data).await?;
+        let key = self.create_key(Self::LISTEN_KEY_FILE).await?;
+        info!(
+            "rotated listen key from {} to {}",
+            rotation.previous.fmt_short(),
+            key.public().fmt_short()
+        );
+        Ok(rotation)
+    }
+
+    /// The previous listen key while its grace period lasts. Once it's
+    /// over, the key is deleted.
+    pub async fn previous_listen_key(&self) -> Result<Option<(SecretKey, KeyRotation)>> {
+        let path = self.path.join(Self::KEY_ROTATION_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = tokio::fs::read_to_string(path)
+            .await
+            .context("failed to read key rotation file")?;
+        let rotation: KeyRotation =
+            serde_yml::from_str(&data).std_context("failed to parse key rotation file")?;
+        if !rotation.in_grace(chrono::Utc::now()) {
+            self.finish_key_rotation().await?;
+            return Ok(None);
+        }
+        let key = self
+            .with_secrets(|secrets| secrets.get(Self::PREVIOUS_LISTEN_KEY_FILE))
+            .await?;
+        let Some(key) = key else {
+            return Ok(None);
+        };
+        let key = key.as_slice().try_into().anyerr()?;
+        Ok(Some((SecretKey::from_bytes(key), rotation)))
+    }
+
+    /// Delete the previous listen key.
+    pub async fn finish_key_rotation(&self) -> Result<()> {
+        self.with_secrets(|secrets| secrets.delete(Self::PREVIOUS_LISTEN_KEY_FILE))
+            .await?;
+        let path = self.path.join(Self::KEY_ROTATION_FILE);
+        if path.exists() {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// The key a tunnel with a pinned endpoint is served under, created on
+    /// first use.
+    pub async fn tunnel_key(&self, tunnel_id: &str) -> Result<SecretKey> {
+        self.secret_key(&format!("{}.{tunnel_id}", Self::TUNNEL_KEY_PREFIX))
+            .await
+    }
+
+    /// Delete the key of a tunnel's pinned endpoint.
+    pub async fn delete_tunnel_key(&self, tunnel_id: &str) -> Result<()> {
+        let name = format!("{}.{tunnel_id}", Self::TUNNEL_KEY_PREFIX);
+        self.with_secrets(move |secrets| secrets.delete(&name))
+            .await
     }
 
     pub async fn gateway_key(&self) -> Result<SecretKey> {
-        let key_file_path = self.0.join(Self::GATEWAY_KEY_FILE);
-        self.secret_key(key_file_path).await
+        self.secret_key(Self::GATEWAY_KEY_FILE).await
     }
 
     pub async fn connect_key(&self) -> Result<SecretKey> {
-        let key_file_path = self.0.join(Self::CONNECT_KEY_FILE);
-        self.secret_key(key_file_path).await
+        self.secret_key(Self::CONNECT_KEY_FILE).await
     }
 
-    async fn secret_key(&self, key_file_path: PathBuf) -> Result<SecretKey> {
-        if !key_file_path.exists() {
+    async fn secret_key(&self, name: &str) -> Result<SecretKey> {
+        let Some(key) = self.read_secret(name).await? else {
             warn!("secret key does not exist. creating new key");
-            tokio::fs::create_dir_all(&self.0).await?;
-            return self.create_key(&key_file_path).await;
+            return self.create_key(name).await;
         };
-
-        let key = tokio::fs::read(key_file_path).await?;
         let key = key.as_slice().try_into().anyerr()?;
         Ok(SecretKey::from_bytes(key))
     }
 
-    async fn create_key(&self, key_file_path: &PathBuf) -> Result<SecretKey> {
+    async fn create_key(&self, name: &str) -> Result<SecretKey> {
         let key = SecretKey::generate(&mut rand::rng());
-        tokio::fs::write(key_file_path, key.to_bytes()).await?;
+        let name = name.to_string();
+        let bytes = key.to_bytes();
+        self.with_secrets(move |secrets| secrets.set(&name, &bytes))
+            .await?;
         Ok(key)
     }
 
-    /// OAuth state is stored per env (e.g. oauth.staging.yml, oauth.production.yml).
-    pub fn oauth_file_path(&self, key: &str) -> PathBuf {
-        self.0.join(format!("oauth.{key}.yml"))
+    /// Run `f` with the secret store on the blocking thread pool. Keychain
+    /// calls may wait on the OS and the encrypted file store runs Argon2,
+    /// neither of which may stall the runtime.
+    async fn with_secrets<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&dyn SecretStore) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let secrets = self.secrets.clone();
+        tokio::task::spawn_blocking(move || f(secrets.as_ref()))
+            .await
+            .std_context("secret store task failed")?
+    }
+
+    /// Reads a secret, moving it out of a legacy plaintext file into the
+    /// secret store if needed.
+    async fn read_secret(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let name = name.to_string();
+        let legacy_path = self.path.join(&name);
+        self.with_secrets(move |secrets| {
+            if let Some(secret) = secrets.get(&name)? {
+                return Ok(Some(secret));
+            }
+            if secrets.is_plaintext() || !legacy_path.exists() {
+                return Ok(None);
+            }
+            let secret = std::fs::read(&legacy_path)?;
+            secrets.set(&name, &secret)?;
+            std::fs::remove_file(&legacy_path)?;
+            info!("moved {name} into the {} secret store", secrets.kind());
+            Ok(Some(secret))
+        })
+        .await
+    }
+
+    /// Name of the OAuth state secret for an env (e.g. oauth.staging.yml, oauth.production.yml).
+    fn oauth_secret_name(key: &str) -> String {
+        format!("oauth.{key}.yml")
+    }
+
+    /// Storage key of an account's OAuth state for the env with `env_key`.
+    pub fn account_oauth_key(env_key: &str, account: &str) -> String {
+        if account == Self::DEFAULT_ACCOUNT {
+            env_key.to_string()
+        } else {
+            format!("{env_key}.{account}")
+        }
+    }
+
+    fn check_account_name(name: &str) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            n0_error::bail_any!("Invalid account name {name:?}: use letters, digits, '-' and '_'");
+        }
+        Ok(())
+    }
+
+    /// The accounts that can be switched to, the default one first.
+    pub async fn list_accounts(&self) -> Result<Vec<String>> {
+        let path = self.path.join(Self::ACCOUNTS_FILE);
+        let mut named: BTreeSet<String> = if path.exists() {
+            let data = tokio::fs::read_to_string(path)
+                .await
+                .context("failed to read accounts file")?;
+            serde_yml::from_str(&data).std_context("failed to parse accounts file")?
+        } else {
+            BTreeSet::new()
+        };
+        named.remove(Self::DEFAULT_ACCOUNT);
+        let mut accounts = vec![Self::DEFAULT_ACCOUNT.to_string()];
+        accounts.extend(named);
+        Ok(accounts)
+    }
+
+    async fn write_accounts(&self, accounts: &[String]) -> Result<()> {
+        let named = accounts
+            .iter()
+            .filter(|name| *name != Self::DEFAULT_ACCOUNT)
+            .collect::<BTreeSet<_>>();
+        let data = serde_yml::to_string(&named).anyerr()?;
+        tokio::fs::write(self.path.join(Self::ACCOUNTS_FILE), data).await?;
+        Ok(())
+    }
+
+    /// Remember an account so it shows up in [`Self::list_accounts`].
+    pub async fn add_account(&self, name: &str) -> Result<()> {
+        Self::check_account_name(name)?;
+        let mut accounts = self.list_accounts().await?;
+        if !accounts.iter().any(|account| account == name) {
+            accounts.push(name.to_string());
+            self.write_accounts(&accounts).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget an account and its login for the env with `env_key`. The
+    /// default account can't be removed.
+    pub async fn remove_account(&self, env_key: &str, name: &str) -> Result<()> {
+        if name == Self::DEFAULT_ACCOUNT {
+            n0_error::bail_any!("The default account can't be removed");
+        }
+        let mut accounts = self.list_accounts().await?;
+        accounts.retain(|account| account != name);
+        self.write_accounts(&accounts).await?;
+        self.write_oauth_for_key(&Self::account_oauth_key(env_key, name), None)
+            .await?;
+        if self.read_active_account().await? == name {
+            self.write_active_account(Self::DEFAULT_ACCOUNT).await?;
+        }
+        Ok(())
+    }
+
+    /// The account used by default, e.g. on app startup.
+    pub async fn read_active_account(&self) -> Result<String> {
+        let path = self.path.join(Self::ACTIVE_ACCOUNT_FILE);
+        if !path.exists() {
+            return Ok(Self::DEFAULT_ACCOUNT.to_string());
+        }
+        let name = tokio::fs::read_to_string(path)
+            .await
+            .context("failed to read active account file")?;
+        match name.trim() {
+            "" => Ok(Self::DEFAULT_ACCOUNT.to_string()),
+            name => Ok(name.to_string()),
+        }
+    }
+
+    pub async fn write_active_account(&self, name: &str) -> Result<()> {
+        self.add_account(name).await?;
+        tokio::fs::write(self.path.join(Self::ACTIVE_ACCOUNT_FILE), name).await?;
+        Ok(())
     }
 
     pub async fn write_oauth(&self, state: Option<&AuthState>) -> Result<()> {
@@ -164,10 +632,18 @@ impl Repo {
     }
 
     pub async fn write_oauth_for_key(&self, key: &str, state: Option<&AuthState>) -> Result<()> {
-        let path = self.oauth_file_path(key);
-        let data = serde_yml::to_string(&state).anyerr()?;
-        tokio::fs::write(path, data).await?;
-        Ok(())
+        let name = Self::oauth_secret_name(key);
+        match state {
+            Some(state) => {
+                let data = migrations::OAUTH.to_string(state)?;
+                self.with_secrets(move |secrets| secrets.set(&name, data.as_bytes()))
+                    .await
+            }
+            None => {
+                self.with_secrets(move |secrets| secrets.delete(&name))
+                    .await
+            }
+        }
     }
 
     pub async fn read_oauth(&self) -> Result<Option<AuthState>> {
@@ -176,32 +652,54 @@ impl Repo {
 
     /// Read OAuth state for an env key. For "staging", falls back to legacy oauth.yml if present.
     pub async fn read_oauth_for_key(&self, key: &str) -> Result<Option<AuthState>> {
-        let path = self.oauth_file_path(key);
-        let legacy = key == "staging";
-        if path.exists() {
-            let data = tokio::fs::read_to_string(path)
+        let name = Self::oauth_secret_name(key);
+        let data = match self.read_secret(&name).await? {
+            Some(data) => Some(data),
+            None if key == "staging" => self
+                .read_secret(Self::OAUTH_FILE)
                 .await
-                .context("failed to read oauth file")?;
-            let state: Option<AuthState> =
-                serde_yml::from_str(&data).std_context("failed to parse oauth file")?;
-            return Ok(state);
-        }
-        if legacy {
-            let legacy_path = self.0.join(Self::OAUTH_FILE);
-            if legacy_path.exists() {
-                let data = tokio::fs::read_to_string(legacy_path)
-                    .await
-                    .context("failed to read legacy oauth file")?;
-                let state: Option<AuthState> =
-                    serde_yml::from_str(&data).std_context("failed to parse oauth file")?;
-                return Ok(state);
-            }
+                .context("failed to read legacy oauth file")?,
+            None => None,
+        };
+        let Some(data) = data else {
+            return Ok(None);
+        };
+        let Some((state, migrated)) = migrations::OAUTH.parse_optional::<AuthState>(&data)? else {
+            return Ok(None);
+        };
+        if migrated {
+            self.write_oauth_for_key(key, Some(&state)).await?;
         }
-        Ok(None)
+        Ok(Some(state))
+    }
+
+    /// The secret store backing this repo.
+    pub fn secret_store(&self) -> &dyn SecretStore {
+        self.secrets.as_ref()
+    }
+
+    /// The event log of this repo.
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
+
+    /// Per-tunnel activity timelines.
+    pub fn activity(&self) -> &ActivityLog {
+        &self.activity
+    }
+
+    /// Bytes each tunnel transferred.
+    pub fn usage(&self) -> &UsageLog {
+        &self.usage
+    }
+
+    /// Throughput of each tunnel over the past week.
+    pub fn bandwidth_history(&self) -> &BandwidthHistory {
+        &self.bandwidth_history
     }
 
     /// Get the base directory path of this repo
     pub fn path(&self) -> &PathBuf {
-        &self.0
+        &self.path
     }
 }
@@ -0,0 +1,214 @@
+//! Versioned on-disk formats for files in the repo.
+//!
+//! Every YAML file the repo owns carries a top-level `version` key. Files
+//! written before versioning existed have none and count as version 0. When a
+//! file is read, the migrations from its version up to the current one run on
+//! the raw YAML mapping before it is deserialized, so old installs upgrade in
+//! place instead of failing to parse. The upgraded file is written back.
+//!
+//! To change a format, append a [`Migration`] to its [`Schema`]. Migrations
+//! are never edited or removed once released: the index of a migration is the
+//! version it upgrades from.
+
+use n0_error::{Result, StdResultExt};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_yml::{Mapping, Value};
+
+const VERSION_KEY: &str = "version";
+
+/// One step from version `n` to `n + 1`.
+pub(crate) struct Migration {
+    pub description: &'static str,
+    pub apply: fn(&mut Mapping) -> Result<()>,
+}
+
+/// The migration history of one file format.
+pub(crate) struct Schema {
+    pub name: &'static str,
+    pub migrations: &'static [Migration],
+}
+
+/// `state.yml`: the local proxies.
+pub(crate) const STATE: Schema = Schema {
+    name: "state",
+    migrations: &[Migration {
+        description: "add version key",
+        apply: |_| Ok(()),
+    }],
+};
+
+/// `preferences.yml`: desktop app preferences.
+pub(crate) const PREFERENCES: Schema = Schema {
+    name: "preferences",
+    migrations: &[Migration {
+        description: "add version key",
+        apply: |_| Ok(()),
+    }],
+};
+
+/// `oauth.<env>.yml`: stored login state.
+pub(crate) const OAUTH: Schema = Schema {
+    name: "oauth",
+    migrations: &[Migration {
+        description: "add version key",
+        apply: |_| Ok(()),
+    }],
+};
+
+impl Schema {
+    fn current_version(&self) -> u64 {
+        self.migrations.len() as u64
+    }
+
+    /// Upgrade a raw document to the current version.
+    ///
+    /// Returns whether anything changed, so callers know to write it back.
+    fn upgrade(&self, value: Value) -> Result<(Value, bool)> {
+        let mut mapping = match value {
+            Value::Mapping(mapping) => mapping,
+            // An empty file parses as null.
+            Value::Null => Mapping::new(),
+            _ => n0_error::bail_any!("{} file is not a YAML mapping", self.name),
+        };
+        let version = mapping
+            .get(VERSION_KEY)
+            .map(|v| {
+                v.as_u64()
+                    .ok_or_else(|| n0_error::anyerr!("{} file has an invalid version", self.name))
+            })
+            .transpose()?
+            .unwrap_or(0);
+        let current = self.current_version();
+        if version > current {
+            n0_error::bail_any!(
+                "{} file has version {version}, but this build only understands up to {current}; \
+                 it was written by a newer release",
+                self.name
+            );
+        }
+        for (from, migration) in self.migrations.iter().enumerate().skip(version as usize) {
+            tracing::info!(
+                "migrating {} file from version {from}: {}",
+                self.name,
+                migration.description
+            );
+            (migration.apply)(&mut mapping)?;
+        }
+        mapping.insert(VERSION_KEY.into(), current.into());
+        Ok((Value::Mapping(mapping), version != current))
+    }
+
+    /// Parse a document, upgrading it first if needed.
+    ///
+    /// Returns whether the document was migrated.
+    pub(crate) fn parse<T: DeserializeOwned>(&self, data: &[u8]) -> Result<(T, bool)> {
+        let value = serde_yml::from_slice(data)
+            .with_std_context(|_| format!("parsing {} file", self.name))?;
+        self.parse_value(value)
+    }
+
+    /// Like [`Self::parse`], but a `null` document (how an absent value used
+    /// to be written) parses as `None`.
+    pub(crate) fn parse_optional<T: DeserializeOwned>(
+        &self,
+        data: &[u8],
+    ) -> Result<Option<(T, bool)>> {
+        let value: Value = serde_yml::from_slice(data)
+            .with_std_context(|_| format!("parsing {} file", self.name))?;
+        if value.is_null() {
+            return Ok(None);
+        }
+        self.parse_value(value).map(Some)
+    }
+
+    fn parse_value<T: DeserializeOwned>(&self, value: Value) -> Result<(T, bool)> {
+        let (value, migrated) = self.upgrade(value)?;
+        let parsed = serde_yml::from_value(value)
+            .with_std_context(|_| format!("parsing {} file", self.name))?;
+        Ok((parsed, migrated))
+    }
+
+    /// Serialize a document, stamped with the current version.
+    pub(crate) fn to_string<T: Serialize>(&self, item: &T) -> Result<String> {
+        let value = serde_yml::to_value(item).anyerr()?;
+        let mut mapping = match value {
+            Value::Mapping(mapping) => mapping,
+            Value::Null => Mapping::new(),
+            _ => n0_error::bail_any!("{} is not a YAML mapping", self.name),
+        };
+        mapping.insert(VERSION_KEY.into(), self.current_version().into());
+        serde_yml::to_string(&mapping).anyerr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Doc {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        port: u16,
+    }
+
+    const TEST: Schema = Schema {
+        name: "test",
+        migrations: &[
+            Migration {
+                description: "add version key",
+                apply: |_| Ok(()),
+            },
+            Migration {
+                description: "rename addr to name",
+                apply: |mapping| {
+                    if let Some(addr) = mapping.remove("addr") {
+                        mapping.insert("name".into(), addr);
+                    }
+                    Ok(())
+                },
+            },
+        ],
+    };
+
+    #[test]
+    fn unversioned_files_are_migrated() {
+        let (doc, migrated): (Doc, bool) = TEST.parse(b"addr: localhost\nport: 80\n").unwrap();
+        assert!(migrated);
+        assert_eq!(
+            doc,
+            Doc {
+                name: "localhost".into(),
+                port: 80
+            }
+        );
+    }
+
+    #[test]
+    fn current_files_are_left_alone() {
+        let data = TEST
+            .to_string(&Doc {
+                name: "a".into(),
+                port: 1,
+            })
+            .unwrap();
+        assert!(data.contains("version: 2"));
+        let (_, migrated): (Doc, bool) = TEST.parse(data.as_bytes()).unwrap();
+        assert!(!migrated);
+    }
+
+    #[test]
+    fn newer_files_are_rejected() {
+        let err = TEST.parse::<Doc>(b"version: 3\n").unwrap_err();
+        assert!(err.to_string().contains("newer release"));
+    }
+
+    #[test]
+    fn empty_files_parse() {
+        let (doc, _): (Doc, bool) = TEST.parse(b"").unwrap();
+        assert_eq!(doc.port, 0);
+    }
+}
@@ -0,0 +1,282 @@
+//! Reverse port forwarding, like `ssh -R`.
+//!
+//! The requesting side asks a remote listen node to open a TCP listener on the
+//! remote machine. Connections to that listener are carried back over the
+//! same iroh connection, one bidirectional stream each, and the requesting
+//! side connects them to a service on its own machine.
+//!
+//! The first stream carries the request and the reply as length-prefixed
+//! JSON. The forward stays up for as long as the iroh connection does.
+//! Listen nodes only serve peers that were allowed with
+//! [`ListenNode::set_reverse_forward_allowed`](crate::ListenNode::set_reverse_forward_allowed).
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use iroh::{
+    Endpoint, EndpointId,
+    endpoint::{Connection, RecvStream, SendStream},
+    protocol::{AcceptError, ProtocolHandler},
+};
+use n0_error::{Result, StdResultExt, anyerr};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{Instrument, debug, error_span, info, warn};
+
+use crate::{StateWrapper, TcpProxyData, dial};
+
+pub const REVERSE_FORWARD_ALPN: &[u8] = b"datum-connect/reverse-forward/0";
+
+/// Largest control message accepted.
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReverseForwardRequest {
+    /// Where the remote should listen.
+    bind_addr: SocketAddr,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ReverseForwardResponse {
+    Bound { addr: SocketAddr },
+    Denied { reason: String },
+}
+
+/// A listener a remote peer asked this node to open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReverseForwardInfo {
+    pub remote_id: EndpointId,
+    pub bound_addr: SocketAddr,
+    pub opened_at: Instant,
+}
+
+/// Serves [`REVERSE_FORWARD_ALPN`] on a listen node.
+#[derive(Debug, Clone)]
+pub(crate) struct ReverseForwardProtocol {
+    state: StateWrapper,
+    active: Arc<Mutex<Vec<ReverseForwardInfo>>>,
+}
+
+impl ReverseForwardProtocol {
+    pub(crate) fn new(state: StateWrapper) -> Self {
+        Self {
+            state,
+            active: Default::default(),
+        }
+    }
+
+    pub(crate) fn active(&self) -> Vec<ReverseForwardInfo> {
+        self.active.lock().expect("poisoned").clone()
+    }
+
+    async fn serve(&self, conn: Connection) -> Result<()> {
+        let remote_id = conn.remote_id();
+        let (mut send, mut recv) = conn.accept_bi().await.anyerr()?;
+        let request: ReverseForwardRequest = read_message(&mut recv).await?;
+
+        if !self.state.get().reverse_forward_peers.contains(&remote_id) {
+            warn!(remote = %remote_id.fmt_short(), "denied reverse forward from unknown peer");
+            let reason = "this peer may not request reverse forwards".to_string();
+            write_message(&mut send, &ReverseForwardResponse::Denied { reason }).await?;
+            send.finish().anyerr()?;
+            return Ok(());
+        }
+        let listener = match TcpListener::bind(request.bind_addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                let reason = format!("failed to bind {}: {err}", request.bind_addr);
+                write_message(&mut send, &ReverseForwardResponse::Denied { reason }).await?;
+                send.finish().anyerr()?;
+                return Ok(());
+            }
+        };
+        let bound_addr = listener.local_addr()?;
+        write_message(
+            &mut send,
+            &ReverseForwardResponse::Bound { addr: bound_addr },
+        )
+        .await?;
+        send.finish().anyerr()?;
+        info!(remote = %remote_id.fmt_short(), %bound_addr, "opened reverse forward");
+
+        self.active
+            .lock()
+            .expect("poisoned")
+            .push(ReverseForwardInfo {
+                remote_id,
+                bound_addr,
+                opened_at: Instant::now(),
+            });
+        tokio::select! {
+            _ = accept_loop(&conn, listener) => {}
+            _ = conn.closed() => {}
+        }
+        self.active
+            .lock()
+            .expect("poisoned")
+            .retain(|info| info.bound_addr != bound_addr);
+        info!(remote = %remote_id.fmt_short(), %bound_addr, "closed reverse forward");
+        Ok(())
+    }
+}
+
+impl ProtocolHandler for ReverseForwardProtocol {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        if let Err(err) = self.serve(connection).await {
+            debug!("reverse forward failed: {err:#}");
+        }
+        Ok(())
+    }
+}
+
+/// Carry each accepted TCP connection back to the requester on its own stream.
+async fn accept_loop(conn: &Connection, listener: TcpListener) {
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("reverse forward listener failed: {err:#}");
+                return;
+            }
+        };
+        let (send, recv) = match conn.open_bi().await {
+            Ok(streams) => streams,
+            Err(err) => {
+                debug!("requester went away: {err:#}");
+                return;
+            }
+        };
+        tokio::spawn(
+            async move {
+                if let Err(err) = splice(tcp, send, recv).await {
+                    debug!("reverse forward stream ended: {err:#}");
+                }
+            }
+            .instrument(error_span!("reverse-forward", %peer)),
+        );
+    }
+}
+
+async fn splice(mut tcp: TcpStream, send: SendStream, recv: RecvStream) -> Result<()> {
+    let mut stream = tokio::io::join(recv, send);
+    tokio::io::copy_bidirectional(&mut tcp, &mut stream).await?;
+    Ok(())
+}
+
+/// A reverse forward this node requested from a remote.
+#[derive(Debug)]
+pub struct ReverseForwardHandle {
+    remote_id: EndpointId,
+    remote_bound_addr: SocketAddr,
+    target: TcpProxyData,
+    conn: Connection,
+    task: JoinHandle<()>,
+}
+
+impl ReverseForwardHandle {
+    pub fn remote_id(&self) -> EndpointId {
+        self.remote_id
+    }
+
+    /// The address the remote is listening on.
+    pub fn remote_bound_addr(&self) -> SocketAddr {
+        self.remote_bound_addr
+    }
+
+    /// The local service connections are forwarded to.
+    pub fn target(&self) -> &TcpProxyData {
+        &self.target
+    }
+
+    /// Stop forwarding; the remote closes its listener.
+    pub fn close(&self) {
+        self.task.abort();
+        self.conn.close(0u32.into(), b"closed");
+    }
+}
+
+/// Ask `remote_id` to listen on `bind_addr` and forward connections to
+/// `target` on this machine.
+pub(crate) async fn request(
+    endpoint: &Endpoint,
+    remote_id: EndpointId,
+    bind_addr: SocketAddr,
+    target: TcpProxyData,
+) -> Result<ReverseForwardHandle> {
+    let conn = endpoint
+        .connect(remote_id, REVERSE_FORWARD_ALPN)
+        .await
+        .std_context("Failed to connect to remote")?;
+    let (mut send, mut recv) = conn.open_bi().await.anyerr()?;
+    write_message(&mut send, &ReverseForwardRequest { bind_addr }).await?;
+    send.finish().anyerr()?;
+    let remote_bound_addr = match read_message(&mut recv).await? {
+        ReverseForwardResponse::Bound { addr } => addr,
+        ReverseForwardResponse::Denied { reason } => {
+            conn.close(0u32.into(), b"denied");
+            return Err(anyerr!("Remote refused reverse forward: {reason}"));
+        }
+    };
+
+    let task = tokio::spawn({
+        let conn = conn.clone();
+        let target = target.clone();
+        async move {
+            loop {
+                let (send, recv) = match conn.accept_bi().await {
+                    Ok(streams) => streams,
+                    Err(err) => {
+                        debug!("reverse forward connection closed: {err:#}");
+                        return;
+                    }
+                };
+                let target = target.clone();
+                tokio::spawn(async move {
+                    let address = target.address();
+                    let tcp = match dial::connect(&target.host, target.port).await {
+                        Ok(tcp) => tcp,
+                        Err(err) => {
+                            warn!("failed to reach {address}: {err:#}");
+                            return;
+                        }
+                    };
+                    if let Err(err) = splice(tcp, send, recv).await {
+                        debug!("reverse forward stream ended: {err:#}");
+                    }
+                });
+            }
+        }
+        .instrument(error_span!("reverse-forward", remote = %remote_id.fmt_short()))
+    });
+    Ok(ReverseForwardHandle {
+        remote_id,
+        remote_bound_addr,
+        target,
+        conn,
+        task,
+    })
+}
+
+async fn write_message(send: &mut SendStream, message: &impl Serialize) -> Result<()> {
+    let data = serde_json::to_vec(message).anyerr()?;
+    send.write_u32(data.len() as u32).await?;
+    send.write_all(&data).await?;
+    Ok(())
+}
+
+async fn read_message<T: DeserializeOwned>(recv: &mut RecvStream) -> Result<T> {
+    let len = recv.read_u32().await? as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(anyerr!("Control message too large: {len} bytes"));
+    }
+    let mut data = vec![0; len];
+    recv.read_exact(&mut data).await?;
+    serde_json::from_slice(&data).std_context("Invalid control message")
+}
@@ -0,0 +1,300 @@
+//! Activation windows for tunnels that should only be reachable at set times.
+//!
+//! A tunnel with a [`TunnelSchedule`] is turned on when the local time enters
+//! one of its windows and off when it leaves them, e.g. `mon-fri 09:00-17:00`
+//! for a service only meant to be used during office hours. The agent flips
+//! the proxy and its ticket; cloud tunnels are additionally turned on and off
+//! in Datum Cloud by [`TunnelService::run_schedules`].
+//!
+//! Only the transitions are applied: turning a scheduled tunnel on or off by
+//! hand holds until the schedule next changes its mind.
+//!
+//! [`TunnelService::run_schedules`]: crate::tunnels::TunnelService::run_schedules
+
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use iroh::EndpointId;
+use n0_error::{AnyError, Result, StdResultExt, anyerr};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{Repo, StateWrapper, events::EventKind, health};
+
+/// How often schedules are evaluated.
+const TICK: Duration = Duration::from_secs(10);
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// When a tunnel is on: any of its windows, in local time.
+///
+/// Written as windows separated by `;`, e.g.
+/// `mon-fri 09:00-17:00; sat 10:00-12:00`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TunnelSchedule {
+    pub windows: Vec<ScheduleWindow>,
+}
+
+impl TunnelSchedule {
+    pub fn is_active(&self, at: NaiveDateTime) -> bool {
+        self.windows.iter().any(|window| window.contains(at))
+    }
+}
+
+impl FromStr for TunnelSchedule {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let windows = s
+            .split(';')
+            .filter(|window| !window.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()?;
+        if windows.is_empty() {
+            n0_error::bail_any!("A schedule needs at least one window");
+        }
+        Ok(Self { windows })
+    }
+}
+
+impl fmt::Display for TunnelSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, window) in self.windows.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{window}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Weekdays and a time of day, e.g. `mon-fri 09:00-17:00`, `sat,sun
+/// 10:00-14:00` or `daily 22:00-06:00`.
+///
+/// A window whose end isn't after its start runs past midnight into the next
+/// day; it belongs to the day it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ScheduleWindow {
+    /// One bit per weekday, Monday first.
+    days: u8,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ScheduleWindow {
+    pub fn contains(&self, at: NaiveDateTime) -> bool {
+        let day = at.weekday();
+        let time = at.time();
+        if self.start < self.end {
+            self.has_day(day) && self.start <= time && time < self.end
+        } else {
+            (self.has_day(day) && time >= self.start)
+                || (self.has_day(day.pred()) && time < self.end)
+        }
+    }
+
+    fn has_day(&self, day: Weekday) -> bool {
+        self.days & (1 << day.num_days_from_monday()) != 0
+    }
+}
+
+fn parse_day(s: &str) -> Result<u32> {
+    DAY_NAMES
+        .iter()
+        .position(|name| s.eq_ignore_ascii_case(name))
+        .map(|i| i as u32)
+        .ok_or_else(|| anyerr!("Unknown weekday {s:?}, expected one of mon, tue, …, sun"))
+}
+
+fn parse_days(s: &str) -> Result<u8> {
+    if s.eq_ignore_ascii_case("daily") {
+        return Ok(0x7f);
+    }
+    let mut days = 0u8;
+    for part in s.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => {
+                let day = parse_day(part)?;
+                (day, day)
+            }
+        };
+        // Ranges may wrap around the week, like `fri-mon`.
+        let mut day = first;
+        loop {
+            days |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(days)
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .with_std_context(|_| format!("Invalid time {s:?}, expected HH:MM"))
+}
+
+impl FromStr for ScheduleWindow {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (days, times) = s
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyerr!("Invalid window {s:?}, expected e.g. mon-fri 09:00-17:00"))?;
+        let (start, end) = times
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| anyerr!("Invalid time range {times:?}, expected e.g. 09:00-17:00"))?;
+        Ok(Self {
+            days: parse_days(days)?,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl fmt::Display for ScheduleWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.days == 0x7f {
+            f.write_str("daily")?;
+        } else {
+            // Consecutive days as ranges, like `mon-wed,fri`.
+            let mut ranges = Vec::new();
+            let mut day = 0;
+            while day < 7 {
+                if self.days & (1 << day) == 0 {
+                    day += 1;
+                    continue;
+                }
+                let first = day;
+                while day + 1 < 7 && self.days & (1 << (day + 1)) != 0 {
+                    day += 1;
+                }
+                ranges.push(if first == day {
+                    DAY_NAMES[first].to_string()
+                } else {
+                    format!("{}-{}", DAY_NAMES[first], DAY_NAMES[day])
+                });
+                day += 1;
+            }
+            f.write_str(&ranges.join(","))?;
+        }
+        write!(
+            f,
+            " {}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl TryFrom<String> for ScheduleWindow {
+    type Error = AnyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ScheduleWindow> for String {
+    fn from(value: ScheduleWindow) -> Self {
+        value.to_string()
+    }
+}
+
+pub(crate) async fn run(
+    state: StateWrapper,
+    repo: Repo,
+    n0des: Option<Arc<iroh_n0des::Client>>,
+    endpoint_id: EndpointId,
+) {
+    // What each schedule asked for when last evaluated.
+    let mut wanted = HashMap::<String, bool>::new();
+    loop {
+        let now = Local::now().naive_local();
+        let current = state.get_cloned();
+        wanted.retain(|id, _| current.proxies.iter().any(|p| p.id() == id));
+        let mut flips = Vec::new();
+        for proxy in &current.proxies {
+            let Some(schedule) = &proxy.schedule else {
+                wanted.remove(proxy.id());
+                continue;
+            };
+            let active = schedule.is_active(now);
+            if wanted.insert(proxy.id().to_string(), active) != Some(active)
+                && proxy.enabled != active
+            {
+                flips.push((proxy.id().to_string(), active));
+            }
+        }
+        for (tunnel_id, enabled) in flips {
+            let updated = state
+                .update(&repo, |state| state.set_enabled(&tunnel_id, enabled))
+                .await;
+            match updated {
+                Ok(Some(proxy)) => {
+                    info!(%tunnel_id, enabled, "applied tunnel schedule");
+                    health::set_published(n0des.as_deref(), &proxy, endpoint_id, enabled).await;
+                    repo.events().record(if enabled {
+                        EventKind::TunnelEnabled { tunnel_id }
+                    } else {
+                        EventKind::TunnelDisabled { tunnel_id }
+                    });
+                }
+                Ok(None) => {}
+                Err(err) => warn!(%tunnel_id, "Failed to apply tunnel schedule: {err:#}"),
+            }
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        // 2024-01-01 was a Monday.
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(parse_time(time).unwrap())
+    }
+
+    #[test]
+    fn evaluates_windows() {
+        let schedule: TunnelSchedule = "mon-fri 09:00-17:00; sat-sun 22:00-02:00".parse().unwrap();
+        assert_eq!(
+            schedule.to_string(),
+            "mon-fri 09:00-17:00; sat,sun 22:00-02:00"
+        );
+        assert!(schedule.is_active(at(1, "09:00")));
+        assert!(!schedule.is_active(at(1, "17:00")));
+        assert!(!schedule.is_active(at(6, "12:00")));
+        assert!(schedule.is_active(at(6, "23:30")));
+        assert!(schedule.is_active(at(7, "01:00")));
+        // Sunday's window runs into Monday morning.
+        assert!(schedule.is_active(at(8, "01:59")));
+        assert!(!schedule.is_active(at(8, "02:00")));
+
+        let wrapped: ScheduleWindow = "fri-mon 00:00-00:00".parse().unwrap();
+        assert_eq!(wrapped.to_string(), "mon,fri-sun 00:00-00:00");
+        assert!(wrapped.contains(at(1, "12:00")) && !wrapped.contains(at(2, "12:00")));
+        assert_eq!(
+            "daily 08:00-09:00".parse::<ScheduleWindow>().unwrap().days,
+            0x7f
+        );
+
+        assert!("weekdays 09:00-17:00".parse::<TunnelSchedule>().is_err());
+        assert!("mon 9-17".parse::<TunnelSchedule>().is_err());
+        assert!("".parse::<TunnelSchedule>().is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! Storage for secrets: iroh secret keys and OAuth tokens.
+//!
+//! [`Repo`](crate::Repo) reads and writes secrets through a [`SecretStore`].
+//! The store is picked once when the repo is opened:
+//!
+//! - `DATUM_CONNECT_SECRET_STORE=keychain`: the OS keychain (macOS Keychain,
+//!   Windows Credential Manager, secret-service on Linux).
+//! - `DATUM_CONNECT_SECRET_STORE=file`: files encrypted with a passphrase from
+//!   `DATUM_CONNECT_PASSPHRASE`.
+//! - `DATUM_CONNECT_SECRET_STORE=plaintext`: plain files in the repo directory.
+//!
+//! When unset, the keychain is used if it works, then encrypted files if a
+//! passphrase is set, and plain files otherwise. Plain files picked that way
+//! are a [fallback](SecretStore::is_fallback): the CLI prints
+//! [`FALLBACK_WARNING`] on every run, the app shows it in a banner and
+//! `doctor` reports it, until a passphrase is set or plain files are chosen
+//! explicitly.
+
+use std::{fmt::Debug, path::PathBuf};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit},
+};
+use n0_error::{Result, StackResultExt, StdResultExt};
+use rand::RngCore;
+use secrecy::{ExposeSecret, SecretString};
+use tracing::{debug, warn};
+
+const KEYCHAIN_SERVICE: &str = "datum-connect";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// What to tell the user when secrets are stored unencrypted because nothing
+/// better was available.
+pub const FALLBACK_WARNING: &str = "No OS keychain is available and DATUM_CONNECT_PASSPHRASE is not set, so keys and logins are stored unencrypted. Set DATUM_CONNECT_PASSPHRASE to encrypt them, or DATUM_CONNECT_SECRET_STORE=plaintext to keep plain files on purpose.";
+
+pub trait SecretStore: Debug + Send + Sync + 'static {
+    /// Short name of the backend, for logs and diagnostics.
+    fn kind(&self) -> &'static str;
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    fn set(&self, name: &str, value: &[u8]) -> Result<()>;
+
+    fn delete(&self, name: &str) -> Result<()>;
+
+    /// Whether secrets are stored as plain files in the repo directory.
+    ///
+    /// Other stores migrate those files on first read.
+    fn is_plaintext(&self) -> bool {
+        false
+    }
+
+    /// Whether this store was picked because no better one was available,
+    /// rather than asked for.
+    fn is_fallback(&self) -> bool {
+        false
+    }
+}
+
+/// Plain files in the repo directory. This is how secrets were stored before
+/// [`SecretStore`] existed.
+#[derive(Debug)]
+pub struct PlaintextStore {
+    dir: PathBuf,
+    fallback: bool,
+}
+
+impl PlaintextStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            fallback: false,
+        }
+    }
+
+    /// Plain files for lack of a keychain or passphrase.
+    pub fn fallback(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            fallback: true,
+            ..Self::new(dir)
+        }
+    }
+}
+
+impl SecretStore for PlaintextStore {
+    fn kind(&self) -> &'static str {
+        "plaintext"
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).with_std_context(|_| format!("reading {name}"))?;
+        Ok(Some(data))
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(name), value)?;
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let path = self.dir.join(name);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn is_plaintext(&self) -> bool {
+        true
+    }
+
+    fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+}
+
+/// The OS keychain. Entries are namespaced by repo directory so profiles and
+/// test repos don't share secrets.
+#[derive(Debug)]
+pub struct KeychainStore {
+    namespace: String,
+}
+
+impl KeychainStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            namespace: dir.into().display().to_string(),
+        }
+    }
+
+    fn entry(&self, name: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(KEYCHAIN_SERVICE, &format!("{}:{name}", self.namespace))
+            .std_context("opening keychain entry")
+    }
+
+    /// Returns true if the keychain can be reached at all.
+    pub fn probe(&self) -> bool {
+        match self.entry("probe").and_then(|e| match e.get_secret() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(err).anyerr(),
+        }) {
+            Ok(()) => true,
+            Err(err) => {
+                debug!("keychain unavailable: {err:#}");
+                false
+            }
+        }
+    }
+}
+
+impl SecretStore for KeychainStore {
+    fn kind(&self) -> &'static str {
+        "keychain"
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self.entry(name)?.get_secret() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(err).with_std_context(|_| format!("reading {name} from keychain")),
+        }
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> Result<()> {
+        self.entry(name)?
+            .set_secret(value)
+            .with_std_context(|_| format!("writing {name} to keychain"))
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match self.entry(name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(err).with_std_context(|_| format!("deleting {name} from keychain")),
+        }
+    }
+}
+
+/// Files in the repo directory encrypted with XChaCha20-Poly1305, using a key
+/// derived from a passphrase with Argon2. Each file is `salt || nonce || ciphertext`.
+#[derive(derive_more::Debug)]
+pub struct EncryptedFileStore {
+    dir: PathBuf,
+    #[debug(skip)]
+    passphrase: SecretString,
+}
+
+impl EncryptedFileStore {
+    pub fn new(dir: impl Into<PathBuf>, passphrase: SecretString) -> Self {
+        Self {
+            dir: dir.into(),
+            passphrase,
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.enc"))
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(self.passphrase.expose_secret().as_bytes(), salt, &mut key)
+            .map_err(|err| n0_error::anyerr!("deriving key from passphrase: {err}"))?;
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn kind(&self) -> &'static str {
+        "encrypted-file"
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).with_std_context(|_| format!("reading {name}"))?;
+        if data.len() < SALT_LEN + NONCE_LEN {
+            n0_error::bail_any!("encrypted secret {name} is truncated");
+        }
+        let (salt, rest) = data.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher(salt)?
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| n0_error::anyerr!("failed to decrypt {name}: wrong passphrase?"))?;
+        Ok(Some(plaintext))
+    }
+
+    fn set(&self, name: &str, value: &[u8]) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        rand::rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher(&salt)?
+            .encrypt(XNonce::from_slice(&nonce), value)
+            .map_err(|_| n0_error::anyerr!("failed to encrypt {name}"))?;
+        let mut data = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(name), data)?;
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path(name);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Pick a secret store for the repo at `dir` based on the environment.
+///
+/// This probes the keychain, which may block, so call it off the async
+/// runtime.
+pub fn from_env(dir: impl Into<PathBuf>) -> Result<Box<dyn SecretStore>> {
+    let dir = dir.into();
+    let passphrase = std::env::var("DATUM_CONNECT_PASSPHRASE")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(SecretString::from);
+    let kind = std::env::var("DATUM_CONNECT_SECRET_STORE").ok();
+    let store: Box<dyn SecretStore> = match kind.as_deref() {
+        Some("plaintext") => Box::new(PlaintextStore::new(dir)),
+        Some("keychain") => Box::new(KeychainStore::new(dir)),
+        Some("file") => {
+            let passphrase = passphrase
+                .context("DATUM_CONNECT_PASSPHRASE is required for the encrypted file store")?;
+            Box::new(EncryptedFileStore::new(dir, passphrase))
+        }
+        Some(other) => n0_error::bail_any!(
+            "unknown DATUM_CONNECT_SECRET_STORE {other:?}: expected keychain, file or plaintext"
+        ),
+        None => {
+            let keychain = KeychainStore::new(dir.clone());
+            if keychain.probe() {
+                Box::new(keychain)
+            } else if let Some(passphrase) = passphrase {
+                Box::new(EncryptedFileStore::new(dir, passphrase))
+            } else {
+                warn!("{FALLBACK_WARNING}");
+                Box::new(PlaintextStore::fallback(dir))
+            }
+        }
+    };
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_file_store_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EncryptedFileStore::new(dir.path(), SecretString::from("hunter2"));
+        assert_eq!(store.get("listen_key").unwrap(), None);
+        store.set("listen_key", b"secret bytes").unwrap();
+        assert_eq!(
+            store.get("listen_key").unwrap().as_deref(),
+            Some(&b"secret bytes"[..])
+        );
+        let on_disk = std::fs::read(dir.path().join("listen_key.enc")).unwrap();
+        assert!(!on_disk.windows(6).any(|w| w == b"secret"));
+
+        let wrong = EncryptedFileStore::new(dir.path(), SecretString::from("nope"));
+        assert!(wrong.get("listen_key").is_err());
+
+        store.delete("listen_key").unwrap();
+        assert_eq!(store.get("listen_key").unwrap(), None);
+    }
+}
@@ -0,0 +1,189 @@
+//! Text for passing a tunnel on: its public URL, its ticket, and snippets
+//! ready to paste into Markdown or Slack.
+//!
+//! [`copy_to_clipboard`] puts text on the system clipboard with the tools of
+//! the platform, for the CLI. The app writes to the clipboard itself.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use n0_error::{Result, StdResultExt};
+
+use crate::TunnelSummary;
+
+/// The hostname a tunnel is best reached at: the first one that isn't pinned
+/// to an IP family, like `v4.<host>`.
+pub fn public_hostname(hostnames: &[String]) -> Option<&str> {
+    hostnames
+        .iter()
+        .find(|h| !h.starts_with("v4.") && !h.starts_with("v6."))
+        .or_else(|| hostnames.first())
+        .map(String::as_str)
+}
+
+/// What can be shared of one tunnel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareText {
+    pub label: String,
+    /// `https://` and the public hostname, once one is assigned.
+    pub url: Option<String>,
+    /// The ticket, if this device serves the tunnel.
+    pub ticket: Option<String>,
+}
+
+impl ShareText {
+    pub fn new(tunnel: &TunnelSummary, ticket: Option<String>) -> Self {
+        Self {
+            label: tunnel.label.clone(),
+            url: public_hostname(&tunnel.hostnames).map(|host| format!("https://{host}")),
+            ticket,
+        }
+    }
+
+    /// A link to the tunnel and its ticket in a code block, in Markdown.
+    pub fn markdown(&self) -> String {
+        let label = escape_markdown(&self.label);
+        let mut text = match &self.url {
+            Some(url) => format!("[{label}]({url})"),
+            None => format!("**{label}**"),
+        };
+        if let Some(ticket) = &self.ticket {
+            text.push_str("\n\nJoin with Datum Connect:\n\n```\n");
+            text.push_str(ticket);
+            text.push_str("\n```");
+        }
+        text
+    }
+
+    /// The same as [`Self::markdown`] in Slack's message formatting.
+    pub fn slack(&self) -> String {
+        let label = escape_slack(&self.label);
+        let mut text = match &self.url {
+            Some(url) => format!("<{url}|{label}>"),
+            None => format!("*{label}*"),
+        };
+        if let Some(ticket) = &self.ticket {
+            text.push_str("\nJoin with Datum Connect: `");
+            text.push_str(ticket);
+            text.push('`');
+        }
+        text
+    }
+}
+
+/// `text` with the characters that would start Markdown formatting escaped.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '*' | '_' | '`' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `text` with the characters Slack reserves for links and mentions escaped.
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Put `text` on the system clipboard with `pbcopy`, `clip`, `wl-copy`,
+/// `xclip` or `xsel`, whichever is there.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let commands: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip", &[])]
+    } else {
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    };
+    for (program, args) in commands {
+        let child = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        // Not installed, try the next one.
+        let Ok(mut child) = child else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .std_context("failed to write to clipboard")?;
+        }
+        let status = child.wait().std_context("failed to write to clipboard")?;
+        if !status.success() {
+            n0_error::bail_any!("{program} failed: {status}");
+        }
+        return Ok(());
+    }
+    n0_error::bail_any!("no clipboard tool found")
+}
+
+/// The escape sequence that asks a terminal to put `text` on the clipboard
+/// (OSC 52), for when no clipboard tool is around, e.g. over SSH. Terminals
+/// that don't support it ignore it.
+pub fn osc52(text: &str) -> String {
+    format!(
+        "\x1b]52;c;{}\x07",
+        data_encoding::BASE64.encode(text.as_bytes())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share(label: &str, ticket: Option<&str>) -> ShareText {
+        ShareText {
+            label: label.to_string(),
+            url: Some("https://abc123.datumproxy.net".to_string()),
+            ticket: ticket.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn formats_snippets() {
+        let text = share("web [prod] <beta>", Some("tunnelabc"));
+        assert_eq!(
+            text.markdown(),
+            "[web \\[prod\\] \\<beta\\>](https://abc123.datumproxy.net)\n\n\
+             Join with Datum Connect:\n\n```\ntunnelabc\n```"
+        );
+        assert_eq!(
+            text.slack(),
+            "<https://abc123.datumproxy.net|web [prod] &lt;beta&gt;>\n\
+             Join with Datum Connect: `tunnelabc`"
+        );
+
+        let pending = ShareText {
+            url: None,
+            ..share("web", None)
+        };
+        assert_eq!(pending.markdown(), "**web**");
+        assert_eq!(pending.slack(), "*web*");
+    }
+
+    #[test]
+    fn prefers_hostnames_for_both_ip_families() {
+        let hostnames = ["v4.abc.datumproxy.net", "abc.datumproxy.net"].map(String::from);
+        assert_eq!(public_hostname(&hostnames), Some("abc.datumproxy.net"));
+        assert_eq!(
+            public_hostname(&hostnames[..1]),
+            Some("v4.abc.datumproxy.net")
+        );
+        assert_eq!(public_hostname(&[]), None);
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+    }
+}
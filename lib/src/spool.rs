@@ -0,0 +1,248 @@
+//! Request bodies read in full before they are forwarded.
+//!
+//! Bodies normally stream through, but one that must be sent with its length
+//! up front, or sent again after a failed attempt, has to be kept until the
+//! request is done. Up to [`SpoolConfig::memory_bytes`] it stays in memory;
+//! beyond that it goes to an anonymous temporary file, which the OS removes
+//! once it is closed, even if the process dies. Bodies over
+//! [`SpoolConfig::max_bytes`] are refused.
+
+use std::{
+    fmt,
+    fs::File,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use tokio::{io::AsyncWriteExt, task::JoinHandle};
+
+use crate::config::SpoolConfig;
+
+/// Largest piece of a spooled file read at once.
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Spool {
+    config: SpoolConfig,
+}
+
+/// Why a body couldn't be spooled.
+#[derive(Debug)]
+pub(crate) enum SpoolError {
+    /// The body is larger than `max_bytes`.
+    TooLarge { limit: u64 },
+    /// Reading the body or writing the file failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { limit } => write!(f, "request body is larger than {limit} bytes"),
+            Self::Io(err) => write!(f, "failed to spool request body: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for SpoolError {}
+
+impl From<io::Error> for SpoolError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl Spool {
+    pub(crate) fn new(config: SpoolConfig) -> Self {
+        Self { config }
+    }
+
+    /// Read `body` in full.
+    pub(crate) async fn spool<B>(&self, mut body: B) -> Result<Spooled, SpoolError>
+    where
+        B: Body<Data = Bytes> + Unpin,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let mut memory = Vec::new();
+        let mut file: Option<tokio::fs::File> = None;
+        let mut len = 0u64;
+        while let Some(frame) = body.frame().await {
+            let Ok(data) = frame.map_err(io::Error::other)?.into_data() else {
+                continue;
+            };
+            len += data.len() as u64;
+            if len > self.config.max_bytes {
+                return Err(SpoolError::TooLarge {
+                    limit: self.config.max_bytes,
+                });
+            }
+            match &mut file {
+                Some(file) => file.write_all(&data).await?,
+                None if len > self.config.memory_bytes => {
+                    let mut spilled = tokio::fs::File::from_std(self.tempfile()?);
+                    spilled.write_all(&memory).await?;
+                    spilled.write_all(&data).await?;
+                    memory = Vec::new();
+                    file = Some(spilled);
+                }
+                None => memory.extend_from_slice(&data),
+            }
+        }
+        match file {
+            None => Ok(Spooled::Memory(memory.into())),
+            Some(mut file) => {
+                file.flush().await?;
+                let file = Arc::new(file.into_std().await);
+                Ok(Spooled::File { file, len })
+            }
+        }
+    }
+
+    fn tempfile(&self) -> io::Result<File> {
+        match &self.config.dir {
+            Some(dir) => tempfile::tempfile_in(dir),
+            None => tempfile::tempfile(),
+        }
+    }
+}
+
+/// A body read in full, which can be sent any number of times.
+#[derive(Debug, Clone)]
+pub(crate) enum Spooled {
+    Memory(Bytes),
+    File { file: Arc<File>, len: u64 },
+}
+
+impl Spooled {
+    /// Whether the body went to a file.
+    pub(crate) fn in_file(&self) -> bool {
+        matches!(self, Self::File { .. })
+    }
+
+    /// The body, from its start.
+    pub(crate) fn body(&self) -> BoxBody<Bytes, io::Error> {
+        match self {
+            Self::Memory(bytes) => Full::new(bytes.clone())
+                .map_err(|never| match never {})
+                .boxed(),
+            Self::File { file, len } => FileBody {
+                file: file.clone(),
+                offset: 0,
+                len: *len,
+                read: None,
+            }
+            .boxed(),
+        }
+    }
+}
+
+/// A spooled file, read from an offset of its own so several bodies of the
+/// same file don't get in each other's way.
+struct FileBody {
+    file: Arc<File>,
+    offset: u64,
+    len: u64,
+    read: Option<JoinHandle<io::Result<Vec<u8>>>>,
+}
+
+impl Body for FileBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Frame<Bytes>>>> {
+        if self.offset >= self.len {
+            return Poll::Ready(None);
+        }
+        let this = &mut *self;
+        let read = this.read.get_or_insert_with(|| {
+            let (file, offset) = (this.file.clone(), this.offset);
+            let want = (this.len - offset).min(READ_CHUNK as u64);
+            tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0; want as usize];
+                let read = read_at(&file, &mut buf, offset)?;
+                buf.truncate(read);
+                Ok(buf)
+            })
+        });
+        let res = ready!(Pin::new(read).poll(cx));
+        self.read = None;
+        let data = res.map_err(io::Error::other)??;
+        if data.is_empty() {
+            return Poll::Ready(Some(Err(io::ErrorKind::UnexpectedEof.into())));
+        }
+        self.offset += data.len() as u64;
+        Poll::Ready(Some(Ok(Frame::data(data.into()))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.offset >= self.len
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.len - self.offset)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(spooled: &Spooled) -> Vec<u8> {
+        spooled.body().collect().await.unwrap().to_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn large_bodies_go_to_a_file() {
+        let spool = Spool::new(SpoolConfig {
+            memory_bytes: 1024,
+            max_bytes: 1024 * 1024,
+            dir: None,
+        });
+        let small = spool
+            .spool(Full::new(Bytes::from(vec![1; 1024])))
+            .await
+            .unwrap();
+        assert!(!small.in_file());
+        assert_eq!(collect(&small).await, vec![1; 1024]);
+
+        let body: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let large = spool
+            .spool(Full::new(Bytes::from(body.clone())))
+            .await
+            .unwrap();
+        assert!(large.in_file());
+        assert_eq!(large.body().size_hint().exact(), Some(200_000));
+        // Each body reads the file from the start.
+        assert_eq!(collect(&large).await, body);
+        assert_eq!(collect(&large).await, body);
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_refused() {
+        let spool = Spool::new(SpoolConfig {
+            memory_bytes: 16,
+            max_bytes: 1024,
+            dir: None,
+        });
+        let res = spool.spool(Full::new(Bytes::from(vec![0; 1025]))).await;
+        assert!(matches!(res, Err(SpoolError::TooLarge { limit: 1024 })));
+    }
+}
@@ -1,19 +1,37 @@
-use std::{path::PathBuf, str::FromStr, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv6Addr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use arc_swap::{ArcSwap, Guard};
+use chrono::{DateTime, Utc};
 use iroh::EndpointId;
 use iroh_proxy_utils::Authority;
 use iroh_tickets::{ParseError, Ticket};
 use n0_error::{Result, StackResultExt, StdResultExt};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Notify, futures::Notified};
+use tokio::sync::{Notify, futures::Notified, watch};
 
-use crate::{DATUM_CONNECT_GATEWAY_DOMAIN_NAME, Repo};
+use crate::{
+    DATUM_CONNECT_GATEWAY_DOMAIN_NAME, Repo, health::HealthCheck, http_front::HttpFront,
+    repo::migrations, schedule::TunnelSchedule, usage::TransferQuota,
+};
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct State {
     pub proxies: Vec<ProxyState>,
+    /// Share links issued for the proxies, including revoked and expired ones
+    /// so their clients stay locked out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shares: Vec<IssuedShare>,
+    /// Peers allowed to ask this device to open reverse forwards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reverse_forward_peers: Vec<EndpointId>,
 }
 
 impl State {
@@ -23,7 +41,58 @@ impl State {
             .iter_mut()
             .find(|p| p.info.resource_id == proxy.info.resource_id)
         {
+            // Timeouts, path routes, weights, served directories, sockets and
+            // host names, header rules, credentials, health checks, schedules,
+            // transfer quotas, the direct-only flag and pinned endpoints are
+            // local settings the cloud doesn't know about; keep them when a
+            // synced copy of the proxy replaces ours.
+            let timeouts = existing.timeouts;
+            let routes = std::mem::take(&mut existing.info.data.routes);
+            let weight = existing.info.weight;
+            let serve_dir = existing.serve_dir.take();
+            let unix_socket = existing.unix_socket.take();
+            let host_target = existing.host_target.take();
+            let http_front = existing.http_front.take();
+            let health_check = existing.health_check.take();
+            let transfer_quota = existing.transfer_quota.take();
+            let schedule = existing.schedule.take();
+            let direct_only = existing.direct_only;
+            let pinned_endpoint = existing.pinned_endpoint.take();
             *existing = proxy;
+            if existing.timeouts == TunnelTimeouts::default() {
+                existing.timeouts = timeouts;
+            }
+            if existing.info.data.routes.is_empty() {
+                existing.info.data.routes = routes;
+            }
+            if existing.info.weight.is_none() {
+                existing.info.weight = weight;
+            }
+            if existing.serve_dir.is_none() {
+                existing.serve_dir = serve_dir;
+            }
+            if existing.unix_socket.is_none() {
+                existing.unix_socket = unix_socket;
+            }
+            if existing.host_target.is_none() {
+                existing.host_target = host_target;
+            }
+            if existing.http_front.is_none() {
+                existing.http_front = http_front;
+            }
+            if existing.health_check.is_none() {
+                existing.health_check = health_check;
+            }
+            if existing.schedule.is_none() {
+                existing.schedule = schedule;
+            }
+            if existing.transfer_quota.is_none() {
+                existing.transfer_quota = transfer_quota;
+            }
+            existing.direct_only |= direct_only;
+            if existing.pinned_endpoint.is_none() {
+                existing.pinned_endpoint = pinned_endpoint;
+            }
         } else {
             self.proxies.push(proxy);
         }
@@ -35,11 +104,220 @@ impl State {
             .iter()
             .position(|p| p.info.resource_id == resouce_id)
         {
+            self.shares.retain(|share| share.tunnel_id != resouce_id);
             Some(self.proxies.remove(idx))
         } else {
             None
         }
     }
+
+    /// The newest share of a proxy that is still usable.
+    /// Set a proxy's timeouts. Returns false if there is no such proxy.
+    pub fn set_timeouts(&mut self, resource_id: &str, timeouts: TunnelTimeouts) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.timeouts = timeouts;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_health_check(&mut self, resource_id: &str, check: Option<HealthCheck>) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.health_check = check;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_schedule(&mut self, resource_id: &str, schedule: Option<TunnelSchedule>) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.schedule = schedule;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set or clear a proxy's transfer quota. Returns false if there is no
+    /// such proxy.
+    pub fn set_transfer_quota(&mut self, resource_id: &str, quota: Option<TransferQuota>) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.transfer_quota = quota.filter(|quota| !quota.is_empty());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The labels of the proxies that have one, by id.
+    pub fn proxy_labels(&self) -> BTreeMap<String, String> {
+        self.proxies
+            .iter()
+            .filter_map(|p| Some((p.id().to_string(), p.info.label.clone()?)))
+            .collect()
+    }
+
+    /// Pause or resume a proxy; returns it if it exists.
+    pub fn set_enabled(&mut self, resource_id: &str, enabled: bool) -> Option<ProxyState> {
+        let proxy = self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)?;
+        proxy.enabled = enabled;
+        Some(proxy.clone())
+    }
+
+    /// The proxy other than `resource_id` that already serves `host:port`.
+    pub fn listener_conflict(
+        &self,
+        resource_id: Option<&str>,
+        host: &str,
+        port: u16,
+    ) -> Option<&ProxyState> {
+        self.proxies.iter().find(|p| {
+            Some(p.info.resource_id.as_str()) != resource_id && p.info.service().serves(host, port)
+        })
+    }
+
+    /// Set or clear the endpoint a proxy is served and advertised under
+    /// instead of the listen endpoint. Returns false if there is no such
+    /// proxy.
+    pub fn set_pinned_endpoint(&mut self, resource_id: &str, endpoint: Option<EndpointId>) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.pinned_endpoint = endpoint;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_direct_only(&mut self, resource_id: &str, direct_only: bool) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.direct_only = direct_only;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_proxy_protocol(&mut self, resource_id: &str, proxy_protocol: bool) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.proxy_protocol = proxy_protocol;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set or clear when a proxy expires. Returns false if there is no such
+    /// proxy.
+    pub fn set_expiry(&mut self, resource_id: &str, expires_at: Option<DateTime<Utc>>) -> bool {
+        match self
+            .proxies
+            .iter_mut()
+            .find(|p| p.info.resource_id == resource_id)
+        {
+            Some(proxy) => {
+                proxy.expires_at = expires_at;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Disable the enabled proxies that expired by `now` and clear their
+    /// expiry, so they can be enabled again. Returns the expired proxies.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<ProxyState> {
+        let mut expired = Vec::new();
+        for proxy in self.proxies.iter_mut() {
+            if proxy.enabled && proxy.is_expired(now) {
+                proxy.enabled = false;
+                proxy.expires_at = None;
+                expired.push(proxy.clone());
+            }
+        }
+        expired
+    }
+
+    pub fn active_share(&self, tunnel_id: &str, now: DateTime<Utc>) -> Option<&IssuedShare> {
+        self.shares
+            .iter()
+            .filter(|share| share.tunnel_id == tunnel_id && share.is_active(now))
+            .max_by_key(|share| share.created_at)
+    }
+}
+
+/// A share link issued for a local proxy.
+///
+/// Tickets aren't individually authenticated: anyone holding a proxy's
+/// ticket can reach it. What a share adds is bookkeeping of the clients that
+/// connected while it was the active share, and once it is revoked or
+/// expires, those clients are refused.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct IssuedShare {
+    pub id: String,
+    pub tunnel_id: String,
+    pub ticket: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub revoked: bool,
+    /// Clients that connected through this share.
+    #[serde(default)]
+    pub clients: Vec<EndpointId>,
+}
+
+impl IssuedShare {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        !self.revoked && self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+
+    /// Time left until expiry, or `None` for shares that don't expire.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| (expires_at - now).to_std().unwrap_or_default())
+    }
+
+    pub fn uses(&self) -> usize {
+        self.clients.len()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -60,16 +338,25 @@ impl SelectedContext {
 pub struct StateWrapper {
     inner: Arc<ArcSwap<State>>,
     notify: Arc<Notify>,
+    proxies: Arc<watch::Sender<Vec<ProxyState>>>,
 }
 
 impl StateWrapper {
     pub fn new(state: State) -> Self {
+        let (proxies, _) = watch::channel(state.proxies.clone());
         Self {
             inner: Arc::new(ArcSwap::new(Arc::new(state))),
             notify: Default::default(),
+            proxies: Arc::new(proxies),
         }
     }
 
+    /// Watch the proxy list. Fires only when a proxy is added, removed or
+    /// changed, not on every write.
+    pub fn proxies_watch(&self) -> watch::Receiver<Vec<ProxyState>> {
+        self.proxies.subscribe()
+    }
+
     pub fn get(&self) -> Guard<Arc<State>> {
         self.inner.load()
     }
@@ -93,14 +380,90 @@ impl StateWrapper {
         self.inner.store(inner.clone());
         repo.write_state(&inner).await?;
         self.notify.notify_waiters();
+        self.publish(&inner);
         Ok(res)
     }
+
+    /// Replace the state with one read back from the repo, e.g. after the
+    /// CLI changed it. Unlike [`Self::update`] nothing is written. Returns
+    /// whether anything changed.
+    pub fn reload(&self, state: State) -> bool {
+        if **self.inner.load() == state {
+            return false;
+        }
+        let inner = Arc::new(state);
+        self.inner.store(inner.clone());
+        self.notify.notify_waiters();
+        self.publish(&inner);
+        true
+    }
+
+    fn publish(&self, inner: &State) {
+        self.proxies.send_if_modified(|proxies| {
+            if *proxies == inner.proxies {
+                return false;
+            }
+            *proxies = inner.proxies.clone();
+            true
+        });
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct ProxyState {
     pub info: Advertisment,
     pub enabled: bool,
+    #[serde(default, skip_serializing_if = "TunnelTimeouts::is_default")]
+    pub timeouts: TunnelTimeouts,
+    /// For directory tunnels, the folder served on the tunnel's target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serve_dir: Option<PathBuf>,
+    /// For unix socket tunnels, the socket the tunnel's target relays to.
+    /// Only served on unix platforms.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+    /// For tunnels to a name from the `hosts` config, the `host:port` the
+    /// tunnel's target relays to. See [`crate::host_bridge`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_target: Option<String>,
+    /// Header rules and credentials, applied by a proxy on the tunnel's
+    /// target in front of the real service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_front: Option<HttpFront>,
+    /// Periodic checks that the tunnel's target is up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// See [`crate::tunnels::parse_tags`]. Mirrors the cloud tunnel's tags,
+    /// if there is one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// When the proxy is turned off by itself. Mirrors the cloud tunnel's
+    /// expiry, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the proxy is turned on and off by itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TunnelSchedule>,
+    /// Bytes the proxy may transfer before it is disabled. See
+    /// [`crate::usage`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transfer_quota: Option<TransferQuota>,
+    /// Only serve clients with a direct path, never over a relay, e.g. for
+    /// traffic that must stay on the local network.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub direct_only: bool,
+    /// Start connections to the service with a PROXY protocol v2 header
+    /// naming the client, for services behind which the real client
+    /// address matters. Only requests the gateway sends over HTTP/2 carry
+    /// it; see [`crate::http2_upstream`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub proxy_protocol: bool,
+    /// Serve and advertise the proxy under an endpoint with its own key,
+    /// kept in the repo, instead of the listen key. Its tickets then name
+    /// the same endpoint across listen key rotations. See
+    /// [`ListenNode::set_key_pinned`](crate::ListenNode::set_key_pinned).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_endpoint: Option<EndpointId>,
 }
 
 impl ProxyState {
@@ -108,12 +471,84 @@ impl ProxyState {
         Self {
             info,
             enabled: true,
+            timeouts: TunnelTimeouts::default(),
+            serve_dir: None,
+            unix_socket: None,
+            host_target: None,
+            http_front: None,
+            health_check: None,
+            tags: Vec::new(),
+            expires_at: None,
+            schedule: None,
+            transfer_quota: None,
+            direct_only: false,
+            proxy_protocol: false,
+            pinned_endpoint: None,
         }
     }
 
     pub fn id(&self) -> &str {
         &self.info.resource_id
     }
+
+    /// The proxy's ticket, naming its pinned endpoint if it has one and
+    /// `listen_endpoint` otherwise.
+    pub fn ticket(&self, listen_endpoint: EndpointId) -> AdvertismentTicket {
+        self.info
+            .ticket(self.pinned_endpoint.unwrap_or(listen_endpoint))
+    }
+
+    /// Let the proxy expire `ttl` from now.
+    pub fn expire_after(&mut self, ttl: Duration) {
+        self.expires_at = Some(Utc::now() + ttl);
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// QUIC idle timeout and keepalive interval for a tunnel.
+///
+/// Unset values use iroh's defaults. Keepalives hold NAT mappings open for
+/// long-lived, mostly quiet connections such as database sessions; a short
+/// idle timeout lets unused connections go away sooner.
+///
+/// iroh applies transport settings per endpoint, not per connection, so the
+/// tunnels sharing an endpoint are [combined](Self::combine): the shortest
+/// keepalive and the longest idle timeout win, so no tunnel is dropped sooner
+/// than it asked for. Changes apply when the endpoint is next started.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct TunnelTimeouts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive_secs: Option<u64>,
+}
+
+impl TunnelTimeouts {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive_secs.map(Duration::from_secs)
+    }
+
+    /// Settings for an endpoint shared by several tunnels.
+    pub fn combine<'a>(timeouts: impl IntoIterator<Item = &'a TunnelTimeouts>) -> Self {
+        timeouts.into_iter().fold(Self::default(), |acc, t| Self {
+            idle_timeout_secs: acc.idle_timeout_secs.max(t.idle_timeout_secs),
+            keepalive_secs: match (acc.keepalive_secs, t.keepalive_secs) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
@@ -121,6 +556,10 @@ pub struct Advertisment {
     pub resource_id: String,
     pub label: Option<String>,
     pub data: TcpProxyData,
+    /// Share of gateway traffic this agent gets relative to other agents
+    /// serving the same tunnel. Unset counts as 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
 }
 
 impl Advertisment {
@@ -130,6 +569,7 @@ impl Advertisment {
             resource_id,
             data,
             label,
+            weight: None,
         }
     }
 
@@ -138,9 +578,15 @@ impl Advertisment {
             resource_id,
             data,
             label,
+            weight: None,
         }
     }
 
+    pub fn with_weight(mut self, weight: Option<u32>) -> Self {
+        self.weight = weight;
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.resource_id
     }
@@ -176,10 +622,54 @@ impl Advertisment {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct TcpProxyData {
     pub host: String,
     pub port: u16,
+    /// Path-prefix routes for HTTP traffic. Requests matching no route go to
+    /// `host:port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteRule>,
+}
+
+/// Sends HTTP requests under `path_prefix` to another local backend.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub struct RouteRule {
+    pub path_prefix: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl RouteRule {
+    pub fn address(&self) -> String {
+        join_host_port(&self.host, self.port)
+    }
+
+    /// Whether `path` is the prefix itself or below it; `/api` matches
+    /// `/api/users` but not `/apiary`.
+    fn matches(&self, path: &str) -> bool {
+        let prefix = self.path_prefix.trim_end_matches('/');
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Parses `/prefix=host:port`.
+impl FromStr for RouteRule {
+    type Err = n0_error::AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path_prefix, target) = s.split_once('=').context("missing '=' in route")?;
+        if !path_prefix.starts_with('/') {
+            n0_error::bail_any!("route prefix must start with '/'");
+        }
+        let (host, port) = TcpProxyData::parse_host_port(target)?;
+        Ok(Self {
+            path_prefix: path_prefix.to_string(),
+            host,
+            port,
+        })
+    }
 }
 
 impl From<TcpProxyData> for Authority {
@@ -194,29 +684,94 @@ impl From<TcpProxyData> for Authority {
 impl TcpProxyData {
     pub fn from_host_port_str(s: &str) -> Result<Self> {
         let (host, port) = Self::parse_host_port(s)?;
-        Ok(Self { host, port })
+        Ok(Self {
+            host,
+            port,
+            routes: Vec::new(),
+        })
+    }
+
+    pub fn with_routes(mut self, routes: Vec<RouteRule>) -> Self {
+        self.routes = routes;
+        self
     }
 
     pub fn address(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+        join_host_port(&self.host, self.port)
+    }
+
+    /// The backend for a request path: the longest matching route, else the
+    /// default target.
+    pub fn target_for_path(&self, path: &str) -> (&str, u16) {
+        self.routes
+            .iter()
+            .filter(|route| route.matches(path))
+            .max_by_key(|route| route.path_prefix.trim_end_matches('/').len())
+            .map(|route| (route.host.as_str(), route.port))
+            .unwrap_or((self.host.as_str(), self.port))
     }
 
-    fn parse_host_port(s: &str) -> Result<(String, u16)> {
-        let (host, port) = s.rsplit_once(":").context("missing port")?;
+    /// Whether `host:port` is the default target or one of the routes.
+    pub fn serves(&self, host: &str, port: u16) -> bool {
+        (same_host(&self.host, host) && self.port == port)
+            || self
+                .routes
+                .iter()
+                .any(|route| same_host(&route.host, host) && route.port == port)
+    }
+
+    /// Parses `host:port`, with IPv6 hosts in brackets like `[::1]:8080`.
+    /// The brackets are not part of the returned host.
+    pub(crate) fn parse_host_port(s: &str) -> Result<(String, u16)> {
+        let (host, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (host, port) = rest.split_once(']').context("missing ']'")?;
+                let port = port.strip_prefix(':').context("missing port")?;
+                host.parse::<Ipv6Addr>()
+                    .std_context("invalid IPv6 address")?;
+                (host, port)
+            }
+            None => s.rsplit_once(":").context("missing port")?,
+        };
+        if host.is_empty() {
+            n0_error::bail_any!("missing host");
+        }
         let port: u16 = port.parse().std_context("invalid port")?;
         Ok((host.to_string(), port))
     }
 }
 
+/// `host:port`, with IPv6 hosts in brackets.
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// Whether two target hosts name the same host, comparing IP addresses by
+/// value so `::1` matches `[0:0::1]`.
+fn same_host(a: &str, b: &str) -> bool {
+    let (a, b) = (crate::dial::unbracket(a), crate::dial::unbracket(b));
+    match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.eq_ignore_ascii_case(b),
+    }
+}
+
 impl State {
     pub(crate) async fn from_file(path: PathBuf) -> Result<Self> {
-        let data = tokio::fs::read(path).await?;
-        let state: State = serde_yml::from_slice(&data).anyerr()?;
+        let data = tokio::fs::read(&path).await?;
+        let (state, migrated): (State, bool) = migrations::STATE.parse(&data)?;
+        if migrated {
+            state.write_to_file(path).await?;
+        }
         Ok(state)
     }
 
     pub(crate) async fn write_to_file(&self, path: PathBuf) -> Result<()> {
-        let data = serde_yml::to_string(&self).anyerr()?;
+        let data = migrations::STATE.to_string(self)?;
         tokio::fs::write(&path, &data).await?;
         Ok(())
     }
@@ -227,6 +782,7 @@ impl Advertisment {
         AdvertismentTicket {
             data: self.clone(),
             endpoint,
+            published_at: None,
         }
     }
 }
@@ -244,12 +800,36 @@ fn rand_str(len: usize) -> String {
 pub struct AdvertismentTicket {
     pub data: Advertisment,
     pub endpoint: EndpointId,
+    /// When the agent last published this ticket to n0des, in seconds since
+    /// the Unix epoch. Agents refresh it periodically, so an old value means
+    /// the agent stopped running. Unset in tickets shared out of band.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<u64>,
 }
 
 impl AdvertismentTicket {
     pub fn service(&self) -> &TcpProxyData {
         &self.data.data
     }
+
+    /// Stamp the ticket with the current time, for publishing.
+    pub fn published_now(mut self) -> Self {
+        self.published_at = Some(Utc::now().timestamp().max(0) as u64);
+        self
+    }
+
+    /// How long ago the ticket was published, if it says.
+    pub fn age(&self) -> Option<Duration> {
+        let now = Utc::now().timestamp().max(0) as u64;
+        self.published_at
+            .map(|at| Duration::from_secs(now.saturating_sub(at)))
+    }
+}
+
+impl std::fmt::Display for AdvertismentTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Ticket::serialize(self))
+    }
 }
 
 impl FromStr for AdvertismentTicket {
@@ -263,20 +843,159 @@ impl FromStr for AdvertismentTicket {
 impl Ticket for AdvertismentTicket {
     const KIND: &'static str = "datum";
 
+    // Routes, the weight and the publish time trail the original layout:
+    // tickets without them are unchanged, and older clients read the default
+    // target and ignore the rest. A publish time needs the fields before it,
+    // so the weight is written as 1 if it is unset.
     fn to_bytes(&self) -> Vec<u8> {
-        postcard::to_allocvec(&self).expect("serialize should work")
+        let data = &self.data;
+        let wire = WireTicket {
+            resource_id: data.resource_id.clone(),
+            label: data.label.clone(),
+            host: data.data.host.clone(),
+            port: data.data.port,
+            endpoint: self.endpoint,
+        };
+        let mut bytes = postcard::to_allocvec(&wire).expect("serialize should work");
+        let weight = match self.published_at {
+            Some(_) => Some(data.weight.unwrap_or(1)),
+            None => data.weight,
+        };
+        if !data.data.routes.is_empty() || weight.is_some() {
+            bytes.extend(postcard::to_allocvec(&data.data.routes).expect("serialize should work"));
+        }
+        if let Some(weight) = weight {
+            bytes.extend(postcard::to_allocvec(&weight).expect("serialize should work"));
+        }
+        if let Some(published_at) = self.published_at {
+            bytes.extend(postcard::to_allocvec(&published_at).expect("serialize should work"));
+        }
+        bytes
     }
 
     fn from_bytes(bytes: &[u8]) -> Result<Self, iroh_tickets::ParseError> {
-        let ticket: Self = postcard::from_bytes(bytes)?;
-        Ok(ticket)
+        let (wire, rest): (WireTicket, _) = postcard::take_from_bytes(bytes)?;
+        let (routes, rest) = if rest.is_empty() {
+            (Vec::new(), rest)
+        } else {
+            postcard::take_from_bytes(rest)?
+        };
+        let (weight, rest) = if rest.is_empty() {
+            (None, rest)
+        } else {
+            let (weight, rest) = postcard::take_from_bytes(rest)?;
+            (Some(weight), rest)
+        };
+        let published_at = if rest.is_empty() {
+            None
+        } else {
+            Some(postcard::from_bytes(rest)?)
+        };
+        let data = TcpProxyData {
+            host: wire.host,
+            port: wire.port,
+            routes,
+        };
+        Ok(Self {
+            data: Advertisment::with_id(wire.resource_id, data, wire.label).with_weight(weight),
+            endpoint: wire.endpoint,
+            published_at,
+        })
     }
 }
 
+/// The original ticket layout, as postcard encodes it.
+#[derive(Serialize, Deserialize)]
+struct WireTicket {
+    resource_id: String,
+    label: Option<String>,
+    host: String,
+    port: u16,
+    endpoint: EndpointId,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn combine_tunnel_timeouts() {
+        let timeouts = [
+            TunnelTimeouts {
+                idle_timeout_secs: Some(60),
+                keepalive_secs: Some(15),
+            },
+            TunnelTimeouts {
+                idle_timeout_secs: Some(3600),
+                keepalive_secs: None,
+            },
+            TunnelTimeouts {
+                idle_timeout_secs: None,
+                keepalive_secs: Some(5),
+            },
+        ];
+        assert_eq!(
+            TunnelTimeouts::combine(&timeouts),
+            TunnelTimeouts {
+                idle_timeout_secs: Some(3600),
+                keepalive_secs: Some(5),
+            }
+        );
+        assert!(TunnelTimeouts::combine([]).is_default());
+    }
+
+    #[test]
+    fn expires_enabled_proxies_once() {
+        let now = Utc::now();
+        let proxy = |expires_at| {
+            let data = TcpProxyData::from_host_port_str("127.0.0.1:3000").unwrap();
+            let mut proxy = ProxyState::new(Advertisment::new(data, None));
+            proxy.expires_at = expires_at;
+            proxy
+        };
+        let mut state = State {
+            proxies: vec![
+                proxy(Some(now - chrono::Duration::minutes(1))),
+                proxy(Some(now + chrono::Duration::minutes(1))),
+                proxy(None),
+            ],
+            ..Default::default()
+        };
+        let expired = state.expire(now);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id(), state.proxies[0].id());
+        assert!(!state.proxies[0].enabled);
+        assert_eq!(state.proxies[0].expires_at, None);
+        assert!(state.proxies[1].enabled && state.proxies[2].enabled);
+        assert!(state.expire(now).is_empty());
+    }
+
+    #[test]
+    fn finds_listener_conflicts() {
+        let proxy = |target| {
+            let data = TcpProxyData::from_host_port_str(target).unwrap();
+            ProxyState::new(Advertisment::new(data, None))
+        };
+        let state = State {
+            proxies: vec![proxy("127.0.0.1:3000"), proxy("127.0.0.1:4000")],
+            ..Default::default()
+        };
+        let first = state.proxies[0].id();
+        let conflict = state.listener_conflict(None, "127.0.0.1", 3000).unwrap();
+        assert_eq!(conflict.id(), first);
+        assert!(
+            state
+                .listener_conflict(Some(first), "127.0.0.1", 3000)
+                .is_none()
+        );
+        assert!(
+            state
+                .listener_conflict(Some(first), "127.0.0.1", 4000)
+                .is_some()
+        );
+        assert!(state.listener_conflict(None, "127.0.0.1", 5000).is_none());
+    }
+
     #[test]
     fn parse_tcp_proxy_data_from_host_port() {
         let data = TcpProxyData::from_host_port_str("example.test:443").unwrap();
@@ -284,6 +1003,96 @@ mod tests {
         assert_eq!(data.port, 443);
     }
 
+    #[test]
+    fn routes_by_longest_path_prefix() {
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:3000")
+            .unwrap()
+            .with_routes(vec![
+                "/api=127.0.0.1:8080".parse().unwrap(),
+                "/api/admin/=127.0.0.1:9000".parse().unwrap(),
+            ]);
+        assert_eq!(data.target_for_path("/"), ("127.0.0.1", 3000));
+        assert_eq!(data.target_for_path("/apiary"), ("127.0.0.1", 3000));
+        assert_eq!(data.target_for_path("/api"), ("127.0.0.1", 8080));
+        assert_eq!(data.target_for_path("/api/users"), ("127.0.0.1", 8080));
+        assert_eq!(data.target_for_path("/api/admin/x"), ("127.0.0.1", 9000));
+        assert!(data.serves("127.0.0.1", 9000));
+        assert!(!data.serves("127.0.0.1", 9001));
+        assert!("api=127.0.0.1:8080".parse::<RouteRule>().is_err());
+    }
+
+    #[test]
+    fn tickets_without_routes_keep_the_old_layout() {
+        #[derive(Serialize)]
+        struct OldTicket {
+            data: OldAdvertisment,
+            endpoint: EndpointId,
+        }
+        #[derive(Serialize)]
+        struct OldAdvertisment {
+            resource_id: String,
+            label: Option<String>,
+            data: (String, u16),
+        }
+
+        let endpoint = iroh::SecretKey::from_bytes(&[7u8; 32]).public();
+        let old = OldTicket {
+            data: OldAdvertisment {
+                resource_id: "proxy-abc".into(),
+                label: Some("web".into()),
+                data: ("127.0.0.1".into(), 3000),
+            },
+            endpoint,
+        };
+        let old_bytes = postcard::to_allocvec(&old).unwrap();
+        let ticket = AdvertismentTicket::from_bytes(&old_bytes).unwrap();
+        assert_eq!(ticket.service().address(), "127.0.0.1:3000");
+        assert!(ticket.service().routes.is_empty());
+        assert_eq!(ticket.to_bytes(), old_bytes);
+
+        let routed = AdvertismentTicket {
+            data: Advertisment::with_id(
+                "proxy-abc".into(),
+                ticket
+                    .service()
+                    .clone()
+                    .with_routes(vec!["/api=127.0.0.1:8080".parse().unwrap()]),
+                Some("web".into()),
+            ),
+            endpoint,
+            published_at: None,
+        };
+        let parsed: AdvertismentTicket = routed.to_string().parse().unwrap();
+        assert_eq!(parsed.service(), routed.service());
+
+        let weighted = AdvertismentTicket {
+            data: ticket.data.clone().with_weight(Some(3)),
+            endpoint,
+            published_at: None,
+        };
+        let parsed: AdvertismentTicket = weighted.to_string().parse().unwrap();
+        assert_eq!(parsed.data, weighted.data);
+        // Clients that only know about routes stop reading after them.
+        let bytes = weighted.to_bytes();
+        let (wire, rest): (WireTicket, _) = postcard::take_from_bytes(&bytes).unwrap();
+        let routes: Vec<RouteRule> = postcard::from_bytes(rest).unwrap();
+        assert_eq!(wire.port, 3000);
+        assert!(routes.is_empty());
+
+        let published = ticket.data.ticket(endpoint).published_now();
+        let parsed: AdvertismentTicket = published.to_string().parse().unwrap();
+        assert_eq!(parsed.published_at, published.published_at);
+        assert!(parsed.age().unwrap() < Duration::from_secs(5));
+        assert_eq!(parsed.data.weight, Some(1));
+        // Clients that only know about the weight stop reading after it.
+        let bytes = published.to_bytes();
+        let (_, rest): (WireTicket, _) = postcard::take_from_bytes(&bytes).unwrap();
+        let (_, rest): (Vec<RouteRule>, _) = postcard::take_from_bytes(rest).unwrap();
+        let weight: u32 = postcard::from_bytes(rest).unwrap();
+        assert_eq!(weight, 1);
+        assert!(ticket.age().is_none());
+    }
+
     #[test]
     fn parse_tcp_proxy_data_rejects_missing_port() {
         let err = TcpProxyData::from_host_port_str("example.test").unwrap_err();
@@ -296,6 +1105,28 @@ mod tests {
         assert!(err.to_string().contains("invalid port"));
     }
 
+    #[test]
+    fn parse_tcp_proxy_data_with_bracketed_ipv6() {
+        let data = TcpProxyData::from_host_port_str("[::1]:8080").unwrap();
+        assert_eq!(data.host, "::1");
+        assert_eq!(data.port, 8080);
+        assert_eq!(data.address(), "[::1]:8080");
+        assert!(data.serves("[::1]", 8080));
+        assert!(data.serves("0:0::1", 8080));
+        assert!(!data.serves("127.0.0.1", 8080));
+
+        let route: RouteRule = "/api=[fd00::12]:9000".parse().unwrap();
+        assert_eq!(route.host, "fd00::12");
+        assert_eq!(route.address(), "[fd00::12]:9000");
+
+        for invalid in ["[::1]", "[::1:8080", "[not-an-ip]:80", "[::1]8080", ":80"] {
+            assert!(
+                TcpProxyData::from_host_port_str(invalid).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
     // #[test]
     // fn test_tcp_proxy_has_codename() {
     //     let proxy = TcpProxy::new("127.0.0.1".to_string(), 8080);
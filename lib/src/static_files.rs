@@ -0,0 +1,454 @@
+//! Serving a local directory through a tunnel, like `ngrok http file://`.
+//!
+//! A directory tunnel is an ordinary tunnel whose target is a small HTTP file
+//! server the agent runs on loopback. Directories are served as their
+//! `index.html` or else as a listing, and single-range `Range` requests are
+//! honored so media can seek. Paths can't leave the served directory, also
+//! not through symlinks.
+
+use std::{
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    response::Response,
+};
+use hyper::body::Bytes;
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    net::TcpListener,
+};
+use tracing::{debug, warn};
+
+/// Size of the chunks files are streamed in.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The server is read-only; everything else gets a 405.
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// A file server for one directory, stopped when dropped.
+#[derive(Debug)]
+pub struct FileServer {
+    root: PathBuf,
+    local_addr: SocketAddr,
+    _task: AbortOnDropHandle<()>,
+}
+
+impl FileServer {
+    /// Serve `root` on `addr`. Use port 0 to bind any free port.
+    pub async fn bind(root: impl AsRef<Path>, addr: SocketAddr) -> Result<Self> {
+        let root = root.as_ref();
+        let root = tokio::fs::canonicalize(root)
+            .await
+            .with_std_context(|_| format!("Failed to open {}", root.display()))?;
+        if !root.is_dir() {
+            n0_error::bail_any!("{} is not a directory", root.display());
+        }
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_std_context(|_| format!("Failed to bind {addr}"))?;
+        let local_addr = listener.local_addr()?;
+        let app = Router::new()
+            .fallback(serve)
+            .with_state(Arc::new(root.clone()));
+        let task = tokio::spawn({
+            let root = root.clone();
+            async move {
+                if let Err(err) = axum::serve(listener, app).await {
+                    warn!(root = %root.display(), "file server failed: {err:#}");
+                }
+            }
+        });
+        debug!(root = %root.display(), %local_addr, "serving directory");
+        Ok(Self {
+            root,
+            local_addr,
+            _task: AbortOnDropHandle::new(task),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+async fn serve(
+    State(root): State<Arc<PathBuf>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if method == Method::OPTIONS {
+        return Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, ALLOWED_METHODS)
+            .body(Body::empty())
+            .expect("valid response");
+    }
+    if method != Method::GET && method != Method::HEAD {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, ALLOWED_METHODS)
+            .body(Body::empty())
+            .expect("valid response");
+    }
+    let head = method == Method::HEAD;
+    let Some(path) = resolve(&root, uri.path()).await else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    if path.is_dir() {
+        if !uri.path().ends_with('/') {
+            return Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(header::LOCATION, format!("{}/", uri.path()))
+                .body(Body::empty())
+                .expect("valid response");
+        }
+        let index = path.join("index.html");
+        if index.is_file() {
+            return serve_file(&index, &headers, head).await;
+        }
+        return listing(&path, uri.path(), head).await;
+    }
+    serve_file(&path, &headers, head).await
+}
+
+/// The file for a request path, if it exists below `root`.
+async fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(request_path)?;
+    let mut path = root.to_path_buf();
+    for segment in decoded.split('/') {
+        match Path::new(segment).components().next() {
+            None => continue,
+            Some(Component::Normal(_)) if !segment.contains('\\') => path.push(segment),
+            _ => return None,
+        }
+    }
+    let path = tokio::fs::canonicalize(&path).await.ok()?;
+    path.starts_with(root).then_some(path)
+}
+
+async fn serve_file(path: &Path, headers: &HeaderMap, head: bool) -> Response {
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(err) => {
+            debug!(path = %path.display(), "failed to open: {err:#}");
+            return status(StatusCode::NOT_FOUND);
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(meta) => meta.len(),
+        Err(_) => return status(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let (status_code, start, end) = match range.map(|range| parse_range(range, len)) {
+        None | Some(Range::Ignored) => (StatusCode::OK, 0, len),
+        Some(Range::Bytes { start, end }) => (StatusCode::PARTIAL_CONTENT, start, end + 1),
+        Some(Range::Unsatisfiable) => {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+                .body(Body::empty())
+                .expect("valid response");
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(status_code)
+        .header(header::CONTENT_TYPE, content_type(path))
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_LENGTH, end - start);
+    if status_code == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {start}-{}/{len}", end - 1),
+        );
+    }
+    if head {
+        return response.body(Body::empty()).expect("valid response");
+    }
+    if start > 0 && file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    response
+        .body(file_body(file, end - start))
+        .expect("valid response")
+}
+
+/// Streams `remaining` bytes from the current position of `file`.
+fn file_body(file: File, remaining: u64) -> Body {
+    let stream = n0_future::stream::unfold((file, remaining), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut buf = vec![0; remaining.min(CHUNK_SIZE) as usize];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), (file, remaining - n as u64)))
+            }
+            Err(err) => Some((Err(err), (file, 0))),
+        }
+    });
+    Body::from_stream(stream)
+}
+
+async fn listing(dir: &Path, request_path: &str, head: bool) -> Response {
+    let mut entries = Vec::new();
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        Err(_) => return status(StatusCode::NOT_FOUND),
+    };
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+        entries.push((is_dir, entry.file_name().to_string_lossy().into_owned()));
+    }
+    // Directories first, then by name.
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let title = html_escape(request_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>Index of {title}</title></head>\n\
+         <body><h1>Index of {title}</h1>\n<ul>\n"
+    );
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_dir, name) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{slash}\">{}{slash}</a></li>\n",
+            percent_encode(&name),
+            html_escape(&name),
+        ));
+    }
+    html.push_str("</ul></body></html>\n");
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_LENGTH, html.len());
+    let body = if head {
+        Body::empty()
+    } else {
+        Body::from(html)
+    };
+    response.body(body).expect("valid response")
+}
+
+fn status(code: StatusCode) -> Response {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Range {
+    /// Inclusive byte range.
+    Bytes {
+        start: u64,
+        end: u64,
+    },
+    Unsatisfiable,
+    /// Not a single byte range; the whole file is sent.
+    Ignored,
+}
+
+fn parse_range(value: &str, len: u64) -> Range {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Range::Ignored;
+    };
+    if spec.contains(',') {
+        return Range::Ignored;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Range::Ignored;
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return Range::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return Range::Ignored,
+    };
+    if len == 0 || start >= len {
+        return Range::Unsatisfiable;
+    }
+    Range::Bytes { start, end }
+}
+
+fn content_type(path: &Path) -> HeaderValue {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let mime = match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    };
+    HeaderValue::from_static(mime)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-9", 100),
+            Range::Bytes { start: 0, end: 9 }
+        );
+        assert_eq!(
+            parse_range("bytes=90-", 100),
+            Range::Bytes { start: 90, end: 99 }
+        );
+        assert_eq!(
+            parse_range("bytes=-10", 100),
+            Range::Bytes { start: 90, end: 99 }
+        );
+        assert_eq!(
+            parse_range("bytes=50-500", 100),
+            Range::Bytes { start: 50, end: 99 }
+        );
+        assert_eq!(parse_range("bytes=100-", 100), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), Range::Ignored);
+        assert_eq!(parse_range("items=0-1", 100), Range::Ignored);
+    }
+
+    #[tokio::test]
+    async fn serves_files_ranges_and_listings() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        tokio::fs::write(dir.path().join("hello.txt"), "hello world").await?;
+        tokio::fs::create_dir(dir.path().join("sub dir")).await?;
+        let server = FileServer::bind(dir.path(), "127.0.0.1:0".parse().unwrap()).await?;
+        let base = format!("http://{}", server.local_addr());
+        let client = reqwest::Client::new();
+
+        let res = client
+            .get(format!("{base}/hello.txt"))
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.anyerr()?, "hello world");
+
+        let res = client
+            .get(format!("{base}/hello.txt"))
+            .header(header::RANGE, "bytes=6-")
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 6-10/11");
+        assert_eq!(res.text().await.anyerr()?, "world");
+
+        let listing = client
+            .get(&base)
+            .send()
+            .await
+            .anyerr()?
+            .text()
+            .await
+            .anyerr()?;
+        assert!(listing.contains("href=\"sub%20dir/\""));
+        assert!(listing.contains("href=\"hello.txt\""));
+
+        let res = client
+            .get(format!("{base}/..%2f..%2fetc/passwd"))
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = client
+            .request(reqwest::Method::OPTIONS, &base)
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()[header::ALLOW], ALLOWED_METHODS);
+        let propfind = reqwest::Method::from_bytes(b"PROPFIND").unwrap();
+        let res = client.request(propfind, &base).send().await.anyerr()?;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+//! Agent metrics pushed to a statsd server, for setups without Prometheus.
+//!
+//! Every [`StatsdConfig::interval`] the bytes each tunnel sent and received
+//! since the last push, client connections and errors from the event log are
+//! sent as counters, and the open client connections and enabled tunnels as
+//! gauges. Counters that stayed at zero are left out. Plain statsd has no
+//! tags, so the tunnel id or error class becomes part of the metric name,
+//! e.g. `datum_connect.tunnel.sent_bytes.<tunnel-id>`; with
+//! [`StatsdConfig::dogstatsd`] they are sent as tags instead.
+//!
+//! Lines are batched into UDP datagrams small enough not to be fragmented.
+//! Nothing is retried: a push the server misses is lost, like any statsd
+//! packet.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use tokio::{
+    net::UdpSocket,
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{Instrument, debug, error_span, warn};
+
+use crate::{
+    ListenNode, Repo, StatsdConfig,
+    events::{Event, EventKind},
+    usage::TunnelUsage,
+};
+
+/// Largest datagram sent, to stay below common path MTUs.
+const MAX_DATAGRAM: usize = 1432;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Counter => "c",
+            Self::Gauge => "g",
+        }
+    }
+}
+
+/// One value of one push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Metric {
+    name: &'static str,
+    metric_type: MetricType,
+    value: u64,
+    tag: Option<(&'static str, String)>,
+}
+
+impl Metric {
+    fn counter(name: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            metric_type: MetricType::Counter,
+            value,
+            tag: None,
+        }
+    }
+
+    fn gauge(name: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            metric_type: MetricType::Gauge,
+            value,
+            tag: None,
+        }
+    }
+
+    fn tagged(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.tag = Some((key, value.into()));
+        self
+    }
+
+    /// The metric as a statsd line, without the trailing newline.
+    fn encode(&self, config: &StatsdConfig) -> String {
+        let mut line = String::new();
+        let prefix = config.prefix.trim_end_matches('.');
+        if !prefix.is_empty() {
+            line.push_str(prefix);
+            line.push('.');
+        }
+        line.push_str(self.name);
+        if let (false, Some((_, value))) = (config.dogstatsd, &self.tag) {
+            line.push('.');
+            line.push_str(&sanitize(value));
+        }
+        write!(line, ":{}|{}", self.value, self.metric_type.as_str()).ok();
+        if let (true, Some((key, value))) = (config.dogstatsd, &self.tag) {
+            write!(line, "|#{key}:{}", sanitize(value)).ok();
+        }
+        line
+    }
+}
+
+/// `value` with everything but letters, digits, `-` and `_` replaced, so it
+/// can't break up a line.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// What happened since the last push.
+#[derive(Debug, Default)]
+struct Collector {
+    /// Byte counters of each tunnel at the last push, `None` before the
+    /// first one.
+    last_usage: Option<BTreeMap<String, (u64, u64)>>,
+    clients_connected: u64,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Collector {
+    fn observe(&mut self, event: &EventKind) {
+        let class = match event {
+            EventKind::ClientConnected { .. } => {
+                self.clients_connected += 1;
+                return;
+            }
+            EventKind::HeartbeatFailed { .. } => "heartbeat",
+            EventKind::TargetUnhealthy { .. } => "target_unhealthy",
+            EventKind::TunnelQuotaExceeded { .. } => "quota_exceeded",
+            _ => return,
+        };
+        *self.errors.entry(class).or_default() += 1;
+    }
+
+    /// The metrics of this push, starting the next one.
+    fn collect(
+        &mut self,
+        usage: &BTreeMap<String, TunnelUsage>,
+        connections: usize,
+        enabled_tunnels: usize,
+    ) -> Vec<Metric> {
+        let mut metrics = vec![
+            Metric::gauge("connections", connections as u64),
+            Metric::gauge("tunnels.enabled", enabled_tunnels as u64),
+        ];
+        let current = usage
+            .iter()
+            .map(|(id, usage)| (id.clone(), (usage.sent_bytes, usage.received_bytes)))
+            .collect::<BTreeMap<_, _>>();
+        // The first push only sets the baseline; all bytes up to it were
+        // transferred before the exporter started.
+        if let Some(last) = self.last_usage.replace(current.clone()) {
+            for (tunnel_id, (sent, received)) in current {
+                let (last_sent, last_received) = last.get(&tunnel_id).copied().unwrap_or_default();
+                // Counters start over when a tunnel's usage is reset.
+                let since = |now: u64, last: u64| if now >= last { now - last } else { now };
+                for (name, bytes) in [
+                    ("tunnel.sent_bytes", since(sent, last_sent)),
+                    ("tunnel.received_bytes", since(received, last_received)),
+                ] {
+                    if bytes > 0 {
+                        metrics.push(Metric::counter(name, bytes).tagged("tunnel", &tunnel_id));
+                    }
+                }
+            }
+        }
+        if self.clients_connected > 0 {
+            metrics.push(Metric::counter(
+                "clients.connected",
+                std::mem::take(&mut self.clients_connected),
+            ));
+        }
+        for (class, count) in std::mem::take(&mut self.errors) {
+            metrics.push(Metric::counter("errors", count).tagged("class", class));
+        }
+        metrics
+    }
+}
+
+/// `lines` joined into datagrams of at most [`MAX_DATAGRAM`] bytes. A line
+/// longer than that gets a datagram of its own.
+fn datagrams(lines: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(&line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+/// Push the metrics of `node` to the server in `config` until dropped.
+pub fn spawn(config: StatsdConfig, repo: Repo, node: ListenNode) -> AbortOnDropHandle<()> {
+    let events = repo.events().subscribe();
+    let task = tokio::spawn(
+        async move {
+            if let Err(err) = run(&config, &repo, &node, events).await {
+                warn!(host = %config.host, "statsd exporter stopped: {err:#}");
+            }
+        }
+        .instrument(error_span!("statsd")),
+    );
+    AbortOnDropHandle::new(task)
+}
+
+async fn run(
+    config: &StatsdConfig,
+    repo: &Repo,
+    node: &ListenNode,
+    mut events: broadcast::Receiver<Event>,
+) -> Result<()> {
+    let socket = connect(config).await?;
+    let mut collector = Collector::default();
+    let mut push = tokio::time::interval(config.interval());
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => collector.observe(&event.kind),
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = push.tick() => {
+                let enabled = node.proxies().iter().filter(|p| p.enabled).count();
+                let metrics = collector.collect(
+                    &repo.usage().snapshot(),
+                    node.connections().len(),
+                    enabled,
+                );
+                let lines = metrics.iter().map(|metric| metric.encode(config));
+                for datagram in datagrams(lines) {
+                    if let Err(err) = socket.send(datagram.as_bytes()).await {
+                        debug!("Failed to send metrics: {err:#}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A socket connected to the statsd server.
+async fn connect(config: &StatsdConfig) -> Result<UdpSocket> {
+    let addr = tokio::net::lookup_host((config.host.as_str(), config.port))
+        .await
+        .std_context("Failed to resolve statsd host")?
+        .next();
+    let Some(addr) = addr else {
+        n0_error::bail_any!("No address for statsd host {}", config.host);
+    };
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .std_context("Failed to bind statsd socket")?;
+    socket
+        .connect(addr)
+        .await
+        .std_context("Failed to connect statsd socket")?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    fn usage(sent: u64, received: u64) -> BTreeMap<String, TunnelUsage> {
+        let usage = TunnelUsage {
+            sent_bytes: sent,
+            received_bytes: received,
+            ..Default::default()
+        };
+        BTreeMap::from([("web.prod".to_string(), usage)])
+    }
+
+    #[test]
+    fn encodes_plain_and_dogstatsd_lines() {
+        let metric = Metric::counter("tunnel.sent_bytes", 42).tagged("tunnel", "web.prod");
+        let mut config = StatsdConfig::new("localhost");
+        assert_eq!(
+            metric.encode(&config),
+            "datum_connect.tunnel.sent_bytes.web_prod:42|c"
+        );
+        config.dogstatsd = true;
+        config.prefix = "agent.".to_string();
+        assert_eq!(
+            metric.encode(&config),
+            "agent.tunnel.sent_bytes:42|c|#tunnel:web_prod"
+        );
+        config.prefix.clear();
+        assert_eq!(
+            Metric::gauge("connections", 3).encode(&config),
+            "connections:3|g"
+        );
+    }
+
+    #[test]
+    fn counts_deltas_since_the_last_push() {
+        let mut collector = Collector::default();
+        let first = collector.collect(&usage(1000, 500), 2, 1);
+        assert_eq!(
+            first,
+            [
+                Metric::gauge("connections", 2),
+                Metric::gauge("tunnels.enabled", 1),
+            ]
+        );
+
+        collector.observe(&EventKind::ClientConnected {
+            remote_id: SecretKey::generate(&mut rand::rng()).public(),
+            service: "web".into(),
+        });
+        collector.observe(&EventKind::HeartbeatFailed {
+            project_id: "p".into(),
+            error: "timeout".into(),
+        });
+        let second = collector.collect(&usage(1500, 500), 2, 1);
+        assert_eq!(
+            second[2..],
+            [
+                Metric::counter("tunnel.sent_bytes", 500).tagged("tunnel", "web.prod"),
+                Metric::counter("clients.connected", 1),
+                Metric::counter("errors", 1).tagged("class", "heartbeat"),
+            ]
+        );
+
+        // A reset counts from zero.
+        let third = collector.collect(&usage(200, 0), 0, 1);
+        assert_eq!(
+            third[2..],
+            [Metric::counter("tunnel.sent_bytes", 200).tagged("tunnel", "web.prod")]
+        );
+    }
+
+    #[test]
+    fn batches_lines_into_datagrams() {
+        let line = "x".repeat(700);
+        let batched = datagrams(vec![line.clone(), line.clone(), line.clone()]);
+        assert_eq!(batched, [format!("{line}\n{line}"), line]);
+        assert!(datagrams(Vec::new()).is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+//! Opt-in, anonymous usage telemetry.
+//!
+//! Nothing is recorded until the user turns telemetry on. While on, counts are
+//! aggregated locally in `telemetry.json` and sent as one small report every
+//! [`FLUSH_INTERVAL`]. A report holds counters, error counts by class and the
+//! platform; never tunnel ids, addresses, hostnames or account details.
+//! [`Telemetry::payload`] returns exactly what the next report would send.
+//! Turning telemetry off drops everything collected so far.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::{
+    datum_cloud::ApiEnv,
+    events::{EventKind, EventLog},
+};
+
+/// How often the aggregated report is sent.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Overrides where reports are sent, e.g. for testing against a local collector.
+const TELEMETRY_URL_ENV: &str = "DATUM_CONNECT_TELEMETRY_URL";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Platform {
+    pub os: String,
+    pub arch: String,
+    pub app_version: String,
+}
+
+impl Platform {
+    pub(crate) fn current(app_version: &str) -> Self {
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            app_version: app_version.to_string(),
+        }
+    }
+}
+
+/// One aggregated telemetry report.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TelemetryReport {
+    pub platform: Platform,
+    pub period_start: DateTime<Utc>,
+    /// Usage counters, e.g. `tunnels_created`.
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    /// Errors by class, e.g. `heartbeat`.
+    #[serde(default)]
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl TelemetryReport {
+    fn new(platform: Platform) -> Self {
+        Self {
+            platform,
+            period_start: Utc::now(),
+            counts: Default::default(),
+            errors: Default::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty() && self.errors.is_empty()
+    }
+
+    /// The report as it is sent, pretty-printed for display.
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report serializes")
+    }
+}
+
+#[derive(derive_more::Debug, Clone)]
+pub struct Telemetry {
+    path: PathBuf,
+    url: String,
+    enabled: Arc<AtomicBool>,
+    #[debug(skip)]
+    report: Arc<Mutex<TelemetryReport>>,
+    #[debug(skip)]
+    http: reqwest::Client,
+}
+
+impl Telemetry {
+    /// Telemetry aggregated at `path`, starting enabled or not.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        api_env: ApiEnv,
+        app_version: &str,
+        enabled: bool,
+    ) -> Self {
+        let path = path.into();
+        let platform = Platform::current(app_version);
+        let report = match std::fs::read(&path) {
+            Ok(data) if enabled => match serde_json::from_slice::<TelemetryReport>(&data) {
+                Ok(report) => TelemetryReport { platform, ..report },
+                Err(err) => {
+                    warn!(
+                        "Discarding unreadable telemetry in {}: {err}",
+                        path.display()
+                    );
+                    TelemetryReport::new(platform)
+                }
+            },
+            _ => TelemetryReport::new(platform),
+        };
+        let url = std::env::var(TELEMETRY_URL_ENV)
+            .unwrap_or_else(|_| format!("{}/telemetry/v1/datum-connect", api_env.api_url()));
+        let this = Self {
+            path,
+            url,
+            enabled: Arc::new(AtomicBool::new(enabled)),
+            report: Arc::new(Mutex::new(report)),
+            http: reqwest::Client::new(),
+        };
+        if !enabled {
+            this.discard();
+        }
+        this
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turn telemetry on or off. Turning it off drops what was collected.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.discard();
+        }
+    }
+
+    /// Count one occurrence of a usage counter.
+    pub fn count(&self, name: &str) {
+        self.modify(|report| *report.counts.entry(name.to_string()).or_default() += 1);
+    }
+
+    /// Count one error of the given class.
+    pub fn error(&self, class: &str) {
+        self.modify(|report| *report.errors.entry(class.to_string()).or_default() += 1);
+    }
+
+    /// Count what an event says about usage.
+    pub fn observe(&self, event: &EventKind) {
+        match event {
+            EventKind::TunnelCreated { .. } => self.count("tunnels_created"),
+            EventKind::TunnelDeleted { .. } => self.count("tunnels_deleted"),
+            EventKind::TunnelExpired { .. } => self.count("tunnels_expired"),
+            EventKind::TunnelQuotaExceeded { .. } => self.count("tunnels_over_quota"),
+            EventKind::ClientConnected { .. } => self.count("clients_connected"),
+            EventKind::HeartbeatFailed { .. } => self.error("heartbeat"),
+            _ => {}
+        }
+    }
+
+    /// The report that would be sent next.
+    pub fn payload(&self) -> TelemetryReport {
+        self.report.lock().expect("poisoned").clone()
+    }
+
+    /// Send the aggregated report and start a new one. Does nothing when
+    /// disabled or when there is nothing to report.
+    pub async fn flush(&self) -> Result<()> {
+        let report = self.payload();
+        if !self.is_enabled() || report.is_empty() {
+            return Ok(());
+        }
+        self.http
+            .post(&self.url)
+            .json(&report)
+            .send()
+            .await
+            .std_context("sending telemetry")?
+            .error_for_status()
+            .std_context("sending telemetry")?;
+        debug!(url = %self.url, "sent telemetry report");
+        // Keep anything counted while the request was in flight.
+        self.modify(|current| {
+            for (name, n) in report.counts {
+                subtract(&mut current.counts, name, n);
+            }
+            for (class, n) in report.errors {
+                subtract(&mut current.errors, class, n);
+            }
+            current.period_start = Utc::now();
+        });
+        Ok(())
+    }
+
+    /// Count events from `events` and flush periodically, until dropped.
+    pub fn spawn(&self, events: &EventLog) -> AbortOnDropHandle<()> {
+        let this = self.clone();
+        let mut events = events.subscribe();
+        let task = tokio::spawn(async move {
+            let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+            flush.tick().await;
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => this.observe(&event.kind),
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = flush.tick() => {
+                        if let Err(err) = this.flush().await {
+                            debug!("Failed to send telemetry: {err:#}");
+                        }
+                    }
+                }
+            }
+        });
+        AbortOnDropHandle::new(task)
+    }
+
+    fn modify(&self, f: impl FnOnce(&mut TelemetryReport)) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut report = self.report.lock().expect("poisoned");
+        f(&mut report);
+        let res = serde_json::to_vec(&*report)
+            .map_err(std::io::Error::from)
+            .and_then(|data| std::fs::write(&self.path, data));
+        if let Err(err) = res {
+            warn!(
+                "Failed to persist telemetry to {}: {err:#}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn discard(&self) {
+        let mut report = self.report.lock().expect("poisoned");
+        *report = TelemetryReport::new(report.platform.clone());
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => warn!("Failed to remove {}: {err:#}", self.path.display()),
+        }
+    }
+}
+
+fn subtract(counts: &mut BTreeMap<String, u64>, key: String, n: u64) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count = count.saturating_sub(n);
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_only_while_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.json");
+        let telemetry = Telemetry::open(&path, ApiEnv::Staging, "1.0.0", false);
+        telemetry.count("tunnels_created");
+        assert!(telemetry.payload().is_empty());
+
+        telemetry.set_enabled(true);
+        telemetry.observe(&EventKind::TunnelCreated {
+            tunnel_id: "t".into(),
+            label: "T".into(),
+        });
+        telemetry.error("heartbeat");
+        telemetry.error("heartbeat");
+
+        let reopened = Telemetry::open(&path, ApiEnv::Staging, "1.0.1", true);
+        let payload = reopened.payload();
+        assert_eq!(payload.counts.get("tunnels_created"), Some(&1));
+        assert_eq!(payload.errors.get("heartbeat"), Some(&2));
+        assert_eq!(payload.platform.app_version, "1.0.1");
+
+        reopened.set_enabled(false);
+        assert!(reopened.payload().is_empty());
+        assert!(!path.exists());
+    }
+}
@@ -1,22 +1,52 @@
-use std::net::Ipv4Addr;
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
 
-use http_body_util::BodyExt;
-use hyper::{Request, StatusCode, client::conn::http2};
+use http_body_util::{BodyExt, StreamBody, combinators::UnsyncBoxBody};
+use hyper::{
+    Request, StatusCode,
+    body::{Bytes, Frame},
+    client::conn::http2,
+};
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use iroh::{Endpoint, discovery::static_provider::StaticProvider};
+use iroh::{Endpoint, SecretKey, discovery::static_provider::StaticProvider};
 use n0_error::{Result, StdResultExt};
 use n0_future::task::AbortOnDropHandle;
 use n0_tracing_test::traced_test;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpListener,
+    sync::mpsc,
 };
 
-use crate::{Advertisment, ListenNode, ProxyState, Repo, TcpProxyData, gateway};
+use crate::{
+    Advertisment, AdvertismentTicket, Config, ConnectNode, IpFamily, ListenNode, Node, Preferences,
+    ProxyState, Repo, TcpProxyData,
+    config::{
+        AccessLogConfig, BalancePolicy, BalancingConfig, ClientIpConfig, GatewayConfig,
+        HeaderLimitsConfig, LivenessConfig, RequestLimitsConfig, ResponseCompressionConfig,
+        TicketsConfig, TunnelCompressionConfig, UpstreamConfig,
+    },
+    gateway, ip_filter,
+    node::{build_endpoint, build_n0des_client},
+};
 
 #[derive(Default)]
 struct TestDiscovery(StaticProvider);
 
+/// A body of the frames sent on `rx`, for streaming tests.
+fn channel_body(
+    rx: mpsc::Receiver<Result<Frame<Bytes>, Infallible>>,
+) -> UnsyncBoxBody<Bytes, Infallible> {
+    let frames = n0_future::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|frame| (frame, rx))
+    });
+    StreamBody::new(Box::pin(frames)).boxed_unsync()
+}
+
 impl TestDiscovery {
     fn add(&self, endpoint: &Endpoint) {
         endpoint.discovery().add(self.0.clone());
@@ -41,6 +71,7 @@ async fn gateway_end_to_end_to_upstream_http() -> Result<()> {
     };
 
     let codename = proxy_state.info.codename();
+    let tunnel_id = proxy_state.id().to_string();
 
     let upstream = ListenNode::new(repo).await?;
     discovery.add(upstream.endpoint());
@@ -73,9 +104,1281 @@ async fn gateway_end_to_end_to_upstream_http() -> Result<()> {
         .await
         .anyerr()?;
     assert_eq!(res.status(), StatusCode::OK);
-    let body = res.text().await.anyerr()?;
-    assert_eq!(body, "origin GET /hello");
+    let body = res.text().await.anyerr()?;
+    assert_eq!(body, "origin GET /hello");
+
+    let activity = upstream.activity(&tunnel_id).expect("activity recorded");
+    assert!(activity.created_at.is_some());
+    assert!(activity.requests >= 1);
+    assert!(activity.first_request_at.is_some());
+
+    Ok(())
+}
+
+/// Endpoints the gateway forwarded to show up with their path on the
+/// metrics server.
+#[tokio::test]
+#[traced_test]
+async fn gateway_reports_upstream_paths() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let metrics_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(gateway::serve_with_metrics(
+            endpoint,
+            listener,
+            Some(metrics_addr),
+        ));
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let res = client
+        .get(format!("http://{domain}:{}/hello", gateway_addr.port()))
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let endpoint_id = upstream.endpoint_id().to_string();
+    let paths: Vec<serde_json::Value> = reqwest::get(format!("http://{metrics_addr}/upstreams"))
+        .await
+        .anyerr()?
+        .json()
+        .await
+        .anyerr()?;
+    let path = paths
+        .iter()
+        .find(|path| path["endpoint_id"] == endpoint_id.as_str())
+        .expect("endpoint listed");
+    assert_ne!(path["path"], "none");
+
+    let metrics = reqwest::get(format!("http://{metrics_addr}/metrics"))
+        .await
+        .anyerr()?
+        .text()
+        .await
+        .anyerr()?;
+    assert!(metrics.contains(&format!(
+        "iroh_gateway_upstream_path{{endpoint=\"{endpoint_id}\""
+    )));
+
+    Ok(())
+}
+
+/// The gateway writes an access log record for each forwarded and each
+/// refused request.
+#[tokio::test]
+#[traced_test]
+async fn gateway_writes_access_log() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let log_path = temp_dir.path().join("access.log");
+    let config = GatewayConfig {
+        access_log: AccessLogConfig {
+            enabled: true,
+            path: Some(log_path.clone()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let url = format!("http://{domain}:{}/hello?x=1", gateway_addr.port());
+    let res = client
+        .get(&url)
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = client.get(&url).send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let mut records = Vec::new();
+    for _ in 0..50 {
+        let log = tokio::fs::read_to_string(&log_path)
+            .await
+            .unwrap_or_default();
+        records = log
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()
+            .anyerr()?;
+        if records.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["outcome"], "forwarded");
+    assert_eq!(
+        records[0]["endpoint_id"],
+        upstream.endpoint_id().to_string()
+    );
+    assert_eq!(records[0]["path"], "/hello?x=1");
+    assert_eq!(records[0]["client"], "127.0.0.1");
+    assert_eq!(records[1]["outcome"], "denied");
+    assert_eq!(records[1]["status"], 400);
+
+    Ok(())
+}
+
+/// Requests over the configured header limits get a 431 from the gateway
+/// instead of failing upstream.
+#[tokio::test]
+#[traced_test]
+async fn gateway_rejects_requests_over_header_limits() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        header_limits: HeaderLimitsConfig {
+            max_request_headers: 16,
+            max_request_header_bytes: 4096,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let request = || {
+        client
+            .get(format!("http://{domain}:{}/hello", gateway_addr.port()))
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+    };
+
+    let res = request().send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let mut many = request();
+    for i in 0..32 {
+        many = many.header(format!("x-extra-{i}"), "1");
+    }
+    let res = many.send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+    let res = request()
+        .header("cookie", "a".repeat(8192))
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+    Ok(())
+}
+
+/// Requests declaring a body over the limit get a 413, and requests whose
+/// endpoint can't be checked in time get a 504.
+#[tokio::test]
+#[traced_test]
+async fn gateway_enforces_request_limits() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn_body_echo("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        liveness: LivenessConfig {
+            enabled: true,
+            probe_timeout_ms: 5000,
+            ..Default::default()
+        },
+        request_limits: RequestLimitsConfig {
+            resolve_timeout_ms: 500,
+            max_request_body_bytes: Some(1024),
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let request = |endpoint_id: String, body: Vec<u8>| {
+        client
+            .post(format!("http://{domain}:{}/upload", gateway_addr.port()))
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", endpoint_id)
+            .body(body)
+    };
+    let endpoint_id = upstream.endpoint_id().to_string();
+
+    let res = request(endpoint_id.clone(), vec![0; 1024])
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.anyerr()?, "origin POST /upload 1024");
+
+    let res = request(endpoint_id, vec![0; 1025]).send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // An endpoint nobody can reach keeps the liveness probe waiting past the
+    // resolve timeout.
+    let unreachable = SecretKey::generate(&mut rand::rng()).public().to_string();
+    let res = request(unreachable, Vec::new()).send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    Ok(())
+}
+
+/// A tunnel served by several endpoints fails over to a reachable one.
+#[tokio::test]
+#[traced_test]
+async fn gateway_fails_over_between_endpoints() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        liveness: LivenessConfig {
+            enabled: true,
+            probe_timeout_ms: 500,
+            ..Default::default()
+        },
+        balancing: BalancingConfig {
+            policy: BalancePolicy::RoundRobin,
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let unreachable = SecretKey::generate(&mut rand::rng()).public();
+    let endpoint_ids = format!("{unreachable}, {}", upstream.endpoint_id());
+    // Round robin starts on each endpoint in turn; both requests end up at
+    // the reachable one.
+    for _ in 0..2 {
+        let res = client
+            .get(format!("http://{domain}:{}/hello", gateway_addr.port()))
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", &endpoint_ids)
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.anyerr()?, "origin GET /hello");
+    }
+
+    Ok(())
+}
+
+/// The gateway enforces the IP allow and deny lists Envoy attaches to a
+/// tunnel's requests, against the socket peer or `X-Forwarded-For`.
+#[tokio::test]
+#[traced_test]
+async fn gateway_enforces_ip_filters() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let spawn_gateway = |trusted_hops| {
+        let discovery = &discovery;
+        async move {
+            let config = GatewayConfig {
+                client_ip: ClientIpConfig {
+                    trusted_hops,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let listener = TcpListener::bind("127.0.0.1:0").await?;
+            let addr = listener.local_addr()?;
+            let endpoint = Endpoint::bind().await?;
+            discovery.add(&endpoint);
+            let task = tokio::task::spawn(async move {
+                gateway::serve_with_config(endpoint, listener, &config, None).await
+            });
+            n0_error::Ok((addr, AbortOnDropHandle::new(task)))
+        }
+    };
+    let (direct_addr, _direct_task) = spawn_gateway(0).await?;
+    let (behind_envoy_addr, _behind_envoy_task) = spawn_gateway(1).await?;
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let status = |gateway_addr: std::net::SocketAddr,
+                  headers: Vec<(&'static str, &'static str)>| {
+        let mut request = client
+            .get(format!("http://{domain}:{}/hello", gateway_addr.port()))
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string());
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        async move { n0_error::Ok(request.send().await.anyerr()?.status()) }
+    };
+
+    assert_eq!(status(direct_addr, vec![]).await?, StatusCode::OK);
+    let allow_loopback = vec![(ip_filter::ALLOW_HEADER, "127.0.0.0/8")];
+    assert_eq!(status(direct_addr, allow_loopback).await?, StatusCode::OK);
+    let deny_loopback = vec![
+        (ip_filter::ALLOW_HEADER, "127.0.0.0/8"),
+        (ip_filter::DENY_HEADER, "127.0.0.1"),
+    ];
+    assert_eq!(
+        status(direct_addr, deny_loopback).await?,
+        StatusCode::FORBIDDEN
+    );
+    let allow_other = vec![(ip_filter::ALLOW_HEADER, "10.0.0.0/8")];
+    assert_eq!(
+        status(direct_addr, allow_other).await?,
+        StatusCode::FORBIDDEN
+    );
+    let invalid = vec![(ip_filter::DENY_HEADER, "not-an-ip")];
+    assert_eq!(status(direct_addr, invalid).await?, StatusCode::FORBIDDEN);
+
+    // Behind Envoy the client is the address Envoy appended last, not the
+    // socket peer and not what the client put in the header itself.
+    let forwarded = vec![
+        ("x-forwarded-for", "127.0.0.1, 203.0.113.5"),
+        (ip_filter::ALLOW_HEADER, "203.0.113.0/24"),
+    ];
+    assert_eq!(status(behind_envoy_addr, forwarded).await?, StatusCode::OK);
+    let spoofed = vec![
+        ("x-forwarded-for", "203.0.113.5, 198.51.100.1"),
+        (ip_filter::ALLOW_HEADER, "203.0.113.0/24"),
+    ];
+    assert_eq!(
+        status(behind_envoy_addr, spoofed).await?,
+        StatusCode::FORBIDDEN
+    );
+    let missing = vec![(ip_filter::ALLOW_HEADER, "127.0.0.0/8")];
+    assert_eq!(
+        status(behind_envoy_addr, missing).await?,
+        StatusCode::FORBIDDEN
+    );
+
+    Ok(())
+}
+
+/// With `proxy_protocol` set, the gateway takes the client address from the
+/// PROXY v2 header a load balancer puts in front of each connection.
+#[tokio::test]
+#[traced_test]
+async fn gateway_reads_proxy_protocol_headers() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let (gateway_addr, _gateway_task) = {
+        let config = GatewayConfig {
+            client_ip: ClientIpConfig {
+                proxy_protocol: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let status_line = |client: Ipv4Addr| {
+        let request = format!(
+            "GET /hello HTTP/1.1\r\n\
+             Host: {codename}.localhost\r\n\
+             x-datum-target-host: {}\r\n\
+             x-datum-target-port: {}\r\n\
+             x-iroh-endpoint-id: {}\r\n\
+             {}: 203.0.113.0/24\r\n\
+             Connection: close\r\n\r\n",
+            origin_addr.ip(),
+            origin_addr.port(),
+            upstream.endpoint_id(),
+            ip_filter::ALLOW_HEADER,
+        );
+        async move {
+            // PROXY v2, TCP over IPv4, from `client`:40000 to 192.0.2.10:80.
+            let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+            header.extend_from_slice(&client.octets());
+            header.extend_from_slice(&[192, 0, 2, 10]);
+            header.extend_from_slice(&40000u16.to_be_bytes());
+            header.extend_from_slice(&80u16.to_be_bytes());
+
+            let mut stream = tokio::net::TcpStream::connect(gateway_addr).await?;
+            stream.write_all(&header).await?;
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            n0_error::Ok(response.lines().next().unwrap_or_default().to_string())
+        }
+    };
+
+    let allowed = status_line(Ipv4Addr::new(203, 0, 113, 5)).await?;
+    assert!(allowed.starts_with("HTTP/1.1 200"), "{allowed}");
+    let denied = status_line(Ipv4Addr::new(198, 51, 100, 1)).await?;
+    assert!(denied.starts_with("HTTP/1.1 403"), "{denied}");
+
+    Ok(())
+}
+
+/// Tunnels with `proxy_protocol` start connections to their service with a
+/// PROXY v2 header naming the client the gateway saw, here the one a load
+/// balancer named in its own PROXY header.
+#[tokio::test]
+#[traced_test]
+async fn agent_writes_proxy_protocol_headers() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    // Answers with the client named in the PROXY header of the connection.
+    let origin = TcpListener::bind("127.0.0.1:0").await?;
+    let origin_addr = origin.local_addr()?;
+    let _origin_task = AbortOnDropHandle::new(tokio::spawn(async move {
+        while let Ok((mut stream, _)) = origin.accept().await {
+            tokio::spawn(async move {
+                let client = gateway::proxy_protocol::read_header(&mut stream).await?;
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(stream.read_u8().await?);
+                }
+                let body = client.map_or("none".to_string(), |client| client.to_string());
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await?;
+                n0_error::Ok(())
+            });
+        }
+    }));
+    let mut proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    proxy_state.proxy_protocol = true;
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let (gateway_addr, _gateway_task) = {
+        let config = GatewayConfig {
+            client_ip: ClientIpConfig {
+                proxy_protocol: true,
+                proxy_protocol_sources: vec!["127.0.0.0/8".parse()?],
+                ..Default::default()
+            },
+            upstream: UpstreamConfig {
+                http2: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let balancer_header = gateway::proxy_protocol::header(
+        Some("203.0.113.5:40000".parse().unwrap()),
+        "192.0.2.10:80".parse().unwrap(),
+    );
+    let request = format!(
+        "GET /hello HTTP/1.1\r\n\
+         Host: {codename}.localhost\r\n\
+         x-datum-target-host: {}\r\n\
+         x-datum-target-port: {}\r\n\
+         x-iroh-endpoint-id: {}\r\n\
+         Connection: close\r\n\r\n",
+        origin_addr.ip(),
+        origin_addr.port(),
+        upstream.endpoint_id(),
+    );
+    let mut stream = tokio::net::TcpStream::connect(gateway_addr).await?;
+    stream.write_all(&balancer_header).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(
+        response.ends_with("\r\n\r\n203.0.113.5:40000"),
+        "{response}"
+    );
+
+    Ok(())
+}
+
+/// Methods beyond the common set, as used by WebDAV and CalDAV servers, are
+/// forwarded with their headers and bodies intact.
+#[tokio::test]
+#[traced_test]
+async fn gateway_forwards_uncommon_methods() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn_body_echo("dav").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(gateway::serve(endpoint, listener));
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let propfind = r#"<?xml version="1.0"?><propfind xmlns="DAV:"><allprop/></propfind>"#;
+    let cases: [(&str, usize); 10] = [
+        ("GET", 0),
+        ("OPTIONS", 0),
+        ("PROPFIND", propfind.len()),
+        ("PROPPATCH", propfind.len()),
+        ("MKCOL", 0),
+        ("REPORT", propfind.len()),
+        ("PUT", 64 * 1024),
+        ("PATCH", 4 * 1024 * 1024),
+        ("MOVE", 0),
+        ("DELETE", 0),
+    ];
+    for (method, body_len) in cases {
+        let body = if method.starts_with("PROP") || method == "REPORT" {
+            propfind.as_bytes().to_vec()
+        } else {
+            vec![b'x'; body_len]
+        };
+        let res = client
+            .request(
+                reqwest::Method::from_bytes(method.as_bytes()).unwrap(),
+                format!("http://{domain}:{}/calendars/me/", gateway_addr.port()),
+            )
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+            .header("depth", "1")
+            .header("destination", format!("http://{domain}/calendars/other/"))
+            .body(body)
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK, "{method}");
+        assert_eq!(res.headers()["x-depth"], "1", "{method}");
+        let text = res.text().await.anyerr()?;
+        assert_eq!(text, format!("dav {method} /calendars/me/ {body_len}"));
+    }
+
+    let res = client
+        .head(format!(
+            "http://{domain}:{}/calendars/me/",
+            gateway_addr.port()
+        ))
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.bytes().await.anyerr()?.is_empty());
+
+    Ok(())
+}
+
+/// With `ipv6_only`, nothing but loopback is bound on IPv4 and the gateway
+/// serves over IPv6. Skipped on hosts without IPv6.
+#[tokio::test]
+#[traced_test]
+async fn gateway_end_to_end_ipv6_only() -> Result<()> {
+    if TcpListener::bind("[::1]:0").await.is_err() {
+        return Ok(());
+    }
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let config = Config {
+        ip_family: IpFamily::Ipv6Only,
+        ..Default::default()
+    };
+    config.write(repo.path().join("config.yml")).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        let advertisment = Advertisment::new(data, None);
+        ProxyState::new(advertisment)
+    };
+    let codename = proxy_state.info.codename();
+
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let endpoint = build_endpoint(
+        SecretKey::generate(&mut rand::rng()),
+        &config,
+        Default::default(),
+    )
+    .await?;
+    for sockets in [
+        upstream.endpoint().bound_sockets(),
+        endpoint.bound_sockets(),
+    ] {
+        assert!(sockets.iter().any(|addr| addr.is_ipv6()), "{sockets:?}");
+        assert!(
+            sockets
+                .iter()
+                .all(|addr| addr.is_ipv6() || addr.ip().is_loopback()),
+            "{sockets:?}"
+        );
+    }
+
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("[::1]:0").await?;
+        let addr = listener.local_addr()?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(gateway::serve(endpoint, listener));
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv6Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let res = client
+        .get(format!("http://{domain}:{}/hello", gateway_addr.port()))
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.anyerr()?, "origin GET /hello");
+
+    Ok(())
+}
+
+/// The listen node opens a port for an allowed peer and carries connections
+/// back to a service next to that peer.
+#[tokio::test]
+#[traced_test]
+async fn reverse_forward_reaches_requester_service() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let listen_dir = tempfile::tempdir()?;
+    let listen = ListenNode::new(Repo::open_or_create(listen_dir.path()).await?).await?;
+    discovery.add(listen.endpoint());
+    let connect_dir = tempfile::tempdir()?;
+    let connect = ConnectNode::new(Repo::open_or_create(connect_dir.path()).await?).await?;
+    discovery.add(connect.endpoint());
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let target = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+    let bind_addr = (Ipv4Addr::LOCALHOST, 0).into();
+
+    let denied = connect
+        .request_reverse_forward(listen.endpoint_id(), bind_addr, target.clone())
+        .await;
+    assert!(denied.is_err());
+
+    listen
+        .set_reverse_forward_allowed(connect.endpoint_id(), true)
+        .await?;
+    let handle = connect
+        .request_reverse_forward(listen.endpoint_id(), bind_addr, target)
+        .await?;
+    let remote_addr = handle.remote_bound_addr();
+    assert_ne!(remote_addr.port(), 0);
+    assert_eq!(listen.reverse_forwards().len(), 1);
+
+    let res = reqwest::get(format!("http://{remote_addr}/hello"))
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.anyerr()?, "origin GET /hello");
+
+    handle.close();
+    Ok(())
+}
+
+/// A proxy removed from state without unpublishing its ticket, as after a
+/// crash mid-delete, has the ticket unpublished when the node starts again,
+/// and a stored proxy whose ticket is missing has it published again.
+#[tokio::test]
+#[traced_test]
+async fn stale_tickets_are_unpublished_at_startup() -> Result<()> {
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let proxy = |port| {
+        let data = TcpProxyData::from_host_port_str(&format!("127.0.0.1:{port}")).unwrap();
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let (kept, stale, unpublished) = (proxy(8001), proxy(8002), proxy(8003));
+
+    let listen = ListenNode::with_n0des_api_secret(repo.clone(), Some(api_secret.clone())).await?;
+    listen.set_proxy(kept.clone()).await?;
+    listen.set_proxy(stale.clone()).await?;
+    listen
+        .state()
+        .update(&repo, |state| state.remove_proxy(stale.id()))
+        .await?;
+    listen
+        .state()
+        .update(&repo, |state| state.set_proxy(unpublished.clone()))
+        .await?;
+    listen.endpoint().close().await;
+    drop(listen);
+
+    let listen = ListenNode::with_n0des_api_secret(repo, Some(api_secret.clone())).await?;
+    let client = build_n0des_client(listen.endpoint(), api_secret).await?;
+    let published = client
+        .fetch_tickets::<AdvertismentTicket>(0, 100)
+        .await
+        .anyerr()?
+        .into_iter()
+        .map(|t| t.name)
+        .collect::<HashSet<_>>();
+    let expected = [kept.id().to_string(), unpublished.id().to_string()];
+    assert_eq!(published, HashSet::from(expected));
+    Ok(())
+}
+
+/// A proxy with a pinned key is served and published under its own endpoint,
+/// which stays the same across restarts, until it is unpinned.
+#[tokio::test]
+#[traced_test]
+async fn pinned_key_survives_restarts() -> Result<()> {
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let proxy = {
+        let data = TcpProxyData::from_host_port_str("127.0.0.1:8001")?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let id = proxy.id().to_string();
+
+    let listen = ListenNode::with_n0des_api_secret(repo.clone(), Some(api_secret.clone())).await?;
+    listen.set_proxy(proxy).await?;
+    assert!(listen.set_key_pinned(&id, true).await?);
+    assert!(!listen.set_key_pinned("unknown", true).await?);
+    let pinned = listen.pinned_endpoint(&id).expect("pinned").id();
+    assert_ne!(pinned, listen.endpoint_id());
+    let client = build_n0des_client(listen.endpoint(), api_secret.clone()).await?;
+    let published = client
+        .fetch_ticket::<AdvertismentTicket>(id.clone())
+        .await
+        .anyerr()?
+        .expect("published");
+    assert_eq!(published.ticket.endpoint, pinned);
+    listen.pinned_endpoint(&id).expect("pinned").close().await;
+    listen.endpoint().close().await;
+    drop(listen);
+
+    let listen = ListenNode::with_n0des_api_secret(repo, Some(api_secret.clone())).await?;
+    assert_eq!(listen.pinned_endpoint(&id).expect("pinned").id(), pinned);
+    assert_eq!(
+        listen.proxy_by_id(&id).expect("stored").pinned_endpoint,
+        Some(pinned)
+    );
+
+    assert!(listen.set_key_pinned(&id, false).await?);
+    assert!(listen.pinned_endpoint(&id).is_none());
+    let published = client
+        .fetch_ticket::<AdvertismentTicket>(id)
+        .await
+        .anyerr()?
+        .expect("published");
+    assert_eq!(published.ticket.endpoint, listen.endpoint_id());
+    Ok(())
+}
+
+/// A paused proxy keeps its id and state, but its ticket is unpublished until
+/// it is resumed, also across restarts.
+#[tokio::test]
+#[traced_test]
+async fn paused_proxy_keeps_id_and_unpublishes_ticket() -> Result<()> {
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let data = TcpProxyData::from_host_port_str("127.0.0.1:8001")?;
+    let proxy = ProxyState::new(Advertisment::new(data, None));
+    let codename = proxy.info.codename();
+
+    let listen = ListenNode::with_n0des_api_secret(repo.clone(), Some(api_secret.clone())).await?;
+    let client = build_n0des_client(listen.endpoint(), api_secret.clone()).await?;
+    listen.set_proxy(proxy.clone()).await?;
+    assert_eq!(
+        published_tickets(&client).await?,
+        vec![proxy.id().to_string()]
+    );
+
+    assert!(listen.set_enabled(proxy.id(), false).await?);
+    let paused = listen.proxy_by_id(proxy.id()).unwrap();
+    assert!(!paused.enabled);
+    assert_eq!(paused.info.codename(), codename);
+    assert!(published_tickets(&client).await?.is_empty());
+    assert!(!listen.set_enabled("missing", false).await?);
+
+    listen.endpoint().close().await;
+    drop((client, listen));
+    let listen = ListenNode::with_n0des_api_secret(repo, Some(api_secret.clone())).await?;
+    let client = build_n0des_client(listen.endpoint(), api_secret).await?;
+    assert!(published_tickets(&client).await?.is_empty());
+
+    assert!(listen.set_enabled(proxy.id(), true).await?);
+    assert_eq!(
+        published_tickets(&client).await?,
+        vec![proxy.id().to_string()]
+    );
+    Ok(())
+}
+
+async fn published_tickets(client: &iroh_n0des::Client) -> Result<Vec<String>> {
+    let tickets = client
+        .fetch_tickets::<AdvertismentTicket>(0, 100)
+        .await
+        .anyerr()?;
+    Ok(tickets.into_iter().map(|t| t.name).collect())
+}
+
+/// A running agent publishes its tickets again with a newer publish time,
+/// so gateways can tell it from a crashed one.
+#[tokio::test]
+#[traced_test]
+async fn agent_refreshes_published_tickets() -> Result<()> {
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let config = Config {
+        ticket_refresh_secs: Some(1),
+        ..Default::default()
+    };
+    config.write(repo.path().join("config.yml")).await?;
+    let data = TcpProxyData::from_host_port_str("127.0.0.1:8001")?;
+    let proxy = ProxyState::new(Advertisment::new(data, None));
+
+    let listen = ListenNode::with_n0des_api_secret(repo, Some(api_secret.clone())).await?;
+    let client = build_n0des_client(listen.endpoint(), api_secret).await?;
+    listen.set_proxy(proxy.clone()).await?;
+    let first = ticket_published_at(&client, proxy.id()).await?;
+    let mut refreshed = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if ticket_published_at(&client, proxy.id()).await? > first {
+            refreshed = true;
+            break;
+        }
+    }
+    assert!(refreshed, "ticket was not published again");
+    Ok(())
+}
+
+async fn ticket_published_at(client: &iroh_n0des::Client, name: &str) -> Result<u64> {
+    let published = client
+        .fetch_ticket::<AdvertismentTicket>(name.to_string())
+        .await
+        .anyerr()?
+        .expect("published");
+    Ok(published.ticket.published_at.expect("stamped"))
+}
+
+/// With ticket lookups on, the gateway routes a codename host without the
+/// Envoy headers by the ticket its agent published, and answers 404 for a
+/// codename nobody published.
+#[tokio::test]
+#[traced_test]
+async fn gateway_routes_codenames_by_ticket() -> Result<()> {
+    let discovery = TestDiscovery::default();
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::with_n0des_api_secret(repo, Some(api_secret.clone())).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        tickets: TicketsConfig {
+            enabled: true,
+            domains: vec!["localhost".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let n0des = build_n0des_client(&endpoint, api_secret).await?;
+        let tickets = gateway::TicketClient::new(n0des, &config);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_tickets(endpoint, listener, &config, None, Some(tickets)).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(
+            &format!("{codename}.localhost"),
+            &[(Ipv4Addr::LOCALHOST, 0).into()],
+        )
+        .resolve_to_addrs("unknown.localhost", &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let port = gateway_addr.port();
+    let res = client
+        .get(format!("http://{codename}.localhost:{port}/hello"))
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.anyerr()?, "origin GET /hello");
+
+    let res = client
+        .get(format!("http://unknown.localhost:{port}/hello"))
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+/// With liveness checks on, a codename whose cached ticket names an endpoint
+/// that doesn't answer is resolved once more with the ticket the agent
+/// published since.
+#[tokio::test]
+#[traced_test]
+async fn gateway_retries_codenames_with_a_republished_ticket() -> Result<()> {
+    let discovery = TestDiscovery::default();
+    let (api_secret, _n0des) = n0des_local::bind_and_start().await?;
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+
+    let config = GatewayConfig {
+        tickets: TicketsConfig {
+            enabled: true,
+            domains: vec!["localhost".to_string()],
+            ..Default::default()
+        },
+        liveness: LivenessConfig {
+            enabled: true,
+            probe_timeout_ms: 500,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let n0des = build_n0des_client(&endpoint, api_secret.clone()).await?;
+        let tickets = gateway::TicketClient::new(n0des, &config);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_tickets(endpoint, listener, &config, None, Some(tickets)).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(
+            &format!("{codename}.localhost"),
+            &[(Ipv4Addr::LOCALHOST, 0).into()],
+        )
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let url = format!("http://{codename}.localhost:{}/hello", gateway_addr.port());
+
+    // The gateway caches a ticket for an endpoint that is gone, as after the
+    // agent's key changed.
+    let publisher = {
+        let endpoint = Endpoint::bind().await?;
+        build_n0des_client(&endpoint, api_secret.clone()).await?
+    };
+    let gone = SecretKey::generate(&mut rand::rng()).public();
+    publisher
+        .publish_ticket(
+            codename.clone(),
+            proxy_state.info.ticket(gone).published_now(),
+        )
+        .await
+        .anyerr()?;
+    let res = client.get(&url).send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let upstream = ListenNode::with_n0des_api_secret(repo, Some(api_secret)).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+    let res = client.get(&url).send().await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.text().await.anyerr()?, "origin GET /hello");
+    Ok(())
+}
+
+/// Changes another process makes to the repo reach a node that is watching
+/// it, without it writing them back.
+#[tokio::test]
+#[traced_test]
+async fn repo_watch_reloads_changes_from_other_processes() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let state = repo.load_state().await?;
+    let mut snapshots = repo.watch_state().await?;
+    assert!(snapshots.borrow().state.proxies.is_empty());
 
+    // A second handle on the same directory, like the CLI would open.
+    let other = Repo::open_or_create(temp_dir.path()).await?;
+    let other_state = other.load_state().await?;
+    let data = TcpProxyData::from_host_port_str("127.0.0.1:8080")?;
+    other_state
+        .update(&other, |state| {
+            state.set_proxy(ProxyState::new(Advertisment::new(data, None)))
+        })
+        .await?;
+    let prefs = Preferences {
+        telemetry: true,
+        ..Default::default()
+    };
+    other.write_preferences(&prefs).await?;
+
+    let snapshot = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            snapshots.changed().await.anyerr()?;
+            let snapshot = snapshots.borrow_and_update().clone();
+            if snapshot.preferences.telemetry && !snapshot.state.proxies.is_empty() {
+                return n0_error::Ok(snapshot);
+            }
+        }
+    })
+    .await
+    .anyerr()??;
+    assert!(state.get().proxies.is_empty());
+    assert!(state.reload(snapshot.state.clone()));
+    assert_eq!(state.get().proxies.len(), 1);
+    assert!(!state.reload(snapshot.state));
+    Ok(())
+}
+
+/// A rotated listen key is served next to the new one until the grace period
+/// ends, and deleted afterwards.
+#[tokio::test]
+#[traced_test]
+async fn rotated_listen_key_is_kept_for_grace_period() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let mut node = Node::new(repo.clone()).await?;
+    let old_id = node.listen.endpoint_id();
+    assert_eq!(node.listen.previous_endpoint_id(), None);
+
+    let rotation = node.rotate_key(Duration::from_secs(60)).await?;
+    assert_eq!(rotation.previous, old_id);
+    assert_ne!(node.listen.endpoint_id(), old_id);
+    assert_eq!(node.listen.previous_endpoint_id(), Some(old_id));
+    assert_eq!(repo.listen_key().await?.public(), node.listen.endpoint_id());
+
+    repo.rotate_listen_key(Duration::ZERO).await?;
+    assert!(repo.previous_listen_key().await?.is_none());
     Ok(())
 }
 
@@ -291,12 +1594,362 @@ async fn gateway_forward_h2c_handles_closed_origin_connections() -> Result<()> {
     Ok(())
 }
 
+/// With `upstream.http2`, requests reach the agent over HTTP/2, unless
+/// they ask for HTTP/1.1 or open a tunnel.
+#[tokio::test]
+#[traced_test]
+async fn gateway_negotiates_http2_upstreams() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn_body_echo("origin").await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        upstream: UpstreamConfig {
+            http2: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let metrics_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, Some(metrics_addr)).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .build()
+        .unwrap();
+    let url = format!("http://{domain}:{}/upload", gateway_addr.port());
+    for protocol in ["http2", "http1"] {
+        let res = client
+            .post(&url)
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+            .header("x-datum-upstream-protocol", protocol)
+            .body(vec![7u8; 100_000])
+            .send()
+            .await
+            .anyerr()?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.anyerr()?, "origin POST /upload 100000");
+    }
+
+    let mut stream = tokio::net::TcpStream::connect(gateway_addr).await?;
+    let connect_request = format!(
+        "CONNECT {origin_addr} HTTP/1.1\r\nHost: {origin_addr}\r\nx-iroh-endpoint-id: {}\r\n\r\n",
+        upstream.endpoint_id(),
+    );
+    stream.write_all(connect_request.as_bytes()).await?;
+    let mut response = vec![0u8; 1024];
+    let read = stream.read(&mut response).await?;
+    let response = String::from_utf8_lossy(&response[..read]);
+    assert!(
+        response.starts_with("HTTP/1.1 200"),
+        "unexpected CONNECT response: {response}"
+    );
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: origin\r\n\r\n")
+        .await?;
+    let mut body = vec![0u8; 1024];
+    let read = stream.read(&mut body).await?;
+    let body = String::from_utf8_lossy(&body[..read]);
+    assert!(
+        body.contains("origin GET /hello 0"),
+        "unexpected tunneled response: {body}"
+    );
+
+    let metrics = reqwest::get(format!("http://{metrics_addr}/metrics"))
+        .await
+        .anyerr()?
+        .text()
+        .await
+        .anyerr()?;
+    assert!(
+        metrics.contains("iroh_gateway_upstream_protocol_requests_total{protocol=\"http2\"} 1")
+    );
+    assert!(
+        metrics.contains("iroh_gateway_upstream_protocol_requests_total{protocol=\"http1\"} 2")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn gateway_decompresses_agent_responses() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+    let config = Config {
+        tunnel_compression: TunnelCompressionConfig {
+            enabled: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    config.write(repo.path().join("config.yml")).await?;
+
+    let text = "compress me\n".repeat(10_000);
+    let (origin_addr, _origin_task) = origin_server::spawn_text(text.clone()).await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        upstream: UpstreamConfig {
+            http2: true,
+            compression: true,
+        },
+        ..Default::default()
+    };
+    let metrics_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, Some(metrics_addr)).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .build()
+        .unwrap();
+    let res = client
+        .get(format!("http://{domain}:{}/", gateway_addr.port()))
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .send()
+        .await
+        .anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-datum-content-encoding").is_none());
+    assert!(res.headers().get("content-encoding").is_none());
+    assert_eq!(res.text().await.anyerr()?, text);
+
+    let metrics = reqwest::get(format!("http://{metrics_addr}/metrics"))
+        .await
+        .anyerr()?
+        .text()
+        .await
+        .anyerr()?;
+    assert!(metrics.contains("iroh_gateway_upstream_compressed_responses_total 1"));
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn gateway_proxies_grpc_over_http2_upstreams() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let (origin_addr, _origin_task) = origin_server::spawn_grpc_echo().await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        upstream: UpstreamConfig {
+            http2: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, None).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let stream = tokio::net::TcpStream::connect(gateway_addr).await?;
+    let (mut sender, conn) = http2::Builder::new(TokioExecutor::new())
+        .handshake(TokioIo::new(stream))
+        .await
+        .anyerr()?;
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            tracing::warn!("h2c client connection error: {err:#}");
+        }
+    });
+
+    let (tx, rx) = mpsc::channel(4);
+    let req = Request::builder()
+        .method("POST")
+        .uri("/echo.Echo/Stream")
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+        .header("x-datum-target-host", origin_addr.ip().to_string())
+        .header("x-datum-target-port", origin_addr.port().to_string())
+        .body(channel_body(rx))
+        .unwrap();
+    let res = sender.send_request(req).await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-origin-version"], "HTTP/2.0");
+
+    // Messages come back one at a time while the request is still open.
+    let mut body = res.into_body();
+    for message in ["first", "second"] {
+        tx.send(Ok(Frame::data(Bytes::from(message))))
+            .await
+            .unwrap();
+        let frame = body.frame().await.expect("frame").anyerr()?;
+        assert_eq!(frame.into_data().unwrap(), message);
+    }
+    drop(tx);
+    let trailers = body
+        .frame()
+        .await
+        .expect("trailers")
+        .anyerr()?
+        .into_trailers()
+        .unwrap();
+    assert_eq!(trailers["grpc-status"], "0");
+
+    Ok(())
+}
+
+#[tokio::test]
+#[traced_test]
+async fn gateway_compresses_responses_for_clients() -> Result<()> {
+    let discovery = TestDiscovery::default();
+
+    let temp_dir = tempfile::tempdir()?;
+    let repo = Repo::open_or_create(temp_dir.path()).await?;
+
+    let text = "a dashboard\n".repeat(10_000);
+    let (origin_addr, _origin_task) = origin_server::spawn_text(text.clone()).await?;
+    let proxy_state = {
+        let data = TcpProxyData::from_host_port_str(&origin_addr.to_string())?;
+        ProxyState::new(Advertisment::new(data, None))
+    };
+    let codename = proxy_state.info.codename();
+    let upstream = ListenNode::new(repo).await?;
+    discovery.add(upstream.endpoint());
+    upstream.set_proxy(proxy_state).await?;
+
+    let config = GatewayConfig {
+        upstream: UpstreamConfig {
+            http2: true,
+            ..Default::default()
+        },
+        response_compression: ResponseCompressionConfig {
+            enabled: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let metrics_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let (gateway_addr, _gateway_task) = {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let endpoint = Endpoint::bind().await?;
+        discovery.add(&endpoint);
+        let task = tokio::task::spawn(async move {
+            gateway::serve_with_config(endpoint, listener, &config, Some(metrics_addr)).await
+        });
+        (addr, AbortOnDropHandle::new(task))
+    };
+
+    let domain = format!("{codename}.localhost");
+    let client = reqwest::Client::builder()
+        .resolve_to_addrs(&domain, &[(Ipv4Addr::LOCALHOST, 0).into()])
+        .build()
+        .unwrap();
+    let get = |accept_encoding: &'static str| {
+        client
+            .get(format!("http://{domain}:{}/", gateway_addr.port()))
+            .header("x-datum-target-host", origin_addr.ip().to_string())
+            .header("x-datum-target-port", origin_addr.port().to_string())
+            .header("x-iroh-endpoint-id", upstream.endpoint_id().to_string())
+            .header("accept-encoding", accept_encoding)
+            .send()
+    };
+
+    let res = get("gzip").await.anyerr()?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-encoding"], "gzip");
+    assert_eq!(res.headers()["vary"], "accept-encoding");
+    let compressed = res.bytes().await.anyerr()?;
+    assert!(compressed.len() < text.len() / 10);
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(
+        &mut flate2::read::GzDecoder::new(&compressed[..]),
+        &mut decoded,
+    )?;
+    assert_eq!(decoded, text);
+
+    let res = get("identity").await.anyerr()?;
+    assert!(res.headers().get("content-encoding").is_none());
+    assert_eq!(res.text().await.anyerr()?, text);
+
+    let metrics = reqwest::get(format!("http://{metrics_addr}/metrics"))
+        .await
+        .anyerr()?
+        .text()
+        .await
+        .anyerr()?;
+    assert!(metrics.contains("iroh_gateway_compressed_responses_total{encoding=\"gzip\"} 1"));
+    assert!(metrics.contains("iroh_gateway_compressed_responses_total{encoding=\"br\"} 0"));
+
+    Ok(())
+}
+
 mod origin_server {
     use std::{convert::Infallible, net::SocketAddr, sync::Arc};
 
-    use http_body_util::Full;
-    use hyper::{Request, Response, body::Bytes, server::conn::http1, service::service_fn};
-    use hyper_util::rt::TokioIo;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{
+        HeaderMap, Request, Response,
+        body::{Bytes, Frame},
+        header::HeaderValue,
+        server::conn::{http1, http2},
+        service::service_fn,
+    };
+    use hyper_util::rt::{TokioExecutor, TokioIo};
     use n0_future::task::AbortOnDropHandle;
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
@@ -315,6 +1968,122 @@ mod origin_server {
         Ok((tcp_addr, AbortOnDropHandle::new(task)))
     }
 
+    /// Spawns an HTTP origin server that reads the whole request body and
+    /// answers "{label} {method} {path} {body length}", echoing a WebDAV
+    /// `Depth` header back as `x-depth`.
+    pub async fn spawn_body_echo(
+        label: &'static str,
+    ) -> n0_error::Result<(SocketAddr, AbortOnDropHandle<()>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = listener.local_addr()?;
+        debug!(%label, %tcp_addr, "spawned body echo origin server");
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let io = TokioIo::new(stream);
+                tokio::task::spawn(async move {
+                    let handler = move |req: Request<hyper::body::Incoming>| async move {
+                        let (parts, body) = req.into_parts();
+                        let len = match body.collect().await {
+                            Ok(body) => body.to_bytes().len(),
+                            Err(_) => 0,
+                        };
+                        let body = format!("{label} {} {} {len}", parts.method, parts.uri.path());
+                        let mut res = Response::new(Full::new(Bytes::from(body)));
+                        if let Some(depth) = parts.headers.get("depth") {
+                            res.headers_mut().insert("x-depth", depth.clone());
+                        }
+                        Ok::<_, Infallible>(res)
+                    };
+                    let _ = http1::Builder::new()
+                        .serve_connection(io, service_fn(handler))
+                        .await;
+                });
+            }
+        });
+        Ok((tcp_addr, AbortOnDropHandle::new(task)))
+    }
+
+    /// Spawns an HTTP origin server that answers every request with `text`
+    /// as `text/plain`.
+    pub async fn spawn_text(text: String) -> n0_error::Result<(SocketAddr, AbortOnDropHandle<()>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = listener.local_addr()?;
+        debug!(%tcp_addr, "spawned text origin server");
+        let text = Bytes::from(text);
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let io = TokioIo::new(stream);
+                let text = text.clone();
+                tokio::task::spawn(async move {
+                    let handler = move |_req: Request<hyper::body::Incoming>| {
+                        let text = text.clone();
+                        async move {
+                            let mut res = Response::new(Full::new(text));
+                            res.headers_mut()
+                                .insert("content-type", "text/plain".parse().unwrap());
+                            Ok::<_, Infallible>(res)
+                        }
+                    };
+                    let _ = http1::Builder::new()
+                        .serve_connection(io, service_fn(handler))
+                        .await;
+                });
+            }
+        });
+        Ok((tcp_addr, AbortOnDropHandle::new(task)))
+    }
+
+    /// Spawns an h2c origin server like a streaming gRPC service: every
+    /// data frame of a request is sent back as it arrives, followed by
+    /// `grpc-status: 0` in the trailers. The HTTP version the request came
+    /// in with is echoed as `x-origin-version`.
+    pub async fn spawn_grpc_echo() -> n0_error::Result<(SocketAddr, AbortOnDropHandle<()>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let tcp_addr = listener.local_addr()?;
+        debug!(%tcp_addr, "spawned gRPC echo origin server");
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let io = TokioIo::new(stream);
+                tokio::task::spawn(async move {
+                    let handler = move |req: Request<hyper::body::Incoming>| async move {
+                        let version = format!("{:?}", req.version());
+                        let mut body = req.into_body();
+                        let (tx, rx) = tokio::sync::mpsc::channel(4);
+                        tokio::spawn(async move {
+                            while let Some(Ok(frame)) = body.frame().await {
+                                if let Ok(data) = frame.into_data() {
+                                    tx.send(Ok::<_, Infallible>(Frame::data(data))).await.ok();
+                                }
+                            }
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                            tx.send(Ok(Frame::trailers(trailers))).await.ok();
+                        });
+                        let mut res = Response::new(super::channel_body(rx));
+                        res.headers_mut()
+                            .insert("content-type", HeaderValue::from_static("application/grpc"));
+                        res.headers_mut()
+                            .insert("x-origin-version", HeaderValue::from_str(&version).unwrap());
+                        Ok::<_, Infallible>(res)
+                    };
+                    let _ = http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(io, service_fn(handler))
+                        .await;
+                });
+            }
+        });
+        Ok((tcp_addr, AbortOnDropHandle::new(task)))
+    }
+
     /// Spawns a raw HTTP/1.1 origin server that always closes after each response.
     pub async fn spawn_closing(
         label: &'static str,
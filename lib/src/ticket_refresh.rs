@@ -0,0 +1,52 @@
+//! Keeping published tickets fresh.
+//!
+//! Tickets carry the time they were published. The agent publishes the
+//! ticket of every enabled tunnel again every
+//! [`ticket_refresh_secs`](crate::config::Config::ticket_refresh_secs), so a
+//! gateway that sees an old ticket knows the agent behind it stopped running
+//! and can answer 503 right away instead of dialing it. Tickets a health
+//! check took out of n0des stay out until the target is healthy again.
+
+use std::{sync::Arc, time::Duration};
+
+use iroh::EndpointId;
+use tracing::{debug, warn};
+
+use crate::{StateWrapper, health::HealthMonitor};
+
+/// How often tickets are published again if the config doesn't say.
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) async fn run(
+    state: StateWrapper,
+    n0des: Option<Arc<iroh_n0des::Client>>,
+    endpoint_id: EndpointId,
+    health: HealthMonitor,
+    interval: Duration,
+) {
+    let Some(n0des) = n0des else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = state.get_cloned();
+        for proxy in current.proxies.iter().filter(|p| p.enabled) {
+            let unpublished = proxy
+                .health_check
+                .as_ref()
+                .is_some_and(|check| check.unpublish_when_unhealthy)
+                && health.get(proxy.id()).is_some_and(|h| !h.healthy);
+            if unpublished {
+                continue;
+            }
+            let tunnel_id = proxy.id().to_string();
+            match n0des
+                .publish_ticket(tunnel_id.clone(), proxy.ticket(endpoint_id).published_now())
+                .await
+            {
+                Ok(_) => debug!(%tunnel_id, "refreshed ticket"),
+                Err(err) => warn!(%tunnel_id, "Failed to refresh ticket: {err:#}"),
+            }
+        }
+    }
+}
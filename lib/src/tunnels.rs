@@ -1,12 +1,20 @@
 use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kube::api::{DeleteParams, ListParams, Patch, PatchParams, PostParams};
 use kube::{Api, ResourceExt};
-use n0_error::{Result, StackResultExt, StdResultExt};
+use n0_error::{Result, StackResultExt, StdResultExt, stack_error};
+use n0_future::{BufferedStreamExt, StreamExt};
 use serde_json::json;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, warn};
 
+use crate::activity::TunnelActivity;
 use crate::datum_apis::connector::{
     Connector, ConnectorConnectionDetails, ConnectorConnectionDetailsPublicKey,
     ConnectorConnectionType, ConnectorSpec, PublicKeyConnectorAddress, PublicKeyDiscoveryMode,
@@ -17,22 +25,58 @@ use crate::datum_apis::connector_advertisement::{
 };
 use crate::datum_apis::http_proxy::{
     ConnectorReference, HTTP_PROXY_CONDITION_ACCEPTED, HTTP_PROXY_CONDITION_PROGRAMMED, HTTPProxy,
-    HTTPProxyRule, HTTPProxyRuleBackend, HTTPProxySpec,
+    HTTPProxyRule, HTTPProxyRuleBackend, HTTPProxySpec, HTTPRouteFilter,
 };
-use crate::datum_cloud::DatumCloudClient;
-use crate::{Advertisment, ListenNode, ProxyState, TcpProxyData};
+use crate::datum_apis::quota::{AllowanceBucket, BANDWIDTH_RESOURCE_TYPE, TUNNEL_RESOURCE_TYPE};
+use crate::datum_cloud::{AuthEvent, DatumCloudClient};
+use crate::events::EventKind;
+use crate::http_front::{HeaderRule, TunnelAuth};
+use crate::ip_filter::IpFilter;
+use crate::permissions::{self, PermissionCache, TunnelPermissions};
+use crate::{Advertisment, ListenNode, ProxyState, Repo, TcpProxyData, join_host_port};
 use gateway_api::apis::standard::httproutes::{
     HTTPRouteRulesMatchesPath, HTTPRouteRulesMatchesPathType,
 };
 
+mod audit;
+mod devices;
+pub mod offline;
+mod reconciler;
+mod sort;
+mod tags;
+
+use self::audit::{AUDIT_ANNOTATION, audit_annotation, proxy_audit};
+pub use self::audit::{AuditAction, AuditEntry, MAX_AUDIT_ENTRIES};
+pub use self::devices::{DeviceConnector, RevokeOwnConnector, STALE_AFTER};
+pub use self::offline::{
+    OfflineQueue, PendingMutation, PendingStatus, QueuedOffline, TunnelMutation,
+};
+pub use self::reconciler::TunnelReconciler;
+pub use self::sort::TunnelSort;
+use self::tags::{TAGS_ANNOTATION, proxy_tags, tags_annotation};
+pub use self::tags::{normalize_tags, parse_tags};
+
 const DEFAULT_PCP_NAMESPACE: &str = "default";
+/// How often queued changes are retried while the API is unreachable.
+const OFFLINE_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+/// How often the selected project's tunnels are checked for expiry.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How often scheduled tunnels are checked against their schedules.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the selected project's tunnels are checked against their
+/// transfer quotas.
+const QUOTA_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How many tunnels a bulk operation changes at once.
+const BULK_CONCURRENCY: usize = 4;
 const DEFAULT_CONNECTOR_CLASS_NAME: &str = "datum-connect";
 const CONNECTOR_SELECTOR_FIELD: &str = "status.connectionDetails.publicKey.id";
 const ADVERTISEMENT_CONNECTOR_FIELD: &str = "spec.connectorRef.name";
 const DISPLAY_NAME_ANNOTATION: &str = "app.kubernetes.io/name";
+/// When a tunnel is turned off by itself, as an RFC 3339 timestamp.
+const EXPIRES_AT_ANNOTATION: &str = "connect.datumapis.com/expires-at";
 
 /// Returns true if any rule in the HTTPProxy has a backend that references the given connector by name.
-fn proxy_uses_connector(proxy: &HTTPProxy, connector_name: &str) -> bool {
+pub(crate) fn proxy_uses_connector(proxy: &HTTPProxy, connector_name: &str) -> bool {
     proxy
         .spec
         .rules
@@ -56,12 +100,137 @@ pub struct TunnelSummary {
     pub enabled: bool,
     pub accepted: bool,
     pub programmed: bool,
+    /// Client addresses the gateway lets through to this tunnel.
+    pub ip_filter: IpFilter,
+    pub tags: Vec<String>,
+    /// When the tunnel is turned off by itself, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct TunnelDeleteOutcome {
     pub project_id: String,
     pub connector_deleted: bool,
+    /// What was removed, for [`TunnelService::restore_deleted`]. `None` if
+    /// the tunnel was already gone.
+    pub deleted: Option<DeletedTunnel>,
+}
+
+/// Per-tunnel results of a bulk operation. One tunnel failing doesn't stop
+/// the others.
+#[derive(Debug, Clone)]
+pub struct BulkOutcome<T> {
+    pub succeeded: Vec<T>,
+    pub failed: Vec<BulkFailure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkFailure {
+    pub tunnel_id: String,
+    pub error: String,
+}
+
+/// Where a limit comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaSource {
+    /// The project's quota in Datum Cloud.
+    #[default]
+    Datum,
+    /// `tunnel_limits` in this device's config.
+    Config,
+}
+
+impl std::fmt::Display for QuotaSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Datum => f.write_str("Datum Cloud"),
+            Self::Config => f.write_str("this device's config"),
+        }
+    }
+}
+
+/// Usage against one project limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub used: u64,
+    pub limit: u64,
+    pub source: QuotaSource,
+}
+
+impl QuotaUsage {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.used >= self.limit
+    }
+
+    /// Fraction of the limit in use, clamped to `0.0..=1.0`.
+    pub fn fraction(&self) -> f64 {
+        if self.limit == 0 {
+            return 1.0;
+        }
+        (self.used as f64 / self.limit as f64).min(1.0)
+    }
+}
+
+/// Project limits from Datum Cloud. A `None` limit is one the project does
+/// not have (or that the API did not report), and is treated as unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectQuotas {
+    pub tunnels: Option<QuotaUsage>,
+    /// Egress bandwidth for the current billing period, in bytes.
+    pub bandwidth: Option<QuotaUsage>,
+}
+
+impl ProjectQuotas {
+    /// Whether creating one more tunnel would exceed the project's limit.
+    pub fn blocks_create(&self) -> bool {
+        self.tunnels.is_some_and(|usage| usage.is_exhausted())
+    }
+
+    /// Apply a configured tunnel limit, if it is stricter than the one from
+    /// Datum Cloud. `count` is the number of tunnels in the project, used
+    /// when Datum Cloud doesn't report it.
+    fn limit_tunnels(&mut self, limit: u64, count: u64) {
+        if self.tunnels.is_some_and(|usage| usage.limit <= limit) {
+            return;
+        }
+        self.tunnels = Some(QuotaUsage {
+            used: self.tunnels.map_or(count, |usage| usage.used),
+            limit,
+            source: QuotaSource::Config,
+        });
+    }
+}
+
+#[stack_error(derive)]
+#[error(
+    "This project has reached its limit of {limit} tunnels. Delete a tunnel or raise the limit in {set_in}."
+)]
+pub struct TunnelLimitReached {
+    pub project_id: String,
+    pub limit: u64,
+    pub set_in: QuotaSource,
+}
+
+/// What deleting a tunnel takes down with it, for confirmation prompts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TunnelDeleteImpact {
+    pub hostnames: Vec<String>,
+    /// The tunnel is the last one on this device's connector, so the
+    /// connector is deleted too.
+    pub deletes_connector: bool,
+}
+
+/// The objects removed by a tunnel delete, captured so it can be undone.
+#[derive(Debug, Clone)]
+pub struct DeletedTunnel {
+    pub project_id: String,
+    pub summary: TunnelSummary,
+    proxy: HTTPProxy,
+    advertisement: Option<ConnectorAdvertisement>,
 }
 
 #[derive(Debug, Clone)]
@@ -69,20 +238,95 @@ pub struct TunnelService {
     datum: DatumCloudClient,
     listen: ListenNode,
     publish_tickets: bool,
+    permissions: PermissionCache,
+    reconciler: Arc<Mutex<Option<TunnelReconciler>>>,
+    offline: Option<OfflineQueue>,
 }
 
 // TODO(zachsmith1): Use connectors + ConnectorAdvertisements across all projects to
 // decide which local proxies should be allowed, instead of only syncing the
 // selected project's tunnel list.
-fn proxy_state_from_summary(
-    tunnel_id: &str,
-    endpoint: &str,
-    label: &str,
-    enabled: bool,
-) -> Result<ProxyState> {
-    let data = TcpProxyData::from_host_port_str(&strip_scheme(endpoint))?;
-    let info = Advertisment::with_id(tunnel_id.to_string(), data, Some(label.to_string()));
-    Ok(ProxyState { info, enabled })
+fn proxy_state_from_summary(tunnel: &TunnelSummary) -> Result<ProxyState> {
+    let data = TcpProxyData::from_host_port_str(&strip_scheme(&tunnel.endpoint))?;
+    let info = Advertisment::with_id(tunnel.id.clone(), data, Some(tunnel.label.clone()));
+    Ok(ProxyState {
+        info,
+        enabled: tunnel.enabled,
+        timeouts: Default::default(),
+        serve_dir: None,
+        unix_socket: None,
+        host_target: None,
+        http_front: None,
+        health_check: None,
+        tags: tunnel.tags.clone(),
+        expires_at: tunnel.expires_at,
+        schedule: None,
+        transfer_quota: None,
+        direct_only: false,
+        proxy_protocol: false,
+        pinned_endpoint: None,
+    })
+}
+
+/// Mirror listed tunnels into local proxy state, for agents that don't pick
+/// them up from published tickets.
+async fn store_proxy_states(listen: &ListenNode, tunnels: &[TunnelSummary]) {
+    for tunnel in tunnels {
+        if let Ok(proxy_state) = proxy_state_from_summary(tunnel)
+            && let Err(err) = listen.set_proxy_state(proxy_state).await
+        {
+            warn!(tunnel_id = %tunnel.id, "Failed to store proxy state: {err:#}");
+        }
+    }
+}
+
+fn tunnel_summary(proxy: &HTTPProxy, name: String, enabled: bool) -> TunnelSummary {
+    let label = proxy
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|labels| labels.get(DISPLAY_NAME_ANNOTATION))
+        .cloned()
+        .unwrap_or_else(|| name.clone());
+    let endpoint = normalize_endpoint(&proxy_backend_endpoint(proxy).unwrap_or_default());
+    let conditions = proxy
+        .status
+        .as_ref()
+        .and_then(|status| status.conditions.as_deref());
+    TunnelSummary {
+        id: name,
+        label,
+        endpoint,
+        hostnames: proxy_hostnames(proxy),
+        enabled,
+        accepted: condition_is_true(conditions, HTTP_PROXY_CONDITION_ACCEPTED),
+        programmed: condition_is_true(conditions, HTTP_PROXY_CONDITION_PROGRAMMED),
+        ip_filter: proxy_ip_filter(proxy),
+        tags: proxy_tags(proxy),
+        expires_at: proxy_expires_at(proxy),
+    }
+}
+
+fn proxy_expires_at(proxy: &HTTPProxy) -> Option<DateTime<Utc>> {
+    let value = proxy
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(EXPIRES_AT_ANNOTATION)?;
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// Metadata for recreating a deleted object: same name, labels and
+/// annotations, without server-assigned fields.
+fn restorable_metadata(meta: &ObjectMeta) -> ObjectMeta {
+    ObjectMeta {
+        name: meta.name.clone(),
+        labels: meta.labels.clone(),
+        annotations: meta.annotations.clone(),
+        ..Default::default()
+    }
 }
 
 fn condition_is_true(
@@ -103,22 +347,86 @@ impl TunnelService {
             datum,
             listen,
             publish_tickets: publish_tickets_enabled(),
+            permissions: PermissionCache::default(),
+            reconciler: Default::default(),
+            offline: None,
         }
     }
 
+    /// Queue changes in `repo` when they fail because the API can't be
+    /// reached, to replay them with [`Self::run_offline_queue`].
+    pub fn with_offline_queue(mut self, repo: Repo) -> Self {
+        self.offline = Some(OfflineQueue::new(repo));
+        self
+    }
+
+    pub fn offline_queue(&self) -> Option<&OfflineQueue> {
+        self.offline.as_ref()
+    }
+
     pub async fn list_active(&self) -> Result<Vec<TunnelSummary>> {
         let Some(selected) = self.datum.selected_context() else {
             return Ok(Vec::new());
         };
+        if let Some(tunnels) = self.synced_tunnels(&selected.project_id) {
+            return Ok(tunnels);
+        }
         self.list_project(&selected.project_id).await
     }
 
+    /// Follow the tunnels of the selected project as they change.
+    ///
+    /// Starts a [`TunnelReconciler`] for the project unless one is running;
+    /// while it is, [`Self::list_active`] answers from its cache.
+    pub fn watch_active(&self) -> Option<watch::Receiver<Option<Vec<TunnelSummary>>>> {
+        let selected = self.datum.selected_context()?;
+        let mut reconciler = self.reconciler.lock().expect("poisoned");
+        match reconciler.as_ref() {
+            Some(current) if current.project_id() == selected.project_id => Some(current.watch()),
+            _ => {
+                let started = TunnelReconciler::spawn(
+                    self.datum.clone(),
+                    self.listen.clone(),
+                    self.publish_tickets,
+                    selected.project_id,
+                );
+                let tunnels = started.watch();
+                *reconciler = Some(started);
+                Some(tunnels)
+            }
+        }
+    }
+
+    fn synced_tunnels(&self, project_id: &str) -> Option<Vec<TunnelSummary>> {
+        let reconciler = self.reconciler.lock().expect("poisoned");
+        reconciler
+            .as_ref()
+            .filter(|reconciler| reconciler.project_id() == project_id)
+            .and_then(|reconciler| reconciler.tunnels())
+    }
+
     pub async fn get_active(&self, tunnel_id: &str) -> Result<Option<TunnelSummary>> {
         let tunnels = self.list_active().await?;
         Ok(tunnels.into_iter().find(|tunnel| tunnel.id == tunnel_id))
     }
 
     pub async fn create_active(&self, label: &str, endpoint: &str) -> Result<TunnelSummary> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        let result = self
+            .create_project(&selected.project_id, label, endpoint)
+            .await;
+        let mutation = TunnelMutation::Create {
+            label: label.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        self.or_queue(&selected.project_id, mutation, result).await
+    }
+
+    /// Create a tunnel to a local address that only lives as long as this
+    /// process, so the change is never queued.
+    async fn create_local_active(&self, label: &str, endpoint: &str) -> Result<TunnelSummary> {
         let Some(selected) = self.datum.selected_context() else {
             n0_error::bail_any!("No project selected");
         };
@@ -126,6 +434,39 @@ impl TunnelService {
             .await
     }
 
+    /// Create a tunnel that serves the files in `dir` from this device.
+    pub async fn create_dir_active(&self, label: &str, dir: &Path) -> Result<TunnelSummary> {
+        let addr = self.listen.start_file_server(dir).await?;
+        let tunnel = match self.create_local_active(label, &addr.to_string()).await {
+            Ok(tunnel) => tunnel,
+            Err(err) => {
+                self.listen.stop_file_server(addr);
+                return Err(err);
+            }
+        };
+        self.listen
+            .set_serve_dir(&tunnel.id, dir.to_path_buf())
+            .await?;
+        Ok(tunnel)
+    }
+
+    /// Create a tunnel to the service listening on the unix socket at `path`.
+    #[cfg(unix)]
+    pub async fn create_socket_active(&self, label: &str, path: &Path) -> Result<TunnelSummary> {
+        let addr = self.listen.start_socket_bridge(path).await?;
+        let tunnel = match self.create_local_active(label, &addr.to_string()).await {
+            Ok(tunnel) => tunnel,
+            Err(err) => {
+                self.listen.stop_socket_bridge(addr);
+                return Err(err);
+            }
+        };
+        self.listen
+            .set_unix_socket(&tunnel.id, path.to_path_buf())
+            .await?;
+        Ok(tunnel)
+    }
+
     pub async fn update_active(
         &self,
         tunnel_id: &str,
@@ -135,10 +476,108 @@ impl TunnelService {
         let Some(selected) = self.datum.selected_context() else {
             n0_error::bail_any!("No project selected");
         };
-        self.update_project(&selected.project_id, tunnel_id, label, endpoint)
+        let result = self
+            .update_project(&selected.project_id, tunnel_id, label, endpoint)
+            .await;
+        let mutation = TunnelMutation::Update {
+            tunnel_id: tunnel_id.to_string(),
+            label: label.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+        self.or_queue(&selected.project_id, mutation, result).await
+    }
+
+    /// Replace the IP allow and deny lists the gateway enforces for a tunnel.
+    pub async fn set_ip_filter_active(
+        &self,
+        tunnel_id: &str,
+        ip_filter: IpFilter,
+    ) -> Result<TunnelSummary> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        self.set_ip_filter_project(&selected.project_id, tunnel_id, ip_filter)
+            .await
+    }
+
+    pub async fn set_tags_active(
+        &self,
+        tunnel_id: &str,
+        tags: Vec<String>,
+    ) -> Result<TunnelSummary> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        self.set_tags_project(&selected.project_id, tunnel_id, tags)
+            .await
+    }
+
+    pub async fn set_expiry_active(
+        &self,
+        tunnel_id: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<TunnelSummary> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        self.set_expiry_project(&selected.project_id, tunnel_id, expires_at)
+            .await
+    }
+
+    pub async fn audit_trail_active(&self, tunnel_id: &str) -> Result<Vec<AuditEntry>> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        self.audit_trail_project(&selected.project_id, tunnel_id)
             .await
     }
 
+    /// Set the header rules of a tunnel, publishing its new target if the
+    /// proxy in front of it was started or stopped. An empty list removes them.
+    pub async fn set_header_rules_active(
+        &self,
+        tunnel_id: &str,
+        rules: Vec<HeaderRule>,
+    ) -> Result<ProxyState> {
+        let before = self.local_proxy(tunnel_id)?;
+        let proxy = self.listen.set_header_rules(tunnel_id, rules).await?;
+        self.publish_front_change(&before, &proxy).await?;
+        Ok(proxy)
+    }
+
+    /// Require credentials for a tunnel, or stop requiring them with `None`.
+    pub async fn set_auth_active(
+        &self,
+        tunnel_id: &str,
+        auth: Option<TunnelAuth>,
+    ) -> Result<ProxyState> {
+        let before = self.local_proxy(tunnel_id)?;
+        let proxy = self.listen.set_tunnel_auth(tunnel_id, auth).await?;
+        self.publish_front_change(&before, &proxy).await?;
+        Ok(proxy)
+    }
+
+    /// Lifecycle and traffic milestones of a tunnel served by this device.
+    pub fn activity(&self, tunnel_id: &str) -> Option<TunnelActivity> {
+        self.listen.activity(tunnel_id)
+    }
+
+    fn local_proxy(&self, tunnel_id: &str) -> Result<ProxyState> {
+        self.listen
+            .proxy_by_id(tunnel_id)
+            .with_context(|| format!("No local proxy for tunnel {tunnel_id}"))
+    }
+
+    async fn publish_front_change(&self, before: &ProxyState, after: &ProxyState) -> Result<()> {
+        if after.info.service().address() == before.info.service().address() {
+            return Ok(());
+        }
+        let label = after.info.label.as_deref().unwrap_or(after.id());
+        self.update_active(after.id(), label, &after.info.service().address())
+            .await?;
+        Ok(())
+    }
+
     pub async fn set_enabled_active(
         &self,
         tunnel_id: &str,
@@ -147,18 +586,422 @@ impl TunnelService {
         let Some(selected) = self.datum.selected_context() else {
             n0_error::bail_any!("No project selected");
         };
-        self.set_enabled_project(&selected.project_id, tunnel_id, enabled)
-            .await
+        let result = self
+            .set_enabled_project(&selected.project_id, tunnel_id, enabled)
+            .await;
+        let mutation = TunnelMutation::SetEnabled {
+            tunnel_id: tunnel_id.to_string(),
+            enabled,
+        };
+        self.or_queue(&selected.project_id, mutation, result).await
     }
 
     pub async fn delete_active(&self, tunnel_id: &str) -> Result<TunnelDeleteOutcome> {
         let Some(selected) = self.datum.selected_context() else {
             n0_error::bail_any!("No project selected");
         };
-        self.delete_project(&selected.project_id, tunnel_id).await
+        let result = self.delete_project(&selected.project_id, tunnel_id).await;
+        let mutation = TunnelMutation::Delete {
+            tunnel_id: tunnel_id.to_string(),
+        };
+        self.or_queue(&selected.project_id, mutation, result).await
+    }
+
+    /// Turn on every tunnel in the selected project that is off.
+    pub async fn enable_all(&self) -> Result<BulkOutcome<TunnelSummary>> {
+        self.set_enabled_all(true).await
+    }
+
+    /// Turn off every tunnel in the selected project that is on.
+    pub async fn disable_all(&self) -> Result<BulkOutcome<TunnelSummary>> {
+        self.set_enabled_all(false).await
+    }
+
+    async fn set_enabled_all(&self, enabled: bool) -> Result<BulkOutcome<TunnelSummary>> {
+        let tunnel_ids = self
+            .list_active()
+            .await?
+            .into_iter()
+            .filter(|tunnel| tunnel.enabled != enabled)
+            .map(|tunnel| tunnel.id)
+            .collect();
+        Ok(self.set_enabled_many(tunnel_ids, enabled).await)
+    }
+
+    /// Turn tunnels in the selected project on or off, a few at a time.
+    pub async fn set_enabled_many(
+        &self,
+        tunnel_ids: Vec<String>,
+        enabled: bool,
+    ) -> BulkOutcome<TunnelSummary> {
+        bulk(tunnel_ids, |tunnel_id| async move {
+            self.set_enabled_active(&tunnel_id, enabled).await
+        })
+        .await
+    }
+
+    /// Delete tunnels in the selected project, a few at a time.
+    pub async fn delete_many(&self, tunnel_ids: Vec<String>) -> BulkOutcome<TunnelDeleteOutcome> {
+        bulk(tunnel_ids, |tunnel_id| async move {
+            self.delete_active(&tunnel_id).await
+        })
+        .await
+    }
+
+    /// Pass `result` through, unless it failed while the API is unreachable
+    /// and there is an offline queue: then queue `mutation` and fail with
+    /// [`QueuedOffline`].
+    async fn or_queue<T>(
+        &self,
+        project_id: &str,
+        mutation: TunnelMutation,
+        result: Result<T>,
+    ) -> Result<T> {
+        let err = match result {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let Some(queue) = &self.offline else {
+            return Err(err);
+        };
+        if self.datum.api_reachable().await {
+            return Err(err);
+        }
+        debug!(%project_id, "API unreachable, queueing tunnel change: {err:#}");
+        let synced = mutation.tunnel_id().and_then(|tunnel_id| {
+            self.synced_tunnels(project_id)?
+                .iter()
+                .find(|tunnel| tunnel.id == tunnel_id)
+                .map(offline::TunnelBase::of)
+        });
+        let id = queue.push(project_id, mutation, synced).await?;
+        Err(QueuedOffline { id }.into())
+    }
+
+    /// Apply queued changes in order. Stops at the first one that fails
+    /// because the API became unreachable again.
+    pub async fn replay_queued(&self) -> Result<()> {
+        let Some(queue) = &self.offline else {
+            return Ok(());
+        };
+        for entry in queue.list().await {
+            if entry.status != PendingStatus::Queued {
+                continue;
+            }
+            let project_id = &entry.project_id;
+            let current = match entry.mutation.tunnel_id() {
+                Some(tunnel_id) => self
+                    .list_project(project_id)
+                    .await?
+                    .into_iter()
+                    .find(|tunnel| tunnel.id == tunnel_id),
+                None => None,
+            };
+            if let Some(reason) = entry.conflict(current.as_ref()) {
+                warn!(%project_id, id = %entry.id, "Queued tunnel change conflicts: {reason}");
+                queue
+                    .set_status(&entry.id, PendingStatus::Conflict { reason })
+                    .await?;
+                continue;
+            }
+            let result = match &entry.mutation {
+                TunnelMutation::Create { label, endpoint } => self
+                    .create_project(project_id, label, endpoint)
+                    .await
+                    .map(drop),
+                TunnelMutation::Update {
+                    tunnel_id,
+                    label,
+                    endpoint,
+                } => self
+                    .update_project(project_id, tunnel_id, label, endpoint)
+                    .await
+                    .map(drop),
+                TunnelMutation::SetEnabled { tunnel_id, enabled } => self
+                    .set_enabled_project(project_id, tunnel_id, *enabled)
+                    .await
+                    .map(drop),
+                // Already gone, which is what the change asked for.
+                TunnelMutation::Delete { .. } if current.is_none() => Ok(()),
+                TunnelMutation::Delete { tunnel_id } => {
+                    self.delete_project(project_id, tunnel_id).await.map(drop)
+                }
+            };
+            match result {
+                Ok(()) => queue.discard(&entry.id).await?,
+                Err(err) if !self.datum.api_reachable().await => return Err(err),
+                Err(err) => {
+                    warn!(%project_id, id = %entry.id, "Queued tunnel change failed: {err:#}");
+                    let error = format!("{err:#}");
+                    queue
+                        .set_status(&entry.id, PendingStatus::Failed { error })
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replay queued changes whenever the API can be reached again. Runs
+    /// until dropped; returns right away without an offline queue.
+    pub async fn run_offline_queue(&self) {
+        let Some(queue) = &self.offline else {
+            return;
+        };
+        let mut pending = queue.watch().await;
+        loop {
+            let queued = pending
+                .borrow_and_update()
+                .iter()
+                .any(|entry| entry.status == PendingStatus::Queued);
+            if queued
+                && self.datum.api_reachable().await
+                && let Err(err) = self.replay_queued().await
+            {
+                debug!("Stopped replaying queued tunnel changes: {err:#}");
+            }
+            tokio::select! {
+                res = pending.changed() => {
+                    if res.is_err() {
+                        return;
+                    }
+                }
+                _ = tokio::time::sleep(OFFLINE_RETRY_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Forget the cached permissions whenever the user changes, so another
+    /// user's grants are never applied. Runs until dropped.
+    pub async fn run_auth_events(&self) {
+        let mut events = self.datum.auth_events();
+        loop {
+            match events.recv().await {
+                Ok(AuthEvent::Refreshed | AuthEvent::ProfileUpdated) => {}
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => self.permissions.clear(),
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// Turn off the selected project's tunnels once their expiry passes.
+    /// Runs until dropped.
+    pub async fn run_expiry(&self) {
+        loop {
+            tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+            if let Err(err) = self.expire_due().await {
+                debug!("Failed to expire tunnels: {err:#}");
+            }
+        }
+    }
+
+    async fn expire_due(&self) -> Result<()> {
+        let Some(selected) = self.datum.selected_context() else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        let due = self
+            .list_active()
+            .await?
+            .into_iter()
+            .filter(|tunnel| tunnel.enabled && tunnel.expires_at.is_some_and(|at| at <= now));
+        for tunnel in due {
+            debug!(tunnel_id = %tunnel.id, "disabling expired tunnel");
+            self.set_enabled_project(&selected.project_id, &tunnel.id, false)
+                .await?;
+            self.set_expiry_project(&selected.project_id, &tunnel.id, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Turn off the selected project's tunnels once they reach their
+    /// transfer quota, see [`crate::usage`]. Runs until dropped.
+    pub async fn run_quotas(&self) {
+        loop {
+            tokio::time::sleep(QUOTA_CHECK_INTERVAL).await;
+            if let Err(err) = self.disable_over_quota().await {
+                debug!("Failed to enforce transfer quotas: {err:#}");
+            }
+        }
+    }
+
+    async fn disable_over_quota(&self) -> Result<()> {
+        let Some(selected) = self.datum.selected_context() else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        for tunnel in self.list_active().await? {
+            let Some(quota) = self
+                .listen
+                .proxy_by_id(&tunnel.id)
+                .and_then(|proxy| proxy.transfer_quota)
+            else {
+                continue;
+            };
+            let usage = self.listen.transfer_usage(&tunnel.id);
+            if tunnel.enabled && quota.exceeded(&usage, now).is_some() {
+                debug!(tunnel_id = %tunnel.id, "disabling tunnel over its transfer quota");
+                self.set_enabled_project(&selected.project_id, &tunnel.id, false)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the selected project's scheduled tunnels on and off in Datum
+    /// Cloud as their schedules say. Like the agent's own schedule task, only
+    /// transitions are applied. Runs until dropped.
+    pub async fn run_schedules(&self) {
+        // What each schedule asked for when last applied.
+        let mut wanted = HashMap::new();
+        loop {
+            tokio::time::sleep(SCHEDULE_CHECK_INTERVAL).await;
+            if let Err(err) = self.apply_schedules(&mut wanted).await {
+                debug!("Failed to apply tunnel schedules: {err:#}");
+            }
+        }
+    }
+
+    async fn apply_schedules(&self, wanted: &mut HashMap<String, bool>) -> Result<()> {
+        let Some(selected) = self.datum.selected_context() else {
+            return Ok(());
+        };
+        let now = chrono::Local::now().naive_local();
+        for tunnel in self.list_active().await? {
+            let Some(schedule) = self
+                .listen
+                .proxy_by_id(&tunnel.id)
+                .and_then(|proxy| proxy.schedule)
+            else {
+                continue;
+            };
+            let active = schedule.is_active(now);
+            if wanted.get(&tunnel.id) == Some(&active) {
+                continue;
+            }
+            if tunnel.enabled != active {
+                debug!(tunnel_id = %tunnel.id, active, "applying tunnel schedule");
+                self.set_enabled_project(&selected.project_id, &tunnel.id, active)
+                    .await?;
+            }
+            wanted.insert(tunnel.id, active);
+        }
+        Ok(())
+    }
+
+    pub async fn quotas_active(&self) -> Result<ProjectQuotas> {
+        let Some(selected) = self.datum.selected_context() else {
+            return Ok(ProjectQuotas::default());
+        };
+        self.quotas_project(&selected.project_id).await
+    }
+
+    pub async fn quotas_project(&self, project_id: &str) -> Result<ProjectQuotas> {
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let buckets: Api<AllowanceBucket> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let list = buckets
+            .list(&ListParams::default())
+            .await
+            .std_context("Failed to list quota buckets")?;
+        let mut quotas = ProjectQuotas::default();
+        for bucket in list.items {
+            let Some(status) = bucket.status else {
+                continue;
+            };
+            let usage = QuotaUsage {
+                used: status.allocated.max(0) as u64,
+                limit: status.limit.max(0) as u64,
+                source: QuotaSource::Datum,
+            };
+            match bucket.spec.resource_type.as_str() {
+                TUNNEL_RESOURCE_TYPE => quotas.tunnels = Some(usage),
+                BANDWIDTH_RESOURCE_TYPE => quotas.bandwidth = Some(usage),
+                _ => {}
+            }
+        }
+        if let Some(limit) = self.listen.tunnel_limit(project_id).await {
+            let count = match quotas.tunnels {
+                Some(usage) => usage.used,
+                None => {
+                    let proxies: Api<HTTPProxy> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+                    proxies
+                        .list_metadata(&ListParams::default())
+                        .await
+                        .std_context("Failed to count tunnels")?
+                        .items
+                        .len() as u64
+                }
+            };
+            quotas.limit_tunnels(limit, count);
+        }
+        Ok(quotas)
+    }
+
+    /// What the user may do with tunnels in the selected project, if any.
+    pub async fn permissions_active(&self) -> Result<Option<TunnelPermissions>> {
+        let Some(selected) = self.datum.selected_context() else {
+            return Ok(None);
+        };
+        self.permissions_project(&selected.project_id)
+            .await
+            .map(Some)
+    }
+
+    pub async fn permissions_project(&self, project_id: &str) -> Result<TunnelPermissions> {
+        if let Some(cached) = self.permissions.get(project_id) {
+            return Ok(cached);
+        }
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let checked = permissions::check(pcp.client(), project_id).await?;
+        self.permissions.insert(checked.clone());
+        Ok(checked)
+    }
+
+    /// Fails with [`permissions::PermissionDenied`] if the user lacks any of
+    /// `needed` in the project.
+    async fn require(
+        &self,
+        project_id: &str,
+        needed: &[(permissions::Verb, permissions::Resource)],
+    ) -> Result<()> {
+        match self.permissions_project(project_id).await {
+            Ok(checked) => checked.require(needed)?,
+            // Access reviews are advisory; the API still enforces access.
+            Err(err) => debug!(%project_id, "Failed to check permissions: {err:#}"),
+        }
+        Ok(())
+    }
+
+    pub async fn delete_impact_active(&self, tunnel_id: &str) -> Result<TunnelDeleteImpact> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        self.delete_impact_project(&selected.project_id, tunnel_id)
+            .await
+    }
+
+    /// A tunnel with a proxy in front keeps targeting the proxy; `endpoint`
+    /// becomes the service behind the proxy instead.
+    async fn route_through_front(&self, tunnel_id: &str, endpoint: String) -> Result<String> {
+        let Some(proxy) = self.listen.proxy_by_id(tunnel_id) else {
+            return Ok(endpoint);
+        };
+        if proxy.http_front.is_none() {
+            return Ok(endpoint);
+        }
+        let target = normalize_endpoint(&proxy.info.service().address());
+        if endpoint == target {
+            return Ok(endpoint);
+        }
+        let upstream = TcpProxyData::from_host_port_str(&strip_scheme(&endpoint))?;
+        self.listen.set_front_upstream(tunnel_id, upstream).await?;
+        Ok(target)
     }
 
     pub async fn list_project(&self, project_id: &str) -> Result<Vec<TunnelSummary>> {
+        self.require(project_id, permissions::LIST).await?;
         let connector = self.find_connector(project_id).await?;
         let Some(connector) = connector else {
             return Ok(Vec::new());
@@ -194,52 +1037,11 @@ impl TunnelService {
             if !proxy_uses_connector(&proxy, &connector_name) {
                 continue;
             }
-            let label = proxy
-                .metadata
-                .annotations
-                .as_ref()
-                .and_then(|labels| labels.get(DISPLAY_NAME_ANNOTATION))
-                .cloned()
-                .unwrap_or_else(|| name.clone());
-            let endpoint = normalize_endpoint(&proxy_backend_endpoint(&proxy).unwrap_or_default());
-            let hostnames = proxy_hostnames(&proxy);
-            let accepted = condition_is_true(
-                proxy
-                    .status
-                    .as_ref()
-                    .and_then(|status| status.conditions.as_deref()),
-                HTTP_PROXY_CONDITION_ACCEPTED,
-            );
-            let programmed = condition_is_true(
-                proxy
-                    .status
-                    .as_ref()
-                    .and_then(|status| status.conditions.as_deref()),
-                HTTP_PROXY_CONDITION_PROGRAMMED,
-            );
             let enabled = enabled_by_name.contains_key(&name);
-            tunnels.push(TunnelSummary {
-                id: name,
-                label,
-                endpoint,
-                hostnames,
-                enabled,
-                accepted,
-                programmed,
-            });
+            tunnels.push(tunnel_summary(&proxy, name, enabled));
         }
         if !self.publish_tickets {
-            for tunnel in &tunnels {
-                if let Ok(proxy_state) = proxy_state_from_summary(
-                    &tunnel.id,
-                    &tunnel.endpoint,
-                    &tunnel.label,
-                    tunnel.enabled,
-                ) && let Err(err) = self.listen.set_proxy_state(proxy_state).await
-                {
-                    warn!(tunnel_id = %tunnel.id, "Failed to store proxy state: {err:#}");
-                }
-            }
+            store_proxy_states(&self.listen, &tunnels).await;
         }
 
         Ok(tunnels)
@@ -251,8 +1053,51 @@ impl TunnelService {
         label: &str,
         endpoint: &str,
     ) -> Result<TunnelSummary> {
-        let endpoint = normalize_endpoint(endpoint);
+        self.require(project_id, permissions::CREATE).await?;
+        let (endpoint, bridge) = self.bridge_host(normalize_endpoint(endpoint)).await?;
+        let result = self.create_project_at(project_id, label, endpoint).await;
+        let Some((addr, host_target)) = bridge else {
+            return result;
+        };
+        match result {
+            Ok(tunnel) => {
+                self.listen
+                    .set_host_target(&tunnel.id, Some(host_target))
+                    .await?;
+                Ok(tunnel)
+            }
+            Err(err) => {
+                self.listen.stop_host_bridge(addr);
+                Err(err)
+            }
+        }
+    }
+
+    async fn create_project_at(
+        &self,
+        project_id: &str,
+        label: &str,
+        endpoint: String,
+    ) -> Result<TunnelSummary> {
         let target = parse_target(&endpoint)?;
+        self.listen
+            .check_available(None, &target.address, target.port)?;
+        match self.quotas_project(project_id).await {
+            Ok(ProjectQuotas {
+                tunnels: Some(usage),
+                ..
+            }) if usage.is_exhausted() => {
+                return Err(TunnelLimitReached {
+                    project_id: project_id.to_string(),
+                    limit: usage.limit,
+                    set_in: usage.source,
+                }
+                .into());
+            }
+            Ok(_) => {}
+            // Quotas are advisory here; the API still enforces them on create.
+            Err(err) => debug!(%project_id, "Failed to load quotas: {err:#}"),
+        }
         let connector = self.ensure_connector(project_id).await?;
         let connector_name = connector.name_any();
 
@@ -278,10 +1123,14 @@ impl TunnelService {
             },
             spec: HTTPProxySpec {
                 hostnames: None,
-                rules: vec![proxy_rule(&endpoint, &connector_name)],
+                rules: vec![proxy_rule(&endpoint, &connector_name, &IpFilter::default())],
             },
             status: None,
         };
+        let trail = self.audit_trail(&proxy, AuditAction::Created);
+        proxy
+            .annotations_mut()
+            .insert(AUDIT_ANNOTATION.to_string(), trail);
         proxy = proxies
             .create(&PostParams::default(), &proxy)
             .await
@@ -335,18 +1184,8 @@ impl TunnelService {
             "created ConnectorAdvertisement"
         );
 
-        let proxy_state = proxy_state_from_summary(&proxy_name, &endpoint, label, true)?;
-        if self.publish_tickets {
-            debug!(%proxy_name, "publishing ticket for tunnel");
-            if let Err(err) = self.listen.set_proxy(proxy_state).await {
-                warn!(%proxy_name, "Failed to publish ticket: {err:#}");
-            }
-        } else if let Err(err) = self.listen.set_proxy_state(proxy_state).await {
-            warn!(%proxy_name, "Failed to store proxy state: {err:#}");
-        }
-
-        Ok(TunnelSummary {
-            id: proxy_name,
+        let summary = TunnelSummary {
+            id: proxy_name.clone(),
             label: label.to_string(),
             endpoint,
             hostnames: proxy_hostnames(&proxy),
@@ -365,7 +1204,28 @@ impl TunnelService {
                     .and_then(|status| status.conditions.as_deref()),
                 HTTP_PROXY_CONDITION_PROGRAMMED,
             ),
-        })
+            ip_filter: IpFilter::default(),
+            tags: Vec::new(),
+            expires_at: None,
+        };
+        let proxy_state = proxy_state_from_summary(&summary)?;
+        if self.publish_tickets {
+            debug!(%proxy_name, "publishing ticket for tunnel");
+            if let Err(err) = self.listen.set_proxy(proxy_state).await {
+                warn!(%proxy_name, "Failed to publish ticket: {err:#}");
+            }
+        } else if let Err(err) = self.listen.set_proxy_state(proxy_state).await {
+            warn!(%proxy_name, "Failed to store proxy state: {err:#}");
+        }
+        self.listen.events().record_by(
+            EventKind::TunnelCreated {
+                tunnel_id: proxy_name,
+                label: label.to_string(),
+            },
+            self.actor(),
+        );
+
+        Ok(summary)
     }
 
     pub async fn update_project(
@@ -375,8 +1235,56 @@ impl TunnelService {
         label: &str,
         endpoint: &str,
     ) -> Result<TunnelSummary> {
+        self.require(project_id, permissions::UPDATE).await?;
         let endpoint = normalize_endpoint(endpoint);
+        let previous = self.listen.proxy_by_id(tunnel_id);
+        let previous_bridge = previous.as_ref().and_then(|proxy| {
+            let target = proxy.host_target.as_ref()?;
+            let addr = proxy.info.service().address().parse::<SocketAddr>().ok()?;
+            Some((addr, target.clone()))
+        });
+        // Keep relaying through the same bridge when the host didn't change.
+        let kept = previous_bridge
+            .as_ref()
+            .filter(|(_, target)| host_target(&endpoint).as_ref() == Some(target))
+            .map(|(addr, _)| *addr);
+        let (endpoint, bridge) = match kept {
+            Some(addr) => (retarget(&endpoint, addr), None),
+            None => self.bridge_host(endpoint).await?,
+        };
+        let tunnel = match self
+            .update_project_at(project_id, tunnel_id, label, endpoint)
+            .await
+        {
+            Ok(tunnel) => tunnel,
+            Err(err) => {
+                if let Some((addr, _)) = bridge {
+                    self.listen.stop_host_bridge(addr);
+                }
+                return Err(err);
+            }
+        };
+        if kept.is_none() {
+            if let Some((addr, _)) = previous_bridge {
+                self.listen.stop_host_bridge(addr);
+            }
+            let host_target = bridge.map(|(_, target)| target);
+            self.listen.set_host_target(tunnel_id, host_target).await?;
+        }
+        Ok(tunnel)
+    }
+
+    async fn update_project_at(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+        label: &str,
+        endpoint: String,
+    ) -> Result<TunnelSummary> {
+        let endpoint = self.route_through_front(tunnel_id, endpoint).await?;
         let target = parse_target(&endpoint)?;
+        self.listen
+            .check_available(Some(tunnel_id), &target.address, target.port)?;
         let connector = self.ensure_connector(project_id).await?;
         let connector_name = connector.name_any();
 
@@ -390,16 +1298,18 @@ impl TunnelService {
             .await
             .std_context("Failed to fetch HTTPProxy")?;
         let hostnames = existing.spec.hostnames.clone().unwrap_or_default();
+        let ip_filter = proxy_ip_filter(&existing);
 
         let patch = json!({
             "metadata": {
                 "annotations": {
                     DISPLAY_NAME_ANNOTATION: label,
+                    AUDIT_ANNOTATION: self.audit_trail(&existing, AuditAction::Updated),
                 }
             },
             "spec": {
                 "hostnames": hostnames,
-                "rules": [proxy_rule(&endpoint, &connector_name)],
+                "rules": [proxy_rule(&endpoint, &connector_name, &ip_filter)],
             }
         });
         proxies
@@ -444,29 +1354,152 @@ impl TunnelService {
                     .and_then(|status| status.conditions.as_deref()),
                 HTTP_PROXY_CONDITION_PROGRAMMED,
             ),
+            ip_filter,
+            tags: proxy_tags(&existing),
+            expires_at: proxy_expires_at(&existing),
         };
 
         if !self.publish_tickets
-            && let Ok(proxy_state) = proxy_state_from_summary(
-                &summary.id,
-                &summary.endpoint,
-                &summary.label,
-                summary.enabled,
-            )
+            && let Ok(proxy_state) = proxy_state_from_summary(&summary)
             && let Err(err) = self.listen.set_proxy_state(proxy_state).await
         {
             warn!(tunnel_id = %summary.id, "Failed to store proxy state: {err:#}");
         }
+        self.record_updated(tunnel_id);
 
         Ok(summary)
     }
 
+    pub async fn set_ip_filter_project(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+        ip_filter: IpFilter,
+    ) -> Result<TunnelSummary> {
+        self.require(project_id, permissions::UPDATE).await?;
+        let connector = self.ensure_connector(project_id).await?;
+        let connector_name = connector.name_any();
+
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let proxies: Api<HTTPProxy> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let existing = proxies
+            .get(tunnel_id)
+            .await
+            .std_context("Failed to fetch HTTPProxy")?;
+        let endpoint = proxy_backend_endpoint(&existing)
+            .with_context(|| format!("Tunnel {tunnel_id} has no backend"))?;
+        let patch = json!({
+            "metadata": {
+                "annotations": {
+                    AUDIT_ANNOTATION: self.audit_trail(&existing, AuditAction::Updated),
+                },
+            },
+            "spec": {
+                "rules": [proxy_rule(&endpoint, &connector_name, &ip_filter)],
+            }
+        });
+        let proxy = proxies
+            .patch(tunnel_id, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .std_context("Failed to update HTTPProxy")?;
+        debug!(%project_id, %tunnel_id, ?ip_filter, "updated IP filter");
+        self.record_updated(tunnel_id);
+
+        let enabled = ads
+            .get_opt(tunnel_id)
+            .await
+            .std_context("Failed to load ConnectorAdvertisement")?
+            .is_some();
+        Ok(tunnel_summary(&proxy, tunnel_id.to_string(), enabled))
+    }
+
+    /// Replace the tags of a tunnel. An empty list removes them.
+    pub async fn set_tags_project(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+        tags: Vec<String>,
+    ) -> Result<TunnelSummary> {
+        self.require(project_id, permissions::UPDATE).await?;
+        let tags = normalize_tags(tags);
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let proxies: Api<HTTPProxy> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let patch = json!({
+            "metadata": {
+                "annotations": { TAGS_ANNOTATION: tags_annotation(&tags) },
+            }
+        });
+        let proxy = proxies
+            .patch(tunnel_id, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .std_context("Failed to update HTTPProxy")?;
+        debug!(%project_id, %tunnel_id, ?tags, "updated tags");
+        self.audit(&proxies, &proxy, AuditAction::Updated).await;
+        self.record_updated(tunnel_id);
+        if let Err(err) = self.listen.set_tags(tunnel_id, tags).await {
+            warn!(%tunnel_id, "Failed to store tags: {err:#}");
+        }
+
+        let enabled = ads
+            .get_opt(tunnel_id)
+            .await
+            .std_context("Failed to load ConnectorAdvertisement")?
+            .is_some();
+        Ok(tunnel_summary(&proxy, tunnel_id.to_string(), enabled))
+    }
+
+    /// Set when a tunnel is turned off by itself, or never with `None`.
+    pub async fn set_expiry_project(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<TunnelSummary> {
+        self.require(project_id, permissions::UPDATE).await?;
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let proxies: Api<HTTPProxy> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let patch = json!({
+            "metadata": {
+                "annotations": {
+                    EXPIRES_AT_ANNOTATION: expires_at.map(|at| at.to_rfc3339()),
+                },
+            }
+        });
+        let proxy = proxies
+            .patch(tunnel_id, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .std_context("Failed to update HTTPProxy")?;
+        debug!(%project_id, %tunnel_id, ?expires_at, "updated expiry");
+        self.audit(&proxies, &proxy, AuditAction::Updated).await;
+        self.record_updated(tunnel_id);
+        if let Err(err) = self.listen.set_expiry(tunnel_id, expires_at).await {
+            warn!(%tunnel_id, "Failed to store expiry: {err:#}");
+        }
+
+        let enabled = ads
+            .get_opt(tunnel_id)
+            .await
+            .std_context("Failed to load ConnectorAdvertisement")?
+            .is_some();
+        Ok(tunnel_summary(&proxy, tunnel_id.to_string(), enabled))
+    }
+
     pub async fn set_enabled_project(
         &self,
         project_id: &str,
         tunnel_id: &str,
         enabled: bool,
     ) -> Result<TunnelSummary> {
+        self.require(project_id, permissions::TOGGLE).await?;
         let connector = self.ensure_connector(project_id).await?;
         let connector_name = connector.name_any();
 
@@ -547,19 +1580,28 @@ impl TunnelService {
                     .and_then(|status| status.conditions.as_deref()),
                 HTTP_PROXY_CONDITION_PROGRAMMED,
             ),
+            ip_filter: proxy_ip_filter(&proxy),
+            tags: proxy_tags(&proxy),
+            expires_at: proxy_expires_at(&proxy),
         };
 
         if !self.publish_tickets
-            && let Ok(proxy_state) = proxy_state_from_summary(
-                &summary.id,
-                &summary.endpoint,
-                &summary.label,
-                summary.enabled,
-            )
+            && let Ok(proxy_state) = proxy_state_from_summary(&summary)
             && let Err(err) = self.listen.set_proxy_state(proxy_state).await
         {
             warn!(tunnel_id = %summary.id, "Failed to store proxy state: {err:#}");
         }
+        let tunnel_id = summary.id.clone();
+        let (action, event) = if enabled {
+            (AuditAction::Enabled, EventKind::TunnelEnabled { tunnel_id })
+        } else {
+            (
+                AuditAction::Disabled,
+                EventKind::TunnelDisabled { tunnel_id },
+            )
+        };
+        self.audit(&proxies, &proxy, action).await;
+        self.listen.events().record_by(event, self.actor());
 
         Ok(summary)
     }
@@ -569,11 +1611,13 @@ impl TunnelService {
         project_id: &str,
         tunnel_id: &str,
     ) -> Result<TunnelDeleteOutcome> {
+        self.require(project_id, permissions::DELETE).await?;
         let connector = self.find_connector(project_id).await?;
         let Some(connector) = connector else {
             return Ok(TunnelDeleteOutcome {
                 project_id: project_id.to_string(),
                 connector_deleted: false,
+                deleted: None,
             });
         };
         let connector_name = connector.name_any();
@@ -585,28 +1629,32 @@ impl TunnelService {
             Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
         let connectors: Api<Connector> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
 
-        if proxies
+        let proxy = proxies
             .get_opt(tunnel_id)
             .await
-            .std_context("Failed to load HTTPProxy")?
-            .is_some()
-        {
+            .std_context("Failed to load HTTPProxy")?;
+        if proxy.is_some() {
             proxies
                 .delete(tunnel_id, &DeleteParams::default())
                 .await
                 .std_context("Failed to delete HTTPProxy")?;
         }
 
-        if ads
+        let advertisement = ads
             .get_opt(tunnel_id)
             .await
-            .std_context("Failed to load ConnectorAdvertisement")?
-            .is_some()
-        {
+            .std_context("Failed to load ConnectorAdvertisement")?;
+        if advertisement.is_some() {
             ads.delete(tunnel_id, &DeleteParams::default())
                 .await
                 .std_context("Failed to delete ConnectorAdvertisement")?;
         }
+        let deleted = proxy.map(|proxy| DeletedTunnel {
+            project_id: project_id.to_string(),
+            summary: tunnel_summary(&proxy, tunnel_id.to_string(), advertisement.is_some()),
+            proxy,
+            advertisement,
+        });
 
         if self.publish_tickets {
             debug!(%tunnel_id, "unpublishing ticket for tunnel");
@@ -655,12 +1703,193 @@ impl TunnelService {
             }
         }
 
+        self.listen.events().record_by(
+            EventKind::TunnelDeleted {
+                tunnel_id: tunnel_id.to_string(),
+            },
+            self.actor(),
+        );
+
         Ok(TunnelDeleteOutcome {
             project_id: project_id.to_string(),
             connector_deleted,
+            deleted,
         })
     }
 
+    pub async fn delete_impact_project(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+    ) -> Result<TunnelDeleteImpact> {
+        let Some(connector) = self.find_connector(project_id).await? else {
+            return Ok(TunnelDeleteImpact::default());
+        };
+        let connector_name = connector.name_any();
+
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let proxies: Api<HTTPProxy> = Api::namespaced(pcp.client(), DEFAULT_PCP_NAMESPACE);
+        let list = proxies
+            .list(&ListParams::default())
+            .await
+            .std_context("Failed to list HTTPProxy objects")?;
+
+        let mut impact = TunnelDeleteImpact {
+            hostnames: Vec::new(),
+            deletes_connector: true,
+        };
+        for proxy in list.items {
+            if !proxy_uses_connector(&proxy, &connector_name) {
+                continue;
+            }
+            if proxy.metadata.name.as_deref() == Some(tunnel_id) {
+                impact.hostnames = proxy_hostnames(&proxy);
+            } else {
+                impact.deletes_connector = false;
+            }
+        }
+        Ok(impact)
+    }
+
+    /// Recreate a deleted tunnel under its original name.
+    ///
+    /// If the connector was deleted along with it, a new one is created and
+    /// the restored objects point at it.
+    pub async fn restore_deleted(&self, deleted: &DeletedTunnel) -> Result<TunnelSummary> {
+        let project_id = &deleted.project_id;
+        self.require(project_id, permissions::CREATE).await?;
+        let connector = self.ensure_connector(project_id).await?;
+        let connector_name = connector.name_any();
+        let tunnel_id = deleted.summary.id.clone();
+
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let proxies: Api<HTTPProxy> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let mut proxy = deleted.proxy.clone();
+        proxy.metadata = restorable_metadata(&proxy.metadata);
+        proxy.status = None;
+        let trail = self.audit_trail(&proxy, AuditAction::Restored);
+        proxy
+            .annotations_mut()
+            .insert(AUDIT_ANNOTATION.to_string(), trail);
+        for backend in proxy
+            .spec
+            .rules
+            .iter_mut()
+            .flat_map(|rule| rule.backends.iter_mut().flatten())
+        {
+            if let Some(connector) = backend.connector.as_mut() {
+                connector.name = connector_name.clone();
+            }
+        }
+        let proxy = proxies
+            .create(&PostParams::default(), &proxy)
+            .await
+            .std_context("Failed to recreate HTTPProxy")?;
+        debug!(%project_id, proxy = %tunnel_id, "restored HTTPProxy");
+
+        let enabled = deleted.advertisement.is_some();
+        if let Some(ad) = &deleted.advertisement {
+            let mut ad = ad.clone();
+            ad.metadata = restorable_metadata(&ad.metadata);
+            ad.status = None;
+            ad.spec.connector_ref.name = connector_name.clone();
+            ads.create(&PostParams::default(), &ad)
+                .await
+                .std_context("Failed to recreate ConnectorAdvertisement")?;
+            debug!(%project_id, proxy = %tunnel_id, "restored ConnectorAdvertisement");
+        }
+
+        let summary = tunnel_summary(&proxy, tunnel_id.clone(), enabled);
+        let proxy_state = proxy_state_from_summary(&summary)?;
+        if self.publish_tickets {
+            if let Err(err) = self.listen.set_proxy(proxy_state).await {
+                warn!(%tunnel_id, "Failed to publish ticket: {err:#}");
+            }
+        } else if let Err(err) = self.listen.set_proxy_state(proxy_state).await {
+            warn!(%tunnel_id, "Failed to store proxy state: {err:#}");
+        }
+        self.listen.events().record_by(
+            EventKind::TunnelCreated {
+                tunnel_id,
+                label: summary.label.clone(),
+            },
+            self.actor(),
+        );
+
+        Ok(summary)
+    }
+
+    /// The changes made to a tunnel, oldest first.
+    pub async fn audit_trail_project(
+        &self,
+        project_id: &str,
+        tunnel_id: &str,
+    ) -> Result<Vec<AuditEntry>> {
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let proxies: Api<HTTPProxy> = Api::namespaced(pcp.client(), DEFAULT_PCP_NAMESPACE);
+        let proxy = proxies
+            .get(tunnel_id)
+            .await
+            .std_context("Failed to fetch HTTPProxy")?;
+        Ok(proxy_audit(&proxy))
+    }
+
+    /// Email of the signed-in user, for the audit trail and event log.
+    fn actor(&self) -> Option<String> {
+        let auth = self.datum.auth_state();
+        auth.get().ok().map(|auth| auth.profile.email.clone())
+    }
+
+    /// `proxy`'s audit trail annotation with `action` by the signed-in user
+    /// appended.
+    fn audit_trail(&self, proxy: &HTTPProxy, action: AuditAction) -> String {
+        let entry = AuditEntry {
+            at: Utc::now(),
+            by: self.actor().unwrap_or_else(|| "unknown".to_string()),
+            action,
+        };
+        audit_annotation(proxy_audit(proxy), entry)
+    }
+
+    /// Append a change to a tunnel's audit trail. Failures are only logged;
+    /// the change itself was made.
+    async fn audit(&self, proxies: &Api<HTTPProxy>, proxy: &HTTPProxy, action: AuditAction) {
+        let tunnel_id = proxy.name_any();
+        let patch = json!({
+            "metadata": {
+                "annotations": { AUDIT_ANNOTATION: self.audit_trail(proxy, action) },
+            }
+        });
+        if let Err(err) = proxies
+            .patch(&tunnel_id, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+        {
+            warn!(%tunnel_id, "Failed to record change in audit trail: {err:#}");
+        }
+    }
+
+    fn record_updated(&self, tunnel_id: &str) {
+        self.listen.events().record_by(
+            EventKind::TunnelUpdated {
+                tunnel_id: tunnel_id.to_string(),
+            },
+            self.actor(),
+        );
+    }
+
+    /// Point the selected project's connector at this device's endpoint id,
+    /// e.g. after a [key rotation](crate::key_rotation). `false` if the
+    /// project has no connector for this device.
+    pub async fn update_connector_active(&self) -> Result<bool> {
+        let Some(selected) = self.datum.selected_context() else {
+            n0_error::bail_any!("No project selected");
+        };
+        Ok(self.find_connector(&selected.project_id).await?.is_some())
+    }
+
     async fn find_connector(&self, project_id: &str) -> Result<Option<Connector>> {
         let pcp = self.datum.project_control_plane_client(project_id).await?;
         let client = pcp.client();
@@ -672,21 +1901,39 @@ impl TunnelService {
             .await
             .std_context("Failed to list connectors")?;
         if list.items.is_empty() {
-            let fallback = connectors
-                .list(&ListParams::default())
-                .await
-                .std_context("Failed to list connectors for fallback")?;
-            if fallback.items.len() != 1 {
-                if !fallback.items.is_empty() {
+            // After a key rotation the connector still carries the previous
+            // id; it is patched to the current one below.
+            let rotated = match self.listen.previous_endpoint_id() {
+                Some(previous) => {
+                    let selector = format!("{CONNECTOR_SELECTOR_FIELD}={previous}");
+                    connectors
+                        .list(&ListParams::default().fields(&selector))
+                        .await
+                        .std_context("Failed to list connectors of the previous key")?
+                        .items
+                }
+                None => Vec::new(),
+            };
+            let fallback = if rotated.is_empty() {
+                connectors
+                    .list(&ListParams::default())
+                    .await
+                    .std_context("Failed to list connectors for fallback")?
+                    .items
+            } else {
+                rotated
+            };
+            if fallback.len() != 1 {
+                if !fallback.is_empty() {
                     warn!(
                         %project_id,
-                        count = fallback.items.len(),
+                        count = fallback.len(),
                         "Multiple connectors found without status match"
                     );
                 }
                 return Ok(None);
             }
-            let mut connector = fallback.items.into_iter().next().unwrap();
+            let mut connector = fallback.into_iter().next().unwrap();
             let needs_patch = connector
                 .status
                 .as_ref()
@@ -729,6 +1976,33 @@ impl TunnelService {
         Ok(list.items.into_iter().next())
     }
 
+    /// Point `endpoint` at a new host bridge when its host is a name the
+    /// connector couldn't resolve with the system resolver, like one from the
+    /// `hosts` config. The name is resolved per connection, see
+    /// [`crate::host_bridge`].
+    ///
+    /// Returns the endpoint to use and, if bridged, the bridge's address and
+    /// the `host:port` it relays to.
+    async fn bridge_host(
+        &self,
+        endpoint: String,
+    ) -> Result<(String, Option<(SocketAddr, String)>)> {
+        let Ok(target) = parse_target(&endpoint) else {
+            return Ok((endpoint, None));
+        };
+        if !self.listen.resolves_target(&target.address).await {
+            return Ok((endpoint, None));
+        }
+        let addr = self
+            .listen
+            .start_host_bridge(&target.address, target.port)
+            .await?;
+        let bridged = retarget(&endpoint, addr);
+        debug!(%endpoint, %bridged, "relaying to host");
+        let host_target = join_host_port(&target.address, target.port);
+        Ok((bridged, Some((addr, host_target))))
+    }
+
     async fn ensure_connector(&self, project_id: &str) -> Result<Connector> {
         if let Some(connector) = self.find_connector(project_id).await? {
             return Ok(connector);
@@ -782,34 +2056,23 @@ struct ParsedTarget {
     port: u16,
 }
 
+/// Parses an endpoint URL or `host:port`. IPv6 hosts are returned without
+/// brackets; URLs without a port get their scheme's default port.
 fn parse_target(target: &str) -> Result<ParsedTarget> {
     let target = target.trim();
-    if let Ok(url) = url::Url::parse(target) {
+    if target.contains("://")
+        && let Ok(url) = url::Url::parse(target)
+    {
         let host = url.host_str().context("missing host")?;
-        let port = url.port().context("missing port")?;
+        let port = url.port_or_known_default().context("missing port")?;
         return Ok(ParsedTarget {
-            address: host.to_string(),
+            address: crate::dial::unbracket(host).to_string(),
             port,
         });
     }
 
-    let (host, port_str) = if target.starts_with('[') {
-        let end = target.find(']').context("invalid IPv6 address")?;
-        let host = &target[1..end];
-        let port = target
-            .get(end + 1..)
-            .and_then(|rest| rest.strip_prefix(':'))
-            .context("missing port")?;
-        (host, port)
-    } else {
-        let (host, port) = target.rsplit_once(':').context("missing port")?;
-        (host, port)
-    };
-    let port: u16 = port_str.parse().std_context("invalid port")?;
-    Ok(ParsedTarget {
-        address: host.to_string(),
-        port,
-    })
+    let (address, port) = TcpProxyData::parse_host_port(target)?;
+    Ok(ParsedTarget { address, port })
 }
 
 fn build_connection_details(listen: &ListenNode) -> Option<ConnectorConnectionDetails> {
@@ -846,14 +2109,25 @@ fn normalize_endpoint(endpoint: &str) -> String {
     format!("http://{endpoint}")
 }
 
+/// The `host:port` an endpoint points at.
+fn host_target(endpoint: &str) -> Option<String> {
+    let target = parse_target(endpoint).ok()?;
+    Some(join_host_port(&target.address, target.port))
+}
+
+/// `endpoint` with its host and port replaced by `addr`.
+fn retarget(endpoint: &str, addr: SocketAddr) -> String {
+    let scheme = endpoint
+        .split_once("://")
+        .map_or("http", |(scheme, _)| scheme);
+    format!("{scheme}://{addr}")
+}
+
 fn strip_scheme(endpoint: &str) -> String {
-    if let Ok(url) = url::Url::parse(endpoint)
-        && let Some(host) = url.host_str()
-        && let Some(port) = url.port()
-    {
-        return format!("{host}:{port}");
+    match parse_target(endpoint) {
+        Ok(target) if endpoint.contains("://") => join_host_port(&target.address, target.port),
+        _ => endpoint.to_string(),
     }
-    endpoint.to_string()
 }
 
 fn proxy_hostnames(proxy: &HTTPProxy) -> Vec<String> {
@@ -865,11 +2139,11 @@ fn proxy_hostnames(proxy: &HTTPProxy) -> Vec<String> {
         .unwrap_or_default()
 }
 
-fn proxy_rule(endpoint: &str, connector_name: &str) -> HTTPProxyRule {
+fn proxy_rule(endpoint: &str, connector_name: &str, ip_filter: &IpFilter) -> HTTPProxyRule {
     HTTPProxyRule {
         name: None,
         matches: vec![default_match()],
-        filters: None,
+        filters: ip_filter_route_filters(ip_filter),
         backends: Some(vec![HTTPProxyRuleBackend {
             endpoint: endpoint.to_string(),
             connector: Some(ConnectorReference {
@@ -880,6 +2154,50 @@ fn proxy_rule(endpoint: &str, connector_name: &str) -> HTTPProxyRule {
     }
 }
 
+/// The IP filter travels to the gateway as request headers that Envoy sets
+/// on every request matching the rule. Setting, rather than adding, replaces
+/// any values a client sent itself.
+fn ip_filter_route_filters(ip_filter: &IpFilter) -> Option<Vec<HTTPRouteFilter>> {
+    if ip_filter.is_empty() {
+        return None;
+    }
+    let set = ip_filter
+        .to_headers()
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect::<Vec<_>>();
+    let filter = json!({
+        "type": "RequestHeaderModifier",
+        "requestHeaderModifier": { "set": set },
+    });
+    Some(vec![
+        serde_json::from_value(filter).expect("valid RequestHeaderModifier filter"),
+    ])
+}
+
+fn proxy_ip_filter(proxy: &HTTPProxy) -> IpFilter {
+    let Some(filters) = proxy
+        .spec
+        .rules
+        .first()
+        .and_then(|rule| rule.filters.as_ref())
+    else {
+        return IpFilter::default();
+    };
+    let filters = serde_json::to_value(filters).unwrap_or_default();
+    let headers = filters
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|filter| filter.pointer("/requestHeaderModifier/set")?.as_array())
+        .flatten()
+        .filter_map(|header| Some((header["name"].as_str()?, header["value"].as_str()?)));
+    IpFilter::from_header_values(headers).unwrap_or_else(|err| {
+        warn!(proxy = %proxy.name_any(), "Ignoring invalid IP filter: {err:#}");
+        IpFilter::default()
+    })
+}
+
 fn proxy_backend_endpoint(proxy: &HTTPProxy) -> Option<String> {
     proxy
         .spec
@@ -890,6 +2208,54 @@ fn proxy_backend_endpoint(proxy: &HTTPProxy) -> Option<String> {
         .map(|backend| backend.endpoint.clone())
 }
 
+/// Run `op` for each tunnel, [`BULK_CONCURRENCY`] at a time.
+async fn bulk<T, Fut>(tunnel_ids: Vec<String>, op: impl Fn(String) -> Fut) -> BulkOutcome<T>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let results = n0_future::stream::iter(tunnel_ids.into_iter().map(|tunnel_id| {
+        let op = op(tunnel_id.clone());
+        async move { (tunnel_id, op.await) }
+    }))
+    .buffered_unordered(BULK_CONCURRENCY)
+    .collect::<Vec<_>>()
+    .await;
+    let mut outcome = BulkOutcome {
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+    };
+    for (tunnel_id, result) in results {
+        match result {
+            Ok(value) => outcome.succeeded.push(value),
+            Err(err) => {
+                warn!(%tunnel_id, "Bulk tunnel operation failed: {err:#}");
+                outcome.failed.push(BulkFailure {
+                    tunnel_id,
+                    error: format!("{err:#}"),
+                });
+            }
+        }
+    }
+    outcome
+}
+
+/// The advertisement a tunnel's HTTPProxy gets when it is enabled, or `None`
+/// if the proxy has no usable backend.
+pub(crate) fn advertisement_for(
+    proxy: &HTTPProxy,
+    connector_name: &str,
+) -> Option<ConnectorAdvertisement> {
+    let target = parse_target(&proxy_backend_endpoint(proxy)?).ok()?;
+    Some(ConnectorAdvertisement {
+        metadata: ObjectMeta {
+            name: Some(proxy.name_any()),
+            ..Default::default()
+        },
+        spec: advertisement_spec(connector_name, target),
+        status: None,
+    })
+}
+
 fn advertisement_spec(connector_name: &str, target: ParsedTarget) -> ConnectorAdvertisementSpec {
     let port_name = format!("tcp-{}", target.port);
     ConnectorAdvertisementSpec {
@@ -925,3 +2291,34 @@ fn publish_tickets_enabled() -> bool {
         .map(|value| matches!(value.as_str(), "1" | "true" | "TRUE" | "yes" | "YES"))
         .unwrap_or(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bracketed_ipv6_targets() {
+        for (endpoint, address, port) in [
+            ("http://[::1]:8080", "::1", 8080),
+            ("[fd00::12]:3000", "fd00::12", 3000),
+            ("https://[::1]", "::1", 443),
+            ("http://localhost", "localhost", 80),
+            ("127.0.0.1:5173", "127.0.0.1", 5173),
+        ] {
+            let target = parse_target(endpoint).unwrap();
+            assert_eq!(
+                (target.address.as_str(), target.port),
+                (address, port),
+                "{endpoint}"
+            );
+        }
+        assert!(parse_target("[::1").is_err());
+        assert_eq!(strip_scheme("http://[::1]:8080"), "[::1]:8080");
+        assert_eq!(host_target("[::1]:8080").as_deref(), Some("[::1]:8080"));
+        let addr = "[::1]:9000".parse().unwrap();
+        assert_eq!(
+            retarget("https://app.internal:443", addr),
+            "https://[::1]:9000"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Who changed a tunnel, and when.
+//!
+//! Every change made through [`TunnelService`](super::TunnelService) is
+//! appended to an annotation on the tunnel's HTTPProxy, so everyone with
+//! access to a shared project sees the same history, whichever device made
+//! the change. Only the newest [`MAX_AUDIT_ENTRIES`] are kept. The same
+//! changes go to the local event log with the user who made them.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::datum_apis::http_proxy::HTTPProxy;
+
+/// Annotation holding a tunnel's audit trail, as a JSON list.
+pub(super) const AUDIT_ANNOTATION: &str = "connect.datumapis.com/audit";
+/// How many changes a tunnel's trail keeps.
+pub const MAX_AUDIT_ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Created,
+    Updated,
+    Enabled,
+    Disabled,
+    Restored,
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            Self::Created => "created",
+            Self::Updated => "changed",
+            Self::Enabled => "enabled",
+            Self::Disabled => "disabled",
+            Self::Restored => "restored",
+        };
+        f.write_str(action)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    /// Email of the user who made the change.
+    pub by: String,
+    pub action: AuditAction,
+}
+
+/// A tunnel's audit trail, oldest first. Empty if it has none or it can't
+/// be read.
+pub(super) fn proxy_audit(proxy: &HTTPProxy) -> Vec<AuditEntry> {
+    proxy
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(AUDIT_ANNOTATION))
+        .and_then(|trail| serde_json::from_str(trail).ok())
+        .unwrap_or_default()
+}
+
+/// The annotation value for `trail` with `entry` appended.
+pub(super) fn audit_annotation(mut trail: Vec<AuditEntry>, entry: AuditEntry) -> String {
+    trail.push(entry);
+    let excess = trail.len().saturating_sub(MAX_AUDIT_ENTRIES);
+    trail.drain(..excess);
+    serde_json::to_string(&trail).expect("serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+    use super::*;
+    use crate::datum_apis::http_proxy::HTTPProxySpec;
+
+    #[test]
+    fn keeps_newest_entries() {
+        let entry = |action| AuditEntry {
+            at: Utc::now(),
+            by: "user@example.com".to_string(),
+            action,
+        };
+        let mut trail = Vec::new();
+        for _ in 0..MAX_AUDIT_ENTRIES {
+            trail.push(entry(AuditAction::Updated));
+        }
+        let annotation = audit_annotation(trail, entry(AuditAction::Disabled));
+
+        let proxy = HTTPProxy {
+            metadata: ObjectMeta {
+                annotations: Some([(AUDIT_ANNOTATION.to_string(), annotation)].into()),
+                ..Default::default()
+            },
+            spec: HTTPProxySpec {
+                hostnames: None,
+                rules: Vec::new(),
+            },
+            status: None,
+        };
+        let trail = proxy_audit(&proxy);
+        assert_eq!(trail.len(), MAX_AUDIT_ENTRIES);
+        assert_eq!(trail.last().unwrap().action, AuditAction::Disabled);
+        assert!(
+            proxy_audit(&HTTPProxy {
+                metadata: ObjectMeta::default(),
+                ..proxy
+            })
+            .is_empty()
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! The Connectors of the signed-in user's projects, one per device.
+//!
+//! Every device that served a tunnel in a project registered a Connector
+//! there, and nothing removes it when the device is wiped or replaced. The
+//! devices view lists them with what their heartbeats last reported, so
+//! connectors of old laptops can be spotted and revoked.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use kube::api::{DeleteParams, ListParams};
+use kube::{Api, ResourceExt};
+use n0_error::{Result, StdResultExt, stack_error};
+use n0_future::{BufferedStreamExt, StreamExt};
+use tracing::{debug, warn};
+
+use super::{
+    ADVERTISEMENT_CONNECTOR_FIELD, BULK_CONCURRENCY, DEFAULT_CONNECTOR_CLASS_NAME,
+    DEFAULT_PCP_NAMESPACE, TunnelService,
+};
+use crate::datum_apis::connector::Connector;
+use crate::datum_apis::connector_advertisement::ConnectorAdvertisement;
+use crate::datum_apis::lease::Lease;
+use crate::heartbeat::AGENT_ANNOTATION_PREFIX;
+use crate::permissions;
+
+/// Devices without a heartbeat for this long are shown as stale.
+pub const STALE_AFTER: chrono::Duration = chrono::Duration::days(7);
+
+/// A device's Connector in one project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceConnector {
+    pub project_id: String,
+    pub project_name: String,
+    pub connector_name: String,
+    /// The device's iroh endpoint id, once it connected.
+    pub endpoint_id: Option<String>,
+    /// Version of the app on the device.
+    pub agent_version: Option<String>,
+    pub agent_os: Option<String>,
+    /// When the device last renewed its lease.
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    /// Set when the device shut down cleanly and hasn't come back since.
+    pub disconnected_at: Option<DateTime<Utc>>,
+    /// Whether this is the connector of the device the app runs on.
+    pub this_device: bool,
+}
+
+impl DeviceConnector {
+    /// Whether the device hasn't been heard from in [`STALE_AFTER`].
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        !self.this_device
+            && self
+                .last_heartbeat
+                .is_none_or(|last| now - last > STALE_AFTER)
+    }
+}
+
+#[stack_error(derive)]
+#[error("Connector {connector_name} belongs to this device and can't be revoked from here")]
+pub struct RevokeOwnConnector {
+    pub connector_name: String,
+}
+
+impl TunnelService {
+    /// The connectors of all projects the user can see. Projects that can't
+    /// be listed are skipped.
+    pub async fn list_devices(&self) -> Result<Vec<DeviceConnector>> {
+        let projects = self
+            .datum
+            .orgs_and_projects()
+            .await?
+            .into_iter()
+            .flat_map(|org| org.projects);
+        let lists = n0_future::stream::iter(projects)
+            .map(|project| async move {
+                let res = self
+                    .list_devices_project(&project.resource_id, &project.display_name)
+                    .await;
+                (project.resource_id, res)
+            })
+            .buffered_unordered(BULK_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+        let mut devices = Vec::new();
+        for (project_id, res) in lists {
+            match res {
+                Ok(list) => devices.extend(list),
+                Err(err) => warn!(%project_id, "Failed to list connectors: {err:#}"),
+            }
+        }
+        devices.sort_by(|a, b| {
+            (&a.project_name, &a.connector_name).cmp(&(&b.project_name, &b.connector_name))
+        });
+        Ok(devices)
+    }
+
+    async fn list_devices_project(
+        &self,
+        project_id: &str,
+        project_name: &str,
+    ) -> Result<Vec<DeviceConnector>> {
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let connectors: Api<Connector> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let leases: Api<Lease> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let connectors = connectors
+            .list(&ListParams::default())
+            .await
+            .std_context("Failed to list Connectors")?;
+        let renewed = leases
+            .list(&ListParams::default())
+            .await
+            .std_context("Failed to list Leases")?
+            .items
+            .into_iter()
+            .filter_map(|lease| {
+                let renewed = lease.spec?.renew_time?.0;
+                Some((lease.metadata.name?, renewed))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let own_id = self.listen.endpoint_id().to_string();
+        let devices = connectors
+            .items
+            .into_iter()
+            .filter(|connector| connector.spec.connector_class_name == DEFAULT_CONNECTOR_CLASS_NAME)
+            .map(|connector| {
+                let annotation = |key: &str| {
+                    connector
+                        .annotations()
+                        .get(&format!("{AGENT_ANNOTATION_PREFIX}{key}"))
+                        .cloned()
+                };
+                let timestamp = |key: &str| {
+                    annotation(key)
+                        .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+                        .map(|at| at.with_timezone(&Utc))
+                };
+                let status = connector.status.as_ref();
+                let endpoint_id = status
+                    .and_then(|status| status.connection_details.as_ref())
+                    .and_then(|details| details.public_key.as_ref())
+                    .map(|key| key.id.clone());
+                let last_heartbeat = status
+                    .and_then(|status| status.lease_ref.as_ref())
+                    .and_then(|lease| renewed.get(&lease.name).copied());
+                DeviceConnector {
+                    project_id: project_id.to_string(),
+                    project_name: project_name.to_string(),
+                    connector_name: connector.name_any(),
+                    this_device: endpoint_id.as_deref() == Some(own_id.as_str()),
+                    endpoint_id,
+                    agent_version: annotation("version"),
+                    agent_os: annotation("os"),
+                    last_heartbeat,
+                    disconnected_at: timestamp("disconnected-at"),
+                }
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    /// Delete another device's connector and the advertisements pointing at
+    /// it. Its tunnels stay, but are unreachable until served again.
+    pub async fn revoke_device(&self, project_id: &str, connector_name: &str) -> Result<()> {
+        self.require(project_id, permissions::REVOKE).await?;
+        let pcp = self.datum.project_control_plane_client(project_id).await?;
+        let client = pcp.client();
+        let connectors: Api<Connector> = Api::namespaced(client.clone(), DEFAULT_PCP_NAMESPACE);
+        let ads: Api<ConnectorAdvertisement> = Api::namespaced(client, DEFAULT_PCP_NAMESPACE);
+
+        let Some(connector) = connectors
+            .get_opt(connector_name)
+            .await
+            .std_context("Failed to load Connector")?
+        else {
+            return Ok(());
+        };
+        let own_id = self.listen.endpoint_id().to_string();
+        let is_own = connector
+            .status
+            .as_ref()
+            .and_then(|status| status.connection_details.as_ref())
+            .and_then(|details| details.public_key.as_ref())
+            .is_some_and(|key| key.id == own_id);
+        if is_own {
+            return Err(RevokeOwnConnector {
+                connector_name: connector_name.to_string(),
+            }
+            .into());
+        }
+
+        let selector = format!("{ADVERTISEMENT_CONNECTOR_FIELD}={connector_name}");
+        let stale_ads = ads
+            .list(&ListParams::default().fields(&selector))
+            .await
+            .std_context("Failed to list ConnectorAdvertisements")?;
+        for ad in stale_ads.items {
+            ads.delete(&ad.name_any(), &DeleteParams::default())
+                .await
+                .std_context("Failed to delete ConnectorAdvertisement")?;
+        }
+        connectors
+            .delete(connector_name, &DeleteParams::default())
+            .await
+            .std_context("Failed to delete Connector")?;
+        debug!(%project_id, connector = %connector_name, "revoked connector");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_stale_devices() {
+        let now = Utc::now();
+        let device = |last_heartbeat, this_device| DeviceConnector {
+            project_id: "p".to_string(),
+            project_name: "Project".to_string(),
+            connector_name: "c".to_string(),
+            endpoint_id: None,
+            agent_version: None,
+            agent_os: None,
+            last_heartbeat,
+            disconnected_at: None,
+            this_device,
+        };
+        assert!(!device(Some(now - chrono::Duration::hours(1)), false).is_stale(now));
+        assert!(device(Some(now - chrono::Duration::days(8)), false).is_stale(now));
+        assert!(device(None, false).is_stale(now));
+        assert!(!device(None, true).is_stale(now));
+    }
+}
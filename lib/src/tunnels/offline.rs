@@ -0,0 +1,365 @@
+//! Tunnel changes made while the Datum API can't be reached.
+//!
+//! With an [`OfflineQueue`], a create, update, toggle or delete that fails
+//! because the API is unreachable is stored in the repo instead of being
+//! lost, and replayed in order once the API answers again. Changes to an
+//! existing tunnel remember what the tunnel looked like when they were
+//! queued, or what it will look like once the changes queued before them are
+//! applied; if it was changed or removed elsewhere in the meantime, the
+//! change is held as a conflict for the user to discard rather than
+//! overwriting the newer edit.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use n0_error::{Result, stack_error};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell, watch};
+use tracing::warn;
+
+use super::TunnelSummary;
+use crate::Repo;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TunnelMutation {
+    Create {
+        label: String,
+        endpoint: String,
+    },
+    Update {
+        tunnel_id: String,
+        label: String,
+        endpoint: String,
+    },
+    SetEnabled {
+        tunnel_id: String,
+        enabled: bool,
+    },
+    Delete {
+        tunnel_id: String,
+    },
+}
+
+impl TunnelMutation {
+    pub fn tunnel_id(&self) -> Option<&str> {
+        match self {
+            Self::Create { .. } => None,
+            Self::Update { tunnel_id, .. }
+            | Self::SetEnabled { tunnel_id, .. }
+            | Self::Delete { tunnel_id } => Some(tunnel_id),
+        }
+    }
+}
+
+/// The fields of a tunnel a queued change was based on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelBase {
+    pub label: String,
+    pub endpoint: String,
+}
+
+impl TunnelBase {
+    pub fn of(tunnel: &TunnelSummary) -> Self {
+        Self {
+            label: tunnel.label.clone(),
+            endpoint: tunnel.endpoint.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PendingStatus {
+    /// Waiting for the API to be reachable.
+    #[default]
+    Queued,
+    /// The tunnel changed elsewhere since the change was queued.
+    Conflict { reason: String },
+    /// The API rejected the change when it was replayed.
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingMutation {
+    pub id: String,
+    pub project_id: String,
+    pub queued_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub mutation: TunnelMutation,
+    /// The tunnel as last seen before the change, if it already existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<TunnelBase>,
+    #[serde(default, rename = "state")]
+    pub status: PendingStatus,
+}
+
+impl PendingMutation {
+    /// Why this change can't be applied on top of `current`, the tunnel as
+    /// it is now.
+    pub fn conflict(&self, current: Option<&TunnelSummary>) -> Option<String> {
+        let tunnel_id = self.mutation.tunnel_id()?;
+        let Some(current) = current else {
+            return match self.mutation {
+                TunnelMutation::Delete { .. } => None,
+                _ => Some(format!("Tunnel {tunnel_id} was deleted")),
+            };
+        };
+        match &self.base {
+            Some(base) if *base != TunnelBase::of(current) => {
+                Some(format!("Tunnel {tunnel_id} was changed elsewhere"))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What `tunnel_id` will look like once the changes already queued for it
+/// are applied, if any are. Replay checks each change against the tunnel as
+/// the one before left it, so this is the base for the next change.
+fn queued_base(
+    pending: &[PendingMutation],
+    project_id: &str,
+    tunnel_id: &str,
+) -> Option<TunnelBase> {
+    let last = pending.iter().rev().find(|entry| {
+        entry.status == PendingStatus::Queued
+            && entry.project_id == project_id
+            && entry.mutation.tunnel_id() == Some(tunnel_id)
+    })?;
+    match &last.mutation {
+        TunnelMutation::Update {
+            label, endpoint, ..
+        } => Some(TunnelBase {
+            label: label.clone(),
+            endpoint: endpoint.clone(),
+        }),
+        _ => last.base.clone(),
+    }
+}
+
+#[stack_error(derive)]
+#[error(
+    "Datum Cloud can't be reached. The change was saved and will be applied once the connection is back."
+)]
+pub struct QueuedOffline {
+    pub id: String,
+}
+
+/// Tunnel changes waiting for the API, persisted in the repo.
+#[derive(Debug, Clone)]
+pub struct OfflineQueue {
+    repo: Repo,
+    pending: Arc<watch::Sender<Vec<PendingMutation>>>,
+    loaded: Arc<OnceCell<()>>,
+    /// Held from a change until it is written, so the file is written in
+    /// the order of the changes and ends up with the last state.
+    writes: Arc<Mutex<()>>,
+}
+
+impl OfflineQueue {
+    pub fn new(repo: Repo) -> Self {
+        Self {
+            repo,
+            pending: Arc::new(watch::channel(Vec::new()).0),
+            loaded: Arc::new(OnceCell::new()),
+            writes: Arc::new(Mutex::new(())),
+        }
+    }
+
+    async fn load(&self) {
+        self.loaded
+            .get_or_init(|| async {
+                match self.repo.read_pending_mutations().await {
+                    Ok(pending) => {
+                        self.pending.send_replace(pending);
+                    }
+                    Err(err) => warn!("Failed to read queued tunnel changes: {err:#}"),
+                }
+            })
+            .await;
+    }
+
+    pub async fn list(&self) -> Vec<PendingMutation> {
+        self.load().await;
+        self.pending.borrow().clone()
+    }
+
+    pub async fn watch(&self) -> watch::Receiver<Vec<PendingMutation>> {
+        self.load().await;
+        self.pending.subscribe()
+    }
+
+    /// Queue `mutation`. `synced` is the tunnel as last fetched from the
+    /// API, used as the base unless changes to it are queued already.
+    pub(super) async fn push(
+        &self,
+        project_id: &str,
+        mutation: TunnelMutation,
+        synced: Option<TunnelBase>,
+    ) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut entry = PendingMutation {
+            id: id.clone(),
+            project_id: project_id.to_string(),
+            queued_at: Utc::now(),
+            mutation,
+            base: None,
+            status: PendingStatus::Queued,
+        };
+        self.modify(|pending| {
+            entry.base = entry
+                .mutation
+                .tunnel_id()
+                .and_then(|tunnel_id| queued_base(pending, project_id, tunnel_id))
+                .or(synced);
+            pending.push(entry);
+        })
+        .await?;
+        Ok(id)
+    }
+
+    /// Drop a queued change without applying it.
+    pub async fn discard(&self, id: &str) -> Result<()> {
+        self.modify(|pending| pending.retain(|entry| entry.id != id))
+            .await
+    }
+
+    pub(super) async fn set_status(&self, id: &str, status: PendingStatus) -> Result<()> {
+        self.modify(|pending| {
+            if let Some(entry) = pending.iter_mut().find(|entry| entry.id == id) {
+                entry.status = status;
+            }
+        })
+        .await
+    }
+
+    async fn modify(&self, f: impl FnOnce(&mut Vec<PendingMutation>)) -> Result<()> {
+        self.load().await;
+        let _write = self.writes.lock().await;
+        self.pending.send_modify(f);
+        let pending = self.pending.borrow().clone();
+        self.repo.write_pending_mutations(&pending).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip_filter::IpFilter;
+
+    #[tokio::test]
+    async fn writes_the_last_state() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let repo = Repo::open_or_create(temp_dir.path()).await?;
+        let queue = OfflineQueue::new(repo.clone());
+        let mut pushes = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let queue = queue.clone();
+            pushes.spawn(async move {
+                let mutation = TunnelMutation::Delete {
+                    tunnel_id: format!("t{i}"),
+                };
+                queue.push("p", mutation, None).await
+            });
+        }
+        for pushed in pushes.join_all().await {
+            pushed?;
+        }
+        let pending = queue.list().await;
+        assert_eq!(pending.len(), 20);
+        assert_eq!(repo.read_pending_mutations().await?, pending);
+        Ok(())
+    }
+
+    fn tunnel(label: &str) -> TunnelSummary {
+        TunnelSummary {
+            id: "t".to_string(),
+            label: label.to_string(),
+            endpoint: "http://127.0.0.1:8080".to_string(),
+            hostnames: Vec::new(),
+            enabled: true,
+            accepted: true,
+            programmed: true,
+            ip_filter: IpFilter::default(),
+            tags: Vec::new(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn detects_conflicting_edits() {
+        let before = tunnel("web");
+        let pending = |mutation| PendingMutation {
+            id: "m".to_string(),
+            project_id: "p".to_string(),
+            queued_at: Utc::now(),
+            mutation,
+            base: Some(TunnelBase::of(&before)),
+            status: PendingStatus::Queued,
+        };
+        let update = pending(TunnelMutation::Update {
+            tunnel_id: "t".to_string(),
+            label: "api".to_string(),
+            endpoint: before.endpoint.clone(),
+        });
+        let delete = pending(TunnelMutation::Delete {
+            tunnel_id: "t".to_string(),
+        });
+
+        assert_eq!(update.conflict(Some(&before)), None);
+        assert!(update.conflict(Some(&tunnel("renamed"))).is_some());
+        assert!(update.conflict(None).is_some());
+        assert_eq!(delete.conflict(None), None);
+        assert!(delete.conflict(Some(&tunnel("renamed"))).is_some());
+
+        let yaml = serde_yml::to_string(&update).unwrap();
+        assert_eq!(
+            serde_yml::from_str::<PendingMutation>(&yaml).unwrap(),
+            update
+        );
+    }
+
+    #[test]
+    fn bases_queued_edits_on_the_one_before() {
+        let synced = tunnel("web");
+        let update = |label: &str| TunnelMutation::Update {
+            tunnel_id: "t".to_string(),
+            label: label.to_string(),
+            endpoint: synced.endpoint.clone(),
+        };
+        let mut pending: Vec<PendingMutation> = Vec::new();
+        for (id, mutation) in [
+            ("first", update("api")),
+            (
+                "toggle",
+                TunnelMutation::SetEnabled {
+                    tunnel_id: "t".to_string(),
+                    enabled: false,
+                },
+            ),
+            ("second", update("docs")),
+        ] {
+            let base = queued_base(&pending, "p", "t").or(Some(TunnelBase::of(&synced)));
+            pending.push(PendingMutation {
+                id: id.to_string(),
+                project_id: "p".to_string(),
+                queued_at: Utc::now(),
+                mutation,
+                base,
+                status: PendingStatus::Queued,
+            });
+        }
+        assert_eq!(queued_base(&pending, "other", "t"), None);
+
+        // Replayed in order, each edit finds the tunnel as the one before
+        // left it.
+        let [first, toggle, second] = &pending[..] else {
+            unreachable!()
+        };
+        assert_eq!(first.conflict(Some(&synced)), None);
+        assert_eq!(toggle.conflict(Some(&tunnel("api"))), None);
+        assert_eq!(second.conflict(Some(&tunnel("api"))), None);
+        assert!(second.conflict(Some(&tunnel("renamed"))).is_some());
+    }
+}
@@ -0,0 +1,244 @@
+//! A live view of a project's tunnels, kept in sync by watching the API.
+//!
+//! Listing tunnels takes three LISTs (Connectors, HTTPProxies and
+//! ConnectorAdvertisements), which views used to repeat on every refresh and
+//! every few seconds while a hostname was provisioning. The reconciler lists
+//! each kind once and then follows its watch stream, so changes made
+//! elsewhere, like in the portal or on another device, show up as they
+//! happen.
+
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc, time::Duration};
+
+use kube::{
+    Api, Resource, ResourceExt,
+    api::{ListParams, WatchEvent, WatchParams},
+};
+use n0_error::{Result, StdResultExt};
+use n0_future::{StreamExt, task::AbortOnDropHandle};
+use serde::de::DeserializeOwned;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use super::{
+    DEFAULT_PCP_NAMESPACE, TunnelSummary, proxy_uses_connector, store_proxy_states, tunnel_summary,
+};
+use crate::{
+    ListenNode,
+    datum_apis::{
+        connector::Connector, connector_advertisement::ConnectorAdvertisement,
+        http_proxy::HTTPProxy,
+    },
+    datum_cloud::DatumCloudClient,
+};
+
+/// Server-side timeout of a single watch request, after which it is renewed.
+const WATCH_TIMEOUT_SECS: u32 = 290;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The watched objects by name, each kind `None` until first listed.
+#[derive(Debug, Default)]
+struct Objects {
+    connectors: Option<BTreeMap<String, Connector>>,
+    proxies: Option<BTreeMap<String, HTTPProxy>>,
+    ads: Option<BTreeMap<String, ConnectorAdvertisement>>,
+}
+
+/// Keeps the tunnels of one project in sync until dropped.
+#[derive(derive_more::Debug, Clone)]
+pub struct TunnelReconciler {
+    project_id: String,
+    tunnels: watch::Receiver<Option<Vec<TunnelSummary>>>,
+    #[debug(skip)]
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+
+impl TunnelReconciler {
+    pub(super) fn spawn(
+        datum: DatumCloudClient,
+        listen: ListenNode,
+        publish_tickets: bool,
+        project_id: String,
+    ) -> Self {
+        let (tx, tunnels) = watch::channel(None);
+        let task = tokio::spawn(run(datum, listen, publish_tickets, project_id.clone(), tx));
+        Self {
+            project_id,
+            tunnels,
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        }
+    }
+
+    pub fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    /// The project's tunnels, or `None` until every kind has been listed.
+    pub fn tunnels(&self) -> Option<Vec<TunnelSummary>> {
+        self.tunnels.borrow().clone()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<Option<Vec<TunnelSummary>>> {
+        self.tunnels.clone()
+    }
+}
+
+async fn run(
+    datum: DatumCloudClient,
+    listen: ListenNode,
+    publish_tickets: bool,
+    project_id: String,
+    tunnels: watch::Sender<Option<Vec<TunnelSummary>>>,
+) {
+    let (objects_tx, mut objects) = watch::channel(Objects::default());
+    let syncs = async {
+        tokio::join!(
+            sync(&datum, &project_id, &objects_tx, |o| &mut o.connectors),
+            sync(&datum, &project_id, &objects_tx, |o| &mut o.proxies),
+            sync(&datum, &project_id, &objects_tx, |o| &mut o.ads),
+        );
+    };
+    let endpoint_id = listen.endpoint_id().to_string();
+    let publish = async {
+        while objects.changed().await.is_ok() {
+            let Some(list) = summaries(&objects.borrow_and_update(), &endpoint_id) else {
+                continue;
+            };
+            if !publish_tickets {
+                store_proxy_states(&listen, &list).await;
+            }
+            tunnels.send_if_modified(|current| {
+                if current.as_ref() == Some(&list) {
+                    return false;
+                }
+                *current = Some(list);
+                true
+            });
+        }
+    };
+    tokio::join!(syncs, publish);
+}
+
+/// The tunnels served by this device's connector.
+fn summaries(objects: &Objects, endpoint_id: &str) -> Option<Vec<TunnelSummary>> {
+    let (Some(connectors), Some(proxies), Some(ads)) =
+        (&objects.connectors, &objects.proxies, &objects.ads)
+    else {
+        return None;
+    };
+    // Same choice as `TunnelService::find_connector`: the connector with this
+    // device's key, or the only one in the project.
+    let connector = connectors
+        .values()
+        .find(|connector| {
+            connector
+                .status
+                .as_ref()
+                .and_then(|status| status.connection_details.as_ref())
+                .and_then(|details| details.public_key.as_ref())
+                .is_some_and(|key| key.id == endpoint_id)
+        })
+        .or_else(|| match connectors.len() {
+            1 => connectors.values().next(),
+            _ => None,
+        });
+    let Some(connector) = connector else {
+        return Some(Vec::new());
+    };
+    let connector_name = connector.name_any();
+    let list = proxies
+        .iter()
+        .filter(|(_, proxy)| proxy_uses_connector(proxy, &connector_name))
+        .map(|(name, proxy)| {
+            let enabled = ads
+                .get(name)
+                .is_some_and(|ad| ad.spec.connector_ref.name == connector_name);
+            tunnel_summary(proxy, name.clone(), enabled)
+        })
+        .collect();
+    Some(list)
+}
+
+/// Keep one kind of object in `objects` up to date, forever.
+async fn sync<K>(
+    datum: &DatumCloudClient,
+    project_id: &str,
+    objects: &watch::Sender<Objects>,
+    slot: fn(&mut Objects) -> &mut Option<BTreeMap<String, K>>,
+) where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let kind = K::kind(&());
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match follow(datum, project_id, objects, slot).await {
+            Ok(()) => backoff = MIN_BACKOFF,
+            Err(err) => {
+                warn!(%project_id, %kind, "Failed to watch tunnel objects: {err:#}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// List all objects of a kind, then apply watch events until the list can't
+/// be continued and has to be taken again.
+async fn follow<K>(
+    datum: &DatumCloudClient,
+    project_id: &str,
+    objects: &watch::Sender<Objects>,
+    slot: fn(&mut Objects) -> &mut Option<BTreeMap<String, K>>,
+) -> Result<()>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let pcp = datum.project_control_plane_client(project_id).await?;
+    let api: Api<K> = Api::namespaced(pcp.client(), DEFAULT_PCP_NAMESPACE);
+    let list = api
+        .list(&ListParams::default())
+        .await
+        .std_context("Failed to list objects")?;
+    let mut version = list.metadata.resource_version.unwrap_or_default();
+    let items = list
+        .items
+        .into_iter()
+        .map(|item| (item.name_any(), item))
+        .collect();
+    objects.send_modify(|objects| *slot(objects) = Some(items));
+
+    loop {
+        let params = WatchParams::default().timeout(WATCH_TIMEOUT_SECS);
+        let stream = api
+            .watch(&params, &version)
+            .await
+            .std_context("Failed to start watch")?;
+        let mut stream = std::pin::pin!(stream);
+        while let Some(event) = stream.next().await {
+            match event.std_context("Watch stream failed")? {
+                WatchEvent::Added(object) | WatchEvent::Modified(object) => {
+                    version = object.resource_version().unwrap_or(version);
+                    objects.send_modify(|objects| {
+                        if let Some(map) = slot(objects) {
+                            map.insert(object.name_any(), object);
+                        }
+                    });
+                }
+                WatchEvent::Deleted(object) => {
+                    version = object.resource_version().unwrap_or(version);
+                    objects.send_modify(|objects| {
+                        if let Some(map) = slot(objects) {
+                            map.remove(&object.name_any());
+                        }
+                    });
+                }
+                WatchEvent::Bookmark(bookmark) => version = bookmark.metadata.resource_version,
+                WatchEvent::Error(err) => {
+                    // Usually 410 Gone: `version` is too old to resume from.
+                    debug!(%project_id, "Watch ended, listing again: {err:?}");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
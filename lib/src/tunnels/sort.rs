@@ -0,0 +1,106 @@
+//! Orders for lists of tunnels.
+//!
+//! When a tunnel last served a request is tracked by the listen node in its
+//! [activity log](crate::activity), not in Datum Cloud, so it is passed in
+//! rather than read from the [`TunnelSummary`].
+
+use std::cmp::Reverse;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::TunnelSummary;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelSort {
+    /// Most recently used first. Tunnels never used come last.
+    #[default]
+    RecentlyUsed,
+    /// By label, ignoring case.
+    Name,
+    /// Serving first, then provisioning, then disabled.
+    Status,
+}
+
+impl TunnelSort {
+    pub const ALL: [Self; 3] = [Self::RecentlyUsed, Self::Name, Self::Status];
+
+    /// Sort `tunnels`, with `last_used` telling when a tunnel last served a
+    /// request. Ties are ordered by name.
+    pub fn sort(
+        self,
+        tunnels: &mut [TunnelSummary],
+        last_used: impl Fn(&str) -> Option<DateTime<Utc>>,
+    ) {
+        match self {
+            Self::RecentlyUsed => {
+                tunnels.sort_by_cached_key(|t| (Reverse(last_used(&t.id)), name_key(t)))
+            }
+            Self::Name => tunnels.sort_by_cached_key(name_key),
+            Self::Status => tunnels.sort_by_cached_key(|t| (status_rank(t), name_key(t))),
+        }
+    }
+}
+
+fn name_key(tunnel: &TunnelSummary) -> (String, String) {
+    (tunnel.label.to_lowercase(), tunnel.id.clone())
+}
+
+fn status_rank(tunnel: &TunnelSummary) -> u8 {
+    match (tunnel.enabled, tunnel.accepted && tunnel.programmed) {
+        (true, true) => 0,
+        (true, false) => 1,
+        (false, _) => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::ip_filter::IpFilter;
+
+    fn tunnel(id: &str, label: &str, enabled: bool, ready: bool) -> TunnelSummary {
+        TunnelSummary {
+            id: id.to_string(),
+            label: label.to_string(),
+            endpoint: "127.0.0.1:8080".to_string(),
+            hostnames: Vec::new(),
+            enabled,
+            accepted: ready,
+            programmed: ready,
+            ip_filter: IpFilter::default(),
+            tags: Vec::new(),
+            expires_at: None,
+        }
+    }
+
+    fn ids(tunnels: &[TunnelSummary]) -> Vec<&str> {
+        tunnels.iter().map(|t| t.id.as_str()).collect()
+    }
+
+    #[test]
+    fn sorts_by_use_name_and_status() {
+        let now = Utc::now();
+        let mut tunnels = vec![
+            tunnel("a", "web", true, true),
+            tunnel("b", "API", false, true),
+            tunnel("c", "db", true, false),
+            tunnel("d", "admin", true, true),
+        ];
+        let last_used = |id: &str| match id {
+            "c" => Some(now),
+            "a" => Some(now - TimeDelta::hours(1)),
+            _ => None,
+        };
+
+        TunnelSort::RecentlyUsed.sort(&mut tunnels, last_used);
+        assert_eq!(ids(&tunnels), ["c", "a", "d", "b"]);
+        TunnelSort::Name.sort(&mut tunnels, last_used);
+        assert_eq!(ids(&tunnels), ["d", "b", "c", "a"]);
+        TunnelSort::Status.sort(&mut tunnels, last_used);
+        assert_eq!(ids(&tunnels), ["d", "a", "c", "b"]);
+    }
+}
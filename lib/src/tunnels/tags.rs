@@ -0,0 +1,60 @@
+//! Free-form tags on tunnels, for grouping and filtering them.
+//!
+//! A tunnel's tags are kept in an annotation on its HTTPProxy, so every
+//! device and the portal see the same ones, and mirrored into the local
+//! [`ProxyState`](crate::ProxyState). Tunnels that only exist on this device
+//! keep them in the local state alone.
+
+use std::collections::BTreeSet;
+
+use crate::datum_apis::http_proxy::HTTPProxy;
+
+/// Annotation holding a tunnel's tags, comma separated.
+pub(super) const TAGS_ANNOTATION: &str = "connect.datumapis.com/tags";
+
+/// Tags from comma separated `input`, see [`normalize_tags`].
+pub fn parse_tags(input: &str) -> Vec<String> {
+    normalize_tags(input.split(','))
+}
+
+/// Trimmed tags, sorted and without empty ones or duplicates.
+pub fn normalize_tags<S: AsRef<str>>(tags: impl IntoIterator<Item = S>) -> Vec<String> {
+    tags.into_iter()
+        .map(|tag| tag.as_ref().trim().to_string())
+        .filter(|tag| !tag.is_empty() && !tag.contains(','))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+pub(super) fn proxy_tags(proxy: &HTTPProxy) -> Vec<String> {
+    proxy
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(TAGS_ANNOTATION))
+        .map(|tags| parse_tags(tags))
+        .unwrap_or_default()
+}
+
+/// The annotation value for `tags`, `null` to remove it in a merge patch.
+pub(super) fn tags_annotation(tags: &[String]) -> serde_json::Value {
+    match tags {
+        [] => serde_json::Value::Null,
+        tags => tags.join(",").into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_tags() {
+        assert_eq!(parse_tags(" web, prod ,,web"), ["prod", "web"]);
+        assert_eq!(parse_tags(""), Vec::<String>::new());
+        assert_eq!(normalize_tags(["b", "a,c", " a "]), ["a", "b"]);
+        assert_eq!(tags_annotation(&parse_tags("prod,web")), "prod,web");
+        assert!(tags_annotation(&[]).is_null());
+    }
+}
@@ -0,0 +1,127 @@
+//! Tunnels to services listening on a unix socket.
+//!
+//! Tunnel targets are `host:port`, in tickets and in iroh-proxy-utils, so a
+//! socket is served through a relay on a loopback port, the same way
+//! [directory tunnels](crate::static_files) are: the tunnel's target is the
+//! relay, which copies each connection to the socket.
+
+use std::{
+    net::SocketAddr,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use n0_error::{Result, StdResultExt};
+use n0_future::task::AbortOnDropHandle;
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tracing::debug;
+
+/// A relay from a loopback port to a unix socket, stopped when dropped.
+#[derive(Debug)]
+pub struct SocketBridge {
+    path: PathBuf,
+    local_addr: SocketAddr,
+    _task: AbortOnDropHandle<()>,
+}
+
+impl SocketBridge {
+    /// Relay connections on `addr` to the socket at `path`. Use port 0 to
+    /// bind any free port.
+    ///
+    /// The socket is connected to per connection, so it may be created or
+    /// replaced after the bridge starts.
+    pub async fn bind(path: impl AsRef<Path>, addr: SocketAddr) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_std_context(|_| format!("Failed to bind {addr}"))?;
+        let local_addr = listener.local_addr()?;
+        let task = tokio::spawn({
+            let path = Arc::new(path.clone());
+            async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else {
+                        continue;
+                    };
+                    let path = path.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = relay(stream, &path).await {
+                            debug!(path = %path.display(), "unix socket relay failed: {err:#}");
+                        }
+                    });
+                }
+            }
+        });
+        debug!(path = %path.display(), %local_addr, "relaying to unix socket");
+        Ok(Self {
+            path,
+            local_addr,
+            _task: AbortOnDropHandle::new(task),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+async fn relay(mut stream: TcpStream, path: &Path) -> Result<()> {
+    let mut socket = UnixStream::connect(path)
+        .await
+        .with_std_context(|_| format!("Failed to connect to {}", path.display()))?;
+    tokio::io::copy_bidirectional(&mut stream, &mut socket)
+        .await
+        .std_context("Failed to relay connection")?;
+    Ok(())
+}
+
+/// Check that `path` is a unix socket, for tunnel input.
+pub async fn check_socket(path: &Path) -> Result<()> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_std_context(|_| format!("Failed to open {}", path.display()))?;
+    if !metadata.file_type().is_socket() {
+        n0_error::bail_any!("{} is not a unix socket", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn relays_to_socket() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.sock");
+        let socket = UnixListener::bind(&path)?;
+        tokio::spawn(async move {
+            let (mut conn, _) = socket.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        });
+        check_socket(&path).await?;
+        assert!(check_socket(dir.path()).await.is_err());
+
+        let bridge = SocketBridge::bind(&path, (Ipv4Addr::LOCALHOST, 0).into()).await?;
+        let mut stream = TcpStream::connect(bridge.local_addr()).await?;
+        stream.write_all(b"ping").await?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+        Ok(())
+    }
+}
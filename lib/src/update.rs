@@ -1,17 +1,55 @@
+//! Update checks against the GitHub releases of the app.
+//!
+//! The desktop app looks for a newer installer and prompts to download it.
+//! The CLI replaces its own executable: a release carries a binary per
+//! platform named like `datum-connect-linux-x86_64`, a [`CLI_MANIFEST_ASSET`]
+//! with the release's version and the BLAKE3 hash of each binary by name,
+//! and [`CLI_SIGNATURE_ASSET`], the hex ed25519 signature of the manifest by
+//! the release key. A binary is only installed if the manifest is signed by
+//! the key this build was compiled with, from `DATUM_RELEASE_PUBLIC_KEY`,
+//! names the version being installed, which must be newer than this one, and
+//! lists the binary under its name with the hash it has. A signed manifest of
+//! an older release therefore can't be used to downgrade, and a binary can't
+//! be swapped for another platform's.
+
+use std::collections::BTreeMap;
 use std::io::{Error as IoError, ErrorKind};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, Utc};
+use iroh_base::{PublicKey, Signature};
 use n0_error::{Result, StackResultExt, StdResultExt};
 use serde::{Deserialize, Serialize};
 
-use crate::Repo;
+use crate::{
+    Repo,
+    updater::{MANIFEST_ASSET, MANIFEST_SIGNATURE_ASSET, ReleaseManifest, platform_key},
+};
 
 const GITHUB_API_BASE: &str = "https://api.github.com";
 const REPO_OWNER: &str = "datum-cloud";
 const REPO_NAME: &str = "app";
 
+/// Name of the [`CliManifest`] in a release.
+pub const CLI_MANIFEST_ASSET: &str = "cli-manifest.json";
+/// Name of the signature of [`CLI_MANIFEST_ASSET`] in a release.
+pub const CLI_SIGNATURE_ASSET: &str = "cli-manifest.json.sig";
+/// The key releases are signed with, set when building release binaries.
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("DATUM_RELEASE_PUBLIC_KEY");
+
+/// Which releases to update to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    /// Releases without a pre-release tag.
+    #[default]
+    Stable,
+    /// Pre-releases too, like `v0.4.0-beta.1`.
+    Beta,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateSettings {
     /// Check interval in hours (default: 12)
@@ -23,6 +61,12 @@ pub struct UpdateSettings {
     /// Whether auto-update is enabled
     #[serde(default = "default_auto_update_enabled")]
     pub auto_update_enabled: bool,
+    /// Which releases to offer
+    #[serde(default)]
+    pub channel: UpdateChannel,
+    /// This device's place in staged rollouts, see [`crate::updater`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollout_bucket: Option<u8>,
 }
 
 fn default_check_interval() -> u64 {
@@ -39,6 +83,8 @@ impl Default for UpdateSettings {
             check_interval_hours: 12,
             last_check_time: None,
             auto_update_enabled: true,
+            channel: UpdateChannel::default(),
+            rollout_bucket: None,
         }
     }
 }
@@ -48,9 +94,34 @@ struct GitHubRelease {
     tag_name: String,
     name: String,
     published_at: String,
+    #[serde(default)]
+    prerelease: bool,
+    /// Release notes, in Markdown
+    #[serde(default)]
+    body: Option<String>,
     assets: Vec<GitHubAsset>,
 }
 
+impl GitHubRelease {
+    fn is_prerelease(&self) -> bool {
+        // Pre-release tags contain a hyphen after the version (e.g., "v0.0.3-beta", "0.1.0-rc.1")
+        self.prerelease || self.tag_name.trim_start_matches('v').contains('-')
+    }
+
+    fn asset(&self, name: &str) -> Result<&GitHubAsset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::NotFound,
+                    format!("Release {} has no {name}", self.tag_name),
+                )
+            })
+            .anyerr()
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct GitHubAsset {
     name: String,
@@ -67,12 +138,37 @@ pub struct UpdateInfo {
     pub download_size: u64,
 }
 
-struct VersionParts {
+/// A newer CLI binary for this platform.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliUpdate {
+    pub version: String,
+    pub release_name: String,
+    pub published_at: DateTime<Utc>,
+    pub asset_name: String,
+    pub download_url: String,
+    pub download_size: u64,
+    manifest_url: String,
+    signature_url: String,
+}
+
+/// What a release signs about its CLI binaries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CliManifest {
+    /// The release's version, like `0.4.1`.
+    pub version: String,
+    /// Hex BLAKE3 hashes of the binaries, by asset name.
+    pub assets: BTreeMap<String, String>,
+}
+
+struct VersionParts<'a> {
     major: u32,
     minor: u32,
     patch: u32,
+    /// The pre-release part, like `beta.1`.
+    pre: Option<&'a str>,
 }
 
+#[derive(Debug, Clone)]
 pub struct UpdateChecker {
     repo: Repo,
     current_version: String,
@@ -140,50 +236,7 @@ impl UpdateChecker {
             return Ok(None);
         }
 
-        // Fetch all releases and filter out "rolling" tag
-        // We want the latest tagged release (like v0.0.3), not the rolling release
-        let url = format!(
-            "{}/repos/{}/{}/releases",
-            GITHUB_API_BASE, REPO_OWNER, REPO_NAME
-        );
-
-        let client = reqwest::Client::builder()
-            .user_agent("DatumConnect/1.0")
-            .build()
-            .anyerr()?;
-
-        let response = client.get(&url).send().await.anyerr()?;
-
-        if !response.status().is_success() {
-            return Err(IoError::new(
-                ErrorKind::Other,
-                format!("GitHub API returned status: {}", response.status()),
-            ))
-            .anyerr();
-        }
-
-        let releases: Vec<GitHubRelease> = response.json().await.anyerr()?;
-
-        // Find the latest release that is not "rolling" and not a pre-release (beta, rc, etc.)
-        // Pre-release tags contain a hyphen after the version (e.g., "v0.0.3-beta", "0.1.0-rc.1")
-        // We check if the tag (after removing 'v' prefix) contains a hyphen
-        let release = releases
-            .into_iter()
-            .find(|r| {
-                if r.tag_name == "rolling" {
-                    return false;
-                }
-                // Remove 'v' prefix if present, then check for hyphens (pre-release indicator)
-                let version_part = r.tag_name.trim_start_matches('v');
-                !version_part.contains('-')
-            })
-            .ok_or_else(|| {
-                IoError::new(
-                    ErrorKind::NotFound,
-                    "No stable release found (excluding rolling and pre-releases)",
-                )
-            })
-            .anyerr()?;
+        let release = self.latest_release(settings.channel).await?;
 
         // Update last check time
         let mut settings = settings;
@@ -197,7 +250,7 @@ impl UpdateChecker {
 
         // Extract version from tag_name (format: "v0.0.3" or "0.0.3")
         let latest_version = Self::extract_version(&release.tag_name);
-        let current_version = Self::extract_version(&self.current_version);
+        let current_version = Self::extract_version(current_version);
 
         // Compare versions - if latest is newer, return update info
         if Self::is_newer_version(&latest_version, &current_version) {
@@ -220,6 +273,182 @@ impl UpdateChecker {
         }
     }
 
+    /// Fetch all releases, newest first
+    async fn fetch_releases(&self) -> Result<Vec<GitHubRelease>> {
+        let url = format!(
+            "{}/repos/{}/{}/releases",
+            GITHUB_API_BASE, REPO_OWNER, REPO_NAME
+        );
+
+        let response = http_client()?.get(&url).send().await.anyerr()?;
+
+        if !response.status().is_success() {
+            return Err(IoError::new(
+                ErrorKind::Other,
+                format!("GitHub API returned status: {}", response.status()),
+            ))
+            .anyerr();
+        }
+
+        response.json().await.anyerr()
+    }
+
+    /// The latest tagged release on `channel`, skipping the rolling release
+    async fn latest_release(&self, channel: UpdateChannel) -> Result<GitHubRelease> {
+        let releases = self.fetch_releases().await?;
+        Self::pick_release(releases, channel)
+            .ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::NotFound,
+                    format!("No {channel:?} release found (excluding rolling)"),
+                )
+            })
+            .anyerr()
+    }
+
+    fn pick_release(releases: Vec<GitHubRelease>, channel: UpdateChannel) -> Option<GitHubRelease> {
+        releases.into_iter().find(|r| {
+            r.tag_name != "rolling" && (channel == UpdateChannel::Beta || !r.is_prerelease())
+        })
+    }
+
+    /// The manifest of the latest release on `channel`, which must be signed
+    /// by the release key. A release without a [`MANIFEST_ASSET`] is offered
+    /// to everyone, with its installer for this platform but no checksum, so
+    /// it can't be installed from the app.
+    pub(crate) async fn release_manifest(&self, channel: UpdateChannel) -> Result<ReleaseManifest> {
+        let release = self.latest_release(channel).await?;
+        if let Ok(asset) = release.asset(MANIFEST_ASSET) {
+            let data = download(&asset.browser_download_url).await?;
+            let signature = release.asset(MANIFEST_SIGNATURE_ASSET)?;
+            let signature = download(&signature.browser_download_url).await?;
+            return ReleaseManifest::from_signed(&release_key()?, &data, &signature);
+        }
+
+        let asset = self.find_platform_asset(&release.assets)?;
+        let published_at = DateTime::parse_from_rfc3339(&release.published_at)
+            .std_context("failed to parse published_at")?
+            .with_timezone(&Utc);
+        Ok(ReleaseManifest {
+            version: Self::extract_version(&release.tag_name),
+            name: release.name.clone(),
+            notes: release.body.clone().unwrap_or_default(),
+            published_at,
+            rollout_percent: 100,
+            downloads: [(platform_key(), asset.browser_download_url.clone())].into(),
+            sha256: Default::default(),
+        })
+    }
+
+    /// Check for a newer CLI binary on `channel`. Unlike
+    /// [`Self::check_for_updates`], this is done even with auto-update
+    /// turned off, as the user asked for it.
+    pub async fn check_cli_update(&self, channel: UpdateChannel) -> Result<Option<CliUpdate>> {
+        let release = self.latest_release(channel).await?;
+        let latest_version = Self::extract_version(&release.tag_name);
+        let current_version = Self::extract_version(current_version);
+        if !Self::is_newer_version(&latest_version, &current_version) {
+            return Ok(None);
+        }
+
+        let asset_name = cli_asset_name();
+        let asset = release.asset(&asset_name)?;
+        let published_at = DateTime::parse_from_rfc3339(&release.published_at)
+            .std_context("failed to parse published_at")?
+            .with_timezone(&Utc);
+        Ok(Some(CliUpdate {
+            version: latest_version,
+            release_name: release.name.clone(),
+            published_at,
+            download_url: asset.browser_download_url.clone(),
+            download_size: asset.size,
+            asset_name,
+            manifest_url: release
+                .asset(CLI_MANIFEST_ASSET)?
+                .browser_download_url
+                .clone(),
+            signature_url: release
+                .asset(CLI_SIGNATURE_ASSET)?
+                .browser_download_url
+                .clone(),
+        }))
+    }
+
+    /// Download `update`, verify it and replace the executable at `exe`
+    /// with it.
+    ///
+    /// The new binary is written next to `exe` and renamed over it, so a
+    /// failed update leaves the old one in place. On Windows, where a running
+    /// executable can't be replaced, the old one is moved aside to
+    /// `<name>.old` first.
+    pub async fn install_cli_update(&self, update: &CliUpdate, exe: &Path) -> Result<()> {
+        let key = release_key()?;
+        let manifest = download(&update.manifest_url).await?;
+        let signature = download(&update.signature_url).await?;
+        let expected =
+            Self::verify_cli_manifest(&key, &manifest, &signature, update, &self.current_version)?;
+
+        let binary = download(&update.download_url).await?;
+        let actual = iroh_blobs::Hash::new(&binary).to_hex();
+        if !actual.eq_ignore_ascii_case(&expected) {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Checksum mismatch for {}", update.asset_name),
+            ))
+            .anyerr();
+        }
+
+        replace_executable(exe, &binary)
+            .await
+            .context("failed to replace executable")?;
+        Ok(())
+    }
+
+    /// Check that `manifest` is signed by `key` and is the manifest of
+    /// `update`, a version newer than `current_version`, and return the hash
+    /// it lists for the update's binary.
+    fn verify_cli_manifest(
+        key: &PublicKey,
+        manifest: &[u8],
+        signature: &[u8],
+        update: &CliUpdate,
+        current_version: &str,
+    ) -> Result<String> {
+        verify_signature(key, manifest, signature)?;
+        let manifest: CliManifest =
+            serde_json::from_slice(manifest).std_context("invalid CLI manifest")?;
+        let version = Self::extract_version(&manifest.version);
+        if version != Self::extract_version(&update.version) {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The manifest is for version {version}, not {}",
+                    update.version
+                ),
+            ))
+            .anyerr();
+        }
+        let current_version = Self::extract_version(current_version);
+        if !Self::is_newer_version(&version, &current_version) {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Version {version} is not newer than {current_version}"),
+            ))
+            .anyerr();
+        }
+        manifest
+            .assets
+            .get(&update.asset_name)
+            .cloned()
+            .ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::NotFound,
+                    format!("{} is not in the manifest", update.asset_name),
+                )
+            })
+            .anyerr()
+    }
+
     /// Extract version string from tag (handles formats like "v0.0.3" or "0.0.3")
     fn extract_version(tag: &str) -> String {
         // Remove 'v' prefix if present
@@ -228,7 +457,7 @@ impl UpdateChecker {
 
     /// Compare semantic version strings - returns true if version1 > version2
     /// Handles semantic versions like "0.0.3", "0.1.0", "1.0.0", etc.
-    fn is_newer_version(version1: &str, version2: &str) -> bool {
+    pub(crate) fn is_newer_version(version1: &str, version2: &str) -> bool {
         let v1_parts = Self::parse_semantic_version(version1);
         let v2_parts = Self::parse_semantic_version(version2);
 
@@ -246,20 +475,49 @@ impl UpdateChecker {
         }
 
         match v1_parts.patch.cmp(&v2_parts.patch) {
-            std::cmp::Ordering::Greater => true,
-            std::cmp::Ordering::Less => false,
-            std::cmp::Ordering::Equal => {
-                // If versions are equal, check for pre-release/build metadata
-                // For now, if versions are equal, consider it not newer
-                false
+            std::cmp::Ordering::Greater => return true,
+            std::cmp::Ordering::Less => return false,
+            std::cmp::Ordering::Equal => {}
+        }
+
+        // A release is newer than its pre-releases, and pre-releases compare
+        // by their dot-separated parts, numbers numerically
+        match (v1_parts.pre, v2_parts.pre) {
+            (None, Some(_)) => true,
+            (Some(_), None) | (None, None) => false,
+            (Some(pre1), Some(pre2)) => {
+                Self::compare_pre_release(pre1, pre2) == std::cmp::Ordering::Greater
+            }
+        }
+    }
+
+    fn compare_pre_release(pre1: &str, pre2: &str) -> std::cmp::Ordering {
+        let mut parts1 = pre1.split('.');
+        let mut parts2 = pre2.split('.');
+        loop {
+            let ordering = match (parts1.next(), parts2.next()) {
+                (None, None) => return std::cmp::Ordering::Equal,
+                (None, Some(_)) => return std::cmp::Ordering::Less,
+                (Some(_), None) => return std::cmp::Ordering::Greater,
+                (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.cmp(b),
+                },
+            };
+            if ordering != std::cmp::Ordering::Equal {
+                return ordering;
             }
         }
     }
 
     /// Parse semantic version string (e.g., "0.0.3" or "0.0.3-beta")
-    fn parse_semantic_version(version: &str) -> VersionParts {
-        // Remove any pre-release or build metadata (everything after '-')
-        let version = version.split('-').next().unwrap_or(version);
+    fn parse_semantic_version(version: &str) -> VersionParts<'_> {
+        // Build metadata doesn't affect precedence
+        let version = version.split('+').next().unwrap_or(version);
+        let (version, pre) = match version.split_once('-') {
+            Some((version, pre)) => (version, Some(pre)),
+            None => (version, None),
+        };
 
         let parts: Vec<&str> = version.split('.').collect();
 
@@ -280,6 +538,7 @@ impl UpdateChecker {
             major,
             minor,
             patch,
+            pre,
         }
     }
 
@@ -356,3 +615,194 @@ impl UpdateChecker {
         &self.current_version
     }
 }
+
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent("DatumConnect/1.0")
+        .build()
+        .anyerr()
+}
+
+pub(crate) async fn download(url: &str) -> Result<Vec<u8>> {
+    let response = http_client()?.get(url).send().await.anyerr()?;
+    if !response.status().is_success() {
+        return Err(IoError::new(
+            ErrorKind::Other,
+            format!("Download failed with status: {}", response.status()),
+        ))
+        .anyerr();
+    }
+    Ok(response.bytes().await.anyerr()?.to_vec())
+}
+
+/// Name of the CLI binary for this platform in a release
+fn cli_asset_name() -> String {
+    let name = format!(
+        "datum-connect-{}-{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    if cfg!(windows) {
+        format!("{name}.exe")
+    } else {
+        name
+    }
+}
+
+/// The key releases are signed with, if this build has one.
+pub(crate) fn release_key() -> Result<PublicKey> {
+    let Some(key) = RELEASE_PUBLIC_KEY else {
+        return Err(IoError::new(
+            ErrorKind::Unsupported,
+            "This build can't verify releases; download the update from GitHub instead",
+        ))
+        .anyerr();
+    };
+    PublicKey::from_str(key).std_context("invalid release public key")
+}
+
+/// Check that `signature`, hex-encoded, is `key`'s signature of `message`
+pub(crate) fn verify_signature(key: &PublicKey, message: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = data_encoding::HEXLOWER_PERMISSIVE
+        .decode(signature.trim_ascii())
+        .std_context("invalid signature encoding")?;
+    let signature: [u8; 64] = signature
+        .try_into()
+        .map_err(|_| IoError::new(ErrorKind::InvalidData, "Signature has the wrong length"))
+        .anyerr()?;
+    key.verify(message, &Signature::from_bytes(&signature))
+        .map_err(|_| IoError::new(ErrorKind::InvalidData, "Release signature is invalid"))
+        .anyerr()
+}
+
+async fn replace_executable(exe: &Path, binary: &[u8]) -> std::io::Result<()> {
+    let file_name = exe.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = exe.with_file_name(format!(".{file_name}.update"));
+    tokio::fs::write(&tmp, binary).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+    #[cfg(windows)]
+    {
+        let old = exe.with_file_name(format!("{file_name}.old"));
+        let _ = tokio::fs::remove_file(&old).await;
+        tokio::fs::rename(exe, &old).await?;
+    }
+    let res = tokio::fs::rename(&tmp, exe).await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_base::SecretKey;
+
+    use super::*;
+
+    fn release(tag: &str) -> GitHubRelease {
+        GitHubRelease {
+            tag_name: tag.to_string(),
+            name: tag.to_string(),
+            published_at: "2026-01-01T00:00:00Z".to_string(),
+            prerelease: false,
+            body: None,
+            assets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn orders_pre_releases() {
+        let newer = UpdateChecker::is_newer_version;
+        assert!(newer("0.4.0", "0.4.0-beta.2"));
+        assert!(!newer("0.4.0-beta.2", "0.4.0"));
+        assert!(newer("0.4.0-beta.10", "0.4.0-beta.2"));
+        assert!(newer("0.4.0-rc.1", "0.4.0-beta.3"));
+        assert!(!newer("0.4.0+build.5", "0.4.0"));
+        assert!(newer("0.4.1-beta.1", "0.4.0"));
+    }
+
+    #[test]
+    fn picks_release_by_channel() {
+        let releases = || {
+            vec![
+                release("rolling"),
+                release("v0.5.0-beta.1"),
+                release("v0.4.2"),
+            ]
+        };
+        let stable = UpdateChecker::pick_release(releases(), UpdateChannel::Stable);
+        assert_eq!(stable.unwrap().tag_name, "v0.4.2");
+        let beta = UpdateChecker::pick_release(releases(), UpdateChannel::Beta);
+        assert_eq!(beta.unwrap().tag_name, "v0.5.0-beta.1");
+    }
+
+    #[test]
+    fn verifies_signed_cli_manifest() {
+        let key = SecretKey::generate(&mut rand::rng());
+        let signed = |version: &str| {
+            let manifest = CliManifest {
+                version: version.to_string(),
+                assets: [
+                    (
+                        "datum-connect-linux-x86_64".to_string(),
+                        "abc123".to_string(),
+                    ),
+                    (
+                        "datum-connect-windows-x86_64.exe".to_string(),
+                        "def456".to_string(),
+                    ),
+                ]
+                .into(),
+            };
+            let manifest = serde_json::to_vec(&manifest).unwrap();
+            let signature = data_encoding::HEXLOWER.encode(&key.sign(&manifest).to_bytes());
+            (manifest, signature)
+        };
+        let update = |version: &str, asset_name: &str| CliUpdate {
+            version: version.to_string(),
+            release_name: version.to_string(),
+            published_at: Utc::now(),
+            asset_name: asset_name.to_string(),
+            download_url: String::new(),
+            download_size: 0,
+            manifest_url: String::new(),
+            signature_url: String::new(),
+        };
+        let verify = |manifest: &(Vec<u8>, String), update: &CliUpdate| {
+            let (manifest, signature) = manifest;
+            UpdateChecker::verify_cli_manifest(
+                &key.public(),
+                manifest,
+                signature.as_bytes(),
+                update,
+                "0.4.0",
+            )
+        };
+
+        let v041 = signed("v0.4.1");
+        let linux = update("0.4.1", "datum-connect-linux-x86_64");
+        assert_eq!(verify(&v041, &linux).unwrap(), "abc123");
+        let windows = update("0.4.1", "datum-connect-windows-x86_64.exe");
+        assert_eq!(verify(&v041, &windows).unwrap(), "def456");
+        assert!(verify(&v041, &update("0.4.1", "datum-connect")).is_err());
+
+        // Another release's signed manifest doesn't vouch for this one.
+        assert!(verify(&v041, &update("0.5.0", "datum-connect-linux-x86_64")).is_err());
+        // Nor does a correctly signed manifest of a version that isn't newer.
+        let v040 = signed("0.4.0");
+        assert!(verify(&v040, &update("0.4.0", "datum-connect-linux-x86_64")).is_err());
+        let v031 = signed("0.3.1");
+        assert!(verify(&v031, &update("0.3.1", "datum-connect-linux-x86_64")).is_err());
+
+        let (manifest, signature) = &v041;
+        let other = SecretKey::generate(&mut rand::rng()).public();
+        assert!(verify_signature(&other, manifest, signature.as_bytes()).is_err());
+        let mut tampered = manifest.clone();
+        tampered[0] = b' ';
+        assert!(verify(&(tampered, signature.clone()), &linux).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Update notifications for the desktop app, with staged rollouts.
+//!
+//! The [`Updater`] reads the [`ReleaseManifest`] of the latest release on the
+//! configured [`UpdateChannel`](crate::UpdateChannel) and publishes an
+//! [`AvailableUpdate`] on a watch channel when that release is newer than
+//! this build and rolled out to this device, for the app to show a banner.
+//!
+//! Releases are rolled out in stages. A manifest names the percentage of
+//! devices it is offered to, and every device draws a
+//! [bucket](crate::UpdateSettings::rollout_bucket) in `0..100` once and keeps
+//! it. A device is offered a release when its bucket is below the
+//! percentage, so raising the percentage reaches more devices without taking
+//! the update away from any that were offered it already.
+//!
+//! The manifest is signed by the release key like the CLI's, see
+//! [`crate::update`], and lists the SHA-256 of each installer. An installer
+//! is only written to disk and started if it has that hash, so a release
+//! without a signed hash for this platform can be shown but not installed.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Utc};
+use iroh_base::PublicKey;
+use n0_error::{Result, StackResultExt, StdResultExt, anyerr};
+use n0_future::task::AbortOnDropHandle;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+use tracing::{Instrument, debug, error_span, info};
+
+use crate::{
+    Repo, UpdateChecker,
+    update::{download, verify_signature},
+};
+
+/// Name of the manifest in a release.
+pub const MANIFEST_ASSET: &str = "update-manifest.json";
+/// Name of the signature of [`MANIFEST_ASSET`] in a release.
+pub const MANIFEST_SIGNATURE_ASSET: &str = "update-manifest.json.sig";
+/// How often [`Updater::spawn`] looks whether a check is due.
+const POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// What a release says about itself and who gets it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ReleaseManifest {
+    pub version: String,
+    #[serde(default)]
+    pub name: String,
+    /// Release notes, in Markdown.
+    #[serde(default)]
+    pub notes: String,
+    pub published_at: DateTime<Utc>,
+    /// Share of devices offered the release, from 0 to 100.
+    #[serde(default = "default_rollout_percent")]
+    pub rollout_percent: u8,
+    /// Installer download URLs by [platform](platform_key).
+    #[serde(default)]
+    pub downloads: BTreeMap<String, String>,
+    /// Hex SHA-256 hashes of the installers, by [platform](platform_key).
+    #[serde(default)]
+    pub sha256: BTreeMap<String, String>,
+}
+
+fn default_rollout_percent() -> u8 {
+    100
+}
+
+impl ReleaseManifest {
+    /// Parse `data` if `signature`, hex-encoded, is `key`'s signature of it.
+    pub(crate) fn from_signed(key: &PublicKey, data: &[u8], signature: &[u8]) -> Result<Self> {
+        verify_signature(key, data, signature)?;
+        serde_json::from_slice(data).std_context("failed to parse release manifest")
+    }
+
+    /// Whether a device in `bucket` is offered the release.
+    pub fn includes(&self, bucket: u8) -> bool {
+        bucket < self.rollout_percent
+    }
+}
+
+/// The key of this platform in [`ReleaseManifest::downloads`], like
+/// `macos-aarch64`.
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// A release to offer, with its installer for this platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvailableUpdate {
+    pub manifest: ReleaseManifest,
+    pub download_url: String,
+    /// The signed hash of the installer, without which it isn't installed.
+    pub sha256: Option<String>,
+}
+
+impl AvailableUpdate {
+    /// The update `manifest` offers a device in `bucket` running
+    /// `current_version`, if any.
+    fn offered(manifest: ReleaseManifest, current_version: &str, bucket: u8) -> Option<Self> {
+        let current_version = current_version.trim_start_matches('v');
+        if !UpdateChecker::is_newer_version(&manifest.version, current_version) {
+            return None;
+        }
+        if !manifest.includes(bucket) {
+            debug!(
+                version = %manifest.version,
+                rollout = manifest.rollout_percent,
+                bucket,
+                "release not rolled out to this device yet"
+            );
+            return None;
+        }
+        let download_url = manifest.downloads.get(&platform_key())?.clone();
+        let sha256 = manifest.sha256.get(&platform_key()).cloned();
+        Some(Self {
+            manifest,
+            download_url,
+            sha256,
+        })
+    }
+
+    /// Check that `installer` is the one the manifest signed.
+    fn verify(&self, installer: &[u8]) -> Result<()> {
+        let Some(expected) = &self.sha256 else {
+            return Err(anyerr!(
+                "Release {} has no signed checksum for this platform",
+                self.manifest.version
+            ));
+        };
+        let actual = data_encoding::HEXLOWER.encode(&Sha256::digest(installer));
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(anyerr!("Checksum mismatch for the installer"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Updater {
+    checker: UpdateChecker,
+    repo: Repo,
+    available: Arc<watch::Sender<Option<AvailableUpdate>>>,
+}
+
+impl Updater {
+    pub fn new(repo: Repo) -> Self {
+        Self {
+            checker: UpdateChecker::new(repo.clone()),
+            repo,
+            available: Arc::new(watch::channel(None).0),
+        }
+    }
+
+    /// The update found by the last check, if any.
+    pub fn get(&self) -> Option<AvailableUpdate> {
+        self.available.borrow().clone()
+    }
+
+    pub fn watch(&self) -> watch::Receiver<Option<AvailableUpdate>> {
+        self.available.subscribe()
+    }
+
+    /// Check for an update now, whatever the check interval, and publish the
+    /// result.
+    pub async fn check(&self) -> Result<Option<AvailableUpdate>> {
+        let mut settings = self.checker.load_settings().await?;
+        let bucket = *settings
+            .rollout_bucket
+            .get_or_insert_with(|| rand::rng().random_range(0..100));
+        let manifest = self.checker.release_manifest(settings.channel).await?;
+        settings.last_check_time = Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+        self.checker.save_settings(&settings).await?;
+
+        let update = AvailableUpdate::offered(manifest, self.checker.current_version(), bucket);
+        self.available.send_replace(update.clone());
+        Ok(update)
+    }
+
+    /// Check whenever the check interval of the update settings has passed,
+    /// until dropped.
+    pub fn spawn(&self) -> AbortOnDropHandle<()> {
+        let this = self.clone();
+        let task = tokio::spawn(
+            async move {
+                loop {
+                    match this.checker.should_check().await {
+                        Ok(true) => match this.check().await {
+                            Ok(Some(update)) => {
+                                info!(version = %update.manifest.version, "update available")
+                            }
+                            Ok(None) => debug!("no update available"),
+                            Err(err) => debug!("Failed to check for updates: {err:#}"),
+                        },
+                        Ok(false) => {}
+                        Err(err) => debug!("Failed to read update settings: {err:#}"),
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+            .instrument(error_span!("updater")),
+        );
+        AbortOnDropHandle::new(task)
+    }
+
+    /// Download the installer of `update` into the repo, once it has the
+    /// signed hash.
+    pub async fn download(&self, update: &AvailableUpdate) -> Result<PathBuf> {
+        let file_name = update
+            .download_url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("update.bin");
+        let dir = self.repo.path().join("updates");
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .context("failed to create updates directory")?;
+        let path = dir.join(file_name);
+        let data = download(&update.download_url).await?;
+        if let Err(err) = update.verify(&data) {
+            tokio::fs::remove_file(&path).await.ok();
+            return Err(err);
+        }
+        tokio::fs::write(&path, data)
+            .await
+            .context("failed to write update file")?;
+        Ok(path)
+    }
+}
+
+/// Start installing the downloaded installer of `update`. The app should
+/// exit right after, for the installer to replace it.
+///
+/// The installer is checked against the signed hash again, as it may have
+/// changed on disk since it was downloaded; if it doesn't match it is
+/// deleted. A running AppImage is replaced with the new one, which is
+/// started in its place. Anywhere else the installer is opened.
+pub fn install(update: &AvailableUpdate, installer: &Path) -> Result<()> {
+    let data = std::fs::read(installer).std_context("failed to read installer")?;
+    if let Err(err) = update.verify(&data) {
+        std::fs::remove_file(installer).ok();
+        return Err(err);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(appimage) = std::env::var_os("APPIMAGE") {
+        use std::os::unix::fs::PermissionsExt;
+
+        let appimage = PathBuf::from(appimage);
+        // Copied next to it first: the repo may be on another file system.
+        let tmp = appimage.with_extension("update");
+        std::fs::copy(installer, &tmp).std_context("failed to copy AppImage")?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o755))
+            .std_context("failed to make AppImage executable")?;
+        std::fs::rename(&tmp, &appimage).std_context("failed to replace AppImage")?;
+        std::process::Command::new(&appimage)
+            .spawn()
+            .std_context("failed to start the new AppImage")?;
+        return Ok(());
+    }
+    open::that(installer).std_context("failed to open installer")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(version: &str, rollout_percent: u8) -> ReleaseManifest {
+        ReleaseManifest {
+            version: version.to_string(),
+            name: format!("v{version}"),
+            notes: "Fixes".to_string(),
+            published_at: Utc::now(),
+            rollout_percent,
+            downloads: [(platform_key(), "https://example.com/Datum.dmg".to_string())].into(),
+            sha256: [(
+                platform_key(),
+                data_encoding::HEXLOWER.encode(&Sha256::digest(b"installer")),
+            )]
+            .into(),
+        }
+    }
+
+    #[test]
+    fn offers_newer_releases_rolled_out_to_the_device() {
+        let offered = AvailableUpdate::offered(manifest("0.5.0", 100), "v0.4.0", 99).unwrap();
+        assert_eq!(offered.download_url, "https://example.com/Datum.dmg");
+        assert_eq!(
+            AvailableUpdate::offered(manifest("0.4.0", 100), "0.4.0", 0),
+            None
+        );
+
+        // A quarter of devices get the release.
+        assert!(AvailableUpdate::offered(manifest("0.5.0", 25), "0.4.0", 24).is_some());
+        assert_eq!(
+            AvailableUpdate::offered(manifest("0.5.0", 25), "0.4.0", 25),
+            None
+        );
+        assert_eq!(
+            AvailableUpdate::offered(manifest("0.5.0", 0), "0.4.0", 0),
+            None
+        );
+
+        let mut other_platform = manifest("0.5.0", 100);
+        other_platform.downloads.clear();
+        assert_eq!(AvailableUpdate::offered(other_platform, "0.4.0", 0), None);
+    }
+
+    #[test]
+    fn installers_need_the_signed_hash() {
+        let offered = AvailableUpdate::offered(manifest("0.5.0", 100), "0.4.0", 0).unwrap();
+        offered.verify(b"installer").unwrap();
+        assert!(offered.verify(b"something else").is_err());
+
+        let mut unsigned = manifest("0.5.0", 100);
+        unsigned.sha256.clear();
+        let offered = AvailableUpdate::offered(unsigned, "0.4.0", 0).unwrap();
+        assert!(offered.verify(b"installer").is_err());
+    }
+
+    #[test]
+    fn manifests_must_be_signed_by_the_release_key() {
+        let key = iroh_base::SecretKey::generate(&mut rand::rng());
+        let data = serde_json::to_vec(&manifest("0.5.0", 100)).unwrap();
+        let signature = data_encoding::HEXLOWER.encode(&key.sign(&data).to_bytes());
+        let parsed = ReleaseManifest::from_signed(&key.public(), &data, signature.as_bytes());
+        assert_eq!(parsed.unwrap().version, "0.5.0");
+
+        let other = iroh_base::SecretKey::generate(&mut rand::rng()).public();
+        assert!(ReleaseManifest::from_signed(&other, &data, signature.as_bytes()).is_err());
+        let mut tampered = data.clone();
+        tampered[1] = b' ';
+        assert!(
+            ReleaseManifest::from_signed(&key.public(), &tampered, signature.as_bytes()).is_err()
+        );
+    }
+
+    #[test]
+    fn reads_manifest_with_defaults() {
+        let manifest: ReleaseManifest =
+            serde_json::from_str(r#"{"version": "0.5.0", "published_at": "2026-10-01T12:00:00Z"}"#)
+                .unwrap();
+        assert_eq!(manifest.rollout_percent, 100);
+        assert!(manifest.downloads.is_empty());
+        assert!(manifest.includes(99));
+    }
+}
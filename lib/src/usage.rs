@@ -0,0 +1,468 @@
+//! Per-tunnel transfer accounting and quotas.
+//!
+//! iroh counts traffic per endpoint, not per tunnel. The listen node samples
+//! the endpoint's byte counters and splits what was transferred since the
+//! last sample between the tunnels that were sent requests in the meantime,
+//! by request count. Samples without new requests go to the tunnels of the
+//! previous split for up to [`CARRY_OVER`], which covers downloads and open
+//! streams. Totals are exact while a single tunnel is busy and an estimate
+//! otherwise. They are stored in `usage.json` in the repo at most every
+//! [`SAVE_INTERVAL`].
+//!
+//! A tunnel with a [`TransferQuota`] is disabled once its usage reaches the
+//! quota, like an expired tunnel: its ticket is taken out of n0des and
+//! [`EventKind::TunnelQuotaExceeded`] is recorded. Cloud tunnels are
+//! additionally turned off in Datum Cloud by [`TunnelService::run_quotas`].
+//! Monthly usage starts over with each calendar month in UTC, but a disabled
+//! tunnel stays off until it is turned on again.
+//!
+//! Quotas are checked against the same usage the app shows, shares of split
+//! transfers included, so they are only as exact as the split. A tunnel
+//! sending many small responses while another serves a large download is
+//! charged part of the download, and may be turned off before it used its
+//! quota by itself; the other tunnel correspondingly later.
+//!
+//! [`TunnelService::run_quotas`]: crate::tunnels::TunnelService::run_quotas
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use iroh::EndpointId;
+use n0_error::{Result, StdResultExt};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{Repo, StateWrapper, events::EventKind, health};
+
+/// How long bytes without new requests are still counted for the tunnels
+/// that were last sent requests.
+const CARRY_OVER: Duration = Duration::from_secs(60);
+/// Most time between writes of the usage file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often quotas are checked.
+const TICK: Duration = Duration::from_secs(5);
+
+/// Bytes a tunnel transferred, in both directions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelUsage {
+    #[serde(default)]
+    pub total_bytes: u64,
+    /// The part of [`Self::total_bytes`] sent to clients.
+    #[serde(default)]
+    pub sent_bytes: u64,
+    /// The part of [`Self::total_bytes`] received from clients.
+    #[serde(default)]
+    pub received_bytes: u64,
+    /// The month [`Self::month_bytes`] counts, as `YYYY-MM` in UTC.
+    #[serde(default)]
+    pub month: String,
+    #[serde(default)]
+    pub month_bytes: u64,
+}
+
+impl TunnelUsage {
+    /// Bytes transferred in the month of `now`.
+    pub fn bytes_this_month(&self, now: DateTime<Utc>) -> u64 {
+        if self.month == month_of(now) {
+            self.month_bytes
+        } else {
+            0
+        }
+    }
+
+    fn add(&mut self, sent: u64, received: u64, now: DateTime<Utc>) {
+        let bytes = sent.saturating_add(received);
+        self.sent_bytes = self.sent_bytes.saturating_add(sent);
+        self.received_bytes = self.received_bytes.saturating_add(received);
+        let month = month_of(now);
+        if self.month != month {
+            self.month = month;
+            self.month_bytes = 0;
+        }
+        self.month_bytes = self.month_bytes.saturating_add(bytes);
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+    }
+}
+
+fn month_of(now: DateTime<Utc>) -> String {
+    now.format("%Y-%m").to_string()
+}
+
+/// Parses a byte size like `500`, `10 MB` or `1.5G`. Units are binary like
+/// the sizes the app shows, so `1 KB` is 1024 bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(end);
+    let number: f64 = number.parse().std_context("invalid size")?;
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => n0_error::bail_any!("unknown size unit {unit:?}"),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+/// How many bytes a tunnel may transfer before it is disabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransferQuota {
+    /// Per calendar month, in UTC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_bytes: Option<u64>,
+    /// Over the tunnel's lifetime.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+}
+
+/// A quota limit that usage reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl TransferQuota {
+    pub fn is_empty(&self) -> bool {
+        self.monthly_bytes.is_none() && self.total_bytes.is_none()
+    }
+
+    /// The first limit `usage` reached at `now`, if any. Bytes split with
+    /// other tunnels count with the tunnel's share, see the
+    /// [module docs](self).
+    pub fn exceeded(&self, usage: &TunnelUsage, now: DateTime<Utc>) -> Option<QuotaExceeded> {
+        let monthly = self.monthly_bytes.map(|limit| QuotaExceeded {
+            used: usage.bytes_this_month(now),
+            limit,
+        });
+        let total = self.total_bytes.map(|limit| QuotaExceeded {
+            used: usage.total_bytes,
+            limit,
+        });
+        [monthly, total]
+            .into_iter()
+            .flatten()
+            .find(|status| status.used >= status.limit)
+    }
+}
+
+#[derive(derive_more::Debug, Clone)]
+pub struct UsageLog {
+    path: Option<PathBuf>,
+    #[debug(skip)]
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    loaded: bool,
+    tunnels: BTreeMap<String, TunnelUsage>,
+    /// Requests per tunnel since the last transfer was split.
+    pending: HashMap<String, u64>,
+    /// The weights of the last split and when its requests came in.
+    last_split: Option<(Instant, HashMap<String, u64>)>,
+    dirty: bool,
+    saved_at: Option<Instant>,
+}
+
+impl UsageLog {
+    /// A log persisted at `path`. The file is read on first use.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self::new(Some(path.into()))
+    }
+
+    /// A log that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::new(None)
+    }
+
+    fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            inner: Default::default(),
+        }
+    }
+
+    pub fn get(&self, tunnel_id: &str) -> Option<TunnelUsage> {
+        self.lock().tunnels.get(tunnel_id).cloned()
+    }
+
+    /// Note a request to a tunnel, for splitting the next transfer.
+    pub fn record_request(&self, tunnel_id: &str) {
+        *self
+            .lock()
+            .pending
+            .entry(tunnel_id.to_string())
+            .or_default() += 1;
+    }
+
+    /// Usage of all tunnels that transferred anything.
+    pub fn snapshot(&self) -> BTreeMap<String, TunnelUsage> {
+        self.lock().tunnels.clone()
+    }
+
+    /// Split the bytes the endpoint sent and received since the last call
+    /// between the tunnels that were sent requests, see the
+    /// [module docs](self).
+    pub fn record_transfer(&self, sent: u64, received: u64, now: DateTime<Utc>) {
+        let mut inner = self.lock();
+        if !inner.pending.is_empty() {
+            let pending = std::mem::take(&mut inner.pending);
+            inner.last_split = Some((Instant::now(), pending));
+        }
+        if sent == 0 && received == 0 {
+            return;
+        }
+        let Some((at, weights)) = &inner.last_split else {
+            return;
+        };
+        if at.elapsed() > CARRY_OVER {
+            inner.last_split = None;
+            return;
+        }
+        let sent = split(sent, weights);
+        let received = split(received, weights)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        for (tunnel_id, sent) in sent {
+            let received = received.get(&tunnel_id).copied().unwrap_or_default();
+            let usage = inner.tunnels.entry(tunnel_id).or_default();
+            usage.add(sent, received, now);
+        }
+        self.save(&mut inner, false);
+    }
+
+    /// Forget a tunnel's usage.
+    pub fn remove(&self, tunnel_id: &str) {
+        let mut inner = self.lock();
+        inner.pending.remove(tunnel_id);
+        if inner.tunnels.remove(tunnel_id).is_some() {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write pending changes, whether or not [`SAVE_INTERVAL`] has passed.
+    pub fn flush(&self) {
+        let mut inner = self.lock();
+        if inner.dirty {
+            self.save(&mut inner, true);
+        }
+    }
+
+    /// Write the log if `now` is set or the last write is old enough.
+    fn save(&self, inner: &mut Inner, now: bool) {
+        inner.dirty = true;
+        let Some(path) = &self.path else {
+            return;
+        };
+        let due = inner
+            .saved_at
+            .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL);
+        if !now && !due {
+            return;
+        }
+        match write(path, &inner.tunnels) {
+            Ok(()) => {
+                inner.dirty = false;
+                inner.saved_at = Some(Instant::now());
+            }
+            Err(err) => warn!("Failed to persist usage to {}: {err:#}", path.display()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        let mut inner = self.inner.lock().expect("poisoned");
+        if !inner.loaded {
+            inner.loaded = true;
+            if let Some(path) = &self.path {
+                match std::fs::read(path) {
+                    Ok(data) => match serde_json::from_slice(&data) {
+                        Ok(tunnels) => inner.tunnels = tunnels,
+                        Err(err) => warn!("Ignoring unreadable {}: {err}", path.display()),
+                    },
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => warn!("Failed to read usage from {}: {err:#}", path.display()),
+                }
+            }
+        }
+        inner
+    }
+}
+
+/// `bytes` split by `weights`, with the rounding remainder going to the
+/// heaviest tunnel.
+fn split(bytes: u64, weights: &HashMap<String, u64>) -> Vec<(String, u64)> {
+    let sum: u64 = weights.values().sum();
+    if sum == 0 {
+        return Vec::new();
+    }
+    let mut shares = weights
+        .iter()
+        .map(|(id, weight)| {
+            let share = (bytes as u128 * *weight as u128 / sum as u128) as u64;
+            (id.clone(), share)
+        })
+        .collect::<Vec<_>>();
+    let remainder = bytes - shares.iter().map(|(_, share)| share).sum::<u64>();
+    if let Some((id, _)) = weights.iter().max_by_key(|(id, weight)| (**weight, *id))
+        && let Some((_, share)) = shares.iter_mut().find(|(other, _)| other == id)
+    {
+        *share += remainder;
+    }
+    shares
+}
+
+fn write(path: &PathBuf, tunnels: &BTreeMap<String, TunnelUsage>) -> std::io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(tunnels)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Disable enabled proxies whose usage reached their quota. Runs until
+/// aborted.
+pub(crate) async fn run(
+    state: StateWrapper,
+    repo: Repo,
+    n0des: Option<Arc<iroh_n0des::Client>>,
+    endpoint_id: EndpointId,
+) {
+    loop {
+        let now = Utc::now();
+        let over = state
+            .get()
+            .proxies
+            .iter()
+            .filter(|p| p.enabled)
+            .filter_map(|p| {
+                let usage = repo.usage().get(p.id())?;
+                let exceeded = p.transfer_quota?.exceeded(&usage, now)?;
+                Some((p.id().to_string(), exceeded))
+            })
+            .collect::<Vec<_>>();
+        for (tunnel_id, exceeded) in over {
+            match state
+                .update(&repo, |state| state.set_enabled(&tunnel_id, false))
+                .await
+            {
+                Ok(Some(proxy)) => {
+                    info!(%tunnel_id, used = exceeded.used, "tunnel reached its transfer quota");
+                    health::set_published(n0des.as_deref(), &proxy, endpoint_id, false).await;
+                    repo.events().record(EventKind::TunnelQuotaExceeded {
+                        tunnel_id,
+                        used: exceeded.used,
+                        limit: exceeded.limit,
+                    });
+                }
+                Ok(None) => {}
+                Err(err) => warn!(%tunnel_id, "Failed to disable tunnel over quota: {err:#}"),
+            }
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn splits_transfers_by_requests() {
+        let log = UsageLog::in_memory();
+        let now = Utc.with_ymd_and_hms(2026, 3, 31, 23, 0, 0).unwrap();
+        // Nothing to attribute before the first request.
+        log.record_transfer(500, 0, now);
+        assert!(log.get("a").is_none());
+
+        log.record_request("a");
+        log.record_request("a");
+        log.record_request("b");
+        log.record_transfer(1000, 0, now);
+        // Later bytes go to the same tunnels until new requests come in.
+        log.record_transfer(0, 301, now);
+        let a = log.get("a").unwrap();
+        let b = log.get("b").unwrap();
+        assert_eq!(a.total_bytes + b.total_bytes, 1301);
+        assert_eq!(b.total_bytes, 333 + 100);
+        assert_eq!(a.bytes_this_month(now), a.total_bytes);
+        assert_eq!(b.sent_bytes, 333);
+        assert_eq!(b.received_bytes, 100);
+
+        log.record_request("b");
+        let next_month = now + chrono::TimeDelta::hours(2);
+        log.record_transfer(25, 25, next_month);
+        let b = log.get("b").unwrap();
+        assert_eq!(b.total_bytes, 483);
+        assert_eq!(b.bytes_this_month(next_month), 50);
+        assert_eq!(log.get("a").unwrap().bytes_this_month(next_month), 0);
+    }
+
+    #[test]
+    fn quotas_count_shares_of_split_transfers() {
+        let log = UsageLog::in_memory();
+        let now = Utc::now();
+        log.record_request("a");
+        log.record_request("b");
+        log.record_transfer(600, 400, now);
+        let quota = TransferQuota {
+            monthly_bytes: Some(500),
+            total_bytes: None,
+        };
+        assert_eq!(
+            quota.exceeded(&log.get("a").unwrap(), now),
+            Some(QuotaExceeded {
+                used: 500,
+                limit: 500
+            })
+        );
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("10 MB").unwrap(), 10 << 20);
+        assert_eq!(parse_size("1.5G").unwrap(), 3 << 29);
+        assert_eq!(parse_size("2TiB").unwrap(), 2 << 40);
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn quota_is_exceeded_at_the_limit() {
+        let now = Utc::now();
+        let mut usage = TunnelUsage::default();
+        usage.add(1000, 0, now);
+        let quota = TransferQuota {
+            monthly_bytes: Some(2000),
+            total_bytes: None,
+        };
+        assert_eq!(quota.exceeded(&usage, now), None);
+        usage.add(0, 1000, now);
+        assert_eq!(
+            quota.exceeded(&usage, now),
+            Some(QuotaExceeded {
+                used: 2000,
+                limit: 2000
+            })
+        );
+        // A new month starts over.
+        let next_month = now + chrono::TimeDelta::days(32);
+        assert_eq!(quota.exceeded(&usage, next_month), None);
+        let quota = TransferQuota {
+            monthly_bytes: None,
+            total_bytes: Some(1500),
+        };
+        assert_eq!(quota.exceeded(&usage, next_month).unwrap().used, 2000);
+        assert!(TransferQuota::default().is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 
-use iroh::protocol::Router;
+use iroh::endpoint::Connection;
+use iroh::protocol::{AcceptError, ProtocolHandler, Router};
 use iroh::{Endpoint, SecretKey};
 use iroh_n0des::ApiSecret;
 use iroh_n0des::protocol::{
@@ -8,8 +9,17 @@ use iroh_n0des::protocol::{
     UnpublishTicket,
 };
 use irpc::WithChannels;
-use n0_error::Result;
-use tracing::info;
+use n0_error::{Result, StdResultExt, anyerr};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+/// Streams ticket changes to gateways. Not part of the n0des protocol; the
+/// messages mirror `lib/src/gateway/ticket_events.rs` in datum-connect.
+pub const TICKET_EVENTS_ALPN: &[u8] = b"datum-connect/ticket-events/0";
+
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
 
 pub async fn bind_and_start() -> Result<(ApiSecret, Router)> {
     let endpoint = Endpoint::bind().await?;
@@ -18,12 +28,14 @@ pub async fn bind_and_start() -> Result<(ApiSecret, Router)> {
 
 pub fn start(endpoint: Endpoint) -> Result<(ApiSecret, Router)> {
     let (tx, rx) = tokio::sync::mpsc::channel::<N0desMessage>(64);
-    tokio::task::spawn(server_actor(rx));
+    let (events, _) = broadcast::channel(256);
+    tokio::task::spawn(server_actor(rx, events.clone()));
 
     // Serve the n0des protocol over iroh via irpc.
     let handler = irpc_iroh::IrohProtocol::<iroh_n0des::protocol::N0desProtocol>::with_sender(tx);
     let router = Router::builder(endpoint.clone())
         .accept(ALPN, handler)
+        .accept(TICKET_EVENTS_ALPN, TicketEvents { events })
         .spawn();
 
     // Create an ApiSecret ticket string that clients can put into N0DES_API_SECRET.
@@ -33,7 +45,10 @@ pub fn start(endpoint: Endpoint) -> Result<(ApiSecret, Router)> {
     Ok((api_secret, router))
 }
 
-async fn server_actor(mut rx: tokio::sync::mpsc::Receiver<N0desMessage>) {
+async fn server_actor(
+    mut rx: tokio::sync::mpsc::Receiver<N0desMessage>,
+    events: broadcast::Sender<TicketChange>,
+) {
     let mut tickets = BTreeMap::new();
     while let Some(msg) = rx.recv().await {
         match msg {
@@ -54,16 +69,35 @@ async fn server_actor(mut rx: tokio::sync::mpsc::Receiver<N0desMessage>) {
                     ticket,
                     ..
                 } = inner;
-                tickets.insert((ticket_kind, name), ticket);
+                tickets.insert((ticket_kind.clone(), name.clone()), ticket);
                 tx.send(Ok(())).await.ok();
+                // No subscribers is not an error.
+                events
+                    .send(TicketChange {
+                        ticket_kind,
+                        name,
+                        published: true,
+                    })
+                    .ok();
             }
             N0desMessage::TicketUnpublish(WithChannels { inner, tx, .. }) => {
                 let UnpublishTicket {
                     name, ticket_kind, ..
                 } = inner;
                 info!("ticket unpublish: kind={ticket_kind} name={name}");
-                let existed = tickets.remove(&(ticket_kind, name)).is_some();
+                let existed = tickets
+                    .remove(&(ticket_kind.clone(), name.clone()))
+                    .is_some();
                 tx.send(Ok(existed)).await.ok();
+                if existed {
+                    events
+                        .send(TicketChange {
+                            ticket_kind,
+                            name,
+                            published: false,
+                        })
+                        .ok();
+                }
             }
             N0desMessage::TicketGet(WithChannels { inner, tx, .. }) => {
                 let GetTicket {
@@ -103,3 +137,69 @@ async fn server_actor(mut rx: tokio::sync::mpsc::Receiver<N0desMessage>) {
         }
     }
 }
+
+#[derive(Debug, Clone)]
+struct TicketChange {
+    ticket_kind: String,
+    name: String,
+    published: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subscribe {
+    ticket_kind: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TicketEvent<'a> {
+    name: &'a str,
+    published: bool,
+}
+
+/// Sends every change to a ticket of the kind a subscriber asked for, until
+/// it goes away. A subscriber that falls behind is disconnected.
+#[derive(Debug, Clone)]
+struct TicketEvents {
+    events: broadcast::Sender<TicketChange>,
+}
+
+impl TicketEvents {
+    async fn serve(&self, connection: Connection) -> Result<()> {
+        let mut changes = self.events.subscribe();
+        let (mut send, mut recv) = connection.accept_bi().await.anyerr()?;
+        let len = recv.read_u32().await? as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(anyerr!("Subscribe message too large: {len} bytes"));
+        }
+        let mut data = vec![0; len];
+        recv.read_exact(&mut data).await?;
+        let Subscribe { ticket_kind } =
+            serde_json::from_slice(&data).std_context("Invalid subscribe message")?;
+        info!("ticket events: kind={ticket_kind}");
+        loop {
+            let change = tokio::select! {
+                change = changes.recv() => change.anyerr()?,
+                _ = connection.closed() => return Ok(()),
+            };
+            if change.ticket_kind != ticket_kind {
+                continue;
+            }
+            let event = TicketEvent {
+                name: &change.name,
+                published: change.published,
+            };
+            let data = serde_json::to_vec(&event).anyerr()?;
+            send.write_u32(data.len() as u32).await?;
+            send.write_all(&data).await?;
+        }
+    }
+}
+
+impl ProtocolHandler for TicketEvents {
+    async fn accept(&self, connection: Connection) -> Result<(), AcceptError> {
+        if let Err(err) = self.serve(connection).await {
+            debug!("ticket event stream ended: {err:#}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+## Tunnels list
+
+tunnels-greeting-fallback-name = du
+tunnels-empty = Hallo { $name }, möchtest du einen lokalen Dienst sicher im Internet bereitstellen?
+tunnels-add-new = Neu hinzufügen
+tunnels-search-placeholder = Tunnel suchen...
+tunnels-permission-denied = Du kannst die Tunnel in diesem Projekt nicht ändern. Bitte einen Projekt-Admin um Zugriff.
+tunnels-select = Auswählen
+tunnels-select-all = Alle auswählen
+tunnels-select-cancel = Abbrechen
+tunnels-selected = { $count } ausgewählt
+tunnels-bulk-enable = Aktivieren
+tunnels-bulk-disable = Deaktivieren
+tunnels-bulk-delete = Löschen
+tunnels-bulk-delete-confirm = { $count } Tunnel löschen?
+tunnels-bulk-failed = { $count ->
+    [one] 1 Tunnel konnte nicht geändert werden:
+   *[other] { $count } Tunnel konnten nicht geändert werden:
+}
+tunnel-endpoint-unknown = unbekannt
+tunnel-hostname-provisioning = Hostname wird eingerichtet...
+tunnel-menu-view = Anzeigen
+tunnel-menu-edit = Bearbeiten
+tunnel-menu-share = Teilen
+tunnel-menu-delete = Löschen
+tunnel-menu-copy-url = URL kopieren
+tunnel-menu-copy-ticket = Ticket kopieren
+tunnel-menu-copy-markdown = Als Markdown kopieren
+tunnel-menu-copy-slack = Für Slack kopieren
+tunnel-copied = Kopiert
+tunnels-filter-all = Alle
+tunnels-group-by-tag = Nach Tag gruppieren
+tunnels-untagged = Ohne Tag
+tunnels-sort-recent = Zuletzt verwendet
+tunnels-sort-name = Name
+tunnels-sort-status = Status
+tunnels-group-count = { $count ->
+    [one] 1 Tunnel
+   *[other] { $count } Tunnel
+}
+
+## Add tunnel
+
+tunnel-serve-folder = Ordner bereitstellen
+tunnel-serve-folder-description = Die Dateien eines Ordners auf diesem Gerät teilen, statt einen Port weiterzuleiten.
+tunnel-folder-path = Bereitzustellender Ordner
+tunnel-folder-placeholder = z. B. /Users/ich/Sites/public
+tunnel-tags = Tags
+tunnel-tags-description = Durch Kommas getrennt, zum Gruppieren und Filtern von Tunneln.
+tunnel-tags-placeholder = z. B. web, staging
+tunnel-expiry = Läuft ab
+tunnel-expiry-description = Schaltet den Tunnel nach dieser Zeit selbst ab, z. B. für eine vorübergehende Freigabe.
+tunnel-already-listening = { $address } wird bereits von Tunnel { $tunnel } bereitgestellt.
+tunnel-expiry-never = Nie
+tunnel-expiry-hour = In 1 Stunde
+tunnel-expiry-day = In 1 Tag
+tunnel-expiry-week = In 7 Tagen
+tunnel-expiry-current = In { $duration }
+tunnel-expires-in = Läuft ab in { $duration }
+tunnel-address-unix-socket = host:port oder der Pfad eines Unix-Sockets wie /var/run/app.sock.
+tunnel-detected-services = Läuft auf diesem Computer
+tunnel-detected-services-placeholder = Erkannten Dienst auswählen
+tunnel-detected-unknown-process = unbekannter Prozess
+
+## Quotas
+
+quota-tunnels = Tunnel
+quota-bandwidth = Bandbreite diesen Monat
+quota-usage = { $used } von { $limit }
+quota-remaining = Noch { $remaining } von { $limit } Tunneln in diesem Projekt frei
+quota-limit-reached = Tunnel-Limit erreicht
+quota-limit-reached-datum = Dieses Projekt hat bereits { $used } von { $limit } Tunneln. Lösche einen Tunnel oder erhöhe das Limit in Datum Cloud, um einen weiteren hinzuzufügen.
+quota-limit-reached-config = Dieses Projekt hat bereits { $used } von { $limit } Tunneln. Lösche einen Tunnel oder erhöhe tunnel_limits in der Konfiguration dieses Geräts, um einen weiteren hinzuzufügen.
+
+## Pending changes
+
+pending-title = Wartet auf Synchronisierung
+pending-create = „{ $label }“ erstellen
+pending-update = „{ $label }“ aktualisieren
+pending-enable = „{ $label }“ einschalten
+pending-disable = „{ $label }“ ausschalten
+pending-delete = „{ $label }“ löschen
+pending-queued = Offline
+pending-conflict = Konflikt
+pending-failed = Fehlgeschlagen
+pending-discard = Verwerfen
+
+## Tunnel bandwidth
+
+bandwidth-back = Zurück zur Tunnelliste
+bandwidth-load-failed = Bandbreite konnte nicht geladen werden
+bandwidth-tunnel-not-found = Tunnel nicht gefunden
+bandwidth-tunnel-load-error = Tunnel konnte nicht geladen werden: { $error }
+bandwidth-send = Senden
+bandwidth-receive = Empfangen
+bandwidth-rate = { $amount }/s
+bandwidth-latency = Latenz
+bandwidth-latency-range = { $min }–{ $max } ms über { $clients } Clients, nur der Tunnel
+bandwidth-latency-none = In letzter Zeit keine verbundenen Clients
+bandwidth-live = Live
+bandwidth-hour = 1 Std.
+bandwidth-day = 24 Std.
+bandwidth-week = 7 Tage
+bandwidth-history-empty = Für diesen Zeitraum wurde noch kein Datenverkehr aufgezeichnet
+bandwidth-export = Als CSV exportieren
+bandwidth-export-hint = Den Datenverkehr des gewählten Zeitraums im Download-Ordner speichern
+
+## Tunnel connections
+
+connections-title = Verbundene Clients
+connections-empty = Keine Clients verbunden
+connections-endpoint = Endpunkt
+connections-path = Pfad
+connections-rtt = RTT
+connections-connected = Verbunden
+connections-requests = Anfragen
+connections-rtt-value = { $ms } ms
+connections-age = vor { $age }
+tunnel-activity-title = Aktivität
+tunnel-activity-empty = Noch keine Aktivität aufgezeichnet
+audit-title = Änderungsverlauf
+audit-empty = Noch keine Änderungen aufgezeichnet
+audit-entry = { $by } hat den Tunnel { $action }
+audit-created = erstellt
+audit-updated = geändert
+audit-enabled = aktiviert
+audit-disabled = deaktiviert
+audit-restored = wiederhergestellt
+
+## Tunnel shares
+
+shares-title = Freigaben
+shares-create = Für { $duration } teilen
+shares-ttl-hour = 1 Stunde
+shares-ttl-day = 1 Tag
+shares-ttl-week = 7 Tage
+shares-empty = Keine aktiven Freigabelinks
+shares-expires-in = Läuft ab in { $duration }
+shares-never-expires = Läuft nie ab
+shares-uses = { $count ->
+    [one] 1 Nutzung
+   *[other] { $count } Nutzungen
+}
+shares-copy = Link kopieren
+shares-copied = Kopiert
+shares-revoke = Widerrufen
+
+## Joined tunnels
+
+join-reconnecting = Verbindung wird wiederhergestellt (Versuch { $attempt })…
+join-no-direct-path = Keine direkte Verbindung, Relays sind aus (Versuch { $attempt })…
+join-port-taken = Dieser Port wird bereits verwendet.
+join-use-free-port = Freien Port verwenden
+join-auto-port = Freien Port wählen, falls dieser belegt ist
+join-rebound = Der Port war belegt, daher wurde der Tunnel auf { $addr } verbunden.
+
+## Tunnel timeouts
+
+timeouts-title = Verbindungs-Timeouts
+timeouts-idle = Leerlauf-Timeout (Sekunden)
+timeouts-keepalive = Keepalive-Intervall (Sekunden)
+timeouts-default = Standard
+timeouts-invalid = Ganze Sekunden eingeben oder leer lassen für den Standard
+timeouts-hint = Keepalives halten ruhige Verbindungen durch NATs offen
+timeouts-restart = Gespeichert. Zum Übernehmen die App neu starten.
+timeouts-save = Speichern
+timeouts-saving = Speichern…
+schedule-title = Zeitplan
+schedule-windows = Aktive Zeiten (Ortszeit)
+schedule-hint = Zeitfenster mit ";" trennen. Leer lassen, damit der Tunnel immer aktiv bleibt.
+schedule-save = Speichern
+schedule-saving = Speichern…
+direct-only-title = Nur direkte Verbindungen
+direct-only-hint = Clients ablehnen, die diesen Tunnel nur über ein Relay erreichen
+quota-title = Datenkontingent
+quota-monthly = Pro Monat
+quota-total = Insgesamt
+quota-unlimited = Kein Limit
+quota-invalid = Eine Größe wie 500MB oder 10GB eingeben
+quota-used = { $month } in diesem Monat, { $total } insgesamt. Der Tunnel wird bei Erreichen eines Limits abgeschaltet. Verkehr, während andere Tunnel aktiv sind, wird nach Anfragen aufgeteilt und ist daher geschätzt.
+quota-save = Speichern
+quota-saving = Speichern…
+tunnel-quota-used = { $used } von { $limit }
+tunnel-quota-reached = Kontingent von { $limit } erreicht
+health-title = Zustandsprüfung
+health-check = Prüfung
+health-check-placeholder = tcp oder /healthz
+health-unpublish = Bei Ausfall nicht veröffentlichen
+health-hint = Prüft per TCP-Verbindung oder HTTP-Pfad, ob der lokale Dienst läuft
+health-invalid = tcp, einen Pfad mit / am Anfang oder nichts für keine Prüfung eingeben
+health-pending = Warte auf die erste Prüfung
+health-healthy = Erreichbar seit { $since }
+health-unhealthy = Nicht erreichbar: { $error }
+health-save = Speichern
+health-saving = Speichern…
+headers-title = Header-Regeln
+headers-hint = Eine Regel pro Zeile, z. B. request:set:X-Forwarded-Host=example.com
+headers-save = Speichern
+headers-saving = Speichern…
+auth-title = Passwortschutz
+auth-hint = Besucher nach Benutzername und Passwort fragen
+auth-protected-basic = Besucher melden sich als { $username } an
+auth-protected-bearer = Anfragen brauchen das Bearer-Token
+auth-username = Benutzername
+auth-password = Passwort
+auth-save = Speichern
+auth-saving = Speichern…
+ip-filter-title = IP-Freigabe- und Sperrlisten
+ip-filter-hint = Ein Eintrag pro Zeile, z. B. allow 203.0.113.0/24 oder deny 192.0.2.7
+ip-filter-invalid-action = Jede Zeile muss mit allow oder deny beginnen: { $line }
+ip-filter-save = Speichern
+ip-filter-saving = Speichern…
+
+## Settings
+
+settings-display = Anzeige
+settings-chart-performance = Schlankes Bandbreitendiagramm
+settings-chart-performance-description = Weniger Punkte mit geraden Linien zeichnen und seltener aktualisieren. Hilft auf langsameren Rechnern. Bei reduzierter Bewegung in den Systemeinstellungen wird das Diagramm ebenfalls seltener aktualisiert.
+settings-telemetry = Anonyme Nutzungsdaten teilen
+settings-telemetry-description = Anzahl erstellter Tunnel, Fehler nach Art und deine Plattform, einige Male am Tag an Datum gesendet, um Korrekturen zu priorisieren. Keine Tunnelnamen, Adressen oder Kontodaten. Beim Ausschalten wird alles noch nicht Gesendete gelöscht.
+settings-telemetry-view = Gesendete Daten ansehen
+settings-telemetry-hide = Bericht ausblenden
+settings-crash-reports = Absturzberichte senden
+settings-crash-reports-description = Wenn Datum abstürzt, wird ein Bericht mit dem Fehler, einem Backtrace, deiner Plattform und den letzten Logzeilen auf diesem Gerät gespeichert. Ist dies eingeschaltet, werden gespeicherte Berichte an Datum gesendet und danach gelöscht.
+settings-agent-status = Agent-Status mit deinem Projekt teilen
+settings-agent-status-description = Wird am Connector dieses Geräts in der Datum-Konsole angezeigt, damit dein Team sieht, wie es seinen Agents geht.
+settings-agent-status-version = App-Version
+settings-agent-status-os = Betriebssystem
+settings-agent-status-uptime = Startzeit
+settings-agent-status-active-tunnels = Anzahl aktiver Tunnel
+settings-agent-status-last-error = Letzter Fehler
+devices-back = Zurück zu den Einstellungen
+devices-title = Geräte
+devices-description = Jedes Gerät, das einen Tunnel bereitgestellt hat, hat einen Connector in seinem Projekt. Widerrufe die Connectoren von Geräten, die du nicht mehr nutzt.
+devices-loading = Geräte werden geladen…
+devices-empty = Keine Geräte gefunden
+devices-this-device = Dieses Gerät
+devices-stale = Seit über einer Woche nicht gesehen
+devices-never = nie
+devices-last-seen = Zuletzt gesehen { $at }
+devices-revoke = Widerrufen
+devices-revoking = Wird widerrufen…
+settings-devices-description = Sieh dir die mit deinen Projekten verbundenen Geräte an und widerrufe alte.
+settings-devices-view = Geräte verwalten
+settings-accounts = Konten
+settings-accounts-description = Bleib bei mehreren Datum-Konten angemeldet und wechsle zwischen ihnen. Die Tunnel dieses Geräts laufen weiter.
+settings-accounts-select = Konto auswählen
+settings-accounts-new = Neues Konto
+settings-accounts-add = Hinzufügen & anmelden
+secret-store-fallback-title = Schlüssel und Anmeldungen werden unverschlüsselt gespeichert
+secret-store-fallback-body = Auf diesem Gerät ist kein Schlüsselbund verfügbar. Setze DATUM_CONNECT_PASSPHRASE vor dem Start der App, um sie zu verschlüsseln, oder DATUM_CONNECT_SECRET_STORE=plaintext, um bewusst einfache Dateien zu verwenden.
+secret-store-fallback-dismiss = Ausblenden
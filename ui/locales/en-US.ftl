@@ -0,0 +1,252 @@
+## Tunnels list
+
+tunnels-greeting-fallback-name = there
+tunnels-empty = Hey { $name }, Want to safely expose a local service on the internet?
+tunnels-add-new = Add New
+tunnels-search-placeholder = Search tunnels...
+tunnels-permission-denied = You can't change tunnels in this project. Ask a project admin for access.
+tunnels-select = Select
+tunnels-select-all = Select all
+tunnels-select-cancel = Cancel
+tunnels-selected = { $count } selected
+tunnels-bulk-enable = Enable
+tunnels-bulk-disable = Disable
+tunnels-bulk-delete = Delete
+tunnels-bulk-delete-confirm = Delete { $count } tunnels?
+tunnels-bulk-failed = { $count ->
+    [one] 1 tunnel couldn't be changed:
+   *[other] { $count } tunnels couldn't be changed:
+}
+tunnel-endpoint-unknown = unknown
+tunnel-hostname-provisioning = Hostname Provisioning...
+tunnel-menu-view = View
+tunnel-menu-edit = Edit
+tunnel-menu-share = Share
+tunnel-menu-delete = Delete
+tunnel-menu-copy-url = Copy URL
+tunnel-menu-copy-ticket = Copy ticket
+tunnel-menu-copy-markdown = Copy as Markdown
+tunnel-menu-copy-slack = Copy for Slack
+tunnel-copied = Copied
+tunnels-filter-all = All
+tunnels-group-by-tag = Group by tag
+tunnels-untagged = Untagged
+tunnels-sort-recent = Recently used
+tunnels-sort-name = Name
+tunnels-sort-status = Status
+tunnels-group-count = { $count ->
+    [one] 1 tunnel
+   *[other] { $count } tunnels
+}
+
+## Add tunnel
+
+tunnel-serve-folder = Serve a folder
+tunnel-serve-folder-description = Share the files in a folder on this device instead of forwarding a port.
+tunnel-folder-path = Folder to serve
+tunnel-folder-placeholder = e.g. /Users/me/Sites/public
+tunnel-tags = Tags
+tunnel-tags-description = Comma separated, for grouping and filtering tunnels.
+tunnel-tags-placeholder = e.g. web, staging
+tunnel-expiry = Expires
+tunnel-expiry-description = Turn the tunnel off by itself after this long, e.g. for a temporary share.
+tunnel-already-listening = { $address } is already served by tunnel { $tunnel }.
+tunnel-expiry-never = Never
+tunnel-expiry-hour = In 1 hour
+tunnel-expiry-day = In 1 day
+tunnel-expiry-week = In 7 days
+tunnel-expiry-current = In { $duration }
+tunnel-expires-in = Expires in { $duration }
+tunnel-address-unix-socket = host:port, or the path of a unix socket such as /var/run/app.sock.
+tunnel-detected-services = Running on this computer
+tunnel-detected-services-placeholder = Pick a detected service
+tunnel-detected-unknown-process = unknown process
+
+## Quotas
+
+quota-tunnels = Tunnels
+quota-bandwidth = Bandwidth this month
+quota-usage = { $used } of { $limit }
+quota-remaining = { $remaining } of { $limit } tunnels left in this project
+quota-limit-reached = Tunnel limit reached
+quota-limit-reached-datum = This project already has { $used } of { $limit } tunnels. Delete a tunnel or raise the limit in Datum Cloud to add another.
+quota-limit-reached-config = This project already has { $used } of { $limit } tunnels. Delete a tunnel or raise tunnel_limits in this device's config to add another.
+
+## Pending changes
+
+pending-title = Waiting to sync
+pending-create = Create “{ $label }”
+pending-update = Update “{ $label }”
+pending-enable = Turn on “{ $label }”
+pending-disable = Turn off “{ $label }”
+pending-delete = Delete “{ $label }”
+pending-queued = Offline
+pending-conflict = Conflict
+pending-failed = Failed
+pending-discard = Discard
+
+## Tunnel bandwidth
+
+bandwidth-back = Back to Tunnels List
+bandwidth-load-failed = Couldn't load bandwidth
+bandwidth-tunnel-not-found = Tunnel not found
+bandwidth-tunnel-load-error = Failed to load tunnel: { $error }
+bandwidth-send = Send
+bandwidth-receive = Receive
+bandwidth-rate = { $amount }/s
+bandwidth-latency = Latency
+bandwidth-latency-range = { $min }–{ $max } ms across { $clients } clients, tunnel only
+bandwidth-latency-none = No clients connected recently
+bandwidth-live = Live
+bandwidth-hour = 1h
+bandwidth-day = 24h
+bandwidth-week = 7d
+bandwidth-history-empty = No traffic recorded in this period yet
+bandwidth-export = Export CSV
+bandwidth-export-hint = Save the traffic of the selected period to your downloads folder
+
+## Tunnel connections
+
+connections-title = Connected clients
+connections-empty = No clients connected
+connections-endpoint = Endpoint
+connections-path = Path
+connections-rtt = RTT
+connections-connected = Connected
+connections-requests = Requests
+connections-rtt-value = { $ms } ms
+connections-age = { $age } ago
+tunnel-activity-title = Activity
+tunnel-activity-empty = No activity recorded yet
+audit-title = Change history
+audit-empty = No changes recorded yet
+audit-entry = { $by } { $action } the tunnel
+audit-created = created
+audit-updated = changed
+audit-enabled = enabled
+audit-disabled = disabled
+audit-restored = restored
+
+## Tunnel shares
+
+shares-title = Shares
+shares-create = Share for { $duration }
+shares-ttl-hour = 1 hour
+shares-ttl-day = 1 day
+shares-ttl-week = 7 days
+shares-empty = No active share links
+shares-expires-in = Expires in { $duration }
+shares-never-expires = Never expires
+shares-uses = { $count ->
+    [one] 1 use
+   *[other] { $count } uses
+}
+shares-copy = Copy link
+shares-copied = Copied
+shares-revoke = Revoke
+
+## Joined tunnels
+
+join-reconnecting = Reconnecting (attempt { $attempt })…
+join-no-direct-path = No direct path, relays are off (attempt { $attempt })…
+join-port-taken = This port is already in use.
+join-use-free-port = Use a free port
+join-auto-port = Pick a free port if this one is taken
+join-rebound = The port was taken, so the tunnel was joined on { $addr }.
+
+## Tunnel timeouts
+
+timeouts-title = Connection timeouts
+timeouts-idle = Idle timeout (seconds)
+timeouts-keepalive = Keepalive interval (seconds)
+timeouts-default = Default
+timeouts-invalid = Enter a whole number of seconds, or leave empty for the default
+timeouts-hint = Keepalives hold quiet connections open through NATs
+timeouts-restart = Saved. Restart the app to apply.
+timeouts-save = Save
+timeouts-saving = Saving…
+schedule-title = Schedule
+schedule-windows = Active hours (local time)
+schedule-hint = Separate windows with ";". Leave empty to keep the tunnel on all the time.
+schedule-save = Save
+schedule-saving = Saving…
+direct-only-title = Direct connections only
+direct-only-hint = Refuse clients that can only reach this tunnel through a relay
+quota-title = Transfer quota
+quota-monthly = Per month
+quota-total = In total
+quota-unlimited = No limit
+quota-invalid = Enter a size like 500MB or 10GB
+quota-used = { $month } this month, { $total } in total. The tunnel turns off when a limit is reached. Traffic while other tunnels are busy is shared out between them by requests, so it is an estimate.
+quota-save = Save
+quota-saving = Saving…
+tunnel-quota-used = { $used } of { $limit }
+tunnel-quota-reached = Quota of { $limit } reached
+health-title = Health check
+health-check = Check
+health-check-placeholder = tcp or /healthz
+health-unpublish = Unpublish while unhealthy
+health-hint = Check that the local service is up, by TCP connect or HTTP path
+health-invalid = Enter tcp, a path starting with /, or leave empty for no check
+health-pending = Waiting for the first check
+health-healthy = Healthy for { $since }
+health-unhealthy = Unhealthy: { $error }
+health-save = Save
+health-saving = Saving…
+headers-title = Header rules
+headers-hint = One rule per line, e.g. request:set:X-Forwarded-Host=example.com
+headers-save = Save
+headers-saving = Saving…
+auth-title = Password protection
+auth-hint = Ask visitors for a username and password
+auth-protected-basic = Visitors sign in as { $username }
+auth-protected-bearer = Requests need the bearer token
+auth-username = Username
+auth-password = Password
+auth-save = Save
+auth-saving = Saving…
+ip-filter-title = IP allow and deny lists
+ip-filter-hint = One entry per line, e.g. allow 203.0.113.0/24 or deny 192.0.2.7
+ip-filter-invalid-action = Start each line with allow or deny: { $line }
+ip-filter-save = Save
+ip-filter-saving = Saving…
+
+## Settings
+
+settings-display = Display
+settings-chart-performance = Lightweight bandwidth chart
+settings-chart-performance-description = Draw fewer points with straight lines and refresh less often. Helps on slower machines. The chart also refreshes less often when your system asks for reduced motion.
+settings-telemetry = Share anonymous usage data
+settings-telemetry-description = Counts of tunnels created, errors by type and your platform, sent to Datum a few times a day to help prioritize fixes. No tunnel names, addresses or account details. Turning this off deletes anything not yet sent.
+settings-telemetry-view = View what's sent
+settings-telemetry-hide = Hide report
+settings-crash-reports = Send crash reports
+settings-crash-reports-description = When Datum crashes, a report with the error, a backtrace, your platform and the last log lines is saved on this device. With this on, saved reports are sent to Datum and then deleted.
+settings-agent-status = Share agent status with your project
+settings-agent-status-description = Shown on this device's connector in the Datum console, so your team can see how its agents are doing.
+settings-agent-status-version = App version
+settings-agent-status-os = Operating system
+settings-agent-status-uptime = Start time
+settings-agent-status-active-tunnels = Number of active tunnels
+settings-agent-status-last-error = Most recent error
+devices-back = Back to Settings
+devices-title = Devices
+devices-description = Every device that served a tunnel has a connector in its project. Revoke the ones of devices you no longer use.
+devices-loading = Loading devices…
+devices-empty = No devices found
+devices-this-device = This device
+devices-stale = Not seen for over a week
+devices-never = never
+devices-last-seen = Last seen { $at }
+devices-revoke = Revoke
+devices-revoking = Revoking…
+settings-devices-description = See the devices connected to your projects and revoke old ones.
+settings-devices-view = Manage Devices
+settings-accounts = Accounts
+settings-accounts-description = Stay logged in to several Datum accounts and switch between them. This device's tunnels keep running.
+settings-accounts-select = Select an account
+settings-accounts-new = New account
+settings-accounts-add = Add & Log In
+secret-store-fallback-title = Keys and logins are stored unencrypted
+secret-store-fallback-body = No keychain is available on this device. Set DATUM_CONNECT_PASSPHRASE before starting the app to encrypt them, or DATUM_CONNECT_SECRET_STORE=plaintext to keep plain files on purpose.
+secret-store-fallback-dismiss = Dismiss
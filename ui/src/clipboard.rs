@@ -0,0 +1,112 @@
+//! Opt-in clipboard watcher that surfaces tunnel tickets the user copied.
+//!
+//! The watcher polls the system clipboard while enabled. Clipboard contents
+//! never leave this module and are never logged: only a hash of the last
+//! seen contents is kept, so the same ticket is offered at most once per copy.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use lib::AdvertismentTicket;
+use n0_future::task::AbortOnDropHandle;
+use tokio::sync::watch;
+use tracing::debug;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Tickets are a few hundred bytes; anything much bigger is not worth parsing.
+const MAX_CANDIDATE_LEN: usize = 4096;
+
+#[derive(derive_more::Debug, Clone)]
+pub struct ClipboardWatch {
+    enabled: Arc<AtomicBool>,
+    #[debug(skip)]
+    candidate_tx: watch::Sender<Option<AdvertismentTicket>>,
+    _task: Arc<AbortOnDropHandle<()>>,
+}
+
+impl ClipboardWatch {
+    pub fn spawn(enabled: bool) -> Self {
+        let enabled = Arc::new(AtomicBool::new(enabled));
+        let (candidate_tx, _) = watch::channel(None);
+        let task = tokio::spawn(run(enabled.clone(), candidate_tx.clone()));
+        Self {
+            enabled,
+            candidate_tx,
+            _task: Arc::new(AbortOnDropHandle::new(task)),
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.dismiss();
+        }
+    }
+
+    /// Tickets detected on the clipboard. `None` once dismissed.
+    pub fn candidates(&self) -> watch::Receiver<Option<AdvertismentTicket>> {
+        self.candidate_tx.subscribe()
+    }
+
+    pub fn dismiss(&self) {
+        self.candidate_tx.send_replace(None);
+    }
+}
+
+async fn run(enabled: Arc<AtomicBool>, candidate_tx: watch::Sender<Option<AdvertismentTicket>>) {
+    // Hash of the contents we last acted on, and of the previous poll. A
+    // candidate is only offered once the contents were the same for two polls
+    // in a row, so rapid copy sequences don't flash prompts.
+    let mut handled: Option<u64> = None;
+    let mut previous: Option<u64> = None;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if !enabled.load(Ordering::Relaxed) {
+            previous = None;
+            continue;
+        }
+        let text = match tokio::task::spawn_blocking(read_clipboard).await {
+            Ok(Some(text)) => text,
+            _ => continue,
+        };
+        let hash = hash_str(&text);
+        let settled = previous == Some(hash);
+        previous = Some(hash);
+        if !settled || handled == Some(hash) {
+            continue;
+        }
+        handled = Some(hash);
+        if let Some(ticket) = parse_candidate(&text) {
+            debug!("clipboard: detected tunnel ticket");
+            candidate_tx.send_replace(Some(ticket));
+        }
+    }
+}
+
+fn read_clipboard() -> Option<String> {
+    let mut clipboard = arboard::Clipboard::new().ok()?;
+    let text = clipboard.get_text().ok()?;
+    (text.len() <= MAX_CANDIDATE_LEN).then_some(text)
+}
+
+fn parse_candidate(text: &str) -> Option<AdvertismentTicket> {
+    let text = text.trim();
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+    AdvertismentTicket::from_str(text).ok()
+}
+
+fn hash_str(s: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    s.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,17 +1,35 @@
+use std::time::Duration;
+
 use dioxus::events::FormEvent;
 use dioxus::prelude::*;
-use lib::{TcpProxyData, TunnelSummary};
+use lib::{tunnels::parse_tags, QuotaSource, TcpProxyData, TunnelSummary};
 
 use crate::{
     components::{
         dialog::{DialogContent, DialogRoot, DialogTitle},
         input::Input,
+        select::{
+            Select, SelectItemIndicator, SelectList, SelectOptionItem, SelectTrigger, SelectValue,
+        },
         switch::{Switch, SwitchThumb},
         Button, ButtonKind,
     },
+    i18n::{tr, translate},
     state::AppState,
+    util::humanize_duration,
 };
 
+/// Tunnel lifetimes offered in the dialog, by message id.
+const TUNNEL_EXPIRIES: &[(&str, Option<Duration>)] = &[
+    ("tunnel-expiry-never", None),
+    ("tunnel-expiry-hour", Some(Duration::from_secs(60 * 60))),
+    ("tunnel-expiry-day", Some(Duration::from_secs(24 * 60 * 60))),
+    (
+        "tunnel-expiry-week",
+        Some(Duration::from_secs(7 * 24 * 60 * 60)),
+    ),
+];
+
 /// Strips "http://" or "https://" from the front of a string (case-insensitive).
 fn strip_http_scheme(s: &str) -> String {
     let s = s.trim();
@@ -25,11 +43,16 @@ fn strip_http_scheme(s: &str) -> String {
     }
 }
 
-/// Validates tunnel address: must be host:port, no http/https scheme.
+/// Whether the address is a unix socket path rather than host:port.
+fn is_socket_path(s: &str) -> bool {
+    cfg!(unix) && s.trim().starts_with('/')
+}
+
+/// Validates tunnel address: must be host:port (or a unix socket path on unix), no http/https scheme.
 /// Returns None when empty (no error shown) or when valid; only shows error when there is input that is invalid.
 fn validate_tunnel_address(s: &str) -> Option<String> {
     let s = s.trim();
-    if s.is_empty() {
+    if s.is_empty() || is_socket_path(s) {
         return None;
     }
     let lower = s.to_lowercase();
@@ -48,6 +71,72 @@ fn validate_tunnel_address(s: &str) -> Option<String> {
     }
 }
 
+#[cfg(unix)]
+async fn create_socket_tunnel(
+    state: &AppState,
+    label: &str,
+    path: &str,
+) -> n0_error::Result<TunnelSummary> {
+    state
+        .tunnel_service()
+        .create_socket_active(label.trim(), std::path::Path::new(path.trim()))
+        .await
+}
+
+#[cfg(not(unix))]
+async fn create_socket_tunnel(
+    _state: &AppState,
+    _label: &str,
+    _path: &str,
+) -> n0_error::Result<TunnelSummary> {
+    n0_error::bail_any!("Unix socket targets are only supported on Linux and macOS")
+}
+
+/// Apply the comma separated `input` as the tunnel's tags, if they changed.
+async fn save_tags(
+    state: &AppState,
+    tunnel: TunnelSummary,
+    input: &str,
+) -> n0_error::Result<TunnelSummary> {
+    let tags = parse_tags(input);
+    if tags == tunnel.tags {
+        return Ok(tunnel);
+    }
+    let tunnel = state
+        .tunnel_service()
+        .set_tags_active(&tunnel.id, tags)
+        .await
+        .context("Failed to save tags")?;
+    Ok(tunnel)
+}
+
+/// Apply the expiry picked in the dialog, by message id. `None` keeps the
+/// tunnel's current expiry.
+async fn save_expiry(
+    state: &AppState,
+    tunnel: TunnelSummary,
+    choice: Option<String>,
+) -> n0_error::Result<TunnelSummary> {
+    let Some(ttl) = choice.and_then(|choice| {
+        TUNNEL_EXPIRIES
+            .iter()
+            .find(|(id, _)| *id == choice)
+            .map(|(_, ttl)| *ttl)
+    }) else {
+        return Ok(tunnel);
+    };
+    if ttl.is_none() && tunnel.expires_at.is_none() {
+        return Ok(tunnel);
+    }
+    let expires_at = ttl.map(|ttl| chrono::Utc::now() + ttl);
+    let tunnel = state
+        .tunnel_service()
+        .set_expiry_active(&tunnel.id, expires_at)
+        .await
+        .context("Failed to save expiry")?;
+    Ok(tunnel)
+}
+
 #[component]
 pub fn AddTunnelDialog(
     /// Pass a signal so the effect re-runs when open/initial_tunnel change and populates the form.
@@ -62,6 +151,10 @@ pub fn AddTunnelDialog(
     let mut address = use_signal(String::new);
     let mut label = use_signal(String::new);
     let mut basic_auth_enabled = use_signal(|| false);
+    let mut serve_folder = use_signal(|| false);
+    let mut folder = use_signal(String::new);
+    let mut tags = use_signal(String::new);
+    let mut expiry = use_signal(|| None::<String>);
 
     // Reset form when dialog closes (after success or cancel) so next open starts clean
     use_effect(move || {
@@ -69,6 +162,10 @@ pub fn AddTunnelDialog(
             label.set(String::new());
             address.set(String::new());
             basic_auth_enabled.set(false);
+            serve_folder.set(false);
+            folder.set(String::new());
+            tags.set(String::new());
+            expiry.set(None);
         }
     });
 
@@ -80,11 +177,13 @@ pub fn AddTunnelDialog(
         if let Some(t) = tunnel_opt {
             label.set(t.label.clone());
             address.set(strip_http_scheme(&t.endpoint));
+            tags.set(t.tags.join(", "));
         } else {
             // Create mode: empty form
             label.set(String::new());
             address.set(String::new());
             basic_auth_enabled.set(false);
+            tags.set(String::new());
         }
     });
 
@@ -95,11 +194,24 @@ pub fn AddTunnelDialog(
             .selected_context()
             .context("No project selected")?
             .project_id;
-        let tunnel = state
-            .tunnel_service()
-            .create_active(label().trim(), address().trim())
-            .await
-            .context("Failed to create tunnel")?;
+        let tunnel = if serve_folder() {
+            let folder = folder();
+            state
+                .tunnel_service()
+                .create_dir_active(label().trim(), std::path::Path::new(folder.trim()))
+                .await
+        } else if is_socket_path(&address()) {
+            create_socket_tunnel(&state, &label(), &address()).await
+        } else {
+            state
+                .tunnel_service()
+                .create_active(label().trim(), address().trim())
+                .await
+        }
+        .inspect_err(|_| state.telemetry().error("tunnel_create"))
+        .context("Failed to create tunnel")?;
+        let tunnel = save_tags(&state, tunnel, &tags()).await?;
+        let tunnel = save_expiry(&state, tunnel, expiry()).await?;
         state.upsert_tunnel(tunnel);
         state.bump_tunnel_refresh();
         state.heartbeat().register_project(project_id).await;
@@ -115,7 +227,10 @@ pub fn AddTunnelDialog(
             .tunnel_service()
             .update_active(&tunnel_id, label().trim(), address().trim())
             .await
+            .inspect_err(|_| state.telemetry().error("tunnel_update"))
             .context("Failed to update tunnel")?;
+        let updated = save_tags(&state, updated, &tags()).await?;
+        let updated = save_expiry(&state, updated, expiry()).await?;
         state.upsert_tunnel(updated);
         state.bump_tunnel_refresh();
         on_save_success.call(());
@@ -123,6 +238,18 @@ pub fn AddTunnelDialog(
         n0_error::Ok(())
     });
 
+    let current_expiry = initial_tunnel
+        .as_ref()
+        .and_then(|s| s())
+        .and_then(|t| t.expires_at);
+    let expiry_placeholder = match current_expiry {
+        Some(at) => tr!(
+            "tunnel-expiry-current",
+            duration = humanize_duration((at - chrono::Utc::now()).to_std().unwrap_or_default()),
+        ),
+        None => tr!("tunnel-expiry-never"),
+    };
+
     let is_edit_tunnel = initial_tunnel.as_ref().and_then(|s| s()).is_some();
     let is_edit = is_edit_tunnel;
     let title = if is_edit {
@@ -142,9 +269,76 @@ pub fn AddTunnelDialog(
         "Couldn't create tunnel"
     };
 
-    let address_validation = use_memo(move || validate_tunnel_address(&address()));
-    let address_invalid =
-        use_memo(move || address().trim().is_empty() || address_validation().is_some());
+    // Check quotas up front so a full project is explained before submit
+    // rather than as an API error after it.
+    let quotas = use_resource(move || async move {
+        if !open() {
+            return None;
+        }
+        consume_context::<AppState>()
+            .tunnel_service()
+            .quotas_active()
+            .await
+            .ok()
+    });
+    let tunnel_quota = if is_edit {
+        None
+    } else {
+        quotas().flatten().and_then(|quotas| quotas.tunnels)
+    };
+    let quota_block = tunnel_quota
+        .filter(|usage| usage.is_exhausted())
+        .map(|usage| match usage.source {
+            QuotaSource::Datum => tr!(
+                "quota-limit-reached-datum",
+                used = usage.used,
+                limit = usage.limit,
+            ),
+            QuotaSource::Config => tr!(
+                "quota-limit-reached-config",
+                used = usage.used,
+                limit = usage.limit,
+            ),
+        });
+
+    let address_validation = use_memo(move || {
+        validate_tunnel_address(&address()).or_else(|| {
+            // Another local tunnel to the same target is explained before submit.
+            let service = TcpProxyData::from_host_port_str(address().trim()).ok()?;
+            let editing = initial_tunnel.and_then(|s| s()).map(|tunnel| tunnel.id);
+            let existing = consume_context::<AppState>()
+                .listen_node()
+                .check_available(editing.as_deref(), &service.host, service.port)
+                .err()?;
+            Some(tr!(
+                "tunnel-already-listening",
+                address = existing.address,
+                tunnel = existing.tunnel_id,
+            ))
+        })
+    });
+    let address_invalid = use_memo(move || {
+        if serve_folder() {
+            folder().trim().is_empty()
+        } else {
+            address().trim().is_empty() || address_validation().is_some()
+        }
+    });
+    let submit_blocked = address_invalid() || quota_block.is_some();
+
+    // Services listening locally, offered as targets so they needn't be typed.
+    let listening = use_resource(move || async move {
+        let editing = initial_tunnel.and_then(|s| s()).is_some();
+        if !open() || editing {
+            return Vec::new();
+        }
+        lib::local_ports::scan().await
+    });
+    let listening_ports = listening().unwrap_or_default();
+    let picked_port = listening_ports
+        .iter()
+        .map(|port| port.target())
+        .find(|target| *target == address().trim());
 
     rsx! {
         DialogRoot {
@@ -161,19 +355,113 @@ pub fn AddTunnelDialog(
                         value: "{label}",
                         onchange: move |e: FormEvent| label.set(e.value()),
                     }
+                    if !is_edit {
+                        div { class: "flex flex-col gap-2",
+                            div { class: "flex items-center justify-between",
+                                label { class: "text-xs text-form-label/90", {tr!("tunnel-serve-folder")} }
+                                Switch {
+                                    checked: serve_folder(),
+                                    on_checked_change: move |checked| serve_folder.set(checked),
+                                    SwitchThumb {}
+                                }
+                            }
+                            div { class: "text-1xs text-form-description",
+                                {tr!("tunnel-serve-folder-description")}
+                            }
+                        }
+                    }
+                    if serve_folder() && !is_edit {
+                        Input {
+                            id: Some("tunnel-folder".into()),
+                            label: Some(tr!("tunnel-folder-path")),
+                            value: "{folder}",
+                            placeholder: tr!("tunnel-folder-placeholder"),
+                            autocomplete: "off",
+                            autocapitalize: "off",
+                            autocorrect: "off",
+                            oninput: move |e: FormEvent| folder.set(e.value()),
+                            onchange: move |e: FormEvent| folder.set(e.value()),
+                            r#type: "text",
+                        }
+                    } else {
+                        Input {
+                            id: Some("tunnel-address".into()),
+                            label: Some("Local address to forward".into()),
+                            description: if cfg!(unix) { Some(tr!("tunnel-address-unix-socket")) } else { None },
+                            value: "{address}",
+                            placeholder: "e.g. 127.0.0.1:5173",
+                            error: address_validation().clone(),
+                            autocomplete: "off",
+                            autocapitalize: "off",
+                            autocorrect: "off",
+                            oninput: move |e: FormEvent| address.set(e.value()),
+                            onchange: move |e: FormEvent| address.set(e.value()),
+                            r#type: "text",
+                        }
+                        if !is_edit && !listening_ports.is_empty() {
+                            div { class: "flex flex-col gap-2",
+                                label { class: "text-xs text-form-label/90", {tr!("tunnel-detected-services")} }
+                                Select {
+                                    value: picked_port.clone(),
+                                    on_value_change: move |value: Option<String>| {
+                                        if let Some(value) = value {
+                                            address.set(value);
+                                        }
+                                    },
+                                    placeholder: tr!("tunnel-detected-services-placeholder"),
+                                    disabled: false,
+                                    SelectTrigger { SelectValue {} }
+                                    SelectList {
+                                        for (i , port) in listening_ports.iter().enumerate() {
+                                            SelectOptionItem {
+                                                value: port.target(),
+                                                text_value: port.target(),
+                                                index: i,
+                                                span { class: "font-mono", {port.target()} }
+                                                span { class: "ml-2 text-foreground/60",
+                                                    {port.process.clone().unwrap_or_else(|| tr!("tunnel-detected-unknown-process"))}
+                                                }
+                                                SelectItemIndicator {}
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
                     Input {
-                        id: Some("tunnel-address".into()),
-                        label: Some("Local address to forward".into()),
-                        value: "{address}",
-                        placeholder: "e.g. 127.0.0.1:5173",
-                        error: address_validation().clone(),
+                        id: Some("tunnel-tags".into()),
+                        label: Some(tr!("tunnel-tags")),
+                        description: Some(tr!("tunnel-tags-description")),
+                        value: "{tags}",
+                        placeholder: tr!("tunnel-tags-placeholder"),
                         autocomplete: "off",
-                        autocapitalize: "off",
-                        autocorrect: "off",
-                        oninput: move |e: FormEvent| address.set(e.value()),
-                        onchange: move |e: FormEvent| address.set(e.value()),
+                        oninput: move |e: FormEvent| tags.set(e.value()),
+                        onchange: move |e: FormEvent| tags.set(e.value()),
                         r#type: "text",
                     }
+                    div { class: "flex flex-col gap-2",
+                        label { class: "text-xs text-form-label/90", {tr!("tunnel-expiry")} }
+                        Select {
+                            value: expiry(),
+                            on_value_change: move |value: Option<String>| expiry.set(value),
+                            placeholder: expiry_placeholder.clone(),
+                            disabled: false,
+                            SelectTrigger { SelectValue {} }
+                            SelectList {
+                                for (i , (id , _)) in TUNNEL_EXPIRIES.iter().copied().enumerate() {
+                                    SelectOptionItem {
+                                        value: id.to_string(),
+                                        text_value: translate(id, None),
+                                        index: i,
+                                        {translate(id, None)}
+                                        SelectItemIndicator {}
+                                    }
+                                }
+                            }
+                        }
+                        div { class: "text-1xs text-form-description", {tr!("tunnel-expiry-description")} }
+                    }
                     div { class: "flex flex-col gap-2",
                         div { class: "flex items-center justify-between",
                             label { class: "text-xs text-form-label/90", "Basic authentication" }
@@ -187,6 +475,16 @@ pub fn AddTunnelDialog(
                             "We'll automatically generate a username and password for you."
                         }
                     }
+                    if let Some(message) = quota_block.clone() {
+                        div { class: "rounded-md border border-amber-200 bg-amber-50 p-4 text-amber-900",
+                            div { class: "text-sm font-semibold", {tr!("quota-limit-reached")} }
+                            div { class: "text-sm mt-1", "{message}" }
+                        }
+                    } else if let Some(usage) = tunnel_quota {
+                        div { class: "text-1xs text-form-description",
+                            {tr!("quota-remaining", remaining = usage.remaining(), limit = usage.limit)}
+                        }
+                    }
                     if let Some(err) = save_tunnel
                         .value()
                         .and_then(|r| r.err())
@@ -200,9 +498,9 @@ pub fn AddTunnelDialog(
                     div { class: "flex items-center gap-2.5 pt-2 justify-start",
                         Button {
                             kind: ButtonKind::Primary,
-                            class: if save_tunnel.pending() || save_create_tunnel.pending() || address_invalid() { Some("opacity-60".to_string()) } else { None },
+                            class: if save_tunnel.pending() || save_create_tunnel.pending() || submit_blocked { Some("opacity-60".to_string()) } else { None },
                             onclick: move |_| {
-                                if address_invalid() {
+                                if submit_blocked {
                                     return;
                                 }
                                 if let Some(tunnel_id) = initial_tunnel
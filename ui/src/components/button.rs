@@ -8,7 +8,6 @@ pub enum ButtonKind {
     Primary,
     Secondary,
     Outline,
-    #[allow(unused)]
     Ghost,
 }
 
@@ -0,0 +1,90 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use dioxus::prelude::*;
+use lib::AdvertismentTicket;
+
+use crate::{
+    components::{
+        dialog::{DialogContent, DialogRoot, DialogTitle},
+        Button, ButtonKind,
+    },
+    state::AppState,
+    Route,
+};
+
+/// Offers to join a tunnel whose ticket the clipboard watcher picked up.
+#[component]
+pub fn ClipboardJoinDialog() -> Element {
+    let nav = use_navigator();
+    let state = consume_context::<AppState>();
+    let mut candidate = use_signal(|| None::<AdvertismentTicket>);
+
+    let state_for_watch = state.clone();
+    use_future(move || {
+        let state = state_for_watch.clone();
+        async move {
+            let mut rx = state.clipboard().candidates();
+            loop {
+                candidate.set(rx.borrow_and_update().clone());
+                if rx.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut join = use_action(move |ticket: AdvertismentTicket| async move {
+        let state = consume_context::<AppState>();
+        let bind = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        state.join_ticket(&ticket, bind, false).await?;
+        state.clipboard().dismiss();
+        nav.push(Route::JoinProxy {});
+        n0_error::Ok(())
+    });
+
+    let Some(ticket) = candidate() else {
+        return rsx! {};
+    };
+    let service = ticket.service().address();
+    let label = ticket.data.label().to_string();
+    let remote = ticket.endpoint.fmt_short().to_string();
+    let state_for_dismiss = state.clone();
+    let dismiss = move |_| state_for_dismiss.clipboard().dismiss();
+
+    rsx! {
+        DialogRoot {
+            open: true,
+            on_open_change: move |open: bool| {
+                if !open {
+                    state.clipboard().dismiss();
+                }
+            },
+            is_modal: true,
+            DialogContent {
+                DialogTitle { "Join this tunnel?" }
+                div { class: "mt-4 mb-6 flex flex-col gap-2",
+                    p { class: "text-sm text-foreground/80",
+                        "You copied a ticket for \"{label}\" ({service}) on {remote}."
+                    }
+                    if let Some(Err(err)) = join.value() {
+                        div { class: "rounded-md border border-red-200 bg-red-50 p-3 text-alert-red-dark text-xs break-words",
+                            "{err}"
+                        }
+                    }
+                }
+                div { class: "flex items-center gap-2.5 justify-end",
+                    Button {
+                        kind: ButtonKind::Ghost,
+                        onclick: dismiss,
+                        text: "Not now",
+                    }
+                    Button {
+                        kind: ButtonKind::Primary,
+                        onclick: move |_| join.call(ticket.clone()),
+                        text: if join.pending() { "Joining…" } else { "Join" },
+                    }
+                }
+            }
+        }
+    }
+}
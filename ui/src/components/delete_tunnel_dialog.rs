@@ -1,9 +1,12 @@
 use dioxus::prelude::*;
 use lib::TunnelSummary;
 
-use crate::components::{
-    dialog::{DialogContent, DialogRoot, DialogTitle},
-    Button, ButtonKind,
+use crate::{
+    components::{
+        dialog::{DialogContent, DialogRoot, DialogTitle},
+        Button, ButtonKind,
+    },
+    state::AppState,
 };
 
 #[component]
@@ -20,6 +23,25 @@ pub fn DeleteTunnelDialog(
         .map(|t| t.label.clone())
         .unwrap_or_default();
 
+    // What else goes down with this tunnel, so the prompt can say so.
+    let impact = use_resource(move || async move {
+        let tunnel = tunnel()?;
+        consume_context::<AppState>()
+            .tunnel_service()
+            .delete_impact_active(&tunnel.id)
+            .await
+            .inspect_err(|err| tracing::warn!("failed to load delete impact: {err:#}"))
+            .ok()
+    });
+    let impact = impact().flatten();
+    let hostnames = impact
+        .as_ref()
+        .map(|impact| impact.hostnames.join(", "))
+        .unwrap_or_default();
+    let deletes_connector = impact
+        .as_ref()
+        .is_some_and(|impact| impact.deletes_connector);
+
     let confirm_delete_handler = move |_| {
         if !delete_pending() {
             if let Some(tunnel_to_delete) = tunnel() {
@@ -55,7 +77,16 @@ pub fn DeleteTunnelDialog(
                 DialogTitle { "Delete tunnel" }
                 div { class: "mt-4 mb-6",
                     p { class: "text-sm text-foreground/80",
-                        "Are you sure you want to delete \"{tunnel_name}\"? This action cannot be undone."
+                        "Are you sure you want to delete \"{tunnel_name}\"?"
+                    }
+                    ul { class: "mt-3 list-disc pl-5 text-xs text-foreground/70 space-y-1",
+                        if !hostnames.is_empty() {
+                            li { "{hostnames} will stop serving traffic." }
+                        }
+                        if deletes_connector {
+                            li { "This is the last tunnel on this device, so its connector will be deleted too." }
+                        }
+                        li { "You can undo this for a few seconds after deleting." }
                     }
                     if let Some(err) = delete_result() {
                         div { class: "mt-4 rounded-md border border-red-200 bg-red-50 p-3 text-alert-red-dark",
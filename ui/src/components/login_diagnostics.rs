@@ -0,0 +1,109 @@
+use dioxus::prelude::*;
+use lib::datum_cloud::{LoginDiagnostics, REDIRECT_SERVER_PORTS};
+
+use crate::components::{Button, ButtonKind};
+
+/// Explains why a browser login isn't completing and offers ways around it.
+#[component]
+pub fn LoginDiagnosticsPanel(
+    diagnostics: LoginDiagnostics,
+    on_retry: EventHandler<()>,
+    on_device_code: EventHandler<()>,
+    device_code_pending: bool,
+) -> Element {
+    let mut copied = use_signal(|| false);
+    let ports = format!(
+        "{}-{}",
+        REDIRECT_SERVER_PORTS.start(),
+        REDIRECT_SERVER_PORTS.end()
+    );
+
+    let redirect_status = match (&diagnostics.bind_error, diagnostics.redirect_addr) {
+        (Some(err), _) => format!("Could not listen on any of ports {ports}: {err}"),
+        (None, Some(addr)) => format!("Listening on {addr}"),
+        (None, None) => "Not started".to_string(),
+    };
+    let browser_status = match diagnostics.browser_opened {
+        Some(true) => "Opened",
+        Some(false) => "Could not be opened automatically",
+        None => "Not opened yet",
+    };
+
+    let mut hints = Vec::new();
+    if diagnostics.bind_error.is_some() {
+        hints.push(format!(
+            "Other apps may already be using ports {ports}. Close other Datum Connect windows or CLI logins and retry."
+        ));
+    }
+    if diagnostics.timed_out {
+        let port = diagnostics
+            .redirect_addr
+            .map(|addr| addr.port())
+            .unwrap_or(*REDIRECT_SERVER_PORTS.start());
+        hints.push(format!(
+            "The browser never returned to localhost:{port}. A firewall, VPN or security tool may be blocking local connections."
+        ));
+    }
+    if diagnostics.browser_opened == Some(false) {
+        hints.push("Copy the login link below and open it in your browser.".to_string());
+    }
+    hints.push("If the redirect can't reach this app, log in with a code instead.".to_string());
+
+    let auth_url = diagnostics.auth_url.clone();
+    let copy_url = move |_| {
+        if let Some(url) = auth_url.clone() {
+            let eval = document::eval("navigator.clipboard.writeText(await dioxus.recv());");
+            let _ = eval.send(url);
+            copied.set(true);
+        }
+    };
+
+    rsx! {
+        div {
+            class: "rounded-lg border border-button-secondary-background bg-button-secondary-background/80 p-4 w-80 flex flex-col gap-3 text-xs",
+            style: "color: var(--glacier-mist-700);",
+            div { class: "text-sm font-semibold", "Login diagnostics" }
+            div { class: "flex flex-col gap-1",
+                div { "Redirect server: {redirect_status}" }
+                div { "Browser: {browser_status}" }
+            }
+            ul { class: "list-disc pl-4 flex flex-col gap-1",
+                for hint in hints {
+                    li { "{hint}" }
+                }
+            }
+            if diagnostics.auth_url.is_some() {
+                Button {
+                    kind: ButtonKind::Ghost,
+                    text: if copied() { "Login link copied" } else { "Copy login link" },
+                    onclick: copy_url,
+                }
+            }
+            if let Some(prompt) = diagnostics.device_code.as_ref() {
+                div { class: "flex flex-col gap-1",
+                    div {
+                        "Open "
+                        span { class: "font-mono break-all", "{prompt.verification_uri}" }
+                        " and enter:"
+                    }
+                    div { class: "text-lg font-mono font-semibold tracking-widest text-center",
+                        "{prompt.user_code}"
+                    }
+                }
+            }
+            div { class: "flex gap-2",
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: "Retry",
+                    onclick: move |_| on_retry.call(()),
+                }
+                Button {
+                    kind: ButtonKind::Ghost,
+                    class: if device_code_pending { Some("opacity-40 pointer-events-none".to_string()) } else { None },
+                    text: if device_code_pending { "Waiting for code…" } else { "Log in with a code" },
+                    onclick: move |_| on_device_code.call(()),
+                }
+            }
+        }
+    }
+}
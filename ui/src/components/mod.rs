@@ -5,25 +5,63 @@
 mod add_tunnel_dialog;
 mod bandwidth_timeseries_chart;
 mod button;
+mod clipboard_join_dialog;
 mod delete_tunnel_dialog;
 mod head;
 mod icon;
 mod invite_user_dialog;
+mod login_diagnostics;
+mod pending_changes;
+mod quota_bars;
+mod secret_store_banner;
+mod share_tunnel_dialog;
 mod splash;
+mod tunnel_activity;
+mod tunnel_audit;
+mod tunnel_auth;
+mod tunnel_connections;
+mod tunnel_direct_only;
+mod tunnel_headers;
+mod tunnel_health;
+mod tunnel_ip_filter;
+mod tunnel_quota;
+mod tunnel_schedule;
+mod tunnel_shares;
+mod tunnel_timeouts;
 mod typography;
-mod update_dialog;
+mod undo_delete_toast;
+mod update_banner;
 
 pub use add_tunnel_dialog::AddTunnelDialog;
 pub use button::Button;
 pub use button::ButtonKind;
+pub use clipboard_join_dialog::ClipboardJoinDialog;
 pub use delete_tunnel_dialog::DeleteTunnelDialog;
 pub use head::Head;
 pub use icon::{Icon, IconSource};
 pub use invite_user_dialog::InviteUserDialog;
+pub use login_diagnostics::LoginDiagnosticsPanel;
+pub use pending_changes::PendingChanges;
+pub use quota_bars::QuotaBars;
+pub use secret_store_banner::SecretStoreBanner;
+pub use share_tunnel_dialog::ShareTunnelDialog;
 pub use splash::Splash;
+pub use tunnel_activity::TunnelActivityPanel;
+pub use tunnel_audit::TunnelAuditPanel;
+pub use tunnel_auth::TunnelAuthPanel;
+pub use tunnel_connections::TunnelConnections;
+pub use tunnel_direct_only::TunnelDirectOnlyPanel;
+pub use tunnel_headers::TunnelHeadersPanel;
+pub use tunnel_health::TunnelHealthPanel;
+pub use tunnel_ip_filter::TunnelIpFilterPanel;
+pub use tunnel_quota::TunnelQuotaPanel;
+pub use tunnel_schedule::TunnelSchedulePanel;
+pub use tunnel_shares::TunnelShares;
+pub use tunnel_timeouts::TunnelTimeoutsPanel;
 #[allow(unused)]
 pub use typography::Subhead;
-pub use update_dialog::UpdateDialog;
+pub use undo_delete_toast::UndoDeleteToast;
+pub use update_banner::UpdateBanner;
 pub mod dialog;
 pub mod input;
 pub mod switch;
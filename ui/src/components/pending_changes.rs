@@ -0,0 +1,124 @@
+use dioxus::prelude::*;
+use lib::tunnels::{PendingMutation, PendingStatus, TunnelMutation};
+
+use crate::{
+    components::{Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Tunnel changes queued while Datum Cloud was unreachable.
+#[component]
+pub fn PendingChanges() -> Element {
+    let mut pending = use_signal(Vec::<PendingMutation>::new);
+    use_future(move || async move {
+        let state = consume_context::<AppState>();
+        let service = state.tunnel_service();
+        let Some(queue) = service.offline_queue() else {
+            return;
+        };
+        let mut rx = queue.watch().await;
+        loop {
+            pending.set(rx.borrow_and_update().clone());
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut discard = use_action(move |id: String| async move {
+        let state = consume_context::<AppState>();
+        if let Some(queue) = state.tunnel_service().offline_queue() {
+            queue.discard(&id).await?;
+        }
+        n0_error::Ok(())
+    });
+
+    let project_id = consume_context::<AppState>()
+        .selected_context()
+        .map(|selected| selected.project_id);
+    let entries = pending()
+        .into_iter()
+        .filter(|entry| Some(&entry.project_id) == project_id.as_ref())
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return rsx! {};
+    }
+    let tunnels = consume_context::<AppState>().tunnel_cache();
+
+    rsx! {
+        div { class: "bg-card-background border border-card-border rounded-lg px-4 py-3 mb-5 flex flex-col gap-2",
+            div { class: "text-xs text-icon-select font-normal", {tr!("pending-title")} }
+            for entry in entries {
+                div {
+                    key: "{entry.id}",
+                    class: "flex items-center justify-between gap-3",
+                    div { class: "flex items-center gap-2 min-w-0",
+                        span { class: "text-xs text-foreground truncate",
+                            {describe(&entry.mutation, &tunnels())}
+                        }
+                        StatusBadge { status: entry.status.clone() }
+                    }
+                    Button {
+                        kind: ButtonKind::Ghost,
+                        text: tr!("pending-discard"),
+                        onclick: move |_| {
+                            if !discard.pending() {
+                                discard.call(entry.id.clone());
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn StatusBadge(status: PendingStatus) -> Element {
+    let (text, detail, class) = match status {
+        PendingStatus::Queued => (
+            tr!("pending-queued"),
+            None,
+            "bg-foreground/10 text-foreground/70",
+        ),
+        PendingStatus::Conflict { reason } => (
+            tr!("pending-conflict"),
+            Some(reason),
+            "bg-red-50 text-alert-red-dark",
+        ),
+        PendingStatus::Failed { error } => (
+            tr!("pending-failed"),
+            Some(error),
+            "bg-red-50 text-alert-red-dark",
+        ),
+    };
+    rsx! {
+        span {
+            class: "text-1xs rounded-full px-2 py-0.5 shrink-0 {class}",
+            title: detail,
+            "{text}"
+        }
+    }
+}
+
+fn describe(mutation: &TunnelMutation, tunnels: &[lib::TunnelSummary]) -> String {
+    let label = |tunnel_id: &str| {
+        tunnels
+            .iter()
+            .find(|tunnel| tunnel.id == tunnel_id)
+            .map_or_else(|| tunnel_id.to_string(), |tunnel| tunnel.label.clone())
+    };
+    match mutation {
+        TunnelMutation::Create { label, .. } => tr!("pending-create", label = label.clone()),
+        TunnelMutation::Update { label, .. } => tr!("pending-update", label = label.clone()),
+        TunnelMutation::SetEnabled {
+            tunnel_id,
+            enabled: true,
+        } => tr!("pending-enable", label = label(tunnel_id)),
+        TunnelMutation::SetEnabled { tunnel_id, .. } => {
+            tr!("pending-disable", label = label(tunnel_id))
+        }
+        TunnelMutation::Delete { tunnel_id } => tr!("pending-delete", label = label(tunnel_id)),
+    }
+}
@@ -0,0 +1,64 @@
+use dioxus::prelude::*;
+use lib::{ProjectQuotas, QuotaUsage};
+
+use crate::{
+    i18n::{format_integer, tr},
+    util::humanize_bytes,
+};
+
+/// Usage/limit bars for the selected project's quotas.
+#[component]
+pub fn QuotaBars(quotas: ProjectQuotas) -> Element {
+    if quotas.tunnels.is_none() && quotas.bandwidth.is_none() {
+        return rsx! {};
+    }
+    rsx! {
+        div { class: "bg-card-background border border-card-border rounded-lg px-4 py-3 mb-5 flex flex-col gap-3",
+            if let Some(usage) = quotas.tunnels {
+                QuotaBar {
+                    label: tr!("quota-tunnels"),
+                    usage,
+                    detail: tr!(
+                        "quota-usage",
+                        used = format_integer(usage.used),
+                        limit = format_integer(usage.limit),
+                    ),
+                }
+            }
+            if let Some(usage) = quotas.bandwidth {
+                QuotaBar {
+                    label: tr!("quota-bandwidth"),
+                    usage,
+                    detail: tr!(
+                        "quota-usage",
+                        used = humanize_bytes(usage.used),
+                        limit = humanize_bytes(usage.limit),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn QuotaBar(label: String, usage: QuotaUsage, detail: String) -> Element {
+    let percent = (usage.fraction() * 100.0).round();
+    let bar_class = if usage.is_exhausted() {
+        "h-full bg-alert-red-dark"
+    } else if usage.fraction() >= 0.8 {
+        "h-full bg-amber-500"
+    } else {
+        "h-full bg-foreground/60"
+    };
+    rsx! {
+        div { class: "flex flex-col gap-1",
+            div { class: "flex items-center justify-between text-1xs text-foreground/70",
+                span { "{label}" }
+                span { "{detail}" }
+            }
+            div { class: "h-1.5 w-full rounded-full bg-foreground/10 overflow-hidden",
+                div { class: "{bar_class}", style: "width: {percent}%;" }
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use dioxus::prelude::*;
+
+use crate::{
+    components::{Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Warns that keys and logins are stored unencrypted because there is no
+/// keychain and no passphrase. Dismissing hides it until the app restarts.
+#[component]
+pub fn SecretStoreBanner() -> Element {
+    let state = consume_context::<AppState>();
+    let mut dismissed = use_signal(|| false);
+    if dismissed() || !state.repo().secret_store().is_fallback() {
+        return rsx! {};
+    }
+    rsx! {
+        div { class: "mx-4 mt-2 flex items-start gap-3 rounded-md border border-amber-200 bg-amber-50 px-4 py-3 text-amber-900",
+            div { class: "flex flex-1 flex-col gap-0.5",
+                span { class: "text-xs font-medium", {tr!("secret-store-fallback-title")} }
+                span { class: "text-1xs", {tr!("secret-store-fallback-body")} }
+            }
+            Button {
+                kind: ButtonKind::Ghost,
+                text: tr!("secret-store-fallback-dismiss"),
+                onclick: move |_| dismissed.set(true),
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use dioxus::prelude::*;
+use lib::TunnelSummary;
+
+use crate::{
+    components::{
+        dialog::{DialogContent, DialogRoot, DialogTitle},
+        Button, ButtonKind,
+    },
+    state::AppState,
+};
+
+const QR_SIZE: u32 = 200;
+
+#[component]
+pub fn ShareTunnelDialog(
+    open: ReadSignal<bool>,
+    on_open_change: EventHandler<bool>,
+    tunnel: ReadSignal<Option<TunnelSummary>>,
+) -> Element {
+    let state = consume_context::<AppState>();
+    let mut copied = use_signal(|| false);
+
+    // Tickets are built from the local proxy state, which shares its id with the tunnel.
+    let ticket = tunnel().and_then(|tunnel| {
+        let listen = state.listen_node();
+        listen
+            .proxy_by_id(&tunnel.id)
+            .map(|proxy| proxy.ticket(listen.endpoint_id()).to_string())
+    });
+    let qr_svg = ticket
+        .as_deref()
+        .and_then(|ticket| lib::qr::render_svg(ticket, QR_SIZE).ok());
+    let tunnel_name = tunnel().map(|t| t.label).unwrap_or_default();
+
+    let ticket_for_copy = ticket.clone();
+    let copy_handler = move |_| {
+        if let Some(ticket) = ticket_for_copy.clone() {
+            let eval = document::eval("navigator.clipboard.writeText(await dioxus.recv());");
+            let _ = eval.send(ticket);
+            copied.set(true);
+        }
+    };
+
+    rsx! {
+        DialogRoot {
+            open: open(),
+            on_open_change: move |open| {
+                copied.set(false);
+                on_open_change.call(open);
+            },
+            is_modal: true,
+            DialogContent {
+                DialogTitle { "Share \"{tunnel_name}\"" }
+                div { class: "mt-4 mb-6 flex flex-col items-center gap-4",
+                    match (ticket.as_ref(), qr_svg.as_ref()) {
+                        (Some(ticket), Some(svg)) => rsx! {
+                            div {
+                                class: "rounded-md bg-white p-2",
+                                style: "width:{QR_SIZE}px;height:{QR_SIZE}px;",
+                                dangerous_inner_html: "{svg}",
+                            }
+                            p { class: "text-1xs text-foreground/60 text-center",
+                                "Scan this code or paste the ticket into Join Tunnel on another device."
+                            }
+                            textarea {
+                                class: "w-full h-20 text-1xs font-mono rounded-md border border-app-border bg-transparent p-2 text-foreground resize-none",
+                                readonly: true,
+                                value: "{ticket}",
+                            }
+                        },
+                        _ => rsx! {
+                            p { class: "text-sm text-foreground/80",
+                                "This tunnel isn't served by this device, so there is no ticket to share."
+                            }
+                        },
+                    }
+                }
+                div { class: "flex items-center gap-2.5 justify-end",
+                    Button {
+                        kind: ButtonKind::Ghost,
+                        onclick: move |_| on_open_change.call(false),
+                        text: "Close",
+                    }
+                    if ticket.is_some() {
+                        Button {
+                            kind: ButtonKind::Primary,
+                            onclick: copy_handler,
+                            text: if copied() { "Copied" } else { "Copy Ticket" },
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
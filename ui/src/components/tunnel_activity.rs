@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use chrono::Local;
+use dioxus::prelude::*;
+use lib::activity::ActivityEntry;
+
+use crate::{
+    i18n::{format_datetime, tr},
+    state::AppState,
+};
+
+/// How often the timeline is re-read; request times change constantly.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lifecycle and traffic milestones of a tunnel, newest first.
+#[component]
+pub fn TunnelActivityPanel(tunnel_id: String) -> Element {
+    let mut timeline = use_signal(Vec::<ActivityEntry>::new);
+
+    use_future(move || {
+        let tunnel_id = tunnel_id.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            loop {
+                let current = state
+                    .listen_node()
+                    .activity(&tunnel_id)
+                    .map(|activity| activity.timeline())
+                    .unwrap_or_default();
+                if *timeline.peek() != current {
+                    timeline.set(current);
+                }
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+            }
+        }
+    });
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("tunnel-activity-title")} }
+            div { class: "flex flex-col gap-2",
+                if timeline().is_empty() {
+                    div { class: "text-xs text-foreground/60", {tr!("tunnel-activity-empty")} }
+                }
+                for entry in timeline() {
+                    div { class: "flex items-baseline gap-3",
+                        span { class: "text-1xs text-foreground/60 font-mono whitespace-nowrap",
+                            {format_datetime(&entry.at.with_timezone(&Local))}
+                        }
+                        span { class: "text-xs text-foreground", "{entry.kind.description()}" }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::Local;
+use dioxus::prelude::*;
+use lib::tunnels::{AuditAction, AuditEntry};
+
+use crate::{
+    i18n::{format_datetime, tr},
+    state::AppState,
+};
+
+/// Who changed a tunnel and when, newest first. Shared by everyone in the
+/// project, unlike the activity of this device.
+#[component]
+pub fn TunnelAuditPanel(tunnel_id: String) -> Element {
+    let mut trail = use_signal(|| None::<Result<Vec<AuditEntry>, String>>);
+
+    use_future(move || {
+        let tunnel_id = tunnel_id.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let refresh = state.tunnel_refresh();
+            loop {
+                let notified = refresh.notified();
+                let loaded = state
+                    .tunnel_service()
+                    .audit_trail_active(&tunnel_id)
+                    .await
+                    .map(|mut entries| {
+                        entries.reverse();
+                        entries
+                    })
+                    .map_err(|err| format!("{err:#}"));
+                trail.set(Some(loaded));
+                notified.await;
+            }
+        }
+    });
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("audit-title")} }
+            div { class: "flex flex-col gap-2",
+                match trail() {
+                    None => rsx! {},
+                    Some(Err(err)) => rsx! {
+                        div { class: "text-xs text-alert-red-dark", "{err}" }
+                    },
+                    Some(Ok(entries)) if entries.is_empty() => rsx! {
+                        div { class: "text-xs text-foreground/60", {tr!("audit-empty")} }
+                    },
+                    Some(Ok(entries)) => rsx! {
+                        for entry in entries {
+                            div { class: "flex items-baseline gap-3",
+                                span { class: "text-1xs text-foreground/60 font-mono whitespace-nowrap",
+                                    {format_datetime(&entry.at.with_timezone(&Local))}
+                                }
+                                span { class: "text-xs text-foreground",
+                                    {tr!("audit-entry", by = entry.by.clone(), action = action_text(entry.action))}
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn action_text(action: AuditAction) -> String {
+    match action {
+        AuditAction::Created => tr!("audit-created"),
+        AuditAction::Updated => tr!("audit-updated"),
+        AuditAction::Enabled => tr!("audit-enabled"),
+        AuditAction::Disabled => tr!("audit-disabled"),
+        AuditAction::Restored => tr!("audit-restored"),
+    }
+}
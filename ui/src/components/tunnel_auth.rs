@@ -0,0 +1,118 @@
+use dioxus::prelude::*;
+use lib::http_front::TunnelAuth;
+
+use crate::{
+    components::{input::Input, Button, ButtonKind, Switch, SwitchThumb},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Password protection of a tunnel.
+#[component]
+pub fn TunnelAuthPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let mut current = use_signal(|| {
+        state
+            .listen_node()
+            .proxy_by_id(&tunnel_id)
+            .and_then(|proxy| proxy.http_front)
+            .and_then(|front| front.auth)
+    });
+    let mut editing = use_signal(|| false);
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut invalid = use_signal(|| None::<String>);
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |auth: Option<TunnelAuth>| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let proxy = state
+                .tunnel_service()
+                .set_auth_active(&tunnel_id, auth)
+                .await?;
+            current.set(proxy.http_front.and_then(|front| front.auth));
+            editing.set(false);
+            password.set(String::new());
+            state.bump_tunnel_refresh();
+            n0_error::Ok(())
+        }
+    });
+
+    let protected = current().is_some();
+    let (status, status_class) = match (save.value(), current()) {
+        _ if invalid().is_some() => (invalid().unwrap_or_default(), "text-alert-red-dark"),
+        (Some(Err(err)), _) => (err.to_string(), "text-alert-red-dark"),
+        (_, Some(TunnelAuth::Basic { username, .. })) => (
+            tr!("auth-protected-basic", username = username),
+            "text-foreground/60",
+        ),
+        (_, Some(TunnelAuth::Bearer { .. })) => {
+            (tr!("auth-protected-bearer"), "text-foreground/60")
+        }
+        (_, None) => (tr!("auth-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "flex items-center justify-between gap-4",
+                div { class: "flex flex-col gap-1",
+                    div { class: "text-xs text-icon-select font-normal", {tr!("auth-title")} }
+                    div { class: "text-xs {status_class}", "{status}" }
+                }
+                Switch {
+                    checked: protected || editing(),
+                    disabled: save.pending(),
+                    on_checked_change: move |next| {
+                        if next {
+                            editing.set(true);
+                        } else if protected {
+                            save.call(None);
+                        } else {
+                            editing.set(false);
+                        }
+                    },
+                    SwitchThumb {}
+                }
+            }
+            if editing() {
+                div { class: "grid grid-cols-2 gap-4 mt-4",
+                    Input {
+                        id: Some("tunnel-auth-username".into()),
+                        label: Some(tr!("auth-username")),
+                        value: "{username}",
+                        autocomplete: Some("off".into()),
+                        oninput: move |e: FormEvent| username.set(e.value()),
+                    }
+                    Input {
+                        id: Some("tunnel-auth-password".into()),
+                        label: Some(tr!("auth-password")),
+                        r#type: "password",
+                        value: "{password}",
+                        autocomplete: Some("new-password".into()),
+                        oninput: move |e: FormEvent| password.set(e.value()),
+                    }
+                }
+                div { class: "flex justify-end mt-3",
+                    Button {
+                        kind: ButtonKind::Secondary,
+                        text: if save.pending() { tr!("auth-saving") } else { tr!("auth-save") },
+                        onclick: move |_| {
+                            if save.pending() {
+                                return;
+                            }
+                            match TunnelAuth::basic(username().trim(), &password()) {
+                                Ok(auth) => {
+                                    invalid.set(None);
+                                    save.call(Some(auth));
+                                }
+                                Err(err) => invalid.set(Some(format!("{err:#}"))),
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
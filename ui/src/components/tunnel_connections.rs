@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use lib::{ListenerConnectionInfo, PathInfo};
+
+use crate::{i18n::tr, state::AppState, util::humanize_duration};
+
+/// How often connection paths and RTTs are re-read.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Remote clients currently connected to a tunnel, with their path and RTT.
+#[component]
+pub fn TunnelConnections(tunnel_id: String) -> Element {
+    let mut connections = use_signal(Vec::<ListenerConnectionInfo>::new);
+
+    use_future(move || {
+        let tunnel_id = tunnel_id.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            loop {
+                let listen = state.listen_node();
+                let current = listen
+                    .proxy_by_id(&tunnel_id)
+                    .map(|proxy| listen.connections_for(&proxy))
+                    .unwrap_or_default();
+                if *connections.peek() != current {
+                    connections.set(current);
+                }
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+            }
+        }
+    });
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("connections-title")} }
+            if connections().is_empty() {
+                div { class: "text-xs text-foreground/60", {tr!("connections-empty")} }
+            } else {
+                table { class: "w-full text-xs text-foreground",
+                    thead {
+                        tr { class: "text-left text-foreground/60",
+                            th { class: "font-normal pb-2", {tr!("connections-endpoint")} }
+                            th { class: "font-normal pb-2", {tr!("connections-path")} }
+                            th { class: "font-normal pb-2", {tr!("connections-rtt")} }
+                            th { class: "font-normal pb-2", {tr!("connections-connected")} }
+                            th { class: "font-normal pb-2", {tr!("connections-requests")} }
+                        }
+                    }
+                    tbody {
+                        for conn in connections() {
+                            tr { key: "{conn.remote_id}",
+                                td { class: "font-mono py-1", "{conn.remote_id.fmt_short()}" }
+                                td { class: "py-1", "{conn.path.kind.label()}" }
+                                td { class: "py-1", "{format_rtt(&conn.path)}" }
+                                td { class: "py-1", {tr!("connections-age", age = humanize_duration(conn.age()))} }
+                                td { class: "py-1", "{conn.requests}" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn format_rtt(path: &PathInfo) -> String {
+    match path.rtt {
+        Some(rtt) => tr!("connections-rtt-value", ms = rtt.as_millis() as u64),
+        None => "—".to_string(),
+    }
+}
@@ -0,0 +1,55 @@
+use dioxus::prelude::*;
+
+use crate::{
+    components::{Switch, SwitchThumb},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Whether a tunnel refuses clients that only reach it over a relay.
+#[component]
+pub fn TunnelDirectOnlyPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let mut direct_only = use_signal(|| {
+        state
+            .listen_node()
+            .proxy_by_id(&tunnel_id)
+            .is_some_and(|proxy| proxy.direct_only)
+    });
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |next: bool| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .set_direct_only(&tunnel_id, next)
+                .await?;
+            direct_only.set(next);
+            n0_error::Ok(())
+        }
+    });
+
+    let (status, status_class) = match save.value() {
+        Some(Err(err)) => (err.to_string(), "text-alert-red-dark"),
+        _ => (tr!("direct-only-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "flex items-center justify-between gap-4",
+                div { class: "flex flex-col gap-1",
+                    div { class: "text-xs text-icon-select font-normal", {tr!("direct-only-title")} }
+                    div { class: "text-xs {status_class}", "{status}" }
+                }
+                Switch {
+                    checked: direct_only(),
+                    disabled: save.pending(),
+                    on_checked_change: move |next| save.call(next),
+                    SwitchThumb {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use dioxus::prelude::*;
+use lib::http_front::HeaderRule;
+
+use crate::{
+    components::{Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Header rewrite rules of a tunnel, one rule per line.
+#[component]
+pub fn TunnelHeadersPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let current = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .and_then(|proxy| proxy.http_front)
+        .map(|front| front.rules)
+        .unwrap_or_default();
+
+    let mut text = use_signal(|| {
+        current
+            .iter()
+            .map(HeaderRule::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |rules: Vec<HeaderRule>| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .tunnel_service()
+                .set_header_rules_active(&tunnel_id, rules)
+                .await?;
+            state.bump_tunnel_refresh();
+            n0_error::Ok(())
+        }
+    });
+
+    let parsed = parse_rules(&text());
+    let (status, status_class) = match (&parsed, save.value()) {
+        (Err(err), _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err))) => (err.to_string(), "text-alert-red-dark"),
+        _ => (tr!("headers-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("headers-title")} }
+            textarea {
+                id: "tunnel-header-rules",
+                class: "w-full h-24 text-1xs font-mono rounded-lg border border-app-border bg-card-background p-2 text-foreground resize-none focus:outline-none focus:ring-1 focus:ring-app-border",
+                placeholder: "response:remove:Server",
+                value: "{text}",
+                oninput: move |e: FormEvent| text.set(e.value()),
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("headers-saving") } else { tr!("headers-save") },
+                    onclick: move |_| {
+                        if let Ok(rules) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(rules);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Rules from the text field, one per line; blank lines are skipped.
+fn parse_rules(text: &str) -> Result<Vec<HeaderRule>, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse::<HeaderRule>().map_err(|err| format!("{err:#}")))
+        .collect()
+}
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use dioxus::prelude::*;
+use lib::health::{HealthCheck, TargetHealth};
+
+use crate::{
+    components::{input::Input, Button, ButtonKind, Switch, SwitchThumb},
+    i18n::tr,
+    state::AppState,
+    util::humanize_duration,
+};
+
+/// Health check of a tunnel's local target and its latest result.
+#[component]
+pub fn TunnelHealthPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let current = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .and_then(|proxy| proxy.health_check);
+    let mut target = use_signal(|| {
+        current
+            .as_ref()
+            .map(|check| check.http_path.clone().unwrap_or_else(|| "tcp".to_string()))
+            .unwrap_or_default()
+    });
+    let mut unpublish = use_signal(|| {
+        current
+            .as_ref()
+            .is_some_and(|check| check.unpublish_when_unhealthy)
+    });
+    let mut health = use_signal(|| None::<TargetHealth>);
+
+    let tunnel_id_for_watch = tunnel_id.clone();
+    use_future(move || {
+        let tunnel_id = tunnel_id_for_watch.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let mut rx = state.listen_node().target_health_watch();
+            loop {
+                let latest = rx.borrow_and_update().get(&tunnel_id).cloned();
+                health.set(latest);
+                if rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |check: Option<HealthCheck>| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .set_health_check(&tunnel_id, check)
+                .await?;
+            n0_error::Ok(())
+        }
+    });
+
+    let parsed = parse_check(&target(), unpublish());
+    let (status, status_class) = match (&parsed, save.value(), health()) {
+        (Err(err), _, _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err)), _) => (err.to_string(), "text-alert-red-dark"),
+        (Ok(None), _, _) => (tr!("health-hint"), "text-foreground/60"),
+        (Ok(Some(_)), _, None) => (tr!("health-pending"), "text-foreground/60"),
+        (Ok(Some(_)), _, Some(health)) if health.healthy => {
+            let since = (Utc::now() - health.since).to_std().unwrap_or_default();
+            (
+                tr!("health-healthy", since = humanize_duration(since)),
+                "text-foreground/60",
+            )
+        }
+        (Ok(Some(_)), _, Some(health)) => (
+            tr!("health-unhealthy", error = health.error.unwrap_or_default()),
+            "text-alert-red-dark",
+        ),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("health-title")} }
+            Input {
+                id: Some("tunnel-health-check".into()),
+                label: Some(tr!("health-check")),
+                value: "{target}",
+                placeholder: tr!("health-check-placeholder"),
+                oninput: move |e: FormEvent| target.set(e.value()),
+            }
+            div { class: "flex items-center gap-3 mt-3",
+                Switch {
+                    checked: unpublish(),
+                    disabled: save.pending(),
+                    on_checked_change: move |next| unpublish.set(next),
+                    SwitchThumb {}
+                }
+                div { class: "text-xs text-foreground", {tr!("health-unpublish")} }
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("health-saving") } else { tr!("health-save") },
+                    onclick: move |_| {
+                        if let Ok(check) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(check);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// `tcp`, an HTTP path, or empty for no check.
+fn parse_check(text: &str, unpublish_when_unhealthy: bool) -> Result<Option<HealthCheck>, String> {
+    let http_path = match text.trim() {
+        "" => return Ok(None),
+        "tcp" => None,
+        path if path.starts_with('/') => Some(path.to_string()),
+        _ => return Err(tr!("health-invalid")),
+    };
+    Ok(Some(HealthCheck {
+        http_path,
+        unpublish_when_unhealthy,
+        ..Default::default()
+    }))
+}
@@ -0,0 +1,85 @@
+use dioxus::prelude::*;
+use lib::ip_filter::IpFilter;
+
+use crate::{
+    components::{Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// IP allow and deny lists of a tunnel, enforced by the gateway. One entry
+/// per line, `allow <cidr>` or `deny <cidr>`.
+#[component]
+pub fn TunnelIpFilterPanel(tunnel_id: String, initial: IpFilter) -> Element {
+    let mut text = use_signal(|| format_filter(&initial));
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |ip_filter: IpFilter| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let tunnel = state
+                .tunnel_service()
+                .set_ip_filter_active(&tunnel_id, ip_filter)
+                .await?;
+            text.set(format_filter(&tunnel.ip_filter));
+            state.bump_tunnel_refresh();
+            n0_error::Ok(())
+        }
+    });
+
+    let parsed = parse_filter(&text());
+    let (status, status_class) = match (&parsed, save.value()) {
+        (Err(err), _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err))) => (err.to_string(), "text-alert-red-dark"),
+        _ => (tr!("ip-filter-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("ip-filter-title")} }
+            textarea {
+                id: "tunnel-ip-filter",
+                class: "w-full h-24 text-1xs font-mono rounded-lg border border-app-border bg-card-background p-2 text-foreground resize-none focus:outline-none focus:ring-1 focus:ring-app-border",
+                placeholder: "allow 203.0.113.0/24",
+                value: "{text}",
+                oninput: move |e: FormEvent| text.set(e.value()),
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("ip-filter-saving") } else { tr!("ip-filter-save") },
+                    onclick: move |_| {
+                        if let Ok(ip_filter) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(ip_filter);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn format_filter(ip_filter: &IpFilter) -> String {
+    let allow = ip_filter.allow.iter().map(|net| format!("allow {net}"));
+    let deny = ip_filter.deny.iter().map(|net| format!("deny {net}"));
+    allow.chain(deny).collect::<Vec<_>>().join("\n")
+}
+
+/// The filter from the text field; blank lines are skipped.
+fn parse_filter(text: &str) -> Result<IpFilter, String> {
+    let mut ip_filter = IpFilter::default();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (action, net) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let net = net.trim().parse().map_err(|err| format!("{err:#}"))?;
+        match action {
+            "allow" => ip_filter.allow.push(net),
+            "deny" => ip_filter.deny.push(net),
+            _ => return Err(tr!("ip-filter-invalid-action", line = line)),
+        }
+    }
+    Ok(ip_filter)
+}
@@ -0,0 +1,115 @@
+use dioxus::prelude::*;
+use lib::usage::{parse_size, TransferQuota};
+
+use crate::{
+    components::{input::Input, Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+    util::humanize_bytes,
+};
+
+/// How much a tunnel may transfer before it turns itself off.
+#[component]
+pub fn TunnelQuotaPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let current = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .and_then(|proxy| proxy.transfer_quota)
+        .unwrap_or_default();
+    let usage = state.listen_node().transfer_usage(&tunnel_id);
+
+    let mut monthly = use_signal(|| size_text(current.monthly_bytes));
+    let mut total = use_signal(|| size_text(current.total_bytes));
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |quota: TransferQuota| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .set_transfer_quota(&tunnel_id, Some(quota))
+                .await?;
+            n0_error::Ok(())
+        }
+    });
+
+    let parsed = match (parse_limit(&monthly()), parse_limit(&total())) {
+        (Ok(monthly_bytes), Ok(total_bytes)) => Ok(TransferQuota {
+            monthly_bytes,
+            total_bytes,
+        }),
+        _ => Err(tr!("quota-invalid")),
+    };
+    let used = tr!(
+        "quota-used",
+        month = humanize_bytes(usage.bytes_this_month(chrono::Utc::now())),
+        total = humanize_bytes(usage.total_bytes),
+    );
+    let (status, status_class) = match (&parsed, save.value()) {
+        (Err(err), _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err))) => (err.to_string(), "text-alert-red-dark"),
+        _ => (used, "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("quota-title")} }
+            div { class: "grid grid-cols-2 gap-4",
+                Input {
+                    id: Some("tunnel-quota-monthly".into()),
+                    label: Some(tr!("quota-monthly")),
+                    value: "{monthly}",
+                    placeholder: tr!("quota-unlimited"),
+                    oninput: move |e: FormEvent| monthly.set(e.value()),
+                }
+                Input {
+                    id: Some("tunnel-quota-total".into()),
+                    label: Some(tr!("quota-total")),
+                    value: "{total}",
+                    placeholder: tr!("quota-unlimited"),
+                    oninput: move |e: FormEvent| total.set(e.value()),
+                }
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("quota-saving") } else { tr!("quota-save") },
+                    onclick: move |_| {
+                        if let Ok(quota) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(quota);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// A limit as typed back into the field, in the largest unit that divides
+/// it evenly.
+fn size_text(bytes: Option<u64>) -> String {
+    let Some(bytes) = bytes else {
+        return String::new();
+    };
+    let unit = ["TB", "GB", "MB", "KB"]
+        .into_iter()
+        .zip([40, 30, 20, 10])
+        .find(|(_, shift)| bytes != 0 && bytes % (1u64 << shift) == 0);
+    match unit {
+        Some((unit, shift)) => format!("{} {unit}", bytes >> shift),
+        None => bytes.to_string(),
+    }
+}
+
+/// A size from a text field; empty means no limit.
+fn parse_limit(text: &str) -> n0_error::Result<Option<u64>> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_size(text).map(Some)
+}
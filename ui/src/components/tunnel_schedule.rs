@@ -0,0 +1,74 @@
+use dioxus::prelude::*;
+use lib::schedule::TunnelSchedule;
+
+use crate::{
+    components::{input::Input, Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// The hours a tunnel is turned on by itself.
+#[component]
+pub fn TunnelSchedulePanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let current = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .and_then(|proxy| proxy.schedule);
+
+    let mut text = use_signal(|| current.map(|s| s.to_string()).unwrap_or_default());
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |schedule: Option<TunnelSchedule>| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .set_schedule(&tunnel_id, schedule)
+                .await?;
+            n0_error::Ok(())
+        }
+    });
+
+    // Empty means no schedule.
+    let parsed = match text().trim() {
+        "" => Ok(None),
+        input => input
+            .parse::<TunnelSchedule>()
+            .map(Some)
+            .map_err(|err| format!("{err:#}")),
+    };
+    let (status, status_class) = match (&parsed, save.value()) {
+        (Err(err), _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err))) => (err.to_string(), "text-alert-red-dark"),
+        _ => (tr!("schedule-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("schedule-title")} }
+            Input {
+                id: Some("tunnel-schedule".into()),
+                label: Some(tr!("schedule-windows")),
+                value: "{text}",
+                placeholder: "mon-fri 09:00-17:00",
+                oninput: move |e: FormEvent| text.set(e.value()),
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("schedule-saving") } else { tr!("schedule-save") },
+                    onclick: move |_| {
+                        if let Ok(schedule) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(schedule);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
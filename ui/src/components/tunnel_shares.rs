@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use lib::IssuedShare;
+
+use crate::{
+    components::{Button, ButtonKind},
+    i18n::{tr, translate},
+    state::AppState,
+    util::humanize_duration,
+};
+
+/// Share lifetimes offered when creating a link, by message id.
+const SHARE_TTLS: &[(&str, Duration)] = &[
+    ("shares-ttl-hour", Duration::from_secs(60 * 60)),
+    ("shares-ttl-day", Duration::from_secs(24 * 60 * 60)),
+    ("shares-ttl-week", Duration::from_secs(7 * 24 * 60 * 60)),
+];
+
+/// Active share links of a tunnel, with their remaining lifetime and uses.
+#[component]
+pub fn TunnelShares(tunnel_id: String) -> Element {
+    let mut shares = use_signal(Vec::<IssuedShare>::new);
+    let mut now = use_signal(chrono::Utc::now);
+    let mut copied = use_signal(|| None::<String>);
+
+    // Re-read every second; it's a local lookup and drives the countdowns.
+    let tunnel_id_for_poll = tunnel_id.clone();
+    use_future(move || {
+        let tunnel_id = tunnel_id_for_poll.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            loop {
+                let current = state.listen_node().shares(&tunnel_id);
+                if *shares.peek() != current {
+                    shares.set(current);
+                }
+                now.set(chrono::Utc::now());
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    });
+
+    let tunnel_id_for_issue = tunnel_id.clone();
+    let mut issue = use_action(move |ttl: Duration| {
+        let tunnel_id = tunnel_id_for_issue.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .issue_share(&tunnel_id, Some(ttl))
+                .await?;
+            shares.set(state.listen_node().shares(&tunnel_id));
+            n0_error::Ok(())
+        }
+    });
+
+    let tunnel_id_for_revoke = tunnel_id.clone();
+    let mut revoke = use_action(move |share_id: String| {
+        let tunnel_id = tunnel_id_for_revoke.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state.listen_node().revoke_share(&share_id).await?;
+            shares.set(state.listen_node().shares(&tunnel_id));
+            n0_error::Ok(())
+        }
+    });
+
+    let error = match (issue.value(), revoke.value()) {
+        (Some(Err(err)), _) | (_, Some(Err(err))) => Some(err.to_string()),
+        _ => None,
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "flex items-center justify-between mb-3",
+                div { class: "text-xs text-icon-select font-normal", {tr!("shares-title")} }
+                div { class: "flex gap-2",
+                    for (label , ttl) in SHARE_TTLS.iter().copied() {
+                        Button {
+                            kind: ButtonKind::Ghost,
+                            text: tr!("shares-create", duration = translate(label, None)),
+                            onclick: move |_| {
+                                if !issue.pending() {
+                                    issue.call(ttl);
+                                }
+                            },
+                        }
+                    }
+                }
+            }
+            if let Some(error) = error {
+                div { class: "text-xs text-alert-red-dark mb-2", "{error}" }
+            }
+            if shares().is_empty() {
+                div { class: "text-xs text-foreground/60", {tr!("shares-empty")} }
+            } else {
+                div { class: "flex flex-col gap-2",
+                    for share in shares() {
+                        div {
+                            key: "{share.id}",
+                            class: "flex items-center gap-4 text-xs text-foreground",
+                            span { class: "w-28",
+                                {
+                                    match share.remaining(now()) {
+                                        Some(left) => tr!("shares-expires-in", duration = humanize_duration(left)),
+                                        None => tr!("shares-never-expires"),
+                                    }
+                                }
+                            }
+                            span { class: "w-16 text-foreground/60",
+                                {tr!("shares-uses", count = share.uses())}
+                            }
+                            Button {
+                                kind: ButtonKind::Ghost,
+                                text: if copied().as_deref() == Some(share.id.as_str()) { tr!("shares-copied") } else { tr!("shares-copy") },
+                                onclick: {
+                                    let share = share.clone();
+                                    move |_| {
+                                        let eval = document::eval("navigator.clipboard.writeText(await dioxus.recv());");
+                                        let _ = eval.send(share.ticket.clone());
+                                        copied.set(Some(share.id.clone()));
+                                    }
+                                },
+                            }
+                            Button {
+                                kind: ButtonKind::Ghost,
+                                text: tr!("shares-revoke"),
+                                onclick: {
+                                    let share_id = share.id.clone();
+                                    move |_| {
+                                        if !revoke.pending() {
+                                            revoke.call(share_id.clone());
+                                        }
+                                    }
+                                },
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use dioxus::prelude::*;
+use lib::TunnelTimeouts;
+
+use crate::{
+    components::{input::Input, Button, ButtonKind},
+    i18n::tr,
+    state::AppState,
+};
+
+/// Idle timeout and keepalive settings of a tunnel.
+#[component]
+pub fn TunnelTimeoutsPanel(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let current = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .map(|proxy| proxy.timeouts)
+        .unwrap_or_default();
+    let applied = state.listen_node().applied_timeouts();
+
+    let mut idle = use_signal(|| secs_text(current.idle_timeout_secs));
+    let mut keepalive = use_signal(|| secs_text(current.keepalive_secs));
+
+    let tunnel_id_for_save = tunnel_id.clone();
+    let mut save = use_action(move |timeouts: TunnelTimeouts| {
+        let tunnel_id = tunnel_id_for_save.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            state
+                .listen_node()
+                .set_timeouts(&tunnel_id, timeouts)
+                .await?;
+            n0_error::Ok(())
+        }
+    });
+
+    let parsed = match (parse_secs(&idle()), parse_secs(&keepalive())) {
+        (Ok(idle_timeout_secs), Ok(keepalive_secs)) => Ok(TunnelTimeouts {
+            idle_timeout_secs,
+            keepalive_secs,
+        }),
+        _ => Err(tr!("timeouts-invalid")),
+    };
+    let needs_restart = TunnelTimeouts::combine([&current, &applied]) != applied;
+    let (status, status_class) = match (&parsed, save.value()) {
+        (Err(err), _) => (err.clone(), "text-alert-red-dark"),
+        (_, Some(Err(err))) => (err.to_string(), "text-alert-red-dark"),
+        _ if needs_restart => (tr!("timeouts-restart"), "text-foreground/60"),
+        _ => (tr!("timeouts-hint"), "text-foreground/60"),
+    };
+
+    rsx! {
+        div { class: "border border-app-border rounded-lg p-6 mt-5",
+            div { class: "text-xs text-icon-select font-normal mb-3", {tr!("timeouts-title")} }
+            div { class: "grid grid-cols-2 gap-4",
+                Input {
+                    id: Some("tunnel-idle-timeout".into()),
+                    label: Some(tr!("timeouts-idle")),
+                    value: "{idle}",
+                    placeholder: tr!("timeouts-default"),
+                    oninput: move |e: FormEvent| idle.set(e.value()),
+                }
+                Input {
+                    id: Some("tunnel-keepalive".into()),
+                    label: Some(tr!("timeouts-keepalive")),
+                    value: "{keepalive}",
+                    placeholder: tr!("timeouts-default"),
+                    oninput: move |e: FormEvent| keepalive.set(e.value()),
+                }
+            }
+            div { class: "flex items-center justify-between mt-3",
+                div { class: "text-xs {status_class}", "{status}" }
+                Button {
+                    kind: ButtonKind::Secondary,
+                    text: if save.pending() { tr!("timeouts-saving") } else { tr!("timeouts-save") },
+                    onclick: move |_| {
+                        if let Ok(timeouts) = parsed.clone() {
+                            if !save.pending() {
+                                save.call(timeouts);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn secs_text(secs: Option<u64>) -> String {
+    secs.map(|secs| secs.to_string()).unwrap_or_default()
+}
+
+/// Seconds from a text field; empty means "use the default".
+fn parse_secs(text: &str) -> Result<Option<u64>, std::num::ParseIntError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.parse().map(Some)
+}
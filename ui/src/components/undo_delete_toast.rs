@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use dioxus::prelude::*;
+
+use crate::{
+    components::{Button, ButtonKind},
+    state::AppState,
+};
+
+/// How long a deleted tunnel can be restored from the toast.
+const UNDO_WINDOW: Duration = Duration::from_secs(10);
+
+/// Offers to undo the most recent tunnel delete for a few seconds.
+#[component]
+pub fn UndoDeleteToast() -> Element {
+    let state = consume_context::<AppState>();
+    let last_deleted = state.last_deleted();
+
+    let state_for_timer = state.clone();
+    use_effect(move || {
+        let Some(tunnel_id) = last_deleted().map(|deleted| deleted.summary.id) else {
+            return;
+        };
+        let state = state_for_timer.clone();
+        spawn(async move {
+            tokio::time::sleep(UNDO_WINDOW).await;
+            state.expire_deleted(&tunnel_id);
+        });
+    });
+
+    let mut undo = use_action(move |_: ()| async move {
+        let state = consume_context::<AppState>();
+        state.undo_delete().await.inspect_err(|err| {
+            tracing::warn!("undo delete failed: {err:#}");
+        })?;
+        n0_error::Ok(())
+    });
+
+    let Some(deleted) = last_deleted() else {
+        return rsx! {};
+    };
+    let label = deleted.summary.label;
+    let message = match undo.value() {
+        Some(Err(err)) => format!("Couldn't restore \"{label}\": {err}"),
+        _ => format!("Deleted \"{label}\""),
+    };
+
+    rsx! {
+        div { class: "fixed bottom-4 left-1/2 -translate-x-1/2 z-50 flex items-center gap-3 rounded-lg border border-card-border bg-card-background px-4 py-2 shadow-card",
+            span { class: "text-xs text-foreground", "{message}" }
+            Button {
+                kind: ButtonKind::Ghost,
+                text: if undo.pending() { "Restoring…" } else { "Undo" },
+                onclick: move |_| {
+                    if !undo.pending() {
+                        undo.call(());
+                    }
+                },
+            }
+            Button {
+                kind: ButtonKind::Ghost,
+                text: "Dismiss",
+                onclick: move |_| state.set_last_deleted(None),
+            }
+        }
+    }
+}
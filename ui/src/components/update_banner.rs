@@ -0,0 +1,100 @@
+use dioxus::prelude::*;
+use lib::AvailableUpdate;
+
+use crate::{
+    components::{Button, ButtonKind},
+    state::AppState,
+};
+
+/// Says that an update is available, with its release notes and a button to
+/// restart into it. Dismissing hides it until a newer release shows up.
+#[component]
+pub fn UpdateBanner() -> Element {
+    let mut update = use_signal(|| None::<AvailableUpdate>);
+    let mut dismissed = use_signal(|| None::<String>);
+    let mut show_notes = use_signal(|| false);
+
+    use_future(move || async move {
+        let state = consume_context::<AppState>();
+        let mut rx = state.updater().watch();
+        loop {
+            update.set(rx.borrow_and_update().clone());
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut restart = use_action(move |update: AvailableUpdate| async move {
+        let state = consume_context::<AppState>();
+        let installer = state
+            .updater()
+            .download(&update)
+            .await
+            .inspect_err(|err| tracing::warn!("update download failed: {err:#}"))?;
+        lib::updater::install(&update, &installer)
+            .inspect_err(|err| tracing::warn!("update install failed: {err:#}"))?;
+        // Release connector leases first, like quitting from the tray.
+        spawn(async move {
+            state.heartbeat().shutdown().await;
+            std::process::exit(0);
+        });
+        n0_error::Ok(())
+    });
+
+    let Some(available) = update() else {
+        return rsx! {};
+    };
+    let manifest = available.manifest.clone();
+    if dismissed().as_deref() == Some(manifest.version.as_str()) {
+        return rsx! {};
+    }
+    let version = manifest.version.clone();
+    let title = if manifest.name.is_empty() {
+        format!("Datum {}", manifest.version)
+    } else {
+        manifest.name.clone()
+    };
+
+    rsx! {
+        div { class: "fixed bottom-4 right-4 z-50 w-80 flex flex-col gap-2 rounded-lg border border-card-border bg-card-background px-4 py-3 shadow-card",
+            div { class: "flex flex-col gap-0.5",
+                span { class: "text-xs font-medium text-foreground", "Update available" }
+                span { class: "text-1xs text-foreground/60",
+                    "{title} · published {manifest.published_at.format(\"%B %d, %Y\")}"
+                }
+            }
+            if show_notes() && !manifest.notes.is_empty() {
+                p { class: "text-1xs text-foreground/80 whitespace-pre-wrap max-h-40 overflow-y-auto",
+                    "{manifest.notes}"
+                }
+            }
+            if let Some(Err(err)) = restart.value() {
+                p { class: "text-1xs text-alert-red-dark", "Couldn't update: {err}" }
+            }
+            div { class: "flex gap-2",
+                Button {
+                    kind: ButtonKind::Primary,
+                    text: if restart.pending() { "Downloading…" } else { "Restart to update" },
+                    onclick: move |_| {
+                        if !restart.pending() {
+                            restart.call(available.clone());
+                        }
+                    },
+                }
+                if !manifest.notes.is_empty() {
+                    Button {
+                        kind: ButtonKind::Ghost,
+                        text: if show_notes() { "Hide notes" } else { "What's new" },
+                        onclick: move |_| show_notes.set(!show_notes()),
+                    }
+                }
+                Button {
+                    kind: ButtonKind::Ghost,
+                    text: "Later",
+                    onclick: move |_| dismissed.set(Some(version.clone())),
+                }
+            }
+        }
+    }
+}
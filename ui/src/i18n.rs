@@ -0,0 +1,203 @@
+//! Translations and locale-aware formatting.
+//!
+//! Messages live in Fluent files under `ui/locales`, one per locale, and are
+//! looked up with [`tr!`]. The locale comes from `DATUM_CONNECT_LOCALE` if set,
+//! otherwise from the OS. Messages missing from the active locale fall back to
+//! `en-US`, so a partial translation is never worse than none.
+//!
+//! Views are moved over to [`tr!`] as they are touched; new user-facing strings
+//! should go through it.
+
+use std::sync::OnceLock;
+
+use chrono::{DateTime, TimeZone};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+const FALLBACK_LOCALE: &str = "en-US";
+
+/// Bundled translations, by locale.
+const LOCALES: &[(&str, &str)] = &[
+    ("en-US", include_str!("../locales/en-US.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+/// Look up a translated message, with optional `name = value` arguments.
+macro_rules! tr {
+    ($id:literal) => {
+        $crate::i18n::translate($id, None)
+    };
+    ($id:literal, $($name:ident = $value:expr),+ $(,)?) => {{
+        let mut args = fluent_bundle::FluentArgs::new();
+        $(args.set(stringify!($name), $value);)+
+        $crate::i18n::translate($id, Some(&args))
+    }};
+}
+pub(crate) use tr;
+
+struct Translations {
+    locale: LanguageIdentifier,
+    active: Option<FluentBundle<FluentResource>>,
+    fallback: FluentBundle<FluentResource>,
+}
+
+fn translations() -> &'static Translations {
+    static TRANSLATIONS: OnceLock<Translations> = OnceLock::new();
+    TRANSLATIONS.get_or_init(|| {
+        let locale = detect_locale();
+        let fallback_id: LanguageIdentifier = FALLBACK_LOCALE.parse().expect("valid locale");
+        let fallback = bundle(&fallback_id, source_for(&fallback_id).expect("bundled"));
+        let active = source_for(&locale)
+            .filter(|_| locale.language != fallback_id.language)
+            .map(|source| bundle(&locale, source));
+        Translations {
+            locale,
+            active,
+            fallback,
+        }
+    })
+}
+
+fn detect_locale() -> LanguageIdentifier {
+    std::env::var("DATUM_CONNECT_LOCALE")
+        .ok()
+        .or_else(sys_locale::get_locale)
+        .and_then(|tag| tag.replace('_', "-").parse().ok())
+        .unwrap_or_else(|| FALLBACK_LOCALE.parse().expect("valid locale"))
+}
+
+/// The bundled source for a locale: an exact match, or else one for the same language.
+fn source_for(locale: &LanguageIdentifier) -> Option<&'static str> {
+    let parsed = || {
+        LOCALES
+            .iter()
+            .filter_map(|(tag, source)| Some((tag.parse::<LanguageIdentifier>().ok()?, *source)))
+    };
+    parsed()
+        .find(|(tag, _)| tag == locale)
+        .or_else(|| parsed().find(|(tag, _)| tag.language == locale.language))
+        .map(|(_, source)| source)
+}
+
+fn bundle(locale: &LanguageIdentifier, source: &str) -> FluentBundle<FluentResource> {
+    let resource =
+        FluentResource::try_new(source.to_string()).unwrap_or_else(|(resource, errors)| {
+            tracing::warn!("errors in {locale} translations: {errors:?}");
+            resource
+        });
+    let mut bundle = FluentBundle::new_concurrent(vec![locale.clone()]);
+    // Unicode isolation marks render as boxes in some webviews.
+    bundle.set_use_isolating(false);
+    if let Err(errors) = bundle.add_resource(resource) {
+        tracing::warn!("duplicate {locale} translations: {errors:?}");
+    }
+    bundle
+}
+
+/// Translate a message. Prefer [`tr!`].
+pub fn translate(id: &str, args: Option<&FluentArgs>) -> String {
+    let translations = translations();
+    translations
+        .active
+        .iter()
+        .chain(std::iter::once(&translations.fallback))
+        .find_map(|bundle| {
+            let pattern = bundle.get_message(id)?.value()?;
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                tracing::debug!("errors formatting {id}: {errors:?}");
+            }
+            Some(text.into_owned())
+        })
+        .unwrap_or_else(|| {
+            tracing::warn!("missing translation for {id}");
+            id.to_string()
+        })
+}
+
+/// Decimal and digit group separators of the active locale.
+fn separators() -> (char, char) {
+    match translations().locale.language.as_str() {
+        "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (',', '.'),
+        "fr" | "ru" | "pl" | "cs" | "sv" | "fi" | "nb" | "uk" | "hu" | "sk" => (',', '\u{202f}'),
+        _ => ('.', ','),
+    }
+}
+
+/// Format an integer with the locale's digit grouping, e.g. `12,345`.
+pub fn format_integer(value: u64) -> String {
+    let (_, group) = separators();
+    let digits = value.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(group);
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Format a number with a fixed number of decimals, e.g. `1.5` or `1,5`.
+pub fn format_decimal(value: f64, decimals: usize) -> String {
+    let (decimal, _) = separators();
+    let formatted = format!("{value:.decimals$}");
+    match formatted.split_once('.') {
+        Some((whole, fraction)) => {
+            let whole = whole
+                .parse::<u64>()
+                .map(format_integer)
+                .unwrap_or_else(|_| whole.to_string());
+            format!("{whole}{decimal}{fraction}")
+        }
+        None => formatted,
+    }
+}
+
+/// Format a byte count with binary units, e.g. `1.5 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+
+    if bytes == 0 {
+        return "0 B".to_string();
+    }
+
+    let mut size = bytes as f64;
+    let mut unit_idx = 0;
+
+    while size >= 1024.0 && unit_idx < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_idx += 1;
+    }
+
+    format!("{} {}", format_decimal(size, 1), UNITS[unit_idx])
+}
+
+fn chrono_locale() -> chrono::Locale {
+    let locale = &translations().locale;
+    let language = locale.language.as_str();
+    let region = locale
+        .region
+        .map(|region| region.as_str().to_string())
+        .unwrap_or_else(|| language.to_uppercase());
+    chrono::Locale::try_from(format!("{language}_{region}").as_str())
+        .or_else(|_| chrono::Locale::try_from(language))
+        .unwrap_or(chrono::Locale::en_US)
+}
+
+/// Format a date and time the way the locale writes them.
+pub fn format_datetime<Tz: TimeZone>(dt: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    dt.format_localized("%x %X", chrono_locale()).to_string()
+}
+
+/// Format a time of day the way the locale writes it.
+pub fn format_time<Tz: TimeZone>(dt: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    dt.format_localized("%X", chrono_locale()).to_string()
+}
@@ -1,15 +1,18 @@
 use dioxus::prelude::*;
+use lib::datum_cloud::AuthEvent;
 #[cfg(feature = "desktop")]
 use n0_error::Result;
 use std::sync::OnceLock;
-use tracing::info;
+use tokio::sync::broadcast;
+use tracing::{debug, info};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-use crate::components::{Head, Splash, UpdateDialog};
+use crate::components::{Head, SecretStoreBanner, Splash, UpdateBanner};
 use crate::state::AppState;
 use crate::views::{
-    Chrome, JoinProxy, Login, ProxiesList, SelectProject, Settings, TunnelBandwidth,
+    Activity, Chrome, Devices, Doctor, JoinProxy, Login, ProxiesList, SelectProject, SessionEnded,
+    Settings, TunnelBandwidth,
 };
 
 #[cfg(feature = "desktop")]
@@ -21,7 +24,9 @@ use dioxus_desktop::{
     use_tray_menu_event_handler, use_window,
 };
 
+mod clipboard;
 mod components;
+mod i18n;
 mod state;
 mod util;
 mod views;
@@ -57,6 +62,12 @@ enum Route {
     JoinProxy {},
     #[route("/settings")]
     Settings {},
+    #[route("/settings/doctor")]
+    Doctor {},
+    #[route("/settings/activity")]
+    Activity {},
+    #[route("/settings/devices")]
+    Devices {},
 }
 
 fn main() {
@@ -126,19 +137,31 @@ fn init_tracing() {
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
     let _ = LOG_GUARD.set(guard);
 
+    let recent_logs = lib::crash::RecentLogs::default();
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::registry()
         .with(filter)
         .with(fmt::layer().with_writer(std::io::stderr))
         .with(fmt::layer().with_writer(non_blocking))
+        .with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(recent_logs.clone()),
+        )
         .init();
+
+    // Crash reports are written next to the log, and only sent once the
+    // user opts in, see `AppState::send_crash_reports`.
+    lib::crash::CrashReports::new(
+        repo_path.join("crashes"),
+        lib::datum_cloud::ApiEnv::default(),
+    )
+    .install_panic_hook(env!("CARGO_PKG_VERSION"), recent_logs);
 }
 
 #[component]
 fn App() -> Element {
     let mut app_state_ready = use_signal(|| false);
-    let mut update_dialog_open = use_signal(|| false);
-    let update_info = use_signal(|| None::<lib::UpdateInfo>);
     let mut manual_update_check = use_signal(|| false);
 
     // Poll for macOS menu bar update check flag
@@ -171,66 +194,38 @@ fn App() -> Element {
             // if state.datum().login_state() == LoginState::Missing {
             //     nav.push(Route::Login {});
             // }
-            provide_context(state);
+            provide_context(state.clone());
             app_state_ready.set(true);
+            let tunnel_service = state.tunnel_service();
+            tokio::join!(
+                state.sync_with_repo(),
+                tunnel_service.run_offline_queue(),
+                tunnel_service.run_expiry(),
+                tunnel_service.run_schedules(),
+                tunnel_service.run_quotas(),
+                tunnel_service.run_auth_events(),
+            );
         }
     });
 
-    // Check for updates on startup and periodically
+    // Manual update checks. The updater in the app state checks on startup
+    // and periodically on its own.
     use_future(move || {
-        let mut update_dialog_open = update_dialog_open;
-        let mut update_info = update_info;
         let mut manual_update_check = manual_update_check;
         async move {
             // Wait for app state to be ready
             while !app_state_ready() {
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
             }
-
-            let repo = match lib::Repo::open_or_create(lib::Repo::default_location()).await {
-                Ok(repo) => repo,
-                Err(e) => {
-                    tracing::warn!("Failed to open repo for update checking: {e:#}");
-                    return ();
-                }
-            };
-
-            let checker = lib::UpdateChecker::new(repo);
-
-            // Check for updates on startup
-            if let Ok(should_check) = checker.should_check().await {
-                if should_check {
-                    if let Ok(Some(info)) = checker.check_for_updates().await {
-                        update_info.set(Some(info));
-                        update_dialog_open.set(true);
-                    }
-                }
-            }
-
-            // Periodic update check (every 12 hours by default) and manual checks
-            let mut last_periodic_check = std::time::Instant::now();
+            let state = consume_context::<AppState>();
             loop {
-                // Check for manual update check trigger (poll every 5 seconds)
-                let should_check_manually = manual_update_check();
-                if should_check_manually {
+                if manual_update_check() {
                     manual_update_check.set(false);
-                    // Force check regardless of interval
-                    if let Ok(Some(info)) = checker.check_for_updates().await {
-                        update_info.set(Some(info));
-                        update_dialog_open.set(true);
+                    if let Err(err) = state.updater().check().await {
+                        tracing::warn!("Failed to check for updates: {err:#}");
                     }
                 }
-
-                // Periodic update check (every 12 hours)
-                if last_periodic_check.elapsed().as_secs() >= 12 * 3600 {
-                    if let Ok(Some(info)) = checker.check_for_updates().await {
-                        update_info.set(Some(info));
-                        update_dialog_open.set(true);
-                    }
-                    last_periodic_check = std::time::Instant::now();
-                }
-
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
             }
         }
     });
@@ -264,7 +259,17 @@ fn App() -> Element {
                 ()
             }
             "Quit" => {
-                std::process::exit(0);
+                // Release connector leases first, so the console doesn't
+                // show this device as connected until they time out.
+                match try_consume_context::<AppState>() {
+                    Some(state) => {
+                        spawn(async move {
+                            state.heartbeat().shutdown().await;
+                            std::process::exit(0);
+                        });
+                    }
+                    None => std::process::exit(0),
+                }
             }
             _ => {
                 eprintln!("Unknown menu event: {}", event.id.0);
@@ -285,16 +290,27 @@ fn App() -> Element {
     // Signal bumped on login/logout and auth state transitions so auth-dependent UI re-renders.
     let auth_changed = use_signal(|| 0u32);
     provide_context(auth_changed);
+    let session_ended = SessionEnded(use_signal(|| None));
+    provide_context(session_ended);
 
     let state_for_auth_watch = consume_context::<AppState>();
     use_future(move || {
         let state_for_auth_watch = state_for_auth_watch.clone();
         let mut auth_changed = auth_changed;
+        let SessionEnded(mut session_ended) = session_ended;
         async move {
-            let mut login_rx = state_for_auth_watch.datum().auth().login_state_watch();
+            let mut events = state_for_auth_watch.datum().auth_events();
             loop {
-                if login_rx.changed().await.is_err() {
-                    return;
+                match events.recv().await {
+                    Ok(AuthEvent::Refreshed) => continue,
+                    Ok(AuthEvent::RefreshFailed { error }) => {
+                        debug!(%error, "ui: session ended");
+                        session_ended.set(Some(error));
+                    }
+                    Ok(AuthEvent::LoggedIn { .. }) => session_ended.set(None),
+                    Ok(event) => debug!(?event, "ui: auth changed"),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
                 }
                 auth_changed.set(auth_changed().wrapping_add(1));
             }
@@ -326,17 +342,9 @@ fn App() -> Element {
             }
             div { class: "flex-1 overflow-hidden",
                 Head {}
+                SecretStoreBanner {}
                 Router::<Route> {}
-                if let Some(info) = update_info() {
-                    UpdateDialog {
-                        open: update_dialog_open,
-                        update_info: info.clone(),
-                        on_restart: move |_| -> () {}, //TODO: Implement proper restart mechanism
-                        on_dismiss: move |_| {
-                            update_dialog_open.set(false);
-                        },
-                    }
-                }
+                UpdateBanner {}
             }
         }
     }
@@ -1,41 +1,122 @@
-use dioxus::prelude::WritableExt;
+use std::{net::SocketAddr, sync::Arc};
+
+use dioxus::prelude::{ReadableExt, WritableExt};
 use lib::{
+    control::{AgentStatus, ControlServer, LocalListener},
+    crash::CrashReports,
     datum_cloud::{ApiEnv, DatumCloudClient},
-    HeartbeatAgent, ListenNode, Node, Repo, SelectedContext, TunnelService, TunnelSummary,
+    AdvertismentTicket, DeletedTunnel, HeartbeatAgent, ListenNode, Node, OutboundProxyHandle,
+    Preferences, Repo, RepoSnapshot, SelectedContext, StatsdConfig, Telemetry, TunnelService,
+    TunnelSummary, Updater,
 };
-use tokio::sync::Notify;
-use tracing::info;
+use n0_future::task::AbortOnDropHandle;
+use tokio::sync::{broadcast::error::RecvError, Notify};
+use tracing::{info, warn};
+
+use crate::clipboard::ClipboardWatch;
 
 #[derive(derive_more::Debug, Clone)]
 pub struct AppState {
+    repo: Repo,
     node: Node,
     datum: DatumCloudClient,
     heartbeat: HeartbeatAgent,
+    tunnel_service: TunnelService,
     tunnel_refresh: std::sync::Arc<Notify>,
     tunnel_cache: dioxus::signals::Signal<Vec<TunnelSummary>>,
+    last_deleted: dioxus::signals::Signal<Option<DeletedTunnel>>,
+    #[debug(skip)]
+    joined: dioxus::signals::Signal<Vec<Arc<OutboundProxyHandle>>>,
+    profile: String,
+    preferences: dioxus::signals::Signal<Preferences>,
+    clipboard: ClipboardWatch,
+    telemetry: Telemetry,
+    #[debug(skip)]
+    _telemetry_task: Arc<AbortOnDropHandle<()>>,
+    crash_reports: CrashReports,
+    updater: Updater,
+    #[debug(skip)]
+    _updater_task: Arc<AbortOnDropHandle<()>>,
+    /// Answers the CLI on the repo's control socket, unless `serve` already
+    /// does.
+    _control: Option<Arc<ControlServer>>,
+    /// The running statsd exporter and its config.
+    #[debug(skip)]
+    statsd: Arc<std::sync::Mutex<Option<(StatsdConfig, AbortOnDropHandle<()>)>>>,
 }
 
 impl AppState {
     pub async fn load() -> n0_error::Result<Self> {
-        let repo_path = Repo::default_location();
-        info!(repo_path = %repo_path.display(), "ui: loading repo");
-        let repo = Repo::open_or_create(repo_path).await?;
+        let base_path = Repo::default_location();
+        let profile = Repo::read_active_profile(&base_path).await?;
+        info!(repo_path = %base_path.display(), %profile, "ui: loading repo");
+        let repo = Repo::open_profile_in(&base_path, &profile).await?;
+        let control = LocalListener::bind(repo.path())
+            .await
+            .inspect_err(|err| warn!("ui: not serving the control socket: {err:#}"))
+            .ok();
         let (node, datum) = tokio::try_join! {
             Node::new(repo.clone()),
-            DatumCloudClient::with_repo(ApiEnv::default(), repo)
+            DatumCloudClient::with_repo(ApiEnv::default(), repo.clone())
         }?;
+        let preferences = repo.preferences().await?;
         let heartbeat = HeartbeatAgent::new(datum.clone(), node.listen.clone());
+        heartbeat.set_redacted_status(preferences.redacted_agent_status.clone());
         heartbeat.start().await;
+        let tunnel_service =
+            TunnelService::new(datum.clone(), node.listen.clone()).with_offline_queue(repo.clone());
+        let clipboard = ClipboardWatch::spawn(preferences.clipboard_watch);
+        let telemetry = Telemetry::open(
+            repo.path().join("telemetry.json"),
+            ApiEnv::default(),
+            env!("CARGO_PKG_VERSION"),
+            preferences.telemetry,
+        );
+        let telemetry_task = telemetry.spawn(repo.events());
+        let crash_reports = CrashReports::new(base_path.join("crashes"), ApiEnv::default());
+        let updater = Updater::new(repo.clone());
+        let updater_task = updater.spawn();
+        let control = control.map(|listener| {
+            let node = node.listen.clone();
+            Arc::new(ControlServer::spawn(listener, move || {
+                AgentStatus::new("app", &node)
+            }))
+        });
         let app_state = AppState {
+            repo,
+            profile,
             node,
             datum,
             heartbeat,
+            tunnel_service,
             tunnel_refresh: std::sync::Arc::new(Notify::new()),
             tunnel_cache: dioxus::signals::Signal::new(Vec::new()),
+            last_deleted: dioxus::signals::Signal::new(None),
+            joined: dioxus::signals::Signal::new(Vec::new()),
+            preferences: dioxus::signals::Signal::new(preferences),
+            clipboard,
+            telemetry,
+            _telemetry_task: Arc::new(telemetry_task),
+            crash_reports,
+            updater,
+            _updater_task: Arc::new(updater_task),
+            _control: control,
+            statsd: Default::default(),
         };
+        app_state.set_statsd(app_state.preferences.peek().statsd.clone());
+        app_state.send_crash_reports();
         Ok(app_state)
     }
 
+    pub fn repo(&self) -> &Repo {
+        &self.repo
+    }
+
+    /// The profile this app instance was started with.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
     pub fn datum(&self) -> &DatumCloudClient {
         &self.datum
     }
@@ -53,7 +134,7 @@ impl AppState {
     }
 
     pub fn tunnel_service(&self) -> TunnelService {
-        TunnelService::new(self.datum.clone(), self.node.listen.clone())
+        self.tunnel_service.clone()
     }
 
     pub fn tunnel_refresh(&self) -> std::sync::Arc<Notify> {
@@ -91,10 +172,220 @@ impl AppState {
         cache.set(list);
     }
 
+    /// The most recent delete that can still be undone.
+    pub fn last_deleted(&self) -> dioxus::signals::Signal<Option<DeletedTunnel>> {
+        self.last_deleted
+    }
+
+    pub fn set_last_deleted(&self, deleted: Option<DeletedTunnel>) {
+        let mut last_deleted = self.last_deleted;
+        last_deleted.set(deleted);
+    }
+
+    /// Drop the undo for `tunnel_id` if it is still the most recent delete.
+    pub fn expire_deleted(&self, tunnel_id: &str) {
+        let mut last_deleted = self.last_deleted;
+        if last_deleted
+            .peek()
+            .as_ref()
+            .is_some_and(|deleted| deleted.summary.id == tunnel_id)
+        {
+            last_deleted.set(None);
+        }
+    }
+
+    pub async fn undo_delete(&self) -> n0_error::Result<TunnelSummary> {
+        let Some(deleted) = self.last_deleted.peek().clone() else {
+            n0_error::bail_any!("Nothing to undo");
+        };
+        let summary = self.tunnel_service().restore_deleted(&deleted).await?;
+        self.heartbeat
+            .register_project(deleted.project_id.clone())
+            .await;
+        self.set_last_deleted(None);
+        self.upsert_tunnel(summary.clone());
+        self.bump_tunnel_refresh();
+        Ok(summary)
+    }
+
+    pub fn preferences(&self) -> dioxus::signals::Signal<Preferences> {
+        self.preferences
+    }
+
+    pub async fn set_preferences(&self, prefs: Preferences) -> n0_error::Result<()> {
+        self.repo.write_preferences(&prefs).await?;
+        self.clipboard.set_enabled(prefs.clipboard_watch);
+        self.telemetry.set_enabled(prefs.telemetry);
+        self.set_statsd(prefs.statsd.clone());
+        self.heartbeat
+            .set_redacted_status(prefs.redacted_agent_status.clone());
+        let mut preferences = self.preferences;
+        preferences.set(prefs);
+        self.send_crash_reports();
+        Ok(())
+    }
+
+    /// Keep the app in step with changes other processes make to the repo,
+    /// like the CLI adding a tunnel or another window changing preferences.
+    /// Runs until the app exits.
+    pub async fn sync_with_repo(&self) {
+        let mut snapshots = match self.repo.watch_state().await {
+            Ok(snapshots) => snapshots,
+            Err(err) => {
+                warn!("ui: not watching repo for changes: {err:#}");
+                return;
+            }
+        };
+        let mut events = self.repo.events().subscribe();
+        loop {
+            tokio::select! {
+                res = snapshots.changed() => {
+                    if res.is_err() {
+                        return;
+                    }
+                    let snapshot = snapshots.borrow_and_update().clone();
+                    self.apply_snapshot(snapshot).await;
+                }
+                event = events.recv() => match event {
+                    Ok(event) if event.kind.tunnel_id().is_some() => self.bump_tunnel_refresh(),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+
+    async fn apply_snapshot(&self, snapshot: RepoSnapshot) {
+        let mut refresh = self.listen_node().state().reload(snapshot.state);
+        if *self.preferences.peek() != snapshot.preferences {
+            info!("ui: preferences changed on disk");
+            self.clipboard
+                .set_enabled(snapshot.preferences.clipboard_watch);
+            self.telemetry.set_enabled(snapshot.preferences.telemetry);
+            self.set_statsd(snapshot.preferences.statsd.clone());
+            self.heartbeat
+                .set_redacted_status(snapshot.preferences.redacted_agent_status.clone());
+            let mut preferences = self.preferences;
+            preferences.set(snapshot.preferences);
+            self.send_crash_reports();
+        }
+        if self.selected_context() != snapshot.selected_context {
+            info!("ui: selected context changed on disk");
+            if let Err(err) = self
+                .datum
+                .set_selected_context(snapshot.selected_context)
+                .await
+            {
+                warn!("ui: failed to apply selected context: {err:#}");
+            }
+            refresh = true;
+        }
+        if refresh {
+            self.bump_tunnel_refresh();
+        }
+    }
+
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Send the crash reports of earlier runs in the background, if the user
+    /// opted in.
+    fn send_crash_reports(&self) {
+        if !self.preferences.peek().crash_reports {
+            return;
+        }
+        let crash_reports = self.crash_reports.clone();
+        tokio::spawn(async move {
+            match crash_reports.submit().await {
+                Ok(0) => {}
+                Ok(sent) => info!("ui: sent {sent} crash reports"),
+                Err(err) => warn!("ui: failed to send crash reports: {err:#}"),
+            }
+        });
+    }
+
+    pub fn updater(&self) -> &Updater {
+        &self.updater
+    }
+
+    /// Start, restart or stop the statsd exporter to match `config`.
+    fn set_statsd(&self, config: Option<StatsdConfig>) {
+        let mut statsd = self.statsd.lock().expect("poisoned");
+        if statsd.as_ref().map(|(current, _)| current) == config.as_ref() {
+            return;
+        }
+        *statsd = config.map(|config| {
+            info!(host = %config.host, port = config.port, "ui: pushing metrics to statsd");
+            let task = lib::statsd::spawn(
+                config.clone(),
+                self.repo.clone(),
+                self.listen_node().clone(),
+            );
+            (config, task)
+        });
+    }
+
+    pub fn clipboard(&self) -> &ClipboardWatch {
+        &self.clipboard
+    }
+
+    /// Tunnels joined from a ticket, forwarding a local port to a remote service.
+    pub fn joined(&self) -> dioxus::signals::Signal<Vec<Arc<OutboundProxyHandle>>> {
+        self.joined
+    }
+
+    /// Join a tunnel on `bind_addr`. With `auto_port`, a taken port is
+    /// replaced by a free one; the returned address is the one bound.
+    pub async fn join_ticket(
+        &self,
+        ticket: &AdvertismentTicket,
+        bind_addr: SocketAddr,
+        auto_port: bool,
+    ) -> n0_error::Result<SocketAddr> {
+        let connect = &self.node.connect;
+        let handle = if auto_port {
+            connect
+                .connect_and_bind_local_auto(ticket.endpoint, ticket.service(), bind_addr)
+                .await?
+        } else {
+            connect
+                .connect_and_bind_local(ticket.endpoint, ticket.service(), bind_addr)
+                .await?
+        };
+        let bound_addr = handle.bound_addr();
+        info!(%bound_addr, remote = %ticket.endpoint.fmt_short(), "ui: joined tunnel");
+        let mut joined = self.joined;
+        joined.write().push(Arc::new(handle));
+        Ok(bound_addr)
+    }
+
+    pub fn leave_joined(&self, bound_addr: SocketAddr) {
+        let mut joined = self.joined;
+        joined.write().retain(|handle| {
+            if handle.bound_addr() == bound_addr {
+                handle.abort();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     pub fn selected_context(&self) -> Option<SelectedContext> {
         self.datum.selected_context()
     }
 
+    /// Switch to another Datum account without restarting the node. Tunnels
+    /// of the previous account are dropped from the cache.
+    pub async fn switch_account(&self, account: &str) -> n0_error::Result<()> {
+        info!(%account, "ui: switching account");
+        self.datum.switch_account(&self.repo, account).await?;
+        self.set_tunnel_cache(Vec::new());
+        self.bump_tunnel_refresh();
+        Ok(())
+    }
+
     pub async fn set_selected_context(
         &self,
         selected_context: Option<SelectedContext>,
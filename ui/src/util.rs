@@ -1,18 +1,21 @@
+// Put text on the system clipboard, through the webview
+pub fn copy_to_clipboard(text: String) {
+    let eval = dioxus::document::eval("navigator.clipboard.writeText(await dioxus.recv());");
+    let _ = eval.send(text);
+}
+
 // Convert bytes to human-readable format
 pub fn humanize_bytes(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-
-    if bytes == 0 {
-        return "0 B".to_string();
-    }
-
-    let mut size = bytes as f64;
-    let mut unit_idx = 0;
+    crate::i18n::format_bytes(bytes)
+}
 
-    while size >= 1024.0 && unit_idx < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_idx += 1;
+// Convert a duration to a short human-readable age, e.g. "42s", "5m", "3h"
+pub fn humanize_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
     }
-
-    format!("{:.1} {}", size, UNITS[unit_idx])
 }
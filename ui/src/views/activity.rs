@@ -0,0 +1,73 @@
+use chrono::Local;
+use dioxus::prelude::*;
+use lib::events::{Event, EventQuery};
+
+use crate::{
+    components::{Icon, IconSource},
+    i18n::format_datetime,
+    state::AppState,
+    Route,
+};
+
+/// How many events the feed shows.
+const FEED_LIMIT: usize = 200;
+
+#[component]
+pub fn Activity() -> Element {
+    let nav = use_navigator();
+    let mut events = use_signal(Vec::<Event>::new);
+
+    use_future(move || async move {
+        let state = consume_context::<AppState>();
+        let log = state.listen_node().events().clone();
+        let mut rx = log.subscribe();
+        loop {
+            events.set(log.query(&EventQuery {
+                limit: Some(FEED_LIMIT),
+                ..Default::default()
+            }));
+            // Lagging only means we missed some notifications; the query above catches up.
+            if let Err(tokio::sync::broadcast::error::RecvError::Closed) = rx.recv().await {
+                return;
+            }
+        }
+    });
+
+    rsx! {
+        div { class: "space-y-5",
+            button {
+                class: "text-xs text-foreground flex items-center gap-1 mt-2 mb-7",
+                onclick: move |_| {
+                    let _ = nav.push(Route::Settings {});
+                },
+                Icon {
+                    source: IconSource::Named("chevron-down".into()),
+                    class: "rotate-90 text-icon-select",
+                    size: 10,
+                }
+                span { class: "underline", "Back to Settings" }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", "Activity" }
+                }
+                div { class: "p-4 flex flex-col gap-2",
+                    if events().is_empty() {
+                        p { class: "text-1xs text-foreground/60", "Nothing has happened yet." }
+                    }
+                    for event in events() {
+                        div { class: "flex items-baseline gap-3",
+                            span { class: "text-1xs text-foreground/60 font-mono whitespace-nowrap",
+                                {format_datetime(&event.at.with_timezone(&Local))}
+                            }
+                            span { class: "text-xs text-foreground break-all", "{event.kind.description()}" }
+                            if let Some(by) = &event.by {
+                                span { class: "text-1xs text-foreground/60 whitespace-nowrap", "{by}" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use chrono::{Local, Utc};
+use dioxus::prelude::*;
+use lib::tunnels::DeviceConnector;
+
+use crate::{
+    components::{Button, ButtonKind, Icon, IconSource},
+    i18n::{format_datetime, tr},
+    state::AppState,
+    Route,
+};
+
+/// The connectors of every project the user can see, so connectors left
+/// behind by old devices can be revoked.
+#[component]
+pub fn Devices() -> Element {
+    let nav = use_navigator();
+    let mut reload = use_signal(|| 0u32);
+    let devices = use_resource(move || async move {
+        let _ = reload();
+        let state = consume_context::<AppState>();
+        state
+            .tunnel_service()
+            .list_devices()
+            .await
+            .map_err(|err| format!("{err:#}"))
+    });
+    let mut revoke = use_action(move |device: DeviceConnector| async move {
+        let state = consume_context::<AppState>();
+        state
+            .tunnel_service()
+            .revoke_device(&device.project_id, &device.connector_name)
+            .await?;
+        reload += 1;
+        n0_error::Ok(())
+    });
+    let revoke_error = match revoke.value() {
+        Some(Err(err)) => Some(err.to_string()),
+        _ => None,
+    };
+    let now = Utc::now();
+
+    rsx! {
+        div { class: "space-y-5",
+            button {
+                class: "text-xs text-foreground flex items-center gap-1 mt-2 mb-7",
+                onclick: move |_| {
+                    let _ = nav.push(Route::Settings {});
+                },
+                Icon {
+                    source: IconSource::Named("chevron-down".into()),
+                    class: "rotate-90 text-icon-select",
+                    size: 10,
+                }
+                span { class: "underline", {tr!("devices-back")} }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", {tr!("devices-title")} }
+                    p { class: "text-1xs text-foreground/60 mt-1", {tr!("devices-description")} }
+                }
+                div { class: "p-4 flex flex-col gap-3",
+                    if let Some(err) = revoke_error {
+                        div { class: "text-xs text-alert-red-dark", "{err}" }
+                    }
+                    match devices() {
+                        None => rsx! {
+                            p { class: "text-1xs text-foreground/60", {tr!("devices-loading")} }
+                        },
+                        Some(Err(err)) => rsx! {
+                            div { class: "text-xs text-alert-red-dark", "{err}" }
+                        },
+                        Some(Ok(list)) if list.is_empty() => rsx! {
+                            p { class: "text-1xs text-foreground/60", {tr!("devices-empty")} }
+                        },
+                        Some(Ok(list)) => rsx! {
+                            for device in list {
+                                DeviceRow {
+                                    key: "{device.project_id}/{device.connector_name}",
+                                    stale: device.is_stale(now),
+                                    revoking: revoke.pending(),
+                                    on_revoke: move |device| revoke.call(device),
+                                    device,
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn DeviceRow(
+    device: DeviceConnector,
+    stale: bool,
+    revoking: bool,
+    on_revoke: EventHandler<DeviceConnector>,
+) -> Element {
+    let last_seen = match device.last_heartbeat {
+        Some(at) => format_datetime(&at.with_timezone(&Local)),
+        None => tr!("devices-never"),
+    };
+    let agent = [device.agent_version.clone(), device.agent_os.clone()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" · ");
+    let endpoint_id = device
+        .endpoint_id
+        .clone()
+        .unwrap_or_else(|| "—".to_string());
+
+    rsx! {
+        div { class: "flex items-center justify-between gap-4 border border-card-border rounded-md px-3 py-2",
+            div { class: "flex flex-col gap-0.5 min-w-0",
+                div { class: "flex items-center gap-2",
+                    span { class: "text-xs text-foreground", "{device.connector_name}" }
+                    if device.this_device {
+                        span { class: "text-1xs text-foreground/60", {tr!("devices-this-device")} }
+                    }
+                    if stale {
+                        span { class: "text-1xs text-alert-red-dark", {tr!("devices-stale")} }
+                    }
+                }
+                span { class: "text-1xs text-foreground/60", "{device.project_name}" }
+                span { class: "text-1xs text-foreground/60 font-mono truncate", "{endpoint_id}" }
+                span { class: "text-1xs text-foreground/60",
+                    {tr!("devices-last-seen", at = last_seen)}
+                    if !agent.is_empty() {
+                        " · {agent}"
+                    }
+                }
+            }
+            if !device.this_device {
+                Button {
+                    class: "shrink-0",
+                    text: if revoking { tr!("devices-revoking") } else { tr!("devices-revoke") },
+                    kind: ButtonKind::Outline,
+                    onclick: move |_| {
+                        if !revoking {
+                            on_revoke.call(device.clone());
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
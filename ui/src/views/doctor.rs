@@ -0,0 +1,97 @@
+use dioxus::prelude::*;
+use lib::doctor::{CheckStatus, DoctorReport};
+
+use crate::{
+    components::{Button, ButtonKind, Icon, IconSource},
+    state::AppState,
+    Route,
+};
+
+#[component]
+pub fn Doctor() -> Element {
+    let nav = use_navigator();
+    let mut report = use_signal(|| None::<DoctorReport>);
+    let mut running = use_signal(|| false);
+
+    let mut run_checks = use_action(move |_: ()| async move {
+        running.set(true);
+        let state = consume_context::<AppState>();
+        let result = lib::doctor::run(state.repo(), state.datum()).await;
+        report.set(Some(result));
+        running.set(false);
+        n0_error::Ok(())
+    });
+
+    let run_label = if running() {
+        "Running..."
+    } else {
+        "Run Checks"
+    };
+    let run_icon = running().then(|| IconSource::Named("loader-circle".into()));
+
+    rsx! {
+        div { class: "space-y-5",
+            button {
+                class: "text-xs text-foreground flex items-center gap-1 mt-2 mb-7",
+                onclick: move |_| {
+                    let _ = nav.push(Route::Settings {});
+                },
+                Icon {
+                    source: IconSource::Named("chevron-down".into()),
+                    class: "rotate-90 text-icon-select",
+                    size: 10,
+                }
+                span { class: "underline", "Back to Settings" }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border flex items-center justify-between",
+                    h2 { class: "text-sm text-foreground", "Connectivity" }
+                    Button {
+                        text: run_label,
+                        kind: ButtonKind::Secondary,
+                        leading_icon: run_icon,
+                        onclick: move |_| {
+                            if !running() {
+                                run_checks.call(());
+                            }
+                        },
+                    }
+                }
+                div { class: "p-4 flex flex-col gap-3",
+                    match report() {
+                        None => rsx! {
+                            p { class: "text-1xs text-foreground/60",
+                                "Checks relay reachability, NAT traversal, n0des, the Datum API and your login."
+                            }
+                        },
+                        Some(report) => rsx! {
+                            for check in report.checks {
+                                div { key: "{check.name}", class: "flex flex-col gap-1",
+                                    div { class: "flex items-center gap-2",
+                                        span { class: "text-1xs font-mono uppercase w-10 {status_class(check.status)}",
+                                            "{check.status.label()}"
+                                        }
+                                        span { class: "text-sm text-foreground w-20", "{check.name}" }
+                                        span { class: "text-xs text-foreground/80 break-all", "{check.detail}" }
+                                    }
+                                    if let Some(hint) = check.hint {
+                                        p { class: "text-1xs text-foreground/60 pl-32", "{hint}" }
+                                    }
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn status_class(status: CheckStatus) -> &'static str {
+    match status {
+        CheckStatus::Ok => "text-green-600",
+        CheckStatus::Warn => "text-yellow-600",
+        CheckStatus::Fail => "text-red-600",
+        CheckStatus::Skipped => "text-foreground/40",
+    }
+}
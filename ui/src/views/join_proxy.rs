@@ -1,61 +1,217 @@
+use std::{net::SocketAddr, str::FromStr};
+
+use dioxus::events::FormEvent;
 use dioxus::prelude::*;
+use lib::{AdvertismentTicket, ConnectionInfo, ConnectionState};
+
+use crate::{
+    components::{input::Input, Button, ButtonKind, Icon, IconSource},
+    i18n::tr,
+    state::AppState,
+    Route,
+};
 
 #[component]
 pub fn JoinProxy() -> Element {
-    rsx! {
-        div {
-            "unimplemented"
+    let nav = use_navigator();
+    let state = consume_context::<AppState>();
+    let joined = state.joined();
+    let mut connections = use_signal(Vec::<ConnectionInfo>::new);
+    let state_for_connections = state.clone();
+    use_future(move || {
+        let state = state_for_connections.clone();
+        async move {
+            let mut rx = state.node().connections_watch();
+            loop {
+                connections.set(rx.borrow_and_update().clone());
+                if rx.changed().await.is_err() {
+                    return;
+                }
+            }
         }
-    }
-    // let mut local_address = use_signal(|| "127.0.0.1:9000".to_string());
-    // let mut label = use_signal(|| "".to_string());
-    // let mut ticket_str = use_signal(|| "".to_string());
-    // // let mut validation_error = use_signal(|| "".to_string());
+    });
+    let mut ticket_str = use_signal(String::new);
+    let mut local_address = use_signal(|| "127.0.0.1:0".to_string());
+    let mut validation_error = use_signal(|| None::<String>);
+    let mut auto_port = use_signal(|| false);
+    // Re-checked as the address is edited, so conflicts show before joining.
+    let port_taken = use_resource(move || async move {
+        match SocketAddr::from_str(local_address().trim()) {
+            Ok(addr) if addr.port() != 0 => !lib::local_addr_available(addr).await,
+            _ => false,
+        }
+    });
+
+    let mut join = use_action(
+        move |(ticket, bind): (AdvertismentTicket, SocketAddr)| async move {
+            let state = consume_context::<AppState>();
+            let bound = state.join_ticket(&ticket, bind, auto_port()).await?;
+            ticket_str.set(String::new());
+            n0_error::Ok((bind, bound))
+        },
+    );
 
-    // rsx! {
-    //     div {
-    //         id: "create-domain",
-    //         class: "flex flex-col",
-    //         h1 { "join proxy" },
-    //         // p {
-    //         //     class: "text-red-500",
-    //         //     "{validation_error}"
-    //         // }
-    //         Subhead { text: "Local Address" }
-    //         input {
-    //             class: "border border-gray-300 rounded-md px-3 py-2 my-1 mr-4",
-    //             value: "{local_address}",
-    //             onchange: move |e| local_address.set(e.value()),
-    //         }
-    //         Subhead { text: "Label" }
-    //         input {
-    //             class: "border border-gray-300 rounded-md px-3 py-2 my-1 mr-4",
-    //             placeholder: "Label",
-    //             value: "{label}",
-    //             onchange: move |e| label.set(e.value()),
-    //         }
-    //         Subhead { text: "Ticket" }
-    //         textarea {
-    //             class: "border border-gray-300 rounded-md px-3 py-2 my-1 mr-4",
-    //             value: "{ticket_str}",
-    //             onchange: move |e| ticket_str.set(e.value()),
-    //         },
-    //         button {
-    //             class: "cursor-pointer",
-    //             onclick: move |_| async move {
-    //                 let state = consume_context::<AppState>();
-    //                 // let ticket = match TcpProxyTicket::from_str(&ticket_str()) {
-    //                 //     Ok(ticket) => ticket,
-    //                 //     Err(err) => {
-    //                 //         validation_error.set(format!("Invalid ticket: {}", err));
-    //                 //         return;
-    //                 //     }
-    //                 // };
-    //                 state.clone().node().outbound.connect(label()).await.unwrap();
-    //             },
-    //             "Join"
-    //         }
+    let on_join = move |_| {
+        let input = ticket_str();
+        let input = input.trim();
+        // A codename works too if the tunnel is announced on the local network.
+        let local = consume_context::<AppState>()
+            .node()
+            .connect
+            .local_discovery()
+            .and_then(|local| local.find(input));
+        let ticket = match (AdvertismentTicket::from_str(input), local) {
+            (Ok(ticket), _) | (Err(_), Some(ticket)) => ticket,
+            (Err(err), None) => {
+                validation_error.set(Some(format!("Invalid ticket: {err}")));
+                return;
+            }
+        };
+        let bind = match SocketAddr::from_str(local_address().trim()) {
+            Ok(bind) => bind,
+            Err(_) => {
+                validation_error.set(Some("Local address must look like 127.0.0.1:8080".into()));
+                return;
+            }
+        };
+        validation_error.set(None);
+        join.call((ticket, bind));
+    };
 
-    //     }
-    // }
+    let join_error = match join.value() {
+        Some(Err(err)) => Some(err.to_string()),
+        _ => None,
+    };
+    let rebound = match join.value() {
+        Some(Ok(joined)) => {
+            let (requested, bound) = joined();
+            (requested.port() != 0 && requested != bound).then_some(bound)
+        }
+        _ => None,
+    };
+
+    rsx! {
+        div { class: "space-y-5",
+            button {
+                class: "text-xs text-foreground flex items-center gap-1 mt-2 mb-7",
+                onclick: move |_| {
+                    let _ = nav.push(Route::ProxiesList {});
+                },
+                Icon {
+                    source: IconSource::Named("chevron-down".into()),
+                    class: "rotate-90 text-icon-select",
+                    size: 10,
+                }
+                span { class: "underline", "Back to Tunnels List" }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", "Join Tunnel" }
+                }
+                div { class: "p-4 flex flex-col gap-4",
+                    p { class: "text-1xs text-foreground/60",
+                        "Paste a ticket shared from another device, or scan its QR code with your camera app and paste the result."
+                    }
+                    div { class: "flex flex-col gap-2",
+                        label { class: "text-xs text-form-label/90", "Ticket" }
+                        textarea {
+                            class: "w-full h-20 text-1xs font-mono rounded-lg border border-app-border bg-card-background p-2 text-foreground resize-none focus:outline-none focus:ring-1 focus:ring-app-border",
+                            placeholder: "datum...",
+                            value: "{ticket_str}",
+                            oninput: move |e: FormEvent| ticket_str.set(e.value()),
+                        }
+                    }
+                    Input {
+                        label: Some("Local address".into()),
+                        description: Some("Use port 0 to pick a free port.".into()),
+                        value: "{local_address}",
+                        oninput: move |e: FormEvent| local_address.set(e.value()),
+                    }
+                    if port_taken().unwrap_or(false) && !auto_port() {
+                        div { class: "flex items-center justify-between gap-2 text-xs text-amber-500",
+                            {tr!("join-port-taken")}
+                            Button {
+                                kind: ButtonKind::Ghost,
+                                text: tr!("join-use-free-port"),
+                                onclick: move |_| {
+                                    if let Ok(addr) = SocketAddr::from_str(local_address().trim()) {
+                                        local_address.set(SocketAddr::new(addr.ip(), 0).to_string());
+                                    }
+                                },
+                            }
+                        }
+                    }
+                    label { class: "flex items-center gap-2 text-xs text-foreground",
+                        input {
+                            r#type: "checkbox",
+                            checked: auto_port(),
+                            onchange: move |e: FormEvent| auto_port.set(e.checked()),
+                        }
+                        {tr!("join-auto-port")}
+                    }
+                    if let Some(bound) = rebound {
+                        div { class: "text-xs text-foreground/70", {tr!("join-rebound", addr = bound.to_string())} }
+                    }
+                    if let Some(err) = validation_error().or(join_error) {
+                        div { class: "rounded-md border border-red-200 bg-red-50 p-3 text-alert-red-dark text-xs break-words",
+                            "{err}"
+                        }
+                    }
+                    Button {
+                        class: "w-fit",
+                        text: if join.pending() { "Joining…" } else { "Join" },
+                        kind: ButtonKind::Primary,
+                        onclick: on_join,
+                    }
+                }
+            }
+            if !joined().is_empty() {
+                div { class: "bg-card-background border border-card-border rounded-lg",
+                    div { class: "px-4 py-3 border-b border-card-border",
+                        h2 { class: "text-sm text-foreground", "Joined Tunnels" }
+                    }
+                    div { class: "p-4 flex flex-col gap-2",
+                        for handle in joined().into_iter() {
+                            {
+                                let bound = handle.bound_addr();
+                                let service = handle.advertisment().address();
+                                let remote = handle.remote_id().fmt_short().to_string();
+                                let reconnecting = connections()
+                                    .iter()
+                                    .find(|c| c.bound_addr == bound)
+                                    .and_then(|c| match c.state {
+                                        ConnectionState::Reconnecting { attempt } => {
+                                            Some(tr!("join-reconnecting", attempt = attempt))
+                                        }
+                                        ConnectionState::NoDirectPath { attempt } => {
+                                            Some(tr!("join-no-direct-path", attempt = attempt))
+                                        }
+                                        ConnectionState::Connected => None,
+                                    });
+                                rsx! {
+                                    div { key: "{bound}", class: "flex items-center justify-between gap-2",
+                                        span { class: "text-xs text-foreground",
+                                            "{bound} → {service} on {remote}"
+                                        }
+                                        if let Some(reconnecting) = reconnecting {
+                                            span { class: "text-1xs text-amber-500 ml-auto",
+                                                "{reconnecting}"
+                                            }
+                                        }
+                                        Button {
+                                            kind: ButtonKind::Ghost,
+                                            text: "Leave",
+                                            onclick: move |_| {
+                                                consume_context::<AppState>().leave_joined(bound);
+                                            },
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
@@ -1,12 +1,17 @@
 use dioxus::prelude::*;
-use lib::datum_cloud::LoginState;
+use lib::datum_cloud::{LoginDiagnostics, LoginState};
 
 use crate::{
-    components::{Button, ButtonKind, IconSource},
+    components::{Button, ButtonKind, IconSource, LoginDiagnosticsPanel},
     state::AppState,
     Route,
 };
 
+/// Why the app was logged out without the user asking, e.g. because the
+/// login could no longer be refreshed. Shown on the login page.
+#[derive(Clone, Copy)]
+pub struct SessionEnded(pub Signal<Option<String>>);
+
 #[component]
 pub fn Login() -> Element {
     let nav = use_navigator();
@@ -33,7 +38,6 @@ pub fn Login() -> Element {
 
     let mut login = use_action(move |_: ()| async move {
         let state = consume_context::<AppState>();
-        let mut auth_changed = consume_context::<Signal<u32>>();
         let datum = state.datum();
         match datum.login_state() {
             LoginState::Missing => datum.auth().login().await?,
@@ -44,29 +48,37 @@ pub fn Login() -> Element {
             }
             LoginState::Valid => {}
         }
-        // Refresh profile to get latest registration_approval status
-        datum.auth().refresh_profile().await?;
-        // Increment auth_changed to trigger navbar re-render with user info
-        auth_changed.set(auth_changed() + 1);
-        datum.refresh_orgs_projects_and_validate_context().await?;
+        finish_login(&state, nav).await
+    });
+
+    let mut device_login = use_action(move |_: ()| async move {
+        let state = consume_context::<AppState>();
+        state.datum().auth().login_device_code().await?;
+        finish_login(&state, nav).await
+    });
 
-        // Check registration approval before navigating
-        if let Ok(auth) = datum.auth_state().get() {
-            if let Some(approval) = &auth.profile.registration_approval {
-                if approval == "Pending" {
-                    // Don't navigate if registration is pending
-                    return Ok(());
+    // Mirror the login progress so the diagnostics panel can explain a stuck login.
+    let diagnostics = use_signal(LoginDiagnostics::default);
+    let mut show_diagnostics = use_signal(|| false);
+    let state_for_diagnostics = state.clone();
+    use_future(move || {
+        let state = state_for_diagnostics.clone();
+        let mut diagnostics = diagnostics;
+        async move {
+            let mut rx = state.datum().auth().login_diagnostics_watch();
+            loop {
+                diagnostics.set(rx.borrow_and_update().clone());
+                if rx.changed().await.is_err() {
+                    return;
                 }
             }
         }
-
-        if state.selected_context().is_some() {
-            nav.push(Route::ProxiesList {});
-        } else {
-            nav.push(Route::SelectProject {});
-        }
-        n0_error::Ok(())
     });
+    let login_failed = matches!(login.value(), Some(Err(_)));
+    let diagnostics_visible = show_diagnostics()
+        || login_failed
+        || device_login.pending()
+        || diagnostics().browser_opened == Some(false);
 
     const HERO_ILLUSTRATION: Asset = asset!("/assets/images/login-hero.png");
 
@@ -86,6 +98,9 @@ pub fn Login() -> Element {
             .map(|approval| approval == "Pending")
             .unwrap_or(false);
 
+    let SessionEnded(session_ended) = consume_context::<SessionEnded>();
+    let busy = login.pending() || device_login.pending();
+
     let title_text = if registration_pending {
         if let Ok(auth) = auth_state.get() {
             format!(
@@ -133,13 +148,77 @@ pub fn Login() -> Element {
                         "Once you've logged in, return back here to continue."
                     }
                 }
+                if let (Some(reason), false) = (session_ended(), busy) {
+                    div { class: "rounded-xl border border-red-200 bg-red-50 p-4 text-alert-red-dark",
+                        div { class: "text-sm font-semibold", "You were logged out" }
+                        div { class: "text-sm mt-1 break-words", "{reason}" }
+                    }
+                }
                 if let Some(Err(err)) = login.value() {
                     div { class: "rounded-xl border border-red-200 bg-red-50 p-4 text-alert-red-dark",
                         div { class: "text-sm font-semibold", "Failed to login" }
                         div { class: "text-sm mt-1 break-words", "{err}" }
                     }
                 }
+                if let Some(Err(err)) = device_login.value() {
+                    div { class: "rounded-xl border border-red-200 bg-red-50 p-4 text-alert-red-dark",
+                        div { class: "text-sm font-semibold", "Failed to login with a code" }
+                        div { class: "text-sm mt-1 break-words", "{err}" }
+                    }
+                }
+                if !registration_pending && diagnostics_visible {
+                    LoginDiagnosticsPanel {
+                        diagnostics: diagnostics(),
+                        device_code_pending: device_login.pending(),
+                        on_retry: move |_| {
+                            if !login.pending() {
+                                login.call(());
+                            }
+                        },
+                        on_device_code: move |_| {
+                            if !device_login.pending() {
+                                device_login.call(());
+                            }
+                        },
+                    }
+                } else if !registration_pending && login.pending() {
+                    button {
+                        class: "text-xs underline cursor-pointer",
+                        style: "color: var(--glacier-mist-700);",
+                        onclick: move |_| show_diagnostics.set(true),
+                        "Having trouble logging in?"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shared tail of the browser and device-code logins: load the profile and
+/// projects, then move on unless registration is still pending.
+async fn finish_login(state: &AppState, nav: Navigator) -> n0_error::Result<()> {
+    let mut auth_changed = consume_context::<Signal<u32>>();
+    let datum = state.datum();
+    // Refresh profile to get latest registration_approval status
+    datum.auth().refresh_profile().await?;
+    // Increment auth_changed to trigger navbar re-render with user info
+    auth_changed.set(auth_changed() + 1);
+    datum.refresh_orgs_projects_and_validate_context().await?;
+
+    // Check registration approval before navigating
+    if let Ok(auth) = datum.auth_state().get() {
+        if let Some(approval) = &auth.profile.registration_approval {
+            if approval == "Pending" {
+                // Don't navigate if registration is pending
+                return Ok(());
             }
         }
     }
+
+    if state.selected_context().is_some() {
+        nav.push(Route::ProxiesList {});
+    } else {
+        nav.push(Route::SelectProject {});
+    }
+    Ok(())
 }
@@ -4,6 +4,9 @@
 //! The [`Navbar`] component will be rendered on all pages of our app since every page is under the layout. The layout defines
 //! a common wrapper around all child routes.
 
+mod activity;
+mod devices;
+mod doctor;
 mod join_proxy;
 mod login;
 mod navbar;
@@ -12,8 +15,11 @@ mod select_project;
 mod settings;
 mod tunnel_bandwidth;
 
+pub use activity::Activity;
+pub use devices::Devices;
+pub use doctor::Doctor;
 pub use join_proxy::JoinProxy;
-pub use login::Login;
+pub use login::{Login, SessionEnded};
 pub use navbar::*;
 pub use proxies_list::{ProxiesList, TunnelCard};
 pub use select_project::SelectProject;
@@ -4,7 +4,8 @@ use crate::{
             DropdownMenu, DropdownMenuContent, DropdownMenuItem, DropdownMenuSeparator,
             DropdownMenuTrigger,
         },
-        AddTunnelDialog, Button, ButtonKind, Icon, IconSource, InviteUserDialog,
+        AddTunnelDialog, Button, ButtonKind, ClipboardJoinDialog, Icon, IconSource,
+        InviteUserDialog, UndoDeleteToast,
     },
     state::AppState,
     Route,
@@ -71,6 +72,8 @@ pub fn Chrome() -> Element {
                 open: invite_user_dialog_open(),
                 on_open_change: move |open| invite_user_dialog_open.set(open),
             }
+            ClipboardJoinDialog {}
+            UndoDeleteToast {}
         }
     }
 }
@@ -212,6 +215,12 @@ pub fn AppHeader(props: AppHeaderProps) -> Element {
                         kind: ButtonKind::Primary,
                         onclick: move |_| add_tunnel_dialog_open.set(true),
                     }
+                    Button {
+                        class: "ml-2",
+                        text: "Join",
+                        kind: ButtonKind::Outline,
+                        to: Route::JoinProxy {},
+                    }
                 }
                 div { class: "flex-1" }
                 // Right side: Org/Project selectors and user menu
@@ -1,6 +1,11 @@
+use std::{collections::BTreeSet, time::Duration};
+
 use dioxus::events::FormEvent;
 use dioxus::prelude::*;
-use lib::TunnelSummary;
+use lib::{
+    share::{public_hostname, ShareText},
+    BulkFailure, TunnelSort, TunnelSummary,
+};
 use open::that;
 
 use crate::{
@@ -10,14 +15,34 @@ use crate::{
             DropdownMenuTrigger,
         },
         input::Input,
+        select::{
+            Select, SelectItemIndicator, SelectList, SelectOptionItem, SelectTrigger, SelectValue,
+        },
         skeleton::Skeleton,
-        AddTunnelDialog, Button, ButtonKind, DeleteTunnelDialog, Icon, IconSource, Switch,
-        SwitchThumb,
+        AddTunnelDialog, Button, ButtonKind, DeleteTunnelDialog, Icon, IconSource, PendingChanges,
+        QuotaBars, ShareTunnelDialog, Switch, SwitchThumb,
     },
+    i18n::{tr, translate},
     state::AppState,
+    util::{copy_to_clipboard, humanize_bytes, humanize_duration},
     Route,
 };
 
+/// Orders offered for the list, by message id.
+const TUNNEL_SORTS: &[(&str, TunnelSort)] = &[
+    ("tunnels-sort-recent", TunnelSort::RecentlyUsed),
+    ("tunnels-sort-name", TunnelSort::Name),
+    ("tunnels-sort-status", TunnelSort::Status),
+];
+
+/// What to do with the tunnels selected in the list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BulkAction {
+    Enable,
+    Disable,
+    Delete,
+}
+
 #[component]
 pub fn ProxiesList() -> Element {
     let state = consume_context::<AppState>();
@@ -31,41 +56,41 @@ pub fn ProxiesList() -> Element {
         let mut has_loaded_for_future = has_loaded;
         async move {
             let mut ctx_rx = state_for_future.datum().selected_context_watch();
-            let refresh = state_for_future.tunnel_refresh();
             loop {
+                // The reconciler follows API changes to the project's tunnels,
+                // so the list only needs loading once per selected project.
+                let tunnels_rx = state_for_future.tunnel_service().watch_active();
                 let list = state_for_future
                     .tunnel_service()
                     .list_active()
                     .await
                     .unwrap_or_default();
-                // Check if any tunnel is missing a hostname or not yet accepted/programmed.
-                // If so, poll more frequently.
-                // TODO(zachsmith1): When pending, poll only the specific HTTPProxy
-                // resource(s) instead of listing all tunnels each cycle.
-                let has_pending_hostname = list.iter().any(|t| t.hostnames.is_empty());
-                let has_pending_status = list.iter().any(|t| !t.accepted || !t.programmed);
                 state_for_future.set_tunnel_cache(list);
                 has_loaded_for_future.set(true);
 
-                if has_pending_hostname || has_pending_status {
-                    // Poll every 3 seconds when waiting for hostname provisioning
+                let Some(mut tunnels_rx) = tunnels_rx else {
+                    if ctx_rx.changed().await.is_err() {
+                        return;
+                    }
+                    continue;
+                };
+                loop {
                     tokio::select! {
                         res = ctx_rx.changed() => {
                             if res.is_err() {
                                 return;
                             }
+                            break;
                         }
-                        _ = refresh.notified() => {}
-                        _ = tokio::time::sleep(std::time::Duration::from_secs(3)) => {}
-                    }
-                } else {
-                    tokio::select! {
-                    res = ctx_rx.changed() => {
-                        if res.is_err() {
-                            return;
+                        res = tunnels_rx.changed() => {
+                            if res.is_err() {
+                                break;
+                            }
                         }
                     }
-                    _ = refresh.notified() => {}
+                    let list = tunnels_rx.borrow_and_update().clone();
+                    if let Some(list) = list {
+                        state_for_future.set_tunnel_cache(list);
                     }
                 }
             }
@@ -85,6 +110,7 @@ pub fn ProxiesList() -> Element {
                 .await
                 .inspect_err(|err| {
                     tracing::warn!("delete tunnel failed: {err:#}");
+                    state.telemetry().error("tunnel_delete");
                 })?;
             if outcome.connector_deleted {
                 state
@@ -93,10 +119,60 @@ pub fn ProxiesList() -> Element {
                     .await;
             }
             state.remove_tunnel(&tunnel.id);
+            state.set_last_deleted(outcome.deleted);
             state.bump_tunnel_refresh();
             n0_error::Ok(())
         }
     });
+    // Multi-select mode, for enabling, disabling or deleting several tunnels.
+    let mut selecting = use_signal(|| false);
+    let mut selected = use_signal(BTreeSet::<String>::new);
+    let mut confirm_bulk_delete = use_signal(|| false);
+    let mut bulk_failures = use_signal(Vec::<BulkFailure>::new);
+    let mut bulk_action = use_action(move |action: BulkAction| async move {
+        let state = consume_context::<AppState>();
+        let service = state.tunnel_service();
+        let tunnel_ids = selected.peek().iter().cloned().collect::<Vec<_>>();
+        let failed = match action {
+            BulkAction::Enable | BulkAction::Disable => {
+                let enabled = action == BulkAction::Enable;
+                let outcome = service.set_enabled_many(tunnel_ids, enabled).await;
+                if enabled && !outcome.succeeded.is_empty() {
+                    if let Some(context) = state.selected_context() {
+                        state.heartbeat().register_project(context.project_id).await;
+                    }
+                }
+                for tunnel in outcome.succeeded {
+                    state.upsert_tunnel(tunnel);
+                }
+                outcome.failed
+            }
+            BulkAction::Delete => {
+                let outcome = service.delete_many(tunnel_ids.clone()).await;
+                if let Some(deleted) = outcome.succeeded.iter().find(|d| d.connector_deleted) {
+                    state
+                        .heartbeat()
+                        .deregister_project(&deleted.project_id)
+                        .await;
+                }
+                for tunnel_id in &tunnel_ids {
+                    if !outcome.failed.iter().any(|f| &f.tunnel_id == tunnel_id) {
+                        state.remove_tunnel(tunnel_id);
+                    }
+                }
+                outcome.failed
+            }
+        };
+        state.bump_tunnel_refresh();
+        // Keep the failed tunnels selected so they can be retried.
+        selected.set(failed.iter().map(|f| f.tunnel_id.clone()).collect());
+        if failed.is_empty() {
+            selecting.set(false);
+        }
+        bulk_failures.set(failed);
+        n0_error::Ok(())
+    });
+
     let mut delete_confirm_open = use_signal(|| false);
     let mut tunnel_to_delete = use_signal(|| None::<TunnelSummary>);
     let mut tunnel_pending_delete = use_signal(|| None::<TunnelSummary>);
@@ -128,7 +204,7 @@ pub fn ProxiesList() -> Element {
         .get()
         .ok()
         .and_then(|a| a.profile.first_name.clone())
-        .unwrap_or_else(|| "there".to_string());
+        .unwrap_or_else(|| tr!("tunnels-greeting-fallback-name"));
 
     const EMPTY_MOON: Asset = asset!("/assets/images/empty-card-moon.png");
     const EMPTY_ROCKS: Asset = asset!("/assets/images/empty-card-rocks.png");
@@ -136,8 +212,43 @@ pub fn ProxiesList() -> Element {
     let mut dialog_open = use_signal(|| false);
     let mut editing_tunnel = use_signal(|| None::<TunnelSummary>);
     let mut search_query = use_signal(String::new);
+    let mut tag_filter = use_signal(|| None::<String>);
+    let mut group_by_tag = use_signal(|| false);
+    let mut sort = use_signal(TunnelSort::default);
+    // Headings of the groups folded away.
+    let mut collapsed = use_signal(BTreeSet::<String>::new);
+
+    // Reload quotas whenever the tunnel list changes.
+    let quotas = use_resource(move || async move {
+        let _ = tunnels().len();
+        consume_context::<AppState>()
+            .tunnel_service()
+            .quotas_active()
+            .await
+            .inspect_err(|err| tracing::debug!("failed to load quotas: {err:#}"))
+            .ok()
+    });
+
+    let permissions = use_resource(move || async move {
+        consume_context::<AppState>()
+            .tunnel_service()
+            .permissions_active()
+            .await
+            .inspect_err(|err| tracing::debug!("failed to check permissions: {err:#}"))
+            .ok()
+            .flatten()
+    });
+    let read_only = permissions()
+        .flatten()
+        .is_some_and(|permissions| !permissions.can_manage());
 
     let show_search = tunnels().len() > 2;
+    let all_ids = tunnels()
+        .iter()
+        .map(|t| t.id.clone())
+        .collect::<BTreeSet<_>>();
+    let show_bulk_bar = has_loaded() && !tunnels().is_empty() && !read_only;
+    let selected_count = selected().len();
     let query = search_query().trim().to_lowercase();
     let filtered_tunnels: Vec<TunnelSummary> = if query.is_empty() {
         tunnels().into_iter().collect()
@@ -151,9 +262,56 @@ pub fn ProxiesList() -> Element {
                         .any(|h| h.to_lowercase().contains(&query))
                     || t.endpoint.to_lowercase().contains(&query)
                     || t.id.to_lowercase().contains(&query)
+                    || t.tags.iter().any(|tag| tag.to_lowercase().contains(&query))
             })
             .collect()
     };
+    let all_tags = tunnels()
+        .iter()
+        .flat_map(|t| t.tags.iter().cloned())
+        .collect::<BTreeSet<_>>();
+    let mut filtered_tunnels = match tag_filter() {
+        Some(tag) => filtered_tunnels
+            .into_iter()
+            .filter(|t| t.tags.contains(&tag))
+            .collect(),
+        None => filtered_tunnels,
+    };
+    // The listen node notes when each of its tunnels last served a request.
+    let service = state.tunnel_service();
+    sort().sort(&mut filtered_tunnels, |id| {
+        service
+            .activity(id)
+            .and_then(|activity| activity.last_request_at)
+    });
+    let sort_id = TUNNEL_SORTS
+        .iter()
+        .find(|(_, s)| *s == sort())
+        .map(|(id, _)| id.to_string());
+    // With grouping, a tunnel shows under each of its tags.
+    let groups: Vec<(Option<String>, Vec<TunnelSummary>)> =
+        if group_by_tag() && tag_filter().is_none() {
+            let mut groups = all_tags
+                .iter()
+                .map(|tag| {
+                    let tagged = filtered_tunnels
+                        .iter()
+                        .filter(|t| t.tags.contains(tag))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    (Some(tag.clone()), tagged)
+                })
+                .collect::<Vec<_>>();
+            let untagged = filtered_tunnels
+                .into_iter()
+                .filter(|t| t.tags.is_empty())
+                .collect::<Vec<_>>();
+            groups.push((Some(tr!("tunnels-untagged")), untagged));
+            groups.retain(|(_, tunnels)| !tunnels.is_empty());
+            groups
+        } else {
+            vec![(None, filtered_tunnels)]
+        };
 
     let list = if !has_loaded() {
         // Loading state: show 3 skeleton items
@@ -205,12 +363,12 @@ pub fn ProxiesList() -> Element {
                         alt: "",
                     }
                     div { class: "text-sm mt-2 max-w-xs",
-                        "Hey {first_name}, Want to safely expose a local service on the internet?"
+                        {tr!("tunnels-empty", name = first_name.clone())}
                     }
                     Button {
                         kind: ButtonKind::Outline,
                         class: "w-fit text-foreground",
-                        text: "Add New",
+                        text: tr!("tunnels-add-new"),
                         leading_icon: Some(IconSource::Named("plus".into())),
                         onclick: move |_| dialog_open.set(true),
                     }
@@ -221,30 +379,128 @@ pub fn ProxiesList() -> Element {
         }
     } else {
         let tunnel_to_delete_for_cards = tunnel_to_delete;
+        let card = move |tunnel: TunnelSummary| {
+            rsx! {
+                TunnelCard {
+                    key: "{tunnel.id}",
+                    selected: selecting().then(|| selected().contains(&tunnel.id)),
+                    on_select: {
+                        let tunnel_id = tunnel.id.clone();
+                        move |checked: bool| {
+                            if checked {
+                                selected.write().insert(tunnel_id.clone());
+                            } else {
+                                selected.write().remove(&tunnel_id);
+                            }
+                        }
+                    },
+                    tunnel,
+                    show_view_item: true,
+                    show_bandwidth: false,
+                    tunnel_to_delete: tunnel_to_delete_for_cards,
+                    on_delete: on_delete_handler,
+                    on_edit: move |t| {
+                        editing_tunnel.set(Some(t));
+                        dialog_open.set(true);
+                    },
+                }
+            }
+        };
         rsx! {
             div { class: "space-y-5",
                 if show_search {
-                    div { class: "mb-4",
-                        Input {
-                            leading_icon: Some(IconSource::Named("search".into())),
-                            placeholder: "Search tunnels...",
-                            value: "{search_query}",
-                            oninput: move |e: FormEvent| search_query.set(e.value()),
+                    div { class: "mb-4 flex items-center gap-2",
+                        div { class: "flex-1",
+                            Input {
+                                leading_icon: Some(IconSource::Named("search".into())),
+                                placeholder: tr!("tunnels-search-placeholder"),
+                                value: "{search_query}",
+                                oninput: move |e: FormEvent| search_query.set(e.value()),
+                            }
+                        }
+                        div { class: "w-44",
+                            Select {
+                                value: sort_id,
+                                on_value_change: move |value: Option<String>| {
+                                    if let Some((_, next)) = TUNNEL_SORTS
+                                        .iter()
+                                        .find(|(id, _)| Some(*id) == value.as_deref())
+                                    {
+                                        sort.set(*next);
+                                    }
+                                },
+                                placeholder: translate("tunnels-sort-recent", None),
+                                disabled: false,
+                                SelectTrigger { SelectValue {} }
+                                SelectList {
+                                    for (i , (id , _)) in TUNNEL_SORTS.iter().copied().enumerate() {
+                                        SelectOptionItem {
+                                            value: id.to_string(),
+                                            text_value: translate(id, None),
+                                            index: i,
+                                            {translate(id, None)}
+                                            SelectItemIndicator {}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                if !all_tags.is_empty() {
+                    div { class: "flex flex-wrap items-center gap-2",
+                        TagChip {
+                            text: tr!("tunnels-filter-all"),
+                            active: tag_filter().is_none(),
+                            onclick: move |_| tag_filter.set(None),
+                        }
+                        for tag in all_tags {
+                            TagChip {
+                                key: "{tag}",
+                                text: tag.clone(),
+                                active: tag_filter().as_ref() == Some(&tag),
+                                onclick: move |_| tag_filter.set(Some(tag.clone())),
+                            }
+                        }
+                        div { class: "ml-auto flex items-center gap-2",
+                            label { class: "text-xs text-foreground/70", {tr!("tunnels-group-by-tag")} }
+                            Switch {
+                                checked: group_by_tag(),
+                                on_checked_change: move |checked| group_by_tag.set(checked),
+                                SwitchThumb {}
+                            }
                         }
                     }
                 }
-                for tunnel in filtered_tunnels.into_iter() {
-                    TunnelCard {
-                        key: "{tunnel.id}",
-                        tunnel,
-                        show_view_item: true,
-                        show_bandwidth: false,
-                        tunnel_to_delete: tunnel_to_delete_for_cards,
-                        on_delete: on_delete_handler,
-                        on_edit: move |t| {
-                            editing_tunnel.set(Some(t));
-                            dialog_open.set(true);
-                        },
+                for (heading, members) in groups {
+                    if let Some(heading) = heading.clone() {
+                        button {
+                            r#type: "button",
+                            class: "flex items-center gap-1.5 text-xs text-icon-select font-normal",
+                            onclick: {
+                                let heading = heading.clone();
+                                move |_| {
+                                    let mut collapsed = collapsed.write();
+                                    if !collapsed.remove(&heading) {
+                                        collapsed.insert(heading.clone());
+                                    }
+                                }
+                            },
+                            Icon {
+                                source: IconSource::Named("chevron-down".into()),
+                                size: 12,
+                                class: if collapsed().contains(&heading) { "-rotate-90" } else { "" },
+                            }
+                            "{heading}"
+                            span { class: "text-foreground/50",
+                                {tr!("tunnels-group-count", count = members.len())}
+                            }
+                        }
+                    }
+                    if !heading.as_ref().is_some_and(|heading| collapsed().contains(heading)) {
+                        for tunnel in members {
+                            {card(tunnel)}
+                        }
                     }
                 }
             }
@@ -252,7 +508,94 @@ pub fn ProxiesList() -> Element {
     };
 
     rsx! {
-        div { class: "max-w-5xl mx-auto", {list} }
+        div { class: "max-w-5xl mx-auto",
+            if read_only {
+                div { class: "mb-4 rounded-lg border border-red-200 bg-red-50 p-4 text-xs text-alert-red-dark",
+                    {tr!("tunnels-permission-denied")}
+                }
+            }
+            if let Some(quotas) = quotas().flatten() {
+                QuotaBars { quotas }
+            }
+            PendingChanges {}
+            if show_bulk_bar {
+                div { class: "flex items-center justify-end gap-2 mb-4",
+                    if selecting() {
+                        span { class: "text-xs text-foreground/70 mr-auto",
+                            {tr!("tunnels-selected", count = selected_count)}
+                        }
+                        Button {
+                            kind: ButtonKind::Ghost,
+                            text: tr!("tunnels-select-all"),
+                            onclick: move |_| selected.set(all_ids.clone()),
+                        }
+                        Button {
+                            kind: ButtonKind::Outline,
+                            text: tr!("tunnels-bulk-enable"),
+                            onclick: move |_| {
+                                if !bulk_action.pending() && selected_count > 0 {
+                                    bulk_action.call(BulkAction::Enable);
+                                }
+                            },
+                        }
+                        Button {
+                            kind: ButtonKind::Outline,
+                            text: tr!("tunnels-bulk-disable"),
+                            onclick: move |_| {
+                                if !bulk_action.pending() && selected_count > 0 {
+                                    bulk_action.call(BulkAction::Disable);
+                                }
+                            },
+                        }
+                        Button {
+                            kind: ButtonKind::Outline,
+                            class: "text-alert-red-dark",
+                            text: if confirm_bulk_delete() {
+                                tr!("tunnels-bulk-delete-confirm", count = selected_count)
+                            } else {
+                                tr!("tunnels-bulk-delete")
+                            },
+                            onclick: move |_| {
+                                if bulk_action.pending() || selected_count == 0 {
+                                    return;
+                                }
+                                if confirm_bulk_delete() {
+                                    confirm_bulk_delete.set(false);
+                                    bulk_action.call(BulkAction::Delete);
+                                } else {
+                                    confirm_bulk_delete.set(true);
+                                }
+                            },
+                        }
+                        Button {
+                            kind: ButtonKind::Ghost,
+                            text: tr!("tunnels-select-cancel"),
+                            onclick: move |_| {
+                                selecting.set(false);
+                                selected.write().clear();
+                                confirm_bulk_delete.set(false);
+                                bulk_failures.set(Vec::new());
+                            },
+                        }
+                    } else {
+                        Button {
+                            kind: ButtonKind::Ghost,
+                            text: tr!("tunnels-select"),
+                            onclick: move |_| selecting.set(true),
+                        }
+                    }
+                }
+            }
+            if !bulk_failures().is_empty() {
+                div { class: "mb-4 rounded-lg border border-red-200 bg-red-50 p-4 text-xs text-alert-red-dark flex flex-col gap-1",
+                    div { {tr!("tunnels-bulk-failed", count = bulk_failures().len())} }
+                    for failure in bulk_failures() {
+                        div { key: "{failure.tunnel_id}", "{failure.tunnel_id}: {failure.error}" }
+                    }
+                }
+            }
+            {list}
+        }
         AddTunnelDialog {
             open: dialog_open,
             on_open_change: move |open| {
@@ -304,6 +647,86 @@ pub fn ProxiesList() -> Element {
     }
 }
 
+#[component]
+fn TagChip(text: String, active: bool, onclick: EventHandler<MouseEvent>) -> Element {
+    let class = if active {
+        "bg-foreground text-background"
+    } else {
+        "bg-foreground/10 text-foreground/70 hover:bg-foreground/20"
+    };
+    rsx! {
+        button {
+            r#type: "button",
+            class: "text-1xs rounded-full px-2.5 py-1 {class}",
+            onclick: move |e| onclick.call(e),
+            "{text}"
+        }
+    }
+}
+
+/// Time left until a tunnel turns itself off, ticking every second.
+#[component]
+fn ExpiryCountdown(expires_at: chrono::DateTime<chrono::Utc>) -> Element {
+    let mut now = use_signal(chrono::Utc::now);
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            now.set(chrono::Utc::now());
+        }
+    });
+    let left = (expires_at - now()).to_std().unwrap_or_default();
+    rsx! {
+        span { class: "text-1xs rounded-full px-2 py-0.5 bg-amber-50 text-amber-900",
+            {tr!("tunnel-expires-in", duration = humanize_duration(left))}
+        }
+    }
+}
+
+/// Transfer against the tunnel's quota, if it has one.
+#[component]
+fn TransferQuotaChip(tunnel_id: String) -> Element {
+    let state = consume_context::<AppState>();
+    let mut now = use_signal(chrono::Utc::now);
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            now.set(chrono::Utc::now());
+        }
+    });
+    let Some(quota) = state
+        .listen_node()
+        .proxy_by_id(&tunnel_id)
+        .and_then(|proxy| proxy.transfer_quota)
+    else {
+        return rsx! {};
+    };
+    let usage = state.listen_node().transfer_usage(&tunnel_id);
+    let now = now();
+    let (text, class) = if let Some(exceeded) = quota.exceeded(&usage, now) {
+        let limit = humanize_bytes(exceeded.limit);
+        (
+            tr!("tunnel-quota-reached", limit = limit),
+            "bg-red-50 text-alert-red-dark",
+        )
+    } else {
+        // The monthly limit is the one a tunnel runs into first in practice.
+        let (used, limit) = match (quota.monthly_bytes, quota.total_bytes) {
+            (Some(limit), _) => (usage.bytes_this_month(now), limit),
+            (None, Some(limit)) => (usage.total_bytes, limit),
+            (None, None) => return rsx! {},
+        };
+        let text = tr!(
+            "tunnel-quota-used",
+            used = humanize_bytes(used),
+            limit = humanize_bytes(limit),
+        );
+        (text, "bg-foreground/10 text-foreground/70")
+    };
+    rsx! {
+        span { class: "text-1xs rounded-full px-2 py-0.5 {class}", "{text}" }
+    }
+}
+
 #[component]
 pub fn TunnelCard(
     tunnel: TunnelSummary,
@@ -312,6 +735,10 @@ pub fn TunnelCard(
     tunnel_to_delete: ReadSignal<Option<TunnelSummary>>,
     on_delete: EventHandler<TunnelSummary>,
     on_edit: EventHandler<TunnelSummary>,
+    /// Whether the card is checked, while the list is in multi-select mode.
+    #[props(default)]
+    selected: Option<bool>,
+    #[props(default)] on_select: Option<EventHandler<bool>>,
 ) -> Element {
     let tunnel_id = tunnel.id.clone();
     let mut menu_open = use_signal(|| None::<bool>);
@@ -351,19 +778,14 @@ pub fn TunnelCard(
     let enabled = tunnel.enabled;
     let is_ready = tunnel.accepted && tunnel.programmed;
     let proxy_name = tunnel.id.clone();
-    let public_hostname = tunnel
-        .hostnames
-        .iter()
-        .find(|h| !h.starts_with("v4.") && !h.starts_with("v6."))
-        .cloned()
-        .or_else(|| tunnel.hostnames.first().cloned());
+    let public_hostname = public_hostname(&tunnel.hostnames).map(str::to_string);
     let public_hostname_click = public_hostname.clone();
     let short_id = public_hostname
         .as_ref()
         .and_then(|h| h.split('.').next())
         .map(|s| s.to_string());
     let display_endpoint = if tunnel.endpoint.is_empty() {
-        "unknown".to_string()
+        tr!("tunnel-endpoint-unknown")
     } else {
         tunnel.endpoint.clone()
     };
@@ -382,6 +804,36 @@ pub fn TunnelCard(
     let tunnel_for_edit = tunnel.clone();
     let tunnel_for_delete = tunnel.clone();
     let tunnel_for_memo = tunnel.clone();
+    let mut share_open = use_signal(|| false);
+    let mut tunnel_to_share = use_signal(|| None::<TunnelSummary>);
+    let tunnel_for_share = tunnel.clone();
+
+    // Tickets are built from the local proxy state, like in the share dialog.
+    let share_text = {
+        let listen = consume_context::<AppState>().listen_node().clone();
+        let ticket = listen
+            .proxy_by_id(&tunnel.id)
+            .map(|proxy| proxy.ticket(listen.endpoint_id()).to_string());
+        ShareText::new(&tunnel, ticket)
+    };
+    let mut copied = use_signal(|| false);
+    let mut copy = move |text: String| {
+        copy_to_clipboard(text);
+        copied.set(true);
+        spawn(async move {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            copied.set(false);
+        });
+    };
+    let share_url = share_text.url.clone();
+    let share_ticket = share_text.ticket.clone();
+    let share_markdown = share_text.markdown();
+    let share_slack = share_text.slack();
+    // Hooks of the items only shown when there is a URL or ticket to copy.
+    let copy_url_value = use_signal(|| "copy-url".to_string());
+    let copy_url_index = use_signal(|| 2);
+    let copy_ticket_value = use_signal(|| "copy-ticket".to_string());
+    let copy_ticket_index = use_signal(|| 3);
 
     // Compute is_deleting reactively based on whether this tunnel is being deleted
     // Only show as deleting when deletion has been confirmed (tunnel is in tunnel_to_delete)
@@ -409,7 +861,34 @@ pub fn TunnelCard(
             div { class: if is_disabled() { "opacity-90" } else { "" },
                 // header row: title + toggle
                 div { class: "px-4 py-2.5 flex items-center justify-between bg-card-background rounded-t-lg",
-                    h2 { class: "text-md font-normal text-foreground", {tunnel.label.clone()} }
+                    div { class: "flex items-center gap-2.5",
+                        if let Some(checked) = selected {
+                            input {
+                                r#type: "checkbox",
+                                checked,
+                                onchange: move |e: FormEvent| {
+                                    if let Some(on_select) = on_select {
+                                        on_select.call(e.checked());
+                                    }
+                                },
+                            }
+                        }
+                        h2 { class: "text-md font-normal text-foreground", {tunnel.label.clone()} }
+                        for tag in tunnel.tags.iter() {
+                            span {
+                                key: "{tag}",
+                                class: "text-1xs rounded-full px-2 py-0.5 bg-foreground/10 text-foreground/70",
+                                "{tag}"
+                            }
+                        }
+                        if let Some(expires_at) = tunnel.expires_at.filter(|_| enabled) {
+                            ExpiryCountdown { expires_at }
+                        }
+                        TransferQuotaChip { tunnel_id: tunnel.id.clone() }
+                        if copied() {
+                            span { class: "text-1xs text-foreground/60", {tr!("tunnel-copied")} }
+                        }
+                    }
                     if is_ready && !is_deleting() {
                         Switch {
                             checked: enabled,
@@ -484,7 +963,7 @@ pub fn TunnelCard(
                                     size: 14,
                                 }
                                 span { class: "text-xs text-foreground/90 font-medium",
-                                    "Hostname Provisioning..."
+                                    {tr!("tunnel-hostname-provisioning")}
                                 }
                             }
                         }
@@ -514,7 +993,7 @@ pub fn TunnelCard(
                                                         id: tunnel_id_for_view.clone(),
                                                     });
                                                 },
-                                                "View"
+                                                {tr!("tunnel-menu-view")}
                                             }
                                         }
                                     } else {
@@ -526,18 +1005,61 @@ pub fn TunnelCard(
                                     index: use_signal(|| 0),
                                     disabled: is_disabled,
                                     on_select: move |_| on_edit.call(tunnel_for_edit.clone()),
-                                    "Edit"
+                                    {tr!("tunnel-menu-edit")}
+                                }
+                                DropdownMenuItem::<String> {
+                                    value: use_signal(|| "share".to_string()),
+                                    index: use_signal(|| 1),
+                                    disabled: is_disabled,
+                                    on_select: move |_| {
+                                        tunnel_to_share.set(Some(tunnel_for_share.clone()));
+                                        share_open.set(true);
+                                    },
+                                    {tr!("tunnel-menu-share")}
+                                }
+                                DropdownMenuSeparator {}
+                                if let Some(url) = share_url {
+                                    DropdownMenuItem::<String> {
+                                        value: copy_url_value,
+                                        index: copy_url_index,
+                                        disabled: is_disabled,
+                                        on_select: move |_| copy(url.clone()),
+                                        {tr!("tunnel-menu-copy-url")}
+                                    }
+                                }
+                                if let Some(ticket) = share_ticket {
+                                    DropdownMenuItem::<String> {
+                                        value: copy_ticket_value,
+                                        index: copy_ticket_index,
+                                        disabled: is_disabled,
+                                        on_select: move |_| copy(ticket.clone()),
+                                        {tr!("tunnel-menu-copy-ticket")}
+                                    }
+                                }
+                                DropdownMenuItem::<String> {
+                                    value: use_signal(|| "copy-markdown".to_string()),
+                                    index: use_signal(|| 4),
+                                    disabled: is_disabled,
+                                    on_select: move |_| copy(share_markdown.clone()),
+                                    {tr!("tunnel-menu-copy-markdown")}
+                                }
+                                DropdownMenuItem::<String> {
+                                    value: use_signal(|| "copy-slack".to_string()),
+                                    index: use_signal(|| 5),
+                                    disabled: is_disabled,
+                                    on_select: move |_| copy(share_slack.clone()),
+                                    {tr!("tunnel-menu-copy-slack")}
                                 }
                                 DropdownMenuSeparator {}
                                 DropdownMenuItem::<String> {
                                     value: use_signal(|| "delete".to_string()),
-                                    index: use_signal(|| 2),
+                                    index: use_signal(|| 6),
                                     disabled: is_disabled,
                                     on_select: move |_| {
                                         on_delete.call(tunnel_for_delete.clone());
                                     },
                                     destructive: true,
-                                    "Delete"
+                                    {tr!("tunnel-menu-delete")}
                                 }
                             }
                         }
@@ -545,5 +1067,10 @@ pub fn TunnelCard(
                 }
             }
         }
+        ShareTunnelDialog {
+            open: share_open,
+            on_open_change: move |open| share_open.set(open),
+            tunnel: tunnel_to_share,
+        }
     }
 }
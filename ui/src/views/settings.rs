@@ -1,9 +1,18 @@
 use crate::{
-    components::{input::Input, Button, ButtonKind, Icon, IconSource},
+    components::{
+        input::Input,
+        select::{
+            Select, SelectItemIndicator, SelectList, SelectOptionItem, SelectTrigger, SelectValue,
+        },
+        Button, ButtonKind, Icon, IconSource, Switch, SwitchThumb,
+    },
+    i18n::tr,
     state::AppState,
     Route,
 };
+use dioxus::events::FormEvent;
 use dioxus::prelude::*;
+use lib::{datum_cloud::LoginState, AgentStatusField, Repo};
 use open::that;
 
 #[component]
@@ -24,6 +33,54 @@ pub fn Settings() -> Element {
         Ok(auth) => auth.profile.email.clone(),
         Err(_) => String::new(),
     };
+    let current_account = state.datum().account();
+    let mut new_account_name = use_signal(String::new);
+    let accounts = use_resource(move || async move {
+        let state = consume_context::<AppState>();
+        state.repo().list_accounts().await.unwrap_or_default()
+    });
+    let mut switch_account = use_action(move |name: String| async move {
+        let state = consume_context::<AppState>();
+        state.switch_account(&name).await?;
+        if state.datum().login_state() == LoginState::Missing {
+            nav.push(Route::Login {});
+        } else {
+            nav.push(Route::SelectProject {});
+        }
+        n0_error::Ok(())
+    });
+    let account_options = accounts().unwrap_or_default();
+    let account_error = match switch_account.value() {
+        Some(Err(err)) => Some(err.to_string()),
+        _ => None,
+    };
+    let current_profile = state.profile().to_string();
+    let mut next_profile = use_signal(|| None::<String>);
+    let mut new_profile_name = use_signal(String::new);
+    let profiles = use_resource(move || async move {
+        let _ = next_profile();
+        Repo::list_profiles(Repo::default_location())
+            .await
+            .unwrap_or_default()
+    });
+    let mut switch_profile = use_action(move |name: String| async move {
+        let base = Repo::default_location();
+        Repo::open_profile_in(&base, &name).await?;
+        Repo::write_active_profile(&base, &name).await?;
+        next_profile.set(Some(name));
+        n0_error::Ok(())
+    });
+    let profile_options = profiles().unwrap_or_default();
+    let profile_error = match switch_profile.value() {
+        Some(Err(err)) => Some(err.to_string()),
+        _ => None,
+    };
+    let preferences = state.preferences();
+    let mut save_preferences = use_action(move |prefs: lib::Preferences| async move {
+        let state = consume_context::<AppState>();
+        state.set_preferences(prefs).await
+    });
+    let mut telemetry_payload = use_signal(|| None::<String>);
     rsx! {
         div { class: "space-y-5",
             // Back link
@@ -77,6 +134,119 @@ pub fn Settings() -> Element {
                     }
                 }
             }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", {tr!("settings-accounts")} }
+                }
+                div { class: "p-4 flex flex-col gap-4 max-w-md",
+                    p { class: "text-1xs text-foreground/60",
+                        {tr!("settings-accounts-description")}
+                    }
+                    Select {
+                        value: Some(current_account.clone()),
+                        on_value_change: move |value: Option<String>| {
+                            if let Some(value) = value {
+                                switch_account.call(value);
+                            }
+                        },
+                        placeholder: tr!("settings-accounts-select"),
+                        disabled: switch_account.pending(),
+                        SelectTrigger { SelectValue {} }
+                        SelectList {
+                            for (i , name) in account_options.into_iter().enumerate() {
+                                SelectOptionItem {
+                                    value: name.clone(),
+                                    text_value: name.clone(),
+                                    index: i,
+                                    span { class: "truncate", "{name}" }
+                                    SelectItemIndicator {}
+                                }
+                            }
+                        }
+                    }
+                    div { class: "flex items-end gap-2",
+                        Input {
+                            label: Some(tr!("settings-accounts-new")),
+                            placeholder: "work",
+                            value: "{new_account_name}",
+                            oninput: move |e: FormEvent| new_account_name.set(e.value()),
+                        }
+                        Button {
+                            text: tr!("settings-accounts-add"),
+                            kind: ButtonKind::Outline,
+                            onclick: move |_| {
+                                let name = new_account_name().trim().to_string();
+                                if !name.is_empty() {
+                                    new_account_name.set(String::new());
+                                    switch_account.call(name);
+                                }
+                            },
+                        }
+                    }
+                    if let Some(err) = account_error {
+                        p { class: "text-1xs text-alert-red-dark", "{err}" }
+                    }
+                }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", "Profile" }
+                }
+                div { class: "p-4 flex flex-col gap-4 max-w-md",
+                    p { class: "text-1xs text-foreground/60",
+                        "Each profile has its own login, keys and tunnels, e.g. to keep work and personal Datum accounts apart."
+                    }
+                    Select {
+                        value: Some(next_profile().unwrap_or(current_profile.clone())),
+                        on_value_change: move |value: Option<String>| {
+                            if let Some(value) = value {
+                                switch_profile.call(value);
+                            }
+                        },
+                        placeholder: "Select a profile".to_string(),
+                        disabled: false,
+                        SelectTrigger { SelectValue {} }
+                        SelectList {
+                            for (i , name) in profile_options.into_iter().enumerate() {
+                                SelectOptionItem {
+                                    value: name.clone(),
+                                    text_value: name.clone(),
+                                    index: i,
+                                    span { class: "truncate", "{name}" }
+                                    SelectItemIndicator {}
+                                }
+                            }
+                        }
+                    }
+                    div { class: "flex items-end gap-2",
+                        Input {
+                            label: Some("New profile".into()),
+                            placeholder: "work",
+                            value: "{new_profile_name}",
+                            oninput: move |e: FormEvent| new_profile_name.set(e.value()),
+                        }
+                        Button {
+                            text: "Create & Switch",
+                            kind: ButtonKind::Outline,
+                            onclick: move |_| {
+                                let name = new_profile_name().trim().to_string();
+                                if !name.is_empty() {
+                                    new_profile_name.set(String::new());
+                                    switch_profile.call(name);
+                                }
+                            },
+                        }
+                    }
+                    if let Some(err) = profile_error {
+                        p { class: "text-1xs text-alert-red-dark", "{err}" }
+                    }
+                    if next_profile().is_some_and(|p| p != current_profile) {
+                        p { class: "text-1xs text-foreground/80",
+                            "Restart Datum to switch to this profile."
+                        }
+                    }
+                }
+            }
             div { class: "bg-card-background border border-card-border rounded-lg",
                 div { class: "px-4 py-3 border-b border-card-border",
                     h2 { class: "text-sm text-foreground", "Updates" }
@@ -101,6 +271,184 @@ pub fn Settings() -> Element {
                     }
                 }
             }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", "Privacy" }
+                }
+                div { class: "p-4 flex items-center justify-between gap-4",
+                    div { class: "flex flex-col gap-1",
+                        p { class: "text-sm text-foreground", "Detect tickets on the clipboard" }
+                        p { class: "text-1xs text-foreground/60",
+                            "Offer to join a tunnel when you copy its ticket. The clipboard is only read on this device while this is on."
+                        }
+                    }
+                    Switch {
+                        checked: preferences().clipboard_watch,
+                        disabled: save_preferences.pending(),
+                        on_checked_change: move |next| {
+                            let mut prefs = preferences();
+                            prefs.clipboard_watch = next;
+                            save_preferences.call(prefs);
+                        },
+                        SwitchThumb {}
+                    }
+                }
+                div { class: "p-4 flex flex-col gap-3 border-t border-card-border",
+                    div { class: "flex items-center justify-between gap-4",
+                        div { class: "flex flex-col gap-1",
+                            p { class: "text-sm text-foreground", {tr!("settings-telemetry")} }
+                            p { class: "text-1xs text-foreground/60",
+                                {tr!("settings-telemetry-description")}
+                            }
+                        }
+                        Switch {
+                            checked: preferences().telemetry,
+                            disabled: save_preferences.pending(),
+                            on_checked_change: move |next| {
+                                let mut prefs = preferences();
+                                prefs.telemetry = next;
+                                save_preferences.call(prefs);
+                                telemetry_payload.set(None);
+                            },
+                            SwitchThumb {}
+                        }
+                    }
+                    if preferences().telemetry {
+                        Button {
+                            class: "w-fit",
+                            kind: ButtonKind::Outline,
+                            text: if telemetry_payload().is_some() { tr!("settings-telemetry-hide") } else { tr!("settings-telemetry-view") },
+                            onclick: move |_| {
+                                if telemetry_payload().is_some() {
+                                    telemetry_payload.set(None);
+                                } else {
+                                    let payload = consume_context::<AppState>().telemetry().payload();
+                                    telemetry_payload.set(Some(payload.to_json_pretty()));
+                                }
+                            },
+                        }
+                    }
+                    if let Some(payload) = telemetry_payload() {
+                        pre { class: "text-1xs text-foreground/80 bg-content-background rounded-md p-3 overflow-x-auto",
+                            "{payload}"
+                        }
+                    }
+                }
+                div { class: "p-4 flex items-center justify-between gap-4 border-t border-card-border",
+                    div { class: "flex flex-col gap-1",
+                        p { class: "text-sm text-foreground", {tr!("settings-crash-reports")} }
+                        p { class: "text-1xs text-foreground/60",
+                            {tr!("settings-crash-reports-description")}
+                        }
+                    }
+                    Switch {
+                        checked: preferences().crash_reports,
+                        disabled: save_preferences.pending(),
+                        on_checked_change: move |next| {
+                            let mut prefs = preferences();
+                            prefs.crash_reports = next;
+                            save_preferences.call(prefs);
+                        },
+                        SwitchThumb {}
+                    }
+                }
+                div { class: "p-4 flex flex-col gap-3 border-t border-card-border",
+                    div { class: "flex flex-col gap-1",
+                        p { class: "text-sm text-foreground", {tr!("settings-agent-status")} }
+                        p { class: "text-1xs text-foreground/60",
+                            {tr!("settings-agent-status-description")}
+                        }
+                    }
+                    for field in AgentStatusField::ALL {
+                        div {
+                            key: "{field:?}",
+                            class: "flex items-center justify-between gap-4",
+                            p { class: "text-xs text-foreground", {agent_status_label(field)} }
+                            Switch {
+                                checked: !preferences().redacted_agent_status.contains(&field),
+                                disabled: save_preferences.pending(),
+                                on_checked_change: move |next| {
+                                    let mut prefs = preferences();
+                                    if next {
+                                        prefs.redacted_agent_status.remove(&field);
+                                    } else {
+                                        prefs.redacted_agent_status.insert(field);
+                                    }
+                                    save_preferences.call(prefs);
+                                },
+                                SwitchThumb {}
+                            }
+                        }
+                    }
+                }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", {tr!("settings-display")} }
+                }
+                div { class: "p-4 flex items-center justify-between gap-4",
+                    div { class: "flex flex-col gap-1",
+                        p { class: "text-sm text-foreground", {tr!("settings-chart-performance")} }
+                        p { class: "text-1xs text-foreground/60",
+                            {tr!("settings-chart-performance-description")}
+                        }
+                    }
+                    Switch {
+                        checked: preferences().chart_performance_mode,
+                        disabled: save_preferences.pending(),
+                        on_checked_change: move |next| {
+                            let mut prefs = preferences();
+                            prefs.chart_performance_mode = next;
+                            save_preferences.call(prefs);
+                        },
+                        SwitchThumb {}
+                    }
+                }
+            }
+            div { class: "bg-card-background border border-card-border rounded-lg",
+                div { class: "px-4 py-3 border-b border-card-border",
+                    h2 { class: "text-sm text-foreground", "Troubleshooting" }
+                }
+                div { class: "p-4 flex flex-col gap-4 max-w-md",
+                    p { class: "text-1xs text-foreground/60",
+                        "Having trouble reaching your tunnels? Run the connectivity checks to find out why."
+                    }
+                    Button {
+                        class: "w-fit",
+                        text: "Run Diagnostics",
+                        kind: ButtonKind::Secondary,
+                        to: Route::Doctor {},
+                    }
+                    p { class: "text-1xs text-foreground/60",
+                        "See recent tunnel changes, client connections, login refreshes and heartbeat failures."
+                    }
+                    Button {
+                        class: "w-fit",
+                        text: "View Activity",
+                        kind: ButtonKind::Secondary,
+                        to: Route::Activity {},
+                    }
+                    p { class: "text-1xs text-foreground/60",
+                        {tr!("settings-devices-description")}
+                    }
+                    Button {
+                        class: "w-fit",
+                        text: tr!("settings-devices-view"),
+                        kind: ButtonKind::Secondary,
+                        to: Route::Devices {},
+                    }
+                }
+            }
         }
     }
 }
+
+fn agent_status_label(field: AgentStatusField) -> String {
+    match field {
+        AgentStatusField::Version => tr!("settings-agent-status-version"),
+        AgentStatusField::Os => tr!("settings-agent-status-os"),
+        AgentStatusField::Uptime => tr!("settings-agent-status-uptime"),
+        AgentStatusField::ActiveTunnels => tr!("settings-agent-status-active-tunnels"),
+        AgentStatusField::LastError => tr!("settings-agent-status-last-error"),
+    }
+}
@@ -1,15 +1,32 @@
 use chrono::{DateTime, Local};
 use dioxus::prelude::*;
-use lib::TunnelSummary;
+use lib::{
+    bandwidth_history::{HistoryRange, SAMPLE_INTERVAL as HISTORY_INTERVAL},
+    latency::TunnelLatency,
+    TunnelSummary,
+};
 
 use super::{OpenEditTunnelDialog, TunnelCard};
 use crate::{
-    components::{skeleton::Skeleton, DeleteTunnelDialog, Icon, IconSource},
+    components::{
+        skeleton::Skeleton, DeleteTunnelDialog, Icon, IconSource, TunnelActivityPanel,
+        TunnelAuditPanel, TunnelAuthPanel, TunnelConnections, TunnelDirectOnlyPanel,
+        TunnelHeadersPanel, TunnelHealthPanel, TunnelIpFilterPanel, TunnelQuotaPanel,
+        TunnelSchedulePanel, TunnelShares, TunnelTimeoutsPanel,
+    },
+    i18n::tr,
     state::AppState,
     util::humanize_bytes,
     Route,
 };
 
+/// How often a point is added to the chart.
+const SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(650);
+/// Sample interval in performance mode or with reduced motion.
+const SLOW_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// Most points drawn in performance mode.
+const PERFORMANCE_MAX_POINTS: usize = 40;
+
 #[derive(Debug, Clone, PartialEq)]
 struct RatePoint {
     ts: DateTime<Local>,
@@ -32,6 +49,75 @@ pub fn TunnelBandwidth(id: String) -> Element {
     let mut points = use_signal(Vec::<RatePoint>::new);
     let mut latest_send = use_signal(|| 0u64);
     let mut latest_recv = use_signal(|| 0u64);
+    let mut latency = use_signal(|| None::<TunnelLatency>);
+    let latency_id = id.clone();
+    use_future(move || {
+        let id = latency_id.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let mut rx = state.listen_node().tunnel_latency_watch();
+            loop {
+                latency.set(rx.borrow_and_update().get(&id).copied());
+                if rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    // None while the chart follows live traffic.
+    let mut range = use_signal(|| None::<HistoryRange>);
+    let history_id = id.clone();
+    let mut history = use_resource(move || {
+        let id = history_id.clone();
+        async move {
+            let range = range()?;
+            let state = consume_context::<AppState>();
+            let points = state
+                .listen_node()
+                .bandwidth_history(&id, range)
+                .into_iter()
+                .map(|point| RatePoint {
+                    ts: point.at.with_timezone(&Local),
+                    send_per_s: point.send_per_s,
+                    recv_per_s: point.recv_per_s,
+                })
+                .collect::<Vec<_>>();
+            Some(points)
+        }
+    });
+    let export_id = id.clone();
+    let mut export = use_action(move |range: HistoryRange| {
+        let id = export_id.clone();
+        async move {
+            let state = consume_context::<AppState>();
+            let since = chrono::Utc::now() - range.duration();
+            let path = state
+                .listen_node()
+                .export_usage_csv(Some(&id), since)
+                .await?;
+            if let Err(err) = open::that(&path) {
+                tracing::warn!("Failed to open {}: {err}", path.display());
+            }
+            n0_error::Ok(path)
+        }
+    });
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(HISTORY_INTERVAL).await;
+            if range.peek().is_some() {
+                history.restart();
+            }
+        }
+    });
+
+    let preferences = state.preferences();
+    let performance = use_memo(move || preferences().chart_performance_mode);
+    let reduced_motion = use_resource(|| async {
+        document::eval("return window.matchMedia('(prefers-reduced-motion: reduce)').matches;")
+            .join::<bool>()
+            .await
+            .unwrap_or(false)
+    });
 
     // Load tunnel metadata and keep it in sync when state updates (e.g. after edit/save).
     let state_for_future = state.clone();
@@ -42,6 +128,8 @@ pub fn TunnelBandwidth(id: String) -> Element {
             let state = state_for_future.clone();
             async move {
                 let refresh = state.tunnel_refresh();
+                // Reload when the tunnel changes in the API, too.
+                let mut tunnels_rx = state.tunnel_service().watch_active();
 
                 loop {
                     if tunnel_loaded().is_none() {
@@ -58,15 +146,28 @@ pub fn TunnelBandwidth(id: String) -> Element {
                         }
                         Ok(None) => {
                             loading.set(false);
-                            load_error.set(Some("Tunnel not found".to_string()));
+                            load_error.set(Some(tr!("bandwidth-tunnel-not-found")));
                         }
                         Err(err) => {
                             loading.set(false);
-                            load_error.set(Some(format!("Failed to load tunnel: {err}")));
+                            load_error.set(Some(tr!(
+                                "bandwidth-tunnel-load-error",
+                                error = err.to_string()
+                            )));
                         }
                     }
 
-                    refresh.notified().await;
+                    match tunnels_rx.as_mut() {
+                        Some(rx) => tokio::select! {
+                            _ = refresh.notified() => {}
+                            res = rx.changed() => {
+                                if res.is_err() {
+                                    tunnels_rx = None;
+                                }
+                            }
+                        },
+                        None => refresh.notified().await,
+                    }
                 }
             }
         }
@@ -101,9 +202,16 @@ pub fn TunnelBandwidth(id: String) -> Element {
                     continue;
                 };
 
-                // Downsample to ~2Hz so the UI stays smooth.
+                // Downsample to ~2Hz so the UI stays smooth, and further when
+                // the chart should move less.
+                let low_motion = *performance.peek() || reduced_motion.peek().unwrap_or(false);
+                let interval = if low_motion {
+                    SLOW_SAMPLE_INTERVAL
+                } else {
+                    SAMPLE_INTERVAL
+                };
                 let dt = now.duration_since(last_sample_at);
-                if dt < std::time::Duration::from_millis(650) {
+                if dt < interval {
                     continue;
                 }
 
@@ -164,7 +272,7 @@ pub fn TunnelBandwidth(id: String) -> Element {
                         class: "rotate-90 text-icon-select",
                         size: 10,
                     }
-                    span { class: "underline", "Back to Tunnels List" }
+                    span { class: "underline", {tr!("bandwidth-back")} }
                 }
 
                 // TunnelCard skeleton
@@ -215,7 +323,7 @@ pub fn TunnelBandwidth(id: String) -> Element {
         return rsx! {
             div { class: "max-w-4xl mx-auto",
                 div { class: "rounded-2xl border border-red-200 bg-red-50 text-alert-red-dark p-6",
-                    div { class: "text-sm font-semibold", "Couldn't load bandwidth" }
+                    div { class: "text-sm font-semibold", {tr!("bandwidth-load-failed")} }
                     div { class: "text-sm mt-1 break-words", "{err}" }
                 }
             }
@@ -232,6 +340,7 @@ pub fn TunnelBandwidth(id: String) -> Element {
                 .await
                 .inspect_err(|err| {
                     tracing::warn!("delete tunnel failed: {err:#}");
+                    state.telemetry().error("tunnel_delete");
                 })?;
             if outcome.connector_deleted {
                 state
@@ -240,6 +349,7 @@ pub fn TunnelBandwidth(id: String) -> Element {
                     .await;
             }
             state.remove_tunnel(&tunnel.id);
+            state.set_last_deleted(outcome.deleted);
             state.bump_tunnel_refresh();
             n0_error::Ok(())
         }
@@ -259,6 +369,39 @@ pub fn TunnelBandwidth(id: String) -> Element {
 
     let mut open_edit_dialog = consume_context::<OpenEditTunnelDialog>();
     let tunnel = tunnel_loaded().expect("tunnel loaded when not loading and no error");
+    // Only the tunnel's share of a slow response: the RTT to the clients.
+    let (latency_avg, latency_range) = match latency() {
+        Some(l) => (
+            tr!("connections-rtt-value", ms = l.avg.as_millis() as u64),
+            tr!(
+                "bandwidth-latency-range",
+                min = l.min.as_millis() as u64,
+                max = l.max.as_millis() as u64,
+                clients = l.clients,
+            ),
+        ),
+        None => ("—".to_string(), tr!("bandwidth-latency-none")),
+    };
+    let chart_points = match range() {
+        None => points(),
+        Some(_) => history().flatten().unwrap_or_default(),
+    };
+    let ranges = [
+        (None, tr!("bandwidth-live")),
+        (Some(HistoryRange::Hour), tr!("bandwidth-hour")),
+        (Some(HistoryRange::Day), tr!("bandwidth-day")),
+        (Some(HistoryRange::Week), tr!("bandwidth-week")),
+    ]
+    .into_iter()
+    .map(|(value, label)| {
+        let class = if range() == value {
+            "bg-foreground text-background"
+        } else {
+            "bg-foreground/10 text-foreground/70 hover:bg-foreground/20"
+        };
+        (value, label, class)
+    })
+    .collect::<Vec<_>>();
 
     rsx! {
         div { id: "tunnel-bandwidth", class: "max-w-4xl mx-auto",
@@ -273,7 +416,7 @@ pub fn TunnelBandwidth(id: String) -> Element {
                     class: "rotate-90 text-icon-select",
                     size: 10,
                 }
-                span { class: "underline", "Back to Tunnels List" }
+                span { class: "underline", {tr!("bandwidth-back")} }
             }
 
             TunnelCard {
@@ -331,30 +474,86 @@ pub fn TunnelBandwidth(id: String) -> Element {
                 div { class: "border border-app-border rounded-lg p-6",
                     div { class: "flex items-center justify-start gap-5 mb-4",
                         div { class: "space-y-1.5 min-w-22",
-                            div { class: "text-xs text-icon-select font-normal", "Send" }
+                            div { class: "text-xs text-icon-select font-normal", {tr!("bandwidth-send")} }
                             div { class: "text-md font-medium text-foreground whitespace-nowrap leading-none ",
-                                "{humanize_bytes(latest_send())}/s"
+                                {tr!("bandwidth-rate", amount = humanize_bytes(latest_send()))}
                             }
                         }
                         div { class: "space-y-1.5 min-w-22",
-                            div { class: "text-xs text-icon-select font-normal", "Receive" }
+                            div { class: "text-xs text-icon-select font-normal", {tr!("bandwidth-receive")} }
+                            div { class: "text-md font-medium text-foreground whitespace-nowrap leading-none ",
+                                {tr!("bandwidth-rate", amount = humanize_bytes(latest_recv()))}
+                            }
+                        }
+                        div { class: "space-y-1.5 min-w-22", title: latency_range,
+                            div { class: "text-xs text-icon-select font-normal", {tr!("bandwidth-latency")} }
                             div { class: "text-md font-medium text-foreground whitespace-nowrap leading-none ",
-                                "{humanize_bytes(latest_recv())}/s"
+                                "{latency_avg}"
+                            }
+                        }
+                        div { class: "ml-auto flex items-center gap-1.5",
+                            if let Some(Err(err)) = export.value() {
+                                span { class: "text-1xs text-alert-red-dark", "{err}" }
+                            }
+                            button {
+                                r#type: "button",
+                                class: "text-1xs rounded-full px-2.5 py-1 bg-foreground/10 text-foreground/70 hover:bg-foreground/20",
+                                disabled: export.pending(),
+                                title: tr!("bandwidth-export-hint"),
+                                onclick: move |_| {
+                                    // Live traffic exports the last hour.
+                                    export.call(range().unwrap_or(HistoryRange::Hour));
+                                },
+                                {tr!("bandwidth-export")}
+                            }
+                            for (value , label , class) in ranges {
+                                button {
+                                    key: "{label}",
+                                    r#type: "button",
+                                    class: "text-1xs rounded-full px-2.5 py-1 {class}",
+                                    onclick: move |_| range.set(value),
+                                    "{label}"
+                                }
                             }
                         }
                     }
 
-                    div { class: "",
-                        BandwidthChart { points: points() }
+                    if range().is_some() && chart_points.is_empty() {
+                        div { class: "h-[45vh] min-h-[200px] sm:h-[400px] flex items-center justify-center text-xs text-foreground/60",
+                            {tr!("bandwidth-history-empty")}
+                        }
+                    } else {
+                        BandwidthChart { points: chart_points, performance: performance() }
                     }
                 }
+                TunnelConnections { tunnel_id: tunnel.id.clone() }
+                TunnelHealthPanel { tunnel_id: tunnel.id.clone() }
+                TunnelActivityPanel { tunnel_id: tunnel.id.clone() }
+                TunnelAuditPanel { tunnel_id: tunnel.id.clone() }
+                TunnelShares { tunnel_id: tunnel.id.clone() }
+                TunnelTimeoutsPanel { tunnel_id: tunnel.id.clone() }
+                TunnelSchedulePanel { tunnel_id: tunnel.id.clone() }
+                TunnelDirectOnlyPanel { tunnel_id: tunnel.id.clone() }
+                TunnelQuotaPanel { tunnel_id: tunnel.id.clone() }
+                TunnelHeadersPanel { tunnel_id: tunnel.id.clone() }
+                TunnelAuthPanel { tunnel_id: tunnel.id.clone() }
+                TunnelIpFilterPanel {
+                    tunnel_id: tunnel.id.clone(),
+                    initial: tunnel.ip_filter.clone(),
+                }
             }
         }
     }
 }
 
 #[component]
-fn BandwidthChart(points: Vec<RatePoint>) -> Element {
+fn BandwidthChart(points: Vec<RatePoint>, performance: bool) -> Element {
+    if performance {
+        return rsx! {
+            FastBandwidthChart { points }
+        };
+    }
+
     // Render with a fixed viewBox but scale to the container width to avoid overflow.
     // Give the left axis more room so labels don't get clipped.
     let width = 860.0;
@@ -576,3 +775,112 @@ fn BandwidthChart(points: Vec<RatePoint>) -> Element {
         }
     }
 }
+
+/// At most `max_points` points, keeping each bucket's peak so spikes stay visible.
+fn decimate(points: &[RatePoint], max_points: usize) -> Vec<RatePoint> {
+    if points.len() <= max_points {
+        return points.to_vec();
+    }
+    let bucket = points.len().div_ceil(max_points);
+    points
+        .chunks(bucket)
+        .map(|chunk| RatePoint {
+            ts: chunk[chunk.len() - 1].ts,
+            send_per_s: chunk.iter().map(|p| p.send_per_s).max().unwrap_or(0),
+            recv_per_s: chunk.iter().map(|p| p.recv_per_s).max().unwrap_or(0),
+        })
+        .collect()
+}
+
+/// The bandwidth chart for [performance mode](lib::Preferences::chart_performance_mode).
+///
+/// Paths are straight lines in data coordinates (sample index, bytes/s), and
+/// a transform on the group maps them onto the chart. A new peak only changes
+/// the transform instead of every path segment.
+#[component]
+fn FastBandwidthChart(points: Vec<RatePoint>) -> Element {
+    let width = 860.0;
+    let height = 400.0;
+    let padding_x = 52.0;
+    let padding_y = 22.0;
+    let w = width - padding_x * 2.0;
+    let h = height - padding_y * 2.0;
+
+    let points = decimate(&points, PERFORMANCE_MAX_POINTS);
+    let max_v = points
+        .iter()
+        .map(|p| p.send_per_s.max(p.recv_per_s))
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let scale_x = w / (points.len().saturating_sub(1).max(1) as f64);
+    let scale_y = h / max_v;
+
+    let line = |get: fn(&RatePoint) -> u64| -> String {
+        let mut d = String::new();
+        for (i, p) in points.iter().enumerate() {
+            let cmd = if i == 0 { "M" } else { " L" };
+            d.push_str(&format!("{cmd} {i} {}", get(p)));
+        }
+        d
+    };
+    let send_path = line(|p| p.send_per_s);
+    let recv_path = line(|p| p.recv_per_s);
+
+    let send_color = "#BF9595";
+    let recv_color = "#4D6356";
+
+    let y_ticks = 2;
+    let mut y_labels = Vec::new();
+    for i in 0..=y_ticks {
+        let frac = i as f64 / y_ticks as f64;
+        let y = padding_y + frac * h;
+        let val = ((1.0 - frac) * max_v) as u64;
+        y_labels.push((humanize_bytes(val), y));
+    }
+
+    rsx! {
+        div { class: "w-full overflow-hidden h-[45vh] min-h-[200px] sm:h-[400px]",
+            svg {
+                width: "100%",
+                height: "100%",
+                view_box: "0 0 {width} {height}",
+                shape_rendering: "optimizeSpeed",
+                for (label , y) in y_labels {
+                    line {
+                        x1: "{padding_x}",
+                        y1: "{y}",
+                        x2: "{width - padding_x}",
+                        y2: "{y}",
+                        stroke: "#eceee9",
+                        stroke_width: "1.5",
+                    }
+                    text {
+                        x: "{padding_x - 12.0}",
+                        y: "{y + 4.0}",
+                        text_anchor: "end",
+                        font_size: "17",
+                        fill: "#94a3b8",
+                        "{label}"
+                    }
+                }
+                g { transform: "translate({padding_x}, {padding_y + h}) scale({scale_x}, {-scale_y})",
+                    path {
+                        d: "{recv_path}",
+                        fill: "none",
+                        stroke: "{recv_color}",
+                        stroke_width: "2",
+                        vector_effect: "non-scaling-stroke",
+                    }
+                    path {
+                        d: "{send_path}",
+                        fill: "none",
+                        stroke: "{send_color}",
+                        stroke_width: "2",
+                        vector_effect: "non-scaling-stroke",
+                    }
+                }
+            }
+        }
+    }
+}